@@ -64,6 +64,11 @@ pub enum Error {
 
     #[error("Conflict: {internal_message}")]
     Conflict { internal_message: String },
+
+    /// The request could not be completed because it would exceed a limit on
+    /// the resources available to the caller, such as a quota.
+    #[error("Insufficient Capacity: {message}")]
+    InsufficientCapacity { message: String },
 }
 
 /// Indicates how an object was looked up (for an `ObjectNotFound` error)
@@ -122,7 +127,8 @@ impl Error {
             | Error::MethodNotAllowed { .. }
             | Error::InternalError { .. }
             | Error::TypeVersionMismatch { .. }
-            | Error::Conflict { .. } => false,
+            | Error::Conflict { .. }
+            | Error::InsufficientCapacity { .. } => false,
         }
     }
 
@@ -190,6 +196,16 @@ impl Error {
         Error::Conflict { internal_message: message.to_owned() }
     }
 
+    /// Generates an [`Error::InsufficientCapacity`] with a specific message.
+    ///
+    /// This should be used when a request would exceed a limit on provisioned
+    /// resources, such as a silo or project quota.  Unlike
+    /// [`Error::ServiceUnavailable`], retrying the request is not expected to
+    /// help until the limit is raised or other resources are released.
+    pub fn insufficient_capacity(message: &str) -> Error {
+        Error::InsufficientCapacity { message: message.to_owned() }
+    }
+
     /// Given an [`Error`] with an internal message, return the same error with
     /// `context` prepended to it to provide more context
     ///
@@ -203,7 +219,8 @@ impl Error {
             | Error::ObjectAlreadyExists { .. }
             | Error::InvalidRequest { .. }
             | Error::InvalidValue { .. }
-            | Error::Forbidden => self,
+            | Error::Forbidden
+            | Error::InsufficientCapacity { .. } => self,
             Error::Unauthenticated { internal_message } => {
                 Error::Unauthenticated {
                     internal_message: format!(
@@ -344,6 +361,13 @@ impl From<Error> for HttpError {
                     internal_message,
                 )
             }
+
+            Error::InsufficientCapacity { message } => HttpError {
+                status_code: http::StatusCode::INSUFFICIENT_STORAGE,
+                error_code: Some(String::from("InsufficientCapacity")),
+                external_message: message.clone(),
+                internal_message: message,
+            },
         }
     }
 }
@@ -41,6 +41,7 @@
 //! each resource paginated that way).  Where possible, we should share code.
 
 use crate::api::external::DataPageParams;
+use crate::api::external::LabelSelector;
 use crate::api::external::Name;
 use crate::api::external::NameOrId;
 use crate::api::external::ObjectIdentity;
@@ -298,6 +299,56 @@ impl<
     }
 }
 
+// Pagination by sequence number, in either order (for append-only logs)
+
+/// Query parameters for pagination by sequence number
+pub type PaginatedBySeq<Selector = ()> =
+    PaginationParams<ScanBySeq<Selector>, PageSelectorBySeq<Selector>>;
+/// Page selector for pagination by sequence number
+pub type PageSelectorBySeq<Selector = ()> =
+    PageSelector<ScanBySeq<Selector>, u64>;
+/// Scan parameters for resources that support scanning by sequence number
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct ScanBySeq<Selector = ()> {
+    #[serde(default = "default_seq_sort_mode")]
+    sort_by: SeqSortMode,
+    #[serde(flatten)]
+    pub selector: Selector,
+}
+
+/// Supported set of sort modes for scanning by sequence number
+#[derive(Copy, Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeqSortMode {
+    /// sort in increasing order of sequence number (oldest first)
+    SeqAscending,
+    /// sort in decreasing order of sequence number (newest first)
+    SeqDescending,
+}
+
+fn default_seq_sort_mode() -> SeqSortMode {
+    SeqSortMode::SeqAscending
+}
+
+impl<
+        T: Clone + Debug + DeserializeOwned + JsonSchema + PartialEq + Serialize,
+    > ScanParams for ScanBySeq<T>
+{
+    type MarkerValue = u64;
+    fn direction(&self) -> PaginationOrder {
+        match self.sort_by {
+            SeqSortMode::SeqAscending => PaginationOrder::Ascending,
+            SeqSortMode::SeqDescending => PaginationOrder::Descending,
+        }
+    }
+    fn from_query(p: &PaginatedBySeq<T>) -> Result<&Self, HttpError> {
+        Ok(match p.page {
+            WhichPage::First(ref scan_params) => scan_params,
+            WhichPage::Next(PageSelector { ref scan, .. }) => scan,
+        })
+    }
+}
+
 // Pagination by any of: name ascending, name descending, or id ascending.
 // We include this now primarily to exercise the interface for doing so.
 
@@ -356,6 +407,20 @@ fn default_nameid_sort_mode() -> NameOrIdSortMode {
     NameOrIdSortMode::NameAscending
 }
 
+/// Selector for listing resources that carry labels
+///
+/// This wraps the resource-specific `Selector` (e.g., the project containing
+/// the resources) with an optional label selector.  It's intended to be used as
+/// the `Selector` of [`PaginatedByNameOrId`].
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct LabelFiltered<Selector = ()> {
+    #[serde(flatten)]
+    pub selector: Selector,
+    /// only list resources whose labels match this selector (e.g.,
+    /// `team=storage,env`)
+    pub label: Option<LabelSelector>,
+}
+
 fn bad_token_error() -> HttpError {
     HttpError::for_bad_request(None, String::from("invalid page token"))
 }
@@ -416,6 +481,7 @@ mod test {
     use super::marker_for_name_or_id;
     use super::page_selector_for;
     use super::IdSortMode;
+    use super::LabelFiltered;
     use super::Name;
     use super::NameOrId;
     use super::NameOrIdSortMode;
@@ -428,10 +494,13 @@ mod test {
     use super::PaginatedById;
     use super::PaginatedByName;
     use super::PaginatedByNameOrId;
+    use super::PaginatedBySeq;
     use super::ScanById;
     use super::ScanByName;
     use super::ScanByNameOrId;
+    use super::ScanBySeq;
     use super::ScanParams;
+    use super::SeqSortMode;
     use crate::api::external::http_pagination::name_or_id_pagination;
     use crate::api::external::IdentityMetadata;
     use crate::api::external::ObjectIdentity;
@@ -736,6 +805,57 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_scan_by_seq() {
+        // Our things don't have sequence numbers, but the number in each
+        // one's name will do.
+        let marker_for_seq = |_: &ScanBySeq, t: &MyThing| -> u64 {
+            t.identity.name.as_str()["thing".len()..].parse().unwrap()
+        };
+
+        // Start with the common battery of tests.
+        let scan =
+            ScanBySeq { sort_by: SeqSortMode::SeqDescending, selector: () };
+        let scan_default =
+            ScanBySeq { sort_by: SeqSortMode::SeqAscending, selector: () };
+
+        let list = list_of_things();
+        let (p0, p1) = test_scan_param_common(
+            &list,
+            &scan,
+            "sort_by=seq_descending",
+            &0,
+            &19,
+            &scan_default,
+            &marker_for_seq,
+        );
+        assert_eq!(scan.direction(), PaginationOrder::Descending);
+        assert_eq!(scan_default.direction(), PaginationOrder::Ascending);
+
+        // Verify data pages based on the query params.
+        let limit = NonZeroU32::new(123).unwrap();
+        let data_page = data_page_params_with_limit(limit, &p0).unwrap();
+        assert_eq!(data_page.marker, None);
+        assert_eq!(data_page.direction, PaginationOrder::Descending);
+        assert_eq!(data_page.limit, limit);
+
+        let data_page = data_page_params_with_limit(limit, &p1).unwrap();
+        assert_eq!(data_page.marker, Some(&19));
+        assert_eq!(data_page.direction, PaginationOrder::Descending);
+        assert_eq!(data_page.limit, limit);
+
+        // Test from_query(): error case.
+        let error = serde_urlencoded::from_str::<PaginatedBySeq>(
+            "sort_by=id_ascending",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unknown variant `id_ascending`, expected `seq_ascending` or \
+             `seq_descending`"
+        );
+    }
+
     #[test]
     fn test_scan_by_nameid_generic() {
         // Test from_query(): error case.
@@ -858,4 +978,39 @@ mod test {
         assert_eq!(data_page.direction, PaginationOrder::Ascending);
         assert_eq!(data_page.limit, limit);
     }
+
+    #[test]
+    fn test_scan_by_nameid_label_filtered() {
+        // The label selector is accepted as a query parameter and preserved in
+        // the page token so that subsequent pages are filtered the same way.
+        let scan = ScanByNameOrId {
+            sort_by: NameOrIdSortMode::NameAscending,
+            selector: LabelFiltered {
+                selector: (),
+                label: Some("team=storage,env".parse().unwrap()),
+            },
+        };
+
+        let list = list_of_things();
+        let thing0_marker = NameOrId::Name("thing0".parse().unwrap());
+        let thinglast_marker = NameOrId::Name("thing19".parse().unwrap());
+        test_scan_param_common(
+            &list,
+            &scan,
+            "sort_by=name_ascending&label=team%3Dstorage%2Cenv",
+            &thing0_marker,
+            &thinglast_marker,
+            &ScanByNameOrId {
+                sort_by: NameOrIdSortMode::NameAscending,
+                selector: LabelFiltered { selector: (), label: None },
+            },
+            &marker_for_name_or_id,
+        );
+
+        // Invalid selectors are rejected.
+        serde_urlencoded::from_str::<PaginatedByNameOrId<LabelFiltered>>(
+            "label=Team",
+        )
+        .unwrap_err();
+    }
 }
@@ -28,6 +28,7 @@ use semver;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_with::{DeserializeFromStr, SerializeDisplay};
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::fmt::Display;
@@ -687,7 +688,10 @@ pub enum ResourceType {
     SiloGroup,
     IdentityProvider,
     SamlIdentityProvider,
+    OidcIdentityProvider,
     SshKey,
+    AccessToken,
+    ScimClientToken,
     Certificate,
     ConsoleSession,
     DeviceAuthRequest,
@@ -701,6 +705,7 @@ pub enum ResourceType {
     ProjectImage,
     Instance,
     IpPool,
+    FloatingIp,
     InstanceNetworkInterface,
     PhysicalDisk,
     Rack,
@@ -708,11 +713,13 @@ pub enum ResourceType {
     Sled,
     SagaDbg,
     Snapshot,
+    SnapshotSchedule,
     Volume,
     Vpc,
     VpcFirewallRule,
     VpcSubnet,
     VpcRouter,
+    VpcPeering,
     RouterRoute,
     Oximeter,
     MetricProducer,
@@ -725,6 +732,7 @@ pub enum ResourceType {
     UpdateableComponent,
     UserBuiltin,
     Zpool,
+    AffinityGroup,
 }
 
 // IDENTITY METADATA
@@ -758,6 +766,309 @@ pub struct IdentityMetadataUpdateParams {
     pub description: Option<String>,
 }
 
+// LABELS
+
+/// Maximum number of labels that may be attached to a single resource
+pub const MAX_LABELS_PER_RESOURCE: usize = 64;
+
+/// Maximum length of a label key or value
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// Key/value labels attached to a resource
+///
+/// Labels are free-form metadata used to organize resources (e.g., by team or
+/// environment) and to select them when listing.  Keys must be 1-63
+/// characters of lowercase ASCII, digits, '-', '_', '.', and '/', and must
+/// begin and end with a letter or digit.  Values may be empty or up to 63
+/// characters of ASCII letters, digits, '-', '_', and '.'.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "BTreeMap<String, String>")]
+pub struct Labels(BTreeMap<String, String>);
+
+impl Labels {
+    /// Returns whether `key` is valid as a label key
+    fn validate_key(key: &str) -> Result<(), String> {
+        if key.is_empty() || key.len() > MAX_LABEL_LEN {
+            return Err(format!(
+                "label key {:?} must contain between 1 and {} characters",
+                key, MAX_LABEL_LEN
+            ));
+        }
+        if let Some(c) = key.chars().find(|c| {
+            !c.is_ascii_lowercase()
+                && !c.is_ascii_digit()
+                && !matches!(c, '-' | '_' | '.' | '/')
+        }) {
+            return Err(format!(
+                "label key {:?} contains invalid character: {:?} (allowed \
+                 characters are lowercase ASCII, digits, \"-\", \"_\", \".\", \
+                 and \"/\")",
+                key, c
+            ));
+        }
+        let is_alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+        if !key.starts_with(is_alnum) || !key.ends_with(is_alnum) {
+            return Err(format!(
+                "label key {:?} must begin and end with a lowercase ASCII \
+                 character or digit",
+                key
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns whether `value` is valid as a label value
+    fn validate_value(value: &str) -> Result<(), String> {
+        if value.len() > MAX_LABEL_LEN {
+            return Err(format!(
+                "label value {:?} may contain at most {} characters",
+                value, MAX_LABEL_LEN
+            ));
+        }
+        if let Some(c) = value.chars().find(|c| {
+            !c.is_ascii_alphanumeric() && !matches!(c, '-' | '_' | '.')
+        }) {
+            return Err(format!(
+                "label value {:?} contains invalid character: {:?} (allowed \
+                 characters are ASCII letters, digits, \"-\", \"_\", and \".\")",
+                value, c
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|v| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns whether these labels satisfy every requirement in `selector`
+    pub fn matches(&self, selector: &LabelSelector) -> bool {
+        selector.requirements().iter().all(|r| match r {
+            LabelRequirement::Exists { key } => self.0.contains_key(key),
+            LabelRequirement::Equals { key, value } => {
+                self.get(key) == Some(value.as_str())
+            }
+        })
+    }
+}
+
+impl TryFrom<BTreeMap<String, String>> for Labels {
+    type Error = String;
+    fn try_from(labels: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        if labels.len() > MAX_LABELS_PER_RESOURCE {
+            return Err(format!(
+                "a resource may have at most {} labels",
+                MAX_LABELS_PER_RESOURCE
+            ));
+        }
+        for (key, value) in &labels {
+            Labels::validate_key(key)?;
+            Labels::validate_value(value)?;
+        }
+        Ok(Labels(labels))
+    }
+}
+
+impl<K, V, const N: usize> TryFrom<[(K, V); N]> for Labels
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    type Error = String;
+    fn try_from(labels: [(K, V); N]) -> Result<Self, Self::Error> {
+        Labels::try_from(
+            labels
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+}
+
+impl From<Labels> for BTreeMap<String, String> {
+    fn from(labels: Labels) -> Self {
+        labels.0
+    }
+}
+
+/// Custom JsonSchema implementation to encode the constraints on Labels.
+impl JsonSchema for Labels {
+    fn schema_name() -> String {
+        "Labels".to_string()
+    }
+    fn json_schema(
+        _: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                title: Some(
+                    "Key/value labels attached to a resource".to_string(),
+                ),
+                description: Some(
+                    "Keys must be 1-63 characters of lowercase ASCII, digits, \
+                     '-', '_', '.', and '/', and must begin and end with a \
+                     letter or digit.  Values may be empty or up to 63 \
+                     characters of ASCII letters, digits, '-', '_', and '.'. \
+                     A resource may have at most 64 labels."
+                        .to_string(),
+                ),
+                ..Default::default()
+            })),
+            instance_type: Some(schemars::schema::InstanceType::Object.into()),
+            object: Some(Box::new(schemars::schema::ObjectValidation {
+                max_properties: Some(MAX_LABELS_PER_RESOURCE as u32),
+                additional_properties: Some(Box::new(
+                    schemars::schema::SchemaObject {
+                        instance_type: Some(
+                            schemars::schema::InstanceType::String.into(),
+                        ),
+                        string: Some(Box::new(
+                            schemars::schema::StringValidation {
+                                max_length: Some(MAX_LABEL_LEN as u32),
+                                min_length: None,
+                                pattern: Some(
+                                    r#"^[a-zA-Z0-9._-]*$"#.to_string(),
+                                ),
+                            },
+                        )),
+                        ..Default::default()
+                    }
+                    .into(),
+                )),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// One requirement of a [`LabelSelector`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LabelRequirement {
+    /// the resource has label `key`, with any value
+    Exists { key: String },
+    /// the resource has label `key` with value `value`
+    Equals { key: String, value: String },
+}
+
+impl Display for LabelRequirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        match self {
+            LabelRequirement::Exists { key } => write!(f, "{}", key),
+            LabelRequirement::Equals { key, value } => {
+                write!(f, "{}={}", key, value)
+            }
+        }
+    }
+}
+
+impl FromStr for LabelRequirement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) => {
+                Labels::validate_key(key)?;
+                Labels::validate_value(value)?;
+                Ok(LabelRequirement::Equals {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+            }
+            None => {
+                Labels::validate_key(s)?;
+                Ok(LabelRequirement::Exists { key: s.to_string() })
+            }
+        }
+    }
+}
+
+/// Selects resources by their labels
+///
+/// A selector is a comma-separated list of requirements, all of which must be
+/// satisfied by a resource's labels.  Each requirement is either `key=value`
+/// (the label `key` is present with value `value`) or just `key` (the label
+/// `key` is present with any value).
+#[derive(Clone, Debug, DeserializeFromStr, Eq, PartialEq, SerializeDisplay)]
+pub struct LabelSelector(Vec<LabelRequirement>);
+
+impl LabelSelector {
+    pub fn requirements(&self) -> &[LabelRequirement] {
+        &self.0
+    }
+}
+
+impl Display for LabelSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        let mut first = true;
+        for requirement in &self.0 {
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+            write!(f, "{}", requirement)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let requirements = s
+            .split(',')
+            .map(|r| r.trim().parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        if requirements.len() > MAX_LABELS_PER_RESOURCE {
+            return Err(format!(
+                "label selector may contain at most {} requirements",
+                MAX_LABELS_PER_RESOURCE
+            ));
+        }
+        Ok(LabelSelector(requirements))
+    }
+}
+
+impl JsonSchema for LabelSelector {
+    fn schema_name() -> String {
+        "LabelSelector".to_string()
+    }
+    fn json_schema(
+        _: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                title: Some("Selects resources by their labels".to_string()),
+                description: Some(
+                    "A comma-separated list of requirements, all of which \
+                     must be satisfied.  Each requirement is either \
+                     \"key=value\" (the label is present with the given \
+                     value) or \"key\" (the label is present with any value)."
+                        .to_string(),
+                ),
+                ..Default::default()
+            })),
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 // Specific API resources
 
 // INSTANCES
@@ -873,6 +1184,49 @@ impl InstanceState {
     }
 }
 
+/// Whether the control plane should start an `Instance` again on its own
+/// after it stops running
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Serialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum InstanceAutoRestartPolicy {
+    /// The instance is never restarted automatically.
+    #[default]
+    Never,
+    /// The instance is restarted, on any sled with room for it, if it fails
+    /// or is lost along with the sled it was running on.
+    OnFailure,
+    /// In addition to restarting after a failure, the instance is restarted
+    /// whenever it stops without having been asked to stop through the API
+    /// (for example, when the guest powers itself off).
+    Always,
+}
+
+impl InstanceAutoRestartPolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            InstanceAutoRestartPolicy::Never => "never",
+            InstanceAutoRestartPolicy::OnFailure => "on_failure",
+            InstanceAutoRestartPolicy::Always => "always",
+        }
+    }
+}
+
+impl Display for InstanceAutoRestartPolicy {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        write!(f, "{}", self.label())
+    }
+}
+
 /// The number of CPUs in an Instance
 #[derive(Copy, Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct InstanceCpuCount(pub u16);
@@ -916,6 +1270,9 @@ pub struct Instance {
     #[serde(flatten)]
     pub identity: IdentityMetadata,
 
+    /// labels attached to this Instance
+    pub labels: Labels,
+
     /// id for the project containing this Instance
     pub project_id: Uuid,
 
@@ -925,6 +1282,8 @@ pub struct Instance {
     pub memory: ByteCount,
     /// RFC1035-compliant hostname for the Instance.
     pub hostname: String, // TODO-cleanup different type?
+    /// whether the control plane restarts this Instance on its own
+    pub auto_restart_policy: InstanceAutoRestartPolicy,
 
     #[serde(flatten)]
     pub runtime: InstanceRuntimeState,
@@ -937,6 +1296,7 @@ pub struct Instance {
 pub struct Disk {
     #[serde(flatten)]
     pub identity: IdentityMetadata,
+    pub labels: Labels,
     pub project_id: Uuid,
     pub snapshot_id: Option<Uuid>,
     pub image_id: Option<Uuid>,
@@ -972,6 +1332,8 @@ pub enum DiskState {
     ImportingFromBulkWrites,
     /// Disk is being finalized to state Detached
     Finalizing,
+    /// Disk is ready for its blocks to be exported
+    Exporting,
     /// Disk is undergoing maintenance
     Maintenance,
     /// Disk is being attached to the given Instance
@@ -1007,6 +1369,7 @@ impl TryFrom<(&str, Option<Uuid>)> for DiskState {
                 Ok(DiskState::ImportingFromBulkWrites)
             }
             ("finalizing", None) => Ok(DiskState::Finalizing),
+            ("exporting", None) => Ok(DiskState::Exporting),
             ("maintenance", None) => Ok(DiskState::Maintenance),
             ("destroyed", None) => Ok(DiskState::Destroyed),
             ("faulted", None) => Ok(DiskState::Faulted),
@@ -1031,6 +1394,7 @@ impl DiskState {
             DiskState::ImportingFromUrl => "importing_from_url",
             DiskState::ImportingFromBulkWrites => "importing_from_bulk_writes",
             DiskState::Finalizing => "finalizing",
+            DiskState::Exporting => "exporting",
             DiskState::Maintenance => "maintenance",
             DiskState::Attaching(_) => "attaching",
             DiskState::Attached(_) => "attached",
@@ -1060,6 +1424,7 @@ impl DiskState {
             DiskState::ImportingFromUrl => None,
             DiskState::ImportingFromBulkWrites => None,
             DiskState::Finalizing => None,
+            DiskState::Exporting => None,
             DiskState::Maintenance => None,
             DiskState::Destroyed => None,
             DiskState::Faulted => None,
@@ -2126,14 +2491,17 @@ mod test {
     use super::VpcFirewallRuleHostFilter;
     use super::VpcFirewallRuleTarget;
     use super::{
-        ByteCount, Digest, L4Port, L4PortRange, Name, RoleName,
-        VpcFirewallRuleAction, VpcFirewallRuleDirection, VpcFirewallRuleFilter,
+        ByteCount, Digest, L4Port, L4PortRange, LabelRequirement,
+        LabelSelector, Labels, Name, RoleName, VpcFirewallRuleAction,
+        VpcFirewallRuleDirection, VpcFirewallRuleFilter,
         VpcFirewallRulePriority, VpcFirewallRuleProtocol,
         VpcFirewallRuleStatus, VpcFirewallRuleUpdate,
         VpcFirewallRuleUpdateParams,
     };
     use crate::api::external::Error;
     use crate::api::external::ResourceType;
+    use crate::api::external::MAX_LABELS_PER_RESOURCE;
+    use std::collections::BTreeMap;
     use std::convert::TryFrom;
     use std::str::FromStr;
 
@@ -2249,6 +2617,82 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_labels_validation() {
+        let labels: Labels = serde_json::from_str(
+            r#"{"team": "storage", "env": "prod", "cost-center/id": ""}"#,
+        )
+        .unwrap();
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels.get("team"), Some("storage"));
+        assert_eq!(labels.get("cost-center/id"), Some(""));
+
+        let error_cases = [
+            (r#"{"": "x"}"#, "must contain between 1 and 63 characters"),
+            (r#"{"Team": "x"}"#, "contains invalid character: 'T'"),
+            (r#"{"-team": "x"}"#, "must begin and end with"),
+            (r#"{"team.": "x"}"#, "must begin and end with"),
+            (r#"{"team": "a b"}"#, "contains invalid character: ' '"),
+        ];
+        for (input, expected) in error_cases {
+            let error = serde_json::from_str::<Labels>(input).unwrap_err();
+            assert!(
+                error.to_string().contains(expected),
+                "input {}: unexpected error {:#}",
+                input,
+                error
+            );
+        }
+
+        let long_value = "a".repeat(64);
+        assert!(Labels::try_from([("team", long_value.as_str())]).is_err());
+        let too_many = (0..=MAX_LABELS_PER_RESOURCE)
+            .map(|i| (format!("key{}", i), String::new()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            Labels::try_from(too_many).unwrap_err(),
+            "a resource may have at most 64 labels"
+        );
+    }
+
+    #[test]
+    fn test_label_selector() {
+        let selector: LabelSelector = "team=storage, env".parse().unwrap();
+        assert_eq!(
+            selector.requirements(),
+            &[
+                LabelRequirement::Equals {
+                    key: "team".to_string(),
+                    value: "storage".to_string()
+                },
+                LabelRequirement::Exists { key: "env".to_string() },
+            ]
+        );
+        assert_eq!(selector.to_string(), "team=storage,env");
+
+        let labels =
+            Labels::try_from([("team", "storage"), ("env", "prod")]).unwrap();
+        assert!(labels.matches(&selector));
+        let labels = Labels::try_from([("team", "storage")]).unwrap();
+        assert!(!labels.matches(&selector));
+        let labels =
+            Labels::try_from([("team", "compute"), ("env", "prod")]).unwrap();
+        assert!(!labels.matches(&selector));
+
+        // An empty value must match exactly.
+        let selector: LabelSelector = "team=".parse().unwrap();
+        assert!(Labels::try_from([("team", "")]).unwrap().matches(&selector));
+        assert!(!Labels::try_from([("team", "x")]).unwrap().matches(&selector));
+
+        for input in ["", "team,", "Team=x", "team=a b", "=x"] {
+            assert!(
+                input.parse::<LabelSelector>().is_err(),
+                "selector {:?} should be invalid",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_role_name_parse() {
         // Error cases
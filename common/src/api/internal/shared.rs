@@ -61,3 +61,34 @@ pub struct SourceNatConfig {
     /// The last port used for source NAT, also inclusive.
     pub last_port: u16,
 }
+
+/// The target of a route, after name resolution has been performed by Nexus
+#[derive(
+    Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Hash,
+)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum RouterTarget {
+    /// Drop matching traffic
+    Drop,
+    /// Forward matching traffic to the internet gateway
+    InternetGateway,
+    /// Forward matching traffic to a particular IP address
+    Ip(IpAddr),
+    /// Forward matching traffic to a VPC Subnet, given by its IP address range
+    VpcSubnet(external::IpNet),
+    /// Forward matching traffic to a VPC Subnet of a peered VPC, given by its
+    /// IP address range, encapsulated with the VNI of that VPC rather than
+    /// that of the sending interface's own
+    VpcPeerSubnet { vni: external::Vni, subnet: external::IpNet },
+}
+
+/// A VPC route, after name resolution has been performed by Nexus
+#[derive(
+    Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Hash,
+)]
+pub struct ResolvedVpcRoute {
+    /// The traffic the route applies to
+    pub dest: external::IpNet,
+    /// Where matching traffic is sent
+    pub target: RouterTarget,
+}
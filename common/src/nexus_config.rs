@@ -272,6 +272,18 @@ pub struct BackgroundTaskConfig {
     pub dns_internal: DnsTasksConfig,
     /// configuration for external DNS background tasks
     pub dns_external: DnsTasksConfig,
+    /// configuration for audit log background tasks
+    pub audit_log: AuditLogTasksConfig,
+    /// configuration for snapshot schedule background task
+    pub snapshot_schedule: SnapshotScheduleTasksConfig,
+    /// configuration for instance auto-restart background task
+    pub instance_auto_restart: InstanceAutoRestartTasksConfig,
+    /// configuration for sled health check background task
+    pub sled_health: SledHealthTasksConfig,
+    /// configuration for sled drain background task
+    pub sled_drain: SledDrainTasksConfig,
+    /// configuration for region replacement background task
+    pub region_replacement: RegionReplacementTasksConfig,
 }
 
 #[serde_as]
@@ -296,6 +308,85 @@ pub struct DnsTasksConfig {
     pub max_concurrent_server_updates: usize,
 }
 
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AuditLogTasksConfig {
+    /// period (in seconds) for periodic activations of the background task that
+    /// removes expired entries from the audit log
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub period_secs_prune: Duration,
+
+    /// number of days for which audit log entries are retained
+    pub retention_days: u32,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SnapshotScheduleTasksConfig {
+    /// period (in seconds) for periodic activations of the background task that
+    /// takes snapshots for snapshot schedules that are due
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub period_secs: Duration,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InstanceAutoRestartTasksConfig {
+    /// period (in seconds) for periodic activations of the background task that
+    /// restarts instances according to their auto-restart policies
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub period_secs: Duration,
+
+    /// how long (in seconds) an instance is left alone after it's restarted
+    /// automatically; this doubles with each further automatic restart, up to
+    /// `max_backoff_secs`
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub backoff_secs: Duration,
+
+    /// longest time (in seconds) an instance is left alone between automatic
+    /// restarts, and how long it must stay up for the backoff to start over
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub max_backoff_secs: Duration,
+
+    /// number of automatic restarts in a row after which an instance that
+    /// keeps failing is left stopped
+    pub max_attempts: u32,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SledHealthTasksConfig {
+    /// period (in seconds) for periodic activations of the background task that
+    /// checks whether each sled agent is responding
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub period_secs: Duration,
+
+    /// number of health checks in a row that a sled must miss before it's
+    /// considered unreachable rather than degraded
+    pub unreachable_after: u32,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SledDrainTasksConfig {
+    /// period (in seconds) for periodic activations of the background task that
+    /// migrates instances off of sleds that are being drained
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub period_secs: Duration,
+
+    /// maximum number of instances migrating off of draining sleds at once
+    pub max_concurrent_migrations: usize,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RegionReplacementTasksConfig {
+    /// period (in seconds) for periodic activations of the background task that
+    /// replaces Crucible regions whose datasets were lost
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub period_secs: Duration,
+}
+
 /// Configuration for a nexus server
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct PackageConfig {
@@ -396,8 +487,10 @@ mod test {
     };
     use crate::address::{Ipv6Subnet, RACK_PREFIX};
     use crate::nexus_config::{
-        BackgroundTaskConfig, Database, DeploymentConfig, DnsTasksConfig,
-        DpdConfig, LoadErrorKind,
+        AuditLogTasksConfig, BackgroundTaskConfig, Database, DeploymentConfig,
+        DnsTasksConfig, DpdConfig, InstanceAutoRestartTasksConfig,
+        LoadErrorKind, RegionReplacementTasksConfig, SledDrainTasksConfig,
+        SledHealthTasksConfig, SnapshotScheduleTasksConfig,
     };
     use dropshot::ConfigDropshot;
     use dropshot::ConfigLogging;
@@ -540,6 +633,18 @@ mod test {
             dns_external.period_secs_servers = 6
             dns_external.period_secs_propagation = 7
             dns_external.max_concurrent_server_updates = 8
+            audit_log.period_secs_prune = 9
+            audit_log.retention_days = 10
+            snapshot_schedule.period_secs = 11
+            instance_auto_restart.period_secs = 12
+            instance_auto_restart.backoff_secs = 30
+            instance_auto_restart.max_backoff_secs = 3600
+            instance_auto_restart.max_attempts = 8
+            sled_health.period_secs = 13
+            sled_health.unreachable_after = 3
+            sled_drain.period_secs = 14
+            sled_drain.max_concurrent_migrations = 4
+            region_replacement.period_secs = 15
             "##,
         )
         .unwrap();
@@ -607,6 +712,30 @@ mod test {
                             period_secs_propagation: Duration::from_secs(7),
                             max_concurrent_server_updates: 8,
                         },
+                        audit_log: AuditLogTasksConfig {
+                            period_secs_prune: Duration::from_secs(9),
+                            retention_days: 10,
+                        },
+                        snapshot_schedule: SnapshotScheduleTasksConfig {
+                            period_secs: Duration::from_secs(11),
+                        },
+                        instance_auto_restart: InstanceAutoRestartTasksConfig {
+                            period_secs: Duration::from_secs(12),
+                            backoff_secs: Duration::from_secs(30),
+                            max_backoff_secs: Duration::from_secs(3600),
+                            max_attempts: 8,
+                        },
+                        sled_health: SledHealthTasksConfig {
+                            period_secs: Duration::from_secs(13),
+                            unreachable_after: 3,
+                        },
+                        sled_drain: SledDrainTasksConfig {
+                            period_secs: Duration::from_secs(14),
+                            max_concurrent_migrations: 4,
+                        },
+                        region_replacement: RegionReplacementTasksConfig {
+                            period_secs: Duration::from_secs(15),
+                        },
                     },
                 },
             }
@@ -653,6 +782,18 @@ mod test {
             dns_external.period_secs_servers = 6
             dns_external.period_secs_propagation = 7
             dns_external.max_concurrent_server_updates = 8
+            audit_log.period_secs_prune = 9
+            audit_log.retention_days = 10
+            snapshot_schedule.period_secs = 11
+            instance_auto_restart.period_secs = 12
+            instance_auto_restart.backoff_secs = 30
+            instance_auto_restart.max_backoff_secs = 3600
+            instance_auto_restart.max_attempts = 8
+            sled_health.period_secs = 13
+            sled_health.unreachable_after = 3
+            sled_drain.period_secs = 14
+            sled_drain.max_concurrent_migrations = 4
+            region_replacement.period_secs = 15
             "##,
         )
         .unwrap();
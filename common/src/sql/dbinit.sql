@@ -62,6 +62,13 @@ CREATE TABLE omicron.public.rack (
  * Sleds
  */
 
+CREATE TYPE omicron.public.sled_state AS ENUM (
+    'active',
+    'degraded',
+    'unreachable',
+    'expunged'
+);
+
 CREATE TABLE omicron.public.sled (
     /* Identity metadata (asset) */
     id UUID PRIMARY KEY,
@@ -92,6 +99,19 @@ CREATE TABLE omicron.public.sled (
     /* The last address allocated to an Oxide service on this sled. */
     last_used_address INET NOT NULL,
 
+    /*
+     * The health of the sled as last observed by Nexus.  Only active sleds are
+     * chosen to run new instances.  An expunged sled never leaves that state.
+     */
+    state omicron.public.sled_state NOT NULL,
+    time_state_updated TIMESTAMPTZ NOT NULL,
+
+    /*
+     * Whether new instances may be placed on this sled.  An operator clears
+     * this to drain the sled of instances before servicing it.
+     */
+    schedulable BOOL NOT NULL,
+
     -- This constraint should be upheld, even for deleted disks
     -- in the fleet.
     CONSTRAINT serial_part_revision_unique UNIQUE (
@@ -141,7 +161,10 @@ CREATE TABLE omicron.public.sled_resource (
     rss_ram INT8 NOT NULL,
 
     -- The maximum amount of Reservoir RAM provisioned to this resource
-    reservoir_ram INT8 NOT NULL
+    reservoir_ram INT8 NOT NULL,
+
+    -- The instance on whose behalf the resource was reserved, if any
+    instance_id UUID
 );
 
 -- Allow looking up all resources which reside on a sled
@@ -340,6 +363,38 @@ CREATE TABLE omicron.public.virtual_provisioning_resource (
     ram_provisioned INT8 NOT NULL
 );
 
+-- Operator-set limits on the virtual resources which may be provisioned within
+-- a silo. These are enforced against the silo's row in
+-- 'virtual_provisioning_collection' whenever provisioning grows.
+--
+-- A silo without a row here, or a NULL limit, is unconstrained.
+CREATE TABLE omicron.public.silo_quotas (
+    silo_id UUID PRIMARY KEY,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+
+    -- The number of CPUs which may be provisioned by VMs.
+    cpus INT8,
+
+    -- The amount of RAM which may be provisioned by VMs.
+    memory_bytes INT8,
+
+    -- The amount of physical disk space which may be provisioned.
+    storage_bytes INT8
+);
+
+-- Silo-admin-set limits on the virtual resources which may be provisioned
+-- within a project. These have the same meaning as 'silo_quotas'.
+CREATE TABLE omicron.public.project_quotas (
+    project_id UUID PRIMARY KEY,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+
+    cpus INT8,
+    memory_bytes INT8,
+    storage_bytes INT8
+);
+
 /*
  * ZPools of Storage, attached to Sleds.
  * These are backed by a single physical disk.
@@ -481,6 +536,57 @@ CREATE INDEX on omicron.public.region_snapshot (
     snapshot_addr
 );
 
+CREATE TYPE omicron.public.region_replacement_state AS ENUM (
+    'requested',
+    'running',
+    'awaiting_restart',
+    'complete'
+);
+
+/*
+ * A request to replace a region whose dataset was lost (along with its zpool,
+ * physical disk or sled) with a new region on another dataset.
+ */
+CREATE TABLE omicron.public.region_replacement (
+    id UUID PRIMARY KEY,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+
+    /* The lost region, and the dataset that it was on */
+    old_region_id UUID NOT NULL,
+    old_dataset_id UUID NOT NULL,
+
+    /* The volume the lost region belonged to, and the disk using it */
+    volume_id UUID NOT NULL,
+    disk_id UUID NOT NULL,
+
+    /* The region that replaced the lost one, once there is one */
+    new_region_id UUID,
+
+    state omicron.public.region_replacement_state NOT NULL,
+
+    /*
+     * The instance that must be restarted or migrated, and its Propolis that
+     * still has the old region in its copy of the volume, while in state
+     * 'awaiting_restart'
+     */
+    instance_id UUID,
+    propolis_id UUID,
+
+    /* Why the last attempt at replacing the region failed, if it did */
+    error TEXT
+);
+
+/* Each lost region is replaced at most once. */
+CREATE UNIQUE INDEX ON omicron.public.region_replacement (
+    old_region_id
+);
+
+/* Quickly find replacements that still have work to do. */
+CREATE INDEX ON omicron.public.region_replacement (
+    state
+) WHERE state != 'complete';
+
 /*
  * A volume within Crucible
  */
@@ -520,7 +626,8 @@ CREATE INDEX on omicron.public.volume (
 
 CREATE TYPE omicron.public.authentication_mode AS ENUM (
   'local',
-  'saml'
+  'saml',
+  'oidc'
 );
 
 CREATE TYPE omicron.public.user_provision_type AS ENUM (
@@ -560,7 +667,13 @@ CREATE TABLE omicron.public.silo_user (
     time_deleted TIMESTAMPTZ,
 
     silo_id UUID NOT NULL,
-    external_id TEXT NOT NULL
+    external_id TEXT NOT NULL,
+
+    /*
+     * Deactivated users (e.g., by a SCIM client) keep their identity but
+     * cannot log in.
+     */
+    active BOOL NOT NULL
 );
 
 /* This index lets us quickly find users for a given silo. */
@@ -619,7 +732,8 @@ CREATE INDEX ON omicron.public.silo_group_membership (
  */
 
 CREATE TYPE omicron.public.provider_type AS ENUM (
-  'saml'
+  'saml',
+  'oidc'
 );
 
 CREATE TABLE omicron.public.identity_provider (
@@ -681,6 +795,41 @@ CREATE INDEX ON omicron.public.saml_identity_provider (
 ) WHERE
     time_deleted IS NULL;
 
+/*
+ * Silo OpenID Connect identity provider
+ */
+CREATE TABLE omicron.public.oidc_identity_provider (
+    /* Identity metadata */
+    id UUID PRIMARY KEY,
+    name STRING(63) NOT NULL,
+    description STRING(512) NOT NULL,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+    time_deleted TIMESTAMPTZ,
+
+    silo_id UUID NOT NULL,
+
+    issuer TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    client_secret TEXT,
+
+    authorization_endpoint TEXT NOT NULL,
+    token_endpoint TEXT NOT NULL,
+    jwks_uri TEXT NOT NULL,
+    redirect_uri TEXT NOT NULL,
+
+    /* space-separated list of scopes requested during login */
+    scopes TEXT NOT NULL,
+
+    group_claim_name TEXT
+);
+
+CREATE INDEX ON omicron.public.oidc_identity_provider (
+    id,
+    silo_id
+) WHERE
+    time_deleted IS NULL;
+
 /*
  * Users' public SSH keys, per RFD 44
  */
@@ -722,6 +871,9 @@ CREATE TABLE omicron.public.project (
     /* Indicates that the object has been deleted */
     time_deleted TIMESTAMPTZ,
 
+    /* User-provided key/value labels, as a JSON object */
+    labels JSONB NOT NULL,
+
     /* child resource generation number, per RFD 192 */
     rcgen INT NOT NULL,
 
@@ -752,6 +904,12 @@ CREATE TYPE omicron.public.instance_state AS ENUM (
     'destroyed'
 );
 
+CREATE TYPE omicron.public.instance_auto_restart_policy AS ENUM (
+    'never',
+    'on_failure',
+    'always'
+);
+
 /*
  * TODO consider how we want to manage multiple sagas operating on the same
  * Instance -- e.g., reboot concurrent with destroy or concurrent reboots or the
@@ -768,6 +926,9 @@ CREATE TABLE omicron.public.instance (
     /* This is redundant for Instances, but we keep it here for consistency. */
     time_deleted TIMESTAMPTZ,
 
+    /* User-provided key/value labels, as a JSON object */
+    labels JSONB NOT NULL,
+
     /* Every Instance is in exactly one Project at a time. */
     project_id UUID NOT NULL,
 
@@ -814,7 +975,21 @@ CREATE TABLE omicron.public.instance (
     /* Instance configuration */
     ncpus INT NOT NULL,
     memory INT NOT NULL,
-    hostname STRING(63) NOT NULL
+    hostname STRING(63) NOT NULL,
+
+    /* Whether Nexus starts the instance again on its own */
+    auto_restart_policy omicron.public.instance_auto_restart_policy NOT NULL,
+    /*
+     * Set when the instance was stopped through the API (or created without
+     * being started), so that an "always" policy doesn't start it again.
+     */
+    stop_requested BOOL NOT NULL,
+    /*
+     * Number of times in a row that Nexus has restarted the instance on its
+     * own, and the earliest time it may do so again.
+     */
+    auto_restart_count INT8 CHECK (auto_restart_count BETWEEN 0 AND 4294967295) NOT NULL,
+    time_auto_restart_next TIMESTAMPTZ
 );
 
 -- Names for instances within a project should be unique
@@ -831,6 +1006,29 @@ CREATE INDEX ON omicron.public.instance (
 ) WHERE
     time_deleted IS NULL;
 
+-- Allow finding instances that may need to be restarted automatically.
+CREATE INDEX ON omicron.public.instance (
+    state
+) WHERE
+    time_deleted IS NULL AND auto_restart_policy != 'never';
+
+/*
+ * Each Propolis that Nexus has registered an instance with, numbered by boot
+ * generation, and the sled it was on.  This is how an instance's recorded
+ * serial console output is found, since different boots may have run on
+ * different sleds.
+ */
+CREATE TABLE omicron.public.instance_boot (
+    instance_id UUID NOT NULL,
+    /* Counts up from 1 for each instance */
+    boot_generation INT NOT NULL,
+    time_created TIMESTAMPTZ NOT NULL,
+    sled_id UUID NOT NULL,
+    propolis_id UUID NOT NULL,
+
+    PRIMARY KEY (instance_id, boot_generation)
+);
+
 /*
  * Guest-Visible, Virtual Disks
  */
@@ -873,6 +1071,9 @@ CREATE TABLE omicron.public.disk (
     /* This is redundant for Disks, but we keep it here for consistency. */
     time_deleted TIMESTAMPTZ,
 
+    /* User-provided key/value labels, as a JSON object */
+    labels JSONB NOT NULL,
+
     /* child resource generation number, per RFD 192 */
     rcgen INT NOT NULL,
 
@@ -927,6 +1128,9 @@ CREATE TABLE omicron.public.image (
     /* Indicates that the object has been deleted */
     time_deleted TIMESTAMPTZ,
 
+    /* User-provided key/value labels, as a JSON object */
+    labels JSONB NOT NULL,
+
     silo_id UUID NOT NULL,
     project_id UUID,
 
@@ -948,6 +1152,7 @@ SELECT
     time_created,
     time_modified,
     time_deleted,
+    labels,
     silo_id,
     project_id,
     volume_id,
@@ -970,6 +1175,7 @@ SELECT
     time_created,
     time_modified,
     time_deleted,
+    labels,
     silo_id,
     volume_id,
     url,
@@ -1033,6 +1239,9 @@ CREATE TABLE omicron.public.snapshot (
     /* Indicates that the object has been deleted */
     time_deleted TIMESTAMPTZ,
 
+    /* User-provided key/value labels, as a JSON object */
+    labels JSONB NOT NULL,
+
     /* Every Snapshot is in exactly one Project at a time. */
     project_id UUID NOT NULL,
 
@@ -1050,7 +1259,10 @@ CREATE TABLE omicron.public.snapshot (
     block_size omicron.public.block_size NOT NULL,
 
     /* Disk configuration (from the time the snapshot was taken) */
-    size_bytes INT NOT NULL
+    size_bytes INT NOT NULL,
+
+    /* The snapshot schedule that took this snapshot, if any */
+    snapshot_schedule_id UUID
 );
 
 CREATE UNIQUE INDEX ON omicron.public.snapshot (
@@ -1059,6 +1271,122 @@ CREATE UNIQUE INDEX ON omicron.public.snapshot (
 ) WHERE
     time_deleted IS NULL;
 
+/* Allow finding the snapshots taken by a snapshot schedule, newest first */
+CREATE INDEX ON omicron.public.snapshot (
+    snapshot_schedule_id,
+    time_created DESC
+) WHERE
+    snapshot_schedule_id IS NOT NULL AND time_deleted IS NULL;
+
+/*
+ * Schedules for periodically snapshotting a disk
+ */
+CREATE TABLE omicron.public.snapshot_schedule (
+    /* Identity metadata (resource) */
+    id UUID PRIMARY KEY,
+    name STRING(63) NOT NULL,
+    description STRING(512) NOT NULL,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+    /* Indicates that the object has been deleted */
+    time_deleted TIMESTAMPTZ,
+
+    /* Every snapshot schedule is in exactly one Project at a time. */
+    project_id UUID NOT NULL,
+
+    /* The disk to snapshot */
+    disk_id UUID NOT NULL,
+
+    /*
+     * The Silo user that created the schedule.  Snapshots are taken (and
+     * expired) on this user's behalf.
+     */
+    silo_id UUID NOT NULL,
+    silo_user_id UUID NOT NULL,
+
+    /* How often to take a snapshot */
+    interval_minutes INT8 NOT NULL CHECK (interval_minutes > 0),
+
+    /*
+     * How many of the snapshots taken by this schedule to keep.  Older ones
+     * are deleted.
+     */
+    retention_count INT8 NOT NULL CHECK (retention_count > 0),
+
+    /* When the next snapshot is due */
+    time_next_snapshot TIMESTAMPTZ NOT NULL,
+
+    /* Status of the most recent attempt to take a snapshot */
+    time_last_snapshot TIMESTAMPTZ,
+    last_error STRING
+);
+
+CREATE UNIQUE INDEX ON omicron.public.snapshot_schedule (
+    project_id,
+    name
+) WHERE
+    time_deleted IS NULL;
+
+/* Allow finding schedules that are due */
+CREATE INDEX ON omicron.public.snapshot_schedule (
+    time_next_snapshot
+) WHERE
+    time_deleted IS NULL;
+
+/*
+ * Affinity and anti-affinity groups for instance placement
+ */
+
+CREATE TYPE omicron.public.affinity_group_kind AS ENUM (
+  'affinity',
+  'anti_affinity'
+);
+
+CREATE TYPE omicron.public.affinity_policy AS ENUM (
+  'fail',
+  'allow'
+);
+
+CREATE TABLE omicron.public.affinity_group (
+    /* Identity metadata (resource) */
+    id UUID PRIMARY KEY,
+    name STRING(63) NOT NULL,
+    description STRING(512) NOT NULL,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+    /* Indicates that the object has been deleted */
+    time_deleted TIMESTAMPTZ,
+
+    /* Every affinity group is in exactly one Project at a time. */
+    project_id UUID NOT NULL,
+
+    /* Whether members should be placed together or apart */
+    kind omicron.public.affinity_group_kind NOT NULL,
+    /*
+     * What to do when the group's rule cannot be satisfied: "fail" refuses
+     * to place the instance, "allow" places it anyway.
+     */
+    policy omicron.public.affinity_policy NOT NULL
+);
+
+CREATE UNIQUE INDEX ON omicron.public.affinity_group (
+    project_id,
+    name
+) WHERE
+    time_deleted IS NULL;
+
+CREATE TABLE omicron.public.affinity_group_instance_membership (
+    group_id UUID NOT NULL,
+    instance_id UUID NOT NULL,
+
+    PRIMARY KEY (group_id, instance_id)
+);
+
+/* Allow finding all groups an instance belongs to */
+CREATE INDEX ON omicron.public.affinity_group_instance_membership (
+    instance_id
+);
+
 /*
  * Oximeter collector servers.
  */
@@ -1105,6 +1433,8 @@ CREATE TABLE omicron.public.vpc (
     time_modified TIMESTAMPTZ NOT NULL,
     /* Indicates that the object has been deleted */
     time_deleted TIMESTAMPTZ,
+    /* User-provided key/value labels, as a JSON object */
+    labels JSONB NOT NULL,
     project_id UUID NOT NULL,
     system_router_id UUID NOT NULL,
     dns_name STRING(63) NOT NULL,
@@ -1151,7 +1481,9 @@ CREATE TABLE omicron.public.vpc_subnet (
     /* Child resource creation generation number */
     rcgen INT8 NOT NULL,
     ipv4_block INET NOT NULL,
-    ipv6_block INET NOT NULL
+    ipv6_block INET NOT NULL,
+    /* The custom router whose routes apply to this subnet, if any */
+    custom_router_id UUID
 );
 
 /* Subnet and network interface names are unique per VPC, not project */
@@ -1379,6 +1711,41 @@ CREATE UNIQUE INDEX ON omicron.public.vpc_router (
 ) WHERE
     time_deleted IS NULL;
 
+/*
+ * A peering connects two VPCs in the same Silo, so that instances in either
+ * VPC can reach instances in the other over their private addresses.
+ *
+ * The peering is created from (and is a child of) `vpc_id`, but is symmetric:
+ * it also applies to `peer_vpc_id`.  A pair of VPCs is peered at most once,
+ * in either direction, which is checked when the peering is created.
+ */
+CREATE TABLE omicron.public.vpc_peering (
+    /* Identity metadata (resource) */
+    id UUID PRIMARY KEY,
+    name STRING(63) NOT NULL,
+    description STRING(512) NOT NULL,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+    /* Indicates that the object has been deleted */
+    time_deleted TIMESTAMPTZ,
+    vpc_id UUID NOT NULL,
+    peer_vpc_id UUID NOT NULL,
+
+    CONSTRAINT distinct_vpcs CHECK (vpc_id != peer_vpc_id)
+);
+
+CREATE UNIQUE INDEX ON omicron.public.vpc_peering (
+    vpc_id,
+    name
+) WHERE
+    time_deleted IS NULL;
+
+/* Used to find the peerings of a VPC from either side */
+CREATE INDEX ON omicron.public.vpc_peering (
+    peer_vpc_id
+) WHERE
+    time_deleted IS NULL;
+
 CREATE TYPE omicron.public.router_route_kind AS ENUM (
     'default',
     'vpc_subnet',
@@ -1535,6 +1902,9 @@ CREATE TABLE omicron.public.external_ip (
     /* The last port in the allowed range, also inclusive. */
     last_port INT4 NOT NULL,
 
+    /* FK to the `project` table. See the constraints below. */
+    project_id UUID,
+
     /* The name must be non-NULL iff this is a floating IP. */
     CONSTRAINT null_fip_name CHECK (
         (kind != 'floating' AND name IS NULL) OR
@@ -1555,6 +1925,12 @@ CREATE TABLE omicron.public.external_ip (
         (kind != 'floating' AND instance_id IS NOT NULL) OR
         (kind = 'floating') OR
         (kind = 'service')
+    ),
+
+    /* The project must be non-NULL iff this is a floating IP. */
+    CONSTRAINT null_project_id CHECK (
+        (kind != 'floating' AND project_id IS NULL) OR
+        (kind = 'floating' AND project_id IS NOT NULL)
     )
 );
 
@@ -1587,6 +1963,32 @@ CREATE UNIQUE INDEX ON omicron.public.external_ip (
 )
     WHERE instance_id IS NOT NULL AND time_deleted IS NULL;
 
+/* Floating IPs are named uniquely within their project. */
+CREATE UNIQUE INDEX ON omicron.public.external_ip (
+    project_id,
+    name
+)
+    WHERE kind = 'floating' AND time_deleted IS NULL;
+
+/* The external IPs of the floating kind, as project-scoped API resources. */
+CREATE VIEW omicron.public.floating_ip AS
+SELECT
+    id,
+    name,
+    description,
+    time_created,
+    time_modified,
+    time_deleted,
+    ip_pool_id,
+    ip_pool_range_id,
+    instance_id,
+    ip,
+    project_id
+FROM
+    omicron.public.external_ip
+WHERE
+    kind = 'floating' AND project_id IS NOT NULL;
+
 /*******************************************************************/
 
 /*
@@ -2073,6 +2475,64 @@ CREATE INDEX ON omicron.public.device_access_token (
     silo_user_id
 );
 
+-- Named, long-lived access tokens that users create for themselves, e.g., for
+-- use by automation.  These are presented in the same way as tokens granted by
+-- the device authorization flow.
+CREATE TABLE omicron.public.access_token (
+    /* Identity metadata (resource) */
+    id UUID PRIMARY KEY,
+    name STRING(63) NOT NULL,
+    description STRING(512) NOT NULL,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+    time_deleted TIMESTAMPTZ,
+
+    /* FK into silo_user table */
+    silo_user_id UUID NOT NULL,
+
+    token STRING(40) NOT NULL,
+    time_expires TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX ON omicron.public.access_token (
+    token
+);
+
+CREATE UNIQUE INDEX ON omicron.public.access_token (
+    silo_user_id,
+    name
+) WHERE
+    time_deleted IS NULL;
+
+/*
+ * Bearer tokens with which an external identity provider authenticates to a
+ * Silo's SCIM 2.0 provisioning endpoint.
+ */
+CREATE TABLE omicron.public.scim_client_token (
+    /* Identity metadata (resource) */
+    id UUID PRIMARY KEY,
+    name STRING(63) NOT NULL,
+    description STRING(512) NOT NULL,
+    time_created TIMESTAMPTZ NOT NULL,
+    time_modified TIMESTAMPTZ NOT NULL,
+    time_deleted TIMESTAMPTZ,
+
+    silo_id UUID NOT NULL,
+
+    token STRING(40) NOT NULL,
+    time_expires TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX ON omicron.public.scim_client_token (
+    token
+);
+
+CREATE UNIQUE INDEX ON omicron.public.scim_client_token (
+    silo_id,
+    name
+) WHERE
+    time_deleted IS NULL;
+
 /*
  * Roles built into the system
  *
@@ -2184,6 +2644,92 @@ CREATE TABLE omicron.public.role_assignment (
 
 /*******************************************************************/
 
+/*
+ * Audit log
+ *
+ * Nexus records an entry here for each external API request that may modify
+ * the state of the system (i.e., every request other than GET and HEAD).  The
+ * entry is recorded before the request is handled, and the request is
+ * rejected if that fails.  Once the request has been handled, its entry is
+ * completed with the result.
+ *
+ * Completed entries form hash chains, one per Silo (identified by the Silo's
+ * id) plus one for requests not made by a Silo user (identified by the
+ * fleet's id).  Each entry's "hash" covers its own contents along with the
+ * "hash" of the entry before it in the same chain (its "prev_hash"), and
+ * "chain_seq" gives its position in that chain.  Modifying or removing an
+ * entry (other than the oldest ones, which are periodically pruned) breaks
+ * the chain.  "seq" orders entries across all chains.
+ */
+CREATE SEQUENCE omicron.public.audit_log_seq;
+
+CREATE TABLE omicron.public.audit_log (
+    id UUID PRIMARY KEY,
+    seq INT8 NOT NULL,
+    chain_id UUID NOT NULL,
+
+    time_started TIMESTAMPTZ NOT NULL,
+    /* Unset until the request has been handled */
+    time_completed TIMESTAMPTZ,
+    request_id STRING NOT NULL,
+    http_method STRING(15) NOT NULL,
+    request_uri STRING NOT NULL,
+
+    /* The authenticated actor, if any, and their Silo */
+    actor_id UUID,
+    actor_silo_id UUID,
+
+    /* Ids of resources referenced by the request's URI */
+    resource_ids UUID[] NOT NULL,
+    /* HTTP status code of the response */
+    result_code INT4,
+
+    /* Position in the chain, and hex-encoded SHA-256 hashes */
+    chain_seq INT8,
+    prev_hash STRING(64),
+    hash STRING(64),
+
+    CONSTRAINT completed_entries_are_chained CHECK (
+        (time_completed IS NULL AND result_code IS NULL AND
+         chain_seq IS NULL AND prev_hash IS NULL AND hash IS NULL) OR
+        (time_completed IS NOT NULL AND result_code IS NOT NULL AND
+         chain_seq IS NOT NULL AND prev_hash IS NOT NULL AND hash IS NOT NULL)
+    )
+);
+
+CREATE UNIQUE INDEX ON omicron.public.audit_log (
+    seq
+);
+
+CREATE UNIQUE INDEX ON omicron.public.audit_log (
+    chain_id,
+    chain_seq
+) WHERE chain_seq IS NOT NULL;
+
+CREATE INDEX ON omicron.public.audit_log (
+    time_started
+);
+
+CREATE INDEX ON omicron.public.audit_log (
+    actor_id,
+    seq
+) WHERE actor_id IS NOT NULL;
+
+/*
+ * The latest entry in each chain of the audit log
+ *
+ * Completing an entry locks its chain's row here, so entries in the same chain
+ * are appended one at a time.  This outlives the entries themselves so that
+ * pruning the log doesn't break the chain.
+ */
+CREATE TABLE omicron.public.audit_log_chain (
+    id UUID PRIMARY KEY,
+    seq INT8 NOT NULL,
+    hash STRING(64) NOT NULL
+);
+
+/*******************************************************************/
+
 /*
  * Metadata for the schema itself.  This version number isn't great, as there's
  * nothing to ensure it gets bumped when it should be, but it's a start.
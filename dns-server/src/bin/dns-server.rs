@@ -8,11 +8,17 @@
 use anyhow::anyhow;
 use anyhow::Context;
 use clap::Parser;
+use internal_dns::resolver::Resolver;
+use internal_dns::ServiceName;
+use omicron_common::address::NEXUS_INTERNAL_PORT;
+use omicron_common::api::internal::nexus::ProducerEndpoint;
+use omicron_common::backoff;
 use serde::Deserialize;
-use slog::info;
 use slog::o;
+use slog::{debug, info, warn};
 use std::net::{SocketAddr, SocketAddrV6};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -31,6 +37,17 @@ pub struct Config {
     pub log: dropshot::ConfigLogging,
     pub dropshot: dropshot::ConfigDropshot,
     pub storage: dns_server::storage::Config,
+    #[serde(default)]
+    pub limits: dns_server::dns_server::Limits,
+    pub metrics: Option<MetricsConfig>,
+}
+
+/// Configuration for reporting metrics to oximeter
+#[derive(Deserialize, Debug)]
+pub struct MetricsConfig {
+    /// address of the Nexus internal API, with which we register as a metric
+    /// producer (if not given, Nexus is found through internal DNS)
+    pub nexus_address: Option<SocketAddr>,
 }
 
 #[tokio::main]
@@ -50,8 +67,10 @@ async fn main() -> Result<(), anyhow::Error> {
         .to_logger("dns-server")
         .context("failed to create logger")?;
 
-    let dns_server_config =
-        dns_server::dns_server::Config { bind_address: args.dns_address };
+    let dns_server_config = dns_server::dns_server::Config {
+        bind_address: args.dns_address,
+        limits: config.limits.clone(),
+    };
 
     info!(&log, "config";
         "config" => ?config,
@@ -65,14 +84,84 @@ async fn main() -> Result<(), anyhow::Error> {
     .context("initializing persistent storage")?;
 
     let (_dns_server, dropshot_server) = dns_server::start_servers(
-        log,
+        log.clone(),
         store,
         &dns_server_config,
         &config.dropshot,
     )
     .await?;
 
+    if let Some(metrics) = &config.metrics {
+        let producer_endpoint = ProducerEndpoint {
+            id: dropshot_server.app_private().producer_registry().producer_id(),
+            address: dropshot_server.local_addr(),
+            base_route: String::from("/metrics/collect"),
+            interval: Duration::from_secs(10),
+        };
+        let resolver = Resolver::new_from_ip(
+            log.new(o!("component" => "DnsResolver")),
+            *args.http_address.ip(),
+        )
+        .context("creating internal DNS resolver")?;
+        tokio::spawn(register_as_producer(
+            log.new(o!("component" => "metrics")),
+            metrics.nexus_address,
+            resolver,
+            producer_endpoint,
+        ));
+    }
+
     dropshot_server
         .await
         .map_err(|error_message| anyhow!("server exiting: {}", error_message))
 }
+
+/// Registers the DNS server as a metric producer with Nexus, retrying until
+/// that succeeds
+///
+/// If `nexus_address` isn't given, Nexus is looked up in internal DNS on each
+/// attempt.
+async fn register_as_producer(
+    log: slog::Logger,
+    nexus_address: Option<SocketAddr>,
+    resolver: Resolver,
+    producer_endpoint: ProducerEndpoint,
+) {
+    let register = || async {
+        let nexus_address = match nexus_address {
+            Some(address) => address,
+            None => SocketAddr::V6(SocketAddrV6::new(
+                resolver.lookup_ipv6(ServiceName::Nexus).await.map_err(
+                    |e| backoff::BackoffError::transient(e.to_string()),
+                )?,
+                NEXUS_INTERNAL_PORT,
+                0,
+                0,
+            )),
+        };
+        debug!(log, "registering as metric producer";
+            "nexus_address" => %nexus_address,
+        );
+        oximeter_producer::register(nexus_address, &log, &producer_endpoint)
+            .await
+            .map_err(|e| backoff::BackoffError::transient(e.to_string()))
+    };
+    let log_registration_failure = |error, delay| {
+        warn!(
+            log,
+            "failed to register as a metric producer, will retry in {:?}",
+            delay;
+            "error_message" => ?error,
+        );
+    };
+    backoff::retry_notify(
+        backoff::retry_policy_internal_service(),
+        register,
+        log_registration_failure,
+    )
+    .await
+    .expect("expected an infinite retry loop registering as a metric producer");
+    info!(log, "registered as metric producer";
+        "producer_id" => %producer_endpoint.id,
+    );
+}
@@ -18,7 +18,7 @@ use anyhow::Result;
 use clap::{Args, Parser, Subcommand};
 use dns_service_client::types::DnsConfig;
 use dns_service_client::{
-    types::{DnsConfigParams, DnsConfigZone, DnsRecord, Srv},
+    types::{DnsConfigParams, DnsConfigZone, DnsRecord, Mx, Srv},
     Client,
 };
 use slog::{Drain, Logger};
@@ -47,6 +47,16 @@ enum SubCommand {
     AddAAAA(AddAAAACommand),
     /// Add a SRV record (non-transactionally) to the DNS server
     AddSRV(AddSRVCommand),
+    /// Add a CNAME record (non-transactionally) to the DNS server
+    AddCNAME(AddNameCommand),
+    /// Add a TXT record (non-transactionally) to the DNS server
+    AddTXT(AddTXTCommand),
+    /// Add a PTR record (non-transactionally) to the DNS server
+    AddPTR(AddNameCommand),
+    /// Add an MX record (non-transactionally) to the DNS server
+    AddMX(AddMXCommand),
+    /// Add an NS record (non-transactionally) to the DNS server
+    AddNS(AddNameCommand),
     /// Delete all records for a name (non-transactionally) in the DNS server
     DeleteRecord(DeleteRecordCommand),
 }
@@ -99,6 +109,49 @@ struct AddSRVCommand {
     target: String,
 }
 
+/// Used for record types (CNAME, PTR, NS) whose only data is another name
+#[derive(Debug, Args)]
+struct AddNameCommand {
+    /// name of one of the server's DNS zones (under ".oxide.test")
+    #[clap(action)]
+    zone_name: String,
+    /// name under which the new record should be added
+    #[clap(action)]
+    name: String,
+    /// name that the new record points to
+    #[clap(action)]
+    target: String,
+}
+
+#[derive(Debug, Args)]
+struct AddTXTCommand {
+    /// name of one of the server's DNS zones (under ".oxide.test")
+    #[clap(action)]
+    zone_name: String,
+    /// name under which the new record should be added
+    #[clap(action)]
+    name: String,
+    /// strings making up the new TXT record
+    #[clap(action, required = true)]
+    strings: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct AddMXCommand {
+    /// name of one of the server's DNS zones (under ".oxide.test")
+    #[clap(action)]
+    zone_name: String,
+    /// name under which the new record should be added
+    #[clap(action)]
+    name: String,
+    /// new MX record preference
+    #[clap(action)]
+    preference: u16,
+    /// new MX record mail exchange
+    #[clap(action)]
+    exchange: String,
+}
+
 #[derive(Debug, Args)]
 struct DeleteRecordCommand {
     /// name of one of the server's DNS zones (under ".oxide.test")
@@ -152,6 +205,25 @@ async fn main() -> Result<()> {
                                     srv.weight
                                 );
                             }
+                            DnsRecord::Cname(target) => {
+                                println!("        CNAME: {}", target);
+                            }
+                            DnsRecord::Txt(strings) => {
+                                println!("        TXT:  {:?}", strings);
+                            }
+                            DnsRecord::Ptr(target) => {
+                                println!("        PTR:  {}", target);
+                            }
+                            DnsRecord::Mx(mx) => {
+                                println!("        MX:   {}", mx.exchange);
+                                println!(
+                                    "              preference {}",
+                                    mx.preference
+                                );
+                            }
+                            DnsRecord::Ns(target) => {
+                                println!("        NS:   {}", target);
+                            }
                         }
                     }
                 }
@@ -196,6 +268,64 @@ async fn main() -> Result<()> {
             client.dns_config_put(&new_config).await.context("updating DNS")?;
         }
 
+        SubCommand::AddCNAME(cmd) => {
+            let old_config = client.dns_config_get().await?.into_inner();
+            let new_config = add_record(
+                old_config,
+                &cmd.zone_name,
+                &cmd.name,
+                DnsRecord::Cname(cmd.target),
+            )?;
+            client.dns_config_put(&new_config).await.context("updating DNS")?;
+        }
+
+        SubCommand::AddTXT(cmd) => {
+            let old_config = client.dns_config_get().await?.into_inner();
+            let new_config = add_record(
+                old_config,
+                &cmd.zone_name,
+                &cmd.name,
+                DnsRecord::Txt(cmd.strings),
+            )?;
+            client.dns_config_put(&new_config).await.context("updating DNS")?;
+        }
+
+        SubCommand::AddPTR(cmd) => {
+            let old_config = client.dns_config_get().await?.into_inner();
+            let new_config = add_record(
+                old_config,
+                &cmd.zone_name,
+                &cmd.name,
+                DnsRecord::Ptr(cmd.target),
+            )?;
+            client.dns_config_put(&new_config).await.context("updating DNS")?;
+        }
+
+        SubCommand::AddMX(cmd) => {
+            let old_config = client.dns_config_get().await?.into_inner();
+            let new_config = add_record(
+                old_config,
+                &cmd.zone_name,
+                &cmd.name,
+                DnsRecord::Mx(Mx {
+                    preference: cmd.preference,
+                    exchange: cmd.exchange,
+                }),
+            )?;
+            client.dns_config_put(&new_config).await.context("updating DNS")?;
+        }
+
+        SubCommand::AddNS(cmd) => {
+            let old_config = client.dns_config_get().await?.into_inner();
+            let new_config = add_record(
+                old_config,
+                &cmd.zone_name,
+                &cmd.name,
+                DnsRecord::Ns(cmd.target),
+            )?;
+            client.dns_config_put(&new_config).await.context("updating DNS")?;
+        }
+
         SubCommand::DeleteRecord(cmd) => {
             let old_config = client.dns_config_get().await?.into_inner();
             verify_zone_name(&cmd.zone_name)?;
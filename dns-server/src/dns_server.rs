@@ -4,13 +4,20 @@
 
 //! Guts of the DNS (protocol) server within our DNS server program
 //!
-//! The facilities here handle binding a UDP socket, receiving DNS messages on
-//! that socket, and replying to them.
+//! The facilities here handle binding UDP and TCP sockets, receiving DNS
+//! messages on them, and replying to them.
 
 use crate::dns_types::DnsRecord;
+use crate::metrics::Counters;
+use crate::rate_limit;
+use crate::rate_limit::RateLimiter;
+use crate::rate_limit::Verdict;
 use crate::storage;
+use crate::storage::Delegation;
 use crate::storage::QueryError;
 use crate::storage::Store;
+use crate::storage::Zone;
+use crate::storage::ZoneContents;
 use anyhow::anyhow;
 use anyhow::Context;
 use pretty_hex::*;
@@ -20,11 +27,24 @@ use slog::{debug, error, o, Logger};
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
 use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
 use trust_dns_proto::op::header::Header;
 use trust_dns_proto::op::response_code::ResponseCode;
+use trust_dns_proto::op::Edns;
+use trust_dns_proto::rr::rdata::MX;
+use trust_dns_proto::rr::rdata::SOA;
 use trust_dns_proto::rr::rdata::SRV;
+use trust_dns_proto::rr::rdata::TXT;
 use trust_dns_proto::rr::record_data::RData;
 use trust_dns_proto::rr::record_type::RecordType;
 use trust_dns_proto::rr::{Name, Record};
@@ -35,11 +55,83 @@ use trust_dns_server::authority::MessageResponse;
 use trust_dns_server::authority::{MessageRequest, MessageResponseBuilder};
 use uuid::Uuid;
 
+/// Mailbox (within each zone) of the person responsible for the zone, as
+/// reported in its SOA record
+const SOA_RNAME: &str = "admin";
+/// How often secondary servers should check for new versions of a zone
+const SOA_REFRESH_SECS: i32 = 3600;
+/// How long secondary servers should wait before retrying a failed refresh
+const SOA_RETRY_SECS: i32 = 600;
+/// How long secondary servers may keep serving a zone they can't refresh
+const SOA_EXPIRE_SECS: i32 = 18000;
+/// How long resolvers may cache negative answers
+///
+/// Our DNS data changes whenever the control plane does, and none of our
+/// records are cacheable (they have TTL 0), so negative answers aren't either.
+const SOA_MINIMUM_SECS: u32 = 0;
+
+/// Size of the largest response we'll send over UDP to a client that doesn't
+/// use EDNS (RFC 1035 section 4.2.1)
+const UDP_MIN_PAYLOAD: u16 = 512;
+/// Size of the largest response we'll send over UDP to any client
+///
+/// This is the value recommended by DNS Flag Day 2020 to avoid IP
+/// fragmentation.  Clients needing larger responses can use TCP.
+const UDP_MAX_PAYLOAD: u16 = 1232;
+
+/// How long to wait before accepting TCP connections again after the first
+/// of a run of failures to accept one
+const TCP_ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+/// The longest we'll wait before accepting TCP connections again after a
+/// failure to accept one
+const TCP_ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+/// Maximum number of records in each message of a zone transfer
+///
+/// This keeps each message well under the 64 KiB limit for messages over TCP.
+const XFR_RECORDS_PER_MESSAGE: usize = 100;
+/// How long we'll keep a TCP connection open without hearing from the client
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of CNAME records we'll follow when answering a query
+///
+/// This bounds the work we do for chains that are very long or that loop.
+const MAX_CNAME_CHAIN: usize = 8;
+
 /// Configuration related to the DNS server
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     /// The address to listen for DNS requests on
     pub bind_address: SocketAddr,
+    /// Bounds on the work that the server will do for its clients
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+/// Bounds on the work that the DNS server will do for its clients
+///
+/// Work beyond these limits is shed rather than queued: the server doesn't
+/// reply to UDP queries that it has no room for (clients will retry) and it
+/// closes TCP connections that it has no room for as soon as they're accepted.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Limits {
+    /// maximum number of UDP queries that we'll handle at once
+    pub max_concurrent_udp_requests: usize,
+    /// maximum number of TCP connections that we'll have open at once
+    pub max_tcp_connections: usize,
+    /// how to limit the rate of responses over UDP to each client network
+    ///
+    /// If unspecified, responses aren't rate-limited.
+    pub rate_limit: Option<rate_limit::Config>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_concurrent_udp_requests: 1024,
+            max_tcp_connections: 128,
+            rate_limit: None,
+        }
+    }
 }
 
 /// Handle to the DNS server
@@ -47,6 +139,7 @@ pub struct Config {
 /// Dropping this handle shuts down the DNS server.
 pub struct ServerHandle {
     local_address: SocketAddr,
+    counters: Arc<Counters>,
     handle: tokio::task::JoinHandle<anyhow::Result<()>>,
 }
 
@@ -60,6 +153,11 @@ impl ServerHandle {
     pub fn local_address(&self) -> &SocketAddr {
         &self.local_address
     }
+
+    /// Returns the counters of queries that the server declined to answer
+    pub fn counters(&self) -> &Arc<Counters> {
+        &self.counters
+    }
 }
 
 /// DNS (protocol) server
@@ -70,15 +168,43 @@ pub struct Server {
     log: Logger,
     store: storage::Store,
     server_socket: Arc<UdpSocket>,
+    tcp_listener: TcpListener,
+    udp_requests: Arc<Semaphore>,
+    tcp_connections: Arc<Semaphore>,
+    rate_limiter: Option<RateLimiter>,
+    counters: Arc<Counters>,
 }
 
 impl Server {
     /// Starts a DNS server whose DNS data comes from the given `store`
+    ///
+    /// The server listens for requests over both UDP and TCP on the same port.
     pub async fn start(
         log: Logger,
         store: storage::Store,
         config: &Config,
     ) -> anyhow::Result<ServerHandle> {
+        let limits = &config.limits;
+        let rate_limiter = limits
+            .rate_limit
+            .clone()
+            .map(RateLimiter::new)
+            .transpose()
+            .context("DNS server start: invalid rate limit configuration")?;
+        for (what, limit) in [
+            ("max_concurrent_udp_requests", limits.max_concurrent_udp_requests),
+            ("max_tcp_connections", limits.max_tcp_connections),
+        ] {
+            if limit > Semaphore::MAX_PERMITS {
+                anyhow::bail!(
+                    "DNS server start: {} must be at most {} (found {})",
+                    what,
+                    Semaphore::MAX_PERMITS,
+                    limit
+                );
+            }
+        }
+
         let server_socket = Arc::new(
             UdpSocket::bind(config.bind_address).await.with_context(|| {
                 format!(
@@ -92,16 +218,42 @@ impl Server {
             "DNS server start: failed to get local address of bound socket",
         )?;
 
+        // Bind the TCP listener to the address that we actually got for UDP,
+        // since the configured port may have been 0.
+        let tcp_listener =
+            TcpListener::bind(local_address).await.with_context(|| {
+                format!("DNS server start: TCP bind to {:?}", local_address)
+            })?;
+
         info!(&log, "DNS server bound to address";
             "local_address" => ?local_address
         );
 
-        let server = Server { log, store, server_socket };
+        let counters = Arc::new(Counters::default());
+        let server = Server {
+            log,
+            store,
+            server_socket,
+            tcp_listener,
+            udp_requests: Arc::new(Semaphore::new(
+                limits.max_concurrent_udp_requests,
+            )),
+            tcp_connections: Arc::new(Semaphore::new(
+                limits.max_tcp_connections,
+            )),
+            rate_limiter,
+            counters: counters.clone(),
+        };
         let handle = tokio::task::spawn(server.run());
-        Ok(ServerHandle { local_address, handle })
+        Ok(ServerHandle { local_address, counters, handle })
     }
 
     async fn run(self) -> anyhow::Result<()> {
+        tokio::try_join!(self.run_udp(), self.run_tcp())?;
+        Ok(())
+    }
+
+    async fn run_udp(&self) -> anyhow::Result<()> {
         // The guts of the DNS server: read packets from the bound socket and
         // handle them.
         loop {
@@ -113,40 +265,224 @@ impl Server {
                 .context("receiving packet from UDP listen socket")?;
             buf.resize(n, 0);
 
+            // Don't do any work for clients whose networks are over their
+            // rate limit, except to send the occasional truncated response.
+            let verdict = match &self.rate_limiter {
+                Some(rate_limiter) => rate_limiter.check(client_addr.ip()),
+                None => Verdict::Respond,
+            };
+            if verdict != Verdict::Respond {
+                self.counters.query_rate_limited();
+                if verdict == Verdict::Drop {
+                    debug!(&self.log, "dropping rate-limited query";
+                        "peer_addr" => client_addr.to_string(),
+                    );
+                    continue;
+                }
+            }
+
+            // If we're already handling as many requests as we're willing to,
+            // drop this one.  The client will retry.
+            let Ok(permit) = self.udp_requests.clone().try_acquire_owned()
+            else {
+                self.counters.query_dropped();
+                debug!(&self.log, "dropping query: too many in progress";
+                    "peer_addr" => client_addr.to_string(),
+                );
+                continue;
+            };
+
             let req_id = Uuid::new_v4();
             let log = self.log.new(o!(
                 "req_id" => req_id.to_string(),
                 "peer_addr" => client_addr.to_string(),
+                "transport" => "udp",
             ));
 
             let request = Request {
                 log,
                 store: self.store.clone(),
-                socket: self.server_socket.clone(),
+                transport: Transport::Udp(self.server_socket.clone()),
                 client_addr,
                 packet: buf,
+                max_response_size: UDP_MIN_PAYLOAD,
+                truncate: verdict == Verdict::Slip,
                 req_id,
             };
 
-            // TODO-robustness We should cap the number of tokio tasks that
-            // we're willing to spawn if we receive a flood of requests.
-            tokio::spawn(handle_dns_packet(request));
+            tokio::spawn(handle_udp_request(request, permit));
+        }
+    }
+
+    async fn run_tcp(&self) -> anyhow::Result<()> {
+        let mut backoff = TCP_ACCEPT_BACKOFF_MIN;
+        loop {
+            // Failing to accept a connection usually means that we've run out
+            // of file descriptors or memory for the moment.  That's no reason
+            // to stop serving TCP (or, by returning an error, UDP), so wait a
+            // bit for things to improve and try again.
+            let (stream, client_addr) = match self.tcp_listener.accept().await {
+                Ok(accepted) => {
+                    backoff = TCP_ACCEPT_BACKOFF_MIN;
+                    accepted
+                }
+                Err(error) => {
+                    error!(&self.log,
+                        "failed to accept connection on TCP listen socket";
+                        "error" => ?error,
+                        "retry_after" => ?backoff,
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(TCP_ACCEPT_BACKOFF_MAX);
+                    continue;
+                }
+            };
+            let log = self.log.new(o!(
+                "peer_addr" => client_addr.to_string(),
+                "transport" => "tcp",
+            ));
+
+            // If we already have as many connections as we're willing to,
+            // close this one (by dropping it) without reading from it.
+            let Ok(permit) = self.tcp_connections.clone().try_acquire_owned()
+            else {
+                self.counters.connection_dropped();
+                debug!(&log, "closing TCP connection: too many open");
+                continue;
+            };
+
+            let store = self.store.clone();
+            tokio::spawn(async move {
+                handle_tcp_connection(log, store, stream, client_addr).await;
+                drop(permit);
+            });
+        }
+    }
+}
+
+/// Handles one DNS request received over UDP
+///
+/// `permit` accounts for this request against the limit on concurrent UDP
+/// requests until the request has been handled.
+async fn handle_udp_request(request: Request, permit: OwnedSemaphorePermit) {
+    handle_dns_packet(request).await;
+    drop(permit);
+}
+
+/// Handles the DNS requests sent over one TCP connection
+///
+/// Each message on the connection is preceded by its length as a two-byte
+/// integer in network byte order (RFC 1035 section 4.2.2; RFC 7766).  Requests
+/// are handled one at a time, in the order they arrive.  The connection is
+/// closed when the client closes it or leaves it idle for too long.
+async fn handle_tcp_connection(
+    log: Logger,
+    store: Store,
+    stream: TcpStream,
+    client_addr: SocketAddr,
+) {
+    let (mut reader, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+    loop {
+        let length =
+            match tokio::time::timeout(TCP_IDLE_TIMEOUT, reader.read_u16())
+                .await
+            {
+                Ok(Ok(length)) => length,
+                Ok(Err(error))
+                    if error.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    debug!(&log, "TCP connection closed by client");
+                    return;
+                }
+                Ok(Err(error)) => {
+                    error!(
+                        &log,
+                        "failed to read from TCP connection: {:#}", error
+                    );
+                    return;
+                }
+                Err(_) => {
+                    debug!(&log, "closing idle TCP connection");
+                    return;
+                }
+            };
+
+        let mut buf = vec![0u8; usize::from(length)];
+        match tokio::time::timeout(
+            TCP_IDLE_TIMEOUT,
+            reader.read_exact(&mut buf),
+        )
+        .await
+        {
+            Ok(Ok(_)) => (),
+            Ok(Err(error)) => {
+                error!(&log, "failed to read from TCP connection: {:#}", error);
+                return;
+            }
+            Err(_) => {
+                error!(&log, "timed out reading request from TCP connection");
+                return;
+            }
         }
+
+        let req_id = Uuid::new_v4();
+        let request = Request {
+            log: log.new(o!("req_id" => req_id.to_string())),
+            store: store.clone(),
+            transport: Transport::Tcp(writer.clone()),
+            client_addr,
+            packet: buf,
+            max_response_size: u16::MAX,
+            truncate: false,
+            req_id,
+        };
+        handle_dns_packet(request).await;
     }
 }
 
+/// Describes how a request arrived, which determines how we reply to it
+enum Transport {
+    /// The request arrived in a UDP packet, and the reply goes back in one
+    Udp(Arc<UdpSocket>),
+    /// The request arrived over a TCP connection, and the reply goes back over
+    /// the same connection
+    Tcp(Arc<Mutex<OwnedWriteHalf>>),
+}
+
 /// Describes an incoming DNS request
 struct Request {
     log: Logger,
     store: Store,
-    socket: Arc<UdpSocket>,
+    transport: Transport,
     client_addr: SocketAddr,
     packet: Vec<u8>,
+    /// the largest response that we may send, in bytes
+    ///
+    /// Responses that would be larger are truncated (and flagged as such).
+    max_response_size: u16,
+    /// whether to reply with an empty, truncated response rather than
+    /// answering the query (see [`crate::rate_limit`])
+    truncate: bool,
     #[allow(dead_code)]
     req_id: Uuid,
 }
 
-async fn handle_dns_packet(request: Request) {
+/// Returns a builder for responses to `mr`
+///
+/// If the client sent an EDNS OPT record, our responses include one too,
+/// advertising the largest UDP payload that we'll send.
+fn response_builder(mr: &MessageRequest) -> MessageResponseBuilder<'_> {
+    let mut rb = MessageResponseBuilder::from_message_request(mr);
+    if mr.edns().is_some() {
+        let mut edns = Edns::new();
+        edns.set_max_payload(UDP_MAX_PAYLOAD);
+        rb.edns(edns);
+    }
+    rb
+}
+
+async fn handle_dns_packet(mut request: Request) {
     let log = &request.log;
     let buf = &request.packet;
 
@@ -162,28 +498,50 @@ async fn handle_dns_packet(request: Request) {
         }
     };
 
+    // A UDP client may tell us (using EDNS) that it can accept a larger
+    // response than the default.  (RFC 6891 section 6.2.5 says to treat
+    // values smaller than the default as the default.)
+    if let (Transport::Udp(_), Some(edns)) = (&request.transport, mr.edns()) {
+        request.max_response_size =
+            edns.max_payload().clamp(UDP_MIN_PAYLOAD, UDP_MAX_PAYLOAD);
+    }
+    let log = &request.log;
+
+    // When the client's network is over its rate limit, we may reply to tell
+    // the client to retry over TCP, but we don't answer the query.
+    if request.truncate {
+        let mut header = Header::response_from_request(mr.header());
+        header.set_truncated(true);
+        let mresp =
+            response_builder(&mr).build(header, vec![], vec![], vec![], vec![]);
+        if let Err(error) = encode_and_send(&request, mresp, "truncated").await
+        {
+            error!(log, "failed to send truncated response: {:#}", error);
+        }
+        return;
+    }
+
     // Handle the message.
     match handle_dns_message(&request, &mr).await {
         Ok(_) => (),
         Err(error) => {
             let header = Header::response_from_request(mr.header());
-            let rb_servfail = MessageResponseBuilder::from_message_request(&mr);
+            let rb_servfail = response_builder(&mr);
             error!(log, "failed to handle incoming DNS message: {:#}", error);
             match error {
-                RequestError::NxDomain(_) => {
-                    let rb_nxdomain =
-                        MessageResponseBuilder::from_message_request(&mr);
+                RequestError::NxDomain(_, zone) => {
+                    let rb_nxdomain = response_builder(&mr);
                     respond_nxdomain(
                         &request,
                         rb_nxdomain,
                         rb_servfail,
                         &header,
+                        &soa_record(&zone),
                     )
                     .await
                 }
                 RequestError::ServFail(_) => {
-                    let rb_servfail =
-                        MessageResponseBuilder::from_message_request(&mr);
+                    let rb_servfail = response_builder(&mr);
                     respond_servfail(&request, rb_servfail, &header).await
                 }
             };
@@ -194,16 +552,18 @@ async fn handle_dns_packet(request: Request) {
 /// Describes how to respond to a particular request failure
 #[derive(Debug, Error)]
 enum RequestError {
-    #[error("NXDOMAIN: {0:#}")]
-    NxDomain(#[source] QueryError),
+    #[error("NXDOMAIN: no records found for name: {0:?}")]
+    NxDomain(String, Zone),
     #[error("SERVFAIL: {0:#}")]
     ServFail(#[source] anyhow::Error),
 }
 
 impl From<QueryError> for RequestError {
     fn from(source: QueryError) -> Self {
-        match &source {
-            QueryError::NoName(_) => RequestError::NxDomain(source),
+        match source {
+            QueryError::NoName(name, zone) => {
+                RequestError::NxDomain(name, zone)
+            }
             // Bail with servfail when this query is for a zone that we don't
             // own (and other server-side failures) so that resolvers will look
             // to other DNS servers for this query.
@@ -223,54 +583,358 @@ async fn handle_dns_message(
     let store = &request.store;
     debug!(&log, "message_request"; "mr" => #?mr);
 
-    let header = Header::response_from_request(mr.header());
     let query = mr.query();
-    let name = query.original().name().clone();
-    let records = store.query(mr)?;
-    let rb = MessageResponseBuilder::from_message_request(mr);
-    let response_records = records
-        .into_iter()
-        .map(|record| match record {
-            DnsRecord::A(addr) => {
-                let mut a = Record::new();
-                a.set_name(name.clone())
-                    .set_rr_type(RecordType::A)
-                    .set_data(Some(RData::A(addr)));
-                Ok(a)
-            }
+    let query_type = query.query_type();
+    if matches!(query_type, RecordType::AXFR | RecordType::IXFR) {
+        return handle_zone_transfer(request, mr).await;
+    }
+
+    let mut header = Header::response_from_request(mr.header());
+    let answer = store.query(mr)?;
+    let rb = response_builder(mr);
+    if let Some(delegation) = &answer.delegation {
+        // We're not authoritative for names in a delegated subtree.  Refer
+        // the client to the servers that are.
+        return respond_referral(request, rb, header, delegation).await;
+    }
 
-            DnsRecord::AAAA(addr) => {
-                let mut aaaa = Record::new();
-                aaaa.set_name(name.clone())
-                    .set_rr_type(RecordType::AAAA)
-                    .set_data(Some(RData::AAAA(addr)));
-                Ok(aaaa)
+    header.set_authoritative(true);
+    let mut response_records = Vec::new();
+    let mut name = query.original().name().clone();
+    let mut records = answer.records;
+    let mut chased = vec![name.clone()];
+    loop {
+        // If this name is an alias, the answer includes the CNAME record
+        // followed by the answer for its target -- if we have that too.
+        // Queries for the CNAME record itself (or for ANY records) are
+        // answered without following it.
+        let cname = match records.as_slice() {
+            [DnsRecord::CNAME(target)]
+                if !record_type_matches(RecordType::CNAME, query_type) =>
+            {
+                target.clone()
             }
+            _ => {
+                for record in records {
+                    if record_type_matches(record_type(&record), query_type) {
+                        response_records
+                            .push(dns_record_to_record(&name, record)?);
+                    }
+                }
+                break;
+            }
+        };
 
-            DnsRecord::SRV(crate::dns_types::SRV {
-                prio,
-                weight,
-                port,
-                target,
-            }) => {
-                let tgt = Name::from_str(&target).map_err(|error| {
-                    RequestError::ServFail(anyhow!(
-                        "serialization failed due to bad SRV target {:?}: {:#}",
-                        &target,
-                        error
-                    ))
-                })?;
-                let mut srv = Record::new();
-                srv.set_name(name.clone())
-                    .set_rr_type(RecordType::SRV)
-                    .set_data(Some(RData::SRV(SRV::new(
-                        prio, weight, port, tgt,
-                    ))));
-                Ok(srv)
+        let target = parse_target("CNAME", &cname)?;
+        response_records
+            .push(dns_record_to_record(&name, DnsRecord::CNAME(cname))?);
+        if chased.len() >= MAX_CNAME_CHAIN || chased.contains(&target) {
+            break;
+        }
+        match store.query_target(&target)? {
+            Some(target_answer) if target_answer.delegation.is_none() => {
+                name = target;
+                records = target_answer.records;
+                chased.push(name.clone());
             }
-        })
-        .collect::<Result<Vec<_>, RequestError>>()?;
-    respond_records(request, rb, header, &response_records).await
+            // The target is outside our zones, doesn't exist, or has been
+            // delegated.  Resolvers can follow it from here.
+            _ => break,
+        }
+    }
+
+    // The SOA and NS records for each zone aren't stored with the rest of its
+    // records.  They're synthesized from the zone's configuration.
+    if answer.is_apex {
+        if record_type_matches(RecordType::SOA, query_type) {
+            response_records.push(soa_record(&answer.zone));
+        }
+        if record_type_matches(RecordType::NS, query_type) {
+            response_records.extend(ns_records(&answer.zone));
+        }
+    }
+
+    if response_records.is_empty() {
+        respond_nodata(request, rb, header, &soa_record(&answer.zone)).await
+    } else {
+        respond_records(request, rb, header, &response_records).await
+    }
+}
+
+/// Handle a request to transfer a whole zone (AXFR, RFC 5936) or the changes
+/// to a zone since a particular version (IXFR, RFC 1995)
+async fn handle_zone_transfer(
+    request: &Request,
+    mr: &MessageRequest,
+) -> Result<(), RequestError> {
+    let mut header = Header::response_from_request(mr.header());
+    header.set_authoritative(true);
+    let query_type = mr.query().query_type();
+    let is_udp = matches!(request.transport, Transport::Udp(_));
+
+    // AXFR isn't defined over UDP (RFC 5936 section 4.2).
+    if query_type == RecordType::AXFR && is_udp {
+        return respond_error(
+            request,
+            response_builder(mr),
+            &header,
+            ResponseCode::FormErr,
+        )
+        .await;
+    }
+
+    // An IXFR request carries the SOA record of the version of the zone that
+    // the client already has.
+    let since_serial = if query_type == RecordType::IXFR {
+        let serial =
+            mr.name_servers().iter().find_map(|record| match record.data() {
+                Some(RData::SOA(soa)) => Some(soa.serial()),
+                _ => None,
+            });
+        let Some(serial) = serial else {
+            return respond_error(
+                request,
+                response_builder(mr),
+                &header,
+                ResponseCode::FormErr,
+            )
+            .await;
+        };
+        Some(serial)
+    } else {
+        None
+    };
+
+    let transfer = match request.store.zone_transfer(mr, since_serial) {
+        Ok(transfer) => transfer,
+        // Zone transfers are only possible for whole zones that we serve.
+        Err(QueryError::NoZone(_)) => {
+            return respond_error(
+                request,
+                response_builder(mr),
+                &header,
+                ResponseCode::NotAuth,
+            )
+            .await;
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    // If the client is already up to date, the response is just the current
+    // SOA record.  This is also the response to an IXFR over UDP, which tells
+    // the client to ask again over TCP (RFC 1995 section 2).
+    let current_soa = soa_record(&transfer.current.zone);
+    if since_serial.is_some()
+        && (is_udp || since_serial == Some(transfer.current.zone.serial()))
+    {
+        return respond_records(
+            request,
+            response_builder(mr),
+            header,
+            &[current_soa],
+        )
+        .await;
+    }
+
+    // The transfer begins and ends with the current SOA record.  For an
+    // incremental transfer, the records in between are the old SOA record, the
+    // records that were removed, the current SOA record, and the records that
+    // were added (RFC 1995 section 4).  Otherwise, they're all of the records
+    // in the zone.
+    let current_records = zone_records(&transfer.current)?;
+    let mut response_records = vec![current_soa.clone()];
+    match &transfer.previous {
+        Some(previous) => {
+            let previous_records = zone_records(previous)?;
+            response_records.push(soa_record(&previous.zone));
+            response_records.extend(
+                previous_records
+                    .iter()
+                    .filter(|record| !current_records.contains(record))
+                    .cloned(),
+            );
+            response_records.push(current_soa.clone());
+            response_records.extend(
+                current_records
+                    .iter()
+                    .filter(|record| !previous_records.contains(record))
+                    .cloned(),
+            );
+        }
+        None => response_records.extend(current_records),
+    }
+    response_records.push(current_soa);
+
+    // Zones can be too big for one message, so the records are spread across
+    // as many as needed.
+    for chunk in response_records.chunks(XFR_RECORDS_PER_MESSAGE) {
+        respond_records(request, response_builder(mr), header, chunk).await?;
+    }
+    Ok(())
+}
+
+/// Returns all of the records in a zone, except for its SOA record
+fn zone_records(contents: &ZoneContents) -> Result<Vec<Record>, RequestError> {
+    let mut records = ns_records(&contents.zone).collect::<Vec<_>>();
+    for (name, name_records) in &contents.records {
+        for record in name_records {
+            records.push(dns_record_to_record(name, record.clone())?);
+        }
+    }
+    Ok(records)
+}
+
+/// Converts one of our stored records for `name` into a DNS record
+fn dns_record_to_record(
+    name: &Name,
+    record: DnsRecord,
+) -> Result<Record, RequestError> {
+    match record {
+        DnsRecord::A(addr) => {
+            let mut a = Record::new();
+            a.set_name(name.clone())
+                .set_rr_type(RecordType::A)
+                .set_data(Some(RData::A(addr)));
+            Ok(a)
+        }
+
+        DnsRecord::AAAA(addr) => {
+            let mut aaaa = Record::new();
+            aaaa.set_name(name.clone())
+                .set_rr_type(RecordType::AAAA)
+                .set_data(Some(RData::AAAA(addr)));
+            Ok(aaaa)
+        }
+
+        DnsRecord::SRV(crate::dns_types::SRV {
+            prio,
+            weight,
+            port,
+            target,
+        }) => {
+            let tgt = parse_target("SRV", &target)?;
+            let mut srv = Record::new();
+            srv.set_name(name.clone())
+                .set_rr_type(RecordType::SRV)
+                .set_data(Some(RData::SRV(SRV::new(prio, weight, port, tgt))));
+            Ok(srv)
+        }
+
+        DnsRecord::CNAME(target) => {
+            let tgt = parse_target("CNAME", &target)?;
+            let mut cname = Record::new();
+            cname
+                .set_name(name.clone())
+                .set_rr_type(RecordType::CNAME)
+                .set_data(Some(RData::CNAME(tgt)));
+            Ok(cname)
+        }
+
+        DnsRecord::TXT(strings) => {
+            let mut txt = Record::new();
+            txt.set_name(name.clone())
+                .set_rr_type(RecordType::TXT)
+                .set_data(Some(RData::TXT(TXT::new(strings))));
+            Ok(txt)
+        }
+
+        DnsRecord::PTR(target) => {
+            let tgt = parse_target("PTR", &target)?;
+            let mut ptr = Record::new();
+            ptr.set_name(name.clone())
+                .set_rr_type(RecordType::PTR)
+                .set_data(Some(RData::PTR(tgt)));
+            Ok(ptr)
+        }
+
+        DnsRecord::MX(crate::dns_types::MX { preference, exchange }) => {
+            let tgt = parse_target("MX", &exchange)?;
+            let mut mx = Record::new();
+            mx.set_name(name.clone())
+                .set_rr_type(RecordType::MX)
+                .set_data(Some(RData::MX(MX::new(preference, tgt))));
+            Ok(mx)
+        }
+
+        DnsRecord::NS(target) => {
+            let tgt = parse_target("NS", &target)?;
+            let mut ns = Record::new();
+            ns.set_name(name.clone())
+                .set_rr_type(RecordType::NS)
+                .set_data(Some(RData::NS(tgt)));
+            Ok(ns)
+        }
+    }
+}
+
+/// Parses the name that one of our stored records of type `kind` points to
+fn parse_target(kind: &str, target: &str) -> Result<Name, RequestError> {
+    let mut name = Name::from_str(target).map_err(|error| {
+        RequestError::ServFail(anyhow!(
+            "serialization failed due to bad {} target {:?}: {:#}",
+            kind,
+            target,
+            error
+        ))
+    })?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+/// Returns the DNS record type of one of our stored records
+fn record_type(record: &DnsRecord) -> RecordType {
+    match record {
+        DnsRecord::A(_) => RecordType::A,
+        DnsRecord::AAAA(_) => RecordType::AAAA,
+        DnsRecord::SRV(_) => RecordType::SRV,
+        DnsRecord::CNAME(_) => RecordType::CNAME,
+        DnsRecord::TXT(_) => RecordType::TXT,
+        DnsRecord::PTR(_) => RecordType::PTR,
+        DnsRecord::MX(_) => RecordType::MX,
+        DnsRecord::NS(_) => RecordType::NS,
+    }
+}
+
+/// Returns whether a record of type `record_type` answers a query for
+/// `query_type` records
+fn record_type_matches(
+    record_type: RecordType,
+    query_type: RecordType,
+) -> bool {
+    query_type == RecordType::ANY || query_type == record_type
+}
+
+/// Returns the SOA record for the given zone
+fn soa_record(zone: &Zone) -> Record {
+    let mname = zone.nameservers[0].clone();
+    let rname = Name::from_str(SOA_RNAME)
+        .unwrap()
+        .append_domain(&zone.name)
+        .unwrap_or_else(|_| zone.name.clone());
+    let mut soa = Record::new();
+    soa.set_name(zone.name.clone()).set_rr_type(RecordType::SOA).set_data(
+        Some(RData::SOA(SOA::new(
+            mname,
+            rname,
+            zone.serial(),
+            SOA_REFRESH_SECS,
+            SOA_RETRY_SECS,
+            SOA_EXPIRE_SECS,
+            SOA_MINIMUM_SECS,
+        ))),
+    );
+    soa
+}
+
+/// Returns the NS records for the given zone
+fn ns_records(zone: &Zone) -> impl Iterator<Item = Record> + '_ {
+    zone.nameservers.iter().map(|nameserver| {
+        let mut ns = Record::new();
+        ns.set_name(zone.name.clone())
+            .set_rr_type(RecordType::NS)
+            .set_data(Some(RData::NS(nameserver.clone())));
+        ns
+    })
 }
 
 /// Respond to a DNS query with the given set of DNS records
@@ -293,18 +957,86 @@ async fn respond_records(
     })
 }
 
+/// Respond to a DNS query for a name in a subtree that we've delegated to
+/// other DNS servers
+///
+/// This is a NOERROR response with no answers and without the authoritative
+/// flag.  The delegation's NS records go in the authority section, and the
+/// addresses of any of those servers within our zone go in the additional
+/// section.
+async fn respond_referral(
+    request: &Request,
+    rb: MessageResponseBuilder<'_>,
+    header: Header,
+    delegation: &Delegation,
+) -> Result<(), RequestError> {
+    let ns_records = delegation
+        .nameservers
+        .iter()
+        .map(|nameserver| {
+            let mut ns = Record::new();
+            ns.set_name(delegation.name.clone())
+                .set_rr_type(RecordType::NS)
+                .set_data(Some(RData::NS(nameserver.clone())));
+            ns
+        })
+        .collect::<Vec<_>>();
+    let mut glue_records = Vec::new();
+    for (name, records) in &delegation.glue {
+        for record in records {
+            glue_records.push(dns_record_to_record(name, record.clone())?);
+        }
+    }
+
+    let mresp = rb.build(
+        header,
+        vec![],
+        ns_records.iter().collect::<Vec<&Record>>(),
+        vec![],
+        glue_records.iter().collect::<Vec<&Record>>(),
+    );
+    encode_and_send(&request, mresp, "referral").await.map_err(|error| {
+        RequestError::ServFail(anyhow!("failed to emit response: {:#}", error))
+    })
+}
+
+/// Respond to a DNS query for a name that exists, but has no records of the
+/// requested type
+///
+/// This is a NOERROR response with no answers (sometimes called NODATA).  The
+/// zone's SOA record goes in the authority section so that resolvers know how
+/// long they may cache the negative answer.
+async fn respond_nodata(
+    request: &Request,
+    rb: MessageResponseBuilder<'_>,
+    header: Header,
+    soa: &Record,
+) -> Result<(), RequestError> {
+    let mresp = rb.build(header, vec![], vec![], vec![soa], vec![]);
+    encode_and_send(&request, mresp, "NODATA").await.map_err(|error| {
+        RequestError::ServFail(anyhow!("failed to emit response: {:#}", error))
+    })
+}
+
 /// Respond to a DNS query with an NXDOMAIN error
 ///
 /// This means that we are authoritative for the parent domain and the requested
-/// name definitely does not exist.
+/// name definitely does not exist.  As with NODATA, the zone's SOA record goes
+/// in the authority section.
 async fn respond_nxdomain(
     request: &Request,
     rb_nxdomain: MessageResponseBuilder<'_>,
     rb_servfail: MessageResponseBuilder<'_>,
     header: &Header,
+    soa: &Record,
 ) {
     let log = &request.log;
-    let mresp = rb_nxdomain.error_msg(&header, ResponseCode::NXDomain);
+    let mut nxdomain_header = *header;
+    nxdomain_header
+        .set_authoritative(true)
+        .set_response_code(ResponseCode::NXDomain);
+    let mresp =
+        rb_nxdomain.build(nxdomain_header, vec![], vec![], vec![soa], vec![]);
     if let Err(error) = encode_and_send(request, mresp, "NXDOMAIN").await {
         error!(
             log,
@@ -315,6 +1047,19 @@ async fn respond_nxdomain(
     }
 }
 
+/// Respond to a DNS query with an error that's neither NXDOMAIN nor SERVFAIL
+async fn respond_error(
+    request: &Request,
+    rb: MessageResponseBuilder<'_>,
+    header: &Header,
+    response_code: ResponseCode,
+) -> Result<(), RequestError> {
+    let mresp = rb.error_msg(header, response_code);
+    encode_and_send(request, mresp, "error").await.map_err(|error| {
+        RequestError::ServFail(anyhow!("failed to emit response: {:#}", error))
+    })
+}
+
 /// Respond to a DNS query with a SERVFAIL error
 ///
 /// This can be a catch-all for any kind of server-side failure.  We also use it
@@ -347,6 +1092,10 @@ where
     async move {
         let mut resp_data = Vec::new();
         let mut enc = BinEncoder::new(&mut resp_data);
+        // If the records don't all fit, as many as do are included and the
+        // response is flagged as truncated so that the client can retry over
+        // TCP.
+        enc.set_max_size(request.max_response_size);
         let _ = mresp
             .destructive_emit(&mut enc)
             .with_context(|| format!("encoding {}", label))?;
@@ -354,9 +1103,23 @@ where
         // If we get this far and fail to send the data, there's nothing else to
         // do.  Log the problem and treat this as a success as far as the caller
         // is concerned.
-        if let Err(error) =
-            request.socket.send_to(&resp_data, &request.client_addr).await
-        {
+        let result = match &request.transport {
+            Transport::Udp(socket) => socket
+                .send_to(&resp_data, &request.client_addr)
+                .await
+                .map(|_| ()),
+            Transport::Tcp(writer) => {
+                // Messages over TCP are preceded by their length.
+                let length = u16::try_from(resp_data.len())
+                    .context("TCP response too large")?;
+                let mut writer = writer.lock().await;
+                match writer.write_all(&length.to_be_bytes()).await {
+                    Ok(()) => writer.write_all(&resp_data).await,
+                    Err(error) => Err(error),
+                }
+            }
+        };
+        if let Err(error) = result {
             error!(
                 &request.log,
                 "failed to send {} to {:?}: {:#}",
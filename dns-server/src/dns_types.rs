@@ -38,6 +38,18 @@ pub enum DnsRecord {
     A(Ipv4Addr),
     AAAA(Ipv6Addr),
     SRV(SRV),
+    /// alias for another name, which must be the only record for its name
+    CNAME(String),
+    /// one or more character strings (each at most 255 bytes long) making up
+    /// a single TXT record
+    TXT(Vec<String>),
+    /// name that this (reverse lookup) name points to
+    PTR(String),
+    MX(MX),
+    /// name server for this name: at the zone apex, one of the zone's own
+    /// name servers; anywhere else, a delegation of the subtree to another
+    /// server
+    NS(String),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
@@ -48,3 +60,10 @@ pub struct SRV {
     pub port: u16,
     pub target: String,
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename = "Mx")]
+pub struct MX {
+    pub preference: u16,
+    pub exchange: String,
+}
@@ -100,14 +100,27 @@ use dns_service_client::{
     ERROR_CODE_BAD_UPDATE_GENERATION, ERROR_CODE_UPDATE_IN_PROGRESS,
 };
 use dropshot::{endpoint, RequestContext};
+use oximeter::types::ProducerRegistry;
+use oximeter::types::ProducerResults;
+use oximeter_producer::ProducerIdPathParams;
 
 pub struct Context {
     store: storage::Store,
+    producer_registry: ProducerRegistry,
 }
 
 impl Context {
-    pub fn new(store: storage::Store) -> Context {
-        Context { store }
+    pub fn new(
+        store: storage::Store,
+        producer_registry: ProducerRegistry,
+    ) -> Context {
+        Context { store, producer_registry }
+    }
+
+    /// Returns the registry of the server's metric producers, whose ID
+    /// identifies this server to oximeter
+    pub fn producer_registry(&self) -> &ProducerRegistry {
+        &self.producer_registry
     }
 }
 
@@ -116,6 +129,7 @@ pub fn api() -> dropshot::ApiDescription<Context> {
 
     api.register(dns_config_get).expect("register dns_config_get");
     api.register(dns_config_put).expect("register dns_config_update");
+    api.register(metrics_collect).expect("register metrics_collect");
     api
 }
 
@@ -149,6 +163,25 @@ async fn dns_config_put(
     Ok(dropshot::HttpResponseUpdatedNoContent())
 }
 
+/// Endpoint for oximeter to collect the server's metrics
+///
+/// This isn't part of the DNS configuration API (and isn't described in its
+/// OpenAPI document): it's fetched by the oximeter collector, which knows
+/// the shape of its results.
+#[endpoint(
+    method = GET,
+    path = "/metrics/collect/{producer_id}",
+    unpublished = true,
+)]
+async fn metrics_collect(
+    rqctx: RequestContext<Context>,
+    path_params: dropshot::Path<ProducerIdPathParams>,
+) -> Result<dropshot::HttpResponseOk<ProducerResults>, dropshot::HttpError> {
+    let apictx = rqctx.context();
+    let producer_id = path_params.into_inner().producer_id;
+    oximeter_producer::collect(&apictx.producer_registry, producer_id).await
+}
+
 impl From<UpdateError> for dropshot::HttpError {
     fn from(error: UpdateError) -> Self {
         let message = format!("{:#}", error);
@@ -169,6 +202,10 @@ impl From<UpdateError> for dropshot::HttpError {
                 internal_message: message,
             },
 
+            UpdateError::InvalidRecords { .. } => {
+                dropshot::HttpError::for_bad_request(None, message)
+            }
+
             UpdateError::InternalError(_) => {
                 dropshot::HttpError::for_internal_error(message)
             }
@@ -4,8 +4,8 @@
 
 //! Dropshot-configurable DNS server
 //!
-//! This crate provides a standalone program that runs a UDP-based DNS server
-//! along with a Dropshot server for configuring the records served over DNS.
+//! This crate provides a standalone program that runs a DNS server (over UDP
+//! and TCP) along with a Dropshot server for configuring the records served over DNS.
 //! The following RFDs describe the overall design of this server and how it's
 //! used:
 //!
@@ -45,12 +45,18 @@
 pub mod dns_server;
 pub mod dns_types;
 pub mod http_server;
+pub mod metrics;
+pub mod rate_limit;
 pub mod storage;
 
 use anyhow::{anyhow, Context};
+use oximeter::types::ProducerRegistry;
 use slog::o;
 
 /// Starts both the HTTP and DNS servers over a given store.
+///
+/// The HTTP server also serves the DNS server's metrics to oximeter, under a
+/// newly-generated producer ID.
 pub async fn start_servers(
     log: slog::Logger,
     store: storage::Store,
@@ -70,9 +76,18 @@ pub async fn start_servers(
         .context("starting DNS server")?
     };
 
+    let producer_registry = ProducerRegistry::new();
+    producer_registry
+        .register_producer(metrics::Producer::new(
+            producer_registry.producer_id(),
+            dns_server.counters().clone(),
+        ))
+        .context("registering metric producer")?;
+
     let dropshot_server = {
         let http_api = http_server::api();
-        let http_api_context = http_server::Context::new(store);
+        let http_api_context =
+            http_server::Context::new(store, producer_registry);
 
         dropshot::HttpServerStarter::new(
             dropshot_config,
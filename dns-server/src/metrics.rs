@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Metrics describing the work that the DNS server turned away
+//!
+//! The DNS server counts the queries that it declined to answer, either
+//! because it was already handling as many as it's willing to or because the
+//! client's network was over its response rate limit.  These counters are
+//! reported to oximeter by a [`Producer`].
+
+use chrono::DateTime;
+use chrono::Utc;
+use oximeter::types::Cumulative;
+use oximeter::types::Sample;
+use oximeter::Metric;
+use oximeter::MetricsError;
+use oximeter::Target;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Oximeter target identifying a DNS server
+#[derive(Debug, Clone, Target)]
+pub struct DnsServer {
+    pub server_id: Uuid,
+}
+
+/// Number of queries dropped because the server was already handling as many
+/// as it's configured to
+#[derive(Debug, Clone, Metric)]
+pub struct QueriesDropped {
+    #[datum]
+    pub count: Cumulative<i64>,
+}
+
+/// Number of queries that were not answered (or that got only a truncated
+/// response) because the client's network was over its response rate limit
+#[derive(Debug, Clone, Metric)]
+pub struct QueriesRateLimited {
+    #[datum]
+    pub count: Cumulative<i64>,
+}
+
+/// Number of TCP connections closed without being served because the server
+/// already had as many open as it's configured to
+#[derive(Debug, Clone, Metric)]
+pub struct ConnectionsDropped {
+    #[datum]
+    pub count: Cumulative<i64>,
+}
+
+/// Counters shared between the DNS server and its metric [`Producer`]
+#[derive(Debug)]
+pub struct Counters {
+    start_time: DateTime<Utc>,
+    queries_dropped: AtomicU64,
+    queries_rate_limited: AtomicU64,
+    connections_dropped: AtomicU64,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Counters {
+            start_time: Utc::now(),
+            queries_dropped: AtomicU64::new(0),
+            queries_rate_limited: AtomicU64::new(0),
+            connections_dropped: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Counters {
+    pub fn queries_dropped(&self) -> u64 {
+        self.queries_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn queries_rate_limited(&self) -> u64 {
+        self.queries_rate_limited.load(Ordering::Relaxed)
+    }
+
+    pub fn connections_dropped(&self) -> u64 {
+        self.connections_dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn query_dropped(&self) {
+        self.queries_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn query_rate_limited(&self) {
+        self.queries_rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_dropped(&self) {
+        self.connections_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn cumulative(&self, value: u64) -> Cumulative<i64> {
+        Cumulative::with_start_time(
+            self.start_time,
+            i64::try_from(value).unwrap_or(i64::MAX),
+        )
+    }
+}
+
+/// Reports a DNS server's [`Counters`] to oximeter
+#[derive(Debug, Clone)]
+pub struct Producer {
+    target: DnsServer,
+    counters: Arc<Counters>,
+}
+
+impl Producer {
+    pub fn new(server_id: Uuid, counters: Arc<Counters>) -> Producer {
+        Producer { target: DnsServer { server_id }, counters }
+    }
+}
+
+impl oximeter::Producer for Producer {
+    fn produce(
+        &mut self,
+    ) -> Result<Box<dyn Iterator<Item = Sample> + 'static>, MetricsError> {
+        let counters = &self.counters;
+        let samples = vec![
+            Sample::new(
+                &self.target,
+                &QueriesDropped {
+                    count: counters.cumulative(counters.queries_dropped()),
+                },
+            ),
+            Sample::new(
+                &self.target,
+                &QueriesRateLimited {
+                    count: counters.cumulative(counters.queries_rate_limited()),
+                },
+            ),
+            Sample::new(
+                &self.target,
+                &ConnectionsDropped {
+                    count: counters.cumulative(counters.connections_dropped()),
+                },
+            ),
+        ];
+        Ok(Box::new(samples.into_iter()))
+    }
+}
@@ -0,0 +1,306 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Response rate limiting for DNS over UDP
+//!
+//! The source address of a UDP packet is trivially forged, so a server that
+//! answers every UDP query can be used to flood a victim with responses that
+//! are larger than the queries that caused them.  Following the response rate
+//! limiting ("RRL") scheme implemented by other authoritative servers, we
+//! limit the rate of responses sent to each client network.  Clients are
+//! grouped by address prefix (by default, /24 for IPv4 and /56 for IPv6)
+//! because an attacker can just as easily forge any address in a victim's
+//! network.
+//!
+//! Most queries beyond the limit get no response at all.  But a legitimate
+//! client whose network happens to be sending a lot of queries shouldn't be
+//! locked out, so every so often one of these queries instead "slips" through
+//! with an empty, truncated response.  That tells a real client to retry the
+//! query over TCP, whose handshake proves that the client really is at the
+//! address it claims.  A truncated response is no larger than the query, so
+//! it's of no use for amplification.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often we'll forget about client networks that haven't been limited
+/// recently
+///
+/// A network's bucket refills completely within a second of its last query,
+/// at which point it's indistinguishable from one we've never seen.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Configuration for response rate limiting
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// number of responses per second that we'll send to each client network
+    ///
+    /// A network may also send a burst of up to this many queries at once.
+    pub responses_per_second: u32,
+    /// how often a response to a query beyond the limit is sent anyway, as an
+    /// empty, truncated response
+    ///
+    /// With `slip` set to N, every Nth such query gets a truncated response
+    /// and the others get none.  1 means that every query gets a truncated
+    /// response, and 0 means that none does.
+    #[serde(default = "default_slip")]
+    pub slip: u32,
+    /// length of the prefix that identifies an IPv4 client's network
+    #[serde(default = "default_ipv4_prefix_len")]
+    pub ipv4_prefix_len: u8,
+    /// length of the prefix that identifies an IPv6 client's network
+    #[serde(default = "default_ipv6_prefix_len")]
+    pub ipv6_prefix_len: u8,
+}
+
+fn default_slip() -> u32 {
+    2
+}
+
+fn default_ipv4_prefix_len() -> u8 {
+    24
+}
+
+fn default_ipv6_prefix_len() -> u8 {
+    56
+}
+
+/// Describes what to do with a query from a particular client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// the client's network is within its limit, so respond normally
+    Respond,
+    /// the client's network is over its limit, but respond with an empty,
+    /// truncated message so that a legitimate client can retry over TCP
+    Slip,
+    /// the client's network is over its limit, so don't respond at all
+    Drop,
+}
+
+/// Keeps track of the responses sent to each client network
+pub struct RateLimiter {
+    config: Config,
+    state: Mutex<State>,
+}
+
+struct State {
+    buckets: HashMap<IpAddr, Bucket>,
+    last_pruned: Instant,
+}
+
+/// Token bucket for one client network
+///
+/// Each response takes a token, and tokens are replenished at the configured
+/// rate, up to one second's worth.
+struct Bucket {
+    tokens: f64,
+    last_updated: Instant,
+    /// number of queries that have been limited since the last one that
+    /// slipped
+    nlimited: u32,
+}
+
+impl RateLimiter {
+    pub fn new(config: Config) -> anyhow::Result<RateLimiter> {
+        if config.responses_per_second == 0 {
+            anyhow::bail!("responses_per_second must be greater than zero");
+        }
+        if config.ipv4_prefix_len > 32 {
+            anyhow::bail!(
+                "ipv4_prefix_len must be at most 32 (found {})",
+                config.ipv4_prefix_len
+            );
+        }
+        if config.ipv6_prefix_len > 128 {
+            anyhow::bail!(
+                "ipv6_prefix_len must be at most 128 (found {})",
+                config.ipv6_prefix_len
+            );
+        }
+
+        Ok(RateLimiter {
+            config,
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        })
+    }
+
+    /// Decides what to do with a query from `client`, counting it against the
+    /// limit for the client's network
+    pub fn check(&self, client: IpAddr) -> Verdict {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Verdict {
+        let rate = f64::from(self.config.responses_per_second);
+        let network = self.network(client);
+        let mut state = self.state.lock().unwrap();
+
+        if now.saturating_duration_since(state.last_pruned) >= PRUNE_INTERVAL {
+            state.buckets.retain(|_, bucket| {
+                now.saturating_duration_since(bucket.last_updated)
+                    < PRUNE_INTERVAL
+            });
+            state.last_pruned = now;
+        }
+
+        let bucket = state.buckets.entry(network).or_insert(Bucket {
+            tokens: rate,
+            last_updated: now,
+            nlimited: 0,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_updated);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        bucket.last_updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Verdict::Respond;
+        }
+
+        bucket.nlimited += 1;
+        if self.config.slip != 0 && bucket.nlimited >= self.config.slip {
+            bucket.nlimited = 0;
+            Verdict::Slip
+        } else {
+            Verdict::Drop
+        }
+    }
+
+    /// Returns the network that `client` belongs to, for the purpose of rate
+    /// limiting
+    fn network(&self, client: IpAddr) -> IpAddr {
+        match client {
+            IpAddr::V4(addr) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.config.ipv4_prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+            }
+            IpAddr::V6(addr) => {
+                // Clients using IPv4-mapped addresses (from a dual-stack
+                // socket) are grouped as IPv4 clients.
+                if let Some(addr) = addr.to_ipv4_mapped() {
+                    return self.network(IpAddr::V4(addr));
+                }
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.config.ipv6_prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+    use super::RateLimiter;
+    use super::Verdict;
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    fn config(responses_per_second: u32, slip: u32) -> Config {
+        Config {
+            responses_per_second,
+            slip,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+        }
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = RateLimiter::new(config(3, 2)).unwrap();
+        let client: IpAddr = "192.168.1.1".parse().unwrap();
+        let neighbor: IpAddr = "192.168.1.200".parse().unwrap();
+        let stranger: IpAddr = "192.168.2.1".parse().unwrap();
+        let start = Instant::now();
+
+        // A burst of up to a second's worth of queries is answered.
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(client, start), Verdict::Respond);
+        }
+
+        // After that, queries from anywhere on the same network are limited,
+        // and every other one slips through.
+        assert_eq!(limiter.check_at(neighbor, start), Verdict::Drop);
+        assert_eq!(limiter.check_at(client, start), Verdict::Slip);
+        assert_eq!(limiter.check_at(client, start), Verdict::Drop);
+        assert_eq!(limiter.check_at(neighbor, start), Verdict::Slip);
+
+        // Other networks aren't affected.
+        assert_eq!(limiter.check_at(stranger, start), Verdict::Respond);
+
+        // Tokens come back at the configured rate.
+        let later = start + Duration::from_millis(400);
+        assert_eq!(limiter.check_at(client, later), Verdict::Respond);
+        assert_eq!(limiter.check_at(client, later), Verdict::Drop);
+
+        // Once it's been quiet long enough, the network is forgotten, and it
+        // gets its whole burst back.
+        let much_later = later + Duration::from_secs(5);
+        assert_eq!(limiter.check_at(stranger, much_later), Verdict::Respond);
+        assert_eq!(limiter.state.lock().unwrap().buckets.len(), 1);
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(client, much_later), Verdict::Respond);
+        }
+        assert_eq!(limiter.check_at(client, much_later), Verdict::Drop);
+    }
+
+    #[test]
+    fn test_rate_limit_slip() {
+        // With slip 0, nothing over the limit gets a response.
+        let limiter = RateLimiter::new(config(1, 0)).unwrap();
+        let client: IpAddr = "fd00:1122:3344:101::1".parse().unwrap();
+        let start = Instant::now();
+        assert_eq!(limiter.check_at(client, start), Verdict::Respond);
+        for _ in 0..5 {
+            assert_eq!(limiter.check_at(client, start), Verdict::Drop);
+        }
+
+        // With slip 1, everything over the limit gets a truncated response.
+        // IPv6 clients in the same /56 share a limit.
+        let limiter = RateLimiter::new(config(1, 1)).unwrap();
+        let neighbor: IpAddr = "fd00:1122:3344:1ff::1".parse().unwrap();
+        let stranger: IpAddr = "fd00:1122:3344:201::1".parse().unwrap();
+        assert_eq!(limiter.check_at(client, start), Verdict::Respond);
+        for _ in 0..5 {
+            assert_eq!(limiter.check_at(neighbor, start), Verdict::Slip);
+        }
+        assert_eq!(limiter.check_at(stranger, start), Verdict::Respond);
+    }
+
+    #[test]
+    fn test_rate_limit_config() {
+        assert!(RateLimiter::new(config(0, 2)).is_err());
+        let mut bad_prefix = config(1, 2);
+        bad_prefix.ipv4_prefix_len = 33;
+        assert!(RateLimiter::new(bad_prefix).is_err());
+        let mut bad_prefix = config(1, 2);
+        bad_prefix.ipv6_prefix_len = 129;
+        assert!(RateLimiter::new(bad_prefix).is_err());
+
+        // Prefixes of length 0 lump all clients together.
+        let mut everyone = config(1, 0);
+        everyone.ipv4_prefix_len = 0;
+        everyone.ipv6_prefix_len = 0;
+        let limiter = RateLimiter::new(everyone).unwrap();
+        let start = Instant::now();
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let stranger: IpAddr = "172.16.0.1".parse().unwrap();
+        assert_eq!(limiter.check_at(client, start), Verdict::Respond);
+        assert_eq!(limiter.check_at(stranger, start), Verdict::Drop);
+    }
+}
@@ -109,6 +109,12 @@ use trust_dns_client::rr::Name;
 
 const KEY_CONFIG: &'static str = "config";
 
+/// Name under which the records for a zone's own name are stored
+const KEY_APEX: &'static str = "@";
+
+/// Name of the SRV records that DNS servers publish about themselves
+const KEY_NAMESERVICE: &'static str = "_nameservice._tcp";
+
 /// Configuration for persistent storage of DNS data
 #[derive(Deserialize, Debug)]
 pub struct Config {
@@ -155,6 +161,9 @@ pub enum UpdateError {
         req_id: String,
     },
 
+    #[error("zone {zone_name:?}: name {name:?}: {message}")]
+    InvalidRecords { zone_name: String, name: String, message: String },
+
     #[error("internal error")]
     InternalError(#[from] anyhow::Error),
 }
@@ -386,6 +395,18 @@ impl Store {
             return Ok(());
         }
 
+        for zone_config in &config.zones {
+            for (name, records) in &zone_config.records {
+                validate_records(records).map_err(|message| {
+                    UpdateError::InvalidRecords {
+                        zone_name: zone_config.zone_name.clone(),
+                        name: name.clone(),
+                        message,
+                    }
+                })?;
+            }
+        }
+
         // Prune any trees in the db that are newer than the current generation.
         // These could exist if we were previously crashed while trying to move
         // to this generation.
@@ -406,10 +427,9 @@ impl Store {
 
             for (name, records) in &zone_config.records {
                 if records.is_empty() {
-                    // There's no distinction between in DNS between a name that
-                    // doesn't exist at all and one with no records associated
-                    // with it.  If there are no records, don't bother inserting
-                    // the name.
+                    // Whether a name with no records exists depends only on
+                    // whether there are names below it, so there's no need to
+                    // insert it.
                     continue;
                 }
                 let records_json =
@@ -580,14 +600,19 @@ impl Store {
         self.prune_trees(trees_to_prune, "too old");
     }
 
-    /// Returns a non-empty list of DNS records associated with the name in the
-    /// given DNS request.
+    /// Returns the DNS records associated with the name in the given DNS
+    /// request, along with a description of the zone that contains it
     ///
-    /// If the returned set would have been empty, returns `QueryError::NoName`.
+    /// The list of records is non-empty unless the name is the zone's own name
+    /// (its apex), which always exists because it has SOA and NS records, the
+    /// name is in a subtree delegated to other DNS servers, or the name has no
+    /// records of its own but some name below it does (an "empty
+    /// non-terminal", which exists according to RFC 8020).  For any other name
+    /// with no records, returns `QueryError::NoName`.
     pub(crate) fn query(
         &self,
         mr: &trust_dns_server::authority::MessageRequest,
-    ) -> Result<Vec<DnsRecord>, QueryError> {
+    ) -> Result<QueryAnswer, QueryError> {
         let name = mr.query().name();
         let orig_name = mr.query().original().name();
         self.query_name(name, orig_name)
@@ -597,7 +622,7 @@ impl Store {
         &self,
         name: &LowerName,
         orig_name: &Name,
-    ) -> Result<Vec<DnsRecord>, QueryError> {
+    ) -> Result<QueryAnswer, QueryError> {
         let config = self.read_config().map_err(QueryError::QueryFail)?;
 
         let zone_name = config
@@ -616,38 +641,41 @@ impl Store {
             .with_context(|| format!("open tree {:?}", tree_name))
             .map_err(QueryError::QueryFail)?;
 
-        // The name tree stores just the part of each name that doesn't include
-        // the zone.  So we need to trim the zone part from the name provided in
-        // the request.  (This basically duplicates work in `zone_of` above.)
+        let zone = self.zone(&tree, zone_name, config.generation)?;
         let name_str = orig_name.to_string();
-        let key = {
-            let zone_name = Name::from_str(zone_name).unwrap();
-            // This is implied by passing the `zone_of()` check above.
-            assert!(zone_name.num_labels() <= orig_name.num_labels());
-            let name_only_labels =
-                usize::from(orig_name.num_labels() - zone_name.num_labels());
-            let mut name_only =
-                Name::from_labels(orig_name.iter().take(name_only_labels))
-                    .unwrap();
-            name_only.set_fqdn(false);
-            let key = name_only.to_string().to_lowercase();
-            assert!(!key.ends_with('.'));
-            key
-        };
-
+        let is_apex = orig_name.num_labels() == zone.name.num_labels();
+        let key = key_for_name(&zone.name, orig_name);
         debug!(&self.log, "query key"; "key" => &key);
 
-        let bits = tree
-            .get(key.as_bytes())
-            .with_context(|| format!("query tree {:?}", tree_name))
-            .map_err(QueryError::QueryFail)?
-            .ok_or_else(|| QueryError::NoName(name_str.clone()))?;
+        if !is_apex {
+            if let Some(delegation) = self.delegation(&tree, &zone, &key)? {
+                return Ok(QueryAnswer {
+                    zone,
+                    is_apex,
+                    records: Vec::new(),
+                    delegation: Some(delegation),
+                });
+            }
+        }
 
-        let records: Vec<DnsRecord> = serde_json::from_slice(&bits)
-            .with_context(|| format!("deserialize record for key {:?}", key))
-            .map_err(QueryError::ParseFail)?;
+        let mut records = match self.read_records(&tree, &key)? {
+            Some(records) => records,
+            None if is_apex => Vec::new(),
+            // A name with no records of its own still exists if a name below
+            // it does, and queries for it get an empty answer rather than
+            // NXDOMAIN (RFC 8020).
+            None if self.has_descendants(&tree, &key)? => {
+                return Ok(QueryAnswer {
+                    zone,
+                    is_apex,
+                    records: Vec::new(),
+                    delegation: None,
+                });
+            }
+            None => return Err(QueryError::NoName(name_str, zone)),
+        };
 
-        if records.is_empty() {
+        if records.is_empty() && !is_apex {
             // This shouldn't be possible because we don't insert names with no
             // records.
             warn!(
@@ -656,20 +684,426 @@ impl Store {
                 "key" => &key
             );
 
-            return Err(QueryError::NoName(name_str));
+            return Err(QueryError::NoName(name_str, zone));
         }
 
-        Ok(records)
+        // NS records at the apex are reflected in the zone's nameservers.
+        if is_apex {
+            records.retain(|r| !matches!(r, DnsRecord::NS(_)));
+        }
+
+        Ok(QueryAnswer { zone, is_apex, records, delegation: None })
+    }
+
+    /// Like `query()`, but for an arbitrary name (like the target of a CNAME
+    /// record) rather than the one in a request
+    ///
+    /// Returns `None` if the name isn't in one of our zones or doesn't exist.
+    pub(crate) fn query_target(
+        &self,
+        name: &Name,
+    ) -> Result<Option<QueryAnswer>, QueryError> {
+        match self.query_name(&LowerName::from(name), name) {
+            Ok(answer) => Ok(Some(answer)),
+            Err(QueryError::NoZone(_)) | Err(QueryError::NoName(..)) => {
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns whether any name below the (non-apex) name stored under `key`
+    /// has records in the zone whose tree is `tree`
+    ///
+    /// Keys are relative to the zone's apex, so those of the names below this
+    /// one all end with "." followed by its key.  Zones are small enough that
+    /// scanning all of their keys is cheap.
+    fn has_descendants(
+        &self,
+        tree: &sled::Tree,
+        key: &str,
+    ) -> Result<bool, QueryError> {
+        let suffix = format!(".{}", key);
+        for entry in tree.iter().keys() {
+            let entry_key = entry
+                .with_context(|| format!("load key from tree for {:?}", key))
+                .map_err(QueryError::QueryFail)?;
+            if entry_key.ends_with(suffix.as_bytes()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the delegation covering the (non-apex) name stored under `key`
+    /// in the zone whose tree is `tree`, if any
+    ///
+    /// A name is delegated to other DNS servers if it or any of its ancestors
+    /// below the apex has NS records.  If there's more than one such name, the
+    /// one closest to the apex wins, since we're not authoritative for any of
+    /// the names below it.
+    fn delegation(
+        &self,
+        tree: &sled::Tree,
+        zone: &Zone,
+        key: &str,
+    ) -> Result<Option<Delegation>, QueryError> {
+        let labels = key.split('.').collect::<Vec<_>>();
+        for first_label in (0..labels.len()).rev() {
+            let ancestor_key = labels[first_label..].join(".");
+            let Some(records) = self.read_records(tree, &ancestor_key)? else {
+                continue;
+            };
+            let nameservers = records
+                .iter()
+                .filter_map(|record| match record {
+                    DnsRecord::NS(target) => Some(parse_target(target)),
+                    _ => None,
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if nameservers.is_empty() {
+                continue;
+            }
+
+            let name = Name::from_str(&ancestor_key)
+                .and_then(|name| name.append_domain(&zone.name))
+                .with_context(|| format!("parse key {:?}", ancestor_key))
+                .map_err(QueryError::ParseFail)?;
+
+            // Resolvers can't find the delegated servers if their names are
+            // only defined within this zone, so we include their addresses.
+            let mut glue = Vec::new();
+            for nameserver in &nameservers {
+                if !zone.name.zone_of(nameserver) {
+                    continue;
+                }
+                let key = key_for_name(&zone.name, nameserver);
+                let addrs = self
+                    .read_records(tree, &key)?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|record| {
+                        matches!(record, DnsRecord::A(_) | DnsRecord::AAAA(_))
+                    })
+                    .collect::<Vec<_>>();
+                if !addrs.is_empty() {
+                    glue.push((nameserver.clone(), addrs));
+                }
+            }
+
+            return Ok(Some(Delegation { name, nameservers, glue }));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the contents of the zone named in the given zone transfer
+    /// request
+    ///
+    /// The requested name must be the name of one of our zones.  If
+    /// `since_serial` is the SOA serial number of an earlier generation whose
+    /// data we still have, the zone's contents as of that generation are
+    /// returned as well, so that the caller can send just what's changed.
+    pub(crate) fn zone_transfer(
+        &self,
+        mr: &trust_dns_server::authority::MessageRequest,
+        since_serial: Option<u32>,
+    ) -> Result<ZoneTransfer, QueryError> {
+        let name = mr.query().name();
+        let orig_name = mr.query().original().name();
+        let config = self.read_config().map_err(QueryError::QueryFail)?;
+
+        let zone_name = config
+            .zones
+            .iter()
+            .find(|z| LowerName::from(Name::from_str(&z).unwrap()) == *name)
+            .ok_or_else(|| QueryError::NoZone(orig_name.to_string()))?;
+        let current = self.zone_contents(zone_name, config.generation)?;
+
+        let previous_generation = since_serial.and_then(|serial| {
+            self.all_name_trees()
+                .filter(|(gen_num, tree_name)| {
+                    *gen_num < config.generation
+                        && serial_for_generation(*gen_num) == serial
+                        && *tree_name
+                            == Self::tree_name_for_zone(zone_name, *gen_num)
+                })
+                .map(|(gen_num, _)| gen_num)
+                .max()
+        });
+        let previous = previous_generation
+            .map(|gen_num| self.zone_contents(zone_name, gen_num))
+            .transpose()?;
+
+        Ok(ZoneTransfer { current, previous })
+    }
+
+    /// Loads all of the records in the given zone as of the given generation
+    fn zone_contents(
+        &self,
+        zone_name: &str,
+        generation: u64,
+    ) -> Result<ZoneContents, QueryError> {
+        // TODO-correctness As with `dns_config()`, if this tree is pruned while
+        // we're reading it, we'll fail the request and the client will have to
+        // retry.
+        let tree_name = Self::tree_name_for_zone(zone_name, generation);
+        let tree = self
+            .db
+            .open_tree(&tree_name)
+            .with_context(|| format!("open tree {:?}", tree_name))
+            .map_err(QueryError::QueryFail)?;
+        let zone = self.zone(&tree, zone_name, generation)?;
+
+        let records = tree
+            .iter()
+            .map(|entry| {
+                let (key_bytes, records_bytes) = entry
+                    .with_context(|| format!("load entry from {:?}", tree_name))
+                    .map_err(QueryError::QueryFail)?;
+                let key = std::str::from_utf8(&key_bytes)
+                    .with_context(|| format!("parse {:?} key", tree_name))
+                    .map_err(QueryError::ParseFail)?;
+                let name = if key == KEY_APEX {
+                    zone.name.clone()
+                } else {
+                    Name::from_str(key)
+                        .and_then(|name| name.append_domain(&zone.name))
+                        .with_context(|| format!("parse {:?} key", tree_name))
+                        .map_err(QueryError::ParseFail)?
+                };
+                let mut records: Vec<DnsRecord> =
+                    serde_json::from_slice(&records_bytes)
+                        .with_context(|| {
+                            format!("deserialize record for key {:?}", key)
+                        })
+                        .map_err(QueryError::ParseFail)?;
+                // As with queries, NS records at the apex are reflected in the
+                // zone's nameservers.
+                if key == KEY_APEX {
+                    records.retain(|r| !matches!(r, DnsRecord::NS(_)));
+                }
+                Ok((name, records))
+            })
+            .collect::<Result<_, QueryError>>()?;
+
+        Ok(ZoneContents { zone, records })
+    }
+
+    /// Describes the zone whose tree (for the given generation) is `tree`
+    fn zone(
+        &self,
+        tree: &sled::Tree,
+        zone_name: &str,
+        generation: u64,
+    ) -> Result<Zone, QueryError> {
+        let mut name = Name::from_str(zone_name).unwrap();
+        name.set_fqdn(true);
+        let nameservers = self.zone_nameservers(tree, &name)?;
+        Ok(Zone { name, generation, nameservers })
+    }
+
+    /// Loads the records stored under `key` in a zone's tree, if any
+    fn read_records(
+        &self,
+        tree: &sled::Tree,
+        key: &str,
+    ) -> Result<Option<Vec<DnsRecord>>, QueryError> {
+        tree.get(key.as_bytes())
+            .with_context(|| format!("query key {:?}", key))
+            .map_err(QueryError::QueryFail)?
+            .map(|bits| {
+                serde_json::from_slice(&bits)
+                    .with_context(|| {
+                        format!("deserialize record for key {:?}", key)
+                    })
+                    .map_err(QueryError::ParseFail)
+            })
+            .transpose()
+    }
+
+    /// Returns the names of the DNS servers for the zone whose tree is `tree`
+    ///
+    /// These are the targets of the NS records at the zone's apex, if there
+    /// are any.  Otherwise, they're the targets of the zone's SRV records for
+    /// the DNS service itself (which is how internal DNS servers advertise
+    /// themselves).  Zones with neither are assumed to be served by "ns1"
+    /// within the zone.
+    fn zone_nameservers(
+        &self,
+        tree: &sled::Tree,
+        zone_name: &Name,
+    ) -> Result<Vec<Name>, QueryError> {
+        let mut nameservers = Vec::new();
+        let apex_records =
+            self.read_records(tree, KEY_APEX)?.unwrap_or_default();
+        for record in apex_records {
+            let DnsRecord::NS(target) = record else {
+                continue;
+            };
+            let target = parse_target(&target)?;
+            if !nameservers.contains(&target) {
+                nameservers.push(target);
+            }
+        }
+
+        if nameservers.is_empty() {
+            let records =
+                self.read_records(tree, KEY_NAMESERVICE)?.unwrap_or_default();
+            for record in records {
+                let DnsRecord::SRV(srv) = record else {
+                    continue;
+                };
+                let target = parse_target(&srv.target)?;
+                if !nameservers.contains(&target) {
+                    nameservers.push(target);
+                }
+            }
+        }
+
+        if nameservers.is_empty() {
+            nameservers.push(
+                Name::from_str("ns1")
+                    .unwrap()
+                    .append_domain(zone_name)
+                    .context("building nameserver name")
+                    .map_err(QueryError::ParseFail)?,
+            );
+        }
+
+        Ok(nameservers)
+    }
+}
+
+/// Returns the key under which the records for `name` are stored in the tree
+/// for the zone `zone_name` (which must contain `name`)
+///
+/// The name tree stores just the part of each name that doesn't include the
+/// zone.  The zone's own name is stored as "@", as in a zone file.
+fn key_for_name(zone_name: &Name, name: &Name) -> String {
+    if name.num_labels() == zone_name.num_labels() {
+        return String::from(KEY_APEX);
+    }
+
+    assert!(zone_name.num_labels() < name.num_labels());
+    let name_only_labels =
+        usize::from(name.num_labels() - zone_name.num_labels());
+    let mut name_only =
+        Name::from_labels(name.iter().take(name_only_labels)).unwrap();
+    name_only.set_fqdn(false);
+    let key = name_only.to_string().to_lowercase();
+    assert!(!key.ends_with('.'));
+    key
+}
+
+/// Parses the name that a record (like an NS or SRV record) points to
+fn parse_target(target: &str) -> Result<Name, QueryError> {
+    let mut name = Name::from_str(target)
+        .with_context(|| format!("parsing target name {:?}", target))
+        .map_err(QueryError::ParseFail)?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+/// Checks that the records for one name make sense together
+fn validate_records(records: &[DnsRecord]) -> Result<(), String> {
+    let has_cname = records.iter().any(|r| matches!(r, DnsRecord::CNAME(_)));
+    if has_cname && records.len() > 1 {
+        return Err(String::from(
+            "a name with a CNAME record cannot have any other records",
+        ));
+    }
+
+    for record in records {
+        if let DnsRecord::TXT(strings) = record {
+            if strings.iter().any(|s| s.len() > 255) {
+                return Err(String::from(
+                    "TXT record strings cannot be longer than 255 bytes",
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes the zone containing a name that was queried
+#[derive(Clone, Debug)]
+pub(crate) struct Zone {
+    /// fully-qualified name of the zone
+    pub name: Name,
+    /// generation of the DNS data that the zone came from
+    pub generation: u64,
+    /// fully-qualified names of the DNS servers for the zone (never empty)
+    pub nameservers: Vec<Name>,
+}
+
+impl Zone {
+    /// Returns the serial number for the zone's SOA record
+    pub fn serial(&self) -> u32 {
+        serial_for_generation(self.generation)
     }
 }
 
+/// Returns the SOA serial number for zones in the given generation
+///
+/// The serial number is the generation number of the DNS data.  Serial numbers
+/// are compared using sequence space arithmetic (RFC 1982), so it's fine for
+/// the generation number to be truncated to 32 bits.
+fn serial_for_generation(generation: u64) -> u32 {
+    generation as u32
+}
+
+/// Describes all of the DNS records in one of our zones
+#[derive(Debug)]
+pub(crate) struct ZoneContents {
+    pub zone: Zone,
+    /// fully-qualified names in the zone and the records for each one
+    pub records: Vec<(Name, Vec<DnsRecord>)>,
+}
+
+/// Describes the data needed to answer a zone transfer request
+#[derive(Debug)]
+pub(crate) struct ZoneTransfer {
+    /// the zone's contents as of the current generation
+    pub current: ZoneContents,
+    /// the zone's contents as of the generation that the client already has,
+    /// if we still have them
+    pub previous: Option<ZoneContents>,
+}
+
+/// Describes the DNS records found for a name in one of our zones
+#[derive(Debug)]
+pub(crate) struct QueryAnswer {
+    pub zone: Zone,
+    /// whether the queried name is the zone's own name
+    pub is_apex: bool,
+    pub records: Vec<DnsRecord>,
+    /// if the name is in a subtree of the zone that's been delegated to other
+    /// DNS servers, a description of that delegation (in which case `records`
+    /// is empty)
+    pub delegation: Option<Delegation>,
+}
+
+/// Describes a subtree of one of our zones that's been delegated to other DNS
+/// servers using NS records
+#[derive(Debug)]
+pub(crate) struct Delegation {
+    /// fully-qualified name of the delegated subtree
+    pub name: Name,
+    /// fully-qualified names of the DNS servers for the subtree
+    pub nameservers: Vec<Name>,
+    /// addresses of those DNS servers whose names are in our zone
+    pub glue: Vec<(Name, Vec<DnsRecord>)>,
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum QueryError {
     #[error("server is not authoritative for name: {0:?}")]
     NoZone(String),
 
     #[error("no records found for name: {0:?}")]
-    NoName(String),
+    NoName(String, Zone),
 
     #[error("failed to query database")]
     QueryFail(#[source] anyhow::Error),
@@ -835,6 +1269,7 @@ mod test {
     enum Expect<'a> {
         NoZone,
         NoName,
+        NoData,
         Record(&'a DnsRecord),
     }
 
@@ -851,9 +1286,14 @@ mod test {
 
         match (expect, result) {
             (Expect::NoZone, Err(QueryError::NoZone(n))) if n == name => (),
-            (Expect::NoName, Err(QueryError::NoName(n))) if n == name => (),
-            (Expect::Record(r), Ok(records))
-                if records.len() == 1 && records[0] == *r =>
+            (Expect::NoName, Err(QueryError::NoName(n, _))) if n == name => (),
+            (Expect::NoData, Ok(answer))
+                if answer.records.is_empty() && answer.delegation.is_none() =>
+            {
+                ()
+            }
+            (Expect::Record(r), Ok(answer))
+                if answer.records.len() == 1 && answer.records[0] == *r =>
             {
                 ()
             }
@@ -898,6 +1338,7 @@ mod test {
                 records: HashMap::from([
                     ("gen1_name".to_string(), vec![dummy_record.clone()]),
                     ("shared_name".to_string(), vec![dummy_record.clone()]),
+                    ("deep.ent".to_string(), vec![dummy_record.clone()]),
                 ]),
             }],
         };
@@ -925,6 +1366,14 @@ mod test {
             Expect::Record(&dummy_record),
         );
         expect(&tc.store, "enoent.zone1.internal", Expect::NoName);
+        expect(
+            &tc.store,
+            "deep.ent.zone1.internal",
+            Expect::Record(&dummy_record),
+        );
+        // "ent" has no records, but the name below it does, so it exists.
+        expect(&tc.store, "ent.zone1.internal", Expect::NoData);
+        expect(&tc.store, "ENT.zone1.internal", Expect::NoData);
         expect(&tc.store, "gen2_name.zone2.internal", Expect::NoZone);
         expect(&tc.store, "gen8_name.zone8.internal", Expect::NoZone);
 
@@ -955,6 +1404,7 @@ mod test {
         expect(&tc.store, "gen1_name.zone1.internal", Expect::NoName);
         expect(&tc.store, "gen1_name.ZONE1.internal", Expect::NoName);
         expect(&tc.store, "Gen1_name.zone1.internal", Expect::NoName);
+        expect(&tc.store, "ent.zone1.internal", Expect::NoName);
         expect(
             &tc.store,
             "shared_name.zone1.internal",
@@ -1234,4 +1684,138 @@ mod test {
 
         tc.cleanup_successful();
     }
+
+    #[tokio::test]
+    async fn test_update_invalid_records() {
+        let tc = TestContext::new("test_update_invalid_records");
+
+        let cname_and_aaaa = DnsConfigParams {
+            time_created: chrono::Utc::now(),
+            generation: 1,
+            zones: vec![DnsConfigZone {
+                zone_name: "zone1.internal".to_string(),
+                records: HashMap::from([(
+                    "alias".to_string(),
+                    vec![
+                        DnsRecord::CNAME("target.zone1.internal".to_string()),
+                        DnsRecord::AAAA(Ipv6Addr::LOCALHOST),
+                    ],
+                )]),
+            }],
+        };
+        let error = tc
+            .store
+            .dns_config_update(&cname_and_aaaa, "my request id")
+            .await
+            .expect_err("unexpected success with CNAME and other records");
+        println!("found error: {:#}", error);
+        assert!(matches!(
+            &error,
+            UpdateError::InvalidRecords { zone_name, name, .. }
+                if zone_name == "zone1.internal" && name == "alias"
+        ));
+
+        let long_txt = DnsConfigParams {
+            time_created: chrono::Utc::now(),
+            generation: 1,
+            zones: vec![DnsConfigZone {
+                zone_name: "zone1.internal".to_string(),
+                records: HashMap::from([(
+                    "_acme-challenge".to_string(),
+                    vec![DnsRecord::TXT(vec!["a".repeat(256)])],
+                )]),
+            }],
+        };
+        let error = tc
+            .store
+            .dns_config_update(&long_txt, "my request id")
+            .await
+            .expect_err("unexpected success with long TXT string");
+        println!("found error: {:#}", error);
+        assert!(matches!(&error, UpdateError::InvalidRecords { .. }));
+
+        // Neither update should have been applied.
+        assert!(generations_with_trees(&tc.store).is_empty());
+        assert_eq!(tc.store.dns_config().await.unwrap().generation, 0);
+
+        tc.cleanup_successful();
+    }
+
+    #[tokio::test]
+    async fn test_query_delegation() {
+        let tc = TestContext::new("test_query_delegation");
+
+        let ns_addr = DnsRecord::AAAA(Ipv6Addr::LOCALHOST);
+        let update = DnsConfigParams {
+            time_created: chrono::Utc::now(),
+            generation: 1,
+            zones: vec![DnsConfigZone {
+                zone_name: "zone1.internal".to_string(),
+                records: HashMap::from([
+                    (
+                        "@".to_string(),
+                        vec![DnsRecord::NS("ns.zone1.internal".to_string())],
+                    ),
+                    ("ns".to_string(), vec![ns_addr.clone()]),
+                    (
+                        "sub".to_string(),
+                        vec![
+                            DnsRecord::NS("ns.zone1.internal".to_string()),
+                            DnsRecord::NS("ns.elsewhere.test".to_string()),
+                        ],
+                    ),
+                    ("name.sub".to_string(), vec![ns_addr.clone()]),
+                ]),
+            }],
+        };
+        tc.store.dns_config_update(&update, "my request id").await.unwrap();
+
+        // The apex's NS records determine the zone's nameservers, but aren't
+        // returned as ordinary records.
+        let apex = Name::from_str("zone1.internal").unwrap();
+        let answer = tc.store.query_target(&apex).unwrap().unwrap();
+        assert!(answer.is_apex);
+        assert!(answer.records.is_empty());
+        assert!(answer.delegation.is_none());
+        assert_eq!(
+            answer.zone.nameservers,
+            vec![Name::from_str("ns.zone1.internal.").unwrap()]
+        );
+
+        // Names outside the delegated subtree are answered normally.
+        expect(&tc.store, "ns.zone1.internal", Expect::Record(&ns_addr));
+
+        // The delegation point and everything below it (whether or not we have
+        // records for it) are delegated.
+        for name in [
+            "sub.zone1.internal",
+            "name.sub.zone1.internal",
+            "enoent.sub.zone1.internal",
+        ] {
+            let name = Name::from_str(name).unwrap();
+            let answer = tc.store.query_target(&name).unwrap().unwrap();
+            assert!(answer.records.is_empty());
+            let delegation = answer.delegation.expect("expected delegation");
+            assert_eq!(
+                delegation.name,
+                Name::from_str("sub.zone1.internal.").unwrap()
+            );
+            assert_eq!(
+                delegation.nameservers,
+                vec![
+                    Name::from_str("ns.zone1.internal.").unwrap(),
+                    Name::from_str("ns.elsewhere.test.").unwrap(),
+                ]
+            );
+            assert_eq!(
+                delegation.glue,
+                vec![(
+                    Name::from_str("ns.zone1.internal.").unwrap(),
+                    vec![ns_addr.clone()]
+                )]
+            );
+        }
+
+        tc.cleanup_successful();
+    }
 }
@@ -3,15 +3,26 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use anyhow::{Context, Result};
+use dns_server::dns_server::Limits;
 use dns_service_client::{
-    types::{DnsConfigParams, DnsConfigZone, DnsRecord, Srv},
+    types::{DnsConfigParams, DnsConfigZone, DnsRecord, Mx, Srv},
     Client,
 };
 use dropshot::test_util::LogContext;
 use omicron_test_utils::dev::test_setup_log;
 use slog::o;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
 use std::{collections::HashMap, net::Ipv4Addr, net::Ipv6Addr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, Query};
+use trust_dns_proto::rr::rdata::SOA;
+use trust_dns_proto::rr::{Name, RData, Record};
+use trust_dns_proto::serialize::binary::BinEncodable;
 use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::proto::rr::RecordType;
 use trust_dns_resolver::TokioAsyncResolver;
 use trust_dns_resolver::{
     config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
@@ -306,6 +317,743 @@ pub async fn servfail() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+#[tokio::test]
+pub async fn query_type() -> Result<(), anyhow::Error> {
+    let test_ctx = init_client_server("query_type").await?;
+    let client = &test_ctx.client;
+    let resolver = &test_ctx.resolver;
+
+    // Add a name with both A and AAAA records, plus an SRV record elsewhere.
+    let name = "devron".to_string();
+    let addr4 = Ipv4Addr::new(10, 1, 2, 3);
+    let addr6 = Ipv6Addr::new(0xfd, 0, 0, 0, 0, 0, 0, 0x1);
+    let srv =
+        Srv { prio: 47, weight: 74, port: 99, target: "outpost47".into() };
+    let input_records = HashMap::from([
+        (name.clone(), vec![DnsRecord::A(addr4), DnsRecord::Aaaa(addr6)]),
+        ("hromi".to_string(), vec![DnsRecord::Srv(srv)]),
+    ]);
+    dns_records_create(client, TEST_ZONE, input_records).await?;
+    let fqdn = format!("{}.{}.", name, TEST_ZONE);
+
+    // Each query should get back only the records of the type it asked for.
+    let response = resolver.ipv4_lookup(fqdn.clone()).await?;
+    assert_eq!(response.iter().collect::<Vec<_>>(), vec![&addr4]);
+    let response = resolver.ipv6_lookup(fqdn.clone()).await?;
+    assert_eq!(response.iter().collect::<Vec<_>>(), vec![&addr6]);
+
+    // Asking for a type that the name doesn't have should get back NODATA
+    // (not NXDOMAIN), with the zone's SOA.
+    lookup_expect_nodata(resolver, &fqdn, RecordType::SRV).await;
+    lookup_expect_nodata(
+        resolver,
+        &format!("hromi.{}.", TEST_ZONE),
+        RecordType::AAAA,
+    )
+    .await;
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn soa_and_ns() -> Result<(), anyhow::Error> {
+    let test_ctx = init_client_server("soa_and_ns").await?;
+    let client = &test_ctx.client;
+    let resolver = &test_ctx.resolver;
+    let zone_fqdn = format!("{}.", TEST_ZONE);
+
+    // Without records for the DNS service itself, the zone's nameserver is
+    // assumed to be "ns1" within the zone.
+    let addr = Ipv6Addr::new(0xfd, 0, 0, 0, 0, 0, 0, 0x1);
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([("devron".to_string(), vec![DnsRecord::Aaaa(addr)])]),
+    )
+    .await?;
+    let generation = client.dns_config_get().await?.into_inner().generation;
+
+    let response = resolver.soa_lookup(zone_fqdn.clone()).await?;
+    let soa = response.iter().next().expect("no SOA returned");
+    assert_eq!(u64::from(soa.serial()), generation);
+    assert_eq!(soa.mname().to_string(), format!("ns1.{}", zone_fqdn));
+    let response = resolver.ns_lookup(zone_fqdn.clone()).await?;
+    let nameservers =
+        response.iter().map(|ns| ns.to_string()).collect::<Vec<_>>();
+    assert_eq!(nameservers, vec![format!("ns1.{}", zone_fqdn)]);
+
+    // The zone's own name has no address records, but it does exist.
+    lookup_expect_nodata(resolver, &zone_fqdn, RecordType::AAAA).await;
+
+    // With records for the DNS service, its servers are the nameservers.  The
+    // SOA serial follows the generation.
+    let srv = Srv {
+        prio: 0,
+        weight: 0,
+        port: 5353,
+        target: format!("ns-a.{}", TEST_ZONE),
+    };
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([(
+            "_nameservice._tcp".to_string(),
+            vec![DnsRecord::Srv(srv)],
+        )]),
+    )
+    .await?;
+    let response = resolver.soa_lookup(zone_fqdn.clone()).await?;
+    let soa = response.iter().next().expect("no SOA returned");
+    assert_eq!(u64::from(soa.serial()), generation + 1);
+    assert_eq!(soa.mname().to_string(), format!("ns-a.{}", zone_fqdn));
+    let response = resolver.ns_lookup(zone_fqdn.clone()).await?;
+    let nameservers =
+        response.iter().map(|ns| ns.to_string()).collect::<Vec<_>>();
+    assert_eq!(nameservers, vec![format!("ns-a.{}", zone_fqdn)]);
+
+    // SOA and NS records exist only for the zone's own name.
+    lookup_expect_nodata(
+        resolver,
+        &format!("devron.{}", zone_fqdn),
+        RecordType::SOA,
+    )
+    .await;
+
+    // NXDOMAIN responses include the SOA, too.
+    match resolver.lookup_ip(format!("unicorn.{}", zone_fqdn)).await {
+        Ok(unexpected) => {
+            panic!("Expected NXDOMAIN, got record {:?}", unexpected);
+        }
+        Err(e) => match e.kind() {
+            ResolveErrorKind::NoRecordsFound {
+                response_code: ResponseCode::NXDomain,
+                soa: Some(_),
+                ..
+            } => (),
+            unexpected => {
+                panic!(
+                    "Expected NXDOMAIN with SOA, got error {:?}",
+                    unexpected
+                );
+            }
+        },
+    };
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn cname_chase() -> Result<(), anyhow::Error> {
+    let test_ctx = init_client_server("cname_chase").await?;
+    let client = &test_ctx.client;
+    let resolver = &test_ctx.resolver;
+    let server_addr = *test_ctx.dns_server.local_address();
+
+    let addr = Ipv6Addr::new(0xfd, 0, 0, 0, 0, 0, 0, 0x1);
+    let cname =
+        |target: &str| DnsRecord::Cname(format!("{}.{}", target, TEST_ZONE));
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([
+            ("target".to_string(), vec![DnsRecord::Aaaa(addr)]),
+            ("alias".to_string(), vec![cname("target")]),
+            ("alias2".to_string(), vec![cname("alias")]),
+            ("loop1".to_string(), vec![cname("loop2")]),
+            ("loop2".to_string(), vec![cname("loop1")]),
+            (
+                "external".to_string(),
+                vec![DnsRecord::Cname("example.com".to_string())],
+            ),
+        ]),
+    )
+    .await?;
+
+    // The answer for an alias includes the whole chain of CNAME records,
+    // followed by the records for the name at the end of it.
+    let name = format!("alias2.{}.", TEST_ZONE);
+    let response =
+        send_udp(server_addr, &make_query(&name, RecordType::AAAA)?).await?;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.header().authoritative());
+    let answers = response
+        .answers()
+        .iter()
+        .map(|r| (r.name().to_string(), r.data().cloned()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        answers,
+        vec![
+            (
+                name.clone(),
+                Some(RData::CNAME(Name::from_str(&format!(
+                    "alias.{}.",
+                    TEST_ZONE
+                ))?))
+            ),
+            (
+                format!("alias.{}.", TEST_ZONE),
+                Some(RData::CNAME(Name::from_str(&format!(
+                    "target.{}.",
+                    TEST_ZONE
+                ))?))
+            ),
+            (format!("target.{}.", TEST_ZONE), Some(RData::AAAA(addr))),
+        ]
+    );
+    let response = resolver.ipv6_lookup(name.clone()).await?;
+    assert_eq!(response.iter().copied().collect::<Vec<_>>(), vec![addr]);
+
+    // Queries for the CNAME record itself aren't chased.
+    let response =
+        send_udp(server_addr, &make_query(&name, RecordType::CNAME)?).await?;
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(response.answers()[0].record_type(), RecordType::CNAME);
+
+    // Loops end when they come back around.
+    let name = format!("loop1.{}.", TEST_ZONE);
+    let response =
+        send_udp(server_addr, &make_query(&name, RecordType::AAAA)?).await?;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), 2);
+
+    // Targets outside our zones are left for the resolver to follow.
+    let name = format!("external.{}.", TEST_ZONE);
+    let response =
+        send_udp(server_addr, &make_query(&name, RecordType::AAAA)?).await?;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(
+        response.answers()[0].data(),
+        Some(&RData::CNAME(Name::from_str("example.com.")?))
+    );
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn txt_ptr_mx() -> Result<(), anyhow::Error> {
+    let test_ctx = init_client_server("txt_ptr_mx").await?;
+    let client = &test_ctx.client;
+    let resolver = &test_ctx.resolver;
+
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([
+            (
+                "_acme-challenge".to_string(),
+                vec![DnsRecord::Txt(vec![
+                    "first".to_string(),
+                    "second".to_string(),
+                ])],
+            ),
+            (
+                "@".to_string(),
+                vec![DnsRecord::Mx(Mx {
+                    preference: 10,
+                    exchange: format!("mail.{}", TEST_ZONE),
+                })],
+            ),
+        ]),
+    )
+    .await?;
+
+    let response =
+        resolver.txt_lookup(format!("_acme-challenge.{}.", TEST_ZONE)).await?;
+    let txt = response.iter().next().expect("no TXT returned");
+    assert_eq!(
+        txt.txt_data(),
+        &[
+            b"first".to_vec().into_boxed_slice(),
+            b"second".to_vec().into_boxed_slice()
+        ]
+    );
+
+    let response = resolver.mx_lookup(format!("{}.", TEST_ZONE)).await?;
+    let mx = response.iter().next().expect("no MX returned");
+    assert_eq!(mx.preference(), 10);
+    assert_eq!(mx.exchange().to_string(), format!("mail.{}.", TEST_ZONE));
+
+    // Reverse lookups work for addresses in a reverse zone for a /64.
+    let addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x1);
+    dns_records_create(
+        client,
+        "0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa",
+        HashMap::from([(
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0".to_string(),
+            vec![DnsRecord::Ptr(format!("devron.{}", TEST_ZONE))],
+        )]),
+    )
+    .await?;
+    let response = resolver.reverse_lookup(addr.into()).await?;
+    let names = response.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    assert_eq!(names, vec![format!("devron.{}.", TEST_ZONE)]);
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn ns_delegation() -> Result<(), anyhow::Error> {
+    let test_ctx = init_client_server("ns_delegation").await?;
+    let client = &test_ctx.client;
+    let resolver = &test_ctx.resolver;
+    let server_addr = *test_ctx.dns_server.local_address();
+    let zone_fqdn = format!("{}.", TEST_ZONE);
+
+    let addr = Ipv6Addr::new(0xfd, 0, 0, 0, 0, 0, 0, 0x1);
+    let ns_name = format!("ns.{}", TEST_ZONE);
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([
+            ("@".to_string(), vec![DnsRecord::Ns(ns_name.clone())]),
+            ("ns".to_string(), vec![DnsRecord::Aaaa(addr)]),
+            ("sub".to_string(), vec![DnsRecord::Ns(ns_name.clone())]),
+            ("devron.sub".to_string(), vec![DnsRecord::Aaaa(addr)]),
+        ]),
+    )
+    .await?;
+
+    // NS records at the apex name the zone's own nameservers.
+    let response = resolver.ns_lookup(zone_fqdn.clone()).await?;
+    let nameservers =
+        response.iter().map(|ns| ns.to_string()).collect::<Vec<_>>();
+    assert_eq!(nameservers, vec![format!("{}.", ns_name)]);
+    let response = resolver.soa_lookup(zone_fqdn.clone()).await?;
+    let soa = response.iter().next().expect("no SOA returned");
+    assert_eq!(soa.mname().to_string(), format!("{}.", ns_name));
+
+    // Queries for names in a delegated subtree get a referral to its
+    // nameservers, along with their addresses, even if we have records for
+    // them.
+    for name in ["sub", "devron.sub", "unicorn.sub"] {
+        let name = format!("{}.{}", name, zone_fqdn);
+        let response =
+            send_udp(server_addr, &make_query(&name, RecordType::AAAA)?)
+                .await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(!response.header().authoritative());
+        assert!(response.answers().is_empty());
+        let referral = response
+            .name_servers()
+            .iter()
+            .map(|r| (r.name().to_string(), r.data().cloned()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            referral,
+            vec![(
+                format!("sub.{}", zone_fqdn),
+                Some(RData::NS(Name::from_str(&format!("{}.", ns_name))?))
+            )]
+        );
+        let glue = response
+            .additionals()
+            .iter()
+            .map(|r| (r.name().to_string(), r.data().cloned()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            glue,
+            vec![(format!("{}.", ns_name), Some(RData::AAAA(addr)))]
+        );
+    }
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn large_response() -> Result<(), anyhow::Error> {
+    let test_ctx = init_client_server("large_response").await?;
+    let client = &test_ctx.client;
+    let resolver = &test_ctx.resolver;
+    let server_addr = *test_ctx.dns_server.local_address();
+
+    // Add more SRV records for one name than fit in a UDP response, plus one
+    // SRV record for another name.
+    let name = "_big._tcp".to_string();
+    let nbackends = 100;
+    let srvs = (0..nbackends)
+        .map(|i| {
+            DnsRecord::Srv(Srv {
+                prio: 0,
+                weight: 0,
+                port: 12345,
+                target: format!("backend-{:03}.{}", i, TEST_ZONE),
+            })
+        })
+        .collect();
+    let small_name = "_small._tcp".to_string();
+    let small_srv = DnsRecord::Srv(Srv {
+        prio: 0,
+        weight: 0,
+        port: 12345,
+        target: format!("backend-000.{}", TEST_ZONE),
+    });
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([
+            (name.clone(), srvs),
+            (small_name.clone(), vec![small_srv]),
+        ]),
+    )
+    .await?;
+    let fqdn = format!("{}.{}.", name, TEST_ZONE);
+
+    // Without EDNS, a UDP response is limited to 512 bytes.  It should be
+    // truncated, and say so.
+    let response = query_udp(server_addr, &fqdn, None).await?;
+    assert!(response.truncated());
+    assert!(response.answers().len() < nbackends);
+    assert!(response.edns().is_none());
+    let nanswers_small = response.answers().len();
+
+    // With EDNS, the client can ask for more, but the server still caps what
+    // it'll send over UDP.
+    let response = query_udp(server_addr, &fqdn, Some(65000)).await?;
+    assert!(response.truncated());
+    assert!(response.answers().len() > nanswers_small);
+    assert!(response.answers().len() < nbackends);
+
+    // A response that fits isn't truncated, and (when the client uses EDNS)
+    // says what the server's cap is.
+    let small_fqdn = format!("{}.{}.", small_name, TEST_ZONE);
+    let response = query_udp(server_addr, &small_fqdn, Some(65000)).await?;
+    assert!(!response.truncated());
+    assert_eq!(response.answers().len(), 1);
+    let edns = response.edns().expect("response had no EDNS");
+    assert_eq!(edns.max_payload(), 1232);
+
+    // The resolver should retry over TCP and get the whole set.
+    let response = resolver.srv_lookup(fqdn).await?;
+    assert_eq!(response.iter().count(), nbackends);
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn zone_transfer() -> Result<(), anyhow::Error> {
+    let test_ctx = init_client_server("zone_transfer").await?;
+    let client = &test_ctx.client;
+    let server_addr = *test_ctx.dns_server.local_address();
+    let zone_fqdn = format!("{}.", TEST_ZONE);
+
+    let addr6 = Ipv6Addr::new(0xfd, 0, 0, 0, 0, 0, 0, 0x1);
+    let srv =
+        Srv { prio: 47, weight: 74, port: 99, target: "outpost47".into() };
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([
+            ("devron".to_string(), vec![DnsRecord::Aaaa(addr6)]),
+            ("hromi".to_string(), vec![DnsRecord::Srv(srv)]),
+        ]),
+    )
+    .await?;
+    let gen1 = client.dns_config_get().await?.into_inner().generation;
+    let serial1 = u32::try_from(gen1).unwrap();
+
+    // AXFR gets the whole zone, between two copies of the SOA record.
+    let axfr = make_query(&zone_fqdn, RecordType::AXFR)?;
+    let records = transfer_tcp(server_addr, &axfr).await?;
+    let types = records.iter().map(|r| r.record_type()).collect::<Vec<_>>();
+    assert_eq!(records.len(), 5, "unexpected records: {:?}", records);
+    assert_eq!(types[0], RecordType::SOA);
+    assert_eq!(types[4], RecordType::SOA);
+    assert_eq!(soa_serial(&records[0]), serial1);
+    for expected in [RecordType::NS, RecordType::AAAA, RecordType::SRV] {
+        assert!(types.contains(&expected), "missing {} record", expected);
+    }
+
+    // AXFR isn't allowed over UDP.
+    let response = send_udp(server_addr, &axfr).await?;
+    assert_eq!(response.response_code(), ResponseCode::FormErr);
+
+    // Transfers are only possible for whole zones that we serve.
+    let not_zone =
+        make_query(&format!("devron.{}", zone_fqdn), RecordType::AXFR)?;
+    assert!(transfer_tcp(server_addr, &not_zone).await.is_err());
+
+    // Make a change, then ask for what's changed since the first generation.
+    let addr4 = Ipv4Addr::new(10, 1, 2, 3);
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([("emy".to_string(), vec![DnsRecord::A(addr4)])]),
+    )
+    .await?;
+    let serial2 = serial1 + 1;
+    let ixfr = make_ixfr_query(&zone_fqdn, serial1)?;
+    let records = transfer_tcp(server_addr, &ixfr).await?;
+    assert_eq!(records.len(), 5, "unexpected records: {:?}", records);
+    assert_eq!(soa_serial(&records[0]), serial2);
+    assert_eq!(soa_serial(&records[1]), serial1);
+    assert_eq!(soa_serial(&records[2]), serial2);
+    assert_eq!(records[3].record_type(), RecordType::A);
+    assert_eq!(records[3].name().to_string(), format!("emy.{}", zone_fqdn));
+    assert_eq!(soa_serial(&records[4]), serial2);
+
+    // A client that's up to date gets just the SOA record.
+    let ixfr = make_ixfr_query(&zone_fqdn, serial2)?;
+    let records = transfer_tcp(server_addr, &ixfr).await?;
+    assert_eq!(records.len(), 1);
+    assert_eq!(soa_serial(&records[0]), serial2);
+
+    // A client with a version we don't know about gets the whole zone.
+    let ixfr = make_ixfr_query(&zone_fqdn, serial1 + 1000)?;
+    let records = transfer_tcp(server_addr, &ixfr).await?;
+    assert_eq!(records.len(), 6, "unexpected records: {:?}", records);
+    assert_eq!(soa_serial(&records[0]), serial2);
+    assert_eq!(soa_serial(&records[5]), serial2);
+
+    // Over UDP, IXFR gets just the SOA record, telling the client to use TCP.
+    let ixfr = make_ixfr_query(&zone_fqdn, serial1)?;
+    let response = send_udp(server_addr, &ixfr).await?;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(soa_serial(&response.answers()[0]), serial2);
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn rate_limit() -> Result<(), anyhow::Error> {
+    let limits = Limits {
+        rate_limit: Some(dns_server::rate_limit::Config {
+            responses_per_second: 1,
+            slip: 1,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+        }),
+        ..Default::default()
+    };
+    let test_ctx = init_client_server_with_limits("rate_limit", limits).await?;
+    let client = &test_ctx.client;
+    let resolver = &test_ctx.resolver;
+    let server_addr = *test_ctx.dns_server.local_address();
+    let counters = test_ctx.dns_server.counters();
+
+    let name = "emy";
+    let fqdn = format!("{}.{}.", name, TEST_ZONE);
+    let addr = Ipv4Addr::new(10, 1, 2, 3);
+    dns_records_create(
+        client,
+        TEST_ZONE,
+        HashMap::from([(name.to_string(), vec![DnsRecord::A(addr)])]),
+    )
+    .await?;
+
+    // The first query is answered as usual.
+    let query = make_query(&fqdn, RecordType::A)?;
+    let response = send_udp(server_addr, &query).await?;
+    assert!(!response.truncated());
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(counters.queries_rate_limited(), 0);
+
+    // The next one is over the limit.  With a "slip" of 1, it gets an empty,
+    // truncated response.
+    let response = send_udp(server_addr, &query).await?;
+    assert!(response.truncated());
+    assert!(response.answers().is_empty());
+    assert_eq!(counters.queries_rate_limited(), 1);
+
+    // That tells a real client to retry over TCP, which isn't limited.
+    let found = resolver.ipv4_lookup(&fqdn).await?;
+    assert_eq!(found.iter().next(), Some(&addr));
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn load_shedding() -> Result<(), anyhow::Error> {
+    // With no room for any requests, all of them are dropped.
+    let limits = Limits {
+        max_concurrent_udp_requests: 0,
+        max_tcp_connections: 0,
+        rate_limit: None,
+    };
+    let test_ctx =
+        init_client_server_with_limits("load_shedding", limits).await?;
+    let server_addr = *test_ctx.dns_server.local_address();
+    let counters = test_ctx.dns_server.counters();
+
+    let query = make_query(&format!("{}.", TEST_ZONE), RecordType::SOA)?;
+    let result = tokio::time::timeout(
+        Duration::from_secs(1),
+        send_udp(server_addr, &query),
+    )
+    .await;
+    assert!(result.is_err(), "unexpected response: {:?}", result);
+    assert_eq!(counters.queries_dropped(), 1);
+
+    // TCP connections are closed without being read.
+    let mut stream = TcpStream::connect(server_addr).await?;
+    let mut buf = [0u8; 2];
+    let result = stream.read(&mut buf).await;
+    assert!(
+        matches!(result, Ok(0) | Err(_)),
+        "unexpected result reading from connection: {:?}",
+        result
+    );
+    assert_eq!(counters.connections_dropped(), 1);
+
+    // The counters are reported to oximeter.
+    let registry = test_ctx.dropshot_server.app_private().producer_registry();
+    let results = registry.collect();
+    assert_eq!(results.len(), 1);
+    let oximeter::types::ProducerResultsItem::Ok(samples) = &results[0] else {
+        panic!("failed to collect metrics: {:?}", results[0]);
+    };
+    let names = samples
+        .iter()
+        .map(|sample| sample.timeseries_name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "dns_server:queries_dropped",
+            "dns_server:queries_rate_limited",
+            "dns_server:connections_dropped",
+        ]
+    );
+
+    test_ctx.cleanup().await;
+    Ok(())
+}
+
+/// Sends an SRV query for `name` directly over UDP, optionally with an EDNS
+/// record advertising `max_payload`, and returns the raw response
+async fn query_udp(
+    server_addr: SocketAddr,
+    name: &str,
+    max_payload: Option<u16>,
+) -> Result<Message, anyhow::Error> {
+    let mut query = make_query(name, RecordType::SRV)?;
+    if let Some(max_payload) = max_payload {
+        let mut edns = Edns::new();
+        edns.set_max_payload(max_payload);
+        query.set_edns(edns);
+    }
+    send_udp(server_addr, &query).await
+}
+
+/// Returns a query for `name`'s records of type `record_type`
+fn make_query(
+    name: &str,
+    record_type: RecordType,
+) -> Result<Message, anyhow::Error> {
+    let mut query = Message::new();
+    query
+        .set_id(1)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .add_query(Query::query(Name::from_str(name)?, record_type));
+    Ok(query)
+}
+
+/// Returns an IXFR query for changes to `zone` since the version with SOA
+/// serial number `serial`
+fn make_ixfr_query(zone: &str, serial: u32) -> Result<Message, anyhow::Error> {
+    let mut query = make_query(zone, RecordType::IXFR)?;
+    let zone_name = Name::from_str(zone)?;
+    let soa =
+        SOA::new(zone_name.clone(), zone_name.clone(), serial, 0, 0, 0, 0);
+    query.add_name_server(Record::from_rdata(zone_name, 0, RData::SOA(soa)));
+    Ok(query)
+}
+
+/// Sends `query` directly over UDP and returns the raw response
+async fn send_udp(
+    server_addr: SocketAddr,
+    query: &Message,
+) -> Result<Message, anyhow::Error> {
+    let socket = UdpSocket::bind("[::1]:0").await?;
+    socket.send_to(&query.to_vec()?, server_addr).await?;
+    let mut buf = vec![0u8; 65536];
+    let (n, _) = socket.recv_from(&mut buf).await?;
+    Ok(Message::from_vec(&buf[..n])?)
+}
+
+/// Sends a zone transfer `query` over TCP and returns the records from all of
+/// the response messages
+///
+/// A transfer consists of either a single SOA record or a sequence of records
+/// that begins and ends with the same SOA record.
+async fn transfer_tcp(
+    server_addr: SocketAddr,
+    query: &Message,
+) -> Result<Vec<Record>, anyhow::Error> {
+    let mut stream = TcpStream::connect(server_addr).await?;
+    let query_bytes = query.to_vec()?;
+    stream.write_u16(u16::try_from(query_bytes.len())?).await?;
+    stream.write_all(&query_bytes).await?;
+
+    let mut records: Vec<Record> = Vec::new();
+    loop {
+        let length = stream.read_u16().await?;
+        let mut buf = vec![0u8; usize::from(length)];
+        stream.read_exact(&mut buf).await?;
+        let response = Message::from_vec(&buf)?;
+        if response.response_code() != ResponseCode::NoError {
+            anyhow::bail!(
+                "zone transfer failed: {:?}",
+                response.response_code()
+            );
+        }
+        records.extend(response.answers().iter().cloned());
+
+        let done = match records.as_slice() {
+            [only] => only.record_type() == RecordType::SOA,
+            [first, .., last] => {
+                last.record_type() == RecordType::SOA
+                    && first.data() == last.data()
+            }
+            [] => false,
+        };
+        if done {
+            return Ok(records);
+        }
+    }
+}
+
+/// Returns the serial number of an SOA record
+fn soa_serial(record: &Record) -> u32 {
+    match record.data() {
+        Some(RData::SOA(soa)) => soa.serial(),
+        _ => panic!("expected SOA record, found {:?}", record),
+    }
+}
+
+async fn lookup_expect_nodata(
+    resolver: &TokioAsyncResolver,
+    name: &str,
+    record_type: RecordType,
+) {
+    match resolver.lookup(name, record_type).await {
+        Ok(unexpected) => {
+            panic!("Expected NODATA, got records {:?}", unexpected);
+        }
+        Err(e) => match e.kind() {
+            ResolveErrorKind::NoRecordsFound {
+                response_code: ResponseCode::NoError,
+                soa: Some(_),
+                ..
+            } => (),
+            unexpected => {
+                panic!("Expected NODATA, got error {:?}", unexpected);
+            }
+        },
+    };
+}
+
 struct TestContext {
     client: Client,
     resolver: TokioAsyncResolver,
@@ -326,6 +1074,13 @@ impl TestContext {
 
 async fn init_client_server(
     test_name: &str,
+) -> Result<TestContext, anyhow::Error> {
+    init_client_server_with_limits(test_name, Limits::default()).await
+}
+
+async fn init_client_server_with_limits(
+    test_name: &str,
+    limits: Limits,
 ) -> Result<TestContext, anyhow::Error> {
     // initialize dns server config
     let (tmp, config_storage, config_dropshot, logctx) =
@@ -343,6 +1098,7 @@ async fn init_client_server(
     // launch a dns server
     let dns_server_config = dns_server::dns_server::Config {
         bind_address: "[::1]:0".parse().unwrap(),
+        limits,
     };
     let (dns_server, dropshot_server) = dns_server::start_servers(
         log.clone(),
@@ -352,14 +1108,17 @@ async fn init_client_server(
     )
     .await?;
 
+    // The resolver falls back to TCP when a UDP response is truncated.
     let mut rc = ResolverConfig::new();
-    rc.add_name_server(NameServerConfig {
-        socket_addr: *dns_server.local_address(),
-        protocol: Protocol::Udp,
-        tls_dns_name: None,
-        trust_nx_responses: false,
-        bind_addr: None,
-    });
+    for protocol in [Protocol::Udp, Protocol::Tcp] {
+        rc.add_name_server(NameServerConfig {
+            socket_addr: *dns_server.local_address(),
+            protocol,
+            tls_dns_name: None,
+            trust_nx_responses: false,
+            bind_addr: None,
+        });
+    }
 
     let resolver =
         TokioAsyncResolver::tokio(rc, ResolverOpts::default()).unwrap();
@@ -34,6 +34,7 @@ async fn test_dnsadm() {
         store,
         &dns_server::dns_server::Config {
             bind_address: "[::1]:0".parse().unwrap(),
+            limits: Default::default(),
         },
         &dropshot::ConfigDropshot {
             bind_address: "[::1]:0".parse().unwrap(),
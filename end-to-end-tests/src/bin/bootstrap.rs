@@ -53,6 +53,7 @@ async fn main() -> Result<()> {
                         block_size: 512.try_into().unwrap(),
                     },
                     size: ByteCount(1024 * 1024 * 1024),
+                    labels: Default::default(),
                 })
                 .send()
                 .await
@@ -6,8 +6,9 @@ use async_trait::async_trait;
 use omicron_test_utils::dev::poll::{wait_for_condition, CondCheckError};
 use oxide_client::types::{
     ByteCount, DiskCreate, DiskSource, Distribution, ExternalIpCreate,
-    GlobalImageCreate, ImageSource, InstanceCpuCount, InstanceCreate,
-    InstanceDiskAttachment, InstanceNetworkInterfaceAttachment, SshKeyCreate,
+    GlobalImageCreate, ImageSource, InstanceAutoRestartPolicy,
+    InstanceCpuCount, InstanceCreate, InstanceDiskAttachment,
+    InstanceNetworkInterfaceAttachment, SshKeyCreate,
 };
 use oxide_client::{
     ClientDisksExt, ClientInstancesExt, ClientSessionExt, ClientSystemExt,
@@ -71,6 +72,7 @@ async fn instance_launch() -> Result<()> {
             description: String::new(),
             disk_source: DiskSource::GlobalImage { image_id },
             size: ByteCount(2048 * 1024 * 1024),
+            labels: Default::default(),
         })
         .send()
         .await?
@@ -94,7 +96,10 @@ async fn instance_launch() -> Result<()> {
             network_interfaces: InstanceNetworkInterfaceAttachment::Default,
             external_ips: vec![ExternalIpCreate::Ephemeral { pool_name: None }],
             user_data: String::new(),
+            affinity_groups: Vec::new(),
+            auto_restart_policy: InstanceAutoRestartPolicy::Never,
             start: true,
+            labels: Default::default(),
         })
         .send()
         .await?;
@@ -5,6 +5,7 @@
 use omicron_common::api::external;
 use omicron_common::api::internal::nexus::HostIdentifier;
 use omicron_common::api::internal::shared::NetworkInterface;
+use omicron_common::api::internal::shared::ResolvedVpcRoute;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
@@ -29,6 +30,24 @@ pub struct VpcFirewallRule {
     pub priority: external::VpcFirewallRulePriority,
 }
 
+/// Update the routes for a VPC
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct VpcRoutesEnsureBody {
+    /// The VNI of the VPC
+    pub vni: external::Vni,
+    /// The route table of each subnet in the VPC
+    pub subnets: Vec<VpcSubnetRoutes>,
+}
+
+/// The effective route table of a VPC Subnet, combining the routes of the
+/// VPC's system router with those of the subnet's custom router, if any
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct VpcSubnetRoutes {
+    pub ipv4_block: external::Ipv4Net,
+    pub ipv6_block: external::Ipv6Net,
+    pub routes: Vec<ResolvedVpcRoute>,
+}
+
 /// A mapping from a virtual NIC to a physical host
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub struct SetVirtualNetworkInterfaceHost {
@@ -6,6 +6,7 @@
 
 use crate::opte::Gateway;
 use crate::opte::Vni;
+use ipnetwork::IpNetwork;
 use macaddr::MacAddr6;
 use std::net::IpAddr;
 use std::sync::Arc;
@@ -16,6 +17,8 @@ struct PortInner {
     name: String,
     // IP address within the VPC Subnet
     _ip: IpAddr,
+    // IP address range of the VPC Subnet
+    subnet: IpNetwork,
     // VPC-private MAC address
     mac: MacAddr6,
     // Emulated PCI slot for the guest NIC, passed to Propolis
@@ -86,6 +89,7 @@ impl Port {
     pub fn new(
         name: String,
         ip: IpAddr,
+        subnet: IpNetwork,
         mac: MacAddr6,
         slot: u8,
         vni: Vni,
@@ -96,6 +100,7 @@ impl Port {
             inner: Arc::new(PortInner {
                 name,
                 _ip: ip,
+                subnet,
                 mac,
                 slot,
                 vni,
@@ -109,6 +114,10 @@ impl Port {
         &self.inner.name
     }
 
+    pub fn subnet(&self) -> &IpNetwork {
+        &self.inner.subnet
+    }
+
     pub fn gateway(&self) -> &Gateway {
         &self.inner.gateway
     }
@@ -8,13 +8,16 @@ use crate::opte::default_boundary_services;
 use crate::opte::opte_firewall_rules;
 use crate::opte::params::SetVirtualNetworkInterfaceHost;
 use crate::opte::params::VpcFirewallRule;
+use crate::opte::params::VpcSubnetRoutes;
 use crate::opte::Error;
 use crate::opte::Gateway;
 use crate::opte::Port;
 use crate::opte::Vni;
 use ipnetwork::IpNetwork;
+use omicron_common::api::external;
 use omicron_common::api::internal::shared::NetworkInterface;
 use omicron_common::api::internal::shared::NetworkInterfaceKind;
+use omicron_common::api::internal::shared::ResolvedVpcRoute;
 use omicron_common::api::internal::shared::SourceNatConfig;
 use oxide_vpc::api::AddRouterEntryReq;
 use oxide_vpc::api::IpCfg;
@@ -31,6 +34,8 @@ use slog::error;
 use slog::info;
 use slog::Logger;
 use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::net::IpAddr;
 use std::net::Ipv6Addr;
 use std::sync::atomic::AtomicU64;
@@ -55,6 +60,14 @@ struct PortManagerInner {
     // Map of all ports, keyed on the interface Uuid and its kind
     // (which includes the Uuid of the parent instance or service)
     ports: Mutex<BTreeMap<(Uuid, NetworkInterfaceKind), Port>>,
+
+    // Map of the most recent route tables provided for each VPC's subnets,
+    // keyed on the VPC's VNI
+    vpc_routes: Mutex<HashMap<external::Vni, Vec<VpcSubnetRoutes>>>,
+
+    // Map of the VPC routes that have been added to each port, keyed on the
+    // port name
+    routes: Mutex<HashMap<String, HashSet<ResolvedVpcRoute>>>,
 }
 
 impl PortManagerInner {
@@ -81,6 +94,8 @@ impl PortManager {
             next_port_id: AtomicU64::new(0),
             underlay_ip,
             ports: Mutex::new(BTreeMap::new()),
+            vpc_routes: Mutex::new(HashMap::new()),
+            routes: Mutex::new(HashMap::new()),
         });
 
         Self { inner }
@@ -309,6 +324,7 @@ impl PortManager {
             let port = Port::new(
                 port_name.clone(),
                 nic.ip,
+                subnet,
                 mac,
                 nic.slot,
                 vni,
@@ -377,6 +393,45 @@ impl PortManager {
             "route" => ?route,
         );
 
+        // Record the routes added above, so that they aren't added again from
+        // the route table of the VPC Subnet.
+        {
+            use omicron_common::api::internal::shared::RouterTarget as Target;
+            let default_dest: IpNetwork = match nic.subnet {
+                external::IpNet::V4(_) => "0.0.0.0/0",
+                external::IpNet::V6(_) => "::/0",
+            }
+            .parse()
+            .unwrap();
+            self.inner.routes.lock().unwrap().insert(
+                port_name.clone(),
+                HashSet::from([
+                    ResolvedVpcRoute {
+                        dest: nic.subnet,
+                        target: Target::VpcSubnet(nic.subnet),
+                    },
+                    ResolvedVpcRoute {
+                        dest: default_dest.into(),
+                        target: Target::InternetGateway,
+                    },
+                ]),
+            );
+        }
+
+        // Add the routes of this interface's VPC Subnet, if Nexus has already
+        // provided them.
+        #[cfg(target_os = "illumos")]
+        if let Some(table) = self
+            .inner
+            .vpc_routes
+            .lock()
+            .unwrap()
+            .get(&nic.vni)
+            .and_then(|subnets| subnet_routes_for_port(&port, subnets))
+        {
+            self.port_routes_ensure(&hdl, &port, table)?;
+        }
+
         info!(
             self.inner.log,
             "Created OPTE port";
@@ -418,6 +473,100 @@ impl PortManager {
         Ok(())
     }
 
+    /// Ensure that each OPTE port in the VPC with the provided VNI has the
+    /// routes in the route table of its VPC Subnet
+    ///
+    /// The tables are also kept, so that ports created later in the VPC get
+    /// the routes of their subnet.
+    #[cfg(target_os = "illumos")]
+    pub fn vpc_routes_ensure(
+        &self,
+        vni: external::Vni,
+        subnets: &[VpcSubnetRoutes],
+    ) -> Result<(), Error> {
+        use opte_ioctl::OpteHdl;
+        let hdl = OpteHdl::open(OpteHdl::XDE_CTL)?;
+        let ports = self.inner.ports.lock().unwrap();
+        self.inner.vpc_routes.lock().unwrap().insert(vni, subnets.to_vec());
+        let opte_vni = Vni::new(vni).unwrap();
+        for port in ports.values().filter(|port| *port.vni() == opte_vni) {
+            if let Some(table) = subnet_routes_for_port(port, subnets) {
+                self.port_routes_ensure(&hdl, port, table)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "illumos"))]
+    pub fn vpc_routes_ensure(
+        &self,
+        vni: external::Vni,
+        subnets: &[VpcSubnetRoutes],
+    ) -> Result<(), Error> {
+        self.inner.vpc_routes.lock().unwrap().insert(vni, subnets.to_vec());
+        info!(self.inner.log, "Ignoring {} VPC route tables", subnets.len());
+        Ok(())
+    }
+
+    // Add the routes in `table` that haven't yet been added to `port`.
+    #[cfg(target_os = "illumos")]
+    fn port_routes_ensure(
+        &self,
+        hdl: &opte_ioctl::OpteHdl,
+        port: &Port,
+        table: &VpcSubnetRoutes,
+    ) -> Result<(), Error> {
+        let mut routes = self.inner.routes.lock().unwrap();
+        let port_routes = routes.entry(port.name().to_string()).or_default();
+        for route in &table.routes {
+            if port_routes.contains(route) {
+                continue;
+            }
+            let Some(target) = opte_router_target(route.target) else {
+                slog::warn!(
+                    self.inner.log,
+                    "Skipping VPC router entry with unsupported target";
+                    "port_name" => port.name(),
+                    "route" => ?route,
+                );
+                continue;
+            };
+            let entry = AddRouterEntryReq {
+                port_name: port.name().to_string(),
+                dest: IpCidr::from(IpNetwork::from(route.dest)),
+                target,
+            };
+            debug!(
+                self.inner.log,
+                "Adding VPC router entry";
+                "port_name" => port.name(),
+                "entry" => ?&entry,
+            );
+            hdl.add_router_entry(&entry)?;
+            port_routes.insert(*route);
+        }
+
+        // TODO-completeness: OPTE can't yet remove router entries, so routes
+        // that have since been removed from the table are left in place until
+        // the port is destroyed. Nexus only removes or changes routes while
+        // no instance in the VPC is running, so this is only expected after
+        // changes that Nexus doesn't check, such as deleting a subnet or a
+        // VPC peering.
+        let stale = port_routes
+            .iter()
+            .filter(|route| !table.routes.contains(route))
+            .count();
+        if stale > 0 {
+            slog::warn!(
+                self.inner.log,
+                "Unable to remove stale VPC router entries";
+                "port_name" => port.name(),
+                "count" => stale,
+            );
+        }
+        Ok(())
+    }
+
     #[cfg(target_os = "illumos")]
     pub fn set_virtual_nic_host(
         &self,
@@ -469,6 +618,40 @@ impl PortManager {
     }
 }
 
+// Find the route table of the VPC Subnet the port is in.
+#[cfg(target_os = "illumos")]
+fn subnet_routes_for_port<'a>(
+    port: &Port,
+    subnets: &'a [VpcSubnetRoutes],
+) -> Option<&'a VpcSubnetRoutes> {
+    subnets.iter().find(|table| {
+        *port.subnet() == IpNetwork::V4(table.ipv4_block.0)
+            || *port.subnet() == IpNetwork::V6(table.ipv6_block.0)
+    })
+}
+
+// Convert a route target to OPTE's, if OPTE supports it.
+#[cfg(target_os = "illumos")]
+fn opte_router_target(
+    target: omicron_common::api::internal::shared::RouterTarget,
+) -> Option<RouterTarget> {
+    use omicron_common::api::internal::shared::RouterTarget as Target;
+    match target {
+        Target::Drop => Some(RouterTarget::Drop),
+        Target::InternetGateway => Some(RouterTarget::InternetGateway),
+        Target::Ip(ip) => Some(RouterTarget::Ip(ip.into())),
+        Target::VpcSubnet(subnet) => {
+            Some(RouterTarget::VpcSubnet(IpCidr::from(IpNetwork::from(subnet))))
+        }
+        // TODO-completeness: OPTE always encapsulates traffic with the
+        // sending port's own VNI, and looks up the destination's physical
+        // host among the V2P mappings for that VNI, so it can't yet deliver
+        // traffic to interfaces in a peered VPC.  Those routes are skipped
+        // until it has a router target that names the VNI to use.
+        Target::VpcPeerSubnet { .. } => None,
+    }
+}
+
 pub struct PortTicket {
     id: Uuid,
     kind: NetworkInterfaceKind,
@@ -505,6 +688,7 @@ impl PortTicket {
             );
             return Err(Error::ReleaseMissingPort(self.id, self.kind));
         };
+        self.manager.routes.lock().unwrap().remove(port.name());
         debug!(
             self.manager.log,
             "Removed OPTE port from manager";
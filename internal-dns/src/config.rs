@@ -62,7 +62,9 @@
 
 use crate::names::{ServiceName, DNS_ZONE};
 use anyhow::{anyhow, ensure};
-use dns_service_client::types::{DnsConfigParams, DnsConfigZone, DnsRecord};
+use dns_service_client::types::{
+    DnsConfigParams, DnsConfigZone, DnsRecord, Mx,
+};
 use std::collections::BTreeMap;
 use std::net::Ipv6Addr;
 use uuid::Uuid;
@@ -129,6 +131,10 @@ impl Host {
 ///
 /// This builder ensures that the constructed DNS data satisfies these
 /// assumptions.
+///
+/// A few other names that aren't hosts or services can be added, too: aliases
+/// (CNAME records), TXT and MX records, and subtrees of the zone delegated to
+/// other DNS servers (NS records).
 pub struct DnsConfigBuilder {
     /// set of hosts of type "sled" that have been configured so far, mapping
     /// each sled's unique uuid to its sole IPv6 address on the control plane
@@ -149,6 +155,10 @@ pub struct DnsConfigBuilder {
 
     /// similar to service_instances_zones, but for services that run on sleds
     service_instances_sleds: BTreeMap<ServiceName, BTreeMap<Sled, u16>>,
+
+    /// records for names that are neither hosts nor services, mapping each
+    /// name (without the zone part) to its records
+    other_records: BTreeMap<String, Vec<DnsRecord>>,
 }
 
 /// Describes a host of type "sled" in the control plane DNS zone
@@ -170,6 +180,7 @@ impl DnsConfigBuilder {
             zones: BTreeMap::new(),
             service_instances_zones: BTreeMap::new(),
             service_instances_sleds: BTreeMap::new(),
+            other_records: BTreeMap::new(),
         }
     }
 
@@ -329,6 +340,131 @@ impl DnsConfigBuilder {
         }
     }
 
+    /// Specify that `name` is an alias for `target` (a fully-qualified name)
+    ///
+    /// Like service names, `name` should not include the zone part.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if `name` already has records, since an alias
+    /// cannot have any other records.
+    pub fn alias(&mut self, name: &str, target: &str) -> anyhow::Result<()> {
+        self.add_other_record(name, DnsRecord::Cname(target.to_owned()))
+    }
+
+    /// Add a TXT record made up of the given strings for `name`
+    ///
+    /// # Errors
+    ///
+    /// This function fails if `name` is an alias or if any of the strings is
+    /// longer than 255 bytes.
+    pub fn txt(
+        &mut self,
+        name: &str,
+        strings: Vec<String>,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            strings.iter().all(|s| s.len() <= 255),
+            "TXT record for {:?}: strings cannot be longer than 255 bytes",
+            name
+        );
+        self.add_other_record(name, DnsRecord::Txt(strings))
+    }
+
+    /// Add an MX record for `name` pointing at the mail exchange `exchange` (a
+    /// fully-qualified name)
+    ///
+    /// # Errors
+    ///
+    /// This function fails if `name` is an alias.
+    pub fn mx(
+        &mut self,
+        name: &str,
+        preference: u16,
+        exchange: &str,
+    ) -> anyhow::Result<()> {
+        self.add_other_record(
+            name,
+            DnsRecord::Mx(Mx { preference, exchange: exchange.to_owned() }),
+        )
+    }
+
+    /// Specify that `name` and everything below it is served by the given DNS
+    /// servers (fully-qualified names) rather than ours
+    ///
+    /// # Errors
+    ///
+    /// This function fails if `name` is an alias or has already been
+    /// delegated.
+    pub fn delegate(
+        &mut self,
+        name: &str,
+        nameservers: &[&str],
+    ) -> anyhow::Result<()> {
+        ensure!(
+            !nameservers.is_empty(),
+            "delegation of {:?}: no nameservers specified",
+            name
+        );
+        ensure!(
+            !self.other_records.get(name).map_or(false, |records| records
+                .iter()
+                .any(|r| matches!(r, DnsRecord::Ns(_)))),
+            "{:?} has already been delegated",
+            name
+        );
+        for nameserver in nameservers {
+            self.add_other_record(
+                name,
+                DnsRecord::Ns((*nameserver).to_owned()),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn add_other_record(
+        &mut self,
+        name: &str,
+        record: DnsRecord,
+    ) -> anyhow::Result<()> {
+        // Host and service names are generated from uuids and well-known
+        // service names, so they're unlikely to collide with these.  But
+        // check anyway, since a collision would silently lose records.
+        ensure!(
+            !self.host_and_service_names().any(|n| n == name),
+            "{:?} is already the name of a host or service",
+            name
+        );
+
+        let records =
+            self.other_records.entry(name.to_owned()).or_insert_with(Vec::new);
+        let is_cname = matches!(record, DnsRecord::Cname(_));
+        ensure!(
+            records.is_empty()
+                || !(is_cname
+                    || records
+                        .iter()
+                        .any(|r| matches!(r, DnsRecord::Cname(_)))),
+            "{:?} cannot be an alias and have other records",
+            name
+        );
+        records.push(record);
+        Ok(())
+    }
+
+    fn host_and_service_names(&self) -> impl Iterator<Item = String> + '_ {
+        let sleds = self.sleds.keys().map(|sled| Host::Sled(sled.0).dns_name());
+        let zones = self.zones.keys().map(|zone| {
+            Host::Zone { id: zone.id, variant: zone.variant.clone() }.dns_name()
+        });
+        let services = self
+            .service_instances_zones
+            .keys()
+            .chain(self.service_instances_sleds.keys())
+            .map(|service| service.dns_name());
+        sleds.chain(zones).chain(services)
+    }
+
     /// Construct a complete [`DnsConfigParams`] (suitable for propagating to
     /// our DNS servers) for the control plane DNS zone described up to this
     /// point
@@ -402,6 +538,7 @@ impl DnsConfigBuilder {
             .chain(zone_records)
             .chain(srv_records_sleds)
             .chain(srv_records_zones)
+            .chain(self.other_records)
             .collect();
 
         DnsConfigParams {
@@ -419,6 +556,7 @@ impl DnsConfigBuilder {
 mod test {
     use super::{DnsConfigBuilder, Host, ServiceName, ZoneVariant};
     use crate::DNS_ZONE;
+    use dns_service_client::types::{DnsRecord, Mx};
     use std::{collections::BTreeMap, io::Write, net::Ipv6Addr};
     use uuid::Uuid;
 
@@ -648,4 +786,79 @@ mod test {
             (previously port 123, now 456)"
         );
     }
+
+    #[test]
+    fn test_builder_other_records() {
+        let zone1_uuid: Uuid = ZONE1_UUID.parse().unwrap();
+
+        let mut builder = DnsConfigBuilder::new();
+        let zone1 = builder.host_zone(zone1_uuid, ZONE1_IP).unwrap();
+        builder.service_backend_zone(ServiceName::Nexus, &zone1, 123).unwrap();
+        builder
+            .alias("nexus", "_nexus._tcp.control-plane.oxide.internal")
+            .unwrap();
+        builder
+            .txt("_acme-challenge", vec![String::from("some-token")])
+            .unwrap();
+        builder.mx("mail", 10, "mx.example.com").unwrap();
+        builder
+            .delegate("sub", &["ns1.example.com", "ns2.example.com"])
+            .unwrap();
+
+        // None of these can be combined with an alias.
+        let error = builder.alias("sub", "elsewhere.example.com").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "\"sub\" cannot be an alias and have other records"
+        );
+        let error = builder.mx("nexus", 10, "mx.example.com").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "\"nexus\" cannot be an alias and have other records"
+        );
+
+        // Other errors
+        let error = builder.delegate("sub", &["ns3.example.com"]).unwrap_err();
+        assert_eq!(error.to_string(), "\"sub\" has already been delegated");
+        let error = builder.txt("long", vec!["a".repeat(256)]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "TXT record for \"long\": strings cannot be longer than 255 bytes"
+        );
+        let error =
+            builder.alias("_nexus._tcp", "elsewhere.example.com").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "\"_nexus._tcp\" is already the name of a host or service"
+        );
+
+        let config = builder.build();
+        assert_eq!(config.zones.len(), 1);
+        let records = &config.zones[0].records;
+        assert_eq!(records.len(), 6);
+        assert_eq!(
+            records["nexus"],
+            vec![DnsRecord::Cname(String::from(
+                "_nexus._tcp.control-plane.oxide.internal"
+            ))]
+        );
+        assert_eq!(
+            records["_acme-challenge"],
+            vec![DnsRecord::Txt(vec![String::from("some-token")])]
+        );
+        assert_eq!(
+            records["mail"],
+            vec![DnsRecord::Mx(Mx {
+                preference: 10,
+                exchange: String::from("mx.example.com")
+            })]
+        );
+        assert_eq!(
+            records["sub"],
+            vec![
+                DnsRecord::Ns(String::from("ns1.example.com")),
+                DnsRecord::Ns(String::from("ns2.example.com")),
+            ]
+        );
+    }
 }
@@ -228,6 +228,7 @@ mod test {
                 store,
                 &dns_server::dns_server::Config {
                     bind_address: "[::1]:0".parse().unwrap(),
+                    limits: Default::default(),
                 },
                 &dropshot::ConfigDropshot {
                     bind_address: "[::1]:0".parse().unwrap(),
@@ -0,0 +1,66 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::device_auth::generate_token;
+use crate::schema::access_token;
+use chrono::{DateTime, Utc};
+use db_macros::Resource;
+use nexus_types::external_api::params;
+use nexus_types::external_api::views;
+use nexus_types::identity::Resource;
+use uuid::Uuid;
+
+/// Describes a named, long-lived access token belonging to a user.
+///
+/// These tokens are accepted by the same authentication scheme as tokens
+/// granted by the device authorization flow, but are created directly by the
+/// user rather than through an interactive approval.
+// TODO-security: wrap token in an opaque struct to avoid accidental leaks.
+#[derive(Clone, Debug, Insertable, Queryable, Resource, Selectable)]
+#[diesel(table_name = access_token)]
+pub struct AccessToken {
+    #[diesel(embed)]
+    identity: AccessTokenIdentity,
+
+    pub silo_user_id: Uuid,
+    pub token: String,
+    pub time_expires: Option<DateTime<Utc>>,
+}
+
+impl AccessToken {
+    pub fn new(silo_user_id: Uuid, params: params::AccessTokenCreate) -> Self {
+        Self {
+            identity: AccessTokenIdentity::new(Uuid::new_v4(), params.identity),
+            silo_user_id,
+            token: generate_token(),
+            time_expires: params.time_expires,
+        }
+    }
+
+    /// Returns whether the token has expired as of `now`
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.time_expires.map_or(false, |time_expires| time_expires <= now)
+    }
+}
+
+impl From<AccessToken> for views::AccessToken {
+    fn from(access_token: AccessToken) -> Self {
+        Self {
+            identity: access_token.identity(),
+            silo_user_id: access_token.silo_user_id,
+            time_expires: access_token.time_expires,
+        }
+    }
+}
+
+impl From<AccessToken> for views::AccessTokenCreated {
+    fn from(access_token: AccessToken) -> Self {
+        Self {
+            identity: access_token.identity(),
+            silo_user_id: access_token.silo_user_id,
+            time_expires: access_token.time_expires,
+            access_token: format!("oxide-token-{}", access_token.token),
+        }
+    }
+}
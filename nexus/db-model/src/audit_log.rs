@@ -0,0 +1,275 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Database representation of the audit log
+
+use crate::schema::{audit_log, audit_log_chain};
+use crate::SqlU16;
+use chrono::{DateTime, SubsecRound, Utc};
+use nexus_types::external_api::views;
+use openssl::sha::Sha256;
+use uuid::Uuid;
+
+/// The `prev_hash` of the first entry in each chain of the audit log
+pub const AUDIT_LOG_GENESIS_HASH: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Describes an API request to be recorded in the audit log
+///
+/// This becomes a pending [`AuditLogEntry`] before the request is handled.
+#[derive(Clone, Debug)]
+pub struct AuditLogEntryInit {
+    pub request_id: String,
+    pub time_started: DateTime<Utc>,
+    pub http_method: String,
+    pub request_uri: String,
+    pub actor_id: Option<Uuid>,
+    pub actor_silo_id: Option<Uuid>,
+    pub resource_ids: Vec<Uuid>,
+}
+
+/// An entry in the audit log
+///
+/// An entry is recorded as pending before its request is handled, and is
+/// completed with the request's result afterwards.  Completing an entry
+/// appends it to the chain identified by `chain_id` (one per Silo, plus one
+/// for requests not made by a Silo user): `hash` is the SHA-256 digest of the
+/// entry's contents (see [`AuditLogEntry::compute_hash()`]), which include the
+/// `hash` of the previous entry in the chain as `prev_hash`.
+///
+/// `seq` orders entries across all chains by when they were recorded.
+#[derive(Queryable, Insertable, Selectable, Clone, Debug)]
+#[diesel(table_name = audit_log)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub seq: i64,
+    pub chain_id: Uuid,
+
+    pub time_started: DateTime<Utc>,
+    pub time_completed: Option<DateTime<Utc>>,
+    pub request_id: String,
+    pub http_method: String,
+    pub request_uri: String,
+
+    pub actor_id: Option<Uuid>,
+    pub actor_silo_id: Option<Uuid>,
+
+    pub resource_ids: Vec<Uuid>,
+    pub result_code: Option<SqlU16>,
+
+    pub chain_seq: Option<i64>,
+    pub prev_hash: Option<String>,
+    pub hash: Option<String>,
+}
+
+impl AuditLogEntry {
+    /// Builds the pending entry at position `seq` in the log, to be appended
+    /// to chain `chain_id` once it's completed
+    pub fn new(init: AuditLogEntryInit, seq: i64, chain_id: Uuid) -> Self {
+        // The database only stores timestamps to microsecond precision.
+        // Truncate them here so that the hash we compute later matches the
+        // one computed from the entry once it's read back.
+        Self {
+            id: Uuid::new_v4(),
+            seq,
+            chain_id,
+            time_started: init.time_started.trunc_subsecs(6),
+            time_completed: None,
+            request_id: init.request_id,
+            http_method: init.http_method,
+            request_uri: init.request_uri,
+            actor_id: init.actor_id,
+            actor_silo_id: init.actor_silo_id,
+            resource_ids: init.resource_ids,
+            result_code: None,
+            chain_seq: None,
+            prev_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Completes this entry with the result of its request, placing it after
+    /// `head` in its chain
+    pub fn complete(
+        &mut self,
+        time_completed: DateTime<Utc>,
+        result_code: u16,
+        head: &AuditLogChain,
+    ) {
+        self.time_completed = Some(time_completed.trunc_subsecs(6));
+        self.result_code = Some(SqlU16::new(result_code));
+        self.chain_seq = Some(head.seq + 1);
+        self.prev_hash = Some(head.hash.clone());
+        self.hash = Some(self.compute_hash());
+    }
+
+    /// Computes the hash of this entry from its contents
+    ///
+    /// This covers every field other than `seq` and `hash` itself.  Each
+    /// field is fed to the hash in a fixed order and in an unambiguous
+    /// encoding: variable-length fields are prefixed with their length,
+    /// optional ones with a byte indicating whether they're present, and
+    /// timestamps are encoded as microseconds since the Unix epoch.  All
+    /// integers are big-endian.  Only completed entries are hashed, so the
+    /// fields filled in by [`AuditLogEntry::complete()`] are always present.
+    pub fn compute_hash(&self) -> String {
+        fn update_bytes(hasher: &mut Sha256, bytes: &[u8]) {
+            hasher.update(&(bytes.len() as u64).to_be_bytes());
+            hasher.update(bytes);
+        }
+
+        fn update_uuid(hasher: &mut Sha256, id: Option<Uuid>) {
+            match id {
+                None => hasher.update(&[0]),
+                Some(id) => {
+                    hasher.update(&[1]);
+                    hasher.update(id.as_bytes());
+                }
+            }
+        }
+
+        let prev_hash = self.prev_hash.as_deref().unwrap_or_default();
+        let chain_seq = self.chain_seq.unwrap_or_default();
+        let time_completed =
+            self.time_completed.map_or(0, |t| t.timestamp_micros());
+        let result_code = self.result_code.map_or(0, |c| c.0);
+
+        let mut hasher = Sha256::new();
+        update_bytes(&mut hasher, prev_hash.as_bytes());
+        hasher.update(self.chain_id.as_bytes());
+        hasher.update(&chain_seq.to_be_bytes());
+        hasher.update(self.id.as_bytes());
+        hasher.update(&self.time_started.timestamp_micros().to_be_bytes());
+        hasher.update(&time_completed.to_be_bytes());
+        update_bytes(&mut hasher, self.request_id.as_bytes());
+        update_bytes(&mut hasher, self.http_method.as_bytes());
+        update_bytes(&mut hasher, self.request_uri.as_bytes());
+        update_uuid(&mut hasher, self.actor_id);
+        update_uuid(&mut hasher, self.actor_silo_id);
+        hasher.update(&(self.resource_ids.len() as u64).to_be_bytes());
+        for id in &self.resource_ids {
+            hasher.update(id.as_bytes());
+        }
+        hasher.update(&result_code.to_be_bytes());
+        hex::encode(hasher.finish())
+    }
+
+    /// Returns whether this entry has been completed and its `hash` matches
+    /// its contents
+    pub fn hash_is_valid(&self) -> bool {
+        self.hash.as_deref() == Some(self.compute_hash().as_str())
+    }
+}
+
+/// The latest entry in one chain of the audit log
+///
+/// Appending to a chain locks its row here, so appends to the same chain are
+/// serialized while those to different chains proceed independently.  The
+/// row outlives the entries it refers to, so pruning the log doesn't break
+/// the chain.
+#[derive(Queryable, Insertable, Selectable, Clone, Debug)]
+#[diesel(table_name = audit_log_chain)]
+pub struct AuditLogChain {
+    pub id: Uuid,
+    pub seq: i64,
+    pub hash: String,
+}
+
+impl AuditLogChain {
+    /// Returns the head of chain `id` before anything has been appended to it
+    pub fn new(id: Uuid) -> Self {
+        Self { id, seq: 0, hash: AUDIT_LOG_GENESIS_HASH.to_string() }
+    }
+}
+
+impl From<AuditLogEntry> for views::AuditLogEntry {
+    fn from(entry: AuditLogEntry) -> Self {
+        Self {
+            id: entry.id,
+            seq: u64::try_from(entry.seq).unwrap(),
+            chain_id: entry.chain_id,
+            chain_seq: entry.chain_seq.map(|seq| u64::try_from(seq).unwrap()),
+            time_started: entry.time_started,
+            time_completed: entry.time_completed,
+            request_id: entry.request_id,
+            http_method: entry.http_method,
+            request_uri: entry.request_uri,
+            actor_id: entry.actor_id,
+            actor_silo_id: entry.actor_silo_id,
+            resource_ids: entry.resource_ids,
+            result_code: entry.result_code.map(|c| *c),
+            prev_hash: entry.prev_hash,
+            hash: entry.hash,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_init() -> AuditLogEntryInit {
+        AuditLogEntryInit {
+            request_id: String::from("test-request"),
+            time_started: Utc::now(),
+            http_method: String::from("POST"),
+            request_uri: String::from("/v1/projects"),
+            actor_id: Some(Uuid::new_v4()),
+            actor_silo_id: None,
+            resource_ids: vec![Uuid::new_v4()],
+        }
+    }
+
+    fn test_entry(head: &AuditLogChain) -> AuditLogEntry {
+        let mut entry = AuditLogEntry::new(test_init(), 1, head.id);
+        entry.complete(Utc::now(), 201, head);
+        entry
+    }
+
+    #[test]
+    fn test_audit_log_hash_covers_contents() {
+        let chain_id = Uuid::new_v4();
+        let pending = AuditLogEntry::new(test_init(), 1, chain_id);
+        assert!(!pending.hash_is_valid());
+
+        let head = AuditLogChain::new(chain_id);
+        let first = test_entry(&head);
+        assert!(first.hash_is_valid());
+        assert_eq!(first.chain_seq, Some(1));
+        assert_eq!(first.prev_hash.as_deref(), Some(AUDIT_LOG_GENESIS_HASH));
+        let first_hash = first.hash.clone().unwrap();
+        assert_eq!(first_hash.len(), AUDIT_LOG_GENESIS_HASH.len());
+
+        let head = AuditLogChain { id: chain_id, seq: 1, hash: first_hash };
+        let second = test_entry(&head);
+        assert!(second.hash_is_valid());
+        assert_eq!(second.chain_seq, Some(2));
+        assert_ne!(first.hash, second.hash);
+
+        // Any change to the entry's contents should invalidate its hash.
+        let mut tampered = second.clone();
+        tampered.result_code = Some(SqlU16::new(500));
+        assert!(!tampered.hash_is_valid());
+
+        let mut tampered = second.clone();
+        tampered.actor_silo_id = tampered.actor_id;
+        assert!(!tampered.hash_is_valid());
+
+        let mut tampered = second.clone();
+        tampered.prev_hash = Some(AUDIT_LOG_GENESIS_HASH.into());
+        assert!(!tampered.hash_is_valid());
+
+        let mut tampered = second.clone();
+        tampered.chain_id = Uuid::new_v4();
+        assert!(!tampered.hash_is_valid());
+
+        // Moving bytes between adjacent fields shouldn't produce the same
+        // hash either.
+        let mut tampered = second;
+        tampered.request_uri =
+            format!("{}{}", tampered.http_method, tampered.request_uri);
+        tampered.http_method = String::new();
+        assert!(!tampered.hash_is_valid());
+    }
+}
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use super::{BlockSize, ByteCount, DiskState, Generation};
+use super::{BlockSize, ByteCount, DiskState, Generation, Labels};
 use crate::schema::disk;
 use chrono::{DateTime, Utc};
 use db_macros::Resource;
@@ -31,6 +31,9 @@ pub struct Disk {
     #[diesel(embed)]
     identity: DiskIdentity,
 
+    /// user-provided key/value labels
+    pub labels: Labels,
+
     /// child resource generation number, per RFD 192
     rcgen: Generation,
 
@@ -77,6 +80,7 @@ impl Disk {
         runtime_initial: DiskRuntimeState,
     ) -> Result<Self, anyhow::Error> {
         let identity = DiskIdentity::new(disk_id, params.identity);
+        let labels = params.labels.into();
 
         let create_snapshot_id = match params.disk_source {
             params::DiskSource::Snapshot { snapshot_id } => Some(snapshot_id),
@@ -92,6 +96,7 @@ impl Disk {
 
         Ok(Self {
             identity,
+            labels,
             rcgen: external::Generation::new().into(),
             project_id,
             volume_id,
@@ -127,6 +132,7 @@ impl Into<external::Disk> for Disk {
         let device_path = format!("/mnt/{}", self.name().as_str());
         external::Disk {
             identity: self.identity(),
+            labels: self.labels.0.clone(),
             project_id: self.project_id,
             snapshot_id: self.create_snapshot_id,
             image_id: self.create_image_id,
@@ -243,6 +249,15 @@ impl DiskRuntimeState {
         }
     }
 
+    pub fn exporting(self) -> Self {
+        Self {
+            disk_state: external::DiskState::Exporting.label().to_string(),
+            attach_instance_id: None,
+            gen: self.gen.next().into(),
+            time_updated: Utc::now(),
+        }
+    }
+
     pub fn state(&self) -> DiskState {
         // TODO: If we could store disk state in-line, we could avoid the
         // unwrap. Would prefer to parse it as such.
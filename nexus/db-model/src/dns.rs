@@ -125,13 +125,20 @@ impl DnsName {
 /// into the database.  We don't want the serialized form to change accidentally
 /// because someone happens to change the DNS server API.
 ///
-/// BE CAREFUL MODIFYING THIS STRUCT.
+/// BE CAREFUL MODIFYING THIS STRUCT.  New variants may be added, but existing
+/// ones must keep their names and contents so that records stored by older
+/// versions can still be read.
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum DnsRecord {
     A(Ipv4Addr),
     AAAA(Ipv6Addr),
     SRV(SRV),
+    CNAME(String),
+    TXT(Vec<String>),
+    PTR(String),
+    MX(MX),
+    NS(String),
 }
 
 impl From<params::DnsRecord> for DnsRecord {
@@ -140,6 +147,11 @@ impl From<params::DnsRecord> for DnsRecord {
             params::DnsRecord::A(addr) => DnsRecord::A(addr),
             params::DnsRecord::Aaaa(addr) => DnsRecord::AAAA(addr),
             params::DnsRecord::Srv(srv) => DnsRecord::SRV(SRV::from(srv)),
+            params::DnsRecord::Cname(target) => DnsRecord::CNAME(target),
+            params::DnsRecord::Txt(strings) => DnsRecord::TXT(strings),
+            params::DnsRecord::Ptr(target) => DnsRecord::PTR(target),
+            params::DnsRecord::Mx(mx) => DnsRecord::MX(MX::from(mx)),
+            params::DnsRecord::Ns(target) => DnsRecord::NS(target),
         }
     }
 }
@@ -152,6 +164,11 @@ impl From<DnsRecord> for params::DnsRecord {
             DnsRecord::SRV(srv) => {
                 params::DnsRecord::Srv(params::Srv::from(srv))
             }
+            DnsRecord::CNAME(target) => params::DnsRecord::Cname(target),
+            DnsRecord::TXT(strings) => params::DnsRecord::Txt(strings),
+            DnsRecord::PTR(target) => params::DnsRecord::Ptr(target),
+            DnsRecord::MX(mx) => params::DnsRecord::Mx(params::Mx::from(mx)),
+            DnsRecord::NS(target) => params::DnsRecord::Ns(target),
         }
     }
 }
@@ -193,6 +210,31 @@ impl From<SRV> for params::Srv {
     }
 }
 
+/// This type is identical to `dns_service_client::Mx`.  It's defined
+/// separately here for stability: this type is serialized to JSON and stored
+/// into the database.  We don't want the serialized form to change accidentally
+/// because someone happens to change the DNS server API.
+///
+/// BE CAREFUL MODIFYING THIS STRUCT.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename = "Mx")]
+pub struct MX {
+    pub preference: u16,
+    pub exchange: String,
+}
+
+impl From<params::Mx> for MX {
+    fn from(mx: params::Mx) -> Self {
+        MX { preference: mx.preference, exchange: mx.exchange }
+    }
+}
+
+impl From<MX> for params::Mx {
+    fn from(mx: MX) -> Self {
+        params::Mx { preference: mx.preference, exchange: mx.exchange }
+    }
+}
+
 /// Describes the initial configuration for a DNS group
 ///
 /// Provides helpers for constructing the database rows to describe that initial
@@ -265,3 +307,80 @@ impl InitialDnsGroup {
             .collect::<Result<Vec<_>, _>>()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{DnsName, Generation};
+    use nexus_types::internal_api::params;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_dns_record_serialization() {
+        // Records stored by older versions must still be readable.
+        let stored = serde_json::json!([
+            { "A": "127.0.0.1" },
+            { "AAAA": "::1" },
+            {
+                "SRV": {
+                    "prio": 1,
+                    "weight": 2,
+                    "port": 3,
+                    "target": "host.example"
+                }
+            }
+        ]);
+        let name = DnsName {
+            dns_zone_id: Uuid::new_v4(),
+            version_added: Generation::new(),
+            version_removed: None,
+            name: String::from("name"),
+            dns_record_data: stored.clone(),
+        };
+        let records = name.records().unwrap();
+        assert!(matches!(records[0], params::DnsRecord::A(_)));
+        assert!(matches!(records[1], params::DnsRecord::Aaaa(_)));
+        assert!(matches!(records[2], params::DnsRecord::Srv(_)));
+
+        // ... and they must be stored the same way they always have been.
+        let restored = DnsName::new(
+            name.dns_zone_id,
+            name.name.clone(),
+            Generation::new(),
+            None,
+            records,
+        )
+        .unwrap();
+        assert_eq!(restored.dns_record_data, stored);
+
+        // The newer record types round-trip, too.
+        let records = vec![
+            params::DnsRecord::Cname(String::from("target.example")),
+            params::DnsRecord::Txt(vec![String::from("v=spf1 -all")]),
+            params::DnsRecord::Ptr(String::from("host.example")),
+            params::DnsRecord::Mx(params::Mx {
+                preference: 10,
+                exchange: String::from("mail.example"),
+            }),
+            params::DnsRecord::Ns(String::from("ns1.example")),
+        ];
+        let name = DnsName::new(
+            Uuid::new_v4(),
+            String::from("name"),
+            Generation::new(),
+            None,
+            records.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            name.dns_record_data,
+            serde_json::json!([
+                { "CNAME": "target.example" },
+                { "TXT": ["v=spf1 -all"] },
+                { "PTR": "host.example" },
+                { "MX": { "preference": 10, "exchange": "mail.example" } },
+                { "NS": "ns1.example" }
+            ])
+        );
+        assert_eq!(name.records().unwrap(), records);
+    }
+}
@@ -7,16 +7,21 @@
 
 use crate::impl_enum_type;
 use crate::schema::external_ip;
+use crate::schema::floating_ip;
 use crate::Name;
 use crate::SqlU16;
 use chrono::DateTime;
 use chrono::Utc;
+use db_macros::Resource;
 use diesel::Queryable;
 use diesel::Selectable;
 use ipnetwork::IpNetwork;
 use nexus_types::external_api::shared;
 use nexus_types::external_api::views;
+use nexus_types::identity::Resource;
 use omicron_common::api::external::Error;
+use serde::Deserialize;
+use serde::Serialize;
 use std::convert::TryFrom;
 use std::net::IpAddr;
 use uuid::Uuid;
@@ -68,6 +73,61 @@ pub struct ExternalIp {
     pub ip: IpNetwork,
     pub first_port: SqlU16,
     pub last_port: SqlU16,
+    // Only Some(_) for Floating IPs
+    pub project_id: Option<Uuid>,
+}
+
+/// Floating IP DB model.
+///
+/// The underlying "table" (`floating_ip`) is actually a view over the
+/// `external_ip` table, that contains only rows with `kind = 'floating'`.
+#[derive(
+    Selectable, Queryable, Clone, Debug, Resource, Serialize, Deserialize,
+)]
+#[diesel(table_name = floating_ip)]
+pub struct FloatingIp {
+    #[diesel(embed)]
+    pub identity: FloatingIpIdentity,
+
+    pub ip_pool_id: Uuid,
+    pub ip_pool_range_id: Uuid,
+    pub instance_id: Option<Uuid>,
+    pub ip: IpNetwork,
+    pub project_id: Uuid,
+}
+
+impl TryFrom<ExternalIp> for FloatingIp {
+    type Error = Error;
+
+    fn try_from(ip: ExternalIp) -> Result<Self, Self::Error> {
+        let (Some(name), Some(description), Some(project_id)) =
+            (ip.name, ip.description, ip.project_id)
+        else {
+            return Err(Error::internal_error(
+                "floating IP must have a name, description, and project",
+            ));
+        };
+        if ip.kind != IpKind::Floating {
+            return Err(Error::internal_error(
+                "external IP is not a floating IP",
+            ));
+        }
+        Ok(FloatingIp {
+            identity: FloatingIpIdentity {
+                id: ip.id,
+                name,
+                description,
+                time_created: ip.time_created,
+                time_modified: ip.time_modified,
+                time_deleted: ip.time_deleted,
+            },
+            ip_pool_id: ip.ip_pool_id,
+            ip_pool_range_id: ip.ip_pool_range_id,
+            instance_id: ip.instance_id,
+            ip: ip.ip,
+            project_id,
+        })
+    }
 }
 
 impl From<ExternalIp> for sled_agent_client::types::SourceNatConfig {
@@ -91,6 +151,7 @@ pub struct IncompleteExternalIp {
     kind: IpKind,
     instance_id: Option<Uuid>,
     pool_id: Uuid,
+    project_id: Option<Uuid>,
     // Optional address requesting that a specific IP address be allocated.
     explicit_ip: Option<IpNetwork>,
 }
@@ -109,6 +170,7 @@ impl IncompleteExternalIp {
             kind: IpKind::SNat,
             instance_id: Some(instance_id),
             pool_id,
+            project_id: None,
             explicit_ip: None,
         }
     }
@@ -122,6 +184,7 @@ impl IncompleteExternalIp {
             kind: IpKind::Ephemeral,
             instance_id: Some(instance_id),
             pool_id,
+            project_id: None,
             explicit_ip: None,
         }
     }
@@ -130,6 +193,7 @@ impl IncompleteExternalIp {
         id: Uuid,
         name: &Name,
         description: &str,
+        project_id: Uuid,
         pool_id: Uuid,
     ) -> Self {
         Self {
@@ -140,6 +204,7 @@ impl IncompleteExternalIp {
             kind: IpKind::Floating,
             instance_id: None,
             pool_id,
+            project_id: Some(project_id),
             explicit_ip: None,
         }
     }
@@ -157,6 +222,7 @@ impl IncompleteExternalIp {
             kind: IpKind::Service,
             instance_id: None,
             pool_id,
+            project_id: None,
             explicit_ip: Some(IpNetwork::from(address)),
         }
     }
@@ -170,6 +236,7 @@ impl IncompleteExternalIp {
             kind: IpKind::Service,
             instance_id: None,
             pool_id,
+            project_id: None,
             explicit_ip: None,
         }
     }
@@ -202,6 +269,10 @@ impl IncompleteExternalIp {
         &self.pool_id
     }
 
+    pub fn project_id(&self) -> &Option<Uuid> {
+        &self.project_id
+    }
+
     pub fn explicit_ip(&self) -> &Option<IpNetwork> {
         &self.explicit_ip
     }
@@ -229,3 +300,14 @@ impl TryFrom<ExternalIp> for views::ExternalIp {
         Ok(views::ExternalIp { kind, ip: ip.ip.ip() })
     }
 }
+
+impl From<FloatingIp> for views::FloatingIp {
+    fn from(ip: FloatingIp) -> Self {
+        views::FloatingIp {
+            identity: ip.identity(),
+            ip: ip.ip.ip(),
+            project_id: ip.project_id,
+            instance_id: ip.instance_id,
+        }
+    }
+}
@@ -3,7 +3,9 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::impl_enum_type;
-use crate::schema::{identity_provider, saml_identity_provider};
+use crate::schema::{
+    identity_provider, oidc_identity_provider, saml_identity_provider,
+};
 use db_macros::Resource;
 use nexus_types::identity::Resource;
 
@@ -22,12 +24,14 @@ impl_enum_type!(
 
     // Enum values
     Saml => b"saml"
+    Oidc => b"oidc"
 );
 
 impl From<IdentityProviderType> for views::IdentityProviderType {
     fn from(idp_type: IdentityProviderType) -> Self {
         match idp_type {
             IdentityProviderType::Saml => views::IdentityProviderType::Saml,
+            IdentityProviderType::Oidc => views::IdentityProviderType::Oidc,
         }
     }
 }
@@ -100,3 +104,61 @@ impl From<SamlIdentityProvider> for views::SamlIdentityProvider {
         }
     }
 }
+
+#[derive(Queryable, Insertable, Clone, Debug, Selectable, Resource)]
+#[diesel(table_name = oidc_identity_provider)]
+pub struct OidcIdentityProvider {
+    #[diesel(embed)]
+    pub identity: OidcIdentityProviderIdentity,
+
+    pub silo_id: Uuid,
+
+    /// the issuer identifier, which must match the `iss` claim of ID tokens
+    pub issuer: String,
+
+    /// the client id registered with the provider, which must appear in the
+    /// `aud` claim of ID tokens
+    pub client_id: String,
+
+    /// the client secret registered with the provider, if any
+    pub client_secret: Option<String>,
+
+    /// provider endpoint where users are sent to authenticate
+    pub authorization_endpoint: String,
+
+    /// provider endpoint where authorization codes are exchanged for tokens
+    pub token_endpoint: String,
+
+    /// provider endpoint where the ID token signing keys are published
+    pub jwks_uri: String,
+
+    /// service provider endpoint where the provider redirects users back to
+    pub redirect_uri: String,
+
+    /// space-separated list of scopes requested during login
+    pub scopes: String,
+
+    /// if set, ID token claims with this name will be considered to denote a
+    /// user's group membership, where the values will be the group names.
+    pub group_claim_name: Option<String>,
+}
+
+impl From<OidcIdentityProvider> for views::OidcIdentityProvider {
+    fn from(oidc_idp: OidcIdentityProvider) -> Self {
+        Self {
+            identity: oidc_idp.identity(),
+            issuer: oidc_idp.issuer,
+            client_id: oidc_idp.client_id,
+            authorization_endpoint: oidc_idp.authorization_endpoint,
+            token_endpoint: oidc_idp.token_endpoint,
+            jwks_uri: oidc_idp.jwks_uri,
+            redirect_uri: oidc_idp.redirect_uri,
+            scopes: oidc_idp
+                .scopes
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            group_claim_name: oidc_idp.group_claim_name,
+        }
+    }
+}
@@ -8,7 +8,7 @@
 //! silo_id and a project_id, while SiloImage only has a silo_id. Image has a
 //! silo_id and an optional project_id to cover both possibilities.
 
-use super::{BlockSize, ByteCount, Digest};
+use super::{BlockSize, ByteCount, Digest, Labels};
 use crate::schema::{image, project_image, silo_image};
 use db_macros::Resource;
 use nexus_types::external_api::views;
@@ -33,6 +33,7 @@ pub struct Image {
     #[diesel(embed)]
     pub identity: ImageIdentity,
 
+    pub labels: Labels,
     pub silo_id: Uuid,
     pub project_id: Option<Uuid>,
 
@@ -55,6 +56,7 @@ pub struct ProjectImage {
     #[diesel(embed)]
     pub identity: ProjectImageIdentity,
 
+    pub labels: Labels,
     pub silo_id: Uuid,
     pub project_id: Uuid,
     pub volume_id: Uuid,
@@ -76,6 +78,7 @@ pub struct SiloImage {
     #[diesel(embed)]
     pub identity: SiloImageIdentity,
 
+    pub labels: Labels,
     pub silo_id: Uuid,
     pub volume_id: Uuid,
     pub url: Option<String>,
@@ -102,6 +105,7 @@ impl TryFrom<Image> for ProjectImage {
                     time_modified: image.time_modified(),
                     time_deleted: image.time_deleted(),
                 },
+                labels: image.labels,
                 silo_id: image.silo_id,
                 project_id,
                 volume_id: image.volume_id,
@@ -136,6 +140,7 @@ impl TryFrom<Image> for SiloImage {
                     time_modified: image.time_modified(),
                     time_deleted: image.time_deleted(),
                 },
+                labels: image.labels,
                 silo_id: image.silo_id,
                 volume_id: image.volume_id,
                 url: image.url,
@@ -160,6 +165,7 @@ impl From<ProjectImage> for Image {
                 time_modified: image.time_modified(),
                 time_deleted: image.time_deleted(),
             },
+            labels: image.labels,
             silo_id: image.silo_id,
             project_id: Some(image.project_id),
             volume_id: image.volume_id,
@@ -184,6 +190,7 @@ impl From<SiloImage> for Image {
                 time_modified: image.time_modified(),
                 time_deleted: image.time_deleted(),
             },
+            labels: image.labels,
             silo_id: image.silo_id,
             project_id: None,
             volume_id: image.volume_id,
@@ -201,6 +208,7 @@ impl From<Image> for views::Image {
     fn from(image: Image) -> Self {
         Self {
             identity: image.identity(),
+            labels: image.labels.0,
             project_id: image.project_id,
             url: image.url,
             os: image.os,
@@ -2,7 +2,10 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use super::{ByteCount, Disk, Generation, InstanceCpuCount, InstanceState};
+use super::{
+    ByteCount, Disk, Generation, InstanceAutoRestartPolicy, InstanceCpuCount,
+    InstanceState, Labels, SqlU32,
+};
 use crate::collection::DatastoreAttachTargetConfig;
 use crate::schema::{disk, instance};
 use chrono::{DateTime, Utc};
@@ -26,6 +29,9 @@ pub struct Instance {
     #[diesel(embed)]
     identity: InstanceIdentity,
 
+    /// user-provided key/value labels
+    pub labels: Labels,
+
     /// id for the project containing this Instance
     pub project_id: Uuid,
 
@@ -35,6 +41,20 @@ pub struct Instance {
     /// runtime state of the Instance
     #[diesel(embed)]
     pub runtime_state: InstanceRuntimeState,
+
+    /// whether Nexus starts the Instance again on its own
+    pub auto_restart_policy: InstanceAutoRestartPolicy,
+
+    /// set when the Instance was stopped through the API (or created without
+    /// being started), so that an `Always` auto-restart policy leaves it alone
+    pub stop_requested: bool,
+
+    /// number of times in a row that Nexus has restarted the Instance on its
+    /// own
+    pub auto_restart_count: SqlU32,
+
+    /// earliest time at which Nexus may restart the Instance on its own again
+    pub time_auto_restart_next: Option<DateTime<Utc>>,
 }
 
 impl Instance {
@@ -48,15 +68,36 @@ impl Instance {
             InstanceIdentity::new(instance_id, params.identity.clone());
         Self {
             identity,
+            labels: params.labels.clone().into(),
             project_id,
             user_data: params.user_data.clone(),
             runtime_state: runtime,
+            auto_restart_policy: params.auto_restart_policy.into(),
+            stop_requested: !params.start,
+            auto_restart_count: SqlU32::new(0),
+            time_auto_restart_next: None,
         }
     }
 
     pub fn runtime(&self) -> &InstanceRuntimeState {
         &self.runtime_state
     }
+
+    /// Returns true if this Instance's auto-restart policy says that it should
+    /// be started again: it failed (including by being lost along with its
+    /// sled), or, under the `Always` policy, it stopped without anyone asking
+    /// it to through the API.
+    pub fn needs_auto_restart(&self) -> bool {
+        use external::InstanceAutoRestartPolicy as Policy;
+        use external::InstanceState as State;
+        match (self.auto_restart_policy.policy(), self.runtime().state.state())
+        {
+            (Policy::Never, _) => false,
+            (_, State::Failed) => true,
+            (Policy::Always, State::Stopped) => !self.stop_requested,
+            _ => false,
+        }
+    }
 }
 
 /// Conversion to the external API type.
@@ -64,10 +105,12 @@ impl Into<external::Instance> for Instance {
     fn into(self) -> external::Instance {
         external::Instance {
             identity: self.identity(),
+            labels: self.labels.0.clone(),
             project_id: self.project_id,
             ncpus: self.runtime().ncpus.into(),
             memory: self.runtime().memory.into(),
             hostname: self.runtime().hostname.clone(),
+            auto_restart_policy: *self.auto_restart_policy.policy(),
             runtime: self.runtime().clone().into(),
         }
     }
@@ -0,0 +1,40 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::impl_enum_wrapper;
+use omicron_common::api::external;
+use serde::Deserialize;
+use serde::Serialize;
+use std::io::Write;
+
+impl_enum_wrapper!(
+    #[derive(SqlType, Debug, QueryId)]
+    #[diesel(postgres_type(name = "instance_auto_restart_policy"))]
+    pub struct InstanceAutoRestartPolicyEnum;
+
+    #[derive(Copy, Clone, Debug, PartialEq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+    #[diesel(sql_type = InstanceAutoRestartPolicyEnum)]
+    pub struct InstanceAutoRestartPolicy(pub external::InstanceAutoRestartPolicy);
+
+    // Enum values
+    Never => b"never"
+    OnFailure => b"on_failure"
+    Always => b"always"
+);
+
+impl InstanceAutoRestartPolicy {
+    pub fn new(policy: external::InstanceAutoRestartPolicy) -> Self {
+        Self(policy)
+    }
+
+    pub fn policy(&self) -> &external::InstanceAutoRestartPolicy {
+        &self.0
+    }
+}
+
+impl From<external::InstanceAutoRestartPolicy> for InstanceAutoRestartPolicy {
+    fn from(policy: external::InstanceAutoRestartPolicy) -> Self {
+        Self::new(policy)
+    }
+}
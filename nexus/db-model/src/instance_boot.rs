@@ -0,0 +1,41 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::schema::instance_boot;
+use crate::Generation;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Records the sled and Propolis of one boot of an instance
+#[derive(Queryable, Insertable, Selectable, Clone, Debug)]
+#[diesel(table_name = instance_boot)]
+pub struct InstanceBoot {
+    pub instance_id: Uuid,
+    pub boot_generation: Generation,
+    pub time_created: DateTime<Utc>,
+    pub sled_id: Uuid,
+    pub propolis_id: Uuid,
+}
+
+impl InstanceBoot {
+    pub fn new(
+        instance_id: Uuid,
+        boot_generation: Generation,
+        sled_id: Uuid,
+        propolis_id: Uuid,
+    ) -> Self {
+        Self {
+            instance_id,
+            boot_generation,
+            time_created: Utc::now(),
+            sled_id,
+            propolis_id,
+        }
+    }
+
+    /// Returns the boot generation as the sled agent and API number it
+    pub fn generation(&self) -> u64 {
+        i64::from(&self.boot_generation.0) as u64
+    }
+}
@@ -0,0 +1,89 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use diesel::backend::RawValue;
+use diesel::deserialize::{self, FromSql};
+use diesel::dsl::sql;
+use diesel::expression::BoxableExpression;
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, ToSql};
+use diesel::sql_types;
+use diesel::IntoSql;
+use omicron_common::api::external;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Newtype wrapper around [external::Labels], stored as a JSONB object.
+#[derive(
+    Clone,
+    Debug,
+    Default,
+    AsExpression,
+    FromSqlRow,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+)]
+#[diesel(sql_type = sql_types::Jsonb)]
+#[serde(transparent)]
+pub struct Labels(pub external::Labels);
+
+NewtypeFrom! { () pub struct Labels(external::Labels); }
+NewtypeDeref! { () pub struct Labels(external::Labels); }
+
+impl ToSql<sql_types::Jsonb, Pg> for Labels {
+    fn to_sql<'a>(
+        &'a self,
+        out: &mut serialize::Output<'a, '_, Pg>,
+    ) -> serialize::Result {
+        // This matches Diesel's implementation for `serde_json::Value`: a
+        // version byte followed by the JSON text.
+        out.write_all(&[1])?;
+        serde_json::to_writer(out, &self.0)?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<sql_types::Jsonb, Pg> for Labels {
+    fn from_sql(bytes: RawValue<Pg>) -> deserialize::Result<Self> {
+        let value = serde_json::Value::from_sql(bytes)?;
+        Ok(Labels(serde_json::from_value(value)?))
+    }
+}
+
+/// Returns a filter expression that's true for rows of a table with a `labels`
+/// column whose labels satisfy every requirement in `selector`
+///
+/// If there's no selector, the expression is true for every row.
+pub fn labels_match<QS>(
+    selector: Option<&external::LabelSelector>,
+) -> Box<dyn BoxableExpression<QS, Pg, SqlType = sql_types::Bool>> {
+    let Some(selector) = selector else {
+        return Box::new(true.into_sql::<sql_types::Bool>());
+    };
+
+    // Equality requirements are checked together using JSONB containment, and
+    // existence requirements using the "all of these keys exist" operator.
+    // Each is trivially true if there are no such requirements.
+    let mut equals = serde_json::Map::new();
+    let mut exists = Vec::new();
+    for requirement in selector.requirements() {
+        match requirement {
+            external::LabelRequirement::Equals { key, value } => {
+                equals.insert(key.clone(), value.clone().into());
+            }
+            external::LabelRequirement::Exists { key } => {
+                exists.push(key.clone());
+            }
+        }
+    }
+
+    Box::new(
+        sql::<sql_types::Bool>("labels @> ")
+            .bind::<sql_types::Jsonb, _>(serde_json::Value::Object(equals))
+            .sql(" AND labels ?& ")
+            .bind::<sql_types::Array<sql_types::Text>, _>(exists),
+    )
+}
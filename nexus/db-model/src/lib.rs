@@ -9,6 +9,9 @@ extern crate diesel;
 #[macro_use]
 extern crate newtype_derive;
 
+mod access_token;
+mod affinity_group;
+mod audit_log;
 mod block_size;
 mod bytecount;
 mod certificate;
@@ -27,6 +30,8 @@ mod global_image;
 mod identity_provider;
 mod image;
 mod instance;
+mod instance_auto_restart_policy;
+mod instance_boot;
 mod instance_cpu_count;
 mod instance_state;
 mod ip_pool;
@@ -34,6 +39,7 @@ mod ipv4net;
 mod ipv6;
 mod ipv6net;
 mod l4_port_range;
+mod labels;
 mod macaddr;
 mod name;
 mod network_interface;
@@ -43,6 +49,7 @@ mod physical_disk;
 mod physical_disk_kind;
 mod producer_endpoint;
 mod project;
+mod quota;
 mod semver_version;
 mod system_update;
 // These actually represent subqueries, not real table.
@@ -51,11 +58,13 @@ mod system_update;
 pub mod queries;
 mod rack;
 mod region;
+mod region_replacement;
 mod region_snapshot;
 mod role_assignment;
 mod role_builtin;
 pub mod saga_types;
 pub mod schema;
+mod scim_client_token;
 mod service;
 mod service_kind;
 mod silo;
@@ -65,7 +74,9 @@ mod silo_user_password_hash;
 mod sled;
 mod sled_resource;
 mod sled_resource_kind;
+mod sled_state;
 mod snapshot;
+mod snapshot_schedule;
 mod ssh_key;
 mod unsigned;
 mod update_artifact;
@@ -76,6 +87,7 @@ mod vni;
 mod volume;
 mod vpc;
 mod vpc_firewall_rule;
+mod vpc_peering;
 mod vpc_route;
 mod vpc_router;
 mod vpc_subnet;
@@ -91,6 +103,9 @@ mod db {
 
 pub use self::macaddr::*;
 pub use self::unsigned::*;
+pub use access_token::*;
+pub use affinity_group::*;
+pub use audit_log::*;
 pub use block_size::*;
 pub use bytecount::*;
 pub use certificate::*;
@@ -109,6 +124,8 @@ pub use global_image::*;
 pub use identity_provider::*;
 pub use image::*;
 pub use instance::*;
+pub use instance_auto_restart_policy::*;
+pub use instance_boot::*;
 pub use instance_cpu_count::*;
 pub use instance_state::*;
 pub use ip_pool::*;
@@ -116,6 +133,7 @@ pub use ipv4net::*;
 pub use ipv6::*;
 pub use ipv6net::*;
 pub use l4_port_range::*;
+pub use labels::*;
 pub use name::*;
 pub use network_interface::*;
 pub use nexus_service::*;
@@ -124,11 +142,14 @@ pub use physical_disk::*;
 pub use physical_disk_kind::*;
 pub use producer_endpoint::*;
 pub use project::*;
+pub use quota::*;
 pub use rack::*;
 pub use region::*;
+pub use region_replacement::*;
 pub use region_snapshot::*;
 pub use role_assignment::*;
 pub use role_builtin::*;
+pub use scim_client_token::*;
 pub use semver_version::*;
 pub use service::*;
 pub use service_kind::*;
@@ -139,7 +160,9 @@ pub use silo_user_password_hash::*;
 pub use sled::*;
 pub use sled_resource::*;
 pub use sled_resource_kind::*;
+pub use sled_state::*;
 pub use snapshot::*;
+pub use snapshot_schedule::*;
 pub use ssh_key::*;
 pub use system_update::*;
 pub use update_artifact::*;
@@ -150,6 +173,7 @@ pub use vni::*;
 pub use volume::*;
 pub use vpc::*;
 pub use vpc_firewall_rule::*;
+pub use vpc_peering::*;
 pub use vpc_route::*;
 pub use vpc_router::*;
 pub use vpc_subnet::*;
@@ -375,6 +399,33 @@ mod tests {
             .is_err());
     }
 
+    #[test]
+    fn test_vpc_subnet_overlaps() {
+        let subnet = |ipv4: &str, ipv6: &str| {
+            VpcSubnet::new(
+                Uuid::new_v4(),
+                Uuid::new_v4(),
+                IdentityMetadataCreateParams {
+                    name: "net-test-subnet".parse().unwrap(),
+                    description: String::from("A test subnet"),
+                },
+                Ipv4Net(ipv4.parse::<Ipv4Network>().unwrap()),
+                Ipv6Net(ipv6.parse::<Ipv6Network>().unwrap()),
+            )
+        };
+        let app = subnet("172.30.0.0/22", "fd00:1::/64");
+
+        // Disjoint ranges of both versions
+        assert!(!app.overlaps(&subnet("172.30.4.0/22", "fd00:2::/64")));
+        // Identical IPv4 ranges
+        assert!(app.overlaps(&subnet("172.30.0.0/22", "fd00:2::/64")));
+        // One IPv4 range contains the other, in either direction
+        assert!(app.overlaps(&subnet("172.30.2.0/24", "fd00:2::/64")));
+        assert!(app.overlaps(&subnet("172.16.0.0/12", "fd00:2::/64")));
+        // Overlapping IPv6 ranges
+        assert!(app.overlaps(&subnet("10.0.0.0/24", "fd00:1::/64")));
+    }
+
     #[test]
     fn test_ipv6_net_random_subnet() {
         let base = super::Ipv6Net(Ipv6Net(
@@ -2,9 +2,15 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use super::{Disk, Generation, Instance, Name, Snapshot, Vpc};
+use super::{
+    AffinityGroup, Disk, Generation, Instance, Labels, Name, Snapshot,
+    SnapshotSchedule, Vpc,
+};
 use crate::collection::DatastoreCollectionConfig;
-use crate::schema::{disk, image, instance, project, snapshot, vpc};
+use crate::schema::{
+    affinity_group, disk, image, instance, project, snapshot,
+    snapshot_schedule, vpc,
+};
 use crate::Image;
 use chrono::{DateTime, Utc};
 use db_macros::Resource;
@@ -24,6 +30,8 @@ pub struct Project {
     #[diesel(embed)]
     identity: ProjectIdentity,
 
+    pub labels: Labels,
+
     /// child resource generation number, per RFD 192
     pub rcgen: Generation,
     pub silo_id: Uuid,
@@ -34,6 +42,7 @@ impl Project {
     pub fn new(silo_id: Uuid, params: params::ProjectCreate) -> Self {
         Self {
             identity: ProjectIdentity::new(Uuid::new_v4(), params.identity),
+            labels: params.labels.into(),
             rcgen: Generation::new(),
             silo_id,
         }
@@ -42,7 +51,7 @@ impl Project {
 
 impl From<Project> for views::Project {
     fn from(project: Project) -> Self {
-        Self { identity: project.identity() }
+        Self { identity: project.identity(), labels: project.labels.0 }
     }
 }
 
@@ -74,6 +83,20 @@ impl DatastoreCollectionConfig<Snapshot> for Project {
     type CollectionIdColumn = snapshot::dsl::project_id;
 }
 
+impl DatastoreCollectionConfig<AffinityGroup> for Project {
+    type CollectionId = Uuid;
+    type GenerationNumberColumn = project::dsl::rcgen;
+    type CollectionTimeDeletedColumn = project::dsl::time_deleted;
+    type CollectionIdColumn = affinity_group::dsl::project_id;
+}
+
+impl DatastoreCollectionConfig<SnapshotSchedule> for Project {
+    type CollectionId = Uuid;
+    type GenerationNumberColumn = project::dsl::rcgen;
+    type CollectionTimeDeletedColumn = project::dsl::time_deleted;
+    type CollectionIdColumn = snapshot_schedule::dsl::project_id;
+}
+
 impl DatastoreCollectionConfig<Vpc> for Project {
     type CollectionId = Uuid;
     type GenerationNumberColumn = project::dsl::rcgen;
@@ -87,6 +110,7 @@ impl DatastoreCollectionConfig<Vpc> for Project {
 pub struct ProjectUpdate {
     pub name: Option<Name>,
     pub description: Option<String>,
+    pub labels: Option<Labels>,
     pub time_modified: DateTime<Utc>,
 }
 
@@ -95,6 +119,7 @@ impl From<params::ProjectUpdate> for ProjectUpdate {
         Self {
             name: params.identity.name.map(Name),
             description: params.identity.description,
+            labels: params.labels.map(Labels),
             time_modified: Utc::now(),
         }
     }
@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Database representation of silo and project quotas
+
+use crate::schema::{project_quotas, silo_quotas};
+use crate::ByteCount;
+use chrono::{DateTime, Utc};
+use nexus_types::external_api::params;
+use nexus_types::external_api::views;
+use uuid::Uuid;
+
+/// Limits on the virtual resources which may be provisioned within a silo
+///
+/// These are enforced by the virtual provisioning CTE, against the silo's
+/// [`crate::VirtualProvisioningCollection`].
+#[derive(Queryable, Insertable, Selectable, Clone, Debug)]
+#[diesel(table_name = silo_quotas)]
+pub struct SiloQuotas {
+    pub silo_id: Uuid,
+    pub time_created: DateTime<Utc>,
+    pub time_modified: DateTime<Utc>,
+
+    pub cpus: Option<i64>,
+    pub memory_bytes: Option<ByteCount>,
+    pub storage_bytes: Option<ByteCount>,
+}
+
+impl SiloQuotas {
+    pub fn new(silo_id: Uuid, quotas: params::QuotasUpdate) -> Self {
+        let now = Utc::now();
+        Self {
+            silo_id,
+            time_created: now,
+            time_modified: now,
+            cpus: quotas.cpus,
+            memory_bytes: quotas.memory.map(ByteCount::from),
+            storage_bytes: quotas.storage.map(ByteCount::from),
+        }
+    }
+}
+
+impl From<SiloQuotas> for views::SiloQuotas {
+    fn from(quotas: SiloQuotas) -> Self {
+        Self {
+            silo_id: quotas.silo_id,
+            cpus: quotas.cpus,
+            memory: quotas.memory_bytes.map(|b| *b),
+            storage: quotas.storage_bytes.map(|b| *b),
+        }
+    }
+}
+
+/// Limits on the virtual resources which may be provisioned within a project
+///
+/// These are enforced in addition to those of the project's silo.
+#[derive(Queryable, Insertable, Selectable, Clone, Debug)]
+#[diesel(table_name = project_quotas)]
+pub struct ProjectQuotas {
+    pub project_id: Uuid,
+    pub time_created: DateTime<Utc>,
+    pub time_modified: DateTime<Utc>,
+
+    pub cpus: Option<i64>,
+    pub memory_bytes: Option<ByteCount>,
+    pub storage_bytes: Option<ByteCount>,
+}
+
+impl ProjectQuotas {
+    pub fn new(project_id: Uuid, quotas: params::QuotasUpdate) -> Self {
+        let now = Utc::now();
+        Self {
+            project_id,
+            time_created: now,
+            time_modified: now,
+            cpus: quotas.cpus,
+            memory_bytes: quotas.memory.map(ByteCount::from),
+            storage_bytes: quotas.storage.map(ByteCount::from),
+        }
+    }
+}
+
+impl From<ProjectQuotas> for views::ProjectQuotas {
+    fn from(quotas: ProjectQuotas) -> Self {
+        Self {
+            project_id: quotas.project_id,
+            cpus: quotas.cpus,
+            memory: quotas.memory_bytes.map(|b| *b),
+            storage: quotas.storage_bytes.map(|b| *b),
+        }
+    }
+}
@@ -39,6 +39,7 @@ pub struct Region {
 
 impl Region {
     pub fn new(
+        id: Uuid,
         dataset_id: Uuid,
         volume_id: Uuid,
         block_size: ByteCount,
@@ -46,7 +47,7 @@ impl Region {
         extent_count: i64,
     ) -> Self {
         Self {
-            identity: RegionIdentity::new(Uuid::new_v4()),
+            identity: RegionIdentity::new(id),
             dataset_id,
             volume_id,
             block_size,
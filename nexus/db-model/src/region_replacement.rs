@@ -0,0 +1,119 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::impl_enum_type;
+use crate::schema::region_replacement;
+use crate::Region;
+use db_macros::Asset;
+use nexus_types::{external_api::views, identity::Asset};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+impl_enum_type!(
+    #[derive(Clone, SqlType, Debug, QueryId)]
+    #[diesel(postgres_type(name = "region_replacement_state"))]
+    pub struct RegionReplacementStateEnum;
+
+    #[derive(Clone, Copy, Debug, AsExpression, FromSqlRow, Serialize, Deserialize, PartialEq, Eq)]
+    #[diesel(sql_type = RegionReplacementStateEnum)]
+    pub enum RegionReplacementState;
+
+    // Enum values
+    Requested => b"requested"
+    Running => b"running"
+    AwaitingRestart => b"awaiting_restart"
+    Complete => b"complete"
+);
+
+impl From<RegionReplacementState> for views::RegionReplacementState {
+    fn from(state: RegionReplacementState) -> Self {
+        use views::RegionReplacementState as api;
+        use RegionReplacementState as db;
+        match state {
+            db::Requested => api::Requested,
+            db::Running => api::Running,
+            db::AwaitingRestart => api::AwaitingRestart,
+            db::Complete => api::Complete,
+        }
+    }
+}
+
+/// The instance whose running Propolis still uses a replaced region, and which
+/// must be restarted or migrated before the replacement region is repaired
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RegionReplacementRestart {
+    pub instance_id: Uuid,
+    pub propolis_id: Uuid,
+}
+
+/// Database representation of the replacement of a region whose dataset was
+/// lost.
+#[derive(
+    Queryable,
+    Insertable,
+    Debug,
+    Clone,
+    Selectable,
+    Asset,
+    Serialize,
+    Deserialize,
+    PartialEq,
+)]
+#[diesel(table_name = region_replacement)]
+pub struct RegionReplacement {
+    #[diesel(embed)]
+    identity: RegionReplacementIdentity,
+
+    pub old_region_id: Uuid,
+    pub old_dataset_id: Uuid,
+
+    pub volume_id: Uuid,
+    pub disk_id: Uuid,
+
+    pub new_region_id: Option<Uuid>,
+
+    pub state: RegionReplacementState,
+
+    /// The instance that must be restarted, while awaiting restart
+    pub instance_id: Option<Uuid>,
+    /// The Propolis that still uses the old region, while awaiting restart
+    pub propolis_id: Option<Uuid>,
+
+    pub error: Option<String>,
+}
+
+impl RegionReplacement {
+    pub fn new(old_region: &Region, disk_id: Uuid) -> Self {
+        Self {
+            identity: RegionReplacementIdentity::new(Uuid::new_v4()),
+            old_region_id: old_region.id(),
+            old_dataset_id: old_region.dataset_id(),
+            volume_id: old_region.volume_id(),
+            disk_id,
+            new_region_id: None,
+            state: RegionReplacementState::Requested,
+            instance_id: None,
+            propolis_id: None,
+            error: None,
+        }
+    }
+}
+
+impl From<RegionReplacement> for views::RegionReplacement {
+    fn from(replacement: RegionReplacement) -> Self {
+        Self {
+            id: replacement.id(),
+            time_created: replacement.time_created(),
+            time_modified: replacement.time_modified(),
+            disk_id: replacement.disk_id,
+            volume_id: replacement.volume_id,
+            old_region_id: replacement.old_region_id,
+            new_region_id: replacement.new_region_id,
+            state: replacement.state.into(),
+            degraded: replacement.state != RegionReplacementState::Complete,
+            instance_id: replacement.instance_id,
+            error: replacement.error,
+        }
+    }
+}
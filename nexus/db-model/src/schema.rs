@@ -14,6 +14,7 @@ table! {
         time_created -> Timestamptz,
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
+        labels -> Jsonb,
         rcgen -> Int8,
         project_id -> Uuid,
         volume_id -> Uuid,
@@ -37,6 +38,7 @@ table! {
         time_created -> Timestamptz,
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
+        labels -> Jsonb,
         silo_id -> Uuid,
         project_id -> Nullable<Uuid>,
         volume_id -> Uuid,
@@ -57,6 +59,7 @@ table! {
         time_created -> Timestamptz,
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
+        labels -> Jsonb,
         silo_id -> Uuid,
         project_id -> Uuid,
         volume_id -> Uuid,
@@ -77,6 +80,7 @@ table! {
         time_created -> Timestamptz,
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
+        labels -> Jsonb,
         silo_id -> Uuid,
         volume_id -> Uuid,
         url -> Nullable<Text>,
@@ -114,6 +118,7 @@ table! {
         time_created -> Timestamptz,
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
+        labels -> Jsonb,
 
         project_id -> Uuid,
         disk_id -> Uuid,
@@ -125,6 +130,51 @@ table! {
         state -> crate::SnapshotStateEnum,
         block_size -> crate::BlockSizeEnum,
         size_bytes -> Int8,
+
+        snapshot_schedule_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    snapshot_schedule (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Text,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        time_deleted -> Nullable<Timestamptz>,
+
+        project_id -> Uuid,
+        disk_id -> Uuid,
+        silo_id -> Uuid,
+        silo_user_id -> Uuid,
+        interval_minutes -> Int8,
+        retention_count -> Int8,
+        time_next_snapshot -> Timestamptz,
+        time_last_snapshot -> Nullable<Timestamptz>,
+        last_error -> Nullable<Text>,
+    }
+}
+
+table! {
+    affinity_group (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Text,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        time_deleted -> Nullable<Timestamptz>,
+
+        project_id -> Uuid,
+        kind -> crate::AffinityGroupKindEnum,
+        policy -> crate::AffinityPolicyEnum,
+    }
+}
+
+table! {
+    affinity_group_instance_membership (group_id, instance_id) {
+        group_id -> Uuid,
+        instance_id -> Uuid,
     }
 }
 
@@ -136,6 +186,7 @@ table! {
         time_created -> Timestamptz,
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
+        labels -> Jsonb,
         project_id -> Uuid,
         user_data -> Binary,
         state -> crate::InstanceStateEnum,
@@ -150,6 +201,20 @@ table! {
         ncpus -> Int8,
         memory -> Int8,
         hostname -> Text,
+        auto_restart_policy -> crate::InstanceAutoRestartPolicyEnum,
+        stop_requested -> Bool,
+        auto_restart_count -> Int8,
+        time_auto_restart_next -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    instance_boot (instance_id, boot_generation) {
+        instance_id -> Uuid,
+        boot_generation -> Int8,
+        time_created -> Timestamptz,
+        sled_id -> Uuid,
+        propolis_id -> Uuid,
     }
 }
 
@@ -262,6 +327,23 @@ table! {
         ip -> Inet,
         first_port -> Int4,
         last_port -> Int4,
+        project_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    floating_ip (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Text,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        time_deleted -> Nullable<Timestamptz>,
+        ip_pool_id -> Uuid,
+        ip_pool_range_id -> Uuid,
+        instance_id -> Nullable<Uuid>,
+        ip -> Inet,
+        project_id -> Uuid,
     }
 }
 
@@ -291,6 +373,7 @@ table! {
 
         silo_id -> Uuid,
         external_id -> Text,
+        active -> Bool,
     }
 }
 
@@ -367,6 +450,29 @@ table! {
     }
 }
 
+table! {
+    oidc_identity_provider (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Text,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        time_deleted -> Nullable<Timestamptz>,
+
+        silo_id -> Uuid,
+
+        issuer -> Text,
+        client_id -> Text,
+        client_secret -> Nullable<Text>,
+        authorization_endpoint -> Text,
+        token_endpoint -> Text,
+        jwks_uri -> Text,
+        redirect_uri -> Text,
+        scopes -> Text,
+        group_claim_name -> Nullable<Text>,
+    }
+}
+
 table! {
     ssh_key (id) {
         id -> Uuid,
@@ -398,6 +504,7 @@ table! {
         time_created -> Timestamptz,
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
+        labels -> Jsonb,
         rcgen -> Int8,
         silo_id -> Uuid,
     }
@@ -467,6 +574,10 @@ table! {
         ip -> Inet,
         port -> Int4,
         last_used_address -> Inet,
+
+        state -> crate::SledStateEnum,
+        time_state_updated -> Timestamptz,
+        schedulable -> Bool,
     }
 }
 
@@ -478,6 +589,7 @@ table! {
         hardware_threads -> Int8,
         rss_ram -> Int8,
         reservoir_ram -> Int8,
+        instance_id -> Nullable<Uuid>,
     }
 }
 
@@ -564,6 +676,28 @@ table! {
     }
 }
 
+table! {
+    silo_quotas (silo_id) {
+        silo_id -> Uuid,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        cpus -> Nullable<Int8>,
+        memory_bytes -> Nullable<Int8>,
+        storage_bytes -> Nullable<Int8>,
+    }
+}
+
+table! {
+    project_quotas (project_id) {
+        project_id -> Uuid,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        cpus -> Nullable<Int8>,
+        memory_bytes -> Nullable<Int8>,
+        storage_bytes -> Nullable<Int8>,
+    }
+}
+
 table! {
     zpool (id) {
         id -> Uuid,
@@ -612,6 +746,27 @@ table! {
     }
 }
 
+table! {
+    region_replacement (id) {
+        id -> Uuid,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+
+        old_region_id -> Uuid,
+        old_dataset_id -> Uuid,
+
+        volume_id -> Uuid,
+        disk_id -> Uuid,
+
+        new_region_id -> Nullable<Uuid>,
+
+        state -> crate::RegionReplacementStateEnum,
+        instance_id -> Nullable<Uuid>,
+        propolis_id -> Nullable<Uuid>,
+        error -> Nullable<Text>,
+    }
+}
+
 table! {
     region_snapshot (dataset_id, region_id, snapshot_id) {
         dataset_id -> Uuid,
@@ -648,6 +803,7 @@ table! {
         time_created -> Timestamptz,
         time_modified -> Timestamptz,
         time_deleted -> Nullable<Timestamptz>,
+        labels -> Jsonb,
         project_id -> Uuid,
         system_router_id -> Uuid,
         vni -> Int4,
@@ -670,6 +826,7 @@ table! {
         rcgen -> Int8,
         ipv4_block -> Inet,
         ipv6_block -> Inet,
+        custom_router_id -> Nullable<Uuid>,
     }
 }
 
@@ -687,6 +844,19 @@ table! {
     }
 }
 
+table! {
+    vpc_peering (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Text,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        time_deleted -> Nullable<Timestamptz>,
+        vpc_id -> Uuid,
+        peer_vpc_id -> Uuid,
+    }
+}
+
 table! {
     router_route (id) {
         id -> Uuid,
@@ -786,6 +956,34 @@ table! {
     }
 }
 
+table! {
+    access_token (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Text,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        time_deleted -> Nullable<Timestamptz>,
+        silo_user_id -> Uuid,
+        token -> Text,
+        time_expires -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    scim_client_token (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Text,
+        time_created -> Timestamptz,
+        time_modified -> Timestamptz,
+        time_deleted -> Nullable<Timestamptz>,
+        silo_id -> Uuid,
+        token -> Text,
+        time_expires -> Nullable<Timestamptz>,
+    }
+}
+
 table! {
     role_builtin (resource_type, role_name) {
         resource_type -> Text,
@@ -810,6 +1008,34 @@ table! {
     }
 }
 
+table! {
+    audit_log (id) {
+        id -> Uuid,
+        seq -> Int8,
+        chain_id -> Uuid,
+        time_started -> Timestamptz,
+        time_completed -> Nullable<Timestamptz>,
+        request_id -> Text,
+        http_method -> Text,
+        request_uri -> Text,
+        actor_id -> Nullable<Uuid>,
+        actor_silo_id -> Nullable<Uuid>,
+        resource_ids -> Array<Uuid>,
+        result_code -> Nullable<Int4>,
+        chain_seq -> Nullable<Int8>,
+        prev_hash -> Nullable<Text>,
+        hash -> Nullable<Text>,
+    }
+}
+
+table! {
+    audit_log_chain (id) {
+        id -> Uuid,
+        seq -> Int8,
+        hash -> Text,
+    }
+}
+
 table! {
     update_artifact (name, version, kind) {
         name -> Text,
@@ -889,20 +1115,26 @@ allow_tables_to_appear_in_same_query!(ip_pool_range, ip_pool);
 joinable!(ip_pool_range -> ip_pool (ip_pool_id));
 
 allow_tables_to_appear_in_same_query!(
+    affinity_group,
+    affinity_group_instance_membership,
     dataset,
     disk,
+    floating_ip,
     image,
     project_image,
     silo_image,
     instance,
+    instance_boot,
     metric_producer,
     network_interface,
     instance_network_interface,
     service_network_interface,
     oximeter,
+    physical_disk,
     project,
     rack,
     region,
+    region_replacement,
     region_snapshot,
     saga,
     saga_node_event,
@@ -917,12 +1149,23 @@ allow_tables_to_appear_in_same_query!(
     vpc,
     vpc_subnet,
     vpc_router,
+    vpc_peering,
     vpc_firewall_rule,
     user_builtin,
     role_builtin,
     role_assignment,
 );
 
+// `zpool` and `dataset` are allowed to appear together by the region
+// allocation queries.
+allow_tables_to_appear_in_same_query!(
+    zpool,
+    physical_disk,
+    region,
+    region_replacement,
+    sled,
+);
+
 allow_tables_to_appear_in_same_query!(dns_zone, dns_version, dns_name);
 allow_tables_to_appear_in_same_query!(external_ip, nexus_service);
 joinable!(nexus_service -> external_ip (external_ip_id));
@@ -0,0 +1,66 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::device_auth::generate_token;
+use crate::schema::scim_client_token;
+use chrono::{DateTime, Utc};
+use db_macros::Resource;
+use nexus_types::external_api::params;
+use nexus_types::external_api::views;
+use nexus_types::identity::Resource;
+use uuid::Uuid;
+
+/// Describes a bearer token with which a SCIM client (i.e., an external
+/// identity provider) provisions the users and groups of a Silo.
+// TODO-security: wrap token in an opaque struct to avoid accidental leaks.
+#[derive(Clone, Debug, Insertable, Queryable, Resource, Selectable)]
+#[diesel(table_name = scim_client_token)]
+pub struct ScimClientToken {
+    #[diesel(embed)]
+    identity: ScimClientTokenIdentity,
+
+    pub silo_id: Uuid,
+    pub token: String,
+    pub time_expires: Option<DateTime<Utc>>,
+}
+
+impl ScimClientToken {
+    pub fn new(silo_id: Uuid, params: params::ScimClientTokenCreate) -> Self {
+        Self {
+            identity: ScimClientTokenIdentity::new(
+                Uuid::new_v4(),
+                params.identity,
+            ),
+            silo_id,
+            token: generate_token(),
+            time_expires: params.time_expires,
+        }
+    }
+
+    /// Returns whether the token has expired as of `now`
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.time_expires.map_or(false, |time_expires| time_expires <= now)
+    }
+}
+
+impl From<ScimClientToken> for views::ScimClientToken {
+    fn from(token: ScimClientToken) -> Self {
+        Self {
+            identity: token.identity(),
+            silo_id: token.silo_id,
+            time_expires: token.time_expires,
+        }
+    }
+}
+
+impl From<ScimClientToken> for views::ScimClientTokenCreated {
+    fn from(token: ScimClientToken) -> Self {
+        Self {
+            identity: token.identity(),
+            silo_id: token.silo_id,
+            time_expires: token.time_expires,
+            bearer_token: format!("oxide-scim-{}", token.token),
+        }
+    }
+}
@@ -20,6 +20,10 @@ pub struct SiloUser {
 
     /// The identity provider's ID for this user.
     pub external_id: String,
+
+    /// Whether the user may log in.  Users provisioned by an external
+    /// directory (e.g., via SCIM) may be deactivated rather than deleted.
+    pub active: bool,
 }
 
 impl SiloUser {
@@ -29,10 +33,20 @@ impl SiloUser {
             time_deleted: None,
             silo_id,
             external_id,
+            active: true,
         }
     }
 }
 
+/// Describes a change to a silo user's external id or whether they're active
+#[derive(AsChangeset)]
+#[diesel(table_name = silo_user)]
+pub struct SiloUserUpdate {
+    pub external_id: Option<String>,
+    pub active: Option<bool>,
+    pub time_modified: chrono::DateTime<chrono::Utc>,
+}
+
 impl From<SiloUser> for views::User {
     fn from(user: SiloUser) -> Self {
         Self {
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use super::{ByteCount, Generation, SqlU16, SqlU32};
+use super::{ByteCount, Generation, SledState, SqlU16, SqlU32};
 use crate::collection::DatastoreCollectionConfig;
 use crate::ipv6;
 use crate::schema::{physical_disk, service, sled, zpool};
@@ -55,6 +55,14 @@ pub struct Sled {
 
     /// The last IP address provided to an Oxide service on this sled
     pub last_used_address: ipv6::Ipv6Addr,
+
+    /// The health of the sled, as last observed by Nexus
+    pub state: SledState,
+    /// When `state` last changed
+    pub time_state_updated: DateTime<Utc>,
+
+    /// Whether new instances may be placed on this sled
+    pub schedulable: bool,
 }
 
 impl Sled {
@@ -70,8 +78,10 @@ impl Sled {
             segments[7] += omicron_common::address::RSS_RESERVED_ADDRESSES;
             ipv6::Ipv6Addr::from(Ipv6Addr::from(segments))
         };
+        let identity = SledIdentity::new(id);
+        let time_state_updated = identity.time_created;
         Self {
-            identity: SledIdentity::new(id),
+            identity,
             time_deleted: None,
             rcgen: Generation::new(),
             rack_id,
@@ -86,6 +96,9 @@ impl Sled {
             ip: ipv6::Ipv6Addr::from(addr.ip()),
             port: addr.port().into(),
             last_used_address,
+            state: SledState::Active,
+            time_state_updated,
+            schedulable: true,
         }
     }
 
@@ -118,6 +131,8 @@ impl From<Sled> for views::Sled {
             },
             usable_hardware_threads: sled.usable_hardware_threads.0,
             usable_physical_ram: *sled.usable_physical_ram,
+            state: sled.state.into(),
+            schedulable: sled.schedulable,
         }
     }
 }
@@ -148,12 +163,22 @@ impl DatastoreCollectionConfig<super::Service> for Sled {
 #[derive(Debug)]
 pub struct SledReservationConstraints {
     must_select_from: Vec<Uuid>,
+    must_not_select_from: Vec<Uuid>,
+    prefer_select_from: Vec<Uuid>,
+    prefer_not_select_from: Vec<Uuid>,
+    affinity_groups: Vec<Uuid>,
 }
 
 impl SledReservationConstraints {
     /// Creates a constraint set with no constraints in it.
     pub fn none() -> Self {
-        Self { must_select_from: Vec::new() }
+        Self {
+            must_select_from: Vec::new(),
+            must_not_select_from: Vec::new(),
+            prefer_select_from: Vec::new(),
+            prefer_not_select_from: Vec::new(),
+            affinity_groups: Vec::new(),
+        }
     }
 
     /// If the constraints include a set of sleds that the caller must select
@@ -167,6 +192,51 @@ impl SledReservationConstraints {
             Some(&self.must_select_from)
         }
     }
+
+    /// If the constraints include a set of sleds that the caller must not
+    /// select, returns `Some` and a slice containing the members of that set.
+    pub fn must_not_select_from(&self) -> Option<&[Uuid]> {
+        if self.must_not_select_from.is_empty() {
+            None
+        } else {
+            Some(&self.must_not_select_from)
+        }
+    }
+
+    /// If the constraints include a set of sleds that should be selected
+    /// ahead of all others when they have space, returns `Some` and a slice
+    /// containing the members of that set.
+    pub fn prefer_select_from(&self) -> Option<&[Uuid]> {
+        if self.prefer_select_from.is_empty() {
+            None
+        } else {
+            Some(&self.prefer_select_from)
+        }
+    }
+
+    /// If the constraints include a set of sleds that should only be selected
+    /// when no other sled has space, returns `Some` and a slice containing
+    /// the members of that set.
+    pub fn prefer_not_select_from(&self) -> Option<&[Uuid]> {
+        if self.prefer_not_select_from.is_empty() {
+            None
+        } else {
+            Some(&self.prefer_not_select_from)
+        }
+    }
+
+    /// If the constraints include affinity groups whose policies the chosen
+    /// sled must honor, returns `Some` and a slice containing their IDs.
+    ///
+    /// The sleds that these groups allow depend on where their members are
+    /// placed, so they're worked out when the reservation is made.
+    pub fn affinity_groups(&self) -> Option<&[Uuid]> {
+        if self.affinity_groups.is_empty() {
+            None
+        } else {
+            Some(&self.affinity_groups)
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -189,6 +259,35 @@ impl SledReservationConstraintBuilder {
         self
     }
 
+    /// Adds a "must not select any of the following sled IDs" constraint,
+    /// appending to any such constraint that already exists.
+    pub fn must_not_select_from(mut self, sled_ids: &[Uuid]) -> Self {
+        self.constraints.must_not_select_from.extend(sled_ids);
+        self
+    }
+
+    /// Adds a "prefer the following sled IDs" constraint, appending to any
+    /// such constraint that already exists.
+    pub fn prefer_select_from(mut self, sled_ids: &[Uuid]) -> Self {
+        self.constraints.prefer_select_from.extend(sled_ids);
+        self
+    }
+
+    /// Adds an "avoid the following sled IDs" constraint, appending to any
+    /// such constraint that already exists.
+    pub fn prefer_not_select_from(mut self, sled_ids: &[Uuid]) -> Self {
+        self.constraints.prefer_not_select_from.extend(sled_ids);
+        self
+    }
+
+    /// Adds a constraint that the chosen sled honor the policies of the
+    /// following affinity groups, appending to any such constraint that
+    /// already exists.
+    pub fn affinity_groups(mut self, group_ids: &[Uuid]) -> Self {
+        self.constraints.affinity_groups.extend(group_ids);
+        self
+    }
+
     /// Builds a set of constraints from this builder's current state.
     pub fn build(self) -> SledReservationConstraints {
         self.constraints
@@ -34,6 +34,9 @@ pub struct SledResource {
 
     #[diesel(embed)]
     pub resources: Resources,
+
+    /// the instance on whose behalf the resources were reserved, if any
+    pub instance_id: Option<Uuid>,
 }
 
 impl SledResource {
@@ -41,8 +44,9 @@ impl SledResource {
         id: Uuid,
         sled_id: Uuid,
         kind: SledResourceKind,
+        instance_id: Option<Uuid>,
         resources: Resources,
     ) -> Self {
-        Self { id, sled_id, kind, resources }
+        Self { id, sled_id, kind, resources, instance_id }
     }
 }
@@ -0,0 +1,36 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::impl_enum_type;
+use nexus_types::external_api::views;
+use serde::{Deserialize, Serialize};
+
+impl_enum_type!(
+    #[derive(Clone, SqlType, Debug, QueryId)]
+    #[diesel(postgres_type(name = "sled_state"))]
+    pub struct SledStateEnum;
+
+    #[derive(Clone, Copy, Debug, AsExpression, FromSqlRow, Serialize, Deserialize, PartialEq, Eq)]
+    #[diesel(sql_type = SledStateEnum)]
+    pub enum SledState;
+
+    // Enum values
+    Active => b"active"
+    Degraded => b"degraded"
+    Unreachable => b"unreachable"
+    Expunged => b"expunged"
+);
+
+impl From<SledState> for views::SledState {
+    fn from(state: SledState) -> Self {
+        use views::SledState as api;
+        use SledState as db;
+        match state {
+            db::Active => api::Active,
+            db::Degraded => api::Degraded,
+            db::Unreachable => api::Unreachable,
+            db::Expunged => api::Expunged,
+        }
+    }
+}
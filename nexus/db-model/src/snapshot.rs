@@ -6,6 +6,7 @@ use super::{impl_enum_type, ByteCount};
 use crate::schema::snapshot;
 use crate::BlockSize;
 use crate::Generation;
+use crate::Labels;
 use db_macros::Resource;
 use nexus_types::external_api::views;
 use nexus_types::identity::Resource;
@@ -42,6 +43,8 @@ pub struct Snapshot {
     #[diesel(embed)]
     pub identity: SnapshotIdentity,
 
+    pub labels: Labels,
+
     pub project_id: Uuid,
     // which disk is this a snapshot of
     pub disk_id: Uuid,
@@ -56,16 +59,21 @@ pub struct Snapshot {
 
     #[diesel(column_name = size_bytes)]
     pub size: ByteCount,
+
+    /// the snapshot schedule that took this snapshot, if any
+    pub snapshot_schedule_id: Option<Uuid>,
 }
 
 impl From<Snapshot> for views::Snapshot {
     fn from(snapshot: Snapshot) -> Self {
         Self {
             identity: snapshot.identity(),
+            labels: snapshot.labels.0,
             project_id: snapshot.project_id,
             disk_id: snapshot.disk_id,
             state: snapshot.state.into(),
             size: snapshot.size.into(),
+            snapshot_schedule_id: snapshot.snapshot_schedule_id,
         }
     }
 }
@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Database representation of snapshot schedules
+
+use crate::schema::snapshot_schedule;
+use crate::SqlU32;
+use chrono::{DateTime, Utc};
+use db_macros::Resource;
+use nexus_types::external_api::params;
+use nexus_types::external_api::views;
+use nexus_types::identity::Resource;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A schedule on which snapshots of a disk are taken automatically
+#[derive(
+    Queryable,
+    Insertable,
+    Selectable,
+    Clone,
+    Debug,
+    Resource,
+    Serialize,
+    Deserialize,
+)]
+#[diesel(table_name = snapshot_schedule)]
+pub struct SnapshotSchedule {
+    #[diesel(embed)]
+    pub identity: SnapshotScheduleIdentity,
+
+    pub project_id: Uuid,
+    pub disk_id: Uuid,
+
+    /// Silo of the user on whose behalf snapshots are taken
+    pub silo_id: Uuid,
+    /// User on whose behalf snapshots are taken
+    pub silo_user_id: Uuid,
+
+    pub interval_minutes: SqlU32,
+    pub retention_count: SqlU32,
+
+    pub time_next_snapshot: DateTime<Utc>,
+    pub time_last_snapshot: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl SnapshotSchedule {
+    /// Creates a new schedule whose first snapshot is due immediately
+    pub fn new(
+        schedule_id: Uuid,
+        project_id: Uuid,
+        disk_id: Uuid,
+        silo_id: Uuid,
+        silo_user_id: Uuid,
+        params: params::SnapshotScheduleCreate,
+    ) -> Self {
+        let identity =
+            SnapshotScheduleIdentity::new(schedule_id, params.identity);
+        let time_next_snapshot = identity.time_created;
+        Self {
+            identity,
+            project_id,
+            disk_id,
+            silo_id,
+            silo_user_id,
+            interval_minutes: params.interval_minutes.into(),
+            retention_count: params.retention_count.into(),
+            time_next_snapshot,
+            time_last_snapshot: None,
+            last_error: None,
+        }
+    }
+
+    /// Returns the time between snapshots
+    pub fn interval(&self) -> chrono::Duration {
+        chrono::Duration::minutes(i64::from(*self.interval_minutes))
+    }
+}
+
+impl From<SnapshotSchedule> for views::SnapshotSchedule {
+    fn from(schedule: SnapshotSchedule) -> Self {
+        Self {
+            identity: schedule.identity(),
+            project_id: schedule.project_id,
+            disk_id: schedule.disk_id,
+            interval_minutes: *schedule.interval_minutes,
+            retention_count: *schedule.retention_count,
+            time_next_snapshot: schedule.time_next_snapshot,
+            time_last_snapshot: schedule.time_last_snapshot,
+            last_error: schedule.last_error,
+        }
+    }
+}
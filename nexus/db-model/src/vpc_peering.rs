@@ -0,0 +1,55 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::schema::vpc_peering;
+use chrono::{DateTime, Utc};
+use db_macros::Resource;
+use nexus_types::external_api::params;
+use nexus_types::external_api::views;
+use nexus_types::identity::Resource;
+use uuid::Uuid;
+
+/// A peering between two VPCs in the same Silo
+///
+/// The peering belongs to `vpc_id`, but applies equally to both VPCs.
+#[derive(Queryable, Insertable, Clone, Debug, Selectable, Resource)]
+#[diesel(table_name = vpc_peering)]
+pub struct VpcPeering {
+    #[diesel(embed)]
+    identity: VpcPeeringIdentity,
+
+    pub vpc_id: Uuid,
+    pub peer_vpc_id: Uuid,
+}
+
+impl VpcPeering {
+    pub fn new(
+        peering_id: Uuid,
+        vpc_id: Uuid,
+        peer_vpc_id: Uuid,
+        params: params::VpcPeeringCreate,
+    ) -> Self {
+        let identity = VpcPeeringIdentity::new(peering_id, params.identity);
+        Self { identity, vpc_id, peer_vpc_id }
+    }
+
+    /// Returns the VPC at the other end of the peering from `vpc_id`
+    pub fn other_vpc_id(&self, vpc_id: Uuid) -> Uuid {
+        if self.vpc_id == vpc_id {
+            self.peer_vpc_id
+        } else {
+            self.vpc_id
+        }
+    }
+}
+
+impl From<VpcPeering> for views::VpcPeering {
+    fn from(peering: VpcPeering) -> Self {
+        Self {
+            identity: peering.identity(),
+            vpc_id: peering.vpc_id,
+            peer_vpc_id: peering.peer_vpc_id,
+        }
+    }
+}
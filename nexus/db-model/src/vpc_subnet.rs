@@ -38,6 +38,7 @@ pub struct VpcSubnet {
     pub rcgen: Generation,
     pub ipv4_block: Ipv4Net,
     pub ipv6_block: Ipv6Net,
+    pub custom_router_id: Option<Uuid>,
 }
 
 impl VpcSubnet {
@@ -59,6 +60,7 @@ impl VpcSubnet {
             rcgen: Generation::new(),
             ipv4_block: Ipv4Net(ipv4_block),
             ipv6_block: Ipv6Net(ipv6_block),
+            custom_router_id: None,
         }
     }
 
@@ -92,6 +94,20 @@ impl VpcSubnet {
             addr, subnet,
         )))
     }
+
+    /// Returns whether either of this subnet's IP address ranges overlaps the
+    /// range of the same IP version in `other`.
+    ///
+    /// Subnets in the same VPC never overlap, but subnets in VPCs that are
+    /// peered must not overlap either.
+    pub fn overlaps(&self, other: &VpcSubnet) -> bool {
+        let (a4, b4) = (self.ipv4_block.0 .0, other.ipv4_block.0 .0);
+        let (a6, b6) = (self.ipv6_block.0 .0, other.ipv6_block.0 .0);
+        a4.contains(b4.network())
+            || b4.contains(a4.network())
+            || a6.contains(b6.network())
+            || b6.contains(a6.network())
+    }
 }
 
 impl From<VpcSubnet> for views::VpcSubnet {
@@ -101,6 +117,7 @@ impl From<VpcSubnet> for views::VpcSubnet {
             vpc_id: subnet.vpc_id,
             ipv4_block: subnet.ipv4_block.0,
             ipv6_block: subnet.ipv6_block.0,
+            custom_router_id: subnet.custom_router_id,
         }
     }
 }
@@ -111,14 +128,21 @@ pub struct VpcSubnetUpdate {
     pub name: Option<Name>,
     pub description: Option<String>,
     pub time_modified: DateTime<Utc>,
+    pub custom_router_id: Option<Option<Uuid>>,
 }
 
-impl From<params::VpcSubnetUpdate> for VpcSubnetUpdate {
-    fn from(params: params::VpcSubnetUpdate) -> Self {
+impl VpcSubnetUpdate {
+    /// Create the update for a VPC Subnet, given the ID its custom router
+    /// resolves to, if any.
+    pub fn new(
+        params: params::VpcSubnetUpdate,
+        custom_router_id: Option<Uuid>,
+    ) -> Self {
         Self {
             name: params.identity.name.map(Name),
             description: params.identity.description,
             time_modified: Utc::now(),
+            custom_router_id: Some(custom_router_id),
         }
     }
 }
@@ -25,6 +25,7 @@
 //! authentication, but they'd all produce the same [`Context`] struct.
 
 pub mod external;
+pub mod oidc;
 pub mod saga;
 pub mod silos;
 
@@ -176,6 +177,21 @@ impl Context {
         Context::context_for_builtin_user(USER_SERVICE_BALANCER.id)
     }
 
+    /// Returns an authenticated context for work that Nexus carries out in
+    /// the background on behalf of a Silo user (e.g., taking scheduled
+    /// snapshots)
+    ///
+    /// Such work is authorized exactly as if the user had requested it
+    /// directly, so it stops being allowed if the user loses access.
+    pub fn silo_user_background(silo_user_id: Uuid, silo_id: Uuid) -> Context {
+        Context {
+            kind: Kind::Authenticated(Details {
+                actor: Actor::SiloUser { silo_user_id, silo_id },
+            }),
+            schemes_tried: Vec::new(),
+        }
+    }
+
     fn context_for_builtin_user(user_builtin_id: Uuid) -> Context {
         Context {
             kind: Kind::Authenticated(Details {
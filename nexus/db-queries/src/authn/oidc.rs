@@ -0,0 +1,549 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! OpenID Connect support: ID token (JWT) validation against a provider's
+//! published signing keys (JWKS), and the HTTP client used to talk to
+//! providers' token and JWKS endpoints.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a fetched key set is used before it is fetched again
+const JWKS_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Minimum time between fetches of a key set triggered by an ID token signed
+/// with a key id that is not in the cached set (i.e., a key rotation)
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Allowed clock skew when checking the time-based claims of an ID token
+const CLOCK_SKEW_LEEWAY_SECONDS: i64 = 60;
+
+/// Returns a random, URL-safe string suitable for use as an OAuth `state`,
+/// OIDC `nonce`, or PKCE code verifier (RFC 7636 section 4.1).
+pub fn random_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Returns the PKCE `S256` code challenge for the given code verifier
+/// (RFC 7636 section 4.2).
+pub fn pkce_code_challenge(code_verifier: &str) -> String {
+    let digest = openssl::sha::sha256(code_verifier.as_bytes());
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(digest)
+}
+
+/// A single JSON Web Key (RFC 7517), restricted to the fields needed to verify
+/// RSA and EC signatures.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default)]
+    pub kid: Option<String>,
+    #[serde(default)]
+    pub alg: Option<String>,
+    #[serde(default, rename = "use")]
+    pub key_use: Option<String>,
+
+    // RSA public key components
+    #[serde(default)]
+    pub n: Option<String>,
+    #[serde(default)]
+    pub e: Option<String>,
+
+    // EC public key components
+    #[serde(default)]
+    pub crv: Option<String>,
+    #[serde(default)]
+    pub x: Option<String>,
+    #[serde(default)]
+    pub y: Option<String>,
+}
+
+/// A JSON Web Key Set, as served from a provider's `jwks_uri`
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl JwkSet {
+    fn contains_kid(&self, kid: &str) -> bool {
+        self.keys.iter().any(|k| k.kid.as_deref() == Some(kid))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// The parts of an ID token needed before its signature can be checked
+pub struct UnverifiedIdToken<'a> {
+    signing_input: &'a str,
+    signature: Vec<u8>,
+    alg: String,
+    kid: Option<String>,
+    claims: serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'a> UnverifiedIdToken<'a> {
+    /// Splits a compact-serialized JWS into its parts.  Nothing about the
+    /// token is trusted at this point.
+    pub fn parse(token: &'a str) -> Result<Self> {
+        let mut parts = token.splitn(3, '.');
+        let (header, payload, signature) =
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(h), Some(p), Some(s)) => (h, p, s),
+                _ => bail!("ID token is not a compact JWS"),
+            };
+        let signing_input = &token[..header.len() + 1 + payload.len()];
+
+        let header: JwtHeader = serde_json::from_slice(
+            &base64url_decode(header).context("decoding JWT header")?,
+        )
+        .context("parsing JWT header")?;
+        let claims = serde_json::from_slice(
+            &base64url_decode(payload).context("decoding JWT payload")?,
+        )
+        .context("parsing JWT claims")?;
+        let signature =
+            base64url_decode(signature).context("decoding JWT signature")?;
+
+        Ok(UnverifiedIdToken {
+            signing_input,
+            signature,
+            alg: header.alg,
+            kid: header.kid,
+            claims,
+        })
+    }
+
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    /// Checks the token signature against the key set, then checks the
+    /// standard ID token claims (OIDC Core section 3.1.3.7).  On success,
+    /// returns the token's claims.
+    pub fn verify(
+        self,
+        keys: &JwkSet,
+        expected: &IdTokenExpectations<'_>,
+    ) -> Result<serde_json::Map<String, serde_json::Value>> {
+        self.verify_signature(keys)?;
+        expected.check(&self.claims)?;
+        Ok(self.claims)
+    }
+
+    fn verify_signature(&self, keys: &JwkSet) -> Result<()> {
+        let (kty, digest) = match self.alg.as_str() {
+            "RS256" => ("RSA", MessageDigest::sha256()),
+            "RS384" => ("RSA", MessageDigest::sha384()),
+            "RS512" => ("RSA", MessageDigest::sha512()),
+            "ES256" => ("EC", MessageDigest::sha256()),
+            "ES384" => ("EC", MessageDigest::sha384()),
+            // Notably, "none" and the HMAC algorithms are rejected here.
+            alg => bail!("unsupported ID token signing algorithm {}", alg),
+        };
+
+        let candidates = keys.keys.iter().filter(|k| {
+            k.kty == kty
+                && k.key_use.as_deref().map_or(true, |u| u == "sig")
+                && k.alg.as_deref().map_or(true, |a| a == self.alg)
+                && match &self.kid {
+                    Some(kid) => k.kid.as_deref() == Some(kid.as_str()),
+                    None => true,
+                }
+        });
+
+        for jwk in candidates {
+            let verified = match kty {
+                "RSA" => verify_rsa(jwk, digest, self)?,
+                _ => verify_ec(jwk, &self.alg, digest, self)?,
+            };
+            if verified {
+                return Ok(());
+            }
+        }
+
+        Err(anyhow!("ID token signature did not verify with any known key"))
+    }
+}
+
+fn verify_rsa(
+    jwk: &Jwk,
+    digest: MessageDigest,
+    token: &UnverifiedIdToken<'_>,
+) -> Result<bool> {
+    let n = jwk.n.as_deref().ok_or_else(|| anyhow!("RSA JWK missing n"))?;
+    let e = jwk.e.as_deref().ok_or_else(|| anyhow!("RSA JWK missing e"))?;
+    let rsa = Rsa::from_public_components(
+        BigNum::from_slice(&base64url_decode(n)?)?,
+        BigNum::from_slice(&base64url_decode(e)?)?,
+    )?;
+    verify_with(&PKey::from_rsa(rsa)?, digest, token, &token.signature)
+}
+
+fn verify_ec(
+    jwk: &Jwk,
+    alg: &str,
+    digest: MessageDigest,
+    token: &UnverifiedIdToken<'_>,
+) -> Result<bool> {
+    let (nid, coordinate_len) = match (alg, jwk.crv.as_deref()) {
+        ("ES256", Some("P-256")) => (Nid::X9_62_PRIME256V1, 32),
+        ("ES384", Some("P-384")) => (Nid::SECP384R1, 48),
+        _ => return Ok(false),
+    };
+    let x = jwk.x.as_deref().ok_or_else(|| anyhow!("EC JWK missing x"))?;
+    let y = jwk.y.as_deref().ok_or_else(|| anyhow!("EC JWK missing y"))?;
+    let group = EcGroup::from_curve_name(nid)?;
+    let key = EcKey::from_public_key_affine_coordinates(
+        &group,
+        &BigNum::from_slice(&base64url_decode(x)?)?,
+        &BigNum::from_slice(&base64url_decode(y)?)?,
+    )?;
+
+    // JWS carries ECDSA signatures as the fixed-width concatenation r || s
+    // (RFC 7518 section 3.4), whereas OpenSSL expects DER.
+    if token.signature.len() != 2 * coordinate_len {
+        return Ok(false);
+    }
+    let (r, s) = token.signature.split_at(coordinate_len);
+    let der = EcdsaSig::from_private_components(
+        BigNum::from_slice(r)?,
+        BigNum::from_slice(s)?,
+    )?
+    .to_der()?;
+
+    verify_with(&PKey::from_ec_key(key)?, digest, token, &der)
+}
+
+fn verify_with(
+    key: &PKey<Public>,
+    digest: MessageDigest,
+    token: &UnverifiedIdToken<'_>,
+    signature: &[u8],
+) -> Result<bool> {
+    let mut verifier = Verifier::new(digest, key)?;
+    verifier.update(token.signing_input.as_bytes())?;
+    Ok(verifier.verify(signature).unwrap_or(false))
+}
+
+/// Values an ID token's claims must match
+pub struct IdTokenExpectations<'a> {
+    pub issuer: &'a str,
+    pub client_id: &'a str,
+    pub nonce: &'a str,
+    pub now: chrono::DateTime<chrono::Utc>,
+}
+
+impl IdTokenExpectations<'_> {
+    fn check(
+        &self,
+        claims: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<()> {
+        let iss = claims.get("iss").and_then(|v| v.as_str());
+        if iss != Some(self.issuer) {
+            bail!(
+                "ID token issuer {:?} does not match configured issuer {}",
+                iss,
+                self.issuer
+            );
+        }
+
+        let audience_ok = match claims.get("aud") {
+            Some(serde_json::Value::String(aud)) => aud == self.client_id,
+            Some(serde_json::Value::Array(auds)) => {
+                auds.iter().any(|a| a.as_str() == Some(self.client_id))
+            }
+            _ => false,
+        };
+        if !audience_ok {
+            bail!("ID token audience does not include {}", self.client_id);
+        }
+
+        let now = self.now.timestamp();
+        let exp = claims
+            .get("exp")
+            .and_then(|v| v.as_i64())
+            .ok_or_else(|| anyhow!("ID token has no exp claim"))?;
+        if now > exp + CLOCK_SKEW_LEEWAY_SECONDS {
+            bail!("ID token has expired");
+        }
+        if let Some(iat) = claims.get("iat").and_then(|v| v.as_i64()) {
+            if iat > now + CLOCK_SKEW_LEEWAY_SECONDS {
+                bail!("ID token was issued in the future");
+            }
+        }
+        if let Some(nbf) = claims.get("nbf").and_then(|v| v.as_i64()) {
+            if nbf > now + CLOCK_SKEW_LEEWAY_SECONDS {
+                bail!("ID token is not yet valid");
+            }
+        }
+
+        if claims.get("nonce").and_then(|v| v.as_str()) != Some(self.nonce) {
+            bail!("ID token nonce does not match login request");
+        }
+
+        Ok(())
+    }
+}
+
+fn base64url_decode(input: &str) -> Result<Vec<u8>> {
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(input.trim_end_matches('='))?)
+}
+
+struct CachedJwkSet {
+    keys: JwkSet,
+    fetched: Instant,
+}
+
+/// HTTP client for OIDC providers, with a cache of their signing keys keyed
+/// by `jwks_uri`.
+pub struct OidcClient {
+    http: reqwest::Client,
+    jwks: Mutex<HashMap<String, CachedJwkSet>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+impl OidcClient {
+    pub fn new(timeout: Duration) -> Result<Self> {
+        let http = reqwest::ClientBuilder::new()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()
+            .context("building OIDC http client")?;
+        Ok(OidcClient { http, jwks: Mutex::new(HashMap::new()) })
+    }
+
+    /// Exchanges an authorization code at the provider's token endpoint,
+    /// returning the ID token.
+    pub async fn exchange_code(
+        &self,
+        token_endpoint: &str,
+        form: &[(&str, &str)],
+    ) -> Result<String> {
+        let response = self
+            .http
+            .post(token_endpoint)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(form)
+            .send()
+            .await
+            .context("sending token request")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!("token endpoint returned {}: {}", status, body);
+        }
+
+        let token_response: TokenResponse =
+            response.json().await.context("parsing token response")?;
+        token_response
+            .id_token
+            .ok_or_else(|| anyhow!("token response did not include id_token"))
+    }
+
+    /// Returns the key set published at `jwks_uri`, from the cache if
+    /// possible.  If the cached set does not contain `kid`, the set is fetched
+    /// again (at most once per [`JWKS_MIN_REFRESH_INTERVAL`]) in case the
+    /// provider has rotated its keys.
+    pub async fn key_set(
+        &self,
+        jwks_uri: &str,
+        kid: Option<&str>,
+    ) -> Result<JwkSet> {
+        {
+            let cache = self.jwks.lock().unwrap();
+            if let Some(cached) = cache.get(jwks_uri) {
+                let age = cached.fetched.elapsed();
+                let has_kid = kid.map_or(true, |k| cached.keys.contains_kid(k));
+                if age < JWKS_CACHE_TTL
+                    && (has_kid || age < JWKS_MIN_REFRESH_INTERVAL)
+                {
+                    return Ok(cached.keys.clone());
+                }
+            }
+        }
+
+        let keys: JwkSet = self
+            .http
+            .get(jwks_uri)
+            .send()
+            .await
+            .context("fetching JWKS")?
+            .error_for_status()
+            .context("fetching JWKS")?
+            .json()
+            .await
+            .context("parsing JWKS")?;
+
+        self.jwks.lock().unwrap().insert(
+            jwks_uri.to_string(),
+            CachedJwkSet { keys: keys.clone(), fetched: Instant::now() },
+        );
+
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+
+    fn b64(data: &[u8]) -> String {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data)
+    }
+
+    fn sign_rs256(key: &PKey<Private>, kid: &str, claims: &str) -> String {
+        let header =
+            b64(format!(r#"{{"alg":"RS256","typ":"JWT","kid":"{}"}}"#, kid)
+                .as_bytes());
+        let payload = b64(claims.as_bytes());
+        let signing_input = format!("{}.{}", header, payload);
+        let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+        signer.update(signing_input.as_bytes()).unwrap();
+        format!("{}.{}", signing_input, b64(&signer.sign_to_vec().unwrap()))
+    }
+
+    fn rsa_jwk(key: &PKey<Private>, kid: &str) -> Jwk {
+        let rsa = key.rsa().unwrap();
+        Jwk {
+            kty: "RSA".to_string(),
+            kid: Some(kid.to_string()),
+            alg: Some("RS256".to_string()),
+            key_use: Some("sig".to_string()),
+            n: Some(b64(&rsa.n().to_vec())),
+            e: Some(b64(&rsa.e().to_vec())),
+            crv: None,
+            x: None,
+            y: None,
+        }
+    }
+
+    fn expectations(nonce: &str) -> IdTokenExpectations<'_> {
+        IdTokenExpectations {
+            issuer: "https://idp.test",
+            client_id: "nexus",
+            nonce,
+            now: chrono::Utc::now(),
+        }
+    }
+
+    fn claims(nonce: &str, exp_offset: i64) -> String {
+        let now = chrono::Utc::now().timestamp();
+        format!(
+            r#"{{"iss":"https://idp.test","aud":["nexus"],"sub":"user1","nonce":"{}","iat":{},"exp":{}}}"#,
+            nonce,
+            now,
+            now + exp_offset
+        )
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // Test vector from RFC 7636 Appendix B
+        assert_eq!(
+            pkce_code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r7wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_rs256_id_token() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let keys = JwkSet { keys: vec![rsa_jwk(&key, "k1")] };
+
+        let token = sign_rs256(&key, "k1", &claims("n1", 300));
+        let parsed = UnverifiedIdToken::parse(&token).unwrap();
+        assert_eq!(parsed.kid(), Some("k1"));
+        let claims = parsed.verify(&keys, &expectations("n1")).unwrap();
+        assert_eq!(claims["sub"], "user1");
+
+        // wrong nonce
+        let parsed = UnverifiedIdToken::parse(&token).unwrap();
+        assert!(parsed.verify(&keys, &expectations("n2")).is_err());
+
+        // expired
+        let token = sign_rs256(&key, "k1", &claims("n1", -300));
+        let parsed = UnverifiedIdToken::parse(&token).unwrap();
+        assert!(parsed.verify(&keys, &expectations("n1")).is_err());
+
+        // signed by a key that isn't in the set
+        let other = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let token = sign_rs256(&other, "k1", &claims("n1", 300));
+        let parsed = UnverifiedIdToken::parse(&token).unwrap();
+        assert!(parsed.verify(&keys, &expectations("n1")).is_err());
+    }
+
+    #[test]
+    fn test_es256_id_token() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let ec = EcKey::generate(&group).unwrap();
+        let mut ctx = openssl::bn::BigNumContext::new().unwrap();
+        let mut x = BigNum::new().unwrap();
+        let mut y = BigNum::new().unwrap();
+        ec.public_key()
+            .affine_coordinates(&group, &mut x, &mut y, &mut ctx)
+            .unwrap();
+        let keys = JwkSet {
+            keys: vec![Jwk {
+                kty: "EC".to_string(),
+                kid: None,
+                alg: None,
+                key_use: None,
+                n: None,
+                e: None,
+                crv: Some("P-256".to_string()),
+                x: Some(b64(&x.to_vec_padded(32).unwrap())),
+                y: Some(b64(&y.to_vec_padded(32).unwrap())),
+            }],
+        };
+
+        let header = b64(br#"{"alg":"ES256"}"#);
+        let payload = b64(claims("n1", 300).as_bytes());
+        let signing_input = format!("{}.{}", header, payload);
+        let digest = openssl::sha::sha256(signing_input.as_bytes());
+        let sig = EcdsaSig::sign(&digest, &ec).unwrap();
+        let mut raw = sig.r().to_vec_padded(32).unwrap();
+        raw.extend(sig.s().to_vec_padded(32).unwrap());
+        let token = format!("{}.{}", signing_input, b64(&raw));
+
+        let parsed = UnverifiedIdToken::parse(&token).unwrap();
+        parsed.verify(&keys, &expectations("n1")).unwrap();
+    }
+
+    #[test]
+    fn test_unsigned_id_token_rejected() {
+        let header = b64(br#"{"alg":"none"}"#);
+        let payload = b64(claims("n1", 300).as_bytes());
+        let token = format!("{}.{}.", header, payload);
+        let parsed = UnverifiedIdToken::parse(&token).unwrap();
+        assert!(parsed
+            .verify(&JwkSet::default(), &expectations("n1"))
+            .is_err());
+    }
+}
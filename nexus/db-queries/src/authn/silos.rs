@@ -4,6 +4,7 @@
 
 //! Silo related authentication types and functions
 
+use crate::authn::oidc;
 use crate::authz;
 use crate::context::OpContext;
 use crate::db::lookup::LookupPath;
@@ -64,8 +65,58 @@ impl TryFrom<model::SamlIdentityProvider> for SamlIdentityProvider {
     }
 }
 
+#[derive(Deserialize)]
+pub struct OidcIdentityProvider {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub group_claim_name: Option<String>,
+}
+
+impl TryFrom<model::OidcIdentityProvider> for OidcIdentityProvider {
+    type Error = anyhow::Error;
+    fn try_from(
+        model: model::OidcIdentityProvider,
+    ) -> Result<Self, Self::Error> {
+        let provider = OidcIdentityProvider {
+            issuer: model.issuer,
+            client_id: model.client_id,
+            client_secret: model.client_secret,
+            authorization_endpoint: model.authorization_endpoint,
+            token_endpoint: model.token_endpoint,
+            jwks_uri: model.jwks_uri,
+            redirect_uri: model.redirect_uri,
+            scopes: model.scopes.split_whitespace().map(String::from).collect(),
+            group_claim_name: model.group_claim_name,
+        };
+
+        // check that every endpoint is a valid absolute url
+        for (name, url) in [
+            ("authorization_endpoint", &provider.authorization_endpoint),
+            ("token_endpoint", &provider.token_endpoint),
+            ("jwks_uri", &provider.jwks_uri),
+            ("redirect_uri", &provider.redirect_uri),
+        ] {
+            reqwest::Url::parse(url)
+                .map_err(|e| anyhow!("{} is not a valid url: {}", name, e))?;
+        }
+
+        if !provider.scopes.iter().any(|s| s == "openid") {
+            return Err(anyhow!("scopes must include \"openid\""));
+        }
+
+        Ok(provider)
+    }
+}
+
 pub enum IdentityProviderType {
     Saml(SamlIdentityProvider),
+    Oidc(OidcIdentityProvider),
 }
 
 impl IdentityProviderType {
@@ -115,6 +166,31 @@ impl IdentityProviderType {
 
                 Ok((authz_silo, db_silo, saml_identity_provider))
             }
+
+            model::IdentityProviderType::Oidc => {
+                let (.., oidc_identity_provider) =
+                    LookupPath::new(opctx, datastore)
+                        .silo_name(silo_name)
+                        .oidc_identity_provider_name(provider_name)
+                        .fetch()
+                        .await?;
+
+                let oidc_identity_provider = IdentityProviderType::Oidc(
+                    oidc_identity_provider.try_into()
+                        .map_err(|e: anyhow::Error|
+                            // As above, this was validated before it went
+                            // into the DB.
+                            omicron_common::api::external::Error::internal_error(
+                                &format!(
+                                    "oidc_identity_provider.try_into() failed! {}",
+                                    &e.to_string()
+                                )
+                            )
+                        )?
+                    );
+
+                Ok((authz_silo, db_silo, oidc_identity_provider))
+            }
         }
     }
 }
@@ -437,6 +513,149 @@ impl SamlIdentityProvider {
     }
 }
 
+impl OidcIdentityProvider {
+    /// Returns the URL of the provider's authorization endpoint that begins an
+    /// authorization code flow with PKCE (RFC 7636).
+    pub fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String> {
+        let scope = self.scopes.join(" ");
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("scope", scope.as_str()),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", code_challenge),
+            ("code_challenge_method", "S256"),
+        ])?;
+
+        let mut url = reqwest::Url::parse(&self.authorization_endpoint)?;
+        // Preserve any query parameters already in the configured endpoint.
+        let query = match url.query() {
+            Some(existing) if !existing.is_empty() => {
+                format!("{}&{}", existing, query)
+            }
+            _ => query,
+        };
+        url.set_query(Some(&query));
+
+        Ok(url.to_string())
+    }
+
+    /// Exchanges an authorization code for an ID token, validates that token
+    /// against the provider's signing keys and the login request's nonce,
+    /// and returns the subject it authenticates.
+    pub async fn authenticated_subject(
+        &self,
+        client: &oidc::OidcClient,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<AuthenticatedSubject, HttpError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("client_id", self.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+
+        let id_token = client
+            .exchange_code(&self.token_endpoint, &form)
+            .await
+            .map_err(|e| {
+                HttpError::for_bad_request(
+                    None,
+                    format!("authorization code exchange failed! {:#}", e),
+                )
+            })?;
+
+        let unverified =
+            oidc::UnverifiedIdToken::parse(&id_token).map_err(|e| {
+                HttpError::for_bad_request(
+                    None,
+                    format!("could not parse ID token! {:#}", e),
+                )
+            })?;
+
+        let keys = client
+            .key_set(&self.jwks_uri, unverified.kid())
+            .await
+            .map_err(|e| {
+                HttpError::for_unavail(
+                    None,
+                    format!("could not fetch provider JWKS! {:#}", e),
+                )
+            })?;
+
+        let claims = unverified
+            .verify(
+                &keys,
+                &oidc::IdTokenExpectations {
+                    issuer: &self.issuer,
+                    client_id: &self.client_id,
+                    nonce,
+                    now: chrono::Utc::now(),
+                },
+            )
+            .map_err(|e| {
+                HttpError::for_bad_request(
+                    None,
+                    format!("ID token is not valid! {:#}", e),
+                )
+            })?;
+
+        let external_id = claims
+            .get("sub")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| {
+                HttpError::for_bad_request(
+                    None,
+                    "ID token has no subject!".into(),
+                )
+            })?
+            .to_string();
+
+        // Extract group membership claims.  Providers variously send these
+        // as an array of group names or as a comma separated string.
+        let mut groups = vec![];
+
+        if let Some(group_claim_name) = &self.group_claim_name {
+            let values: Vec<&str> = match claims.get(group_claim_name) {
+                Some(serde_json::Value::Array(values)) => {
+                    values.iter().filter_map(|v| v.as_str()).collect()
+                }
+                Some(serde_json::Value::String(value)) => {
+                    value.split(',').collect()
+                }
+                _ => vec![],
+            };
+
+            for group in values {
+                // Trim whitespace
+                let group = group.trim().to_string();
+
+                // Skip empty groups
+                if group.is_empty() {
+                    continue;
+                }
+
+                groups.push(group);
+            }
+        }
+
+        Ok(AuthenticatedSubject { external_id, groups })
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SamlLoginPost {
     #[serde(rename = "SAMLResponse")]
@@ -123,6 +123,9 @@ pub fn make_omicron_oso(log: &slog::Logger) -> Result<OsoInit, anyhow::Error> {
         Disk::init(),
         Snapshot::init(),
         ProjectImage::init(),
+        AffinityGroup::init(),
+        SnapshotSchedule::init(),
+        FloatingIp::init(),
         Instance::init(),
         IpPool::init(),
         InstanceNetworkInterface::init(),
@@ -130,6 +133,7 @@ pub fn make_omicron_oso(log: &slog::Logger) -> Result<OsoInit, anyhow::Error> {
         VpcRouter::init(),
         RouterRoute::init(),
         VpcSubnet::init(),
+        VpcPeering::init(),
         // Silo-level resources
         Image::init(),
         SiloImage::init(),
@@ -142,11 +146,14 @@ pub fn make_omicron_oso(log: &slog::Logger) -> Result<OsoInit, anyhow::Error> {
         Rack::init(),
         RoleBuiltin::init(),
         SshKey::init(),
+        AccessToken::init(),
         Silo::init(),
         SiloUser::init(),
         SiloGroup::init(),
         IdentityProvider::init(),
         SamlIdentityProvider::init(),
+        OidcIdentityProvider::init(),
+        ScimClientToken::init(),
         Sled::init(),
         UpdateArtifact::init(),
         UserBuiltin::init(),
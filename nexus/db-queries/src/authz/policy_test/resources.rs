@@ -174,6 +174,17 @@ async fn make_silo(
         idp_id,
         LookupType::ByName(format!("{}-saml-identity-provider", silo_name)),
     ));
+    builder.new_resource(authz::OidcIdentityProvider::new(
+        silo.clone(),
+        idp_id,
+        LookupType::ByName(format!("{}-oidc-identity-provider", silo_name)),
+    ));
+    let scim_client_token_id = Uuid::new_v4();
+    builder.new_resource(authz::ScimClientToken::new(
+        silo.clone(),
+        scim_client_token_id,
+        LookupType::ByName(format!("{}-scim-client-token", silo_name)),
+    ));
 
     builder.new_resource(authz::SiloUserList::new(silo.clone()));
     let silo_user_id = Uuid::new_v4();
@@ -185,10 +196,16 @@ async fn make_silo(
     builder.new_resource(silo_user.clone());
     let ssh_key_id = Uuid::new_v4();
     builder.new_resource(authz::SshKey::new(
-        silo_user,
+        silo_user.clone(),
         ssh_key_id,
         LookupType::ByName(format!("{}-user-ssh-key", silo_name)),
     ));
+    let access_token_id = Uuid::new_v4();
+    builder.new_resource(authz::AccessToken::new(
+        silo_user.clone(),
+        access_token_id,
+        LookupType::ByName(format!("{}-user-access-token", silo_name)),
+    ));
     let silo_group_id = Uuid::new_v4();
     builder.new_resource(authz::SiloGroup::new(
         silo.clone(),
@@ -266,10 +283,15 @@ async fn make_project(
     builder.new_resource(vpc1.clone());
     // Test a resource nested two levels below Project
     builder.new_resource(authz::VpcSubnet::new(
-        vpc1,
+        vpc1.clone(),
         Uuid::new_v4(),
         LookupType::ByName(format!("{}-subnet1", vpc1_name)),
     ));
+    builder.new_resource(authz::VpcPeering::new(
+        vpc1,
+        Uuid::new_v4(),
+        LookupType::ByName(format!("{}-peering1", vpc1_name)),
+    ));
 
     builder.new_resource(authz::Snapshot::new(
         project.clone(),
@@ -283,6 +305,24 @@ async fn make_project(
         Uuid::new_v4(),
         LookupType::ByName(image_name),
     ));
+
+    builder.new_resource(authz::AffinityGroup::new(
+        project.clone(),
+        Uuid::new_v4(),
+        LookupType::ByName(format!("{}-affinity-group1", project_name)),
+    ));
+
+    builder.new_resource(authz::SnapshotSchedule::new(
+        project.clone(),
+        Uuid::new_v4(),
+        LookupType::ByName(format!("{}-snapshot-schedule1", project_name)),
+    ));
+
+    builder.new_resource(authz::FloatingIp::new(
+        project.clone(),
+        Uuid::new_v4(),
+        LookupType::ByName(format!("{}-floating-ip1", project_name)),
+    ));
 }
 
 /// Returns the set of authz classes exempted from the coverage test
@@ -0,0 +1,136 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! [`DataStore`] methods related to [`AccessToken`]s.
+
+use super::DataStore;
+use crate::authz;
+use crate::context::OpContext;
+use crate::db;
+use crate::db::error::public_error_from_diesel_pool;
+use crate::db::error::ErrorHandler;
+use crate::db::identity::Resource;
+use crate::db::model::AccessToken;
+use crate::db::model::Name;
+use crate::db::pagination::paginated;
+use crate::db::update_and_check::UpdateAndCheck;
+use async_bb8_diesel::AsyncRunQueryDsl;
+use chrono::Utc;
+use diesel::prelude::*;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::LookupType;
+use omicron_common::api::external::ResourceType;
+use ref_cast::RefCast;
+
+impl DataStore {
+    pub async fn access_tokens_list(
+        &self,
+        opctx: &OpContext,
+        authz_user: &authz::SiloUser,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<AccessToken> {
+        opctx.authorize(authz::Action::ListChildren, authz_user).await?;
+
+        use db::schema::access_token::dsl;
+        match pagparams {
+            PaginatedBy::Id(pagparams) => {
+                paginated(dsl::access_token, dsl::id, &pagparams)
+            }
+            PaginatedBy::Name(pagparams) => paginated(
+                dsl::access_token,
+                dsl::name,
+                &pagparams.map_name(|n| Name::ref_cast(n)),
+            ),
+        }
+        .filter(dsl::silo_user_id.eq(authz_user.id()))
+        .filter(dsl::time_deleted.is_null())
+        .select(AccessToken::as_select())
+        .load_async(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Create a new access token for a user.
+    pub async fn access_token_create(
+        &self,
+        opctx: &OpContext,
+        authz_user: &authz::SiloUser,
+        access_token: AccessToken,
+    ) -> CreateResult<AccessToken> {
+        assert_eq!(authz_user.id(), access_token.silo_user_id);
+        opctx.authorize(authz::Action::CreateChild, authz_user).await?;
+        let name = access_token.name().to_string();
+
+        use db::schema::access_token::dsl;
+        diesel::insert_into(dsl::access_token)
+            .values(access_token)
+            .returning(AccessToken::as_returning())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::Conflict(ResourceType::AccessToken, &name),
+                )
+            })
+    }
+
+    /// Delete (revoke) an existing access token.
+    pub async fn access_token_delete(
+        &self,
+        opctx: &OpContext,
+        authz_access_token: &authz::AccessToken,
+    ) -> DeleteResult {
+        opctx.authorize(authz::Action::Delete, authz_access_token).await?;
+
+        use db::schema::access_token::dsl;
+        diesel::update(dsl::access_token)
+            .filter(dsl::id.eq(authz_access_token.id()))
+            .filter(dsl::time_deleted.is_null())
+            .set(dsl::time_deleted.eq(Utc::now()))
+            .check_if_exists::<AccessToken>(authz_access_token.id())
+            .execute_and_check(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_access_token),
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Look up an access token that has not been deleted by the token itself.
+    /// Note: like `device_access_token_fetch`, this lookup is by a unique
+    /// index rather than by primary key or name, and so it does not fit the
+    /// usual lookup machinery and does not include any authz checks.  The
+    /// token is a high-entropy random value and so should not be guessable
+    /// by an attacker.
+    pub async fn access_token_fetch_by_token(
+        &self,
+        opctx: &OpContext,
+        token: String,
+    ) -> LookupResult<AccessToken> {
+        use db::schema::access_token::dsl;
+        dsl::access_token
+            .filter(dsl::token.eq(token))
+            .filter(dsl::time_deleted.is_null())
+            .select(AccessToken::as_select())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByLookup(
+                        ResourceType::AccessToken,
+                        LookupType::ByCompositeId("token".to_string()),
+                    ),
+                )
+            })
+    }
+}
@@ -0,0 +1,330 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! [`DataStore`] methods on [`AffinityGroup`]s.
+
+use super::DataStore;
+use crate::authz;
+use crate::authz::ApiResource;
+use crate::context::OpContext;
+use crate::db;
+use crate::db::collection_insert::AsyncInsertError;
+use crate::db::collection_insert::DatastoreCollection;
+use crate::db::error::public_error_from_diesel_pool;
+use crate::db::error::ErrorHandler;
+use crate::db::error::TransactionError;
+use crate::db::identity::Resource;
+use crate::db::model::AffinityGroup;
+use crate::db::model::AffinityGroupInstanceMembership;
+use crate::db::model::AffinityGroupKind;
+use crate::db::model::AffinityGroupUpdate;
+use crate::db::model::AffinityPolicy;
+use crate::db::model::InstanceState as DbInstanceState;
+use crate::db::model::Name;
+use crate::db::model::Project;
+use crate::db::pagination::paginated;
+use crate::db::pool::DbConnection;
+use async_bb8_diesel::AsyncConnection;
+use async_bb8_diesel::AsyncRunQueryDsl;
+use chrono::Utc;
+use diesel::prelude::*;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState as ApiInstanceState;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::ResourceType;
+use omicron_common::api::external::UpdateResult;
+use ref_cast::RefCast;
+use uuid::Uuid;
+
+impl DataStore {
+    pub async fn affinity_group_create(
+        &self,
+        opctx: &OpContext,
+        authz_project: &authz::Project,
+        group: AffinityGroup,
+    ) -> CreateResult<AffinityGroup> {
+        use db::schema::affinity_group::dsl;
+
+        opctx.authorize(authz::Action::CreateChild, authz_project).await?;
+
+        let name = group.name().clone();
+        let project_id = group.project_id;
+
+        Project::insert_resource(
+            project_id,
+            diesel::insert_into(dsl::affinity_group).values(group),
+        )
+        .insert_and_get_result_async(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| match e {
+            AsyncInsertError::CollectionNotFound => authz_project.not_found(),
+            AsyncInsertError::DatabaseError(e) => {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::Conflict(
+                        ResourceType::AffinityGroup,
+                        name.as_str(),
+                    ),
+                )
+            }
+        })
+    }
+
+    pub async fn affinity_group_list(
+        &self,
+        opctx: &OpContext,
+        authz_project: &authz::Project,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<AffinityGroup> {
+        opctx.authorize(authz::Action::ListChildren, authz_project).await?;
+
+        use db::schema::affinity_group::dsl;
+        match pagparams {
+            PaginatedBy::Id(pagparams) => {
+                paginated(dsl::affinity_group, dsl::id, &pagparams)
+            }
+            PaginatedBy::Name(pagparams) => paginated(
+                dsl::affinity_group,
+                dsl::name,
+                &pagparams.map_name(|n| Name::ref_cast(n)),
+            ),
+        }
+        .filter(dsl::time_deleted.is_null())
+        .filter(dsl::project_id.eq(authz_project.id()))
+        .select(AffinityGroup::as_select())
+        .load_async::<AffinityGroup>(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    pub async fn affinity_group_update(
+        &self,
+        opctx: &OpContext,
+        authz_group: &authz::AffinityGroup,
+        updates: AffinityGroupUpdate,
+    ) -> UpdateResult<AffinityGroup> {
+        opctx.authorize(authz::Action::Modify, authz_group).await?;
+
+        use db::schema::affinity_group::dsl;
+        diesel::update(dsl::affinity_group)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(authz_group.id()))
+            .set(updates)
+            .returning(AffinityGroup::as_returning())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_group),
+                )
+            })
+    }
+
+    /// Deletes an affinity group, along with the memberships of any instances
+    /// that are still in it.
+    pub async fn affinity_group_delete(
+        &self,
+        opctx: &OpContext,
+        authz_group: &authz::AffinityGroup,
+    ) -> DeleteResult {
+        opctx.authorize(authz::Action::Delete, authz_group).await?;
+
+        use db::schema::affinity_group::dsl;
+        use db::schema::affinity_group_instance_membership::dsl as member_dsl;
+
+        type TxnError = TransactionError<Error>;
+        let group_id = authz_group.id();
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                let updated = diesel::update(dsl::affinity_group)
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(dsl::id.eq(group_id))
+                    .set(dsl::time_deleted.eq(Utc::now()))
+                    .execute_async(&conn)
+                    .await?;
+                if updated == 0 {
+                    return Err(TxnError::CustomError(authz_group.not_found()));
+                }
+
+                diesel::delete(member_dsl::affinity_group_instance_membership)
+                    .filter(member_dsl::group_id.eq(group_id))
+                    .execute_async(&conn)
+                    .await?;
+                Ok(())
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(e) => e,
+                TxnError::Pool(e) => {
+                    public_error_from_diesel_pool(e, ErrorHandler::Server)
+                }
+            })
+    }
+
+    /// Adds an instance to an affinity group.
+    ///
+    /// This is idempotent: adding an instance that is already a member
+    /// succeeds without changing anything.
+    pub async fn affinity_group_member_add(
+        &self,
+        opctx: &OpContext,
+        authz_group: &authz::AffinityGroup,
+        instance_id: Uuid,
+    ) -> CreateResult<()> {
+        opctx.authorize(authz::Action::Modify, authz_group).await?;
+
+        use db::schema::affinity_group_instance_membership::dsl;
+        diesel::insert_into(dsl::affinity_group_instance_membership)
+            .values(AffinityGroupInstanceMembership::new(
+                authz_group.id(),
+                instance_id,
+            ))
+            .on_conflict((dsl::group_id, dsl::instance_id))
+            .do_nothing()
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        Ok(())
+    }
+
+    /// Removes an instance from an affinity group, if it is a member.
+    pub async fn affinity_group_member_remove(
+        &self,
+        opctx: &OpContext,
+        authz_group: &authz::AffinityGroup,
+        instance_id: Uuid,
+    ) -> DeleteResult {
+        opctx.authorize(authz::Action::Modify, authz_group).await?;
+
+        use db::schema::affinity_group_instance_membership::dsl;
+        diesel::delete(dsl::affinity_group_instance_membership)
+            .filter(dsl::group_id.eq(authz_group.id()))
+            .filter(dsl::instance_id.eq(instance_id))
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        Ok(())
+    }
+
+    /// Returns the IDs of the affinity groups of which an instance is a
+    /// member.
+    pub async fn instance_affinity_group_ids(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+    ) -> ListResultVec<Uuid> {
+        opctx.authorize(authz::Action::Read, authz_instance).await?;
+
+        use db::schema::affinity_group::dsl as group_dsl;
+        use db::schema::affinity_group_instance_membership::dsl as member_dsl;
+        member_dsl::affinity_group_instance_membership
+            .filter(member_dsl::instance_id.eq(authz_instance.id()))
+            .filter(
+                member_dsl::group_id.eq_any(
+                    group_dsl::affinity_group
+                        .filter(group_dsl::time_deleted.is_null())
+                        .select(group_dsl::id),
+                ),
+            )
+            .select(member_dsl::group_id)
+            .load_async::<Uuid>(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Returns the kind and policy of each of the given affinity groups that
+    /// still exists, along with the sleds on which its members other than
+    /// `instance_id` are placed.
+    ///
+    /// Members only count while they're placed somewhere: members that are
+    /// being created count on the sleds reserved for them, and existing
+    /// members count on the sleds of their active and migration target
+    /// Propolises, unless they're stopped, failed, or destroyed.  A stopped
+    /// member is placed anew when it's next started, so the sled it last ran
+    /// on doesn't constrain anyone.
+    ///
+    /// This is meant to be called in the same transaction that reserves a
+    /// sled for `instance_id`, so that members placed concurrently are seen.
+    pub(crate) async fn affinity_group_placements_on_connection<ConnErr>(
+        &self,
+        conn: &(impl async_bb8_diesel::AsyncConnection<DbConnection, ConnErr>
+              + Sync),
+        group_ids: &[Uuid],
+        instance_id: Option<Uuid>,
+    ) -> Result<Vec<(AffinityGroupKind, AffinityPolicy, Vec<Uuid>)>, ConnErr>
+    where
+        ConnErr: From<diesel::result::Error> + Send + 'static,
+    {
+        use db::schema::affinity_group::dsl as group_dsl;
+        use db::schema::affinity_group_instance_membership::dsl as member_dsl;
+        use db::schema::instance::dsl as instance_dsl;
+        use db::schema::sled_resource::dsl as resource_dsl;
+
+        let groups = group_dsl::affinity_group
+            .filter(group_dsl::time_deleted.is_null())
+            .filter(group_dsl::id.eq_any(group_ids.to_vec()))
+            .select((group_dsl::id, group_dsl::kind, group_dsl::policy))
+            .load_async::<(Uuid, AffinityGroupKind, AffinityPolicy)>(conn)
+            .await?;
+
+        let placed_nowhere = vec![
+            DbInstanceState::new(ApiInstanceState::Stopped),
+            DbInstanceState::new(ApiInstanceState::Failed),
+            DbInstanceState::new(ApiInstanceState::Destroyed),
+        ];
+        let mut placements = Vec::with_capacity(groups.len());
+        for (group_id, kind, policy) in groups {
+            let mut query = resource_dsl::sled_resource
+                .left_join(instance_dsl::instance.on(
+                    instance_dsl::id.nullable().eq(resource_dsl::instance_id),
+                ))
+                .filter(
+                    resource_dsl::instance_id.assume_not_null().eq_any(
+                        member_dsl::affinity_group_instance_membership
+                            .filter(member_dsl::group_id.eq(group_id))
+                            .select(member_dsl::instance_id),
+                    ),
+                )
+                .filter(
+                    // A member that's still being created has no instance
+                    // record yet.
+                    instance_dsl::id.nullable().is_null().or(
+                        instance_dsl::time_deleted
+                            .is_null()
+                            .and(
+                                instance_dsl::state
+                                    .ne_all(placed_nowhere.clone()),
+                            )
+                            .and(
+                                resource_dsl::id
+                                    .nullable()
+                                    .eq(instance_dsl::active_propolis_id
+                                        .nullable())
+                                    .or(resource_dsl::id
+                                        .nullable()
+                                        .eq(instance_dsl::target_propolis_id)),
+                            ),
+                    ),
+                )
+                .select(resource_dsl::sled_id)
+                .distinct()
+                .into_boxed();
+            if let Some(instance_id) = instance_id {
+                query = query.filter(resource_dsl::instance_id.ne(instance_id));
+            }
+            let sleds = query.load_async::<Uuid>(conn).await?;
+            placements.push((kind, policy, sleds));
+        }
+        Ok(placements)
+    }
+}
@@ -0,0 +1,364 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! [`DataStore`] methods on the audit log.
+
+use super::DataStore;
+use crate::authz;
+use crate::context::OpContext;
+use crate::db;
+use crate::db::error::public_error_from_diesel_pool;
+use crate::db::error::ErrorHandler;
+use crate::db::error::TransactionError;
+use crate::db::fixed_data::FLEET_ID;
+use crate::db::model::AuditLogChain;
+use crate::db::model::AuditLogEntry;
+use crate::db::model::AuditLogEntryInit;
+use crate::db::pagination::paginated;
+use async_bb8_diesel::AsyncConnection;
+use async_bb8_diesel::AsyncRunQueryDsl;
+use chrono::DateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+use nexus_types::external_api::params;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DataPageParams;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::UpdateResult;
+
+diesel::sql_function!(
+    fn nextval(name: diesel::sql_types::Text) -> diesel::sql_types::Int8
+);
+
+impl DataStore {
+    /// Records a pending entry in the audit log for a request that's about to
+    /// be handled
+    ///
+    /// The entry is given the next position in the log as a whole, but isn't
+    /// appended to its chain until it's completed with
+    /// [`DataStore::audit_log_complete()`].  Requests made by Silo users go to
+    /// their Silo's chain, and all others to the fleet's.
+    pub async fn audit_log_begin(
+        &self,
+        opctx: &OpContext,
+        init: AuditLogEntryInit,
+    ) -> CreateResult<AuditLogEntry> {
+        opctx.authorize(authz::Action::CreateChild, &authz::FLEET).await?;
+
+        use db::schema::audit_log::dsl;
+        let pool = self.pool_authorized(opctx).await?;
+        let seq = diesel::select(nextval("omicron.public.audit_log_seq"))
+            .get_result_async::<i64>(pool)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        let chain_id = init.actor_silo_id.unwrap_or(*FLEET_ID);
+        let entry = AuditLogEntry::new(init, seq, chain_id);
+        diesel::insert_into(dsl::audit_log)
+            .values(entry.clone())
+            .execute_async(pool)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        Ok(entry)
+    }
+
+    /// Completes a pending entry in the audit log with the result of its
+    /// request, appending it to the end of its chain
+    ///
+    /// The head of the chain is locked for the duration of the append, so
+    /// concurrent appends to the same chain wait for each other rather than
+    /// racing for the next position.
+    pub async fn audit_log_complete(
+        &self,
+        opctx: &OpContext,
+        mut entry: AuditLogEntry,
+        time_completed: DateTime<Utc>,
+        result_code: u16,
+    ) -> UpdateResult<AuditLogEntry> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+
+        use db::schema::audit_log::dsl;
+        use db::schema::audit_log_chain::dsl as chain_dsl;
+        type TxnError = TransactionError<Error>;
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                diesel::insert_into(chain_dsl::audit_log_chain)
+                    .values(AuditLogChain::new(entry.chain_id))
+                    .on_conflict(chain_dsl::id)
+                    .do_nothing()
+                    .execute_async(&conn)
+                    .await?;
+                let head = chain_dsl::audit_log_chain
+                    .filter(chain_dsl::id.eq(entry.chain_id))
+                    .select(AuditLogChain::as_select())
+                    .for_update()
+                    .get_result_async::<AuditLogChain>(&conn)
+                    .await?;
+
+                entry.complete(time_completed, result_code, &head);
+                let updated = diesel::update(dsl::audit_log)
+                    .filter(dsl::id.eq(entry.id))
+                    .filter(dsl::hash.is_null())
+                    .set((
+                        dsl::time_completed.eq(entry.time_completed),
+                        dsl::result_code.eq(entry.result_code),
+                        dsl::chain_seq.eq(entry.chain_seq),
+                        dsl::prev_hash.eq(entry.prev_hash.clone()),
+                        dsl::hash.eq(entry.hash.clone()),
+                    ))
+                    .execute_async(&conn)
+                    .await?;
+                if updated != 1 {
+                    return Err(TxnError::CustomError(Error::internal_error(
+                        &format!(
+                            "audit log entry {} is missing or already \
+                            completed",
+                            entry.id
+                        ),
+                    )));
+                }
+
+                diesel::update(chain_dsl::audit_log_chain)
+                    .filter(chain_dsl::id.eq(entry.chain_id))
+                    .set((
+                        chain_dsl::seq.eq(head.seq + 1),
+                        chain_dsl::hash.eq(entry.hash.clone()),
+                    ))
+                    .execute_async(&conn)
+                    .await?;
+                Ok(entry)
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(e) => e,
+                TxnError::Pool(e) => {
+                    public_error_from_diesel_pool(e, ErrorHandler::Server)
+                }
+            })
+    }
+
+    /// Lists entries in the audit log, ordered by their position in the log
+    pub async fn audit_log_list(
+        &self,
+        opctx: &OpContext,
+        filter: &params::AuditLogSelector,
+        pagparams: &DataPageParams<'_, i64>,
+    ) -> ListResultVec<AuditLogEntry> {
+        opctx.authorize(authz::Action::ListChildren, &authz::FLEET).await?;
+
+        use db::schema::audit_log::dsl;
+        let mut query = paginated(dsl::audit_log, dsl::seq, pagparams);
+        if let Some(start_time) = filter.start_time {
+            query = query.filter(dsl::time_started.ge(start_time));
+        }
+        if let Some(end_time) = filter.end_time {
+            query = query.filter(dsl::time_started.lt(end_time));
+        }
+        if let Some(actor_id) = filter.actor_id {
+            query = query.filter(dsl::actor_id.eq(actor_id));
+        }
+        query
+            .select(AuditLogEntry::as_select())
+            .load_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Removes entries for requests which started before `cutoff` from the
+    /// audit log, returning the number of entries removed
+    ///
+    /// Each chain's latest hash is kept in its head, so entries appended
+    /// afterwards continue the existing chains rather than starting new ones.
+    pub async fn audit_log_prune(
+        &self,
+        opctx: &OpContext,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize, Error> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+
+        use db::schema::audit_log::dsl;
+        diesel::delete(dsl::audit_log)
+            .filter(dsl::time_started.lt(cutoff))
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db::datastore::datastore_test;
+    use crate::db::fixed_data::FLEET_ID;
+    use crate::db::model::AuditLogEntryInit;
+    use crate::db::model::AUDIT_LOG_GENESIS_HASH;
+    use chrono::Utc;
+    use dropshot::PaginationOrder;
+    use nexus_test_utils::db::test_setup_database;
+    use nexus_types::external_api::params;
+    use omicron_common::api::external::DataPageParams;
+    use omicron_test_utils::dev;
+    use std::collections::BTreeMap;
+    use std::num::NonZeroU32;
+    use uuid::Uuid;
+
+    fn test_entry(
+        actor_id: Option<Uuid>,
+        actor_silo_id: Option<Uuid>,
+    ) -> AuditLogEntryInit {
+        AuditLogEntryInit {
+            request_id: Uuid::new_v4().to_string(),
+            time_started: Utc::now(),
+            http_method: String::from("POST"),
+            request_uri: String::from("/v1/projects"),
+            actor_id,
+            actor_silo_id,
+            resource_ids: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_chain() {
+        let logctx = dev::test_setup_log("test_audit_log_chain");
+        let mut db = test_setup_database(&logctx.log).await;
+        let (opctx, datastore) = datastore_test(&logctx, &db).await;
+
+        // Record a bunch of requests concurrently, half of them from a user in
+        // some Silo and half from nobody in particular.
+        let actor_id = Uuid::new_v4();
+        let silo_id = Uuid::new_v4();
+        let appends = (0..10).map(|i| {
+            let init = if i % 2 == 0 {
+                test_entry(Some(actor_id), Some(silo_id))
+            } else {
+                test_entry(None, None)
+            };
+            let datastore = &datastore;
+            let opctx = &opctx;
+            async move {
+                let entry = datastore.audit_log_begin(opctx, init).await?;
+                assert!(entry.hash.is_none());
+                datastore
+                    .audit_log_complete(opctx, entry, Utc::now(), 201)
+                    .await
+            }
+        });
+        for result in futures::future::join_all(appends).await {
+            result.expect("failed to append to audit log");
+        }
+
+        // A pending entry is listed, but isn't part of any chain yet.
+        let pending = datastore
+            .audit_log_begin(&opctx, test_entry(None, None))
+            .await
+            .unwrap();
+
+        let pagparams = DataPageParams {
+            marker: None,
+            direction: PaginationOrder::Ascending,
+            limit: NonZeroU32::new(100).unwrap(),
+        };
+        let entries = datastore
+            .audit_log_list(
+                &opctx,
+                &params::AuditLogSelector::default(),
+                &pagparams,
+            )
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 11);
+        assert!(entries.windows(2).all(|w| w[0].seq < w[1].seq));
+        let last = entries.last().unwrap();
+        assert_eq!(last.id, pending.id);
+        assert!(last.result_code.is_none());
+        assert!(last.chain_seq.is_none());
+
+        // The completed entries should form an unbroken chain per Silo, in
+        // the order in which they were completed.
+        let mut chains = BTreeMap::new();
+        for entry in entries.iter().filter(|e| e.hash.is_some()) {
+            assert!(entry.hash_is_valid());
+            assert_eq!(
+                entry.chain_id,
+                entry.actor_silo_id.unwrap_or(*FLEET_ID)
+            );
+            chains.entry(entry.chain_id).or_insert_with(Vec::new).push(entry);
+        }
+        assert_eq!(chains.len(), 2);
+        let mut heads = BTreeMap::new();
+        for (chain_id, mut chain) in chains {
+            assert_eq!(chain.len(), 5);
+            chain.sort_by_key(|e| e.chain_seq);
+            let mut prev_hash = AUDIT_LOG_GENESIS_HASH.to_string();
+            for (i, entry) in chain.iter().enumerate() {
+                assert_eq!(
+                    entry.chain_seq,
+                    Some(i64::try_from(i).unwrap() + 1)
+                );
+                assert_eq!(entry.prev_hash.as_ref(), Some(&prev_hash));
+                prev_hash = entry.hash.clone().unwrap();
+            }
+            heads.insert(chain_id, prev_hash);
+        }
+
+        // Filter by actor.
+        let filtered = datastore
+            .audit_log_list(
+                &opctx,
+                &params::AuditLogSelector {
+                    actor_id: Some(actor_id),
+                    ..Default::default()
+                },
+                &pagparams,
+            )
+            .await
+            .unwrap();
+        assert_eq!(filtered.len(), 5);
+        assert!(filtered.iter().all(|e| e.actor_id == Some(actor_id)));
+
+        // Filter by time.
+        let earliest = entries.iter().map(|e| e.time_started).min().unwrap();
+        let filtered = datastore
+            .audit_log_list(
+                &opctx,
+                &params::AuditLogSelector {
+                    end_time: Some(earliest),
+                    ..Default::default()
+                },
+                &pagparams,
+            )
+            .await
+            .unwrap();
+        assert!(filtered.is_empty());
+
+        // Pruning removes everything, including the pending entry, but new
+        // entries continue the chains where they left off.
+        let pruned =
+            datastore.audit_log_prune(&opctx, Utc::now()).await.unwrap();
+        assert_eq!(pruned, 11);
+        datastore
+            .audit_log_complete(&opctx, pending, Utc::now(), 201)
+            .await
+            .expect_err("completed an entry that was pruned");
+        for silo_id in [Some(silo_id), None] {
+            let entry = datastore
+                .audit_log_begin(&opctx, test_entry(None, silo_id))
+                .await
+                .unwrap();
+            let entry = datastore
+                .audit_log_complete(&opctx, entry, Utc::now(), 204)
+                .await
+                .unwrap();
+            assert_eq!(entry.chain_seq, Some(6));
+            assert_eq!(entry.prev_hash.as_ref(), heads.get(&entry.chain_id));
+        }
+
+        db.cleanup().await.unwrap();
+        logctx.cleanup_successful();
+    }
+}
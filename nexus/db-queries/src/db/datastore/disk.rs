@@ -19,6 +19,7 @@ use crate::db::error::public_error_from_diesel_pool;
 use crate::db::error::ErrorHandler;
 use crate::db::identity::Resource;
 use crate::db::lookup::LookupPath;
+use crate::db::model::labels_match;
 use crate::db::model::Disk;
 use crate::db::model::DiskRuntimeState;
 use crate::db::model::DiskUpdate;
@@ -35,6 +36,7 @@ use omicron_common::api;
 use omicron_common::api::external::http_pagination::PaginatedBy;
 use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::LookupType;
@@ -128,6 +130,7 @@ impl DataStore {
         opctx: &OpContext,
         authz_project: &authz::Project,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<Disk> {
         opctx.authorize(authz::Action::ListChildren, authz_project).await?;
 
@@ -144,6 +147,7 @@ impl DataStore {
         }
         .filter(dsl::time_deleted.is_null())
         .filter(dsl::project_id.eq(authz_project.id()))
+        .filter(labels_match(label_selector))
         .select(Disk::as_select())
         .load_async::<Disk>(self.pool_authorized(opctx).await?)
         .await
@@ -517,6 +521,107 @@ impl DataStore {
         Ok(updated)
     }
 
+    /// Updates the recorded size of a disk from `old_size` to `new_size`.
+    ///
+    /// This only changes the database record; callers are responsible for
+    /// growing the disk's volume beforehand. It's idempotent: if the disk is
+    /// already `new_size`, it's returned as it is. It fails with
+    /// [`Error::Conflict`] if the disk is any other size.
+    pub async fn disk_set_size(
+        &self,
+        opctx: &OpContext,
+        authz_disk: &authz::Disk,
+        old_size: api::external::ByteCount,
+        new_size: api::external::ByteCount,
+    ) -> UpdateResult<Disk> {
+        opctx.authorize(authz::Action::Modify, authz_disk).await?;
+
+        let disk_id = authz_disk.id();
+        use db::schema::disk::dsl;
+        let result = diesel::update(dsl::disk)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(disk_id))
+            .filter(dsl::size_bytes.eq(db::model::ByteCount::from(old_size)))
+            .set((
+                dsl::size_bytes.eq(db::model::ByteCount::from(new_size)),
+                dsl::time_modified.eq(Utc::now()),
+            ))
+            .check_if_exists::<Disk>(disk_id)
+            .execute_and_check(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_disk),
+                )
+            })?;
+
+        match result.status {
+            UpdateStatus::Updated => Ok(result.found),
+            UpdateStatus::NotUpdatedButExists
+                if result.found.size.0 == new_size =>
+            {
+                Ok(result.found)
+            }
+            UpdateStatus::NotUpdatedButExists => {
+                Err(Error::conflict(&format!(
+                    "disk {} is no longer {} bytes",
+                    disk_id,
+                    old_size.to_bytes(),
+                )))
+            }
+        }
+    }
+
+    /// Replaces the volume backing a disk, along with its runtime state, as
+    /// long as the disk is still backed by `old_volume_id`.
+    ///
+    /// This is idempotent: if the disk is already backed by `new_volume_id`,
+    /// it's returned as it is.
+    pub async fn disk_set_volume(
+        &self,
+        opctx: &OpContext,
+        authz_disk: &authz::Disk,
+        old_volume_id: Uuid,
+        new_volume_id: Uuid,
+        new_runtime: &DiskRuntimeState,
+    ) -> UpdateResult<Disk> {
+        opctx.authorize(authz::Action::Modify, authz_disk).await?;
+
+        let disk_id = authz_disk.id();
+        use db::schema::disk::dsl;
+        let result = diesel::update(dsl::disk)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(disk_id))
+            .filter(dsl::volume_id.eq(old_volume_id))
+            .filter(dsl::state_generation.lt(new_runtime.gen))
+            .set((dsl::volume_id.eq(new_volume_id), new_runtime.clone()))
+            .check_if_exists::<Disk>(disk_id)
+            .execute_and_check(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_disk),
+                )
+            })?;
+
+        match result.status {
+            UpdateStatus::Updated => Ok(result.found),
+            UpdateStatus::NotUpdatedButExists
+                if result.found.volume_id == new_volume_id =>
+            {
+                Ok(result.found)
+            }
+            UpdateStatus::NotUpdatedButExists => {
+                Err(Error::conflict(&format!(
+                    "disk {} is no longer backed by volume {}",
+                    disk_id, old_volume_id,
+                )))
+            }
+        }
+    }
+
     /// Fetches information about a Disk that the caller has previously fetched
     ///
     /// The only difference between this function and a new fetch by id is that
@@ -689,6 +689,7 @@ mod test {
     use nexus_db_model::InitialDnsGroup;
     use nexus_test_utils::db::test_setup_database;
     use nexus_types::internal_api::params::DnsRecord;
+    use nexus_types::internal_api::params::Mx;
     use nexus_types::internal_api::params::Srv;
     use omicron_common::api::external::Error;
     use omicron_test_utils::dev;
@@ -828,6 +829,19 @@ mod test {
             port: 12345,
             target: "wendell.dummy.oxide.internal".to_string(),
         })];
+        let nelson_records =
+            vec![DnsRecord::Cname("wendell.dummy.oxide.internal".to_string())];
+        let skinner_records = vec![
+            DnsRecord::Txt(vec!["verification=1234".to_string()]),
+            DnsRecord::Mx(Mx {
+                preference: 10,
+                exchange: "wendell.dummy.oxide.internal".to_string(),
+            }),
+        ];
+        let chalmers_records =
+            vec![DnsRecord::Ptr("wendell.dummy.oxide.internal".to_string())];
+        let springfield_records =
+            vec![DnsRecord::Ns("wendell.dummy.oxide.internal".to_string())];
         let initial = InitialDnsGroup::new(
             DnsGroup::Internal,
             "dummy.oxide.internal",
@@ -836,6 +850,10 @@ mod test {
             HashMap::from([
                 ("wendell".to_string(), wendell_records.clone()),
                 ("krabappel".to_string(), krabappel_records.clone()),
+                ("nelson".to_string(), nelson_records.clone()),
+                ("skinner".to_string(), skinner_records.clone()),
+                ("chalmers".to_string(), chalmers_records.clone()),
+                ("springfield".to_string(), springfield_records.clone()),
             ]),
         );
         {
@@ -860,7 +878,11 @@ mod test {
             dns_config.zones[0].records,
             HashMap::from([
                 ("krabappel".to_string(), krabappel_records),
-                ("wendell".to_string(), wendell_records)
+                ("wendell".to_string(), wendell_records),
+                ("nelson".to_string(), nelson_records),
+                ("skinner".to_string(), skinner_records),
+                ("chalmers".to_string(), chalmers_records),
+                ("springfield".to_string(), springfield_records),
             ])
         );
 
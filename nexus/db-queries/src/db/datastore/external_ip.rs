@@ -6,14 +6,18 @@
 
 use super::DataStore;
 use crate::authz;
+use crate::authz::ApiResource;
 use crate::context::OpContext;
 use crate::db;
 use crate::db::error::public_error_from_diesel_pool;
 use crate::db::error::ErrorHandler;
+use crate::db::error::TransactionError;
 use crate::db::model::ExternalIp;
+use crate::db::model::FloatingIp;
 use crate::db::model::IncompleteExternalIp;
 use crate::db::model::IpKind;
 use crate::db::model::Name;
+use crate::db::pagination::paginated;
 use crate::db::pool::DbConnection;
 use crate::db::queries::external_ip::NextExternalIp;
 use crate::db::update_and_check::UpdateAndCheck;
@@ -22,10 +26,17 @@ use async_bb8_diesel::{AsyncRunQueryDsl, PoolError};
 use chrono::Utc;
 use diesel::prelude::*;
 use nexus_types::identity::Resource;
+use omicron_common::api::external::http_pagination::PaginatedBy;
 use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::Name as ExternalName;
+use omicron_common::api::external::ResourceType;
+use omicron_common::api::external::UpdateResult;
+use ref_cast::RefCast;
 use std::net::IpAddr;
 use std::str::FromStr;
 use uuid::Uuid;
@@ -68,6 +79,315 @@ impl DataStore {
         self.allocate_external_ip(opctx, data).await
     }
 
+    /// Create a Floating IP address in a project.
+    ///
+    /// The address is allocated from the IP Pool with the provided ID, or
+    /// from the default pool if none is provided.
+    pub async fn allocate_floating_ip(
+        &self,
+        opctx: &OpContext,
+        authz_project: &authz::Project,
+        ip_id: Uuid,
+        identity: &IdentityMetadataCreateParams,
+        pool_id: Option<Uuid>,
+    ) -> CreateResult<FloatingIp> {
+        opctx.authorize(authz::Action::CreateChild, authz_project).await?;
+
+        let pool_id = match pool_id {
+            Some(pool_id) => pool_id,
+            None => {
+                let (.., pool) = self
+                    .ip_pools_fetch_default_for(
+                        opctx,
+                        authz::Action::CreateChild,
+                    )
+                    .await?;
+                pool.identity.id
+            }
+        };
+
+        let name = Name(identity.name.clone());
+        let data = IncompleteExternalIp::for_floating(
+            ip_id,
+            &name,
+            &identity.description,
+            authz_project.id(),
+            pool_id,
+        );
+        NextExternalIp::new(data)
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                use async_bb8_diesel::ConnectionError::Query;
+                use async_bb8_diesel::PoolError::Connection;
+                use diesel::result::Error::NotFound;
+                match e {
+                    Connection(Query(NotFound)) => Error::invalid_request(
+                        "No external IP addresses available",
+                    ),
+                    _ => public_error_from_diesel_pool(
+                        e,
+                        ErrorHandler::Conflict(
+                            ResourceType::FloatingIp,
+                            name.as_str(),
+                        ),
+                    ),
+                }
+            })
+            .and_then(FloatingIp::try_from)
+    }
+
+    /// List the Floating IP addresses in a project.
+    pub async fn floating_ip_list(
+        &self,
+        opctx: &OpContext,
+        authz_project: &authz::Project,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<FloatingIp> {
+        opctx.authorize(authz::Action::ListChildren, authz_project).await?;
+
+        use db::schema::floating_ip::dsl;
+        match pagparams {
+            PaginatedBy::Id(pagparams) => {
+                paginated(dsl::floating_ip, dsl::id, &pagparams)
+            }
+            PaginatedBy::Name(pagparams) => paginated(
+                dsl::floating_ip,
+                dsl::name,
+                &pagparams.map_name(|n| Name::ref_cast(n)),
+            ),
+        }
+        .filter(dsl::project_id.eq(authz_project.id()))
+        .filter(dsl::time_deleted.is_null())
+        .select(FloatingIp::as_select())
+        .load_async::<FloatingIp>(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Delete a Floating IP address, which must not be attached to an
+    /// instance.
+    pub async fn floating_ip_delete(
+        &self,
+        opctx: &OpContext,
+        authz_fip: &authz::FloatingIp,
+    ) -> DeleteResult {
+        opctx.authorize(authz::Action::Delete, authz_fip).await?;
+
+        use db::schema::external_ip::dsl;
+        let result = diesel::update(dsl::external_ip)
+            .filter(dsl::id.eq(authz_fip.id()))
+            .filter(dsl::kind.eq(IpKind::Floating))
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::instance_id.is_null())
+            .set(dsl::time_deleted.eq(Utc::now()))
+            .check_if_exists::<ExternalIp>(authz_fip.id())
+            .execute_and_check(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_fip),
+                )
+            })?;
+        match result.status {
+            UpdateStatus::Updated => Ok(()),
+            UpdateStatus::NotUpdatedButExists => {
+                if result.found.time_deleted.is_some() {
+                    Err(authz_fip.not_found())
+                } else {
+                    Err(Error::invalid_request(
+                        "Floating IP cannot be deleted while attached to an \
+                        instance",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Fetch the underlying external IP record for a Floating IP address.
+    ///
+    /// This includes the port range used to program NAT for the address.
+    pub async fn floating_ip_fetch_external_ip(
+        &self,
+        opctx: &OpContext,
+        authz_fip: &authz::FloatingIp,
+    ) -> LookupResult<ExternalIp> {
+        opctx.authorize(authz::Action::Read, authz_fip).await?;
+
+        use db::schema::external_ip::dsl;
+        dsl::external_ip
+            .filter(dsl::id.eq(authz_fip.id()))
+            .filter(dsl::kind.eq(IpKind::Floating))
+            .filter(dsl::time_deleted.is_null())
+            .select(ExternalIp::as_select())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_fip),
+                )
+            })
+    }
+
+    /// Attach a Floating IP address to an instance.
+    ///
+    /// This only records the attachment. Attaching an address that is already
+    /// attached to the same instance succeeds, for idempotency. It fails if
+    /// the address is attached to another instance, or if the instance
+    /// already has `max_external_ips` external addresses besides its source
+    /// NAT address.
+    pub async fn floating_ip_attach(
+        &self,
+        opctx: &OpContext,
+        authz_fip: &authz::FloatingIp,
+        authz_instance: &authz::Instance,
+        max_external_ips: usize,
+    ) -> UpdateResult<FloatingIp> {
+        opctx.authorize(authz::Action::Modify, authz_fip).await?;
+        opctx.authorize(authz::Action::Modify, authz_instance).await?;
+
+        use db::model::InstanceState as DbInstanceState;
+        use db::schema::external_ip::dsl;
+        use db::schema::instance::dsl as instance_dsl;
+        use omicron_common::api::external::InstanceState as ApiInstanceState;
+        type TxnError = TransactionError<Error>;
+        let fip_id = authz_fip.id();
+        let instance_id = authz_instance.id();
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                // OPTE ports are given their external IP when they're
+                // created, so the instance mustn't be running. Checking that
+                // here means an instance that starts concurrently sees the
+                // address once it's attached.
+                let instance_state = instance_dsl::instance
+                    .filter(instance_dsl::id.eq(instance_id))
+                    .filter(instance_dsl::time_deleted.is_null())
+                    .select(instance_dsl::state)
+                    .get_result_async::<DbInstanceState>(&conn)
+                    .await?;
+                match instance_state.state() {
+                    ApiInstanceState::Creating | ApiInstanceState::Stopped => {}
+                    state => {
+                        return Err(TxnError::CustomError(
+                            Error::invalid_request(&format!(
+                                "cannot attach a floating IP to an instance \
+                                in state {}",
+                                state.label(),
+                            )),
+                        ));
+                    }
+                }
+
+                let fip = dsl::external_ip
+                    .filter(dsl::id.eq(fip_id))
+                    .filter(dsl::kind.eq(IpKind::Floating))
+                    .filter(dsl::time_deleted.is_null())
+                    .select(ExternalIp::as_select())
+                    .get_result_async::<ExternalIp>(&conn)
+                    .await?;
+                match fip.instance_id {
+                    Some(id) if id == instance_id => return Ok(fip),
+                    Some(_) => {
+                        return Err(TxnError::CustomError(
+                            Error::invalid_request(
+                                "Floating IP is already attached to an \
+                                instance",
+                            ),
+                        ));
+                    }
+                    None => {}
+                }
+
+                let n_external_ips = dsl::external_ip
+                    .filter(dsl::instance_id.eq(instance_id))
+                    .filter(dsl::kind.ne(IpKind::SNat))
+                    .filter(dsl::time_deleted.is_null())
+                    .count()
+                    .get_result_async::<i64>(&conn)
+                    .await?;
+                if n_external_ips >= max_external_ips as i64 {
+                    return Err(TxnError::CustomError(Error::invalid_request(
+                        &format!(
+                            "An instance may not have more than {} external \
+                            IP addresses",
+                            max_external_ips,
+                        ),
+                    )));
+                }
+
+                Ok(diesel::update(dsl::external_ip)
+                    .filter(dsl::id.eq(fip_id))
+                    .set((
+                        dsl::instance_id.eq(Some(instance_id)),
+                        dsl::time_modified.eq(Utc::now()),
+                    ))
+                    .returning(ExternalIp::as_returning())
+                    .get_result_async(&conn)
+                    .await?)
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(e) => e,
+                TxnError::Pool(e) => public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_fip),
+                ),
+            })
+            .and_then(FloatingIp::try_from)
+    }
+
+    /// Detach a Floating IP address from an instance.
+    ///
+    /// Detaching an address that isn't attached to any instance succeeds, for
+    /// idempotency. It fails if the address is attached to another instance.
+    pub async fn floating_ip_detach(
+        &self,
+        opctx: &OpContext,
+        authz_fip: &authz::FloatingIp,
+        authz_instance: &authz::Instance,
+    ) -> UpdateResult<FloatingIp> {
+        opctx.authorize(authz::Action::Modify, authz_fip).await?;
+        opctx.authorize(authz::Action::Modify, authz_instance).await?;
+
+        use db::schema::external_ip::dsl;
+        let result = diesel::update(dsl::external_ip)
+            .filter(dsl::id.eq(authz_fip.id()))
+            .filter(dsl::kind.eq(IpKind::Floating))
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::instance_id.eq(authz_instance.id()))
+            .set((
+                dsl::instance_id.eq(Option::<Uuid>::None),
+                dsl::time_modified.eq(Utc::now()),
+            ))
+            .check_if_exists::<ExternalIp>(authz_fip.id())
+            .execute_and_check(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_fip),
+                )
+            })?;
+        let fip = result.found;
+        match result.status {
+            UpdateStatus::Updated => {}
+            UpdateStatus::NotUpdatedButExists => {
+                if fip.time_deleted.is_some() {
+                    return Err(authz_fip.not_found());
+                }
+                if fip.instance_id.is_some() {
+                    return Err(Error::invalid_request(
+                        "Floating IP is attached to another instance",
+                    ));
+                }
+            }
+        }
+        FloatingIp::try_from(fip)
+    }
+
     /// Allocates an IP address for internal service usage.
     pub async fn allocate_service_ip(
         &self,
@@ -170,13 +490,14 @@ impl DataStore {
     }
 
     /// Delete all external IP addresses associated with the provided instance
-    /// ID.
+    /// ID, and detach any Floating IP addresses from it.
+    ///
+    /// Floating IPs outlive the instances they're attached to, so they're
+    /// left in place for attaching to another instance.
     ///
     /// This method returns the number of records deleted, rather than the usual
     /// `DeleteResult`. That's mostly useful for tests, but could be important
     /// if callers have some invariants they'd like to check.
-    // TODO-correctness: This can't be used for Floating IPs, we'll need a
-    // _detatch_ method for that.
     pub async fn deallocate_external_ip_by_instance_id(
         &self,
         opctx: &OpContext,
@@ -184,12 +505,26 @@ impl DataStore {
     ) -> Result<usize, Error> {
         use db::schema::external_ip::dsl;
         let now = Utc::now();
+        let conn = self.pool_authorized(opctx).await?;
+        diesel::update(dsl::external_ip)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::instance_id.eq(instance_id))
+            .filter(dsl::kind.eq(IpKind::Floating))
+            .set((
+                dsl::instance_id.eq(Option::<Uuid>::None),
+                dsl::time_modified.eq(now),
+            ))
+            .execute_async(conn)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
         diesel::update(dsl::external_ip)
             .filter(dsl::time_deleted.is_null())
             .filter(dsl::instance_id.eq(instance_id))
             .filter(dsl::kind.ne(IpKind::Floating))
             .set(dsl::time_deleted.eq(now))
-            .execute_async(self.pool_authorized(opctx).await?)
+            .execute_async(conn)
             .await
             .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
     }
@@ -4,6 +4,7 @@ use nexus_db_model::Name;
 use nexus_types::identity::Resource;
 use omicron_common::api::external::http_pagination::PaginatedBy;
 use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::ResourceType;
 use omicron_common::api::external::UpdateResult;
@@ -17,6 +18,7 @@ use crate::db::collection_insert::AsyncInsertError;
 use crate::db::collection_insert::DatastoreCollection;
 use crate::db::error::public_error_from_diesel_pool;
 use crate::db::error::ErrorHandler;
+use crate::db::model::labels_match;
 use crate::db::model::Image;
 use crate::db::model::Project;
 use crate::db::model::ProjectImage;
@@ -37,6 +39,7 @@ impl DataStore {
         authz_project: &authz::Project,
         include_silo_images: bool,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<Image> {
         opctx.authorize(authz::Action::ListChildren, authz_project).await?;
 
@@ -60,6 +63,7 @@ impl DataStore {
                     .is_null()
                     .or(dsl::project_id.eq(authz_project.id())),
             )
+            .filter(labels_match(label_selector))
             .select(Image::as_select())
             .load_async::<Image>(self.pool_authorized(opctx).await?)
             .await
@@ -80,6 +84,7 @@ impl DataStore {
             }
             .filter(project_dsl::time_deleted.is_null())
             .filter(project_dsl::project_id.eq(authz_project.id()))
+            .filter(labels_match(label_selector))
             .select(ProjectImage::as_select())
             .load_async::<ProjectImage>(self.pool_authorized(opctx).await?)
             .await
@@ -93,6 +98,7 @@ impl DataStore {
         opctx: &OpContext,
         authz_silo: &authz::Silo,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<Image> {
         opctx.authorize(authz::Action::ListChildren, authz_silo).await?;
 
@@ -109,6 +115,7 @@ impl DataStore {
         }
         .filter(dsl::time_deleted.is_null())
         .filter(dsl::silo_id.eq(authz_silo.id()))
+        .filter(labels_match(label_selector))
         .select(SiloImage::as_select())
         .load_async::<SiloImage>(self.pool_authorized(opctx).await?)
         .await
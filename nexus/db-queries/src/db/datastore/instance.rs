@@ -15,27 +15,39 @@ use crate::db::collection_insert::AsyncInsertError;
 use crate::db::collection_insert::DatastoreCollection;
 use crate::db::error::public_error_from_diesel_pool;
 use crate::db::error::ErrorHandler;
+use crate::db::error::TransactionError;
 use crate::db::identity::Resource;
 use crate::db::lookup::LookupPath;
+use crate::db::model::labels_match;
+use crate::db::model::ByteCount;
+use crate::db::model::Generation;
 use crate::db::model::Instance;
+use crate::db::model::InstanceAutoRestartPolicy;
+use crate::db::model::InstanceBoot;
+use crate::db::model::InstanceCpuCount;
 use crate::db::model::InstanceRuntimeState;
+use crate::db::model::Labels;
 use crate::db::model::Name;
 use crate::db::model::Project;
 use crate::db::pagination::paginated;
 use crate::db::update_and_check::UpdateAndCheck;
 use crate::db::update_and_check::UpdateStatus;
-use async_bb8_diesel::AsyncRunQueryDsl;
+use async_bb8_diesel::{AsyncConnection, AsyncRunQueryDsl, OptionalExtension};
+use chrono::DateTime;
 use chrono::Utc;
 use diesel::prelude::*;
 use omicron_common::api;
 use omicron_common::api::external::http_pagination::PaginatedBy;
 use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DataPageParams;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::LookupType;
 use omicron_common::api::external::ResourceType;
+use omicron_common::api::external::UpdateResult;
 use omicron_common::bail_unless;
 use ref_cast::RefCast;
 use uuid::Uuid;
@@ -118,6 +130,7 @@ impl DataStore {
         opctx: &OpContext,
         authz_project: &authz::Project,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<Instance> {
         opctx.authorize(authz::Action::ListChildren, authz_project).await?;
 
@@ -134,6 +147,7 @@ impl DataStore {
         }
         .filter(dsl::project_id.eq(authz_project.id()))
         .filter(dsl::time_deleted.is_null())
+        .filter(labels_match(label_selector))
         .select(Instance::as_select())
         .load_async::<Instance>(self.pool_authorized(opctx).await?)
         .await
@@ -244,6 +258,377 @@ impl DataStore {
         Ok(updated)
     }
 
+    /// Lists instances that have an auto-restart policy and that are believed
+    /// to be running on some sled
+    ///
+    /// Instances that are migrating are left out, since they're in the middle
+    /// of moving from one sled to another.
+    pub async fn instance_list_active_auto_restart(
+        &self,
+        opctx: &OpContext,
+        pagparams: &DataPageParams<'_, Uuid>,
+    ) -> ListResultVec<Instance> {
+        opctx.authorize(authz::Action::Read, &authz::FLEET).await?;
+
+        use api::external::InstanceAutoRestartPolicy as ApiPolicy;
+        use api::external::InstanceState as ApiInstanceState;
+        use db::model::InstanceState as DbInstanceState;
+        use db::schema::instance::dsl;
+
+        let active = [
+            ApiInstanceState::Starting,
+            ApiInstanceState::Running,
+            ApiInstanceState::Rebooting,
+            ApiInstanceState::Stopping,
+        ]
+        .into_iter()
+        .map(DbInstanceState::new)
+        .collect::<Vec<_>>();
+        paginated(dsl::instance, dsl::id, pagparams)
+            .filter(dsl::time_deleted.is_null())
+            .filter(
+                dsl::auto_restart_policy
+                    .ne(InstanceAutoRestartPolicy::new(ApiPolicy::Never)),
+            )
+            .filter(dsl::state.eq_any(active))
+            .filter(dsl::migration_id.is_null())
+            .select(Instance::as_select())
+            .load_async::<Instance>(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Lists up to `limit` instances that need to be started again according
+    /// to their auto-restart policies, least recently updated first
+    ///
+    /// Instances whose automatic restart backoff hasn't yet run out are left
+    /// out, as are instances that have already been restarted `max_attempts`
+    /// times in a row unless they've changed state since Nexus last looked at
+    /// them.  See [`Instance::needs_auto_restart`].
+    pub async fn instance_list_auto_restart_due(
+        &self,
+        opctx: &OpContext,
+        max_attempts: u32,
+        limit: u32,
+    ) -> ListResultVec<Instance> {
+        opctx.authorize(authz::Action::Read, &authz::FLEET).await?;
+
+        use api::external::InstanceAutoRestartPolicy as ApiPolicy;
+        use api::external::InstanceState as ApiInstanceState;
+        use db::model::InstanceState as DbInstanceState;
+        use db::schema::instance::dsl;
+
+        let failed = DbInstanceState::new(ApiInstanceState::Failed);
+        let stopped = DbInstanceState::new(ApiInstanceState::Stopped);
+        dsl::instance
+            .filter(dsl::time_deleted.is_null())
+            .filter(
+                dsl::auto_restart_policy
+                    .ne(InstanceAutoRestartPolicy::new(ApiPolicy::Never)),
+            )
+            .filter(
+                dsl::state.eq(failed).or(dsl::auto_restart_policy
+                    .eq(InstanceAutoRestartPolicy::new(ApiPolicy::Always))
+                    .and(dsl::state.eq(stopped))
+                    .and(dsl::stop_requested.eq(false))),
+            )
+            .filter(
+                dsl::time_auto_restart_next
+                    .is_null()
+                    .or(dsl::time_auto_restart_next.le(Utc::now())),
+            )
+            .filter(
+                dsl::auto_restart_count.lt(i64::from(max_attempts)).or(
+                    dsl::time_state_updated
+                        .nullable()
+                        .gt(dsl::time_auto_restart_next),
+                ),
+            )
+            .order(dsl::time_state_updated.asc())
+            .limit(i64::from(limit))
+            .select(Instance::as_select())
+            .load_async::<Instance>(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Records how many times in a row an instance has been restarted
+    /// automatically, and the earliest time at which it may be again
+    pub async fn instance_set_auto_restart_backoff(
+        &self,
+        instance_id: &Uuid,
+        count: u32,
+        next: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        use db::schema::instance::dsl;
+        diesel::update(dsl::instance)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(*instance_id))
+            .set((
+                dsl::auto_restart_count.eq(i64::from(count)),
+                dsl::time_auto_restart_next.eq(Some(next)),
+            ))
+            .execute_async(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        Ok(())
+    }
+
+    /// Records whether an instance was last asked to stop, as opposed to
+    /// start, through the API
+    pub async fn instance_set_stop_requested(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+        stop_requested: bool,
+    ) -> UpdateResult<()> {
+        opctx.authorize(authz::Action::Modify, authz_instance).await?;
+
+        use db::schema::instance::dsl;
+        diesel::update(dsl::instance)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(authz_instance.id()))
+            .set(dsl::stop_requested.eq(stop_requested))
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_instance),
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Moves a stopped or failed instance to a new sled and Propolis before it
+    /// is started again
+    ///
+    /// The update only happens if the instance's runtime state still matches
+    /// `old_runtime`, in which case the sled reservation held by the old
+    /// Propolis is released in the same transaction.  Returns whether the
+    /// instance was moved.
+    pub async fn instance_relocate(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+        old_runtime: &InstanceRuntimeState,
+        new_runtime: &InstanceRuntimeState,
+    ) -> UpdateResult<bool> {
+        opctx.authorize(authz::Action::Modify, authz_instance).await?;
+
+        use db::schema::instance::dsl;
+        use db::schema::sled_resource::dsl as resource_dsl;
+
+        let instance_id = authz_instance.id();
+        let old_runtime = old_runtime.clone();
+        let new_runtime = new_runtime.clone();
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                let updated = diesel::update(dsl::instance)
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(dsl::id.eq(instance_id))
+                    .filter(dsl::state_generation.eq(old_runtime.gen))
+                    .filter(
+                        dsl::propolis_generation.eq(old_runtime.propolis_gen),
+                    )
+                    .set(new_runtime)
+                    .execute_async(&conn)
+                    .await?;
+                if updated == 0 {
+                    return Ok(false);
+                }
+
+                diesel::delete(resource_dsl::sled_resource)
+                    .filter(resource_dsl::id.eq(old_runtime.propolis_id))
+                    .execute_async(&conn)
+                    .await?;
+                Ok(true)
+            })
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_instance),
+                )
+            })
+    }
+
+    /// Records that an instance is being registered with a new Propolis on
+    /// the given sled, giving it the instance's next boot generation
+    pub async fn instance_boot_create(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+        sled_id: Uuid,
+        propolis_id: Uuid,
+    ) -> CreateResult<InstanceBoot> {
+        opctx.authorize(authz::Action::Modify, authz_instance).await?;
+
+        use db::schema::instance_boot::dsl;
+
+        let instance_id = authz_instance.id();
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                let last = dsl::instance_boot
+                    .filter(dsl::instance_id.eq(instance_id))
+                    .order(dsl::boot_generation.desc())
+                    .select(InstanceBoot::as_select())
+                    .first_async::<InstanceBoot>(&conn)
+                    .await
+                    .optional()?;
+                let boot_generation = last.map_or_else(Generation::new, |b| {
+                    Generation::from(b.boot_generation.next())
+                });
+                let boot = InstanceBoot::new(
+                    instance_id,
+                    boot_generation,
+                    sled_id,
+                    propolis_id,
+                );
+                diesel::insert_into(dsl::instance_boot)
+                    .values(boot.clone())
+                    .execute_async(&conn)
+                    .await?;
+                Ok(boot)
+            })
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Lists the boots of an instance, oldest first
+    pub async fn instance_boot_list(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+    ) -> ListResultVec<InstanceBoot> {
+        opctx.authorize(authz::Action::Read, authz_instance).await?;
+
+        use db::schema::instance_boot::dsl;
+        dsl::instance_boot
+            .filter(dsl::instance_id.eq(authz_instance.id()))
+            .order(dsl::boot_generation.asc())
+            .select(InstanceBoot::as_select())
+            .load_async::<InstanceBoot>(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Changes the CPU count, memory, and hostname of a stopped instance.
+    ///
+    /// The instance's CPU and RAM provisioning is adjusted in the same
+    /// transaction. The sled reservation held by the instance's Propolis is
+    /// released, so that capacity for the new configuration is reserved again
+    /// the next time the instance starts.
+    pub async fn instance_reconfigure(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+        db_instance: &Instance,
+        ncpus: InstanceCpuCount,
+        memory: ByteCount,
+        hostname: String,
+    ) -> UpdateResult<Instance> {
+        opctx.authorize(authz::Action::Modify, authz_instance).await?;
+
+        use api::external::InstanceState as ApiInstanceState;
+        use db::model::InstanceState as DbInstanceState;
+        use db::schema::instance::dsl;
+        use db::schema::sled_resource::dsl as resource_dsl;
+
+        let stopped = DbInstanceState::new(ApiInstanceState::Stopped);
+        let old_runtime = db_instance.runtime().clone();
+
+        type TxnError = TransactionError<Error>;
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                // Only a stopped instance whose state hasn't changed since the
+                // caller looked at it may be reconfigured.
+                let updated = diesel::update(dsl::instance)
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(dsl::id.eq(authz_instance.id()))
+                    .filter(dsl::state.eq(stopped))
+                    .filter(dsl::state_generation.eq(old_runtime.gen))
+                    .filter(
+                        dsl::propolis_generation.eq(old_runtime.propolis_gen),
+                    )
+                    .set((
+                        dsl::ncpus.eq(ncpus),
+                        dsl::memory.eq(memory),
+                        dsl::hostname.eq(hostname),
+                        dsl::time_modified.eq(Utc::now()),
+                    ))
+                    .returning(Instance::as_returning())
+                    .get_results_async(&conn)
+                    .await?;
+
+                let Some(instance) = updated.into_iter().next() else {
+                    return Err(TxnError::CustomError(Error::invalid_request(
+                        "instance changed state while being updated",
+                    )));
+                };
+
+                self.virtual_provisioning_collection_update_instance_on_connection(
+                    &conn,
+                    instance.id(),
+                    instance.project_id,
+                    i64::from(old_runtime.ncpus.0 .0),
+                    old_runtime.memory,
+                    i64::from(ncpus.0 .0),
+                    memory,
+                )
+                .await
+                .map_err(TxnError::CustomError)?;
+
+                diesel::delete(resource_dsl::sled_resource)
+                    .filter(resource_dsl::id.eq(old_runtime.propolis_id))
+                    .execute_async(&conn)
+                    .await?;
+
+                Ok(instance)
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(e) => e,
+                TxnError::Pool(e) => public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_instance),
+                ),
+            })
+    }
+
+    /// Replaces the labels attached to an instance.
+    ///
+    /// Unlike the rest of the instance's configuration, labels may be changed
+    /// regardless of the instance's state.
+    pub async fn instance_update_labels(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+        labels: Labels,
+    ) -> UpdateResult<Instance> {
+        opctx.authorize(authz::Action::Modify, authz_instance).await?;
+
+        use db::schema::instance::dsl;
+        diesel::update(dsl::instance)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(authz_instance.id()))
+            .set((dsl::labels.eq(labels), dsl::time_modified.eq(Utc::now())))
+            .returning(Instance::as_returning())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_instance),
+                )
+            })
+    }
+
     pub async fn project_delete_instance(
         &self,
         opctx: &OpContext,
@@ -257,7 +642,7 @@ impl DataStore {
         // and also sets the state to "destroyed".
         use api::external::InstanceState as ApiInstanceState;
         use db::model::InstanceState as DbInstanceState;
-        use db::schema::{disk, instance};
+        use db::schema::{affinity_group_instance_membership, disk, instance};
 
         let stopped = DbInstanceState::new(ApiInstanceState::Stopped);
         let failed = DbInstanceState::new(ApiInstanceState::Failed);
@@ -312,6 +697,18 @@ impl DataStore {
             }
         })?;
 
+        // A deleted instance no longer belongs to any affinity groups.
+        diesel::delete(
+            affinity_group_instance_membership::dsl::affinity_group_instance_membership,
+        )
+        .filter(
+            affinity_group_instance_membership::dsl::instance_id
+                .eq(authz_instance.id()),
+        )
+        .execute_async(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))?;
+
         Ok(())
     }
 }
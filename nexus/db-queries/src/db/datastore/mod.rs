@@ -41,6 +41,9 @@ use std::net::Ipv6Addr;
 use std::sync::Arc;
 use uuid::Uuid;
 
+mod access_token;
+mod affinity_group;
+mod audit_log;
 mod certificate;
 mod console_session;
 mod dataset;
@@ -57,17 +60,21 @@ mod network_interface;
 mod oximeter;
 mod physical_disk;
 mod project;
+mod quota;
 mod rack;
 mod region;
+mod region_replacement;
 mod region_snapshot;
 mod role;
 mod saga;
+mod scim_client_token;
 mod service;
 mod silo;
 mod silo_group;
 mod silo_user;
 mod sled;
 mod snapshot;
+mod snapshot_schedule;
 mod ssh_key;
 mod update;
 mod virtual_provisioning_collection;
@@ -77,6 +84,7 @@ mod zpool;
 
 pub use dns::DnsVersionUpdateBuilder;
 pub use rack::RackInit;
+pub use silo_group::SiloGroupMembershipUpdate;
 pub use virtual_provisioning_collection::StorageType;
 pub use volume::CrucibleResources;
 
@@ -273,6 +281,7 @@ mod test {
     use crate::db::explain::ExplainableAsync;
     use crate::db::fixed_data::silo::SILO_ID;
     use crate::db::identity::Asset;
+    use crate::db::identity::Resource;
     use crate::db::lookup::LookupPath;
     use crate::db::model::{
         BlockSize, ComponentUpdate, ComponentUpdateIdentity, ConsoleSession,
@@ -337,6 +346,7 @@ mod test {
                     name: "project".parse().unwrap(),
                     description: "desc".to_string(),
                 },
+                labels: Default::default(),
             },
         );
         datastore.project_create(&opctx, project).await.unwrap();
@@ -353,6 +363,92 @@ mod test {
         logctx.cleanup_successful();
     }
 
+    #[tokio::test]
+    async fn test_instance_provisioning_update_conflict() {
+        let logctx =
+            dev::test_setup_log("test_instance_provisioning_update_conflict");
+        let mut db = test_setup_database(&logctx.log).await;
+        let (opctx, datastore) = datastore_test(&logctx, &db).await;
+
+        let authz_silo = opctx.authn.silo_required().unwrap();
+        let (.., project) = datastore
+            .project_create(
+                &opctx,
+                Project::new(
+                    authz_silo.id(),
+                    params::ProjectCreate {
+                        identity: IdentityMetadataCreateParams {
+                            name: "project".parse().unwrap(),
+                            description: "desc".to_string(),
+                        },
+                        labels: Default::default(),
+                    },
+                ),
+            )
+            .await
+            .unwrap();
+        let project_id = project.id();
+
+        let instance_id = Uuid::new_v4();
+        let small_ram: crate::db::model::ByteCount =
+            ByteCount::from_gibibytes_u32(1).into();
+        let large_ram: crate::db::model::ByteCount =
+            ByteCount::from_gibibytes_u32(2).into();
+        datastore
+            .virtual_provisioning_collection_insert_instance(
+                &opctx,
+                instance_id,
+                project_id,
+                2,
+                small_ram,
+            )
+            .await
+            .unwrap();
+
+        // An update that expects the instance to have a configuration it
+        // doesn't have fails, rather than leaving the provisioning as it was.
+        let conn = datastore.pool_for_tests().await.unwrap();
+        let error = datastore
+            .virtual_provisioning_collection_update_instance_on_connection(
+                conn,
+                instance_id,
+                project_id,
+                4,
+                large_ram,
+                8,
+                large_ram,
+            )
+            .await
+            .unwrap_err();
+        assert_matches!(error, Error::Conflict { .. });
+
+        // An update from the actual configuration succeeds, and repeating it
+        // changes nothing.
+        for _ in 0..2 {
+            datastore
+                .virtual_provisioning_collection_update_instance_on_connection(
+                    conn,
+                    instance_id,
+                    project_id,
+                    2,
+                    small_ram,
+                    4,
+                    large_ram,
+                )
+                .await
+                .unwrap();
+            let provisioned = datastore
+                .virtual_provisioning_collection_get(&opctx, project_id)
+                .await
+                .unwrap();
+            assert_eq!(provisioned.cpus_provisioned, 4);
+            assert_eq!(provisioned.ram_provisioned, large_ram);
+        }
+
+        db.cleanup().await.unwrap();
+        logctx.cleanup_successful();
+    }
+
     #[tokio::test]
     async fn test_session_methods() {
         let logctx = dev::test_setup_log("test_session_methods");
@@ -560,6 +656,7 @@ mod test {
                 name: Name::try_from(name.to_string()).unwrap(),
                 description: name.to_string(),
             },
+            labels: Default::default(),
             disk_source: params::DiskSource::Blank {
                 block_size: params::BlockSize::try_from(4096).unwrap(),
             },
@@ -1305,6 +1402,7 @@ mod test {
                 ))),
                 first_port: crate::db::model::SqlU16(0),
                 last_port: crate::db::model::SqlU16(10),
+                project_id: None,
             })
             .collect::<Vec<_>>();
         diesel::insert_into(dsl::external_ip)
@@ -1363,6 +1461,7 @@ mod test {
             ))),
             first_port: crate::db::model::SqlU16(0),
             last_port: crate::db::model::SqlU16(10),
+            project_id: None,
         };
         diesel::insert_into(dsl::external_ip)
             .values(ip.clone())
@@ -1431,6 +1530,7 @@ mod test {
             ip: addresses.next().unwrap().into(),
             first_port: crate::db::model::SqlU16(0),
             last_port: crate::db::model::SqlU16(10),
+            project_id: Some(Uuid::new_v4()),
         };
 
         // Combinations of NULL and non-NULL for:
@@ -1501,6 +1601,7 @@ mod test {
                             kind,
                             ip: addresses.next().unwrap().into(),
                             instance_id: *instance_id,
+                            project_id: None,
                             ..ip
                         };
                         let res = diesel::insert_into(dsl::external_ip)
@@ -16,6 +16,7 @@ use crate::db::error::public_error_from_diesel_pool;
 use crate::db::error::ErrorHandler;
 use crate::db::error::TransactionError;
 use crate::db::identity::Resource;
+use crate::db::model::labels_match;
 use crate::db::model::CollectionTypeProvisioned;
 use crate::db::model::Name;
 use crate::db::model::Project;
@@ -31,6 +32,7 @@ use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
 use omicron_common::api::external::InternalContext;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupType;
 use omicron_common::api::external::ResourceType;
@@ -170,6 +172,9 @@ impl DataStore {
     generate_fn_to_ensure_none_in_project!(project_image, name, String);
     generate_fn_to_ensure_none_in_project!(snapshot, name, String);
     generate_fn_to_ensure_none_in_project!(vpc, name, String);
+    generate_fn_to_ensure_none_in_project!(affinity_group, name, String);
+    generate_fn_to_ensure_none_in_project!(snapshot_schedule, name, String);
+    generate_fn_to_ensure_none_in_project!(floating_ip, name, String);
 
     /// Delete a project
     pub async fn project_delete(
@@ -186,6 +191,10 @@ impl DataStore {
         self.ensure_no_project_images_in_project(opctx, authz_project).await?;
         self.ensure_no_snapshots_in_project(opctx, authz_project).await?;
         self.ensure_no_vpcs_in_project(opctx, authz_project).await?;
+        self.ensure_no_affinity_groups_in_project(opctx, authz_project).await?;
+        self.ensure_no_snapshot_schedules_in_project(opctx, authz_project)
+            .await?;
+        self.ensure_no_floating_ips_in_project(opctx, authz_project).await?;
 
         use db::schema::project::dsl;
 
@@ -222,6 +231,11 @@ impl DataStore {
                     db_project.id(),
                 )
                 .await?;
+                self.project_quotas_delete_on_connection(
+                    &conn,
+                    db_project.id(),
+                )
+                .await?;
                 Ok(())
             })
             .await
@@ -238,6 +252,7 @@ impl DataStore {
         &self,
         opctx: &OpContext,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<Project> {
         let authz_silo =
             opctx.authn.silo_required().internal_context("listing Projects")?;
@@ -256,6 +271,7 @@ impl DataStore {
         }
         .filter(dsl::silo_id.eq(authz_silo.id()))
         .filter(dsl::time_deleted.is_null())
+        .filter(labels_match(label_selector))
         .select(Project::as_select())
         .load_async(self.pool_authorized(opctx).await?)
         .await
@@ -0,0 +1,191 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! [`DataStore`] methods on [`SiloQuotas`] and [`ProjectQuotas`].
+
+use super::DataStore;
+use crate::authz;
+use crate::context::OpContext;
+use crate::db;
+use crate::db::error::diesel_pool_result_optional;
+use crate::db::error::public_error_from_diesel_pool;
+use crate::db::error::ErrorHandler;
+use crate::db::model::ProjectQuotas;
+use crate::db::model::SiloQuotas;
+use crate::db::pool::DbConnection;
+use async_bb8_diesel::{AsyncRunQueryDsl, PoolError};
+use chrono::Utc;
+use diesel::prelude::*;
+use nexus_types::external_api::params;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::UpdateResult;
+use uuid::Uuid;
+
+impl DataStore {
+    /// Fetches the quotas of a silo.
+    ///
+    /// A silo whose quotas have never been set is unconstrained, and this
+    /// returns quotas without any limits for it.
+    pub async fn silo_quotas_view(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+    ) -> LookupResult<SiloQuotas> {
+        opctx.authorize(authz::Action::Read, authz_silo).await?;
+
+        use db::schema::silo_quotas::dsl;
+        let quotas = diesel_pool_result_optional(
+            dsl::silo_quotas
+                .filter(dsl::silo_id.eq(authz_silo.id()))
+                .select(SiloQuotas::as_select())
+                .get_result_async(self.pool_authorized(opctx).await?)
+                .await,
+        )
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))?;
+        Ok(quotas.unwrap_or_else(|| {
+            SiloQuotas::new(authz_silo.id(), params::QuotasUpdate::default())
+        }))
+    }
+
+    /// Replaces the quotas of a silo.
+    ///
+    /// Silo quotas are set by operators, so this requires permission to
+    /// modify the fleet rather than the silo itself.
+    pub async fn silo_quotas_update(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        quotas: SiloQuotas,
+    ) -> UpdateResult<SiloQuotas> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+        assert_eq!(authz_silo.id(), quotas.silo_id);
+
+        use db::schema::silo_quotas::dsl;
+        diesel::insert_into(dsl::silo_quotas)
+            .values(quotas.clone())
+            .on_conflict(dsl::silo_id)
+            .do_update()
+            .set((
+                dsl::time_modified.eq(Utc::now()),
+                dsl::cpus.eq(quotas.cpus),
+                dsl::memory_bytes.eq(quotas.memory_bytes),
+                dsl::storage_bytes.eq(quotas.storage_bytes),
+            ))
+            .returning(SiloQuotas::as_returning())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Deletes the quotas of a silo which is itself being deleted.
+    pub(crate) async fn silo_quotas_delete_on_connection<ConnErr>(
+        &self,
+        conn: &(impl async_bb8_diesel::AsyncConnection<DbConnection, ConnErr>
+              + Sync),
+        silo_id: Uuid,
+    ) -> DeleteResult
+    where
+        ConnErr: From<diesel::result::Error> + Send + 'static,
+        PoolError: From<ConnErr>,
+    {
+        use db::schema::silo_quotas::dsl;
+        diesel::delete(dsl::silo_quotas)
+            .filter(dsl::silo_id.eq(silo_id))
+            .execute_async(conn)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    PoolError::from(e),
+                    ErrorHandler::Server,
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Fetches the quotas of a project.
+    ///
+    /// A project whose quotas have never been set is constrained only by the
+    /// quotas of its silo, and this returns quotas without any limits for it.
+    pub async fn project_quotas_view(
+        &self,
+        opctx: &OpContext,
+        authz_project: &authz::Project,
+    ) -> LookupResult<ProjectQuotas> {
+        opctx.authorize(authz::Action::Read, authz_project).await?;
+
+        use db::schema::project_quotas::dsl;
+        let quotas = diesel_pool_result_optional(
+            dsl::project_quotas
+                .filter(dsl::project_id.eq(authz_project.id()))
+                .select(ProjectQuotas::as_select())
+                .get_result_async(self.pool_authorized(opctx).await?)
+                .await,
+        )
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))?;
+        Ok(quotas.unwrap_or_else(|| {
+            ProjectQuotas::new(
+                authz_project.id(),
+                params::QuotasUpdate::default(),
+            )
+        }))
+    }
+
+    /// Replaces the quotas of a project.
+    ///
+    /// Project quotas are set by silo administrators, so this requires
+    /// permission to modify the project's silo rather than the project
+    /// itself.
+    pub async fn project_quotas_update(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        authz_project: &authz::Project,
+        quotas: ProjectQuotas,
+    ) -> UpdateResult<ProjectQuotas> {
+        opctx.authorize(authz::Action::Modify, authz_silo).await?;
+        assert_eq!(authz_project.id(), quotas.project_id);
+
+        use db::schema::project_quotas::dsl;
+        diesel::insert_into(dsl::project_quotas)
+            .values(quotas.clone())
+            .on_conflict(dsl::project_id)
+            .do_update()
+            .set((
+                dsl::time_modified.eq(Utc::now()),
+                dsl::cpus.eq(quotas.cpus),
+                dsl::memory_bytes.eq(quotas.memory_bytes),
+                dsl::storage_bytes.eq(quotas.storage_bytes),
+            ))
+            .returning(ProjectQuotas::as_returning())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Deletes the quotas of a project which is itself being deleted.
+    pub(crate) async fn project_quotas_delete_on_connection<ConnErr>(
+        &self,
+        conn: &(impl async_bb8_diesel::AsyncConnection<DbConnection, ConnErr>
+              + Sync),
+        project_id: Uuid,
+    ) -> DeleteResult
+    where
+        ConnErr: From<diesel::result::Error> + Send + 'static,
+        PoolError: From<ConnErr>,
+    {
+        use db::schema::project_quotas::dsl;
+        diesel::delete(dsl::project_quotas)
+            .filter(dsl::project_id.eq(project_id))
+            .execute_async(conn)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    PoolError::from(e),
+                    ErrorHandler::Server,
+                )
+            })?;
+        Ok(())
+    }
+}
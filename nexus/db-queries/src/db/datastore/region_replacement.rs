@@ -0,0 +1,471 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! [`DataStore`] methods on [`RegionReplacement`]s.
+
+use super::DataStore;
+use crate::authz;
+use crate::context::OpContext;
+use crate::db;
+use crate::db::error::public_error_from_diesel_pool;
+use crate::db::error::ErrorHandler;
+use crate::db::error::TransactionError;
+use crate::db::identity::Asset;
+use crate::db::model::Dataset;
+use crate::db::model::DatasetKind;
+use crate::db::model::Region;
+use crate::db::model::RegionReplacement;
+use crate::db::model::RegionReplacementRestart;
+use crate::db::model::RegionReplacementState;
+use crate::db::model::SledState;
+use crate::db::model::Zpool;
+use crate::db::pagination::paginated;
+use async_bb8_diesel::AsyncConnection;
+use async_bb8_diesel::AsyncRunQueryDsl;
+use chrono::Utc;
+use diesel::prelude::*;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DataPageParams;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::UpdateResult;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+impl DataStore {
+    /// Returns the regions of disks' volumes whose datasets have been lost,
+    /// along with the ID of the disk using each region.  Regions that already
+    /// have a replacement request are left out.
+    ///
+    /// A dataset is lost when it, its zpool or the zpool's physical disk has
+    /// been deleted, or when its sled has been deleted or expunged.
+    pub async fn find_regions_on_lost_datasets(
+        &self,
+        opctx: &OpContext,
+    ) -> ListResultVec<(Region, Uuid)> {
+        opctx.authorize(authz::Action::Read, &authz::FLEET).await?;
+
+        use db::schema::dataset::dsl as dataset_dsl;
+        use db::schema::disk::dsl as disk_dsl;
+        use db::schema::physical_disk::dsl as physical_disk_dsl;
+        use db::schema::region::dsl as region_dsl;
+        use db::schema::region_replacement::dsl as replacement_dsl;
+        use db::schema::sled::dsl as sled_dsl;
+        use db::schema::zpool::dsl as zpool_dsl;
+
+        let lost_sleds = sled_dsl::sled
+            .filter(
+                sled_dsl::time_deleted
+                    .is_not_null()
+                    .or(sled_dsl::state.eq(SledState::Expunged)),
+            )
+            .select(sled_dsl::id);
+        let lost_physical_disks = physical_disk_dsl::physical_disk
+            .filter(physical_disk_dsl::time_deleted.is_not_null())
+            .select(physical_disk_dsl::id);
+        let lost_zpools = zpool_dsl::zpool
+            .filter(
+                zpool_dsl::time_deleted
+                    .is_not_null()
+                    .or(zpool_dsl::physical_disk_id.eq_any(lost_physical_disks))
+                    .or(zpool_dsl::sled_id.eq_any(lost_sleds)),
+            )
+            .select(zpool_dsl::id);
+        let lost_datasets = dataset_dsl::dataset
+            .filter(dataset_dsl::kind.eq(DatasetKind::Crucible))
+            .filter(
+                dataset_dsl::time_deleted
+                    .is_not_null()
+                    .or(dataset_dsl::pool_id.eq_any(lost_zpools)),
+            )
+            .select(dataset_dsl::id);
+
+        let conn = self.pool_authorized(opctx).await?;
+        let regions = region_dsl::region
+            .filter(region_dsl::dataset_id.eq_any(lost_datasets))
+            .filter(diesel::dsl::not(
+                region_dsl::id.eq_any(
+                    replacement_dsl::region_replacement
+                        .select(replacement_dsl::old_region_id),
+                ),
+            ))
+            .select(Region::as_select())
+            .load_async(conn)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        if regions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Only the regions of volumes that back disks are replaced: any other
+        // volume is either on its way to being deleted, or is a temporary
+        // volume that's only used while a saga runs.
+        let volume_ids: Vec<Uuid> =
+            regions.iter().map(|region| region.volume_id()).collect();
+        let disks: BTreeMap<Uuid, Uuid> = disk_dsl::disk
+            .filter(disk_dsl::time_deleted.is_null())
+            .filter(disk_dsl::volume_id.eq_any(volume_ids))
+            .select((disk_dsl::volume_id, disk_dsl::id))
+            .load_async::<(Uuid, Uuid)>(conn)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?
+            .into_iter()
+            .collect();
+
+        Ok(regions
+            .into_iter()
+            .filter_map(|region| {
+                let disk_id = *disks.get(&region.volume_id())?;
+                Some((region, disk_id))
+            })
+            .collect())
+    }
+
+    /// Records requests to replace regions, skipping regions that already
+    /// have one
+    pub async fn region_replacements_create(
+        &self,
+        opctx: &OpContext,
+        replacements: Vec<RegionReplacement>,
+    ) -> Result<usize, Error> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+        use db::schema::region_replacement::dsl;
+        diesel::insert_into(dsl::region_replacement)
+            .values(replacements)
+            .on_conflict(dsl::old_region_id)
+            .do_nothing()
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Lists region replacements, newest and oldest alike
+    pub async fn region_replacement_list(
+        &self,
+        opctx: &OpContext,
+        pagparams: &DataPageParams<'_, Uuid>,
+    ) -> ListResultVec<RegionReplacement> {
+        opctx.authorize(authz::Action::Read, &authz::FLEET).await?;
+        use db::schema::region_replacement::dsl;
+        paginated(dsl::region_replacement, dsl::id, pagparams)
+            .select(RegionReplacement::as_select())
+            .load_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Lists the region replacements that are in `state`, oldest first
+    pub async fn region_replacement_list_in_state(
+        &self,
+        opctx: &OpContext,
+        state: RegionReplacementState,
+    ) -> ListResultVec<RegionReplacement> {
+        opctx.authorize(authz::Action::Read, &authz::FLEET).await?;
+        use db::schema::region_replacement::dsl;
+        dsl::region_replacement
+            .filter(dsl::state.eq(state))
+            .order(dsl::time_created.asc())
+            .select(RegionReplacement::as_select())
+            .load_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Fetches a region replacement by ID
+    pub async fn region_replacement_get(
+        &self,
+        opctx: &OpContext,
+        replacement_id: Uuid,
+    ) -> Result<RegionReplacement, Error> {
+        opctx.authorize(authz::Action::Read, &authz::FLEET).await?;
+        use db::schema::region_replacement::dsl;
+        dsl::region_replacement
+            .filter(dsl::id.eq(replacement_id))
+            .select(RegionReplacement::as_select())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Lists the IDs of a volume's regions that have been lost, i.e. that have
+    /// a replacement request
+    pub async fn region_replacement_old_region_ids(
+        &self,
+        opctx: &OpContext,
+        volume_id: Uuid,
+    ) -> ListResultVec<Uuid> {
+        opctx.authorize(authz::Action::Read, &authz::FLEET).await?;
+        use db::schema::region_replacement::dsl;
+        dsl::region_replacement
+            .filter(dsl::volume_id.eq(volume_id))
+            .select(dsl::old_region_id)
+            .load_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Moves a region replacement from state `from` to state `to`, returning
+    /// whether it was in state `from`
+    ///
+    /// Moving a replacement to `AwaitingRestart` records `restart`, the
+    /// instance whose Propolis is still using the old region, and moving it
+    /// anywhere other than back to `Requested` clears the error from any
+    /// earlier attempt.
+    pub async fn region_replacement_set_state(
+        &self,
+        opctx: &OpContext,
+        replacement_id: Uuid,
+        from: RegionReplacementState,
+        to: RegionReplacementState,
+        new_region_id: Option<Uuid>,
+        restart: Option<RegionReplacementRestart>,
+    ) -> UpdateResult<bool> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+        use db::schema::region_replacement::dsl;
+        let instance_id = restart.map(|restart| restart.instance_id);
+        let propolis_id = restart.map(|restart| restart.propolis_id);
+        let query = diesel::update(dsl::region_replacement)
+            .filter(dsl::id.eq(replacement_id))
+            .filter(dsl::state.eq(from));
+        let conn = self.pool_authorized(opctx).await?;
+        let updated = if to == RegionReplacementState::Requested {
+            query
+                .set((
+                    dsl::state.eq(to),
+                    dsl::new_region_id.eq(new_region_id),
+                    dsl::instance_id.eq(instance_id),
+                    dsl::propolis_id.eq(propolis_id),
+                    dsl::time_modified.eq(Utc::now()),
+                ))
+                .execute_async(conn)
+                .await
+        } else {
+            query
+                .set((
+                    dsl::state.eq(to),
+                    dsl::new_region_id.eq(new_region_id),
+                    dsl::instance_id.eq(instance_id),
+                    dsl::propolis_id.eq(propolis_id),
+                    dsl::error.eq(None::<String>),
+                    dsl::time_modified.eq(Utc::now()),
+                ))
+                .execute_async(conn)
+                .await
+        }
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))?;
+        Ok(updated != 0)
+    }
+
+    /// Records why the last attempt at replacing a region failed
+    pub async fn region_replacement_set_error(
+        &self,
+        opctx: &OpContext,
+        replacement_id: Uuid,
+        error: String,
+    ) -> UpdateResult<()> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+        use db::schema::region_replacement::dsl;
+        diesel::update(dsl::region_replacement)
+            .filter(dsl::id.eq(replacement_id))
+            .set((dsl::error.eq(error), dsl::time_modified.eq(Utc::now())))
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map(|_| ())
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Allocates a region with ID `new_region_id` to replace `old_region`
+    /// within its volume, returning it along with its dataset
+    ///
+    /// The new region is the same size as the old one, and is placed on a
+    /// Crucible dataset that isn't lost and that doesn't already hold a region
+    /// of the same volume.  Datasets on sleds that don't hold any of the
+    /// volume's other regions are preferred, then the least used datasets.
+    /// If the region already exists, it's returned as is, so that this can be
+    /// replayed from a saga node.
+    pub async fn region_replacement_allocate(
+        &self,
+        opctx: &OpContext,
+        new_region_id: Uuid,
+        old_region: &Region,
+    ) -> CreateResult<(Dataset, Region)> {
+        #[derive(Debug)]
+        enum RegionReplacementAllocateError {
+            NotEnoughDatasets,
+        }
+        type TxnError = TransactionError<RegionReplacementAllocateError>;
+
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+
+        let volume_id = old_region.volume_id();
+        let block_size = old_region.block_size();
+        let blocks_per_extent = old_region.blocks_per_extent();
+        let extent_count = old_region.extent_count();
+        let region_size = i64::try_from(block_size.to_bytes())
+            .unwrap()
+            .saturating_mul(blocks_per_extent)
+            .saturating_mul(extent_count);
+
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                use db::schema::dataset::dsl as dataset_dsl;
+                use db::schema::physical_disk::dsl as physical_disk_dsl;
+                use db::schema::region::dsl as region_dsl;
+                use db::schema::sled::dsl as sled_dsl;
+                use db::schema::zpool::dsl as zpool_dsl;
+
+                // If this region was already allocated, return it.
+                let existing = region_dsl::region
+                    .filter(region_dsl::id.eq(new_region_id))
+                    .inner_join(
+                        dataset_dsl::dataset
+                            .on(region_dsl::dataset_id.eq(dataset_dsl::id)),
+                    )
+                    .select((Dataset::as_select(), Region::as_select()))
+                    .get_results_async::<(Dataset, Region)>(&conn)
+                    .await?;
+                if let Some(existing) = existing.into_iter().next() {
+                    return Ok(existing);
+                }
+
+                // Find the datasets and sleds already used by the volume.
+                let used_datasets: BTreeSet<Uuid> = region_dsl::region
+                    .filter(region_dsl::volume_id.eq(volume_id))
+                    .select(region_dsl::dataset_id)
+                    .get_results_async::<Uuid>(&conn)
+                    .await?
+                    .into_iter()
+                    .collect();
+                let used_sleds: BTreeSet<Uuid> = zpool_dsl::zpool
+                    .filter(
+                        zpool_dsl::id.eq_any(
+                            dataset_dsl::dataset
+                                .filter(
+                                    dataset_dsl::id.eq_any(
+                                        used_datasets
+                                            .iter()
+                                            .copied()
+                                            .collect::<Vec<_>>(),
+                                    ),
+                                )
+                                .select(dataset_dsl::pool_id),
+                        ),
+                    )
+                    .select(zpool_dsl::sled_id)
+                    .get_results_async::<Uuid>(&conn)
+                    .await?
+                    .into_iter()
+                    .collect();
+
+                // Find the zpools that new regions may be placed on: those
+                // whose physical disks are still present, on healthy sleds.
+                let zpools: BTreeMap<Uuid, Zpool> = zpool_dsl::zpool
+                    .filter(zpool_dsl::time_deleted.is_null())
+                    .filter(diesel::dsl::not(
+                        zpool_dsl::physical_disk_id.eq_any(
+                            physical_disk_dsl::physical_disk
+                                .filter(
+                                    physical_disk_dsl::time_deleted
+                                        .is_not_null(),
+                                )
+                                .select(physical_disk_dsl::id),
+                        ),
+                    ))
+                    .filter(
+                        zpool_dsl::sled_id.eq_any(
+                            sled_dsl::sled
+                                .filter(sled_dsl::time_deleted.is_null())
+                                .filter(sled_dsl::state.eq(SledState::Active))
+                                .select(sled_dsl::id),
+                        ),
+                    )
+                    .select(Zpool::as_select())
+                    .get_results_async::<Zpool>(&conn)
+                    .await?
+                    .into_iter()
+                    .map(|zpool| (zpool.id(), zpool))
+                    .collect();
+
+                let datasets = dataset_dsl::dataset
+                    .filter(dataset_dsl::time_deleted.is_null())
+                    .filter(
+                        dataset_dsl::pool_id
+                            .eq_any(zpools.keys().copied().collect::<Vec<_>>()),
+                    )
+                    .select(Dataset::as_select())
+                    .get_results_async::<Dataset>(&conn)
+                    .await?;
+
+                // A region fits on a zpool if the zpool has room for it on top
+                // of everything used by the zpool's datasets.
+                let mut zpool_size_used: BTreeMap<Uuid, i64> = BTreeMap::new();
+                for dataset in &datasets {
+                    *zpool_size_used.entry(dataset.pool_id).or_default() +=
+                        dataset.size_used.unwrap_or(0);
+                }
+
+                let dataset = datasets
+                    .into_iter()
+                    .filter(|dataset| {
+                        dataset.kind == DatasetKind::Crucible
+                            && dataset.size_used.is_some()
+                            && !used_datasets.contains(&dataset.id())
+                    })
+                    .filter(|dataset| {
+                        let zpool = &zpools[&dataset.pool_id];
+                        zpool_size_used[&dataset.pool_id]
+                            .saturating_add(region_size)
+                            <= i64::from(zpool.total_size)
+                    })
+                    .min_by_key(|dataset| {
+                        let sled_id = zpools[&dataset.pool_id].sled_id;
+                        (used_sleds.contains(&sled_id), dataset.size_used)
+                    })
+                    .ok_or(TxnError::CustomError(
+                        RegionReplacementAllocateError::NotEnoughDatasets,
+                    ))?;
+
+                let region = Region::new(
+                    new_region_id,
+                    dataset.id(),
+                    volume_id,
+                    block_size.into(),
+                    blocks_per_extent,
+                    extent_count,
+                );
+                let region = diesel::insert_into(region_dsl::region)
+                    .values(region)
+                    .returning(Region::as_returning())
+                    .get_result_async(&conn)
+                    .await?;
+
+                let dataset = diesel::update(dataset_dsl::dataset)
+                    .filter(dataset_dsl::id.eq(dataset.id()))
+                    .set(
+                        dataset_dsl::size_used
+                            .eq(dataset_dsl::size_used + region_size),
+                    )
+                    .returning(Dataset::as_returning())
+                    .get_result_async(&conn)
+                    .await?;
+
+                Ok((dataset, region))
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(
+                    RegionReplacementAllocateError::NotEnoughDatasets,
+                ) => Error::unavail("Not enough datasets to replace region"),
+                TxnError::Pool(e) => {
+                    public_error_from_diesel_pool(e, ErrorHandler::Server)
+                }
+            })
+    }
+}
@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! [`DataStore`] methods related to [`ScimClientToken`]s.
+
+use super::DataStore;
+use crate::authz;
+use crate::context::OpContext;
+use crate::db;
+use crate::db::error::public_error_from_diesel_pool;
+use crate::db::error::ErrorHandler;
+use crate::db::identity::Resource;
+use crate::db::model::Name;
+use crate::db::model::ScimClientToken;
+use crate::db::pagination::paginated;
+use crate::db::update_and_check::UpdateAndCheck;
+use async_bb8_diesel::AsyncRunQueryDsl;
+use chrono::Utc;
+use diesel::prelude::*;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::LookupType;
+use omicron_common::api::external::ResourceType;
+use ref_cast::RefCast;
+
+impl DataStore {
+    /// List the SCIM client tokens of a Silo
+    ///
+    /// Tokens are only visible to those who could create them, so this
+    /// requires permission to modify the Silo rather than to list its
+    /// children.
+    pub async fn scim_client_tokens_list(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<ScimClientToken> {
+        opctx.authorize(authz::Action::Modify, authz_silo).await?;
+
+        use db::schema::scim_client_token::dsl;
+        match pagparams {
+            PaginatedBy::Id(pagparams) => {
+                paginated(dsl::scim_client_token, dsl::id, &pagparams)
+            }
+            PaginatedBy::Name(pagparams) => paginated(
+                dsl::scim_client_token,
+                dsl::name,
+                &pagparams.map_name(|n| Name::ref_cast(n)),
+            ),
+        }
+        .filter(dsl::silo_id.eq(authz_silo.id()))
+        .filter(dsl::time_deleted.is_null())
+        .select(ScimClientToken::as_select())
+        .load_async(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Create a new SCIM client token for a Silo.
+    pub async fn scim_client_token_create(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        scim_client_token: ScimClientToken,
+    ) -> CreateResult<ScimClientToken> {
+        assert_eq!(authz_silo.id(), scim_client_token.silo_id);
+        opctx.authorize(authz::Action::Modify, authz_silo).await?;
+        let name = scim_client_token.name().to_string();
+
+        use db::schema::scim_client_token::dsl;
+        diesel::insert_into(dsl::scim_client_token)
+            .values(scim_client_token)
+            .returning(ScimClientToken::as_returning())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::Conflict(
+                        ResourceType::ScimClientToken,
+                        &name,
+                    ),
+                )
+            })
+    }
+
+    /// Delete (revoke) an existing SCIM client token.
+    pub async fn scim_client_token_delete(
+        &self,
+        opctx: &OpContext,
+        authz_scim_client_token: &authz::ScimClientToken,
+    ) -> DeleteResult {
+        opctx.authorize(authz::Action::Delete, authz_scim_client_token).await?;
+
+        use db::schema::scim_client_token::dsl;
+        diesel::update(dsl::scim_client_token)
+            .filter(dsl::id.eq(authz_scim_client_token.id()))
+            .filter(dsl::time_deleted.is_null())
+            .set(dsl::time_deleted.eq(Utc::now()))
+            .check_if_exists::<ScimClientToken>(authz_scim_client_token.id())
+            .execute_and_check(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_scim_client_token),
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Look up a SCIM client token that has not been deleted by the token
+    /// itself.
+    ///
+    /// Like `access_token_fetch_by_token`, this does not include any authz
+    /// checks: this is how we find out which Silo a SCIM request is for in
+    /// the first place.
+    pub async fn scim_client_token_fetch_by_token(
+        &self,
+        opctx: &OpContext,
+        token: String,
+    ) -> LookupResult<ScimClientToken> {
+        use db::schema::scim_client_token::dsl;
+        dsl::scim_client_token
+            .filter(dsl::token.eq(token))
+            .filter(dsl::time_deleted.is_null())
+            .select(ScimClientToken::as_select())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByLookup(
+                        ResourceType::ScimClientToken,
+                        LookupType::ByCompositeId("token".to_string()),
+                    ),
+                )
+            })
+    }
+}
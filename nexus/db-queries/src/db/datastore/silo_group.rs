@@ -26,9 +26,19 @@ use omicron_common::api::external::Error;
 use omicron_common::api::external::InternalContext;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::ResourceType;
 use omicron_common::api::external::UpdateResult;
 use uuid::Uuid;
 
+/// Describes a change to the members of a silo group
+pub enum SiloGroupMembershipUpdate {
+    /// Make these users the only members of the group
+    Replace(Vec<Uuid>),
+    /// Add some users to the group (if they're not members already) and
+    /// remove others (if they are)
+    Modify { add: Vec<Uuid>, remove: Vec<Uuid> },
+}
+
 impl DataStore {
     pub(super) async fn silo_group_ensure_query(
         opctx: &OpContext,
@@ -106,6 +116,22 @@ impl DataStore {
             .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
     }
 
+    pub async fn silo_group_membership_for_group(
+        &self,
+        opctx: &OpContext,
+        authz_silo_group: &authz::SiloGroup,
+    ) -> ListResultVec<SiloGroupMembership> {
+        opctx.authorize(authz::Action::Read, authz_silo_group).await?;
+
+        use db::schema::silo_group_membership::dsl;
+        dsl::silo_group_membership
+            .filter(dsl::silo_group_id.eq(authz_silo_group.id()))
+            .select(SiloGroupMembership::as_returning())
+            .get_results_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
     pub async fn silo_groups_for_self(
         &self,
         opctx: &OpContext,
@@ -181,6 +207,144 @@ impl DataStore {
             .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
     }
 
+    /// Update a silo group's members, as one transaction
+    ///
+    /// This is the group-centric counterpart of
+    /// `silo_group_membership_replace_for_user`, for callers (like SCIM
+    /// clients) that manage membership from the group's side.  Users being
+    /// added must exist in the group's Silo.
+    pub async fn silo_group_membership_update(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        authz_silo_group: &authz::SiloGroup,
+        update: SiloGroupMembershipUpdate,
+    ) -> UpdateResult<()> {
+        opctx.authorize(authz::Action::Modify, authz_silo_group).await?;
+
+        #[derive(Debug, thiserror::Error)]
+        enum MembershipUpdateError {
+            #[error("user {0} does not exist in this silo")]
+            NoSuchUser(Uuid),
+        }
+        type TxnError = TransactionError<MembershipUpdateError>;
+
+        let silo_id = authz_silo.id();
+        let group_id = authz_silo_group.id();
+
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                use db::schema::silo_group_membership::dsl;
+
+                // Make sure that any users being added are in this Silo
+                let new_member_ids = match &update {
+                    SiloGroupMembershipUpdate::Replace(ids) => ids.clone(),
+                    SiloGroupMembershipUpdate::Modify { add, .. } => {
+                        add.clone()
+                    }
+                };
+                if !new_member_ids.is_empty() {
+                    use db::schema::silo_user;
+                    let found_ids: Vec<Uuid> = silo_user::dsl::silo_user
+                        .filter(
+                            silo_user::dsl::id.eq_any(new_member_ids.clone()),
+                        )
+                        .filter(silo_user::dsl::silo_id.eq(silo_id))
+                        .filter(silo_user::dsl::time_deleted.is_null())
+                        .select(silo_user::dsl::id)
+                        .load_async(&conn)
+                        .await?;
+                    if let Some(missing_id) =
+                        new_member_ids.iter().find(|id| !found_ids.contains(id))
+                    {
+                        return Err(TxnError::CustomError(
+                            MembershipUpdateError::NoSuchUser(*missing_id),
+                        ));
+                    }
+                }
+
+                let mut to_insert = match update {
+                    SiloGroupMembershipUpdate::Replace(ids) => {
+                        diesel::delete(dsl::silo_group_membership)
+                            .filter(dsl::silo_group_id.eq(group_id))
+                            .execute_async(&conn)
+                            .await?;
+                        ids
+                    }
+                    SiloGroupMembershipUpdate::Modify { add, remove } => {
+                        diesel::delete(dsl::silo_group_membership)
+                            .filter(dsl::silo_group_id.eq(group_id))
+                            .filter(dsl::silo_user_id.eq_any(remove))
+                            .execute_async(&conn)
+                            .await?;
+                        add
+                    }
+                };
+
+                to_insert.sort();
+                to_insert.dedup();
+                let silo_group_memberships: Vec<SiloGroupMembership> =
+                    to_insert
+                        .into_iter()
+                        .map(|user_id| {
+                            SiloGroupMembership::new(group_id, user_id)
+                        })
+                        .collect();
+                diesel::insert_into(dsl::silo_group_membership)
+                    .values(silo_group_memberships)
+                    .on_conflict_do_nothing()
+                    .execute_async(&conn)
+                    .await?;
+
+                Ok(())
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(MembershipUpdateError::NoSuchUser(
+                    id,
+                )) => Error::invalid_request(&format!(
+                    "user {} does not exist in this silo",
+                    id
+                )),
+                TxnError::Pool(pool_error) => public_error_from_diesel_pool(
+                    pool_error,
+                    ErrorHandler::Server,
+                ),
+            })
+    }
+
+    /// Change the external id (i.e., the display name) of a silo group
+    pub async fn silo_group_update_external_id(
+        &self,
+        opctx: &OpContext,
+        authz_silo_group: &authz::SiloGroup,
+        external_id: String,
+    ) -> UpdateResult<SiloGroup> {
+        opctx.authorize(authz::Action::Modify, authz_silo_group).await?;
+
+        use db::schema::silo_group::dsl;
+        diesel::update(dsl::silo_group)
+            .filter(dsl::id.eq(authz_silo_group.id()))
+            .filter(dsl::time_deleted.is_null())
+            .set((
+                dsl::external_id.eq(external_id.clone()),
+                dsl::time_modified.eq(Utc::now()),
+            ))
+            .returning(SiloGroup::as_returning())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::Conflict(
+                        ResourceType::SiloGroup,
+                        &external_id,
+                    ),
+                )
+            })
+    }
+
     pub async fn silo_group_delete(
         &self,
         opctx: &OpContext,
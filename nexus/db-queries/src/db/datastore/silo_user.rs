@@ -17,6 +17,7 @@ use crate::db::model::Silo;
 use crate::db::model::SiloUser;
 use crate::db::model::SiloUserPasswordHash;
 use crate::db::model::SiloUserPasswordUpdate;
+use crate::db::model::SiloUserUpdate;
 use crate::db::model::UserBuiltin;
 use crate::db::model::UserProvisionType;
 use crate::db::pagination::paginated;
@@ -124,6 +125,17 @@ impl DataStore {
                         .await?;
                 }
 
+                // Delete access tokens.
+                {
+                    use db::schema::access_token::dsl;
+                    diesel::update(dsl::access_token)
+                        .filter(dsl::silo_user_id.eq(authz_silo_user_id))
+                        .filter(dsl::time_deleted.is_null())
+                        .set(dsl::time_deleted.eq(Utc::now()))
+                        .execute_async(&mut conn)
+                        .await?;
+                }
+
                 // Delete group memberships.
                 {
                     use db::schema::silo_group_membership::dsl;
@@ -156,6 +168,83 @@ impl DataStore {
             })
     }
 
+    /// Update a silo user's external id and/or whether they're active
+    ///
+    /// Deactivating a user also deletes their console sessions and tokens so
+    /// that they're logged out everywhere.  Unlike `silo_user_delete`, this
+    /// leaves the user, their group memberships, and their SSH keys in place,
+    /// so that they can be reactivated later.
+    pub async fn silo_user_update(
+        &self,
+        opctx: &OpContext,
+        authz_silo_user: &authz::SiloUser,
+        update: SiloUserUpdate,
+    ) -> UpdateResult<SiloUser> {
+        opctx.authorize(authz::Action::Modify, authz_silo_user).await?;
+
+        let deactivating = update.active == Some(false);
+        let new_external_id = update.external_id.clone();
+        let authz_silo_user_id = authz_silo_user.id();
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|mut conn| async move {
+                let db_silo_user = {
+                    use db::schema::silo_user::dsl;
+                    diesel::update(dsl::silo_user)
+                        .filter(dsl::id.eq(authz_silo_user_id))
+                        .filter(dsl::time_deleted.is_null())
+                        .set(update)
+                        .returning(SiloUser::as_returning())
+                        .get_result_async(&mut conn)
+                        .await?
+                };
+
+                if deactivating {
+                    // Delete console sessions.
+                    {
+                        use db::schema::console_session::dsl;
+                        diesel::delete(dsl::console_session)
+                            .filter(dsl::silo_user_id.eq(authz_silo_user_id))
+                            .execute_async(&mut conn)
+                            .await?;
+                    }
+
+                    // Delete device authentication tokens.
+                    {
+                        use db::schema::device_access_token::dsl;
+                        diesel::delete(dsl::device_access_token)
+                            .filter(dsl::silo_user_id.eq(authz_silo_user_id))
+                            .execute_async(&mut conn)
+                            .await?;
+                    }
+
+                    // Delete access tokens.
+                    {
+                        use db::schema::access_token::dsl;
+                        diesel::update(dsl::access_token)
+                            .filter(dsl::silo_user_id.eq(authz_silo_user_id))
+                            .filter(dsl::time_deleted.is_null())
+                            .set(dsl::time_deleted.eq(Utc::now()))
+                            .execute_async(&mut conn)
+                            .await?;
+                    }
+                }
+
+                Ok(db_silo_user)
+            })
+            .await
+            .map_err(|e| match &new_external_id {
+                Some(external_id) => public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::Conflict(ResourceType::SiloUser, external_id),
+                ),
+                None => public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_silo_user),
+                ),
+            })
+    }
+
     /// Given an external ID, return
     /// - Ok(Some((authz::SiloUser, SiloUser))) if that external id refers to an
     ///   existing silo user
@@ -12,8 +12,12 @@ use crate::db::error::public_error_from_diesel_pool;
 use crate::db::error::ErrorHandler;
 use crate::db::error::TransactionError;
 use crate::db::identity::Asset;
+use crate::db::model::AffinityGroupKind;
+use crate::db::model::AffinityPolicy;
+use crate::db::model::Instance;
 use crate::db::model::Sled;
 use crate::db::model::SledResource;
+use crate::db::model::SledState;
 use crate::db::pagination::paginated;
 use async_bb8_diesel::AsyncConnection;
 use async_bb8_diesel::AsyncRunQueryDsl;
@@ -25,6 +29,8 @@ use omicron_common::api::external::DataPageParams;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::ResourceType;
+use omicron_common::api::external::UpdateResult;
+use std::collections::BTreeSet;
 use uuid::Uuid;
 
 impl DataStore {
@@ -70,17 +76,182 @@ impl DataStore {
             .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
     }
 
+    /// Records the health of a sled as observed by Nexus
+    ///
+    /// An expunged sled is never moved to another state.  Returns whether the
+    /// sled's state changed.
+    pub async fn sled_set_state(
+        &self,
+        opctx: &OpContext,
+        sled_id: Uuid,
+        state: SledState,
+    ) -> UpdateResult<bool> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+        use db::schema::sled::dsl;
+        let updated = diesel::update(dsl::sled)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(sled_id))
+            .filter(dsl::state.ne(SledState::Expunged))
+            .filter(dsl::state.ne(state))
+            .set((dsl::state.eq(state), dsl::time_state_updated.eq(Utc::now())))
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        Ok(updated != 0)
+    }
+
+    /// Sets whether new instances may be placed on a sled
+    pub async fn sled_set_schedulable(
+        &self,
+        opctx: &OpContext,
+        authz_sled: &authz::Sled,
+        schedulable: bool,
+    ) -> UpdateResult<Sled> {
+        opctx.authorize(authz::Action::Modify, authz_sled).await?;
+        use db::schema::sled::dsl;
+        diesel::update(dsl::sled)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(authz_sled.id()))
+            .set((
+                dsl::schedulable.eq(schedulable),
+                dsl::time_modified.eq(Utc::now()),
+            ))
+            .returning(Sled::as_returning())
+            .get_result_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_sled),
+                )
+            })
+    }
+
+    /// Lists the sleds that are being drained of their instances
+    ///
+    /// Expunged sleds aren't included, since their instances are gone anyway.
+    pub async fn sled_list_draining(
+        &self,
+        opctx: &OpContext,
+        pagparams: &DataPageParams<'_, Uuid>,
+    ) -> ListResultVec<Sled> {
+        opctx.authorize(authz::Action::Read, &authz::FLEET).await?;
+        use db::schema::sled::dsl;
+        paginated(dsl::sled, dsl::id, pagparams)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::schedulable.eq(false))
+            .filter(dsl::state.ne(SledState::Expunged))
+            .select(Sled::as_select())
+            .load_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Lists the instances assigned to a sled
+    ///
+    /// A sled only has room for so many instances, so the list isn't
+    /// paginated.
+    pub async fn sled_instance_list(
+        &self,
+        opctx: &OpContext,
+        authz_sled: &authz::Sled,
+    ) -> ListResultVec<Instance> {
+        opctx.authorize(authz::Action::Read, authz_sled).await?;
+        use db::schema::instance::dsl;
+        dsl::instance
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::active_server_id.eq(authz_sled.id()))
+            .order(dsl::id)
+            .select(Instance::as_select())
+            .load_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Permanently removes a sled from service
+    ///
+    /// In the same transaction, every instance that was on the sled is marked
+    /// failed and every sled reservation held on the sled is released.
+    /// Returns the updated sled along with the IDs of the instances that were
+    /// marked failed.
+    pub async fn sled_expunge(
+        &self,
+        opctx: &OpContext,
+        authz_sled: &authz::Sled,
+    ) -> UpdateResult<(Sled, Vec<Uuid>)> {
+        opctx.authorize(authz::Action::Modify, authz_sled).await?;
+
+        use db::model::InstanceState as DbInstanceState;
+        use db::schema::instance::dsl as instance_dsl;
+        use db::schema::sled::dsl;
+        use db::schema::sled_resource::dsl as resource_dsl;
+        use external::InstanceState as ApiInstanceState;
+
+        let sled_id = authz_sled.id();
+        let now = Utc::now();
+        self.pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                let sled = diesel::update(dsl::sled)
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(dsl::id.eq(sled_id))
+                    .set((
+                        dsl::state.eq(SledState::Expunged),
+                        dsl::time_state_updated.eq(now),
+                    ))
+                    .returning(Sled::as_returning())
+                    .get_result_async(&conn)
+                    .await?;
+
+                let failed_instances = diesel::update(instance_dsl::instance)
+                    .filter(instance_dsl::time_deleted.is_null())
+                    .filter(instance_dsl::active_server_id.eq(sled_id))
+                    .filter(instance_dsl::state.ne_all(vec![
+                        DbInstanceState::new(ApiInstanceState::Failed),
+                        DbInstanceState::new(ApiInstanceState::Destroyed),
+                    ]))
+                    .set((
+                        instance_dsl::state
+                            .eq(DbInstanceState::new(ApiInstanceState::Failed)),
+                        instance_dsl::state_generation
+                            .eq(instance_dsl::state_generation + 1),
+                        instance_dsl::time_state_updated.eq(now),
+                    ))
+                    .returning(instance_dsl::id)
+                    .get_results_async(&conn)
+                    .await?;
+
+                diesel::delete(resource_dsl::sled_resource)
+                    .filter(resource_dsl::sled_id.eq(sled_id))
+                    .execute_async(&conn)
+                    .await?;
+
+                Ok((sled, failed_instances))
+            })
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_sled),
+                )
+            })
+    }
+
     pub async fn sled_reservation_create(
         &self,
         opctx: &OpContext,
         resource_id: Uuid,
         resource_kind: db::model::SledResourceKind,
+        instance_id: Option<Uuid>,
         resources: db::model::Resources,
         constraints: db::model::SledReservationConstraints,
     ) -> CreateResult<db::model::SledResource> {
         #[derive(Debug)]
         enum SledReservationError {
             NotFound,
+            AffinityConflict,
         }
         type TxnError = TransactionError<SledReservationError>;
 
@@ -100,6 +271,75 @@ impl DataStore {
                     return Ok(old_resource[0].clone());
                 }
 
+                // Work out which sleds the affinity groups allow from where
+                // their members are placed now.  Doing this in the same
+                // transaction as the reservation means that members placed
+                // concurrently can't both miss each other.
+                let mut affinity_must_select_from: Option<BTreeSet<Uuid>> =
+                    None;
+                let mut affinity_must_not_select_from = Vec::new();
+                let mut affinity_prefer_select_from = Vec::new();
+                let mut affinity_prefer_not_select_from = Vec::new();
+                if let Some(group_ids) = constraints.affinity_groups() {
+                    let placements = self
+                        .affinity_group_placements_on_connection(
+                            &conn,
+                            group_ids,
+                            instance_id,
+                        )
+                        .await?;
+                    for (kind, policy, sleds) in placements {
+                        match (kind, policy) {
+                            (
+                                AffinityGroupKind::Affinity,
+                                AffinityPolicy::Fail,
+                            ) => {
+                                // An instance in several such groups has to
+                                // run alongside the members of all of them.
+                                // Groups with no members placed anywhere
+                                // don't constrain it.
+                                if sleds.is_empty() {
+                                    continue;
+                                }
+                                let sleds: BTreeSet<Uuid> =
+                                    sleds.into_iter().collect();
+                                affinity_must_select_from =
+                                    Some(match affinity_must_select_from {
+                                        Some(required) => required
+                                            .intersection(&sleds)
+                                            .copied()
+                                            .collect(),
+                                        None => sleds,
+                                    });
+                            }
+                            (
+                                AffinityGroupKind::Affinity,
+                                AffinityPolicy::Allow,
+                            ) => {
+                                affinity_prefer_select_from.extend(sleds);
+                            }
+                            (
+                                AffinityGroupKind::AntiAffinity,
+                                AffinityPolicy::Fail,
+                            ) => {
+                                affinity_must_not_select_from.extend(sleds);
+                            }
+                            (
+                                AffinityGroupKind::AntiAffinity,
+                                AffinityPolicy::Allow,
+                            ) => {
+                                affinity_prefer_not_select_from.extend(sleds);
+                            }
+                        }
+                    }
+                }
+                if matches!(&affinity_must_select_from, Some(s) if s.is_empty())
+                {
+                    return Err(TxnError::CustomError(
+                        SledReservationError::AffinityConflict,
+                    ));
+                }
+
                 // If it doesn't already exist, find a sled with enough space
                 // for the resources we're requesting.
                 use db::schema::sled::dsl as sled_dsl;
@@ -135,6 +375,10 @@ impl DataStore {
                         // TODO: We should also validate the reservoir space, when it exists.
                     )
                     .filter(sled_dsl::time_deleted.is_null())
+                    // Only sleds that are known to be healthy and that aren't
+                    // being drained get new work.
+                    .filter(sled_dsl::state.eq(SledState::Active))
+                    .filter(sled_dsl::schedulable.eq(true))
                     .select(sled_dsl::id)
                     .into_boxed();
 
@@ -144,10 +388,58 @@ impl DataStore {
                     sled_targets = sled_targets
                         .filter(sled_dsl::id.eq_any(must_select_from.to_vec()));
                 }
+                if let Some(must_not_select_from) =
+                    constraints.must_not_select_from()
+                {
+                    sled_targets = sled_targets.filter(
+                        sled_dsl::id.ne_all(must_not_select_from.to_vec()),
+                    );
+                }
+                if let Some(must_select_from) = affinity_must_select_from {
+                    sled_targets = sled_targets.filter(sled_dsl::id.eq_any(
+                        must_select_from.into_iter().collect::<Vec<_>>(),
+                    ));
+                }
+                if !affinity_must_not_select_from.is_empty() {
+                    sled_targets = sled_targets.filter(
+                        sled_dsl::id.ne_all(affinity_must_not_select_from),
+                    );
+                }
+
+                // Among the sleds that remain, pick preferred sleds first and
+                // sleds the caller would rather avoid last.
+                if let Some(prefer_select_from) =
+                    constraints.prefer_select_from()
+                {
+                    sled_targets = sled_targets.then_order_by(
+                        sled_dsl::id.eq_any(prefer_select_from.to_vec()).desc(),
+                    );
+                }
+                if let Some(prefer_not_select_from) =
+                    constraints.prefer_not_select_from()
+                {
+                    sled_targets = sled_targets.then_order_by(
+                        sled_dsl::id
+                            .eq_any(prefer_not_select_from.to_vec())
+                            .asc(),
+                    );
+                }
+                if !affinity_prefer_select_from.is_empty() {
+                    sled_targets = sled_targets.then_order_by(
+                        sled_dsl::id.eq_any(affinity_prefer_select_from).desc(),
+                    );
+                }
+                if !affinity_prefer_not_select_from.is_empty() {
+                    sled_targets = sled_targets.then_order_by(
+                        sled_dsl::id
+                            .eq_any(affinity_prefer_not_select_from)
+                            .asc(),
+                    );
+                }
 
                 sql_function!(fn random() -> diesel::sql_types::Float);
                 let sled_targets = sled_targets
-                    .order(random())
+                    .then_order_by(random())
                     .limit(1)
                     .get_results_async::<Uuid>(&conn)
                     .await?;
@@ -164,6 +456,7 @@ impl DataStore {
                     resource_id,
                     sled_targets[0],
                     resource_kind,
+                    instance_id,
                     resources,
                 );
 
@@ -180,6 +473,12 @@ impl DataStore {
                         "No sleds can fit the requested instance",
                     )
                 }
+                TxnError::CustomError(
+                    SledReservationError::AffinityConflict,
+                ) => external::Error::invalid_request(
+                    "the instance's affinity groups with the \"fail\" policy \
+                    have no sled in common",
+                ),
                 TxnError::Pool(e) => {
                     public_error_from_diesel_pool(e, ErrorHandler::Server)
                 }
@@ -12,6 +12,7 @@ use crate::db::collection_insert::AsyncInsertError;
 use crate::db::collection_insert::DatastoreCollection;
 use crate::db::error::public_error_from_diesel_pool;
 use crate::db::error::ErrorHandler;
+use crate::db::model::labels_match;
 use crate::db::model::Generation;
 use crate::db::model::Name;
 use crate::db::model::Project;
@@ -25,6 +26,7 @@ use diesel::prelude::*;
 use omicron_common::api::external::http_pagination::PaginatedBy;
 use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupType;
 use omicron_common::api::external::ResourceType;
@@ -114,6 +116,7 @@ impl DataStore {
         opctx: &OpContext,
         authz_project: &authz::Project,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<Snapshot> {
         opctx.authorize(authz::Action::ListChildren, authz_project).await?;
 
@@ -130,6 +133,7 @@ impl DataStore {
         }
         .filter(dsl::time_deleted.is_null())
         .filter(dsl::project_id.eq(authz_project.id()))
+        .filter(labels_match(label_selector))
         .select(Snapshot::as_select())
         .load_async::<Snapshot>(self.pool_authorized(opctx).await?)
         .await
@@ -0,0 +1,239 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! [`DataStore`] methods on [`SnapshotSchedule`]s.
+
+use super::DataStore;
+use crate::authz;
+use crate::authz::ApiResource;
+use crate::context::OpContext;
+use crate::db;
+use crate::db::collection_insert::AsyncInsertError;
+use crate::db::collection_insert::DatastoreCollection;
+use crate::db::error::public_error_from_diesel_pool;
+use crate::db::error::ErrorHandler;
+use crate::db::identity::Resource;
+use crate::db::model::Name;
+use crate::db::model::Project;
+use crate::db::model::Snapshot;
+use crate::db::model::SnapshotSchedule;
+use crate::db::pagination::paginated;
+use async_bb8_diesel::AsyncRunQueryDsl;
+use chrono::DateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::ResourceType;
+use omicron_common::api::external::UpdateResult;
+use ref_cast::RefCast;
+use uuid::Uuid;
+
+impl DataStore {
+    pub async fn snapshot_schedule_create(
+        &self,
+        opctx: &OpContext,
+        authz_project: &authz::Project,
+        schedule: SnapshotSchedule,
+    ) -> CreateResult<SnapshotSchedule> {
+        use db::schema::snapshot_schedule::dsl;
+
+        opctx.authorize(authz::Action::CreateChild, authz_project).await?;
+
+        let name = schedule.name().clone();
+        let project_id = schedule.project_id;
+
+        Project::insert_resource(
+            project_id,
+            diesel::insert_into(dsl::snapshot_schedule).values(schedule),
+        )
+        .insert_and_get_result_async(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| match e {
+            AsyncInsertError::CollectionNotFound => authz_project.not_found(),
+            AsyncInsertError::DatabaseError(e) => {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::Conflict(
+                        ResourceType::SnapshotSchedule,
+                        name.as_str(),
+                    ),
+                )
+            }
+        })
+    }
+
+    pub async fn snapshot_schedule_list(
+        &self,
+        opctx: &OpContext,
+        authz_project: &authz::Project,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<SnapshotSchedule> {
+        opctx.authorize(authz::Action::ListChildren, authz_project).await?;
+
+        use db::schema::snapshot_schedule::dsl;
+        match pagparams {
+            PaginatedBy::Id(pagparams) => {
+                paginated(dsl::snapshot_schedule, dsl::id, &pagparams)
+            }
+            PaginatedBy::Name(pagparams) => paginated(
+                dsl::snapshot_schedule,
+                dsl::name,
+                &pagparams.map_name(|n| Name::ref_cast(n)),
+            ),
+        }
+        .filter(dsl::time_deleted.is_null())
+        .filter(dsl::project_id.eq(authz_project.id()))
+        .select(SnapshotSchedule::as_select())
+        .load_async::<SnapshotSchedule>(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Deletes a snapshot schedule.  Snapshots that it has already taken are
+    /// left alone.
+    pub async fn snapshot_schedule_delete(
+        &self,
+        opctx: &OpContext,
+        authz_schedule: &authz::SnapshotSchedule,
+    ) -> DeleteResult {
+        opctx.authorize(authz::Action::Delete, authz_schedule).await?;
+
+        use db::schema::snapshot_schedule::dsl;
+        let updated = diesel::update(dsl::snapshot_schedule)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(authz_schedule.id()))
+            .set(dsl::time_deleted.eq(Utc::now()))
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_schedule),
+                )
+            })?;
+        if updated == 0 {
+            return Err(authz_schedule.not_found());
+        }
+        Ok(())
+    }
+
+    /// Claims up to `limit` snapshot schedules whose next snapshot is due as
+    /// of `now`, returning them.
+    ///
+    /// Claiming a schedule advances its next snapshot time by one interval
+    /// (or to one interval from `now`, if the schedule has fallen more than
+    /// an interval behind).  This is done with a conditional update so that
+    /// if several Nexus instances look for due schedules at the same time,
+    /// each schedule is claimed by only one of them.
+    pub async fn snapshot_schedules_claim_due(
+        &self,
+        opctx: &OpContext,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> ListResultVec<SnapshotSchedule> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+
+        use db::schema::snapshot_schedule::dsl;
+        let pool = self.pool_authorized(opctx).await?;
+        let due = dsl::snapshot_schedule
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::time_next_snapshot.le(now))
+            .order(dsl::time_next_snapshot.asc())
+            .limit(i64::from(limit))
+            .select(SnapshotSchedule::as_select())
+            .load_async::<SnapshotSchedule>(pool)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+
+        let mut claimed = Vec::with_capacity(due.len());
+        for schedule in due {
+            let mut time_next =
+                schedule.time_next_snapshot + schedule.interval();
+            if time_next <= now {
+                time_next = now + schedule.interval();
+            }
+            let updated = diesel::update(dsl::snapshot_schedule)
+                .filter(dsl::time_deleted.is_null())
+                .filter(dsl::id.eq(schedule.id()))
+                .filter(dsl::time_next_snapshot.eq(schedule.time_next_snapshot))
+                .set(dsl::time_next_snapshot.eq(time_next))
+                .returning(SnapshotSchedule::as_returning())
+                .get_results_async::<SnapshotSchedule>(pool)
+                .await
+                .map_err(|e| {
+                    public_error_from_diesel_pool(e, ErrorHandler::Server)
+                })?;
+            claimed.extend(updated);
+        }
+        Ok(claimed)
+    }
+
+    /// Records the outcome of an attempt to take a snapshot for a snapshot
+    /// schedule
+    ///
+    /// On success, `result` is the time at which the snapshot was taken.  On
+    /// failure, it's a description of the error.
+    pub async fn snapshot_schedule_record_result(
+        &self,
+        opctx: &OpContext,
+        schedule_id: Uuid,
+        result: Result<DateTime<Utc>, String>,
+    ) -> UpdateResult<()> {
+        opctx.authorize(authz::Action::Modify, &authz::FLEET).await?;
+
+        use db::schema::snapshot_schedule::dsl;
+        let query = diesel::update(dsl::snapshot_schedule)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(schedule_id));
+        let pool = self.pool_authorized(opctx).await?;
+        match result {
+            Ok(time_snapshot) => {
+                query
+                    .set((
+                        dsl::time_last_snapshot.eq(time_snapshot),
+                        dsl::last_error.eq(None::<String>),
+                    ))
+                    .execute_async(pool)
+                    .await
+            }
+            Err(error) => {
+                query.set(dsl::last_error.eq(error)).execute_async(pool).await
+            }
+        }
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))?;
+        Ok(())
+    }
+
+    /// Returns the snapshots taken by a snapshot schedule beyond the newest
+    /// `retention_count`, oldest first
+    pub async fn snapshot_schedule_expired_snapshots(
+        &self,
+        opctx: &OpContext,
+        authz_schedule: &authz::SnapshotSchedule,
+        retention_count: u32,
+    ) -> ListResultVec<Snapshot> {
+        opctx.authorize(authz::Action::Read, authz_schedule).await?;
+
+        use db::schema::snapshot::dsl;
+        let mut expired = dsl::snapshot
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::snapshot_schedule_id.eq(authz_schedule.id()))
+            .order(dsl::time_created.desc())
+            .offset(i64::from(retention_count))
+            .select(Snapshot::as_select())
+            .load_async::<Snapshot>(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        expired.reverse();
+        Ok(expired)
+    }
+}
@@ -12,6 +12,7 @@ use crate::db::error::ErrorHandler;
 use crate::db::model::ByteCount;
 use crate::db::model::VirtualProvisioningCollection;
 use crate::db::pool::DbConnection;
+use crate::db::queries::virtual_provisioning_collection_update;
 use crate::db::queries::virtual_provisioning_collection_update::VirtualProvisioningCollectionUpdate;
 use async_bb8_diesel::{AsyncRunQueryDsl, PoolError};
 use diesel::prelude::*;
@@ -194,6 +195,9 @@ impl DataStore {
     }
 
     /// Transitively updates all provisioned disk provisions from project -> fleet.
+    ///
+    /// Fails with [`Error::InsufficientCapacity`] if this would exceed the
+    /// quotas of the project or its silo.
     async fn virtual_provisioning_collection_insert_storage(
         &self,
         opctx: &OpContext,
@@ -211,9 +215,7 @@ impl DataStore {
             )
             .get_results_async(self.pool_authorized(opctx).await?)
             .await
-            .map_err(|e| {
-                public_error_from_diesel_pool(e, ErrorHandler::Server)
-            })?;
+            .map_err(virtual_provisioning_collection_update::from_pool)?;
         self.virtual_provisioning_collection_producer
             .append_disk_metrics(&provisions);
         Ok(provisions)
@@ -275,7 +277,36 @@ impl DataStore {
         Ok(provisions)
     }
 
+    /// Transitively updates all provisioned disk provisions from project ->
+    /// fleet to reflect a disk changing size from `old_size` to `new_size`.
+    ///
+    /// This is idempotent: if the disk is already provisioned at `new_size`,
+    /// nothing changes. It fails with [`Error::Conflict`] if the disk is
+    /// provisioned at any other size.
+    pub async fn virtual_provisioning_collection_resize_disk(
+        &self,
+        opctx: &OpContext,
+        id: Uuid,
+        project_id: Uuid,
+        old_size: ByteCount,
+        new_size: ByteCount,
+    ) -> Result<Vec<VirtualProvisioningCollection>, Error> {
+        let provisions =
+            VirtualProvisioningCollectionUpdate::new_resize_storage(
+                id, old_size, new_size, project_id,
+            )
+            .get_results_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(virtual_provisioning_collection_update::from_pool)?;
+        self.virtual_provisioning_collection_producer
+            .append_disk_metrics(&provisions);
+        Ok(provisions)
+    }
+
     /// Transitively updates all CPU/RAM provisions from project -> fleet.
+    ///
+    /// Fails with [`Error::InsufficientCapacity`] if this would exceed the
+    /// quotas of the project or its silo.
     pub async fn virtual_provisioning_collection_insert_instance(
         &self,
         opctx: &OpContext,
@@ -290,9 +321,7 @@ impl DataStore {
             )
             .get_results_async(self.pool_authorized(opctx).await?)
             .await
-            .map_err(|e| {
-                public_error_from_diesel_pool(e, ErrorHandler::Server)
-            })?;
+            .map_err(virtual_provisioning_collection_update::from_pool)?;
         self.virtual_provisioning_collection_producer
             .append_cpu_metrics(&provisions);
         Ok(provisions)
@@ -321,6 +350,45 @@ impl DataStore {
         Ok(provisions)
     }
 
+    /// Transitively replaces an instance's CPU/RAM provisions from project ->
+    /// fleet, when the instance's configuration changes.
+    ///
+    /// This is idempotent: if the instance is already provisioned with the new
+    /// CPUs and RAM, nothing changes. It fails with [`Error::Conflict`] if the
+    /// instance is provisioned with anything other than the old or new values.
+    pub(crate) async fn virtual_provisioning_collection_update_instance_on_connection<
+        ConnErr,
+    >(
+        &self,
+        conn: &(impl async_bb8_diesel::AsyncConnection<DbConnection, ConnErr>
+              + Sync),
+        id: Uuid,
+        project_id: Uuid,
+        old_cpus: i64,
+        old_ram: ByteCount,
+        new_cpus: i64,
+        new_ram: ByteCount,
+    ) -> Result<Vec<VirtualProvisioningCollection>, Error>
+    where
+        ConnErr: From<diesel::result::Error> + Send + 'static,
+        PoolError: From<ConnErr>,
+    {
+        let provisions =
+            VirtualProvisioningCollectionUpdate::new_update_instance(
+                id, old_cpus, old_ram, new_cpus, new_ram, project_id,
+            )
+            .get_results_async(conn)
+            .await
+            .map_err(|e| {
+                virtual_provisioning_collection_update::from_pool(
+                    PoolError::from(e),
+                )
+            })?;
+        self.virtual_provisioning_collection_producer
+            .append_cpu_metrics(&provisions);
+        Ok(provisions)
+    }
+
     pub async fn load_builtin_fleet_virtual_provisioning_collection(
         &self,
         opctx: &OpContext,
@@ -29,6 +29,7 @@ use omicron_common::api::external::ResourceType;
 use serde::Deserialize;
 use serde::Serialize;
 use sled_agent_client::types::VolumeConstructionRequest;
+use std::net::SocketAddrV6;
 use uuid::Uuid;
 
 impl DataStore {
@@ -894,6 +895,324 @@ impl DataStore {
                 }
             })
     }
+
+    /// Grow a volume by appending a sub-volume to it.
+    ///
+    /// The regions backing the new sub-volume were allocated under
+    /// `extension_volume_id`; they are moved into `volume_id` so that they are
+    /// cleaned up along with the rest of the volume. The new sub-volume is
+    /// identified by `extension_volume_id` as well, which lets this function
+    /// be replayed from a saga node without appending it twice.
+    pub async fn volume_extend(
+        &self,
+        volume_id: Uuid,
+        extension_volume_id: Uuid,
+        sub_volume: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        #[derive(Debug, thiserror::Error)]
+        enum VolumeExtendError {
+            #[error("Error extending volume: {0}")]
+            DieselError(#[from] diesel::result::Error),
+
+            #[error("Serde error extending volume: {0}")]
+            SerdeError(#[from] serde_json::Error),
+
+            #[error("Volume {0} cannot be extended")]
+            NotExtendable(Uuid),
+        }
+        type TxnError = TransactionError<VolumeExtendError>;
+
+        self.pool()
+            .transaction(move |conn| {
+                use db::schema::region::dsl as region_dsl;
+                use db::schema::volume::dsl as volume_dsl;
+
+                let volume = volume_dsl::volume
+                    .filter(volume_dsl::id.eq(volume_id))
+                    .filter(volume_dsl::time_deleted.is_null())
+                    .select(Volume::as_select())
+                    .get_result(conn)?;
+
+                let vcr: VolumeConstructionRequest =
+                    serde_json::from_str(volume.data()).map_err(|e| {
+                        TxnError::CustomError(VolumeExtendError::SerdeError(e))
+                    })?;
+
+                let new_vcr = match vcr {
+                    VolumeConstructionRequest::Volume {
+                        id,
+                        block_size,
+                        mut sub_volumes,
+                        read_only_parent,
+                    } => {
+                        if !sub_volumes.iter().any(|sv| {
+                            sub_volume_has_id(sv, extension_volume_id)
+                        }) {
+                            sub_volumes.push(sub_volume);
+                        }
+                        VolumeConstructionRequest::Volume {
+                            id,
+                            block_size,
+                            sub_volumes,
+                            read_only_parent,
+                        }
+                    }
+                    _ => {
+                        return Err(TxnError::CustomError(
+                            VolumeExtendError::NotExtendable(volume_id),
+                        ));
+                    }
+                };
+
+                let new_volume_data =
+                    serde_json::to_string(&new_vcr).map_err(|e| {
+                        TxnError::CustomError(VolumeExtendError::SerdeError(e))
+                    })?;
+
+                diesel::update(volume_dsl::volume)
+                    .filter(volume_dsl::id.eq(volume_id))
+                    .set(volume_dsl::data.eq(new_volume_data))
+                    .execute(conn)?;
+
+                diesel::update(region_dsl::region)
+                    .filter(region_dsl::volume_id.eq(extension_volume_id))
+                    .set(region_dsl::volume_id.eq(volume_id))
+                    .execute(conn)?;
+
+                Ok(())
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(VolumeExtendError::DieselError(e)) => {
+                    public_error_from_diesel_pool(
+                        e.into(),
+                        ErrorHandler::NotFoundByLookup(
+                            ResourceType::Volume,
+                            LookupType::ById(volume_id),
+                        ),
+                    )
+                }
+
+                _ => {
+                    Error::internal_error(&format!("Transaction error: {}", e))
+                }
+            })
+    }
+
+    /// Reverse [`DataStore::volume_extend`]: remove the sub-volume identified
+    /// by `extension_volume_id` from the volume, and move the given regions
+    /// back under `extension_volume_id`.
+    pub async fn volume_extend_undo(
+        &self,
+        volume_id: Uuid,
+        extension_volume_id: Uuid,
+        region_ids: Vec<Uuid>,
+    ) -> Result<(), Error> {
+        #[derive(Debug, thiserror::Error)]
+        enum VolumeExtendUndoError {
+            #[error("Error undoing volume extension: {0}")]
+            DieselError(#[from] diesel::result::Error),
+
+            #[error("Serde error undoing volume extension: {0}")]
+            SerdeError(#[from] serde_json::Error),
+        }
+        type TxnError = TransactionError<VolumeExtendUndoError>;
+
+        self.pool()
+            .transaction(move |conn| {
+                use db::schema::region::dsl as region_dsl;
+                use db::schema::volume::dsl as volume_dsl;
+
+                let volume = volume_dsl::volume
+                    .filter(volume_dsl::id.eq(volume_id))
+                    .select(Volume::as_select())
+                    .get_result(conn)
+                    .optional()?;
+
+                if let Some(volume) = volume {
+                    let vcr: VolumeConstructionRequest =
+                        serde_json::from_str(volume.data()).map_err(|e| {
+                            TxnError::CustomError(
+                                VolumeExtendUndoError::SerdeError(e),
+                            )
+                        })?;
+
+                    if let VolumeConstructionRequest::Volume {
+                        id,
+                        block_size,
+                        mut sub_volumes,
+                        read_only_parent,
+                    } = vcr
+                    {
+                        sub_volumes.retain(|sv| {
+                            !sub_volume_has_id(sv, extension_volume_id)
+                        });
+                        let new_vcr = VolumeConstructionRequest::Volume {
+                            id,
+                            block_size,
+                            sub_volumes,
+                            read_only_parent,
+                        };
+                        let new_volume_data = serde_json::to_string(&new_vcr)
+                            .map_err(|e| {
+                            TxnError::CustomError(
+                                VolumeExtendUndoError::SerdeError(e),
+                            )
+                        })?;
+
+                        diesel::update(volume_dsl::volume)
+                            .filter(volume_dsl::id.eq(volume_id))
+                            .set(volume_dsl::data.eq(new_volume_data))
+                            .execute(conn)?;
+                    }
+                }
+
+                diesel::update(region_dsl::region)
+                    .filter(region_dsl::id.eq_any(region_ids))
+                    .set(region_dsl::volume_id.eq(extension_volume_id))
+                    .execute(conn)?;
+
+                Ok(())
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(VolumeExtendUndoError::DieselError(
+                    e,
+                )) => public_error_from_diesel_pool(
+                    e.into(),
+                    ErrorHandler::Server,
+                ),
+
+                _ => {
+                    Error::internal_error(&format!("Transaction error: {}", e))
+                }
+            })
+    }
+
+    /// Replace the target `old_target` with `new_target` in whichever of the
+    /// volume's read-write region sub-volumes uses it.
+    ///
+    /// Returns whether the volume was changed: if `old_target` isn't part of
+    /// the volume (for example because the replacement already happened), the
+    /// volume is left alone, which lets this function be replayed from a saga
+    /// node.
+    pub async fn volume_replace_region_target(
+        &self,
+        volume_id: Uuid,
+        old_target: SocketAddrV6,
+        new_target: SocketAddrV6,
+    ) -> Result<bool, Error> {
+        #[derive(Debug, thiserror::Error)]
+        enum VolumeReplaceTargetError {
+            #[error("Error replacing volume target: {0}")]
+            DieselError(#[from] diesel::result::Error),
+
+            #[error("Serde error replacing volume target: {0}")]
+            SerdeError(#[from] serde_json::Error),
+        }
+        type TxnError = TransactionError<VolumeReplaceTargetError>;
+
+        let old_target = old_target.to_string();
+        let new_target = new_target.to_string();
+        self.pool()
+            .transaction(move |conn| {
+                use db::schema::volume::dsl as volume_dsl;
+
+                let volume = volume_dsl::volume
+                    .filter(volume_dsl::id.eq(volume_id))
+                    .filter(volume_dsl::time_deleted.is_null())
+                    .select(Volume::as_select())
+                    .get_result(conn)?;
+
+                let mut vcr: VolumeConstructionRequest =
+                    serde_json::from_str(volume.data()).map_err(|e| {
+                        TxnError::CustomError(
+                            VolumeReplaceTargetError::SerdeError(e),
+                        )
+                    })?;
+
+                if !replace_region_target(&mut vcr, &old_target, &new_target) {
+                    return Ok(false);
+                }
+
+                let new_volume_data =
+                    serde_json::to_string(&vcr).map_err(|e| {
+                        TxnError::CustomError(
+                            VolumeReplaceTargetError::SerdeError(e),
+                        )
+                    })?;
+
+                diesel::update(volume_dsl::volume)
+                    .filter(volume_dsl::id.eq(volume_id))
+                    .set((
+                        volume_dsl::data.eq(new_volume_data),
+                        volume_dsl::time_modified.eq(Utc::now()),
+                    ))
+                    .execute(conn)?;
+
+                Ok(true)
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(
+                    VolumeReplaceTargetError::DieselError(e),
+                ) => public_error_from_diesel_pool(
+                    e.into(),
+                    ErrorHandler::NotFoundByLookup(
+                        ResourceType::Volume,
+                        LookupType::ById(volume_id),
+                    ),
+                ),
+
+                _ => {
+                    Error::internal_error(&format!("Transaction error: {}", e))
+                }
+            })
+    }
+}
+
+/// Replace `old_target` with `new_target` in the read-write region
+/// sub-volumes of `vcr`.  Returns whether a target was replaced.
+fn replace_region_target(
+    vcr: &mut VolumeConstructionRequest,
+    old_target: &str,
+    new_target: &str,
+) -> bool {
+    match vcr {
+        VolumeConstructionRequest::Volume { sub_volumes, .. } => {
+            let mut replaced = false;
+            for sub_volume in sub_volumes {
+                replaced |=
+                    replace_region_target(sub_volume, old_target, new_target);
+            }
+            replaced
+        }
+
+        VolumeConstructionRequest::Region { opts, .. } => {
+            if opts.read_only {
+                return false;
+            }
+            let mut replaced = false;
+            for target in opts.target.iter_mut() {
+                if target == old_target {
+                    *target = new_target.to_string();
+                    replaced = true;
+                }
+            }
+            replaced
+        }
+
+        VolumeConstructionRequest::Url { .. }
+        | VolumeConstructionRequest::File { .. } => false,
+    }
+}
+
+/// Returns true if `sub_volume` is a region sub-volume with the given ID.
+fn sub_volume_has_id(sub_volume: &VolumeConstructionRequest, id: Uuid) -> bool {
+    match sub_volume {
+        VolumeConstructionRequest::Region { opts, .. } => opts.id == id,
+        _ => false,
+    }
 }
 
 #[derive(Default)]
@@ -15,6 +15,7 @@ use crate::db::error::public_error_from_diesel_pool;
 use crate::db::error::ErrorHandler;
 use crate::db::error::TransactionError;
 use crate::db::identity::Resource;
+use crate::db::model::labels_match;
 use crate::db::model::IncompleteVpc;
 use crate::db::model::InstanceNetworkInterface;
 use crate::db::model::Name;
@@ -25,6 +26,7 @@ use crate::db::model::Sled;
 use crate::db::model::Vni;
 use crate::db::model::Vpc;
 use crate::db::model::VpcFirewallRule;
+use crate::db::model::VpcPeering;
 use crate::db::model::VpcRouter;
 use crate::db::model::VpcRouterUpdate;
 use crate::db::model::VpcSubnet;
@@ -44,6 +46,7 @@ use omicron_common::api::external::http_pagination::PaginatedBy;
 use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::LookupType;
@@ -59,6 +62,7 @@ impl DataStore {
         opctx: &OpContext,
         authz_project: &authz::Project,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<Vpc> {
         opctx.authorize(authz::Action::ListChildren, authz_project).await?;
 
@@ -75,6 +79,7 @@ impl DataStore {
         }
         .filter(dsl::time_deleted.is_null())
         .filter(dsl::project_id.eq(authz_project.id()))
+        .filter(labels_match(label_selector))
         .select(Vpc::as_select())
         .load_async(self.pool_authorized(opctx).await?)
         .await
@@ -157,6 +162,7 @@ impl DataStore {
         opctx.authorize(authz::Action::Delete, authz_vpc).await?;
 
         use db::schema::vpc::dsl;
+        use db::schema::vpc_peering;
         use db::schema::vpc_subnet;
 
         // Note that we don't ensure the firewall rules are empty here, because
@@ -193,6 +199,31 @@ impl DataStore {
             });
         }
 
+        // Peerings refer to the VPCs at both ends, so they must be deleted
+        // before either VPC.
+        if diesel_pool_result_optional(
+            vpc_peering::dsl::vpc_peering
+                .filter(
+                    vpc_peering::dsl::vpc_id
+                        .eq(authz_vpc.id())
+                        .or(vpc_peering::dsl::peer_vpc_id.eq(authz_vpc.id())),
+                )
+                .filter(vpc_peering::dsl::time_deleted.is_null())
+                .select(vpc_peering::dsl::id)
+                .limit(1)
+                .first_async::<Uuid>(self.pool_authorized(opctx).await?)
+                .await,
+        )
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))?
+        .is_some()
+        {
+            return Err(Error::InvalidRequest {
+                message: String::from(
+                    "VPC cannot be deleted while it is peered with other VPCs",
+                ),
+            });
+        }
+
         // Delete the VPC, conditional on the subnet_gen not having changed.
         let now = Utc::now();
         let updated_rows = diesel::update(dsl::vpc)
@@ -386,6 +417,44 @@ impl DataStore {
             .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
     }
 
+    /// Return whether any instance with a network interface on the provided
+    /// VPC may have a running OPTE port, i.e. isn't being created, stopped,
+    /// failed or destroyed.
+    pub async fn vpc_has_active_instances(
+        &self,
+        vpc_id: Uuid,
+    ) -> Result<bool, Error> {
+        use db::model::InstanceState as DbInstanceState;
+        use db::schema::{instance, instance_network_interface};
+        use omicron_common::api::external::InstanceState as ApiInstanceState;
+
+        let inactive = [
+            ApiInstanceState::Creating,
+            ApiInstanceState::Stopped,
+            ApiInstanceState::Failed,
+            ApiInstanceState::Destroyed,
+        ]
+        .into_iter()
+        .map(DbInstanceState::new)
+        .collect::<Vec<_>>();
+        let instance_id = diesel_pool_result_optional(
+            instance_network_interface::table
+                .inner_join(instance::table.on(
+                    instance::id.eq(instance_network_interface::instance_id),
+                ))
+                .filter(instance_network_interface::vpc_id.eq(vpc_id))
+                .filter(instance_network_interface::time_deleted.is_null())
+                .filter(instance::time_deleted.is_null())
+                .filter(instance::state.ne_all(inactive))
+                .select(instance::id)
+                .limit(1)
+                .first_async::<Uuid>(self.pool())
+                .await,
+        )
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))?;
+        Ok(instance_id.is_some())
+    }
+
     pub async fn vpc_subnet_list(
         &self,
         opctx: &OpContext,
@@ -633,6 +702,29 @@ impl DataStore {
     ) -> DeleteResult {
         opctx.authorize(authz::Action::Delete, authz_router).await?;
 
+        // Verify no VPC Subnet still uses this router
+        {
+            use db::schema::vpc_subnet::dsl;
+            if diesel_pool_result_optional(
+                dsl::vpc_subnet
+                    .filter(dsl::custom_router_id.eq(authz_router.id()))
+                    .filter(dsl::time_deleted.is_null())
+                    .select(dsl::id)
+                    .limit(1)
+                    .first_async::<Uuid>(self.pool_authorized(opctx).await?)
+                    .await,
+            )
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?
+            .is_some()
+            {
+                return Err(Error::invalid_request(
+                    "VPC Router cannot be deleted while VPC Subnets use it",
+                ));
+            }
+        }
+
         use db::schema::vpc_router::dsl;
         let now = Utc::now();
         diesel::update(dsl::vpc_router)
@@ -787,6 +879,248 @@ impl DataStore {
             })
     }
 
+    pub async fn vpc_peering_list(
+        &self,
+        opctx: &OpContext,
+        authz_vpc: &authz::Vpc,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<VpcPeering> {
+        opctx.authorize(authz::Action::ListChildren, authz_vpc).await?;
+
+        use db::schema::vpc_peering::dsl;
+        match pagparams {
+            PaginatedBy::Id(pagparams) => {
+                paginated(dsl::vpc_peering, dsl::id, pagparams)
+            }
+            PaginatedBy::Name(pagparams) => paginated(
+                dsl::vpc_peering,
+                dsl::name,
+                &pagparams.map_name(|n| Name::ref_cast(n)),
+            ),
+        }
+        .filter(dsl::time_deleted.is_null())
+        .filter(dsl::vpc_id.eq(authz_vpc.id()))
+        .select(VpcPeering::as_select())
+        .load_async::<VpcPeering>(self.pool_authorized(opctx).await?)
+        .await
+        .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Peer `authz_vpc` with `authz_peer_vpc`.
+    ///
+    /// This fails if the two VPCs are already peered (in either direction), or
+    /// if any subnet of one VPC overlaps with a subnet of the other.  The
+    /// caller is responsible for checking that the VPCs are distinct and in
+    /// the same Silo.
+    pub async fn vpc_create_peering(
+        &self,
+        opctx: &OpContext,
+        authz_vpc: &authz::Vpc,
+        authz_peer_vpc: &authz::Vpc,
+        peering: VpcPeering,
+    ) -> CreateResult<(authz::VpcPeering, VpcPeering)> {
+        opctx.authorize(authz::Action::CreateChild, authz_vpc).await?;
+        opctx.authorize(authz::Action::Modify, authz_peer_vpc).await?;
+        assert_eq!(peering.vpc_id, authz_vpc.id());
+        assert_eq!(peering.peer_vpc_id, authz_peer_vpc.id());
+
+        use db::schema::vpc_peering::dsl;
+        use db::schema::vpc_subnet;
+
+        type TxnError = TransactionError<Error>;
+        let name = peering.name().clone();
+        let vpc_id = authz_vpc.id();
+        let peer_vpc_id = authz_peer_vpc.id();
+        let peering = self
+            .pool_authorized(opctx)
+            .await?
+            .transaction_async(|conn| async move {
+                let existing = dsl::vpc_peering
+                    .filter(dsl::time_deleted.is_null())
+                    .filter(
+                        (dsl::vpc_id
+                            .eq(vpc_id)
+                            .and(dsl::peer_vpc_id.eq(peer_vpc_id)))
+                        .or(dsl::vpc_id
+                            .eq(peer_vpc_id)
+                            .and(dsl::peer_vpc_id.eq(vpc_id))),
+                    )
+                    .select(dsl::id)
+                    .limit(1)
+                    .load_async::<Uuid>(&conn)
+                    .await?;
+                if !existing.is_empty() {
+                    return Err(TxnError::CustomError(Error::invalid_request(
+                        "the VPCs are already peered",
+                    )));
+                }
+
+                let subnets = vpc_subnet::dsl::vpc_subnet
+                    .filter(vpc_subnet::dsl::time_deleted.is_null())
+                    .filter(
+                        vpc_subnet::dsl::vpc_id.eq_any([vpc_id, peer_vpc_id]),
+                    )
+                    .select(VpcSubnet::as_select())
+                    .load_async::<VpcSubnet>(&conn)
+                    .await?;
+                let (ours, theirs): (Vec<_>, Vec<_>) =
+                    subnets.iter().partition(|s| s.vpc_id == vpc_id);
+                for subnet in &ours {
+                    if let Some(other) =
+                        theirs.iter().find(|other| subnet.overlaps(other))
+                    {
+                        return Err(TxnError::CustomError(
+                            Error::invalid_request(&format!(
+                                "subnet \"{}\" overlaps with subnet \"{}\" \
+                                of the peer VPC",
+                                subnet.name(),
+                                other.name(),
+                            )),
+                        ));
+                    }
+                }
+
+                Ok(diesel::insert_into(dsl::vpc_peering)
+                    .values(peering)
+                    .returning(VpcPeering::as_returning())
+                    .get_result_async(&conn)
+                    .await?)
+            })
+            .await
+            .map_err(|e| match e {
+                TxnError::CustomError(e) => e,
+                TxnError::Pool(e) => public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::Conflict(
+                        ResourceType::VpcPeering,
+                        name.as_str(),
+                    ),
+                ),
+            })?;
+        Ok((
+            authz::VpcPeering::new(
+                authz_vpc.clone(),
+                peering.id(),
+                LookupType::ById(peering.id()),
+            ),
+            peering,
+        ))
+    }
+
+    pub async fn vpc_delete_peering(
+        &self,
+        opctx: &OpContext,
+        authz_peering: &authz::VpcPeering,
+    ) -> DeleteResult {
+        opctx.authorize(authz::Action::Delete, authz_peering).await?;
+
+        use db::schema::vpc_peering::dsl;
+        let updated = diesel::update(dsl::vpc_peering)
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::id.eq(authz_peering.id()))
+            .set(dsl::time_deleted.eq(Utc::now()))
+            .execute_async(self.pool_authorized(opctx).await?)
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(
+                    e,
+                    ErrorHandler::NotFoundByResource(authz_peering),
+                )
+            })?;
+        if updated == 0 {
+            return Err(authz_peering.not_found());
+        }
+        Ok(())
+    }
+
+    /// Return the VPCs peered with the provided VPC, from either side of the
+    /// peering.
+    pub async fn vpc_resolve_peers(
+        &self,
+        vpc_id: Uuid,
+    ) -> Result<Vec<Vpc>, Error> {
+        use db::schema::{vpc, vpc_peering};
+
+        let peerings = vpc_peering::table
+            .filter(vpc_peering::time_deleted.is_null())
+            .filter(
+                vpc_peering::vpc_id
+                    .eq(vpc_id)
+                    .or(vpc_peering::peer_vpc_id.eq(vpc_id)),
+            )
+            .select(VpcPeering::as_select())
+            .get_results_async::<VpcPeering>(self.pool())
+            .await
+            .map_err(|e| {
+                public_error_from_diesel_pool(e, ErrorHandler::Server)
+            })?;
+        if peerings.is_empty() {
+            return Ok(vec![]);
+        }
+
+        vpc::table
+            .filter(vpc::time_deleted.is_null())
+            .filter(
+                vpc::id.eq_any(
+                    peerings
+                        .iter()
+                        .map(|p| p.other_vpc_id(vpc_id))
+                        .collect::<Vec<_>>(),
+                ),
+            )
+            .select(Vpc::as_select())
+            .get_results_async(self.pool())
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Return the subnets of every VPC peered with the provided VPC.
+    pub async fn vpc_resolve_peer_subnets(
+        &self,
+        vpc_id: Uuid,
+    ) -> Result<Vec<VpcSubnet>, Error> {
+        let peer_ids: Vec<Uuid> = self
+            .vpc_resolve_peers(vpc_id)
+            .await?
+            .into_iter()
+            .map(|vpc| vpc.id())
+            .collect();
+        if peer_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        self.vpc_resolve_subnets(peer_ids).await
+    }
+
+    /// Return the subnets of each of the provided VPCs.
+    pub async fn vpc_resolve_subnets(
+        &self,
+        vpc_ids: Vec<Uuid>,
+    ) -> Result<Vec<VpcSubnet>, Error> {
+        use db::schema::vpc_subnet::dsl;
+        dsl::vpc_subnet
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::vpc_id.eq_any(vpc_ids))
+            .select(VpcSubnet::as_select())
+            .get_results_async(self.pool())
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
+    /// Return the routes of each of the provided VPC Routers.
+    pub async fn vpc_resolve_router_routes(
+        &self,
+        router_ids: Vec<Uuid>,
+    ) -> Result<Vec<RouterRoute>, Error> {
+        use db::schema::router_route::dsl;
+        dsl::router_route
+            .filter(dsl::time_deleted.is_null())
+            .filter(dsl::vpc_router_id.eq_any(router_ids))
+            .select(RouterRoute::as_select())
+            .get_results_async(self.pool())
+            .await
+            .map_err(|e| public_error_from_diesel_pool(e, ErrorHandler::Server))
+    }
+
     /// Identify all subnets in use by each VpcSubnet
     pub async fn resolve_vpc_subnets_to_ip_networks<
         T: IntoIterator<Item = Name>,
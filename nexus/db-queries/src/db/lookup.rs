@@ -202,6 +202,21 @@ impl<'a> LookupPath<'a> {
         Snapshot::PrimaryKey(Root { lookup_root: self }, id)
     }
 
+    /// Select a resource of type AffinityGroup, identified by its id
+    pub fn affinity_group_id(self, id: Uuid) -> AffinityGroup<'a> {
+        AffinityGroup::PrimaryKey(Root { lookup_root: self }, id)
+    }
+
+    /// Select a resource of type FloatingIp, identified by its id
+    pub fn floating_ip_id(self, id: Uuid) -> FloatingIp<'a> {
+        FloatingIp::PrimaryKey(Root { lookup_root: self }, id)
+    }
+
+    /// Select a resource of type SnapshotSchedule, identified by its id
+    pub fn snapshot_schedule_id(self, id: Uuid) -> SnapshotSchedule<'a> {
+        SnapshotSchedule::PrimaryKey(Root { lookup_root: self }, id)
+    }
+
     /// Select a resource of type InstanceNetworkInterface, identified by its id
     pub fn instance_network_interface_id(
         self,
@@ -225,6 +240,11 @@ impl<'a> LookupPath<'a> {
         VpcRouter::PrimaryKey(Root { lookup_root: self }, id)
     }
 
+    /// Select a resource of type VpcPeering, identified by its id
+    pub fn vpc_peering_id(self, id: Uuid) -> VpcPeering<'a> {
+        VpcPeering::PrimaryKey(Root { lookup_root: self }, id)
+    }
+
     /// Select a resource of type RouterRoute, identified by its id
     pub fn router_route_id(self, id: Uuid) -> RouterRoute<'a> {
         RouterRoute::PrimaryKey(Root { lookup_root: self }, id)
@@ -336,6 +356,11 @@ impl<'a> LookupPath<'a> {
         SshKey::PrimaryKey(Root { lookup_root: self }, id)
     }
 
+    /// Select a resource of type AccessToken, identified by its id
+    pub fn access_token_id(self, id: Uuid) -> AccessToken<'a> {
+        AccessToken::PrimaryKey(Root { lookup_root: self }, id)
+    }
+
     /// Select a resource of type Rack, identified by its id
     pub fn rack_id(self, id: Uuid) -> Rack<'a> {
         Rack::PrimaryKey(Root { lookup_root: self }, id)
@@ -470,6 +495,22 @@ impl<'a> LookupPath<'a> {
     {
         SamlIdentityProvider::PrimaryKey(Root { lookup_root: self }, id)
     }
+
+    /// Select a resource of type OidcIdentityProvider, identified by its id
+    pub fn oidc_identity_provider_id<'b>(
+        self,
+        id: Uuid,
+    ) -> OidcIdentityProvider<'b>
+    where
+        'a: 'b,
+    {
+        OidcIdentityProvider::PrimaryKey(Root { lookup_root: self }, id)
+    }
+
+    /// Select a resource of type ScimClientToken, identified by its id
+    pub fn scim_client_token_id(self, id: Uuid) -> ScimClientToken<'a> {
+        ScimClientToken::PrimaryKey(Root { lookup_root: self }, id)
+    }
 }
 
 /// Represents the head of the selection path for a resource
@@ -493,7 +534,14 @@ impl<'a> Root<'a> {
 lookup_resource! {
     name = "Silo",
     ancestors = [],
-    children = [ "IdentityProvider", "SamlIdentityProvider", "Project", "SiloImage" ],
+    children = [
+        "IdentityProvider",
+        "SamlIdentityProvider",
+        "OidcIdentityProvider",
+        "ScimClientToken",
+        "Project",
+        "SiloImage"
+    ],
     lookup_by_name = true,
     soft_deletes = true,
     primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
@@ -502,7 +550,7 @@ lookup_resource! {
 lookup_resource! {
     name = "SiloUser",
     ancestors = [ "Silo" ],
-    children = [ "SshKey" ],
+    children = [ "SshKey", "AccessToken" ],
     lookup_by_name = false,
     soft_deletes = true,
     primary_key_columns = [ { column_name = "id", rust_type = Uuid } ],
@@ -560,6 +608,28 @@ lookup_resource! {
     visible_outside_silo = true
 }
 
+lookup_resource! {
+    name = "OidcIdentityProvider",
+    ancestors = [ "Silo" ],
+    children = [],
+    lookup_by_name = true,
+    soft_deletes = true,
+    primary_key_columns = [
+        { column_name = "id", rust_type = Uuid },
+    ],
+    visible_outside_silo = true
+}
+
+lookup_resource! {
+    name = "ScimClientToken",
+    ancestors = [ "Silo" ],
+    children = [],
+    lookup_by_name = true,
+    soft_deletes = true,
+    primary_key_columns = [ { column_name = "id", rust_type = Uuid } ],
+    visible_outside_silo = true
+}
+
 lookup_resource! {
     name = "SshKey",
     ancestors = [ "Silo", "SiloUser" ],
@@ -569,10 +639,19 @@ lookup_resource! {
     primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
 }
 
+lookup_resource! {
+    name = "AccessToken",
+    ancestors = [ "Silo", "SiloUser" ],
+    children = [],
+    lookup_by_name = true,
+    soft_deletes = true,
+    primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
+}
+
 lookup_resource! {
     name = "Project",
     ancestors = [ "Silo" ],
-    children = [ "Disk", "Instance", "Vpc", "Snapshot", "ProjectImage" ],
+    children = [ "Disk", "Instance", "Vpc", "Snapshot", "ProjectImage", "AffinityGroup", "SnapshotSchedule", "FloatingIp" ],
     lookup_by_name = true,
     soft_deletes = true,
     primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
@@ -614,6 +693,33 @@ lookup_resource! {
     primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
 }
 
+lookup_resource! {
+    name = "AffinityGroup",
+    ancestors = [ "Silo", "Project" ],
+    children = [],
+    lookup_by_name = true,
+    soft_deletes = true,
+    primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
+}
+
+lookup_resource! {
+    name = "SnapshotSchedule",
+    ancestors = [ "Silo", "Project" ],
+    children = [],
+    lookup_by_name = true,
+    soft_deletes = true,
+    primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
+}
+
+lookup_resource! {
+    name = "FloatingIp",
+    ancestors = [ "Silo", "Project" ],
+    children = [],
+    lookup_by_name = true,
+    soft_deletes = true,
+    primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
+}
+
 lookup_resource! {
     name = "Instance",
     ancestors = [ "Silo", "Project" ],
@@ -635,7 +741,7 @@ lookup_resource! {
 lookup_resource! {
     name = "Vpc",
     ancestors = [ "Silo", "Project" ],
-    children = [ "VpcRouter", "VpcSubnet" ],
+    children = [ "VpcRouter", "VpcSubnet", "VpcPeering" ],
     lookup_by_name = true,
     soft_deletes = true,
     primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
@@ -668,6 +774,15 @@ lookup_resource! {
     primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
 }
 
+lookup_resource! {
+    name = "VpcPeering",
+    ancestors = [ "Silo", "Project", "Vpc" ],
+    children = [ ],
+    lookup_by_name = true,
+    soft_deletes = true,
+    primary_key_columns = [ { column_name = "id", rust_type = Uuid } ]
+}
+
 // Miscellaneous resources nested directly below "Fleet"
 
 lookup_resource! {
@@ -1690,6 +1690,7 @@ mod tests {
                 name,
                 description: "desc".to_string(),
             },
+            labels: Default::default(),
             ncpus: InstanceCpuCount(4),
             memory: ByteCount::from_gibibytes_u32(4),
             hostname: "inst".to_string(),
@@ -1697,6 +1698,8 @@ mod tests {
             network_interfaces: InstanceNetworkInterfaceAttachment::None,
             external_ips: vec![],
             disks: vec![],
+            affinity_groups: Vec::new(),
+            auto_restart_policy: Default::default(),
             start: true,
         };
         let runtime = InstanceRuntimeState {
@@ -1850,6 +1853,7 @@ mod tests {
                         name: "project".parse().unwrap(),
                         description: "desc".to_string(),
                     },
+                    labels: Default::default(),
                 },
             );
             let (.., project) =
@@ -13,16 +13,65 @@ use crate::db::pool::DbConnection;
 use crate::db::schema::virtual_provisioning_collection;
 use crate::db::schema::virtual_provisioning_resource;
 use crate::db::subquery::{AsQuerySource, Cte, CteBuilder, CteQuery};
+use crate::db::true_or_cast_error::{matches_sentinel, TrueOrCastError};
 use db_macros::Subquery;
+use diesel::expression::{is_aggregate, ValidGrouping};
 use diesel::pg::Pg;
 use diesel::query_builder::{AstPass, Query, QueryFragment, QueryId};
 use diesel::{
-    sql_types, CombineDsl, ExpressionMethods, IntoSql,
-    NullableExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper,
+    sql_types, BoolExpressionMethods, CombineDsl, Expression,
+    ExpressionMethods, IntoSql, NullableExpressionMethods, QueryDsl,
+    RunQueryDsl, SelectableHelper,
 };
 use nexus_db_model::queries::virtual_provisioning_collection_update::{
     all_collections, do_update, parent_silo,
 };
+use omicron_common::api::external;
+
+const PROJECT_CPUS_SENTINEL: &'static str =
+    "Not enough CPUs remaining in the project quota";
+const PROJECT_RAM_SENTINEL: &'static str =
+    "Not enough memory remaining in the project quota";
+const PROJECT_STORAGE_SENTINEL: &'static str =
+    "Not enough storage remaining in the project quota";
+const SILO_CPUS_SENTINEL: &'static str =
+    "Not enough CPUs remaining in the silo quota";
+const SILO_RAM_SENTINEL: &'static str =
+    "Not enough memory remaining in the silo quota";
+const SILO_STORAGE_SENTINEL: &'static str =
+    "Not enough storage remaining in the silo quota";
+const RESOURCE_STORAGE_SENTINEL: &'static str =
+    "The resource's provisioned storage has changed";
+const RESOURCE_INSTANCE_SENTINEL: &'static str =
+    "The resource's provisioned CPUs or memory have changed";
+
+/// Translates a generic pool error to an external error based
+/// on messages which may be emitted when a provisioning update would exceed a
+/// quota.
+pub fn from_pool(e: async_bb8_diesel::PoolError) -> external::Error {
+    use crate::db::error;
+
+    let sentinels = [
+        PROJECT_CPUS_SENTINEL,
+        PROJECT_RAM_SENTINEL,
+        PROJECT_STORAGE_SENTINEL,
+        SILO_CPUS_SENTINEL,
+        SILO_RAM_SENTINEL,
+        SILO_STORAGE_SENTINEL,
+    ];
+    if let Some(sentinel) = matches_sentinel(&e, &sentinels) {
+        // Each of the sentinels is already a suitable description of the
+        // quota that would have been exceeded.
+        return external::Error::insufficient_capacity(sentinel);
+    }
+    let conflict_sentinels =
+        [RESOURCE_STORAGE_SENTINEL, RESOURCE_INSTANCE_SENTINEL];
+    if let Some(sentinel) = matches_sentinel(&e, &conflict_sentinels) {
+        return external::Error::conflict(sentinel);
+    }
+
+    error::public_error_from_diesel_pool(e, error::ErrorHandler::Server)
+}
 
 #[derive(Subquery, QueryId)]
 #[subquery(name = parent_silo)]
@@ -99,6 +148,85 @@ impl DoUpdate {
         }
     }
 
+    fn new_for_resize(
+        id: uuid::Uuid,
+        old_size: ByteCount,
+        new_size: ByteCount,
+    ) -> Self {
+        use virtual_provisioning_resource::dsl;
+
+        let at_old_size = dsl::virtual_provisioning_resource
+            .filter(dsl::id.eq(id))
+            .filter(dsl::virtual_disk_bytes_provisioned.eq(old_size))
+            .count()
+            .single_value()
+            .assume_not_null()
+            .eq(1);
+
+        // The resource being at neither size means that something else
+        // changed it since the caller looked, and the whole update fails.
+        let provisioned_as_expected = TrueOrCastError::new(
+            ProvisionedAsExpected {
+                id,
+                expected: ExpectedProvision::Storage {
+                    old: old_size.into(),
+                    new: new_size.into(),
+                },
+            },
+            RESOURCE_STORAGE_SENTINEL,
+        );
+
+        Self {
+            query: Box::new(diesel::select((ExpressionAlias::new::<
+                do_update::update,
+            >(
+                provisioned_as_expected.and(at_old_size),
+            ),))),
+        }
+    }
+
+    fn new_for_instance_update(
+        id: uuid::Uuid,
+        old_cpus: i64,
+        old_ram: ByteCount,
+        new_cpus: i64,
+        new_ram: ByteCount,
+    ) -> Self {
+        use virtual_provisioning_resource::dsl;
+
+        let at_old_values = dsl::virtual_provisioning_resource
+            .filter(dsl::id.eq(id))
+            .filter(dsl::cpus_provisioned.eq(old_cpus))
+            .filter(dsl::ram_provisioned.eq(old_ram))
+            .count()
+            .single_value()
+            .assume_not_null()
+            .eq(1);
+
+        // As with a resize, the resource being at neither configuration fails
+        // the whole update.
+        let provisioned_as_expected = TrueOrCastError::new(
+            ProvisionedAsExpected {
+                id,
+                expected: ExpectedProvision::Instance {
+                    old_cpus,
+                    old_ram: old_ram.into(),
+                    new_cpus,
+                    new_ram: new_ram.into(),
+                },
+            },
+            RESOURCE_INSTANCE_SENTINEL,
+        );
+
+        Self {
+            query: Box::new(diesel::select((ExpressionAlias::new::<
+                do_update::update,
+            >(
+                provisioned_as_expected.and(at_old_values),
+            ),))),
+        }
+    }
+
     fn new_for_delete(id: uuid::Uuid) -> Self {
         use virtual_provisioning_resource::dsl;
 
@@ -117,6 +245,270 @@ impl DoUpdate {
     }
 }
 
+/// The values an update expects a resource to be provisioned at: either those
+/// it replaces, or (if the update has already been applied) those it sets.
+#[derive(Clone, Copy)]
+enum ExpectedProvision {
+    Storage { old: i64, new: i64 },
+    Instance { old_cpus: i64, old_ram: i64, new_cpus: i64, new_ram: i64 },
+}
+
+/// A boolean expression which is "true" if the resource is provisioned at
+/// the values an update expects it to be.
+///
+/// Used with [`TrueOrCastError`] in the "do_update" arm of the CTE, so that
+/// an update racing with another one to the same resource fails instead of
+/// silently doing nothing.
+#[derive(QueryId)]
+struct ProvisionedAsExpected {
+    id: uuid::Uuid,
+    expected: ExpectedProvision,
+}
+
+impl Expression for ProvisionedAsExpected {
+    type SqlType = sql_types::Bool;
+}
+
+impl<QS> diesel::AppearsOnTable<QS> for ProvisionedAsExpected {}
+
+impl<T> diesel::SelectableExpression<T> for ProvisionedAsExpected {}
+
+impl<GB> ValidGrouping<GB> for ProvisionedAsExpected {
+    type IsAggregate = is_aggregate::Never;
+}
+
+impl QueryFragment<Pg> for ProvisionedAsExpected {
+    fn walk_ast<'a>(
+        &'a self,
+        mut out: AstPass<'_, 'a, Pg>,
+    ) -> diesel::QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        out.push_sql(
+            "EXISTS (SELECT 1 FROM virtual_provisioning_resource WHERE id = ",
+        );
+        out.push_bind_param::<sql_types::Uuid, _>(&self.id)?;
+        match &self.expected {
+            ExpectedProvision::Storage { old, new } => {
+                out.push_sql(" AND virtual_disk_bytes_provisioned IN (");
+                out.push_bind_param::<sql_types::BigInt, _>(old)?;
+                out.push_sql(", ");
+                out.push_bind_param::<sql_types::BigInt, _>(new)?;
+                out.push_sql(")");
+            }
+            ExpectedProvision::Instance {
+                old_cpus,
+                old_ram,
+                new_cpus,
+                new_ram,
+            } => {
+                out.push_sql(" AND ((cpus_provisioned = ");
+                out.push_bind_param::<sql_types::BigInt, _>(old_cpus)?;
+                out.push_sql(" AND ram_provisioned = ");
+                out.push_bind_param::<sql_types::BigInt, _>(old_ram)?;
+                out.push_sql(") OR (cpus_provisioned = ");
+                out.push_bind_param::<sql_types::BigInt, _>(new_cpus)?;
+                out.push_sql(" AND ram_provisioned = ");
+                out.push_bind_param::<sql_types::BigInt, _>(new_ram)?;
+                out.push_sql("))");
+            }
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+/// The amount by which an update increases the resources provisioned within a
+/// project (and transitively, within its silo).
+///
+/// Components which don't increase (such as those of a delete, or a resource
+/// shrinking) are ignored, since they can't exceed a quota.
+#[derive(Clone, Copy, Default)]
+struct ProvisioningIncrease {
+    cpus: i64,
+    ram: i64,
+    virtual_disk_bytes: i64,
+}
+
+/// A collection whose provisioning may be limited by a quota.
+#[derive(Clone, Copy)]
+enum QuotaCollection {
+    Project,
+    Silo,
+}
+
+/// A kind of resource whose provisioning may be limited by a quota.
+#[derive(Clone, Copy)]
+enum QuotaResource {
+    Cpus,
+    Ram,
+    Storage,
+}
+
+impl QuotaResource {
+    /// The column of the quota tables which limits this resource.
+    fn quota_column(&self) -> &'static str {
+        match self {
+            QuotaResource::Cpus => "cpus",
+            QuotaResource::Ram => "memory_bytes",
+            QuotaResource::Storage => "storage_bytes",
+        }
+    }
+
+    /// The column of "virtual_provisioning_collection" which tracks how much
+    /// of this resource has been provisioned.
+    fn provisioned_column(&self) -> &'static str {
+        match self {
+            QuotaResource::Cpus => "cpus_provisioned",
+            QuotaResource::Ram => "ram_provisioned",
+            QuotaResource::Storage => "virtual_disk_bytes_provisioned",
+        }
+    }
+}
+
+/// A boolean expression which is "true" if increasing the provisioning of
+/// `resource` by `increase` fits within the quota (if any) set on the
+/// collection.
+///
+/// This refers to the "parent_silo" and "do_update" arms of the CTE: quotas
+/// are only checked if the update is actually going to be applied, so that
+/// re-issuing an update which has already happened doesn't fail spuriously.
+#[derive(QueryId)]
+struct FitsQuota {
+    collection: QuotaCollection,
+    resource: QuotaResource,
+    project_id: uuid::Uuid,
+    increase: i64,
+}
+
+impl FitsQuota {
+    fn sentinel(&self) -> &'static str {
+        match (self.collection, self.resource) {
+            (QuotaCollection::Project, QuotaResource::Cpus) => {
+                PROJECT_CPUS_SENTINEL
+            }
+            (QuotaCollection::Project, QuotaResource::Ram) => {
+                PROJECT_RAM_SENTINEL
+            }
+            (QuotaCollection::Project, QuotaResource::Storage) => {
+                PROJECT_STORAGE_SENTINEL
+            }
+            (QuotaCollection::Silo, QuotaResource::Cpus) => SILO_CPUS_SENTINEL,
+            (QuotaCollection::Silo, QuotaResource::Ram) => SILO_RAM_SENTINEL,
+            (QuotaCollection::Silo, QuotaResource::Storage) => {
+                SILO_STORAGE_SENTINEL
+            }
+        }
+    }
+}
+
+impl Expression for FitsQuota {
+    type SqlType = sql_types::Bool;
+}
+
+impl<QS> diesel::AppearsOnTable<QS> for FitsQuota {}
+
+impl<GB> ValidGrouping<GB> for FitsQuota {
+    type IsAggregate = is_aggregate::Never;
+}
+
+impl QueryFragment<Pg> for FitsQuota {
+    fn walk_ast<'a>(
+        &'a self,
+        mut out: AstPass<'_, 'a, Pg>,
+    ) -> diesel::QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        let quota_column = self.resource.quota_column();
+        let provisioned_column = self.resource.provisioned_column();
+
+        out.push_sql("NOT EXISTS (SELECT 1 FROM ");
+        match self.collection {
+            QuotaCollection::Project => {
+                out.push_sql(
+                    "project_quotas AS q INNER JOIN \
+                    virtual_provisioning_collection AS c \
+                    ON c.id = q.project_id WHERE q.project_id = ",
+                );
+                out.push_bind_param::<sql_types::Uuid, _>(&self.project_id)?;
+            }
+            QuotaCollection::Silo => {
+                out.push_sql(
+                    "silo_quotas AS q INNER JOIN \
+                    virtual_provisioning_collection AS c \
+                    ON c.id = q.silo_id \
+                    WHERE q.silo_id = (SELECT id FROM parent_silo)",
+                );
+            }
+        }
+        // A NULL quota places no limit on the resource, and the comparison
+        // below is never true for it.
+        out.push_sql(" AND q.");
+        out.push_sql(quota_column);
+        out.push_sql(" < c.");
+        out.push_sql(provisioned_column);
+        out.push_sql(" + ");
+        out.push_bind_param::<sql_types::BigInt, _>(&self.increase)?;
+        out.push_sql(" AND (SELECT \"update\" FROM do_update))");
+        Ok(())
+    }
+}
+
+/// A boolean expression which is "true" if an update fits within all quotas on
+/// the project and its silo, and which forces an error out of the CTE
+/// (identifying the quota) otherwise.
+#[derive(QueryId)]
+struct FitsQuotas {
+    checks: Vec<TrueOrCastError<FitsQuota>>,
+}
+
+impl FitsQuotas {
+    fn new(project_id: uuid::Uuid, increase: ProvisioningIncrease) -> Self {
+        let mut checks = vec![];
+        for collection in [QuotaCollection::Project, QuotaCollection::Silo] {
+            for (resource, increase) in [
+                (QuotaResource::Cpus, increase.cpus),
+                (QuotaResource::Ram, increase.ram),
+                (QuotaResource::Storage, increase.virtual_disk_bytes),
+            ] {
+                if increase <= 0 {
+                    continue;
+                }
+                let check =
+                    FitsQuota { collection, resource, project_id, increase };
+                let sentinel = check.sentinel();
+                checks.push(TrueOrCastError::new(check, sentinel));
+            }
+        }
+        Self { checks }
+    }
+}
+
+impl Expression for FitsQuotas {
+    type SqlType = sql_types::Bool;
+}
+
+impl<QS> diesel::AppearsOnTable<QS> for FitsQuotas {}
+
+impl<GB> ValidGrouping<GB> for FitsQuotas {
+    type IsAggregate = is_aggregate::Never;
+}
+
+impl QueryFragment<Pg> for FitsQuotas {
+    fn walk_ast<'a>(
+        &'a self,
+        mut out: AstPass<'_, 'a, Pg>,
+    ) -> diesel::QueryResult<()> {
+        out.push_sql("(TRUE");
+        for check in &self.checks {
+            out.push_sql(" AND ");
+            check.walk_ast(out.reborrow())?;
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
 #[derive(Subquery, QueryId)]
 #[subquery(name = virtual_provisioning_collection)]
 struct UpdatedProvisions {
@@ -128,6 +520,7 @@ impl UpdatedProvisions {
     fn new<V>(
         all_collections: &AllCollections,
         do_update: &DoUpdate,
+        fits_quotas: FitsQuotas,
         values: V,
     ) -> Self
     where
@@ -155,6 +548,7 @@ impl UpdatedProvisions {
                             .single_value()
                             .assume_not_null(),
                     )
+                    .filter(fits_quotas)
                     .returning(virtual_provisioning_collection::all_columns),
             ),
         }
@@ -217,12 +611,15 @@ impl VirtualProvisioningCollectionUpdate {
     // - update: A SQL query to actually modify the resource record. Generally
     // this is an "INSERT", "UPDATE", or "DELETE".
     // - project_id: The project to which the resource belongs.
+    // - increase: The amount by which the update grows the project's
+    // provisioning, which must fit within the quotas of the project and silo.
     // - values: The updated values to propagate through collections (iff
     // "do_update" evaluates to "true").
     fn apply_update<U, V>(
         do_update: DoUpdate,
         update: U,
         project_id: uuid::Uuid,
+        increase: ProvisioningIncrease,
         values: V,
     ) -> Self
     where
@@ -237,8 +634,12 @@ impl VirtualProvisioningCollectionUpdate {
             &parent_silo,
             *crate::db::fixed_data::FLEET_ID,
         );
-        let updated_collections =
-            UpdatedProvisions::new(&all_collections, &do_update, values);
+        let updated_collections = UpdatedProvisions::new(
+            &all_collections,
+            &do_update,
+            FitsQuotas::new(project_id, increase),
+            values,
+        );
 
         // TODO: Do we want to select from "all_collections" instead? Seems more
         // idempotent; it'll work even when we don't update anything...
@@ -286,6 +687,11 @@ impl VirtualProvisioningCollectionUpdate {
             ),
             // Within this project, silo, fleet...
             project_id,
+            // ... As long as the disk usage fits within any quotas ...
+            ProvisioningIncrease {
+                virtual_disk_bytes: i64::from(disk_byte_diff),
+                ..Default::default()
+            },
             // ... We add the disk usage.
             (
                 collection_dsl::time_modified.eq(diesel::dsl::now),
@@ -315,6 +721,7 @@ impl VirtualProvisioningCollectionUpdate {
             ),
             // Within this project, silo, fleet...
             project_id,
+            ProvisioningIncrease::default(),
             // ... We subtract the disk usage.
             (
                 collection_dsl::time_modified.eq(diesel::dsl::now),
@@ -325,6 +732,72 @@ impl VirtualProvisioningCollectionUpdate {
         )
     }
 
+    pub fn new_resize_storage(
+        id: uuid::Uuid,
+        old_size: ByteCount,
+        new_size: ByteCount,
+        project_id: uuid::Uuid,
+    ) -> Self {
+        use virtual_provisioning_collection::dsl as collection_dsl;
+        use virtual_provisioning_resource::dsl as resource_dsl;
+
+        // We should update the record if it still reflects the old size.
+        let do_update = DoUpdate::new_for_resize(id, old_size, new_size);
+        // The query to actually update the record.
+        let update = UnreferenceableSubquery(
+            diesel::update(resource_dsl::virtual_provisioning_resource)
+                .filter(resource_dsl::id.eq(id))
+                .filter(
+                    resource_dsl::virtual_disk_bytes_provisioned.eq(old_size),
+                )
+                .set((
+                    resource_dsl::time_modified.eq(diesel::dsl::now),
+                    resource_dsl::virtual_disk_bytes_provisioned.eq(new_size),
+                ))
+                .returning(virtual_provisioning_resource::all_columns),
+        );
+
+        // Within this project, silo, fleet, we apply the difference in disk
+        // usage. Since `ByteCount` can't be negative, growing and shrinking
+        // are expressed separately.
+        if new_size.to_bytes() >= old_size.to_bytes() {
+            let disk_byte_diff =
+                ByteCount::try_from(i64::from(new_size) - i64::from(old_size))
+                    .unwrap();
+            Self::apply_update(
+                do_update,
+                update,
+                project_id,
+                ProvisioningIncrease {
+                    virtual_disk_bytes: i64::from(disk_byte_diff),
+                    ..Default::default()
+                },
+                (
+                    collection_dsl::time_modified.eq(diesel::dsl::now),
+                    collection_dsl::virtual_disk_bytes_provisioned
+                        .eq(collection_dsl::virtual_disk_bytes_provisioned
+                            + disk_byte_diff),
+                ),
+            )
+        } else {
+            let disk_byte_diff =
+                ByteCount::try_from(i64::from(old_size) - i64::from(new_size))
+                    .unwrap();
+            Self::apply_update(
+                do_update,
+                update,
+                project_id,
+                ProvisioningIncrease::default(),
+                (
+                    collection_dsl::time_modified.eq(diesel::dsl::now),
+                    collection_dsl::virtual_disk_bytes_provisioned
+                        .eq(collection_dsl::virtual_disk_bytes_provisioned
+                            - disk_byte_diff),
+                ),
+            )
+        }
+    }
+
     pub fn new_insert_instance(
         id: uuid::Uuid,
         cpus_diff: i64,
@@ -355,6 +828,12 @@ impl VirtualProvisioningCollectionUpdate {
             ),
             // Within this project, silo, fleet...
             project_id,
+            // ... As long as the resource usage fits within any quotas ...
+            ProvisioningIncrease {
+                cpus: cpus_diff,
+                ram: i64::from(ram_diff),
+                ..Default::default()
+            },
             // ... We update the resource usage.
             (
                 collection_dsl::time_modified.eq(diesel::dsl::now),
@@ -386,6 +865,7 @@ impl VirtualProvisioningCollectionUpdate {
             ),
             // Within this project, silo, fleet...
             project_id,
+            ProvisioningIncrease::default(),
             // ... We update the resource usage.
             (
                 collection_dsl::time_modified.eq(diesel::dsl::now),
@@ -396,6 +876,55 @@ impl VirtualProvisioningCollectionUpdate {
             ),
         )
     }
+
+    pub fn new_update_instance(
+        id: uuid::Uuid,
+        old_cpus: i64,
+        old_ram: ByteCount,
+        new_cpus: i64,
+        new_ram: ByteCount,
+        project_id: uuid::Uuid,
+    ) -> Self {
+        use virtual_provisioning_collection::dsl as collection_dsl;
+        use virtual_provisioning_resource::dsl as resource_dsl;
+
+        Self::apply_update(
+            // We should update the record if it still reflects the old
+            // configuration.
+            DoUpdate::new_for_instance_update(
+                id, old_cpus, old_ram, new_cpus, new_ram,
+            ),
+            // The query to actually update the record.
+            UnreferenceableSubquery(
+                diesel::update(resource_dsl::virtual_provisioning_resource)
+                    .filter(resource_dsl::id.eq(id))
+                    .filter(resource_dsl::cpus_provisioned.eq(old_cpus))
+                    .filter(resource_dsl::ram_provisioned.eq(old_ram))
+                    .set((
+                        resource_dsl::time_modified.eq(diesel::dsl::now),
+                        resource_dsl::cpus_provisioned.eq(new_cpus),
+                        resource_dsl::ram_provisioned.eq(new_ram),
+                    ))
+                    .returning(virtual_provisioning_resource::all_columns),
+            ),
+            // Within this project, silo, fleet...
+            project_id,
+            // ... As long as any growth fits within quotas ...
+            ProvisioningIncrease {
+                cpus: new_cpus - old_cpus,
+                ram: i64::from(new_ram) - i64::from(old_ram),
+                ..Default::default()
+            },
+            // ... We replace the old resource usage with the new one.
+            (
+                collection_dsl::time_modified.eq(diesel::dsl::now),
+                collection_dsl::cpus_provisioned
+                    .eq(collection_dsl::cpus_provisioned - old_cpus + new_cpus),
+                collection_dsl::ram_provisioned
+                    .eq(collection_dsl::ram_provisioned - old_ram + new_ram),
+            ),
+        )
+    }
 }
 
 impl QueryFragment<Pg> for VirtualProvisioningCollectionUpdate {
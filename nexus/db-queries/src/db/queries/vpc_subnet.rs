@@ -192,7 +192,8 @@ fn push_null_if_overlapping_ip_range<'a>(
 ///     time_modified,
 ///     time_deleted,
 ///     vpc_id,
-///     rcgen
+///     rcgen,
+///     custom_router_id
 /// ) AS (VALUES (
 ///     <id>,
 ///     <name>,
@@ -201,7 +202,8 @@ fn push_null_if_overlapping_ip_range<'a>(
 ///     <time_modified>,
 ///     NULL::TIMESTAMPTZ,
 ///     <vpc_id>,
-///     0
+///     0,
+///     <custom_router_id>
 /// )),
 /// candidate_ipv4(ipv4_block) AS (
 ///     SELECT(
@@ -280,6 +282,8 @@ impl QueryFragment<Pg> for FilterConflictingVpcSubnetRangesQuery {
         out.push_identifier(dsl::vpc_id::NAME)?;
         out.push_sql(",");
         out.push_identifier(dsl::rcgen::NAME)?;
+        out.push_sql(", ");
+        out.push_identifier(dsl::custom_router_id::NAME)?;
         out.push_sql(") AS (VALUES (");
         out.push_bind_param::<sql_types::Uuid, Uuid>(&self.subnet.identity.id)?;
         out.push_sql(", ");
@@ -301,7 +305,11 @@ impl QueryFragment<Pg> for FilterConflictingVpcSubnetRangesQuery {
         out.push_sql(", ");
         out.push_sql("NULL::TIMESTAMPTZ, ");
         out.push_bind_param::<sql_types::Uuid, Uuid>(&self.subnet.vpc_id)?;
-        out.push_sql(", 0)), ");
+        out.push_sql(", 0, ");
+        out.push_bind_param::<sql_types::Nullable<sql_types::Uuid>, Option<Uuid>>(
+            &self.subnet.custom_router_id,
+        )?;
+        out.push_sql(")), ");
 
         // Push the candidate IPv4 and IPv6 selection subqueries, which return
         // NULL if the corresponding address range overlaps.
@@ -385,6 +393,8 @@ impl QueryFragment<Pg> for FilterConflictingVpcSubnetRangesQueryValues {
         out.push_sql(", ");
         out.push_identifier(dsl::rcgen::NAME)?;
         out.push_sql(", ");
+        out.push_identifier(dsl::custom_router_id::NAME)?;
+        out.push_sql(", ");
         out.push_identifier(dsl::ipv4_block::NAME)?;
         out.push_sql(", ");
         out.push_identifier(dsl::ipv6_block::NAME)?;
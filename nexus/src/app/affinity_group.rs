@@ -0,0 +1,150 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Affinity and anti-affinity groups
+
+use crate::authz;
+use crate::db;
+use crate::db::lookup;
+use crate::db::lookup::LookupPath;
+use crate::external_api::params;
+use nexus_db_queries::context::OpContext;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::NameOrId;
+use omicron_common::api::external::UpdateResult;
+use uuid::Uuid;
+
+impl super::Nexus {
+    pub fn affinity_group_lookup<'a>(
+        &'a self,
+        opctx: &'a OpContext,
+        affinity_group_selector: params::AffinityGroupSelector,
+    ) -> LookupResult<lookup::AffinityGroup<'a>> {
+        match affinity_group_selector {
+            params::AffinityGroupSelector {
+                affinity_group: NameOrId::Id(id),
+                project: None,
+            } => {
+                let group = LookupPath::new(opctx, &self.db_datastore)
+                    .affinity_group_id(id);
+                Ok(group)
+            }
+            params::AffinityGroupSelector {
+                affinity_group: NameOrId::Name(name),
+                project: Some(project),
+            } => {
+                let group = self
+                    .project_lookup(opctx, params::ProjectSelector { project })?
+                    .affinity_group_name_owned(name.into());
+                Ok(group)
+            }
+            params::AffinityGroupSelector {
+                affinity_group: NameOrId::Id(_),
+                ..
+            } => Err(Error::invalid_request(
+                "when providing affinity_group as an ID, project should not \
+                be specified",
+            )),
+            _ => Err(Error::invalid_request(
+                "affinity_group should either be an ID or project should be \
+                specified",
+            )),
+        }
+    }
+
+    pub async fn affinity_group_create(
+        &self,
+        opctx: &OpContext,
+        project_lookup: &lookup::Project<'_>,
+        params: &params::AffinityGroupCreate,
+    ) -> CreateResult<db::model::AffinityGroup> {
+        let (.., authz_project) =
+            project_lookup.lookup_for(authz::Action::CreateChild).await?;
+        let group = db::model::AffinityGroup::new(
+            Uuid::new_v4(),
+            authz_project.id(),
+            params.clone(),
+        );
+        self.db_datastore
+            .affinity_group_create(opctx, &authz_project, group)
+            .await
+    }
+
+    pub async fn affinity_group_list(
+        &self,
+        opctx: &OpContext,
+        project_lookup: &lookup::Project<'_>,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<db::model::AffinityGroup> {
+        let (.., authz_project) =
+            project_lookup.lookup_for(authz::Action::ListChildren).await?;
+        self.db_datastore
+            .affinity_group_list(opctx, &authz_project, pagparams)
+            .await
+    }
+
+    pub async fn affinity_group_update(
+        &self,
+        opctx: &OpContext,
+        group_lookup: &lookup::AffinityGroup<'_>,
+        params: &params::AffinityGroupUpdate,
+    ) -> UpdateResult<db::model::AffinityGroup> {
+        let (.., authz_group) =
+            group_lookup.lookup_for(authz::Action::Modify).await?;
+        self.db_datastore
+            .affinity_group_update(opctx, &authz_group, params.clone().into())
+            .await
+    }
+
+    pub async fn affinity_group_delete(
+        &self,
+        opctx: &OpContext,
+        group_lookup: &lookup::AffinityGroup<'_>,
+    ) -> DeleteResult {
+        let (.., authz_group) =
+            group_lookup.lookup_for(authz::Action::Delete).await?;
+        self.db_datastore.affinity_group_delete(opctx, &authz_group).await
+    }
+
+    /// Resolves the affinity groups requested for a new instance in project
+    /// `project_id`, returning their IDs.
+    ///
+    /// Every group must be in the instance's project.
+    pub(crate) async fn affinity_groups_resolve_for_instance(
+        &self,
+        opctx: &OpContext,
+        project_id: Uuid,
+        groups: &[NameOrId],
+    ) -> ListResultVec<Uuid> {
+        let mut group_ids = Vec::with_capacity(groups.len());
+        for group in groups {
+            let lookup = match group {
+                NameOrId::Id(id) => LookupPath::new(opctx, &self.db_datastore)
+                    .affinity_group_id(*id),
+                NameOrId::Name(name) => {
+                    LookupPath::new(opctx, &self.db_datastore)
+                        .project_id(project_id)
+                        .affinity_group_name_owned(name.clone().into())
+                }
+            };
+            let (.., authz_project, authz_group) =
+                lookup.lookup_for(authz::Action::Modify).await?;
+            if authz_project.id() != project_id {
+                return Err(Error::invalid_request(&format!(
+                    "affinity group {} is not in the instance's project",
+                    group
+                )));
+            }
+            if !group_ids.contains(&authz_group.id()) {
+                group_ids.push(authz_group.id());
+            }
+        }
+        Ok(group_ids)
+    }
+}
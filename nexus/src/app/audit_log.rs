@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Audit log of external API requests
+
+use crate::db;
+use crate::external_api::params;
+use nexus_db_queries::context::OpContext;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DataPageParams;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::UpdateResult;
+
+impl super::Nexus {
+    /// Records an external API request in the audit log before it's handled
+    ///
+    /// This happens with Nexus's own credentials rather than those of the
+    /// request, since requests which fail authentication or authorization are
+    /// recorded too.
+    pub async fn audit_log_begin(
+        &self,
+        entry: db::model::AuditLogEntryInit,
+    ) -> CreateResult<db::model::AuditLogEntry> {
+        self.db_datastore.audit_log_begin(&self.opctx_audit_log, entry).await
+    }
+
+    /// Records the result of a request recorded with
+    /// [`Nexus::audit_log_begin()`]
+    pub async fn audit_log_complete(
+        &self,
+        entry: db::model::AuditLogEntry,
+        result_code: u16,
+    ) -> UpdateResult<db::model::AuditLogEntry> {
+        self.db_datastore
+            .audit_log_complete(
+                &self.opctx_audit_log,
+                entry,
+                chrono::Utc::now(),
+                result_code,
+            )
+            .await
+    }
+
+    pub async fn audit_log_list(
+        &self,
+        opctx: &OpContext,
+        filter: &params::AuditLogSelector,
+        pagparams: &DataPageParams<'_, u64>,
+    ) -> ListResultVec<db::model::AuditLogEntry> {
+        let marker = pagparams
+            .marker
+            .map(|seq| i64::try_from(*seq))
+            .transpose()
+            .map_err(|_| Error::invalid_request("invalid page token"))?;
+        let pagparams = DataPageParams {
+            marker: marker.as_ref(),
+            direction: pagparams.direction,
+            limit: pagparams.limit,
+        };
+        self.db_datastore.audit_log_list(opctx, filter, &pagparams).await
+    }
+}
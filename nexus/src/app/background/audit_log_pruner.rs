@@ -0,0 +1,80 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Background task for removing expired entries from the audit log
+
+use super::common::BackgroundTask;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::DataStore;
+use serde_json::json;
+use std::sync::Arc;
+
+/// Background task that removes audit log entries which are older than the
+/// configured retention period
+pub struct AuditLogPruner {
+    datastore: Arc<DataStore>,
+    retention: chrono::Duration,
+}
+
+impl AuditLogPruner {
+    pub fn new(
+        datastore: Arc<DataStore>,
+        retention_days: u32,
+    ) -> AuditLogPruner {
+        AuditLogPruner {
+            datastore,
+            retention: chrono::Duration::days(i64::from(retention_days)),
+        }
+    }
+}
+
+impl BackgroundTask for AuditLogPruner {
+    fn activate<'a, 'b, 'c>(
+        &'a mut self,
+        opctx: &'b OpContext,
+    ) -> BoxFuture<'c, serde_json::Value>
+    where
+        'a: 'c,
+        'b: 'c,
+    {
+        async {
+            let log = &opctx.log;
+            let cutoff = chrono::Utc::now() - self.retention;
+            match self.datastore.audit_log_prune(opctx, cutoff).await {
+                Ok(nremoved) => {
+                    if nremoved > 0 {
+                        info!(
+                            log,
+                            "removed expired audit log entries";
+                            "cutoff" => %cutoff,
+                            "nremoved" => nremoved,
+                        );
+                    }
+                    json!({
+                        "cutoff": cutoff,
+                        "nremoved": nremoved,
+                    })
+                }
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to remove expired audit log entries";
+                        "error" => format!("{:#}", error)
+                    );
+                    json!({
+                        "error":
+                            format!(
+                                "failed to remove expired audit log entries: \
+                                {:#}",
+                                error
+                            )
+                    })
+                }
+            }
+        }
+        .boxed()
+    }
+}
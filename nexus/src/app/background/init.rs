@@ -4,10 +4,17 @@
 
 //! Background task initialization
 
+use super::audit_log_pruner;
 use super::common;
 use super::dns_config;
 use super::dns_propagation;
 use super::dns_servers;
+use super::instance_auto_restart;
+use super::region_replacement;
+use super::sled_drain;
+use super::sled_health;
+use super::snapshot_scheduler;
+use crate::app::sagas::SagaRequest;
 use nexus_db_model::DnsGroup;
 use nexus_db_queries::context::OpContext;
 use nexus_db_queries::db::DataStore;
@@ -15,6 +22,7 @@ use omicron_common::nexus_config::BackgroundTaskConfig;
 use omicron_common::nexus_config::DnsTasksConfig;
 use std::collections::BTreeMap;
 use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
 
 /// Describes ongoing background tasks and provides interfaces for working with
 /// them
@@ -34,6 +42,18 @@ pub struct BackgroundTasks {
     pub task_external_dns_config: common::TaskHandle,
     /// task handle for the external DNS servers background task
     pub task_external_dns_servers: common::TaskHandle,
+    /// task handle for the audit log pruning background task
+    pub task_audit_log_pruner: common::TaskHandle,
+    /// task handle for the snapshot schedule background task
+    pub task_snapshot_scheduler: common::TaskHandle,
+    /// task handle for the instance auto-restart background task
+    pub task_instance_auto_restart: common::TaskHandle,
+    /// task handle for the sled health check background task
+    pub task_sled_health: common::TaskHandle,
+    /// task handle for the sled drain background task
+    pub task_sled_drain: common::TaskHandle,
+    /// task handle for the region replacement background task
+    pub task_region_replacement: common::TaskHandle,
 }
 
 impl BackgroundTasks {
@@ -42,6 +62,7 @@ impl BackgroundTasks {
         opctx: &OpContext,
         datastore: Arc<DataStore>,
         config: &BackgroundTaskConfig,
+        saga_request: Sender<SagaRequest>,
     ) -> BackgroundTasks {
         let mut driver = common::Driver::new();
 
@@ -55,17 +76,99 @@ impl BackgroundTasks {
         let (task_external_dns_config, task_external_dns_servers) = init_dns(
             &mut driver,
             opctx,
-            datastore,
+            datastore.clone(),
             DnsGroup::External,
             &config.dns_external,
         );
 
+        // Background task: audit log pruner
+        let task_audit_log_pruner = driver.register(
+            String::from("audit_log_pruner"),
+            config.audit_log.period_secs_prune,
+            Box::new(audit_log_pruner::AuditLogPruner::new(
+                datastore.clone(),
+                config.audit_log.retention_days,
+            )),
+            opctx.child(BTreeMap::new()),
+            vec![],
+        );
+
+        // Background task: snapshot scheduler
+        let task_snapshot_scheduler = driver.register(
+            String::from("snapshot_scheduler"),
+            config.snapshot_schedule.period_secs,
+            Box::new(snapshot_scheduler::SnapshotScheduler::new(
+                datastore.clone(),
+                saga_request.clone(),
+            )),
+            opctx.child(BTreeMap::new()),
+            vec![],
+        );
+
+        // Background task: instance auto-restart
+        let task_instance_auto_restart = driver.register(
+            String::from("instance_auto_restart"),
+            config.instance_auto_restart.period_secs,
+            Box::new(instance_auto_restart::InstanceAutoRestarter::new(
+                datastore.clone(),
+                saga_request.clone(),
+                config.instance_auto_restart.backoff_secs,
+                config.instance_auto_restart.max_backoff_secs,
+                config.instance_auto_restart.max_attempts,
+            )),
+            opctx.child(BTreeMap::new()),
+            vec![],
+        );
+
+        // Background task: sled health checks
+        let task_sled_health = driver.register(
+            String::from("sled_health"),
+            config.sled_health.period_secs,
+            Box::new(sled_health::SledHealthChecker::new(
+                datastore.clone(),
+                config.sled_health.unreachable_after,
+            )),
+            opctx.child(BTreeMap::new()),
+            vec![],
+        );
+
+        // Background task: sled drain
+        let task_sled_drain = driver.register(
+            String::from("sled_drain"),
+            config.sled_drain.period_secs,
+            Box::new(sled_drain::SledDrainer::new(
+                datastore.clone(),
+                saga_request.clone(),
+                config.sled_drain.max_concurrent_migrations,
+            )),
+            opctx.child(BTreeMap::new()),
+            vec![],
+        );
+
+        // Background task: region replacement
+        let task_region_replacement = driver.register(
+            String::from("region_replacement"),
+            config.region_replacement.period_secs,
+            Box::new(region_replacement::RegionReplacer::new(
+                datastore,
+                saga_request,
+            )),
+            opctx.child(BTreeMap::new()),
+            vec![],
+        );
+
         BackgroundTasks {
             driver,
             task_internal_dns_config,
             task_internal_dns_servers,
             task_external_dns_config,
             task_external_dns_servers,
+            task_audit_log_pruner,
+            task_snapshot_scheduler,
+            task_instance_auto_restart,
+            task_sled_health,
+            task_sled_drain,
+            task_region_replacement,
         }
     }
 
@@ -219,6 +322,7 @@ pub mod test {
             store,
             &dns_server::dns_server::Config {
                 bind_address: "[::1]:0".parse().unwrap(),
+                limits: Default::default(),
             },
             &dropshot::ConfigDropshot {
                 bind_address: "[::1]:0".parse().unwrap(),
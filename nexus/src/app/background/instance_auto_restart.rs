@@ -0,0 +1,441 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Background task for restarting instances according to their auto-restart
+//! policies
+
+use super::common::BackgroundTask;
+use crate::app::sagas::SagaRequest;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use nexus_db_model::Generation;
+use nexus_db_model::Instance;
+use nexus_db_model::InstanceRuntimeState;
+use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::identity::Resource;
+use nexus_db_queries::db::lookup::LookupPath;
+use nexus_db_queries::db::DataStore;
+use omicron_common::api::external::DataPageParams;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
+use serde_json::json;
+use sled_agent_client::Client as SledAgentClient;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// Number of instances read from the database at a time while looking for
+/// instances that were lost along with their sleds
+const INSTANCE_BATCH_SIZE: u32 = 100;
+
+/// Maximum number of instances restarted in one activation
+///
+/// If more than this many need to be restarted, the rest are picked up by the
+/// next activation.
+const MAX_RESTARTS_PER_ACTIVATION: u32 = 16;
+
+/// How long to wait for a sled agent to say which instances it has
+const SLED_AGENT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Background task that restarts instances according to their auto-restart
+/// policies
+///
+/// Each activation first looks for instances with an auto-restart policy that
+/// the database says are running, but whose sled has been removed or no longer
+/// knows about them (for example, because the sled rebooted).  Those instances
+/// are marked failed.  Unless the sled itself is gone, the same incarnation of
+/// an instance has to be missing from its sled in two activations in a row
+/// before it's considered lost, so that an instance that is legitimately
+/// coming or going isn't mistaken for a lost one.
+///
+/// The task then asks Nexus to restart each instance whose policy says it
+/// should be running again.  Each automatic restart of an instance puts off
+/// the next one by `backoff`, doubling with every restart in a row up to
+/// `max_backoff`, and a restart that fails counts the same as one that
+/// succeeds.  After `max_attempts` restarts in a row, an instance is left
+/// stopped.  An instance that stays up for `max_backoff` after its backoff runs
+/// out starts over with a count of zero.
+///
+/// Instances on a sled that doesn't respond are left alone: they may well
+/// still be running, and starting them somewhere else could leave two copies
+/// of the same instance running at once.  An operator who knows that an
+/// unreachable sled is gone for good must expunge it (with `POST
+/// /v1/system/hardware/sleds/{sled_id}/expunge`), after which its instances
+/// are marked failed and restarted on other sleds.
+pub struct InstanceAutoRestarter {
+    datastore: Arc<DataStore>,
+    saga_request: Sender<SagaRequest>,
+    backoff: Duration,
+    max_backoff: Duration,
+    max_attempts: u32,
+    /// instances that were missing from their sleds in the last activation,
+    /// along with the Propolis and state generations they had then
+    missing: BTreeMap<Uuid, (Generation, Generation)>,
+}
+
+impl InstanceAutoRestarter {
+    pub fn new(
+        datastore: Arc<DataStore>,
+        saga_request: Sender<SagaRequest>,
+        backoff: Duration,
+        max_backoff: Duration,
+        max_attempts: u32,
+    ) -> InstanceAutoRestarter {
+        InstanceAutoRestarter {
+            datastore,
+            saga_request,
+            backoff,
+            max_backoff,
+            max_attempts,
+            missing: BTreeMap::new(),
+        }
+    }
+
+    /// Returns how long to leave an instance alone after restarting it
+    /// automatically, given how many times in a row it had already been
+    fn backoff(&self, count: u32) -> chrono::Duration {
+        let backoff = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(count))
+            .min(self.max_backoff);
+        chrono::Duration::from_std(backoff).unwrap()
+    }
+
+    /// Marks failed the instances with an auto-restart policy that have been
+    /// lost along with their sleds, returning how many were marked
+    ///
+    /// Instances on sleds that can't be reached are skipped, since there's no
+    /// telling whether they're still running.  They're only marked failed once
+    /// their sled has been expunged.
+    async fn mark_lost_instances(
+        &mut self,
+        opctx: &OpContext,
+    ) -> Result<usize, Error> {
+        let mut by_sled: BTreeMap<Uuid, Vec<Instance>> = BTreeMap::new();
+        let mut marker = None;
+        loop {
+            let batch = self
+                .datastore
+                .instance_list_active_auto_restart(
+                    opctx,
+                    &DataPageParams {
+                        marker: marker.as_ref(),
+                        direction: dropshot::PaginationOrder::Ascending,
+                        limit: NonZeroU32::new(INSTANCE_BATCH_SIZE).unwrap(),
+                    },
+                )
+                .await?;
+            let done = batch.len() < INSTANCE_BATCH_SIZE as usize;
+            marker = batch.last().map(|instance| instance.id());
+            for instance in batch {
+                by_sled
+                    .entry(instance.runtime().sled_id)
+                    .or_default()
+                    .push(instance);
+            }
+            if done {
+                break;
+            }
+        }
+
+        let mut missing = BTreeMap::new();
+        let mut lost = Vec::new();
+        for (sled_id, instances) in by_sled {
+            let registered = match self.sled_instances(opctx, sled_id).await {
+                Ok(Some(registered)) => registered,
+                Ok(None) => {
+                    // The sled is gone, and its instances with it.
+                    lost.extend(instances);
+                    continue;
+                }
+                Err(error) => {
+                    // There's no telling what happened to these instances.
+                    // If the sled is gone for good, an operator has to
+                    // expunge it before they're restarted anywhere else.
+                    warn!(
+                        opctx.log,
+                        "failed to list the instances on a sled";
+                        "sled_id" => %sled_id,
+                        "error" => format!("{:#}", error),
+                    );
+                    continue;
+                }
+            };
+
+            for instance in instances {
+                if registered.contains(&instance.id()) {
+                    continue;
+                }
+                let runtime = instance.runtime();
+                let generations = (runtime.propolis_gen, runtime.gen);
+                if self.missing.get(&instance.id()) == Some(&generations) {
+                    lost.push(instance);
+                } else {
+                    missing.insert(instance.id(), generations);
+                }
+            }
+        }
+        self.missing = missing;
+
+        let mut nlost = 0;
+        for instance in lost {
+            let old_runtime = instance.runtime();
+            let new_runtime = InstanceRuntimeState {
+                state: nexus_db_model::InstanceState::new(
+                    InstanceState::Failed,
+                ),
+                time_updated: chrono::Utc::now(),
+                gen: old_runtime.gen.next().into(),
+                ..old_runtime.clone()
+            };
+            if self
+                .datastore
+                .instance_update_runtime(&instance.id(), &new_runtime)
+                .await?
+            {
+                warn!(
+                    opctx.log,
+                    "instance was lost along with its sled";
+                    "instance_id" => %instance.id(),
+                    "sled_id" => %old_runtime.sled_id,
+                );
+                nlost += 1;
+            }
+        }
+        Ok(nlost)
+    }
+
+    /// Returns the IDs of the instances registered on a sled, or `None` if the
+    /// sled no longer exists
+    async fn sled_instances(
+        &self,
+        opctx: &OpContext,
+        sled_id: Uuid,
+    ) -> Result<Option<BTreeSet<Uuid>>, Error> {
+        let sled = match LookupPath::new(opctx, &self.datastore)
+            .sled_id(sled_id)
+            .fetch()
+            .await
+        {
+            Ok((.., sled)) => sled,
+            Err(Error::ObjectNotFound { .. }) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        let client = reqwest::ClientBuilder::new()
+            .connect_timeout(SLED_AGENT_TIMEOUT)
+            .timeout(SLED_AGENT_TIMEOUT)
+            .build()
+            .unwrap();
+        let sled_client = SledAgentClient::new_with_client(
+            &format!("http://{}", sled.address()),
+            client,
+            opctx.log.new(o!("SledAgent" => sled_id.to_string())),
+        );
+        let instances = sled_client.instances_get().await?.into_inner();
+        Ok(Some(instances.into_iter().collect()))
+    }
+}
+
+impl BackgroundTask for InstanceAutoRestarter {
+    fn activate<'a, 'b, 'c>(
+        &'a mut self,
+        opctx: &'b OpContext,
+    ) -> BoxFuture<'c, serde_json::Value>
+    where
+        'a: 'c,
+        'b: 'c,
+    {
+        async {
+            let log = &opctx.log;
+            let mut errors = Vec::new();
+
+            let nlost = match self.mark_lost_instances(opctx).await {
+                Ok(nlost) => nlost,
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to look for lost instances";
+                        "error" => format!("{:#}", error)
+                    );
+                    errors.push(json!({
+                        "error":
+                            format!(
+                                "failed to look for lost instances: {:#}",
+                                error
+                            )
+                    }));
+                    0
+                }
+            };
+
+            let due = match self
+                .datastore
+                .instance_list_auto_restart_due(
+                    opctx,
+                    self.max_attempts,
+                    MAX_RESTARTS_PER_ACTIVATION,
+                )
+                .await
+            {
+                Ok(due) => due,
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to find instances to restart";
+                        "error" => format!("{:#}", error)
+                    );
+                    errors.push(json!({
+                        "error":
+                            format!(
+                                "failed to find instances to restart: {:#}",
+                                error
+                            )
+                    }));
+                    return json!({
+                        "nlost": nlost,
+                        "nrestarted": 0,
+                        "ngave_up": 0,
+                        "errors": errors,
+                    });
+                }
+            };
+
+            let mut nrestarted = 0;
+            let mut ngave_up = 0;
+            for instance in due {
+                let instance_id = instance.id();
+                let now = Utc::now();
+                let time_updated = instance.runtime().time_updated;
+                // An instance that stayed up for long enough after its last
+                // backoff ran out starts over.
+                let reset_after =
+                    chrono::Duration::from_std(self.max_backoff).unwrap();
+                let count = match instance.time_auto_restart_next {
+                    Some(next) if time_updated <= next + reset_after => {
+                        *instance.auto_restart_count
+                    }
+                    _ => 0,
+                };
+
+                if count >= self.max_attempts {
+                    // Leave the instance alone until it changes state again.
+                    // If someone starts it through the API and it then stays
+                    // up for long enough, it gets a fresh set of attempts.
+                    if let Err(error) = self
+                        .datastore
+                        .instance_set_auto_restart_backoff(
+                            &instance_id,
+                            count,
+                            now.max(time_updated),
+                        )
+                        .await
+                    {
+                        warn!(
+                            log,
+                            "failed to record giving up on restarting instance";
+                            "instance_id" => %instance_id,
+                            "error" => format!("{:#}", error),
+                        );
+                        errors.push(json!({
+                            "instance_id": instance_id,
+                            "error": format!("{:#}", error),
+                        }));
+                        continue;
+                    }
+                    warn!(
+                        log,
+                        "giving up on restarting instance";
+                        "instance_id" => %instance_id,
+                        "attempts" => count,
+                    );
+                    ngave_up += 1;
+                    continue;
+                }
+
+                // Record the attempt before making it, so that an instance
+                // that can't be restarted backs off too.
+                if let Err(error) = self
+                    .datastore
+                    .instance_set_auto_restart_backoff(
+                        &instance_id,
+                        count + 1,
+                        now + self.backoff(count),
+                    )
+                    .await
+                {
+                    warn!(
+                        log,
+                        "failed to record automatic restart of instance";
+                        "instance_id" => %instance_id,
+                        "error" => format!("{:#}", error),
+                    );
+                    errors.push(json!({
+                        "instance_id": instance_id,
+                        "error": format!("{:#}", error),
+                    }));
+                    continue;
+                }
+
+                let (reply, reply_rx) = oneshot::channel();
+                let request =
+                    SagaRequest::InstanceAutoRestart { instance_id, reply };
+                let result = match self.saga_request.send(request).await {
+                    Ok(()) => match reply_rx.await {
+                        Ok(Ok(restarted)) => Ok(restarted),
+                        Ok(Err(error)) => Err(format!("{:#}", error)),
+                        Err(_) => Err(String::from(
+                            "Nexus dropped the request to restart the \
+                            instance",
+                        )),
+                    },
+                    Err(_) => Err(String::from(
+                        "failed to ask Nexus to restart the instance: saga \
+                        request channel closed",
+                    )),
+                };
+
+                match result {
+                    Ok(true) => {
+                        info!(
+                            log,
+                            "restarted instance";
+                            "instance_id" => %instance_id,
+                            "auto_restart_policy" =>
+                                %instance.auto_restart_policy.policy(),
+                        );
+                        nrestarted += 1;
+                    }
+                    Ok(false) => (),
+                    Err(error) => {
+                        warn!(
+                            log,
+                            "failed to restart instance";
+                            "instance_id" => %instance_id,
+                            "error" => &error,
+                        );
+                        errors.push(json!({
+                            "instance_id": instance_id,
+                            "error": error,
+                        }));
+                    }
+                }
+            }
+
+            json!({
+                "nlost": nlost,
+                "nrestarted": nrestarted,
+                "ngave_up": ngave_up,
+                "errors": errors,
+            })
+        }
+        .boxed()
+    }
+}
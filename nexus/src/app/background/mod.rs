@@ -4,11 +4,17 @@
 
 //! Background tasks
 
+mod audit_log_pruner;
 mod common;
 mod dns_config;
 mod dns_propagation;
 mod dns_servers;
 mod init;
+mod instance_auto_restart;
+mod region_replacement;
+mod sled_drain;
+mod sled_health;
+mod snapshot_scheduler;
 
 pub use common::Driver;
 pub use common::TaskHandle;
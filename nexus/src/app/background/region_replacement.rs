@@ -0,0 +1,301 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Background task for replacing Crucible regions that were lost along with
+//! their physical disks, zpools or sleds
+
+use super::common::BackgroundTask;
+use crate::app::sagas::SagaRequest;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use nexus_db_model::RegionReplacement;
+use nexus_db_model::RegionReplacementState;
+use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::identity::Asset;
+use nexus_db_queries::db::lookup::LookupPath;
+use nexus_db_queries::db::DataStore;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+/// Background task that replaces Crucible regions whose datasets were lost
+///
+/// Each activation records a replacement request for every region on a lost
+/// dataset, then asks Nexus to carry out each request that's waiting, one at
+/// a time.  Requests that fail are tried again by a later activation.  It
+/// also notices when instances that were still using lost regions have been
+/// restarted or migrated, which finishes the replacement of those regions.
+pub struct RegionReplacer {
+    datastore: Arc<DataStore>,
+    saga_request: Sender<SagaRequest>,
+}
+
+impl RegionReplacer {
+    pub fn new(
+        datastore: Arc<DataStore>,
+        saga_request: Sender<SagaRequest>,
+    ) -> RegionReplacer {
+        RegionReplacer { datastore, saga_request }
+    }
+
+    /// Records a replacement request for each newly lost region, returning
+    /// how many were recorded
+    async fn request_replacements(
+        &self,
+        opctx: &OpContext,
+    ) -> Result<usize, Error> {
+        let lost = self.datastore.find_regions_on_lost_datasets(opctx).await?;
+        if lost.is_empty() {
+            return Ok(0);
+        }
+        for (region, disk_id) in &lost {
+            warn!(
+                opctx.log,
+                "found region on lost dataset";
+                "region_id" => %region.id(),
+                "dataset_id" => %region.dataset_id(),
+                "volume_id" => %region.volume_id(),
+                "disk_id" => %disk_id,
+            );
+        }
+        let replacements = lost
+            .iter()
+            .map(|(region, disk_id)| RegionReplacement::new(region, *disk_id))
+            .collect();
+        self.datastore.region_replacements_create(opctx, replacements).await
+    }
+
+    /// Returns whether the instance that was still using the old region of
+    /// `replacement` has stopped using it
+    async fn old_region_released(
+        &self,
+        opctx: &OpContext,
+        replacement: &RegionReplacement,
+    ) -> Result<bool, Error> {
+        let disk = match LookupPath::new(opctx, &self.datastore)
+            .disk_id(replacement.disk_id)
+            .fetch()
+            .await
+        {
+            Ok((.., disk)) => disk,
+            Err(Error::ObjectNotFound { .. }) => return Ok(true),
+            Err(error) => return Err(error),
+        };
+        let Some(instance_id) = disk.runtime().attach_instance_id else {
+            return Ok(true);
+        };
+        let instance = match LookupPath::new(opctx, &self.datastore)
+            .instance_id(instance_id)
+            .fetch()
+            .await
+        {
+            Ok((.., instance)) => instance,
+            Err(Error::ObjectNotFound { .. }) => return Ok(true),
+            Err(error) => return Err(error),
+        };
+
+        let runtime = instance.runtime();
+        let running = matches!(
+            runtime.state.0,
+            InstanceState::Running
+                | InstanceState::Rebooting
+                | InstanceState::Migrating
+        );
+        Ok(!running || Some(runtime.propolis_id) != replacement.propolis_id)
+    }
+
+    /// Asks Nexus to replace the old region of `replacement`
+    async fn replace(
+        &self,
+        replacement: &RegionReplacement,
+    ) -> Result<bool, String> {
+        let (reply, reply_rx) = oneshot::channel();
+        let request = SagaRequest::RegionReplace {
+            replacement_id: replacement.id(),
+            reply,
+        };
+        match self.saga_request.send(request).await {
+            Ok(()) => match reply_rx.await {
+                Ok(Ok(replaced)) => Ok(replaced),
+                Ok(Err(error)) => Err(format!("{:#}", error)),
+                Err(_) => Err(String::from(
+                    "Nexus dropped the request to replace the region",
+                )),
+            },
+            Err(_) => Err(String::from(
+                "failed to ask Nexus to replace the region: saga request \
+                channel closed",
+            )),
+        }
+    }
+}
+
+impl BackgroundTask for RegionReplacer {
+    fn activate<'a, 'b, 'c>(
+        &'a mut self,
+        opctx: &'b OpContext,
+    ) -> BoxFuture<'c, serde_json::Value>
+    where
+        'a: 'c,
+        'b: 'c,
+    {
+        async {
+            let log = &opctx.log;
+            let mut errors = Vec::new();
+
+            let nrequested = match self.request_replacements(opctx).await {
+                Ok(nrequested) => nrequested,
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to look for regions on lost datasets";
+                        "error" => format!("{:#}", error)
+                    );
+                    errors.push(json!({
+                        "error": format!(
+                            "failed to look for regions on lost datasets: {:#}",
+                            error
+                        ),
+                    }));
+                    0
+                }
+            };
+
+            let requested = match self
+                .datastore
+                .region_replacement_list_in_state(
+                    opctx,
+                    RegionReplacementState::Requested,
+                )
+                .await
+            {
+                Ok(requested) => requested,
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to list region replacements";
+                        "error" => format!("{:#}", error)
+                    );
+                    return json!({
+                        "error":
+                            format!(
+                                "failed to list region replacements: {:#}",
+                                error
+                            )
+                    });
+                }
+            };
+            let mut nreplaced = 0;
+            for replacement in &requested {
+                match self.replace(replacement).await {
+                    Ok(true) => {
+                        info!(
+                            log,
+                            "replaced lost region";
+                            "replacement_id" => %replacement.id(),
+                            "old_region_id" => %replacement.old_region_id,
+                            "volume_id" => %replacement.volume_id,
+                        );
+                        nreplaced += 1;
+                    }
+                    Ok(false) => (),
+                    Err(error) => {
+                        warn!(
+                            log,
+                            "failed to replace lost region";
+                            "replacement_id" => %replacement.id(),
+                            "old_region_id" => %replacement.old_region_id,
+                            "error" => &error,
+                        );
+                        errors.push(json!({
+                            "replacement_id": replacement.id(),
+                            "error": error,
+                        }));
+                    }
+                }
+            }
+
+            let awaiting_restart = match self
+                .datastore
+                .region_replacement_list_in_state(
+                    opctx,
+                    RegionReplacementState::AwaitingRestart,
+                )
+                .await
+            {
+                Ok(awaiting_restart) => awaiting_restart,
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to list region replacements";
+                        "error" => format!("{:#}", error)
+                    );
+                    return json!({
+                        "error":
+                            format!(
+                                "failed to list region replacements: {:#}",
+                                error
+                            )
+                    });
+                }
+            };
+            let mut nrestarted = 0;
+            let mut instances_to_restart = Vec::new();
+            for replacement in &awaiting_restart {
+                let result =
+                    match self.old_region_released(opctx, replacement).await {
+                        Ok(false) => {
+                            instances_to_restart
+                                .extend(replacement.instance_id);
+                            continue;
+                        }
+                        Ok(true) => {
+                            self.datastore
+                                .region_replacement_set_state(
+                                    opctx,
+                                    replacement.id(),
+                                    RegionReplacementState::AwaitingRestart,
+                                    RegionReplacementState::Complete,
+                                    replacement.new_region_id,
+                                    None,
+                                )
+                                .await
+                        }
+                        Err(error) => Err(error),
+                    };
+                match result {
+                    Ok(_) => nrestarted += 1,
+                    Err(error) => {
+                        warn!(
+                            log,
+                            "failed to check on instance using lost region";
+                            "replacement_id" => %replacement.id(),
+                            "error" => format!("{:#}", error),
+                        );
+                        errors.push(json!({
+                            "replacement_id": replacement.id(),
+                            "error": format!("{:#}", error),
+                        }));
+                    }
+                }
+            }
+
+            json!({
+                "nrequested": nrequested,
+                "nreplaced": nreplaced,
+                "nrestarted": nrestarted,
+                // Volumes still running on fewer regions than they should,
+                // because the instance using them hasn't been restarted since
+                // the replacement.
+                "ndegraded": awaiting_restart.len() - nrestarted,
+                "instances_to_restart": instances_to_restart,
+                "errors": errors,
+            })
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,204 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Background task for migrating instances off of sleds that are being drained
+
+use super::common::BackgroundTask;
+use crate::app::sagas::SagaRequest;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use nexus_db_model::Instance;
+use nexus_db_model::Sled;
+use nexus_db_queries::authz;
+use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::identity::Asset;
+use nexus_db_queries::db::identity::Resource;
+use nexus_db_queries::db::lookup::LookupPath;
+use nexus_db_queries::db::DataStore;
+use omicron_common::api::external::DataPageParams;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
+use serde_json::json;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+/// Number of sleds read from the database at a time
+const SLED_BATCH_SIZE: u32 = 100;
+
+/// Background task that migrates instances off of sleds that are being drained
+///
+/// Each activation starts migrating running instances off of every draining
+/// sled, keeping no more than `max_concurrent_migrations` migrations going at
+/// once across all of them.  Instances that can't be migrated yet (because
+/// the limit was reached or the migration failed) are picked up by a later
+/// activation.
+pub struct SledDrainer {
+    datastore: Arc<DataStore>,
+    saga_request: Sender<SagaRequest>,
+    max_concurrent_migrations: usize,
+}
+
+impl SledDrainer {
+    pub fn new(
+        datastore: Arc<DataStore>,
+        saga_request: Sender<SagaRequest>,
+        max_concurrent_migrations: usize,
+    ) -> SledDrainer {
+        SledDrainer { datastore, saga_request, max_concurrent_migrations }
+    }
+
+    /// Returns the instances on each sled that's being drained
+    async fn draining_sleds(
+        &self,
+        opctx: &OpContext,
+    ) -> Result<Vec<(Sled, Vec<Instance>)>, Error> {
+        let mut sleds = Vec::new();
+        let mut marker = None;
+        loop {
+            let batch = self
+                .datastore
+                .sled_list_draining(
+                    opctx,
+                    &DataPageParams {
+                        marker: marker.as_ref(),
+                        direction: dropshot::PaginationOrder::Ascending,
+                        limit: NonZeroU32::new(SLED_BATCH_SIZE).unwrap(),
+                    },
+                )
+                .await?;
+            let done = batch.len() < SLED_BATCH_SIZE as usize;
+            marker = batch.last().map(|sled| sled.id());
+            sleds.extend(batch);
+            if done {
+                break;
+            }
+        }
+
+        let mut draining = Vec::with_capacity(sleds.len());
+        for sled in sleds {
+            let (authz_sled,) = LookupPath::new(opctx, &self.datastore)
+                .sled_id(sled.id())
+                .lookup_for(authz::Action::Read)
+                .await?;
+            let instances =
+                self.datastore.sled_instance_list(opctx, &authz_sled).await?;
+            draining.push((sled, instances));
+        }
+        Ok(draining)
+    }
+}
+
+impl BackgroundTask for SledDrainer {
+    fn activate<'a, 'b, 'c>(
+        &'a mut self,
+        opctx: &'b OpContext,
+    ) -> BoxFuture<'c, serde_json::Value>
+    where
+        'a: 'c,
+        'b: 'c,
+    {
+        async {
+            let log = &opctx.log;
+            let draining = match self.draining_sleds(opctx).await {
+                Ok(draining) => draining,
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to list draining sleds";
+                        "error" => format!("{:#}", error)
+                    );
+                    return json!({
+                        "error":
+                            format!(
+                                "failed to list draining sleds: {:#}",
+                                error
+                            )
+                    });
+                }
+            };
+
+            let nmigrating = draining
+                .iter()
+                .flat_map(|(_, instances)| instances)
+                .filter(|instance| instance.runtime().migration_id.is_some())
+                .count();
+            let mut budget =
+                self.max_concurrent_migrations.saturating_sub(nmigrating);
+            let mut nstarted = 0;
+            let mut errors = Vec::new();
+            for (sled, instances) in &draining {
+                for instance in instances {
+                    if budget == 0 {
+                        break;
+                    }
+                    let runtime = instance.runtime();
+                    if runtime.state.0 != InstanceState::Running
+                        || runtime.migration_id.is_some()
+                    {
+                        continue;
+                    }
+
+                    let instance_id = instance.id();
+                    let (reply, reply_rx) = oneshot::channel();
+                    let request =
+                        SagaRequest::SledDrainMigrate { instance_id, reply };
+                    let result = match self.saga_request.send(request).await {
+                        Ok(()) => match reply_rx.await {
+                            Ok(Ok(started)) => Ok(started),
+                            Ok(Err(error)) => Err(format!("{:#}", error)),
+                            Err(_) => Err(String::from(
+                                "Nexus dropped the request to migrate the \
+                                instance",
+                            )),
+                        },
+                        Err(_) => Err(String::from(
+                            "failed to ask Nexus to migrate the instance: \
+                            saga request channel closed",
+                        )),
+                    };
+
+                    match result {
+                        Ok(true) => {
+                            info!(
+                                log,
+                                "started migrating instance off of draining \
+                                sled";
+                                "instance_id" => %instance_id,
+                                "sled_id" => %sled.id(),
+                            );
+                            budget -= 1;
+                            nstarted += 1;
+                        }
+                        Ok(false) => (),
+                        Err(error) => {
+                            warn!(
+                                log,
+                                "failed to migrate instance off of draining \
+                                sled";
+                                "instance_id" => %instance_id,
+                                "sled_id" => %sled.id(),
+                                "error" => &error,
+                            );
+                            errors.push(json!({
+                                "instance_id": instance_id,
+                                "sled_id": sled.id(),
+                                "error": error,
+                            }));
+                        }
+                    }
+                }
+            }
+
+            json!({
+                "nsleds": draining.len(),
+                "nmigrating": nmigrating,
+                "nstarted": nstarted,
+                "errors": errors,
+            })
+        }
+        .boxed()
+    }
+}
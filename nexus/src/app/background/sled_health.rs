@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Background task for checking whether each sled agent is responding
+
+use super::common::BackgroundTask;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use nexus_db_model::Sled;
+use nexus_db_model::SledState;
+use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::identity::Asset;
+use nexus_db_queries::db::DataStore;
+use omicron_common::api::external::DataPageParams;
+use omicron_common::api::external::Error;
+use serde_json::json;
+use sled_agent_client::Client as SledAgentClient;
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Number of sleds read from the database at a time
+const SLED_BATCH_SIZE: u32 = 100;
+
+/// How long to wait for a sled agent to answer a health check
+const SLED_AGENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Background task that checks whether each sled agent is responding
+///
+/// A sled that answers its health check is active.  A sled that misses a check
+/// is degraded until it has missed `unreachable_after` checks in a row, after
+/// which it's unreachable.  Only active sleds are chosen to run new instances.
+/// Expunged sleds aren't checked at all.
+pub struct SledHealthChecker {
+    datastore: Arc<DataStore>,
+    unreachable_after: u32,
+    /// number of health checks in a row that each sled has missed
+    missed: BTreeMap<Uuid, u32>,
+}
+
+impl SledHealthChecker {
+    pub fn new(
+        datastore: Arc<DataStore>,
+        unreachable_after: u32,
+    ) -> SledHealthChecker {
+        SledHealthChecker {
+            datastore,
+            unreachable_after,
+            missed: BTreeMap::new(),
+        }
+    }
+
+    async fn list_sleds(&self, opctx: &OpContext) -> Result<Vec<Sled>, Error> {
+        let mut sleds = Vec::new();
+        let mut marker = None;
+        loop {
+            let batch = self
+                .datastore
+                .sled_list(
+                    opctx,
+                    &DataPageParams {
+                        marker: marker.as_ref(),
+                        direction: dropshot::PaginationOrder::Ascending,
+                        limit: NonZeroU32::new(SLED_BATCH_SIZE).unwrap(),
+                    },
+                )
+                .await?;
+            let done = batch.len() < SLED_BATCH_SIZE as usize;
+            marker = batch.last().map(|sled| sled.id());
+            sleds.extend(batch);
+            if done {
+                return Ok(sleds);
+            }
+        }
+    }
+}
+
+/// Returns whether the sled agent on `sled` answered a request
+async fn sled_responds(opctx: &OpContext, sled: &Sled) -> bool {
+    let client = reqwest::ClientBuilder::new()
+        .connect_timeout(SLED_AGENT_TIMEOUT)
+        .timeout(SLED_AGENT_TIMEOUT)
+        .build()
+        .unwrap();
+    let sled_client = SledAgentClient::new_with_client(
+        &format!("http://{}", sled.address()),
+        client,
+        opctx.log.new(o!("SledAgent" => sled.id().to_string())),
+    );
+    match sled_client.instances_get().await {
+        Ok(_) => true,
+        Err(error) => {
+            debug!(
+                opctx.log,
+                "sled agent missed a health check";
+                "sled_id" => %sled.id(),
+                "error" => %error,
+            );
+            false
+        }
+    }
+}
+
+impl BackgroundTask for SledHealthChecker {
+    fn activate<'a, 'b, 'c>(
+        &'a mut self,
+        opctx: &'b OpContext,
+    ) -> BoxFuture<'c, serde_json::Value>
+    where
+        'a: 'c,
+        'b: 'c,
+    {
+        async {
+            let log = &opctx.log;
+            let sleds = match self.list_sleds(opctx).await {
+                Ok(sleds) => sleds,
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to list sleds";
+                        "error" => format!("{:#}", error)
+                    );
+                    return json!({
+                        "error": format!("failed to list sleds: {:#}", error)
+                    });
+                }
+            };
+
+            let sleds: Vec<Sled> = sleds
+                .into_iter()
+                .filter(|sled| sled.state != SledState::Expunged)
+                .collect();
+            let responses = futures::future::join_all(
+                sleds.iter().map(|sled| sled_responds(opctx, sled)),
+            )
+            .await;
+
+            let mut missed = BTreeMap::new();
+            let mut nactive = 0;
+            let mut ndegraded = 0;
+            let mut nunreachable = 0;
+            let mut errors = Vec::new();
+            for (sled, responded) in sleds.iter().zip(responses) {
+                let state = if responded {
+                    SledState::Active
+                } else {
+                    let nmissed =
+                        self.missed.get(&sled.id()).copied().unwrap_or(0) + 1;
+                    missed.insert(sled.id(), nmissed);
+                    if nmissed >= self.unreachable_after {
+                        SledState::Unreachable
+                    } else {
+                        SledState::Degraded
+                    }
+                };
+                match state {
+                    SledState::Active => nactive += 1,
+                    SledState::Degraded => ndegraded += 1,
+                    _ => nunreachable += 1,
+                }
+
+                if state == sled.state {
+                    continue;
+                }
+                match self
+                    .datastore
+                    .sled_set_state(opctx, sled.id(), state)
+                    .await
+                {
+                    Ok(true) => {
+                        info!(
+                            log,
+                            "sled health changed";
+                            "sled_id" => %sled.id(),
+                            "old_state" => ?sled.state,
+                            "new_state" => ?state,
+                        );
+                    }
+                    Ok(false) => (),
+                    Err(error) => {
+                        warn!(
+                            log,
+                            "failed to record sled health";
+                            "sled_id" => %sled.id(),
+                            "error" => format!("{:#}", error),
+                        );
+                        errors.push(json!({
+                            "sled_id": sled.id(),
+                            "error": format!("{:#}", error),
+                        }));
+                    }
+                }
+            }
+            self.missed = missed;
+
+            json!({
+                "nactive": nactive,
+                "ndegraded": ndegraded,
+                "nunreachable": nunreachable,
+                "errors": errors,
+            })
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Background task for taking snapshots on snapshot schedules
+
+use super::common::BackgroundTask;
+use crate::app::sagas::SagaRequest;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::identity::Resource;
+use nexus_db_queries::db::DataStore;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+/// Maximum number of schedules claimed in one activation
+///
+/// If more than this many are due, the rest are picked up by the next
+/// activation.
+const MAX_SCHEDULES_PER_ACTIVATION: u32 = 32;
+
+/// Background task that takes a snapshot for each snapshot schedule that is
+/// due, and expires old snapshots taken by that schedule
+///
+/// Taking and deleting snapshots are driven by sagas, which this task asks
+/// Nexus to run.  Snapshots for a given activation are taken one at a time.
+pub struct SnapshotScheduler {
+    datastore: Arc<DataStore>,
+    saga_request: Sender<SagaRequest>,
+}
+
+impl SnapshotScheduler {
+    pub fn new(
+        datastore: Arc<DataStore>,
+        saga_request: Sender<SagaRequest>,
+    ) -> SnapshotScheduler {
+        SnapshotScheduler { datastore, saga_request }
+    }
+}
+
+impl BackgroundTask for SnapshotScheduler {
+    fn activate<'a, 'b, 'c>(
+        &'a mut self,
+        opctx: &'b OpContext,
+    ) -> BoxFuture<'c, serde_json::Value>
+    where
+        'a: 'c,
+        'b: 'c,
+    {
+        async {
+            let log = &opctx.log;
+            let now = chrono::Utc::now();
+            let schedules = match self
+                .datastore
+                .snapshot_schedules_claim_due(
+                    opctx,
+                    now,
+                    MAX_SCHEDULES_PER_ACTIVATION,
+                )
+                .await
+            {
+                Ok(schedules) => schedules,
+                Err(error) => {
+                    warn!(
+                        log,
+                        "failed to find snapshot schedules that are due";
+                        "error" => format!("{:#}", error)
+                    );
+                    return json!({
+                        "error":
+                            format!(
+                                "failed to find snapshot schedules that are \
+                                due: {:#}",
+                                error
+                            )
+                    });
+                }
+            };
+
+            let mut nsucceeded = 0;
+            let mut errors = Vec::new();
+            for schedule in schedules {
+                let schedule_id = schedule.id();
+                let (reply, reply_rx) = oneshot::channel();
+                let request = SagaRequest::SnapshotSchedule { schedule, reply };
+                let result = match self.saga_request.send(request).await {
+                    Ok(()) => match reply_rx.await {
+                        Ok(Ok(time_snapshot)) => Ok(time_snapshot),
+                        Ok(Err(error)) => Err(format!("{:#}", error)),
+                        Err(_) => Err(String::from(
+                            "Nexus dropped the request to take a snapshot",
+                        )),
+                    },
+                    Err(_) => Err(String::from(
+                        "failed to ask Nexus to take a snapshot: saga \
+                        request channel closed",
+                    )),
+                };
+
+                match &result {
+                    Ok(_) => {
+                        info!(
+                            log,
+                            "took scheduled snapshot";
+                            "snapshot_schedule_id" => %schedule_id,
+                        );
+                        nsucceeded += 1;
+                    }
+                    Err(error) => {
+                        warn!(
+                            log,
+                            "failed to take scheduled snapshot";
+                            "snapshot_schedule_id" => %schedule_id,
+                            "error" => error,
+                        );
+                        errors.push(json!({
+                            "snapshot_schedule_id": schedule_id,
+                            "error": error,
+                        }));
+                    }
+                }
+
+                if let Err(error) = self
+                    .datastore
+                    .snapshot_schedule_record_result(opctx, schedule_id, result)
+                    .await
+                {
+                    warn!(
+                        log,
+                        "failed to record status of snapshot schedule";
+                        "snapshot_schedule_id" => %schedule_id,
+                        "error" => format!("{:#}", error),
+                    );
+                }
+            }
+
+            json!({
+                "nsucceeded": nsucceeded,
+                "errors": errors,
+            })
+        }
+        .boxed()
+    }
+}
@@ -151,23 +151,25 @@ impl super::Nexus {
 
     /// Look up the actor for which a token was granted.
     /// Corresponds to a request *after* completing the flow above.
+    ///
+    /// Access tokens created directly by users (see `access_token_create`)
+    /// are presented in the same way, so we also accept those here.
     pub async fn device_access_token_actor(
         &self,
         opctx: &OpContext,
         token: String,
     ) -> Result<Actor, Reason> {
-        let (.., db_access_token) = LookupPath::new(opctx, &self.db_datastore)
+        let silo_user_id = match LookupPath::new(opctx, &self.db_datastore)
             .device_access_token(&token)
             .fetch()
             .await
-            .map_err(|e| match e {
-                Error::ObjectNotFound { .. } => Reason::UnknownActor {
-                    actor: "from device access token".to_string(),
-                },
-                e => Reason::UnknownError { source: e },
-            })?;
-
-        let silo_user_id = db_access_token.silo_user_id;
+        {
+            Ok((.., db_access_token)) => db_access_token.silo_user_id,
+            Err(Error::ObjectNotFound { .. }) => {
+                self.access_token_user(opctx, token).await?
+            }
+            Err(e) => return Err(Reason::UnknownError { source: e }),
+        };
         let (.., db_silo_user) = LookupPath::new(opctx, &self.db_datastore)
             .silo_user_id(silo_user_id)
             .fetch()
@@ -182,4 +184,28 @@ impl super::Nexus {
 
         Ok(Actor::SiloUser { silo_user_id, silo_id })
     }
+
+    /// Look up the user to whom an unexpired access token belongs.
+    async fn access_token_user(
+        &self,
+        opctx: &OpContext,
+        token: String,
+    ) -> Result<Uuid, Reason> {
+        let db_access_token = self
+            .db_datastore
+            .access_token_fetch_by_token(opctx, token)
+            .await
+            .map_err(|e| match e {
+                Error::ObjectNotFound { .. } => Reason::UnknownActor {
+                    actor: "from device access token".to_string(),
+                },
+                e => Reason::UnknownError { source: e },
+            })?;
+        if db_access_token.is_expired(Utc::now()) {
+            return Err(Reason::UnknownActor {
+                actor: "from expired access token".to_string(),
+            });
+        }
+        Ok(db_access_token.silo_user_id)
+    }
 }
@@ -4,6 +4,7 @@
 
 //! Disks and snapshots
 
+use crate::app::image_conversion;
 use crate::app::sagas;
 use crate::authn;
 use crate::authz;
@@ -19,7 +20,9 @@ use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::DiskState;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
 use omicron_common::api::external::InternalContext;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::NameOrId;
@@ -33,9 +36,13 @@ fn validate_disk_create_params(
     params: &params::DiskCreate,
     block_size: u64,
 ) -> Result<(), Error> {
+    validate_disk_size(&params.size, block_size)
+}
+
+fn validate_disk_size(size: &ByteCount, block_size: u64) -> Result<(), Error> {
     // Reject disks where the block size doesn't evenly divide the
     // total size
-    if (params.size.to_bytes() % block_size) != 0 {
+    if (size.to_bytes() % block_size) != 0 {
         return Err(Error::InvalidValue {
             label: String::from("size and block_size"),
             message: format!(
@@ -47,7 +54,7 @@ fn validate_disk_create_params(
 
     // Reject disks where the size isn't at least
     // MIN_DISK_SIZE_BYTES
-    if params.size.to_bytes() < params::MIN_DISK_SIZE_BYTES as u64 {
+    if size.to_bytes() < params::MIN_DISK_SIZE_BYTES as u64 {
         return Err(Error::InvalidValue {
             label: String::from("size"),
             message: format!(
@@ -59,7 +66,7 @@ fn validate_disk_create_params(
 
     // Reject disks where the MIN_DISK_SIZE_BYTES doesn't evenly
     // divide the size
-    if (params.size.to_bytes() % params::MIN_DISK_SIZE_BYTES as u64) != 0 {
+    if (size.to_bytes() % params::MIN_DISK_SIZE_BYTES as u64) != 0 {
         return Err(Error::InvalidValue {
             label: String::from("size"),
             message: format!(
@@ -250,10 +257,13 @@ impl super::Nexus {
         opctx: &OpContext,
         project_lookup: &lookup::Project<'_>,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<db::model::Disk> {
         let (.., authz_project) =
             project_lookup.lookup_for(authz::Action::ListChildren).await?;
-        self.db_datastore.disk_list(opctx, &authz_project, pagparams).await
+        self.db_datastore
+            .disk_list(opctx, &authz_project, pagparams, label_selector)
+            .await
     }
 
     /// Modifies the runtime state of the Disk as requested.  This generally
@@ -374,6 +384,78 @@ impl super::Nexus {
         Ok(())
     }
 
+    /// Grow a disk to a new, larger size.
+    ///
+    /// The disk must be either detached or attached to an instance. The
+    /// guest of an instance that's running doesn't see the additional space
+    /// until the instance is stopped and started again: its sled agent is
+    /// sent the grown volume, but only uses it the next time it creates a
+    /// Propolis for the instance.
+    pub async fn disk_resize(
+        self: &Arc<Self>,
+        opctx: &OpContext,
+        disk_lookup: &lookup::Disk<'_>,
+        resize_params: &params::DiskResize,
+    ) -> UpdateResult<db::model::Disk> {
+        let (.., authz_proj, authz_disk, db_disk) =
+            disk_lookup.fetch_for(authz::Action::Modify).await?;
+
+        let disk_state: DiskState = db_disk.state().into();
+        match disk_state {
+            DiskState::Detached => {}
+            // TODO-completeness: Propolis can't yet grow a disk that's in use,
+            // so the guest of a running instance wouldn't see the new size.
+            DiskState::Attached(instance_id) => {
+                let (.., db_instance) =
+                    LookupPath::new(opctx, &self.db_datastore)
+                        .instance_id(instance_id)
+                        .fetch()
+                        .await?;
+                let instance_state = db_instance.runtime().state.0;
+                if instance_state != InstanceState::Stopped {
+                    return Err(Error::invalid_request(&format!(
+                        "cannot resize a disk attached to an instance in \
+                        state {}; stop the instance first",
+                        instance_state.label(),
+                    )));
+                }
+            }
+            _ => {
+                return Err(Error::invalid_request(&format!(
+                    "cannot resize disk in state {}",
+                    disk_state.label(),
+                )));
+            }
+        }
+
+        let old_size = db_disk.size.0;
+        let new_size = resize_params.size;
+        if new_size.to_bytes() <= old_size.to_bytes() {
+            return Err(Error::InvalidValue {
+                label: String::from("size"),
+                message: format!(
+                    "new size must be larger than the current size {}",
+                    old_size,
+                ),
+            });
+        }
+        validate_disk_size(&new_size, db_disk.block_size.to_bytes().into())?;
+
+        let saga_params = sagas::disk_resize::Params {
+            serialized_authn: authn::saga::Serialized::for_opctx(opctx),
+            project_id: authz_proj.id(),
+            disk_id: authz_disk.id(),
+            volume_id: db_disk.volume_id,
+            block_size: params::BlockSize(db_disk.block_size.to_bytes()),
+            old_size,
+            new_size,
+        };
+        self.execute_saga::<sagas::disk_resize::SagaDiskResize>(saga_params)
+            .await?;
+
+        self.db_datastore.disk_refetch(opctx, &authz_disk).await
+    }
+
     /// Remove a read only parent from a disk.
     /// This is just a wrapper around the volume operation of the same
     /// name, but we provide this interface when all the caller has is
@@ -583,6 +665,15 @@ impl super::Nexus {
     /// Move a disk from the "ImportingFromBulkWrites" state to the
     /// "ImportReady" state, usually signalling the end of manually importing
     /// blocks.
+    ///
+    /// If what was written is an image in a container format (qcow2, VMDK or
+    /// dynamic VHD or VHDX) rather than raw blocks, a saga converts it into a
+    /// new volume that then replaces the disk's. The image's metadata is read
+    /// first, so that an image that can't be converted is rejected with the
+    /// disk left as it was. If the conversion itself fails, the saga unwinds
+    /// and the disk is also left in "ImportingFromBulkWrites", with the image
+    /// intact. Blocks written while the conversion runs may not be part of
+    /// the converted disk.
     pub async fn disk_manual_import_stop(
         self: &Arc<Self>,
         opctx: &OpContext,
@@ -609,6 +700,31 @@ impl super::Nexus {
             }
         }
 
+        if let Some(endpoint) = db_disk.pantry_address() {
+            let format =
+                self.disk_bulk_written_image_format(&db_disk, endpoint).await?;
+            if format != image_conversion::ImageFormat::Raw {
+                let saga_params = sagas::disk_convert_image::Params {
+                    serialized_authn: authn::saga::Serialized::for_opctx(opctx),
+                    disk_id: authz_disk.id(),
+                    volume_id: db_disk.volume_id,
+                    pantry_address: endpoint,
+                    format,
+                    block_size: params::BlockSize(
+                        db_disk.block_size.to_bytes(),
+                    ),
+                    size: db_disk.size.0,
+                };
+
+                self.execute_saga::<
+                    sagas::disk_convert_image::SagaDiskConvertImage,
+                >(saga_params)
+                .await?;
+
+                return Ok(());
+            }
+        }
+
         self.db_datastore
             .disk_update_runtime(
                 opctx,
@@ -619,6 +735,34 @@ impl super::Nexus {
             .map(|_| ())
     }
 
+    /// Detect whether the blocks written to a disk are an image in a container
+    /// format, and if so check that the image can be converted onto the disk.
+    /// Fixed size VHDs are not detected, as their footer is past the end of
+    /// the written blocks (unless they fill the disk); they import as raw
+    /// blocks all the same.
+    async fn disk_bulk_written_image_format(
+        &self,
+        db_disk: &db::model::Disk,
+        endpoint: std::net::SocketAddrV6,
+    ) -> Result<image_conversion::ImageFormat, Error> {
+        let disk = image_conversion::PantryDisk::new(
+            endpoint,
+            db_disk.id().to_string(),
+            db_disk.size.to_bytes(),
+            db_disk.block_size.to_bytes().into(),
+        );
+
+        let format = image_conversion::detect_format(&disk).await?;
+        if format == image_conversion::ImageFormat::Raw {
+            return Ok(format);
+        }
+
+        let map = image_conversion::map_image(&disk, format).await?;
+        map.check_fits(db_disk.size.to_bytes())?;
+
+        Ok(format)
+    }
+
     /// Move a disk from the "ImportReady" state to the "Detach" state, making
     /// it ready for general use.
     pub async fn disk_finalize_import(
@@ -646,4 +790,50 @@ impl super::Nexus {
 
         Ok(())
     }
+
+    /// Move a disk from the "Detached" state to the "Exporting" state,
+    /// attaching it to a Pantry so that its blocks can be exported.
+    pub async fn disk_export_start(
+        self: &Arc<Self>,
+        opctx: &OpContext,
+        disk_lookup: &lookup::Disk<'_>,
+    ) -> UpdateResult<()> {
+        let (.., authz_disk) =
+            disk_lookup.lookup_for(authz::Action::Modify).await?;
+
+        let saga_params = sagas::disk_export_start::Params {
+            serialized_authn: authn::saga::Serialized::for_opctx(opctx),
+            disk_id: authz_disk.id(),
+        };
+
+        self.execute_saga::<sagas::disk_export_start::SagaDiskExportStart>(
+            saga_params,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Move a disk from the "Exporting" state back to the "Detached" state,
+    /// detaching it from its Pantry.
+    pub async fn disk_export_stop(
+        self: &Arc<Self>,
+        opctx: &OpContext,
+        disk_lookup: &lookup::Disk<'_>,
+    ) -> UpdateResult<()> {
+        let (.., authz_disk) =
+            disk_lookup.lookup_for(authz::Action::Modify).await?;
+
+        let saga_params = sagas::disk_export_stop::Params {
+            serialized_authn: authn::saga::Serialized::for_opctx(opctx),
+            disk_id: authz_disk.id(),
+        };
+
+        self.execute_saga::<sagas::disk_export_stop::SagaDiskExportStop>(
+            saga_params,
+        )
+        .await?;
+
+        Ok(())
+    }
 }
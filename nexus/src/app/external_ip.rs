@@ -6,10 +6,14 @@
 
 use crate::authz;
 use crate::db::lookup;
+use crate::db::lookup::LookupPath;
 use crate::db::model::IpKind;
 use crate::external_api::views::ExternalIp;
 use nexus_db_queries::context::OpContext;
+use omicron_common::api::external::Error;
 use omicron_common::api::external::ListResultVec;
+use std::str::FromStr;
+use uuid::Uuid;
 
 impl super::Nexus {
     pub async fn instance_list_external_ips(
@@ -33,4 +37,139 @@ impl super::Nexus {
             })
             .collect::<Vec<_>>())
     }
+
+    /// Ensure that dpd has a NAT entry directing traffic for `target_ip` to
+    /// the primary network interface of the instance, on the sled with ID
+    /// `sled_id`.
+    ///
+    /// This does nothing if an entry already exists for the address, or if
+    /// the instance has no primary network interface.
+    pub(crate) async fn instance_ensure_dpd_nat(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+        sled_id: Uuid,
+        target_ip: &crate::db::model::ExternalIp,
+    ) -> Result<(), Error> {
+        let log = &self.log;
+        let dpd_client = &self.dpd_client;
+
+        let (.., sled) = LookupPath::new(&self.opctx_alloc, &self.db_datastore)
+            .sled_id(sled_id)
+            .fetch()
+            .await?;
+        let sled_ip_address = sled.address();
+
+        let network_interface = match self
+            .db_datastore
+            .derive_guest_network_interface_info(opctx, authz_instance)
+            .await?
+            .into_iter()
+            .find(|interface| interface.primary)
+        {
+            Some(interface) => interface,
+            None => return Ok(()),
+        };
+        let mac_address =
+            macaddr::MacAddr6::from_str(&network_interface.mac.to_string())
+                .map_err(|e| {
+                    Error::internal_error(&format!(
+                        "failed to convert mac address: {e}"
+                    ))
+                })?;
+        let vni: u32 = network_interface.vni.into();
+
+        debug!(log, "checking for existing nat mapping for {target_ip:#?}");
+        let existing_nat = match target_ip.ip {
+            ipnetwork::IpNetwork::V4(network) => {
+                dpd_client
+                    .nat_ipv4_get(&network.ip(), *target_ip.first_port)
+                    .await
+            }
+            ipnetwork::IpNetwork::V6(network) => {
+                dpd_client
+                    .nat_ipv6_get(&network.ip(), *target_ip.first_port)
+                    .await
+            }
+        };
+        match existing_nat {
+            Ok(_) => return Ok(()),
+            Err(e) if e.status() == Some(http::StatusCode::NOT_FOUND) => {}
+            Err(e) => {
+                return Err(Error::internal_error(&format!(
+                    "failed to query dpd: {e}"
+                )));
+            }
+        }
+
+        debug!(log, "creating nat entry for: {target_ip:#?}");
+        let nat_target = dpd_client::types::NatTarget {
+            inner_mac: dpd_client::types::MacAddr {
+                a: mac_address.into_array().to_vec(),
+            },
+            internal_ip: *sled_ip_address.ip(),
+            vni: vni.into(),
+        };
+        match target_ip.ip {
+            ipnetwork::IpNetwork::V4(network) => {
+                dpd_client
+                    .nat_ipv4_create(
+                        &network.ip(),
+                        *target_ip.first_port,
+                        *target_ip.last_port,
+                        &nat_target,
+                    )
+                    .await
+            }
+            ipnetwork::IpNetwork::V6(network) => {
+                dpd_client
+                    .nat_ipv6_create(
+                        &network.ip(),
+                        *target_ip.first_port,
+                        *target_ip.last_port,
+                        &nat_target,
+                    )
+                    .await
+            }
+        }
+        .map_err(|e| {
+            Error::internal_error(&format!(
+                "failed to create nat entry via dpd: {e}"
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Delete dpd's NAT entry for `target_ip`, if there is one.
+    pub(crate) async fn instance_delete_dpd_nat(
+        &self,
+        target_ip: &crate::db::model::ExternalIp,
+    ) -> Result<(), Error> {
+        let log = &self.log;
+        let dpd_client = &self.dpd_client;
+
+        debug!(log, "deleting nat mapping for entry: {target_ip:#?}");
+        let result = match target_ip.ip {
+            ipnetwork::IpNetwork::V4(network) => {
+                dpd_client
+                    .nat_ipv4_delete(&network.ip(), *target_ip.first_port)
+                    .await
+            }
+            ipnetwork::IpNetwork::V6(network) => {
+                dpd_client
+                    .nat_ipv6_delete(&network.ip(), *target_ip.first_port)
+                    .await
+            }
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if e.status() == Some(http::StatusCode::NOT_FOUND) => {
+                debug!(log, "no nat entry found for: {target_ip:#?}");
+                Ok(())
+            }
+            Err(e) => Err(Error::internal_error(&format!(
+                "failed to delete nat entry via dpd: {e}"
+            ))),
+        }
+    }
 }
@@ -0,0 +1,239 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Floating IP addresses
+
+use super::sagas;
+use crate::authn;
+use crate::authz;
+use crate::db;
+use crate::db::lookup;
+use crate::db::lookup::LookupPath;
+use crate::external_api::params;
+use nexus_db_queries::context::OpContext;
+use nexus_types::identity::Resource;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::NameOrId;
+use omicron_common::api::external::UpdateResult;
+use uuid::Uuid;
+
+impl super::Nexus {
+    pub fn floating_ip_lookup<'a>(
+        &'a self,
+        opctx: &'a OpContext,
+        fip_selector: params::FloatingIpSelector,
+    ) -> LookupResult<lookup::FloatingIp<'a>> {
+        match fip_selector {
+            params::FloatingIpSelector {
+                floating_ip: NameOrId::Id(id),
+                project: None,
+            } => {
+                let fip = LookupPath::new(opctx, &self.db_datastore)
+                    .floating_ip_id(id);
+                Ok(fip)
+            }
+            params::FloatingIpSelector {
+                floating_ip: NameOrId::Name(name),
+                project: Some(project),
+            } => {
+                let fip = self
+                    .project_lookup(opctx, params::ProjectSelector { project })?
+                    .floating_ip_name_owned(name.into());
+                Ok(fip)
+            }
+            params::FloatingIpSelector {
+                floating_ip: NameOrId::Id(_), ..
+            } => Err(Error::invalid_request(
+                "when providing floating_ip as an ID, project should not be \
+                specified",
+            )),
+            _ => Err(Error::invalid_request(
+                "floating_ip should either be an ID or project should be \
+                specified",
+            )),
+        }
+    }
+
+    pub async fn floating_ip_create(
+        &self,
+        opctx: &OpContext,
+        project_lookup: &lookup::Project<'_>,
+        params: &params::FloatingIpCreate,
+    ) -> CreateResult<db::model::FloatingIp> {
+        let (.., authz_project) =
+            project_lookup.lookup_for(authz::Action::CreateChild).await?;
+
+        let pool_id = match &params.pool {
+            Some(pool) => {
+                let (.., authz_pool, db_pool) = self
+                    .ip_pool_lookup(opctx, pool)?
+                    .fetch_for(authz::Action::CreateChild)
+                    .await?;
+                // Internal pools are for services only.
+                if db_pool.internal {
+                    return Err(authz_pool.not_found());
+                }
+                Some(authz_pool.id())
+            }
+            None => None,
+        };
+
+        self.db_datastore
+            .allocate_floating_ip(
+                opctx,
+                &authz_project,
+                Uuid::new_v4(),
+                &params.identity,
+                pool_id,
+            )
+            .await
+    }
+
+    pub async fn floating_ip_list(
+        &self,
+        opctx: &OpContext,
+        project_lookup: &lookup::Project<'_>,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<db::model::FloatingIp> {
+        let (.., authz_project) =
+            project_lookup.lookup_for(authz::Action::ListChildren).await?;
+        self.db_datastore
+            .floating_ip_list(opctx, &authz_project, pagparams)
+            .await
+    }
+
+    pub async fn floating_ip_delete(
+        &self,
+        opctx: &OpContext,
+        fip_lookup: &lookup::FloatingIp<'_>,
+    ) -> DeleteResult {
+        let (.., authz_fip) =
+            fip_lookup.lookup_for(authz::Action::Delete).await?;
+        self.db_datastore.floating_ip_delete(opctx, &authz_fip).await
+    }
+
+    /// Attach a Floating IP address to an instance in the same project.
+    pub async fn floating_ip_attach(
+        &self,
+        opctx: &OpContext,
+        fip_lookup: &lookup::FloatingIp<'_>,
+        attach: &params::FloatingIpAttach,
+    ) -> UpdateResult<db::model::FloatingIp> {
+        let (.., authz_project, authz_fip, db_fip) =
+            fip_lookup.fetch_for(authz::Action::Modify).await?;
+        let (authz_instance, db_instance) = self
+            .floating_ip_instance_fetch(opctx, &authz_project, &attach.instance)
+            .await?;
+
+        // Attaching is idempotent, but re-running the saga here would undo
+        // the existing attachment if it failed.
+        if db_fip.instance_id == Some(authz_instance.id()) {
+            return Ok(db_fip);
+        }
+        Self::floating_ip_check_instance_state(&db_instance)?;
+
+        let saga_params = sagas::instance_ip_attach::Params {
+            serialized_authn: authn::saga::Serialized::for_opctx(opctx),
+            authz_instance,
+            authz_fip: authz_fip.clone(),
+        };
+        self.execute_saga::<sagas::instance_ip_attach::SagaInstanceIpAttach>(
+            saga_params,
+        )
+        .await?;
+
+        let (.., db_fip) = LookupPath::new(opctx, &self.db_datastore)
+            .floating_ip_id(authz_fip.id())
+            .fetch()
+            .await?;
+        Ok(db_fip)
+    }
+
+    /// Detach a Floating IP address from the instance it's attached to.
+    pub async fn floating_ip_detach(
+        &self,
+        opctx: &OpContext,
+        fip_lookup: &lookup::FloatingIp<'_>,
+    ) -> UpdateResult<db::model::FloatingIp> {
+        let (.., authz_project, authz_fip, db_fip) =
+            fip_lookup.fetch_for(authz::Action::Modify).await?;
+        let Some(instance_id) = db_fip.instance_id else {
+            return Ok(db_fip);
+        };
+        let (authz_instance, db_instance) = self
+            .floating_ip_instance_fetch(
+                opctx,
+                &authz_project,
+                &NameOrId::Id(instance_id),
+            )
+            .await?;
+        Self::floating_ip_check_instance_state(&db_instance)?;
+
+        let saga_params = sagas::instance_ip_detach::Params {
+            serialized_authn: authn::saga::Serialized::for_opctx(opctx),
+            authz_instance,
+            authz_fip: authz_fip.clone(),
+        };
+        self.execute_saga::<sagas::instance_ip_detach::SagaInstanceIpDetach>(
+            saga_params,
+        )
+        .await?;
+
+        let (.., db_fip) = LookupPath::new(opctx, &self.db_datastore)
+            .floating_ip_id(authz_fip.id())
+            .fetch()
+            .await?;
+        Ok(db_fip)
+    }
+
+    /// Fetch an instance for modification by attaching or detaching a Floating
+    /// IP address, which requires that it be in the address's project.
+    async fn floating_ip_instance_fetch(
+        &self,
+        opctx: &OpContext,
+        authz_project: &authz::Project,
+        instance: &NameOrId,
+    ) -> LookupResult<(authz::Instance, db::model::Instance)> {
+        let instance_lookup = match instance {
+            NameOrId::Id(id) => {
+                LookupPath::new(opctx, &self.db_datastore).instance_id(*id)
+            }
+            NameOrId::Name(name) => LookupPath::new(opctx, &self.db_datastore)
+                .project_id(authz_project.id())
+                .instance_name_owned(name.clone().into()),
+        };
+        let (.., instance_project, authz_instance, db_instance) =
+            instance_lookup.fetch_for(authz::Action::Modify).await?;
+        if instance_project.id() != authz_project.id() {
+            return Err(Error::invalid_request(&format!(
+                "instance {} is not in the floating IP's project",
+                instance
+            )));
+        }
+        Ok((authz_instance, db_instance))
+    }
+
+    /// Floating IPs can only be attached to or detached from stopped
+    /// instances. The external IP of an OPTE port is fixed when the port is
+    /// created, so a running instance couldn't use an address attached to
+    /// it, and would keep using one detached from it.
+    fn floating_ip_check_instance_state(
+        instance: &db::model::Instance,
+    ) -> Result<(), Error> {
+        match instance.runtime().state.0 {
+            InstanceState::Stopped => Ok(()),
+            state => Err(Error::invalid_request(&format!(
+                "cannot change the floating IPs of an instance in state {}; \
+                stop the instance first",
+                state.label()
+            ))),
+        }
+    }
+}
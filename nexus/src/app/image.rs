@@ -20,6 +20,7 @@ use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::DataPageParams;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::LookupType;
@@ -202,6 +203,7 @@ impl super::Nexus {
                         image_id,
                         params.identity.clone(),
                     ),
+                    labels: params.labels.clone().into(),
                     silo_id: authz_silo.id(),
                     project_id: maybe_authz_project.clone().map(|p| p.id()),
                     volume_id: volume.id(),
@@ -240,6 +242,7 @@ impl super::Nexus {
                         image_id,
                         params.identity.clone(),
                     ),
+                    labels: params.labels.clone().into(),
                     silo_id: authz_silo.id(),
                     project_id: maybe_authz_project.clone().map(|p| p.id()),
                     volume_id: image_volume.id(),
@@ -294,6 +297,7 @@ impl super::Nexus {
                         global_image_id,
                         params.identity.clone(),
                     ),
+                    labels: params.labels.clone().into(),
                     silo_id: authz_silo.id(),
                     project_id: maybe_authz_project.clone().map(|p| p.id()),
                     volume_id: volume.id(),
@@ -335,6 +339,7 @@ impl super::Nexus {
         parent_lookup: &ImageParentLookup<'_>,
         include_silo_images: bool,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<db::model::Image> {
         match parent_lookup {
             ImageParentLookup::Project(project) => {
@@ -347,6 +352,7 @@ impl super::Nexus {
                         &authz_project,
                         include_silo_images,
                         pagparams,
+                        label_selector,
                     )
                     .await
             }
@@ -354,7 +360,12 @@ impl super::Nexus {
                 let (.., authz_silo) =
                     silo.lookup_for(authz::Action::ListChildren).await?;
                 self.db_datastore
-                    .silo_image_list(opctx, &authz_silo, pagparams)
+                    .silo_image_list(
+                        opctx,
+                        &authz_silo,
+                        pagparams,
+                        label_selector,
+                    )
                     .await
             }
         }
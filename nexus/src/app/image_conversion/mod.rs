@@ -0,0 +1,1024 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Converting disk images in container formats (qcow2, VMDK, VHD and VHDX) to
+//! raw blocks during an import
+//!
+//! Each supported format is first mapped: its metadata is read and turned into
+//! a [`DiskMap`], a sorted list of extents saying where each allocated range
+//! of the guest-visible disk lives in the image file. Anything not covered by
+//! an extent reads as zeros. Converting is then a matter of reading the disk
+//! map back in guest order, which only needs random access to the image: over
+//! HTTP range requests when importing from a URL, and from the disk itself
+//! (through its Pantry) when the image was uploaded with bulk writes.
+//!
+//! Images that depend on anything outside of themselves (backing files,
+//! parent disks, external data files) and encrypted images are rejected.
+
+mod qcow2;
+mod vhd;
+mod vhdx;
+mod vmdk;
+
+use async_trait::async_trait;
+use omicron_common::api::external::Error;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::io::Read;
+use std::net::SocketAddrV6;
+use std::time::Duration;
+
+/// How many bytes of the converted disk are produced and written at a time.
+/// This matches the largest bulk write accepted by the Pantry, and is a
+/// multiple of every supported block size.
+const CONVERSION_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Raw extents whose data lies within this many bytes of each other in the
+/// image are read with a single request, rather than one each.
+const MAX_READ_GAP: u64 = 64 * 1024;
+
+/// The most read from an image at once. Metadata tables larger than this are
+/// rejected rather than read into memory; it's enough for disks of several
+/// terabytes in any supported format.
+const MAX_READ_SIZE: u64 = 32 * 1024 * 1024;
+
+/// The largest compressed cluster or grain, before or after inflating it
+const MAX_COMPRESSED_LEN: u64 = CONVERSION_CHUNK_SIZE;
+
+/// The on-disk format of an image
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    Raw,
+    Qcow2,
+    Vmdk,
+    Vhd,
+    Vhdx,
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ImageFormat::Raw => "raw",
+            ImageFormat::Qcow2 => "qcow2",
+            ImageFormat::Vmdk => "VMDK",
+            ImageFormat::Vhd => "VHD",
+            ImageFormat::Vhdx => "VHDX",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConversionError {
+    /// The image is well formed, but uses a feature that can't be converted
+    #[error("unsupported {format} image: {message}")]
+    Unsupported { format: ImageFormat, message: String },
+
+    /// The image is malformed or truncated
+    #[error("invalid {format} image: {message}")]
+    Invalid { format: ImageFormat, message: String },
+
+    /// The image couldn't be read from where it is
+    #[error("error reading image: {0}")]
+    Read(String),
+}
+
+impl ConversionError {
+    fn unsupported(format: ImageFormat, message: impl ToString) -> Self {
+        ConversionError::Unsupported { format, message: message.to_string() }
+    }
+
+    fn invalid(format: ImageFormat, message: impl ToString) -> Self {
+        ConversionError::Invalid { format, message: message.to_string() }
+    }
+
+    /// Arithmetic on a value read from an image overflowed
+    fn out_of_range(format: ImageFormat, what: &str) -> Self {
+        ConversionError::invalid(format, format!("{} is out of range", what))
+    }
+}
+
+impl From<ConversionError> for Error {
+    fn from(e: ConversionError) -> Error {
+        match e {
+            ConversionError::Unsupported { .. }
+            | ConversionError::Invalid { .. } => {
+                Error::invalid_request(&e.to_string())
+            }
+            ConversionError::Read(_) => Error::unavail(&e.to_string()),
+        }
+    }
+}
+
+/// Somewhere an image can be read from at arbitrary offsets
+#[async_trait]
+pub trait ImageSource: Send + Sync {
+    /// The size of the image in bytes, if it's known
+    fn size(&self) -> Option<u64>;
+
+    /// Reads exactly `len` bytes at `offset`
+    async fn read_at(
+        &self,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, ConversionError>;
+}
+
+/// Where the data for one extent of the guest-visible disk comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExtentData {
+    /// Stored as is, starting at `source_offset`
+    Raw { source_offset: u64 },
+    /// A raw deflate stream (qcow2 compressed clusters) of `compressed_len`
+    /// bytes, inflating to `decompressed_len` bytes of which the extent is
+    /// the start.
+    Deflate { source_offset: u64, compressed_len: u64, decompressed_len: u64 },
+    /// The same, but zlib framed (VMDK compressed grains)
+    Zlib { source_offset: u64, compressed_len: u64, decompressed_len: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Extent {
+    guest_offset: u64,
+    len: u64,
+    data: ExtentData,
+}
+
+/// Where each allocated range of an image's guest-visible disk lives in the
+/// image file
+#[derive(Debug)]
+pub struct DiskMap {
+    format: ImageFormat,
+    /// The size of the guest-visible disk
+    virtual_size: u64,
+    /// Allocated extents, sorted by guest offset and not overlapping
+    extents: Vec<Extent>,
+}
+
+impl DiskMap {
+    fn new(format: ImageFormat, virtual_size: u64) -> Self {
+        DiskMap { format, virtual_size, extents: vec![] }
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    pub fn virtual_size(&self) -> u64 {
+        self.virtual_size
+    }
+
+    /// Adds an extent. Extents must be pushed in guest order, unless
+    /// `finish` sorts them afterwards.
+    fn push(
+        &mut self,
+        guest_offset: u64,
+        len: u64,
+        data: ExtentData,
+    ) -> Result<(), ConversionError> {
+        let len =
+            std::cmp::min(len, self.virtual_size.saturating_sub(guest_offset));
+        if len == 0 {
+            return Ok(());
+        }
+
+        let end = match data {
+            ExtentData::Raw { source_offset } => source_offset.checked_add(len),
+            ExtentData::Deflate {
+                source_offset,
+                compressed_len,
+                decompressed_len,
+            }
+            | ExtentData::Zlib {
+                source_offset,
+                compressed_len,
+                decompressed_len,
+            } => {
+                if compressed_len > MAX_COMPRESSED_LEN
+                    || decompressed_len > MAX_COMPRESSED_LEN
+                {
+                    return Err(ConversionError::invalid(
+                        self.format,
+                        format!(
+                            "compressed data for guest offset {} is too large",
+                            guest_offset
+                        ),
+                    ));
+                }
+                source_offset.checked_add(compressed_len)
+            }
+        };
+        end.ok_or_else(|| {
+            ConversionError::out_of_range(
+                self.format,
+                &format!("data offset for guest offset {}", guest_offset),
+            )
+        })?;
+
+        // Merge raw extents that are contiguous both in the guest and in the
+        // image, which is the common case for images written sequentially.
+        if let (
+            Some(Extent {
+                guest_offset: last_offset,
+                len: last_len,
+                data: ExtentData::Raw { source_offset: last_source },
+            }),
+            ExtentData::Raw { source_offset },
+        ) = (self.extents.last_mut(), data)
+        {
+            if *last_offset + *last_len == guest_offset
+                && *last_source + *last_len == source_offset
+            {
+                *last_len += len;
+                return Ok(());
+            }
+        }
+
+        self.extents.push(Extent { guest_offset, len, data });
+        Ok(())
+    }
+
+    /// Sorts the extents by guest offset, and checks that none overlap
+    fn finish(mut self) -> Result<Self, ConversionError> {
+        self.extents.sort_by_key(|e| e.guest_offset);
+        for pair in self.extents.windows(2) {
+            if pair[0].guest_offset + pair[0].len > pair[1].guest_offset {
+                return Err(ConversionError::invalid(
+                    self.format,
+                    format!(
+                        "data for guest offset {} is stored more than once",
+                        pair[1].guest_offset
+                    ),
+                ));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Checks that the converted image fits on a disk of `disk_size` bytes
+    pub fn check_fits(&self, disk_size: u64) -> Result<(), Error> {
+        if self.virtual_size > disk_size {
+            return Err(Error::invalid_request(&format!(
+                "{} image has a virtual size of {} bytes, which is larger \
+                than the disk's {} bytes",
+                self.format, self.virtual_size, disk_size,
+            )));
+        }
+        Ok(())
+    }
+
+    /// The extents overlapping `[offset, offset + len)`
+    fn extents_in(&self, offset: u64, len: u64) -> &[Extent] {
+        let end = offset + len;
+        let first =
+            self.extents.partition_point(|e| e.guest_offset + e.len <= offset);
+        let last = self.extents.partition_point(|e| e.guest_offset < end);
+        &self.extents[first..std::cmp::max(first, last)]
+    }
+
+    /// Reads `[offset, offset + len)` of the guest-visible disk out of the
+    /// image
+    pub async fn read(
+        &self,
+        source: &dyn ImageSource,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, ConversionError> {
+        let mut data = vec![0; len as usize];
+        let end = offset + len;
+
+        // The range of the image read for each extent, along with the part
+        // of the extent's data wanted and where it goes in `data`, so that
+        // pieces close together in the image can be read at once.
+        struct Piece<'a> {
+            source_offset: u64,
+            source_len: u64,
+            extent: &'a Extent,
+            skip: usize,
+            dst: usize,
+            n: usize,
+        }
+
+        let mut pieces: Vec<Piece> = vec![];
+        for extent in self.extents_in(offset, len) {
+            let start = std::cmp::max(offset, extent.guest_offset);
+            let stop = std::cmp::min(end, extent.guest_offset + extent.len);
+            let skip = start - extent.guest_offset;
+
+            let (source_offset, source_len) = match extent.data {
+                ExtentData::Raw { source_offset } => {
+                    (source_offset + skip, stop - start)
+                }
+                // The compressed length is sometimes rounded up past the end
+                // of the image, which is fine: the stream ends first.
+                ExtentData::Deflate {
+                    source_offset, compressed_len, ..
+                }
+                | ExtentData::Zlib { source_offset, compressed_len, .. } => {
+                    let compressed_len = match source.size() {
+                        Some(size) => std::cmp::min(
+                            compressed_len,
+                            size.saturating_sub(source_offset),
+                        ),
+                        None => compressed_len,
+                    };
+                    (source_offset, compressed_len)
+                }
+            };
+
+            pieces.push(Piece {
+                source_offset,
+                source_len,
+                extent,
+                skip: skip as usize,
+                dst: (start - offset) as usize,
+                n: (stop - start) as usize,
+            });
+        }
+
+        pieces.sort_by_key(|piece| piece.source_offset);
+        let mut i = 0;
+        while i < pieces.len() {
+            let start = pieces[i].source_offset;
+            let mut stop = start + pieces[i].source_len;
+            let mut j = i + 1;
+            while j < pieces.len() {
+                let next = &pieces[j];
+                let next_stop =
+                    std::cmp::max(stop, next.source_offset + next.source_len);
+                if next.source_offset > stop + MAX_READ_GAP
+                    || next_stop - start > CONVERSION_CHUNK_SIZE
+                {
+                    break;
+                }
+                stop = next_stop;
+                j += 1;
+            }
+
+            let buf = source.read_at(start, stop - start).await?;
+            for piece in &pieces[i..j] {
+                let from = (piece.source_offset - start) as usize;
+                let piece_data = &buf[from..from + piece.source_len as usize];
+                let dst = &mut data[piece.dst..piece.dst + piece.n];
+                match piece.extent.data {
+                    ExtentData::Raw { .. } => dst.copy_from_slice(piece_data),
+                    ExtentData::Deflate { decompressed_len, .. }
+                    | ExtentData::Zlib { decompressed_len, .. } => {
+                        let decompressed = self.decompress(
+                            &piece.extent.data,
+                            piece_data,
+                            decompressed_len,
+                        )?;
+                        dst.copy_from_slice(
+                            &decompressed[piece.skip..piece.skip + piece.n],
+                        );
+                    }
+                }
+            }
+            i = j;
+        }
+
+        Ok(data)
+    }
+
+    fn decompress(
+        &self,
+        data: &ExtentData,
+        compressed: &[u8],
+        decompressed_len: u64,
+    ) -> Result<Vec<u8>, ConversionError> {
+        let mut out = Vec::with_capacity(decompressed_len as usize);
+        let result = match data {
+            ExtentData::Deflate { .. } => {
+                flate2::read::DeflateDecoder::new(compressed)
+                    .take(decompressed_len)
+                    .read_to_end(&mut out)
+            }
+            ExtentData::Zlib { .. } => {
+                flate2::read::ZlibDecoder::new(compressed)
+                    .take(decompressed_len)
+                    .read_to_end(&mut out)
+            }
+            ExtentData::Raw { .. } => unreachable!("raw data isn't compressed"),
+        };
+        result.map_err(|e| {
+            ConversionError::invalid(
+                self.format,
+                format!("error decompressing data: {}", e),
+            )
+        })?;
+
+        // Streams that end early leave the rest of the cluster or grain
+        // zeroed.
+        out.resize(decompressed_len as usize, 0);
+        Ok(out)
+    }
+}
+
+/// Works out the format of an image from its contents
+pub async fn detect_format(
+    source: &dyn ImageSource,
+) -> Result<ImageFormat, ConversionError> {
+    let first_len = match source.size() {
+        Some(size) => std::cmp::min(size, 512),
+        None => 512,
+    };
+    let first = source.read_at(0, first_len).await?;
+
+    if first.starts_with(qcow2::MAGIC) {
+        return Ok(ImageFormat::Qcow2);
+    }
+    if vmdk::is_vmdk(&first) {
+        return Ok(ImageFormat::Vmdk);
+    }
+    if first.starts_with(vhdx::SIGNATURE) {
+        return Ok(ImageFormat::Vhdx);
+    }
+    if first.starts_with(vhd::COOKIE) {
+        return Ok(ImageFormat::Vhd);
+    }
+
+    // Fixed size VHDs are raw data followed by a single footer sector. If it
+    // can't be read, treat the image as raw, which it mostly is.
+    if let Some(size) = source.size() {
+        if size >= 1024 && size % 512 == 0 {
+            match source.read_at(size - 512, 512).await {
+                Ok(last) if last.starts_with(vhd::COOKIE) => {
+                    return Ok(ImageFormat::Vhd);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(ImageFormat::Raw)
+}
+
+/// Reads the metadata of an image of the given format
+pub async fn map_image(
+    source: &dyn ImageSource,
+    format: ImageFormat,
+) -> Result<DiskMap, ConversionError> {
+    let map = match format {
+        ImageFormat::Raw => {
+            let size = source.size().ok_or_else(|| {
+                ConversionError::Read(String::from(
+                    "the size of the image is not known",
+                ))
+            })?;
+            let mut map = DiskMap::new(ImageFormat::Raw, size);
+            map.push(0, size, ExtentData::Raw { source_offset: 0 })?;
+            map
+        }
+        ImageFormat::Qcow2 => qcow2::map(source).await?,
+        ImageFormat::Vmdk => vmdk::map(source).await?,
+        ImageFormat::Vhd => vhd::map(source).await?,
+        ImageFormat::Vhdx => vhdx::map(source).await?,
+    };
+    map.finish()
+}
+
+/// Somewhere the converted disk can be written to
+#[async_trait]
+pub trait DiskWriter: Send + Sync {
+    /// The disk's block size. Writes are always a multiple of it.
+    fn block_size(&self) -> u64;
+
+    async fn write_at(&self, offset: u64, data: Vec<u8>) -> Result<(), Error>;
+}
+
+/// Writes a chunk of the converted disk, skipping chunks of zeros where the
+/// disk already reads as zeros (as it does unless it has been written to
+/// before), so that the unallocated parts of large images cost a read rather
+/// than a write.
+async fn write_chunk<D>(
+    disk: &D,
+    offset: u64,
+    data: Vec<u8>,
+) -> Result<(), Error>
+where
+    D: ImageSource + DiskWriter + ?Sized,
+{
+    if data.iter().all(|b| *b == 0) {
+        let current = disk.read_at(offset, data.len() as u64).await?;
+        if current.iter().all(|b| *b == 0) {
+            return Ok(());
+        }
+    }
+    disk.write_at(offset, data).await
+}
+
+/// Writes the whole guest-visible disk of an image to `disk`. `disk` must not
+/// overlap `source`.
+pub async fn convert<D>(
+    source: &dyn ImageSource,
+    map: &DiskMap,
+    disk: &D,
+) -> Result<(), Error>
+where
+    D: ImageSource + DiskWriter,
+{
+    let end = round_up(map.virtual_size, disk.block_size());
+    let mut offset = 0;
+    while offset < end {
+        let len = std::cmp::min(CONVERSION_CHUNK_SIZE, end - offset);
+        let data = map.read(source, offset, len).await?;
+        write_chunk(disk, offset, data).await?;
+        offset += len;
+    }
+    Ok(())
+}
+
+/// Reads an image at a URL, using HTTP range requests
+pub struct UrlSource {
+    client: reqwest::Client,
+    url: String,
+    size: Option<u64>,
+    /// The first bytes of the image, from the initial request
+    head: Vec<u8>,
+    /// Whether the server honours range requests
+    supports_ranges: bool,
+}
+
+impl UrlSource {
+    /// How much of the image is requested up front, for format detection
+    const HEAD_SIZE: u64 = 512;
+
+    /// Probes the image at `url` for its size and first bytes
+    pub async fn new(url: &str) -> Result<Self, ConversionError> {
+        let client = reqwest::ClientBuilder::new()
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(Duration::from_secs(60))
+            .build()
+            .map_err(|e| ConversionError::Read(e.to_string()))?;
+
+        let mut response = client
+            .get(url)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes=0-{}", Self::HEAD_SIZE - 1),
+            )
+            .send()
+            .await
+            .map_err(|e| {
+                ConversionError::Read(format!("error querying url: {}", e))
+            })?;
+
+        let (size, supports_ranges) = match response.status() {
+            reqwest::StatusCode::PARTIAL_CONTENT => {
+                // Content-Range: bytes 0-511/<size>, where the size may be *
+                let size = response
+                    .headers()
+                    .get(reqwest::header::CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.rsplit_once('/'))
+                    .and_then(|(_, size)| size.parse::<u64>().ok());
+                (size, true)
+            }
+            reqwest::StatusCode::OK => (response.content_length(), false),
+            status => {
+                return Err(ConversionError::Read(format!(
+                    "querying url returned: {}",
+                    status
+                )));
+            }
+        };
+
+        // Only keep the start of the body, whether or not the server sent
+        // more.
+        let mut head = Vec::with_capacity(Self::HEAD_SIZE as usize);
+        while (head.len() as u64) < Self::HEAD_SIZE {
+            match response.chunk().await.map_err(|e| {
+                ConversionError::Read(format!("error reading url: {}", e))
+            })? {
+                Some(chunk) => head.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        head.truncate(Self::HEAD_SIZE as usize);
+
+        Ok(UrlSource {
+            client,
+            url: url.to_string(),
+            size,
+            head,
+            supports_ranges,
+        })
+    }
+}
+
+#[async_trait]
+impl ImageSource for UrlSource {
+    fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    async fn read_at(
+        &self,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, ConversionError> {
+        if len == 0 {
+            return Ok(vec![]);
+        }
+        let end = check_read(offset, len)?;
+        if end <= self.head.len() as u64 {
+            return Ok(self.head[offset as usize..end as usize].to_vec());
+        }
+        if !self.supports_ranges {
+            return Err(ConversionError::Read(String::from(
+                "the server does not support HTTP range requests, which are \
+                needed to convert images",
+            )));
+        }
+
+        let mut response = self
+            .client
+            .get(&self.url)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", offset, end - 1),
+            )
+            .send()
+            .await
+            .map_err(|e| {
+                ConversionError::Read(format!("error reading url: {}", e))
+            })?;
+
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Err(ConversionError::Read(format!(
+                "range request for bytes {}-{} returned: {}",
+                offset,
+                end - 1,
+                response.status(),
+            )));
+        }
+
+        // Don't take the server's word for how much it sends.
+        let mut body = Vec::with_capacity(len as usize);
+        while let Some(chunk) = response.chunk().await.map_err(|e| {
+            ConversionError::Read(format!("error reading url: {}", e))
+        })? {
+            if (body.len() + chunk.len()) as u64 > len {
+                return Err(ConversionError::Read(format!(
+                    "range request for bytes {}-{} returned more than {} bytes",
+                    offset,
+                    end - 1,
+                    len,
+                )));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        if body.len() as u64 != len {
+            return Err(ConversionError::Read(format!(
+                "image ended before byte {}",
+                end
+            )));
+        }
+
+        Ok(body)
+    }
+}
+
+/// A disk attached to a Pantry, read from and written to in block-aligned
+/// bulk operations
+pub struct PantryDisk {
+    client: crucible_pantry_client::Client,
+    /// The id the disk is attached to the Pantry with
+    attach_id: String,
+    size: u64,
+    block_size: u64,
+}
+
+impl PantryDisk {
+    pub fn new(
+        pantry_address: SocketAddrV6,
+        attach_id: String,
+        size: u64,
+        block_size: u64,
+    ) -> Self {
+        PantryDisk {
+            client: crucible_pantry_client::Client::new(&format!(
+                "http://{}",
+                pantry_address
+            )),
+            attach_id,
+            size,
+            block_size,
+        }
+    }
+
+    async fn bulk_read(
+        &self,
+        offset: u64,
+        size: u64,
+    ) -> Result<Vec<u8>, ConversionError> {
+        let request = crucible_pantry_client::types::BulkReadRequest {
+            offset,
+            size: size as usize,
+        };
+
+        let response = self
+            .client
+            .bulk_read(&self.attach_id, &request)
+            .await
+            .map_err(|e| {
+                ConversionError::Read(format!(
+                    "error sending bulk read to pantry: {}",
+                    e
+                ))
+            })?
+            .into_inner();
+
+        base64::Engine::decode(
+            &base64::engine::general_purpose::STANDARD,
+            &response.base64_encoded_data,
+        )
+        .map_err(|e| {
+            ConversionError::Read(format!(
+                "error base64 decoding data from pantry: {}",
+                e
+            ))
+        })
+    }
+}
+
+#[async_trait]
+impl ImageSource for PantryDisk {
+    fn size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+
+    async fn read_at(
+        &self,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, ConversionError> {
+        if check_read(offset, len)? > self.size {
+            return Err(ConversionError::Read(format!(
+                "read of {} bytes at offset {} is past the end of the disk",
+                len, offset
+            )));
+        }
+
+        let start = offset - offset % self.block_size;
+        let end = round_up(offset + len, self.block_size);
+        let mut data = Vec::with_capacity((end - start) as usize);
+        let mut pos = start;
+        while pos < end {
+            let size = std::cmp::min(CONVERSION_CHUNK_SIZE, end - pos);
+            data.extend(self.bulk_read(pos, size).await?);
+            pos += size;
+        }
+
+        let skip = (offset - start) as usize;
+        Ok(data[skip..skip + len as usize].to_vec())
+    }
+}
+
+#[async_trait]
+impl DiskWriter for PantryDisk {
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    async fn write_at(
+        &self,
+        offset: u64,
+        mut data: Vec<u8>,
+    ) -> Result<(), Error> {
+        data.resize(round_up(data.len() as u64, self.block_size) as usize, 0);
+
+        let request = crucible_pantry_client::types::BulkWriteRequest {
+            offset,
+            base64_encoded_data: base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                &data,
+            ),
+        };
+
+        self.client.bulk_write(&self.attach_id, &request).await.map_err(
+            |e| {
+                Error::internal_error(&format!(
+                    "error sending bulk write to pantry: {}",
+                    e,
+                ))
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Checks a read from an image source isn't too large, returning where it
+/// ends
+fn check_read(offset: u64, len: u64) -> Result<u64, ConversionError> {
+    if len > MAX_READ_SIZE {
+        return Err(ConversionError::Read(format!(
+            "read of {} bytes at offset {} is too large",
+            len, offset
+        )));
+    }
+    offset.checked_add(len).ok_or_else(|| {
+        ConversionError::Read(format!(
+            "read of {} bytes at offset {} is out of range",
+            len, offset
+        ))
+    })
+}
+
+/// Reads a metadata table of `len` bytes at `offset`, after checking that
+/// it's not too large and lies within the image. These come from the image
+/// itself, and so can't be trusted.
+async fn read_table(
+    source: &dyn ImageSource,
+    format: ImageFormat,
+    what: &str,
+    offset: u64,
+    len: u64,
+) -> Result<Vec<u8>, ConversionError> {
+    if len > MAX_READ_SIZE {
+        return Err(ConversionError::invalid(
+            format,
+            format!("{} of {} bytes is too large", what, len),
+        ));
+    }
+    let end = offset
+        .checked_add(len)
+        .ok_or_else(|| ConversionError::out_of_range(format, what))?;
+    if source.size().map_or(false, |size| end > size) {
+        return Err(ConversionError::invalid(
+            format,
+            format!(
+                "{} at offset {} runs past the end of the image",
+                what, offset
+            ),
+        ));
+    }
+    source.read_at(offset, len).await
+}
+
+fn div_ceil(n: u64, d: u64) -> u64 {
+    n / d + u64::from(n % d != 0)
+}
+
+fn round_up(n: u64, d: u64) -> u64 {
+    div_ceil(n, d) * d
+}
+
+fn checked_round_up(n: u64, d: u64) -> Option<u64> {
+    div_ceil(n, d).checked_mul(d)
+}
+
+fn be_u32(b: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(b[offset..offset + 4].try_into().unwrap())
+}
+
+fn be_u64(b: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(b[offset..offset + 8].try_into().unwrap())
+}
+
+fn le_u16(b: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(b[offset..offset + 2].try_into().unwrap())
+}
+
+fn le_u32(b: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(b[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(b: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(b[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// An image, or a disk with an image written to it, held in memory
+    pub(super) struct MemoryDisk {
+        pub data: Mutex<Vec<u8>>,
+    }
+
+    impl MemoryDisk {
+        pub fn new(data: Vec<u8>) -> Self {
+            MemoryDisk { data: Mutex::new(data) }
+        }
+    }
+
+    #[async_trait]
+    impl ImageSource for MemoryDisk {
+        fn size(&self) -> Option<u64> {
+            Some(self.data.lock().unwrap().len() as u64)
+        }
+
+        async fn read_at(
+            &self,
+            offset: u64,
+            len: u64,
+        ) -> Result<Vec<u8>, ConversionError> {
+            let data = self.data.lock().unwrap();
+            let end = check_read(offset, len)? as usize;
+            if end > data.len() {
+                return Err(ConversionError::Read(String::from("short read")));
+            }
+            Ok(data[offset as usize..end].to_vec())
+        }
+    }
+
+    #[async_trait]
+    impl DiskWriter for MemoryDisk {
+        fn block_size(&self) -> u64 {
+            512
+        }
+
+        async fn write_at(
+            &self,
+            offset: u64,
+            data: Vec<u8>,
+        ) -> Result<(), Error> {
+            let mut disk = self.data.lock().unwrap();
+            let offset = offset as usize;
+            disk[offset..offset + data.len()].copy_from_slice(&data);
+            Ok(())
+        }
+    }
+
+    /// A guest-visible disk with a recognisable pattern in some places, and
+    /// zeros elsewhere
+    pub(super) fn pattern(
+        size: usize,
+        allocated: &[(usize, usize)],
+    ) -> Vec<u8> {
+        let mut data = vec![0; size];
+        for (offset, len) in allocated {
+            for (i, b) in data.iter_mut().enumerate().skip(*offset).take(*len) {
+                *b = (i % 251) as u8 + 1;
+            }
+        }
+        data
+    }
+
+    /// Maps `image`, checks it's detected as `format`, and returns the
+    /// guest-visible disk
+    pub(super) async fn convert_image(
+        image: Vec<u8>,
+        format: ImageFormat,
+    ) -> Result<Vec<u8>, ConversionError> {
+        let source = MemoryDisk::new(image);
+        assert_eq!(detect_format(&source).await.unwrap(), format);
+        let map = map_image(&source, format).await?;
+        map.read(&source, 0, map.virtual_size()).await
+    }
+
+    #[tokio::test]
+    async fn test_detect_raw() {
+        let source = MemoryDisk::new(pattern(4096, &[(0, 4096)]));
+        assert_eq!(detect_format(&source).await.unwrap(), ImageFormat::Raw);
+
+        // Too small to even have a first sector
+        let source = MemoryDisk::new(vec![1, 2, 3]);
+        assert_eq!(detect_format(&source).await.unwrap(), ImageFormat::Raw);
+    }
+
+    #[tokio::test]
+    async fn test_disk_map_read() {
+        let image = pattern(64 * 1024, &[(0, 64 * 1024)]);
+        let source = MemoryDisk::new(image.clone());
+
+        // Two extents that are contiguous in the guest and the image, one
+        // that's out of order, and a hole.
+        let mut map = DiskMap::new(ImageFormat::Qcow2, 32 * 1024);
+        map.push(0, 4096, ExtentData::Raw { source_offset: 8192 }).unwrap();
+        map.push(4096, 4096, ExtentData::Raw { source_offset: 12288 }).unwrap();
+        map.push(16384, 4096, ExtentData::Raw { source_offset: 0 }).unwrap();
+        let map = map.finish().unwrap();
+        assert_eq!(map.extents.len(), 2);
+
+        let data = map.read(&source, 2048, 20480).await.unwrap();
+        assert_eq!(&data[..6144], &image[10240..16384]);
+        assert!(data[6144..14336].iter().all(|b| *b == 0));
+        assert_eq!(&data[14336..18432], &image[0..4096]);
+        assert!(data[18432..].iter().all(|b| *b == 0));
+    }
+
+    #[tokio::test]
+    async fn test_disk_map_overlap() {
+        let mut map = DiskMap::new(ImageFormat::Vmdk, 8192);
+        map.push(4096, 4096, ExtentData::Raw { source_offset: 0 }).unwrap();
+        map.push(0, 8192, ExtentData::Raw { source_offset: 16384 }).unwrap();
+        let error = map.finish().unwrap_err();
+        assert!(matches!(error, ConversionError::Invalid { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_check_fits() {
+        let map = DiskMap::new(ImageFormat::Qcow2, 2 << 30);
+        map.check_fits(2 << 30).unwrap();
+        let error = map.check_fits(1 << 30).unwrap_err();
+        assert!(error.to_string().contains("larger than the disk"));
+    }
+}
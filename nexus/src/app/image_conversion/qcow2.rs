@@ -0,0 +1,322 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! qcow2 images, versions 2 and 3
+//!
+//! Guest clusters are found through a two level table: the L1 table points
+//! at L2 tables, whose entries point at data clusters. Only the active L1
+//! table is read, so internal snapshots are ignored.
+
+use super::be_u32;
+use super::be_u64;
+use super::read_table;
+use super::ConversionError;
+use super::DiskMap;
+use super::ExtentData;
+use super::ImageFormat;
+use super::ImageSource;
+
+pub(super) const MAGIC: &[u8; 4] = b"QFI\xfb";
+
+const HEADER_READ_SIZE: u64 = 512;
+
+/// Host offsets in L1 and L2 entries, without their flags
+const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const L2_COMPRESSED: u64 = 1 << 62;
+/// Only meaningful in version 3 images
+const L2_ZERO: u64 = 1;
+
+const INCOMPAT_DIRTY: u64 = 1 << 0;
+const INCOMPAT_CORRUPT: u64 = 1 << 1;
+const INCOMPAT_DATA_FILE: u64 = 1 << 2;
+const INCOMPAT_COMPRESSION: u64 = 1 << 3;
+const INCOMPAT_EXTL2: u64 = 1 << 4;
+
+fn invalid(message: impl ToString) -> ConversionError {
+    ConversionError::invalid(ImageFormat::Qcow2, message)
+}
+
+fn unsupported(message: impl ToString) -> ConversionError {
+    ConversionError::unsupported(ImageFormat::Qcow2, message)
+}
+
+pub(super) async fn map(
+    source: &dyn ImageSource,
+) -> Result<DiskMap, ConversionError> {
+    let header = source.read_at(0, HEADER_READ_SIZE).await?;
+
+    let version = be_u32(&header, 4);
+    if version != 2 && version != 3 {
+        return Err(unsupported(format!("version {}", version)));
+    }
+    if be_u64(&header, 8) != 0 {
+        return Err(unsupported("images with a backing file"));
+    }
+    let cluster_bits = be_u32(&header, 20);
+    if !(9..=21).contains(&cluster_bits) {
+        return Err(invalid(format!("cluster bits {}", cluster_bits)));
+    }
+    let cluster_size = 1u64 << cluster_bits;
+    let virtual_size = be_u64(&header, 24);
+    if be_u32(&header, 32) != 0 {
+        return Err(unsupported("encrypted images"));
+    }
+    let l1_size = u64::from(be_u32(&header, 36));
+    let l1_offset = be_u64(&header, 40);
+
+    if version == 3 {
+        let incompatible = be_u64(&header, 72);
+        let header_length = be_u32(&header, 100);
+        if incompatible & INCOMPAT_CORRUPT != 0 {
+            return Err(invalid("the image is marked corrupt"));
+        }
+        if incompatible & INCOMPAT_DATA_FILE != 0 {
+            return Err(unsupported("images with an external data file"));
+        }
+        if incompatible & INCOMPAT_EXTL2 != 0 {
+            return Err(unsupported("extended L2 entries"));
+        }
+        if incompatible & INCOMPAT_COMPRESSION != 0
+            && header_length > 104
+            && header[104] != 0
+        {
+            return Err(unsupported("zstd compression"));
+        }
+        // A dirty image has refcounts that need repairing, but since they
+        // aren't used here it converts just as well.
+        let known = INCOMPAT_DIRTY
+            | INCOMPAT_CORRUPT
+            | INCOMPAT_DATA_FILE
+            | INCOMPAT_COMPRESSION
+            | INCOMPAT_EXTL2;
+        if incompatible & !known != 0 {
+            return Err(unsupported(format!(
+                "incompatible features {:#x}",
+                incompatible & !known
+            )));
+        }
+    }
+
+    let l2_entries = cluster_size / 8;
+    let guest_clusters = super::div_ceil(virtual_size, cluster_size);
+    if l1_size < super::div_ceil(guest_clusters, l2_entries) {
+        return Err(invalid(format!(
+            "L1 table of {} entries is too small for {} bytes",
+            l1_size, virtual_size
+        )));
+    }
+    if l1_offset % cluster_size != 0 {
+        return Err(invalid("L1 table is not cluster aligned"));
+    }
+
+    let mut map = DiskMap::new(ImageFormat::Qcow2, virtual_size);
+
+    // Only the part of the L1 table that maps the guest disk is read.
+    let l1_used = super::div_ceil(guest_clusters, l2_entries);
+    let l1 = read_table(
+        source,
+        ImageFormat::Qcow2,
+        "L1 table",
+        l1_offset,
+        l1_used * 8,
+    )
+    .await?;
+
+    // Compressed cluster descriptors: the host offset is in the low bits, and
+    // the number of additional 512 byte sectors in the high bits.
+    let csize_shift = 62 - (cluster_bits - 8);
+    let csize_mask = (1u64 << (cluster_bits - 8)) - 1;
+    let coffset_mask = (1u64 << csize_shift) - 1;
+
+    for (l1_index, l1_entry) in l1.chunks_exact(8).enumerate() {
+        let l2_offset = be_u64(l1_entry, 0) & OFFSET_MASK;
+        if l2_offset == 0 {
+            continue;
+        }
+        if l2_offset % cluster_size != 0 {
+            return Err(invalid(format!(
+                "L2 table offset {} is not cluster aligned",
+                l2_offset
+            )));
+        }
+
+        let l2 = read_table(
+            source,
+            ImageFormat::Qcow2,
+            "L2 table",
+            l2_offset,
+            cluster_size,
+        )
+        .await?;
+
+        let first_cluster = l1_index as u64 * l2_entries;
+        for (l2_index, l2_entry) in l2.chunks_exact(8).enumerate() {
+            let guest_cluster = first_cluster + l2_index as u64;
+            if guest_cluster >= guest_clusters {
+                break;
+            }
+            let guest_offset = guest_cluster * cluster_size;
+            let entry = be_u64(l2_entry, 0);
+
+            if entry & L2_COMPRESSED != 0 {
+                let source_offset = entry & coffset_mask;
+                let sectors = ((entry >> csize_shift) & csize_mask) + 1;
+                let compressed_len = sectors * 512 - (source_offset & 511);
+                map.push(
+                    guest_offset,
+                    cluster_size,
+                    ExtentData::Deflate {
+                        source_offset,
+                        compressed_len,
+                        decompressed_len: cluster_size,
+                    },
+                )?;
+                continue;
+            }
+
+            if version == 3 && entry & L2_ZERO != 0 {
+                continue;
+            }
+
+            let source_offset = entry & OFFSET_MASK;
+            if source_offset == 0 {
+                continue;
+            }
+            if source_offset % cluster_size != 0 {
+                return Err(invalid(format!(
+                    "data cluster offset {} is not cluster aligned",
+                    source_offset
+                )));
+            }
+            map.push(
+                guest_offset,
+                cluster_size,
+                ExtentData::Raw { source_offset },
+            )?;
+        }
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::convert_image;
+    use super::super::test::pattern;
+    use super::super::ConversionError;
+    use super::super::ImageFormat;
+    use std::io::Write;
+
+    const CLUSTER: usize = 512;
+
+    /// Builds a version 3 image with 512 byte clusters: the header, the L1
+    /// table, the refcount table, a single L2 table, then data clusters. Guest
+    /// clusters 0, 1 and 9 are stored as is, 5 is compressed, and 7 is marked
+    /// as zero.
+    fn image(expected: &[u8]) -> Vec<u8> {
+        let size = expected.len();
+        let mut image = vec![0u8; 8 * CLUSTER];
+
+        let header = &mut image[..CLUSTER];
+        header[..4].copy_from_slice(super::MAGIC);
+        header[4..8].copy_from_slice(&3u32.to_be_bytes());
+        header[20..24].copy_from_slice(&9u32.to_be_bytes());
+        header[24..32].copy_from_slice(&(size as u64).to_be_bytes());
+        header[36..40].copy_from_slice(&1u32.to_be_bytes());
+        header[40..48].copy_from_slice(&(CLUSTER as u64).to_be_bytes());
+        header[48..56].copy_from_slice(&(2 * CLUSTER as u64).to_be_bytes());
+        header[56..60].copy_from_slice(&1u32.to_be_bytes());
+        header[96..100].copy_from_slice(&4u32.to_be_bytes());
+        header[100..104].copy_from_slice(&104u32.to_be_bytes());
+
+        let l1_entry = (3 * CLUSTER as u64) | (1 << 63);
+        image[CLUSTER..CLUSTER + 8].copy_from_slice(&l1_entry.to_be_bytes());
+
+        let mut set_l2 = |guest_cluster: usize, entry: u64| {
+            let at = 3 * CLUSTER + guest_cluster * 8;
+            image[at..at + 8].copy_from_slice(&entry.to_be_bytes());
+        };
+        set_l2(0, 4 * CLUSTER as u64);
+        set_l2(1, 5 * CLUSTER as u64);
+        set_l2(9, 7 * CLUSTER as u64);
+        set_l2(7, 1);
+        // One compressed sector, at the start of the cluster after the data
+        set_l2(5, (1 << 62) | (6 * CLUSTER as u64));
+
+        let cluster = |n: usize| &expected[n * CLUSTER..(n + 1) * CLUSTER];
+        image[4 * CLUSTER..6 * CLUSTER].copy_from_slice(&expected[..1024]);
+        image[7 * CLUSTER..].copy_from_slice(cluster(9));
+
+        let mut encoder = flate2::write::DeflateEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(cluster(5)).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() <= CLUSTER);
+        image[6 * CLUSTER..6 * CLUSTER + compressed.len()]
+            .copy_from_slice(&compressed);
+
+        image
+    }
+
+    #[tokio::test]
+    async fn test_qcow2() {
+        let expected = pattern(
+            32 * CLUSTER,
+            &[(0, 2 * CLUSTER), (5 * CLUSTER, CLUSTER), (9 * CLUSTER, CLUSTER)],
+        );
+        let mut image = image(&expected);
+
+        // The zero flag hides whatever the cluster would otherwise be.
+        let at = 3 * CLUSTER + 7 * 8;
+        image[at..at + 8]
+            .copy_from_slice(&((7 * CLUSTER as u64) | 1).to_be_bytes());
+
+        let data = convert_image(image, ImageFormat::Qcow2).await.unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn test_qcow2_unsupported() {
+        let expected = pattern(32 * CLUSTER, &[(0, CLUSTER)]);
+
+        let mut backed = image(&expected);
+        backed[8..16].copy_from_slice(&4096u64.to_be_bytes());
+        let error =
+            convert_image(backed, ImageFormat::Qcow2).await.unwrap_err();
+        assert!(matches!(error, ConversionError::Unsupported { .. }));
+        assert!(error.to_string().contains("backing file"), "{}", error);
+
+        let mut encrypted = image(&expected);
+        encrypted[32..36].copy_from_slice(&1u32.to_be_bytes());
+        let error =
+            convert_image(encrypted, ImageFormat::Qcow2).await.unwrap_err();
+        assert!(error.to_string().contains("encrypted"), "{}", error);
+
+        let mut corrupt = image(&expected);
+        corrupt[72..80].copy_from_slice(&2u64.to_be_bytes());
+        let error =
+            convert_image(corrupt, ImageFormat::Qcow2).await.unwrap_err();
+        assert!(matches!(error, ConversionError::Invalid { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_qcow2_malformed() {
+        let expected = pattern(32 * CLUSTER, &[(0, CLUSTER)]);
+
+        let mut l1_past_end = image(&expected);
+        l1_past_end[40..48].copy_from_slice(&(1u64 << 40).to_be_bytes());
+        let error =
+            convert_image(l1_past_end, ImageFormat::Qcow2).await.unwrap_err();
+        assert!(error.to_string().contains("past the end"), "{}", error);
+
+        // A disk big enough to need an 8 GiB L1 table
+        let mut huge = image(&expected);
+        huge[24..32].copy_from_slice(&(1u64 << 45).to_be_bytes());
+        huge[36..40].copy_from_slice(&(1u32 << 30).to_be_bytes());
+        let error = convert_image(huge, ImageFormat::Qcow2).await.unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+    }
+}
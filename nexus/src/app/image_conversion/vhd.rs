@@ -0,0 +1,293 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! VHD images, fixed and dynamic
+//!
+//! Fixed disks are the raw disk followed by a footer. Dynamic disks start
+//! with a copy of the footer, followed by a header pointing at the block
+//! allocation table. Each allocated block is a sector bitmap followed by the
+//! block's data; blocks are converted whole, as unwritten sectors of an
+//! allocated block are zeroed.
+
+use super::be_u32;
+use super::be_u64;
+use super::read_table;
+use super::ConversionError;
+use super::DiskMap;
+use super::ExtentData;
+use super::ImageFormat;
+use super::ImageSource;
+
+pub(super) const COOKIE: &[u8; 8] = b"conectix";
+const DYNAMIC_COOKIE: &[u8; 8] = b"cxsparse";
+
+const SECTOR_SIZE: u64 = 512;
+
+const DISK_TYPE_FIXED: u32 = 2;
+const DISK_TYPE_DYNAMIC: u32 = 3;
+const DISK_TYPE_DIFFERENCING: u32 = 4;
+
+const BAT_UNALLOCATED: u32 = 0xffff_ffff;
+
+fn invalid(message: impl ToString) -> ConversionError {
+    ConversionError::invalid(ImageFormat::Vhd, message)
+}
+
+fn unsupported(message: impl ToString) -> ConversionError {
+    ConversionError::unsupported(ImageFormat::Vhd, message)
+}
+
+/// Footers and dynamic disk headers are checksummed with the ones' complement
+/// of the sum of their bytes, leaving out the checksum itself.
+fn check_checksum(
+    what: &str,
+    data: &[u8],
+    checksum_offset: usize,
+) -> Result<(), ConversionError> {
+    let sum = data
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !(checksum_offset..checksum_offset + 4).contains(i))
+        .fold(0u32, |sum, (_, b)| sum.wrapping_add(u32::from(*b)));
+    if !sum != be_u32(data, checksum_offset) {
+        return Err(invalid(format!("bad {} checksum", what)));
+    }
+    Ok(())
+}
+
+pub(super) async fn map(
+    source: &dyn ImageSource,
+) -> Result<DiskMap, ConversionError> {
+    // Dynamic disks have a copy of the footer at the start of the image, and
+    // fixed disks only the one at the end.
+    let first = source.read_at(0, SECTOR_SIZE).await?;
+    let footer = if first.starts_with(COOKIE) {
+        first
+    } else {
+        let size = source
+            .size()
+            .ok_or_else(|| invalid("the size of the image is not known"))?;
+        if size < SECTOR_SIZE {
+            return Err(invalid("the image is too small"));
+        }
+        let last = source.read_at(size - SECTOR_SIZE, SECTOR_SIZE).await?;
+        if !last.starts_with(COOKIE) {
+            return Err(invalid("no footer"));
+        }
+        last
+    };
+
+    check_checksum("footer", &footer, 64)?;
+    let data_offset = be_u64(&footer, 16);
+    let virtual_size = be_u64(&footer, 48);
+    let disk_type = be_u32(&footer, 60);
+
+    match disk_type {
+        DISK_TYPE_FIXED => {
+            let mut map = DiskMap::new(ImageFormat::Vhd, virtual_size);
+            map.push(0, virtual_size, ExtentData::Raw { source_offset: 0 })?;
+            Ok(map)
+        }
+
+        DISK_TYPE_DYNAMIC => {
+            map_dynamic(source, data_offset, virtual_size).await
+        }
+
+        DISK_TYPE_DIFFERENCING => {
+            Err(unsupported("differencing disks, which need their parent"))
+        }
+
+        other => Err(invalid(format!("disk type {}", other))),
+    }
+}
+
+async fn map_dynamic(
+    source: &dyn ImageSource,
+    header_offset: u64,
+    virtual_size: u64,
+) -> Result<DiskMap, ConversionError> {
+    let header = read_table(
+        source,
+        ImageFormat::Vhd,
+        "dynamic disk header",
+        header_offset,
+        2 * SECTOR_SIZE,
+    )
+    .await?;
+    if !header.starts_with(DYNAMIC_COOKIE) {
+        return Err(invalid("bad dynamic disk header cookie"));
+    }
+    check_checksum("dynamic disk header", &header, 36)?;
+
+    let table_offset = be_u64(&header, 16);
+    let max_entries = u64::from(be_u32(&header, 28));
+    let block_size = u64::from(be_u32(&header, 32));
+    if block_size < SECTOR_SIZE || !block_size.is_power_of_two() {
+        return Err(invalid(format!("block size {}", block_size)));
+    }
+
+    let blocks = super::div_ceil(virtual_size, block_size);
+    if blocks > max_entries {
+        return Err(invalid(format!(
+            "block allocation table of {} entries is too small for {} bytes",
+            max_entries, virtual_size
+        )));
+    }
+
+    // One bit per sector of the block, padded out to a whole sector
+    let bitmap_size = super::round_up(
+        super::div_ceil(block_size / SECTOR_SIZE, 8),
+        SECTOR_SIZE,
+    );
+
+    let mut map = DiskMap::new(ImageFormat::Vhd, virtual_size);
+    let bat = read_table(
+        source,
+        ImageFormat::Vhd,
+        "block allocation table",
+        table_offset,
+        blocks * 4,
+    )
+    .await?;
+    for (block, entry) in bat.chunks_exact(4).enumerate() {
+        let sector = be_u32(entry, 0);
+        if sector == BAT_UNALLOCATED {
+            continue;
+        }
+        map.push(
+            block as u64 * block_size,
+            block_size,
+            ExtentData::Raw {
+                source_offset: u64::from(sector) * SECTOR_SIZE + bitmap_size,
+            },
+        )?;
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::convert_image;
+    use super::super::test::pattern;
+    use super::super::ConversionError;
+    use super::super::ImageFormat;
+    use super::COOKIE;
+    use super::DYNAMIC_COOKIE;
+
+    const SECTOR: usize = 512;
+    const BLOCK: usize = 4096;
+
+    fn set_checksum(data: &mut [u8], offset: usize) {
+        let sum = data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !(offset..offset + 4).contains(i))
+            .fold(0u32, |sum, (_, b)| sum.wrapping_add(u32::from(*b)));
+        data[offset..offset + 4].copy_from_slice(&(!sum).to_be_bytes());
+    }
+
+    fn footer(disk_type: u32, data_offset: u64, size: u64) -> Vec<u8> {
+        let mut footer = vec![0u8; SECTOR];
+        footer[..8].copy_from_slice(COOKIE);
+        footer[12..16].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        footer[16..24].copy_from_slice(&data_offset.to_be_bytes());
+        footer[40..48].copy_from_slice(&size.to_be_bytes());
+        footer[48..56].copy_from_slice(&size.to_be_bytes());
+        footer[60..64].copy_from_slice(&disk_type.to_be_bytes());
+        set_checksum(&mut footer, 64);
+        footer
+    }
+
+    #[tokio::test]
+    async fn test_vhd_fixed() {
+        let expected = pattern(8 * BLOCK, &[(0, 8 * BLOCK)]);
+        let mut image = expected.clone();
+        image.extend(footer(2, u64::MAX, expected.len() as u64));
+
+        let data = convert_image(image, ImageFormat::Vhd).await.unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn test_vhd_dynamic() {
+        // The footer copy, the header at sector 1, the table at sector 3,
+        // then blocks of a sector bitmap and 4 KiB of data
+        let expected =
+            pattern(8 * BLOCK, &[(BLOCK, BLOCK), (6 * BLOCK, BLOCK)]);
+        let footer = footer(3, SECTOR as u64, expected.len() as u64);
+        let mut image = footer.clone();
+
+        let mut header = vec![0u8; 2 * SECTOR];
+        header[..8].copy_from_slice(DYNAMIC_COOKIE);
+        header[8..16].copy_from_slice(&u64::MAX.to_be_bytes());
+        header[16..24].copy_from_slice(&(3 * SECTOR as u64).to_be_bytes());
+        header[24..28].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        header[28..32].copy_from_slice(&8u32.to_be_bytes());
+        header[32..36].copy_from_slice(&(BLOCK as u32).to_be_bytes());
+        set_checksum(&mut header, 36);
+        image.extend(header);
+
+        let mut bat = vec![0xffu8; SECTOR];
+        bat[4..8].copy_from_slice(&13u32.to_be_bytes());
+        bat[24..28].copy_from_slice(&4u32.to_be_bytes());
+        image.extend(bat);
+
+        for block in [6, 1] {
+            image.extend([0xffu8; SECTOR]);
+            image.extend(&expected[block * BLOCK..(block + 1) * BLOCK]);
+        }
+        image.extend(footer);
+
+        let data = convert_image(image, ImageFormat::Vhd).await.unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn test_vhd_unsupported() {
+        let mut image = footer(4, SECTOR as u64, 8 * BLOCK as u64);
+        image.resize(8 * SECTOR, 0);
+        let error = convert_image(image, ImageFormat::Vhd).await.unwrap_err();
+        assert!(matches!(error, ConversionError::Unsupported { .. }));
+
+        let mut image = footer(3, SECTOR as u64, 8 * BLOCK as u64);
+        image[48] ^= 1;
+        image.resize(8 * SECTOR, 0);
+        let error = convert_image(image, ImageFormat::Vhd).await.unwrap_err();
+        assert!(error.to_string().contains("checksum"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_vhd_malformed() {
+        let dynamic_header = |table_offset: u64, max_entries: u32| {
+            let mut header = vec![0u8; 2 * SECTOR];
+            header[..8].copy_from_slice(DYNAMIC_COOKIE);
+            header[16..24].copy_from_slice(&table_offset.to_be_bytes());
+            header[28..32].copy_from_slice(&max_entries.to_be_bytes());
+            header[32..36].copy_from_slice(&(BLOCK as u32).to_be_bytes());
+            set_checksum(&mut header, 36);
+            header
+        };
+
+        // A dynamic disk header past the end of the image
+        let mut image = footer(3, u64::MAX - 256, 8 * BLOCK as u64);
+        image.resize(8 * SECTOR, 0);
+        let error = convert_image(image, ImageFormat::Vhd).await.unwrap_err();
+        assert!(matches!(error, ConversionError::Invalid { .. }));
+
+        // A block allocation table whose end overflows
+        let mut image = footer(3, SECTOR as u64, 8 * BLOCK as u64);
+        image.extend(dynamic_header(u64::MAX - 4, 8));
+        image.resize(8 * SECTOR, 0);
+        let error = convert_image(image, ImageFormat::Vhd).await.unwrap_err();
+        assert!(error.to_string().contains("out of range"), "{}", error);
+
+        // A disk big enough to need a 16 GiB block allocation table
+        let mut image = footer(3, SECTOR as u64, u64::from(u32::MAX) << 12);
+        image.extend(dynamic_header(3 * SECTOR as u64, u32::MAX));
+        image.resize(8 * SECTOR, 0);
+        let error = convert_image(image, ImageFormat::Vhd).await.unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+    }
+}
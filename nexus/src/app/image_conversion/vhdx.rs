@@ -0,0 +1,476 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! VHDX images, fixed and dynamic
+//!
+//! The image has two copies of its header, the current one being the valid
+//! copy with the highest sequence number. The header leads to the region
+//! table, which locates the block allocation table (BAT) and the metadata
+//! region holding the disk's size and block size. Images with a log that has
+//! not been replayed are rejected, as their metadata may be out of date.
+
+use super::le_u16;
+use super::le_u32;
+use super::le_u64;
+use super::read_table;
+use super::ConversionError;
+use super::DiskMap;
+use super::ExtentData;
+use super::ImageFormat;
+use super::ImageSource;
+
+pub(super) const SIGNATURE: &[u8; 8] = b"vhdxfile";
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+
+const HEADER_OFFSETS: [u64; 2] = [64 * KIB, 128 * KIB];
+const HEADER_SIZE: u64 = 4 * KIB;
+const REGION_TABLE_OFFSETS: [u64; 2] = [192 * KIB, 256 * KIB];
+const REGION_TABLE_SIZE: u64 = 64 * KIB;
+const METADATA_TABLE_SIZE: u64 = 64 * KIB;
+
+/// On-disk bytes of the GUIDs identifying regions and metadata items
+const BAT_REGION: [u8; 16] = [
+    0x66, 0x77, 0xc2, 0x2d, 0x23, 0xf6, 0x00, 0x42, 0x9d, 0x64, 0x11, 0x5e,
+    0x9b, 0xfd, 0x4a, 0x08,
+];
+const METADATA_REGION: [u8; 16] = [
+    0x06, 0xa2, 0x7c, 0x8b, 0x90, 0x47, 0x9a, 0x4b, 0xb8, 0xfe, 0x57, 0x5f,
+    0x05, 0x0f, 0x88, 0x6e,
+];
+const FILE_PARAMETERS: [u8; 16] = [
+    0x37, 0x67, 0xa1, 0xca, 0x36, 0xfa, 0x43, 0x4d, 0xb3, 0xb6, 0x33, 0xf0,
+    0xaa, 0x44, 0xe7, 0x6b,
+];
+const VIRTUAL_DISK_SIZE: [u8; 16] = [
+    0x24, 0x42, 0xa5, 0x2f, 0x1b, 0xcd, 0x76, 0x48, 0xb2, 0x11, 0x5d, 0xbe,
+    0xd8, 0x3b, 0xf4, 0xb8,
+];
+const LOGICAL_SECTOR_SIZE: [u8; 16] = [
+    0x1d, 0xbf, 0x41, 0x81, 0x6f, 0xa9, 0x09, 0x47, 0xba, 0x47, 0xf2, 0x33,
+    0xa8, 0xfa, 0xab, 0x5f,
+];
+
+const FILE_PARAMETERS_HAS_PARENT: u32 = 1 << 1;
+
+/// BAT entry states for payload blocks
+const PAYLOAD_BLOCK_NOT_PRESENT: u64 = 0;
+const PAYLOAD_BLOCK_UNDEFINED: u64 = 1;
+const PAYLOAD_BLOCK_ZERO: u64 = 2;
+const PAYLOAD_BLOCK_UNMAPPED: u64 = 3;
+const PAYLOAD_BLOCK_FULLY_PRESENT: u64 = 6;
+const PAYLOAD_BLOCK_PARTIALLY_PRESENT: u64 = 7;
+
+fn invalid(message: impl ToString) -> ConversionError {
+    ConversionError::invalid(ImageFormat::Vhdx, message)
+}
+
+fn unsupported(message: impl ToString) -> ConversionError {
+    ConversionError::unsupported(ImageFormat::Vhdx, message)
+}
+
+fn out_of_range(what: &str) -> ConversionError {
+    ConversionError::out_of_range(ImageFormat::Vhdx, what)
+}
+
+/// CRC-32C (Castagnoli), which checksums VHDX headers and region tables
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc =
+                if crc & 1 != 0 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Whether a header or region table has a good checksum, stored at offset 4
+fn checksum_ok(data: &[u8]) -> bool {
+    let mut copy = data.to_vec();
+    copy[4..8].fill(0);
+    crc32c(&copy) == le_u32(data, 4)
+}
+
+pub(super) async fn map(
+    source: &dyn ImageSource,
+) -> Result<DiskMap, ConversionError> {
+    // The current header is the valid one with the highest sequence number.
+    let mut current: Option<Vec<u8>> = None;
+    for offset in HEADER_OFFSETS {
+        let header = source.read_at(offset, HEADER_SIZE).await?;
+        if !header.starts_with(b"head") || !checksum_ok(&header) {
+            continue;
+        }
+        if current.as_ref().map_or(true, |c| le_u64(&header, 8) > le_u64(c, 8))
+        {
+            current = Some(header);
+        }
+    }
+    let header = current.ok_or_else(|| invalid("no valid header"))?;
+
+    if header[48..64].iter().any(|b| *b != 0) {
+        return Err(unsupported(
+            "images with a log that needs replaying; open and close the \
+            image cleanly first",
+        ));
+    }
+    let version = le_u16(&header, 66);
+    if version != 1 {
+        return Err(unsupported(format!("version {}", version)));
+    }
+
+    let mut region_table = None;
+    for offset in REGION_TABLE_OFFSETS {
+        let table = source.read_at(offset, REGION_TABLE_SIZE).await?;
+        if table.starts_with(b"regi") && checksum_ok(&table) {
+            region_table = Some(table);
+            break;
+        }
+    }
+    let region_table =
+        region_table.ok_or_else(|| invalid("no valid region table"))?;
+
+    let entry_count = le_u32(&region_table, 8) as usize;
+    if 16 + entry_count * 32 > region_table.len() {
+        return Err(invalid(format!("{} region table entries", entry_count)));
+    }
+    let mut bat_region = None;
+    let mut metadata_region = None;
+    for entry in region_table[16..16 + entry_count * 32].chunks_exact(32) {
+        let region = (le_u64(entry, 16), u64::from(le_u32(entry, 24)));
+        if entry[..16] == BAT_REGION {
+            bat_region = Some(region);
+        } else if entry[..16] == METADATA_REGION {
+            metadata_region = Some(region);
+        } else if le_u32(entry, 28) & 1 != 0 {
+            return Err(unsupported("unknown required regions"));
+        }
+    }
+    let (bat_offset, bat_length) =
+        bat_region.ok_or_else(|| invalid("no block allocation table"))?;
+    let (metadata_offset, metadata_length) =
+        metadata_region.ok_or_else(|| invalid("no metadata region"))?;
+
+    let metadata =
+        Metadata::read(source, metadata_offset, metadata_length).await?;
+
+    // Every chunk of payload blocks is followed by a sector bitmap block's
+    // entry, which is only used by differencing disks.
+    let chunk_ratio =
+        (1u64 << 23) * metadata.logical_sector_size / metadata.block_size;
+    let blocks = super::div_ceil(metadata.virtual_size, metadata.block_size);
+    let bat_entries =
+        if blocks == 0 { 0 } else { blocks + (blocks - 1) / chunk_ratio };
+    if bat_entries.saturating_mul(8) > bat_length {
+        return Err(invalid(format!(
+            "block allocation table of {} bytes is too small for {} bytes",
+            bat_length, metadata.virtual_size
+        )));
+    }
+
+    let mut map = DiskMap::new(ImageFormat::Vhdx, metadata.virtual_size);
+
+    let bat = read_table(
+        source,
+        ImageFormat::Vhdx,
+        "block allocation table",
+        bat_offset,
+        bat_entries * 8,
+    )
+    .await?;
+    for block in 0..blocks {
+        let index = (block + block / chunk_ratio) as usize;
+        let entry = le_u64(&bat, index * 8);
+        match entry & 0x7 {
+            PAYLOAD_BLOCK_NOT_PRESENT
+            | PAYLOAD_BLOCK_UNDEFINED
+            | PAYLOAD_BLOCK_ZERO
+            | PAYLOAD_BLOCK_UNMAPPED => {}
+            PAYLOAD_BLOCK_FULLY_PRESENT => {
+                map.push(
+                    block * metadata.block_size,
+                    metadata.block_size,
+                    ExtentData::Raw { source_offset: (entry >> 20) * MIB },
+                )?;
+            }
+            PAYLOAD_BLOCK_PARTIALLY_PRESENT => {
+                return Err(unsupported(
+                    "differencing disks, which need their parent",
+                ));
+            }
+            other => {
+                return Err(invalid(format!(
+                    "block {} has state {}",
+                    block, other
+                )));
+            }
+        }
+    }
+
+    Ok(map)
+}
+
+/// The metadata items needed to convert an image
+struct Metadata {
+    block_size: u64,
+    virtual_size: u64,
+    logical_sector_size: u64,
+}
+
+impl Metadata {
+    async fn read(
+        source: &dyn ImageSource,
+        offset: u64,
+        length: u64,
+    ) -> Result<Self, ConversionError> {
+        let table_size = std::cmp::min(length, METADATA_TABLE_SIZE);
+        let table = read_table(
+            source,
+            ImageFormat::Vhdx,
+            "metadata table",
+            offset,
+            table_size,
+        )
+        .await?;
+        if !table.starts_with(b"metadata") {
+            return Err(invalid("bad metadata table signature"));
+        }
+
+        let entry_count = le_u16(&table, 10) as usize;
+        if 32 + entry_count * 32 > table.len() {
+            return Err(invalid(format!(
+                "{} metadata table entries",
+                entry_count
+            )));
+        }
+
+        let mut file_parameters = None;
+        let mut virtual_size = None;
+        let mut logical_sector_size = None;
+        for entry in table[32..32 + entry_count * 32].chunks_exact(32) {
+            let item_offset = u64::from(le_u32(entry, 16));
+            let item_length = u64::from(le_u32(entry, 20));
+            let is_required = le_u32(entry, 24) & (1 << 2) != 0;
+
+            let wanted = if entry[..16] == FILE_PARAMETERS {
+                Some((&mut file_parameters, 8))
+            } else if entry[..16] == VIRTUAL_DISK_SIZE {
+                Some((&mut virtual_size, 8))
+            } else if entry[..16] == LOGICAL_SECTOR_SIZE {
+                Some((&mut logical_sector_size, 4))
+            } else {
+                None
+            };
+
+            match wanted {
+                Some((item, size)) => {
+                    if item_length < size || item_offset + size > length {
+                        return Err(invalid("metadata item out of bounds"));
+                    }
+                    let item_offset = offset
+                        .checked_add(item_offset)
+                        .ok_or_else(|| out_of_range("metadata item offset"))?;
+                    *item = Some(
+                        read_table(
+                            source,
+                            ImageFormat::Vhdx,
+                            "metadata item",
+                            item_offset,
+                            size,
+                        )
+                        .await?,
+                    );
+                }
+                // Other required items (like the parent locator of a
+                // differencing disk) can't be ignored.
+                None if is_required => {
+                    return Err(unsupported("unknown required metadata"));
+                }
+                None => {}
+            }
+        }
+
+        let file_parameters = file_parameters
+            .ok_or_else(|| invalid("no file parameters metadata"))?;
+        let virtual_size =
+            virtual_size.ok_or_else(|| invalid("no virtual disk size"))?;
+        let logical_sector_size = logical_sector_size
+            .ok_or_else(|| invalid("no logical sector size"))?;
+
+        if le_u32(&file_parameters, 4) & FILE_PARAMETERS_HAS_PARENT != 0 {
+            return Err(unsupported(
+                "differencing disks, which need their parent",
+            ));
+        }
+        let block_size = u64::from(le_u32(&file_parameters, 0));
+        if !(MIB..=256 * MIB).contains(&block_size)
+            || !block_size.is_power_of_two()
+        {
+            return Err(invalid(format!("block size {}", block_size)));
+        }
+        let logical_sector_size = u64::from(le_u32(&logical_sector_size, 0));
+        if logical_sector_size != 512 && logical_sector_size != 4096 {
+            return Err(invalid(format!(
+                "logical sector size {}",
+                logical_sector_size
+            )));
+        }
+
+        Ok(Metadata {
+            block_size,
+            virtual_size: le_u64(&virtual_size, 0),
+            logical_sector_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::convert_image;
+    use super::super::test::pattern;
+    use super::*;
+
+    const BLOCK: usize = MIB as usize;
+
+    /// Sets the CRC-32C of a header or region table
+    fn set_checksum(data: &mut [u8]) {
+        data[4..8].fill(0);
+        let crc = crc32c(data);
+        data[4..8].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Builds a 4 MiB disk with 1 MiB blocks: the file identifier, headers
+    /// and region tables, the metadata region at 320 KiB, the BAT at 1 MiB,
+    /// then blocks 3 and 1 (in that order) from 2 MiB.
+    fn image(expected: &[u8], has_parent: bool) -> Vec<u8> {
+        let mut image = vec![0u8; 4 * BLOCK];
+        image[..8].copy_from_slice(SIGNATURE);
+
+        for (i, offset) in HEADER_OFFSETS.iter().enumerate() {
+            let offset = *offset as usize;
+            let header = &mut image[offset..offset + HEADER_SIZE as usize];
+            header[..4].copy_from_slice(b"head");
+            header[8..16].copy_from_slice(&(i as u64 + 1).to_le_bytes());
+            header[66..68].copy_from_slice(&1u16.to_le_bytes());
+            set_checksum(header);
+        }
+        // The newer header is damaged, so the older one is used.
+        image[128 * KIB as usize + 100] ^= 1;
+
+        for offset in REGION_TABLE_OFFSETS {
+            let offset = offset as usize;
+            let table = &mut image[offset..offset + REGION_TABLE_SIZE as usize];
+            table[..4].copy_from_slice(b"regi");
+            table[8..12].copy_from_slice(&2u32.to_le_bytes());
+            table[16..32].copy_from_slice(&BAT_REGION);
+            table[32..40].copy_from_slice(&MIB.to_le_bytes());
+            table[40..44].copy_from_slice(&(MIB as u32).to_le_bytes());
+            table[44..48].copy_from_slice(&1u32.to_le_bytes());
+            table[48..64].copy_from_slice(&METADATA_REGION);
+            table[64..72].copy_from_slice(&(320 * KIB).to_le_bytes());
+            table[72..76].copy_from_slice(&(64 * KIB as u32).to_le_bytes());
+            table[76..80].copy_from_slice(&1u32.to_le_bytes());
+            set_checksum(table);
+        }
+
+        let metadata = 320 * KIB as usize;
+        image[metadata..metadata + 8].copy_from_slice(b"metadata");
+        image[metadata + 10..metadata + 12]
+            .copy_from_slice(&3u16.to_le_bytes());
+        let items: [(&[u8; 16], &[u8]); 3] = [
+            (&FILE_PARAMETERS, &[0, 0, 0x10, 0, has_parent as u8 * 2, 0, 0, 0]),
+            (&VIRTUAL_DISK_SIZE, &(expected.len() as u64).to_le_bytes()),
+            (&LOGICAL_SECTOR_SIZE, &512u32.to_le_bytes()),
+        ];
+        for (i, (id, value)) in items.iter().enumerate() {
+            let entry = metadata + 32 + i * 32;
+            let item_offset = 64 * KIB as usize / 2 + i * 8;
+            image[entry..entry + 16].copy_from_slice(&id[..]);
+            image[entry + 16..entry + 20]
+                .copy_from_slice(&(item_offset as u32).to_le_bytes());
+            image[entry + 20..entry + 24]
+                .copy_from_slice(&(value.len() as u32).to_le_bytes());
+            image[entry + 24..entry + 28].copy_from_slice(&4u32.to_le_bytes());
+            image[metadata + item_offset..metadata + item_offset + value.len()]
+                .copy_from_slice(value);
+        }
+
+        let bat = MIB as usize;
+        let entry = |mb: u64| (mb << 20) | PAYLOAD_BLOCK_FULLY_PRESENT;
+        image[bat + 8..bat + 16].copy_from_slice(&entry(3).to_le_bytes());
+        image[bat + 16..bat + 24]
+            .copy_from_slice(&PAYLOAD_BLOCK_ZERO.to_le_bytes());
+        image[bat + 24..bat + 32].copy_from_slice(&entry(2).to_le_bytes());
+
+        image[2 * BLOCK..3 * BLOCK].copy_from_slice(&expected[3 * BLOCK..]);
+        image[3 * BLOCK..].copy_from_slice(&expected[BLOCK..2 * BLOCK]);
+        image
+    }
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[tokio::test]
+    async fn test_vhdx() {
+        let expected =
+            pattern(4 * BLOCK, &[(BLOCK, BLOCK), (3 * BLOCK, BLOCK)]);
+        let data = convert_image(image(&expected, false), ImageFormat::Vhdx)
+            .await
+            .unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn test_vhdx_unsupported() {
+        let expected = pattern(4 * BLOCK, &[(BLOCK, BLOCK)]);
+        let error = convert_image(image(&expected, true), ImageFormat::Vhdx)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ConversionError::Unsupported { .. }));
+
+        // A log that hasn't been replayed
+        let mut with_log = image(&expected, false);
+        let header = 64 * KIB as usize;
+        with_log[header + 48] = 1;
+        set_checksum(&mut with_log[header..header + HEADER_SIZE as usize]);
+        let error =
+            convert_image(with_log, ImageFormat::Vhdx).await.unwrap_err();
+        assert!(error.to_string().contains("log"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_vhdx_malformed() {
+        let expected = pattern(4 * BLOCK, &[(BLOCK, BLOCK)]);
+
+        // A block whose end overflows
+        let mut overflow = image(&expected, false);
+        let bat = MIB as usize;
+        overflow[bat + 8..bat + 16].copy_from_slice(
+            &((u64::MAX & !0xf_ffff) | PAYLOAD_BLOCK_FULLY_PRESENT)
+                .to_le_bytes(),
+        );
+        let error =
+            convert_image(overflow, ImageFormat::Vhdx).await.unwrap_err();
+        assert!(matches!(error, ConversionError::Invalid { .. }));
+        assert!(error.to_string().contains("out of range"), "{}", error);
+
+        // A block allocation table past the end of the image
+        let mut past_end = image(&expected, false);
+        for offset in REGION_TABLE_OFFSETS {
+            let offset = offset as usize;
+            let table =
+                &mut past_end[offset..offset + REGION_TABLE_SIZE as usize];
+            table[32..40].copy_from_slice(&(u64::MAX - MIB).to_le_bytes());
+            set_checksum(table);
+        }
+        let error =
+            convert_image(past_end, ImageFormat::Vhdx).await.unwrap_err();
+        assert!(error.to_string().contains("past the end"), "{}", error);
+    }
+}
@@ -0,0 +1,471 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! VMDK images with a single hosted sparse extent: "monolithicSparse" and
+//! "streamOptimized" images
+//!
+//! Uncompressed grains are found through the grain directory and grain
+//! tables. Stream optimized images write their grain directory at the end of
+//! the file, so they're instead read from start to end, following the marker
+//! in front of each compressed grain.
+
+use super::le_u16;
+use super::le_u32;
+use super::le_u64;
+use super::read_table;
+use super::ConversionError;
+use super::DiskMap;
+use super::ExtentData;
+use super::ImageFormat;
+use super::ImageSource;
+
+pub(super) const MAGIC: &[u8; 4] = b"KDMV";
+/// ESX server sparse extents, which are not supported
+const COWD_MAGIC: &[u8; 4] = b"COWD";
+/// Descriptor files on their own, which refer to separate extent files
+const DESCRIPTOR_PREFIX: &[u8] = b"# Disk DescriptorFile";
+
+const SECTOR_SIZE: u64 = 512;
+
+const FLAG_COMPRESSED: u32 = 1 << 16;
+const FLAG_MARKERS: u32 = 1 << 17;
+const COMPRESSION_DEFLATE: u16 = 1;
+/// The grain directory offset of stream optimized images, whose grain
+/// directory is found through the footer
+const GD_AT_END: u64 = u64::MAX;
+
+const MARKER_EOS: u32 = 0;
+const MARKER_GT: u32 = 1;
+const MARKER_GD: u32 = 2;
+const MARKER_FOOTER: u32 = 3;
+
+/// How much of a stream optimized image is read at a time
+const STREAM_READ_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The largest embedded descriptor that's read
+const MAX_DESCRIPTOR_SIZE: u64 = 64 * 1024;
+
+fn invalid(message: impl ToString) -> ConversionError {
+    ConversionError::invalid(ImageFormat::Vmdk, message)
+}
+
+fn unsupported(message: impl ToString) -> ConversionError {
+    ConversionError::unsupported(ImageFormat::Vmdk, message)
+}
+
+fn out_of_range(what: &str) -> ConversionError {
+    ConversionError::out_of_range(ImageFormat::Vmdk, what)
+}
+
+/// The byte offset of a sector number read from the image
+fn sector_offset(sector: u64, what: &str) -> Result<u64, ConversionError> {
+    sector.checked_mul(SECTOR_SIZE).ok_or_else(|| out_of_range(what))
+}
+
+/// Whether an image that starts with `first` is any kind of VMDK file,
+/// including those that can't be converted
+pub(super) fn is_vmdk(first: &[u8]) -> bool {
+    first.starts_with(MAGIC)
+        || first.starts_with(COWD_MAGIC)
+        || first.starts_with(DESCRIPTOR_PREFIX)
+}
+
+pub(super) async fn map(
+    source: &dyn ImageSource,
+) -> Result<DiskMap, ConversionError> {
+    let header = source.read_at(0, SECTOR_SIZE).await?;
+
+    if header.starts_with(COWD_MAGIC) {
+        return Err(unsupported("ESX server sparse extents"));
+    }
+    if header.starts_with(DESCRIPTOR_PREFIX) {
+        return Err(unsupported(
+            "descriptor files with separate extents; upload a \
+            monolithicSparse or streamOptimized image instead",
+        ));
+    }
+    if !header.starts_with(MAGIC) {
+        return Err(invalid("bad magic number"));
+    }
+
+    let version = le_u32(&header, 4);
+    if !(1..=3).contains(&version) {
+        return Err(unsupported(format!("version {}", version)));
+    }
+    let flags = le_u32(&header, 8);
+    let capacity = le_u64(&header, 12);
+    let grain_size = le_u64(&header, 20);
+    let descriptor_offset = le_u64(&header, 28);
+    let descriptor_size = le_u64(&header, 36);
+    let gtes_per_gt = u64::from(le_u32(&header, 44));
+    let gd_offset = le_u64(&header, 56);
+    let overhead = le_u64(&header, 64);
+    let compress_algorithm = le_u16(&header, 77);
+
+    if grain_size == 0 || !grain_size.is_power_of_two() || grain_size > 2048 {
+        return Err(invalid(format!("grain size of {} sectors", grain_size)));
+    }
+    let grain_bytes = grain_size * SECTOR_SIZE;
+    let virtual_size = capacity
+        .checked_mul(SECTOR_SIZE)
+        .ok_or_else(|| invalid(format!("capacity of {} sectors", capacity)))?;
+
+    // Images that are deltas on top of another (snapshots and linked clones)
+    // say so in their embedded descriptor.
+    if descriptor_offset != 0 && descriptor_size != 0 {
+        let len = std::cmp::min(
+            descriptor_size.saturating_mul(SECTOR_SIZE),
+            MAX_DESCRIPTOR_SIZE,
+        );
+        let descriptor = read_table(
+            source,
+            ImageFormat::Vmdk,
+            "embedded descriptor",
+            sector_offset(descriptor_offset, "descriptor offset")?,
+            len,
+        )
+        .await?;
+        if String::from_utf8_lossy(&descriptor).contains("parentFileNameHint") {
+            return Err(unsupported("images with a parent"));
+        }
+    }
+
+    let compressed = flags & FLAG_COMPRESSED != 0;
+    if compressed && compress_algorithm != COMPRESSION_DEFLATE {
+        return Err(unsupported(format!(
+            "compression algorithm {}",
+            compress_algorithm
+        )));
+    }
+
+    let overhead = sector_offset(overhead, "overhead")?;
+    let mut map = DiskMap::new(ImageFormat::Vmdk, virtual_size);
+
+    if compressed || gd_offset == GD_AT_END {
+        if flags & FLAG_MARKERS == 0 {
+            return Err(unsupported(
+                "compressed grains or a trailing grain directory without \
+                markers",
+            ));
+        }
+        scan_stream(source, &mut map, overhead, grain_bytes).await?;
+        return Ok(map);
+    }
+
+    if gtes_per_gt == 0 {
+        return Err(invalid("grain tables have no entries"));
+    }
+    let gt_coverage = gtes_per_gt * grain_size;
+    let gd_entries = super::div_ceil(capacity, gt_coverage);
+    let gd_offset = sector_offset(gd_offset, "grain directory offset")?;
+    let gd_len = gd_entries
+        .checked_mul(4)
+        .ok_or_else(|| out_of_range("grain directory size"))?;
+    let gd = read_table(
+        source,
+        ImageFormat::Vmdk,
+        "grain directory",
+        gd_offset,
+        gd_len,
+    )
+    .await?;
+
+    for (gd_index, gd_entry) in gd.chunks_exact(4).enumerate() {
+        let gt_sector = u64::from(le_u32(gd_entry, 0));
+        if gt_sector == 0 {
+            continue;
+        }
+
+        let gt_offset = gt_sector * SECTOR_SIZE;
+        let gt = read_table(
+            source,
+            ImageFormat::Vmdk,
+            "grain table",
+            gt_offset,
+            gtes_per_gt * 4,
+        )
+        .await?;
+
+        for (gt_index, gt_entry) in gt.chunks_exact(4).enumerate() {
+            // 0 is an unallocated grain, and 1 one that reads as zeros.
+            let grain_sector = u64::from(le_u32(gt_entry, 0));
+            if grain_sector <= 1 {
+                continue;
+            }
+            let grain = gd_index as u64 * gtes_per_gt + gt_index as u64;
+            // The last grain table can run past the end of the disk.
+            let Some(guest_offset) = grain
+                .checked_mul(grain_bytes)
+                .filter(|offset| *offset < virtual_size)
+            else {
+                break;
+            };
+            map.push(
+                guest_offset,
+                grain_bytes,
+                ExtentData::Raw { source_offset: grain_sector * SECTOR_SIZE },
+            )?;
+        }
+    }
+
+    Ok(map)
+}
+
+/// Reads a stream optimized image from `start` to its end of stream marker,
+/// mapping each compressed grain along the way
+async fn scan_stream(
+    source: &dyn ImageSource,
+    map: &mut DiskMap,
+    start: u64,
+    grain_bytes: u64,
+) -> Result<(), ConversionError> {
+    let mut reader = StreamReader { source, buf: vec![], buf_offset: 0 };
+    let mut pos = start;
+
+    loop {
+        if source.size().map_or(false, |size| pos >= size) {
+            // The image was cut off before its end of stream marker. Use the
+            // grains that are there.
+            break;
+        }
+
+        let marker = reader.read(pos, SECTOR_SIZE).await?;
+        let value = le_u64(marker, 0);
+        let size = u64::from(le_u32(marker, 8));
+
+        if size != 0 {
+            // A grain marker: the guest sector of the grain, then its
+            // compressed data
+            let guest_offset =
+                value.checked_mul(SECTOR_SIZE).unwrap_or(u64::MAX);
+            if guest_offset >= map.virtual_size
+                || guest_offset % grain_bytes != 0
+            {
+                return Err(invalid(format!(
+                    "grain marker at offset {} is for sector {}",
+                    pos, value
+                )));
+            }
+            map.push(
+                guest_offset,
+                grain_bytes,
+                ExtentData::Zlib {
+                    source_offset: pos + 12,
+                    compressed_len: size,
+                    decompressed_len: grain_bytes,
+                },
+            )?;
+            // The push checked that the compressed data's end is in range.
+            pos = super::checked_round_up(pos + 12 + size, SECTOR_SIZE)
+                .ok_or_else(|| out_of_range("grain marker size"))?;
+            continue;
+        }
+
+        // A metadata marker, followed by `value` sectors of metadata
+        match le_u32(marker, 12) {
+            MARKER_EOS => break,
+            MARKER_GT | MARKER_GD | MARKER_FOOTER => {
+                pos = sector_offset(value, "metadata marker size")?
+                    .checked_add(pos + SECTOR_SIZE)
+                    .ok_or_else(|| out_of_range("metadata marker size"))?;
+            }
+            other => {
+                return Err(invalid(format!(
+                    "unknown marker type {} at offset {}",
+                    other, pos
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads through an image from start to end, a large piece at a time
+struct StreamReader<'a> {
+    source: &'a dyn ImageSource,
+    buf: Vec<u8>,
+    buf_offset: u64,
+}
+
+impl StreamReader<'_> {
+    async fn read(
+        &mut self,
+        offset: u64,
+        len: u64,
+    ) -> Result<&[u8], ConversionError> {
+        let buf_end = self.buf_offset + self.buf.len() as u64;
+        if offset < self.buf_offset || offset.saturating_add(len) > buf_end {
+            let read_len = match self.source.size() {
+                Some(size) => std::cmp::max(
+                    len,
+                    std::cmp::min(
+                        STREAM_READ_SIZE,
+                        size.saturating_sub(offset),
+                    ),
+                ),
+                None => len,
+            };
+            self.buf = self.source.read_at(offset, read_len).await?;
+            self.buf_offset = offset;
+        }
+        let start = (offset - self.buf_offset) as usize;
+        Ok(&self.buf[start..start + len as usize])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::convert_image;
+    use super::super::test::pattern;
+    use super::super::ConversionError;
+    use super::super::ImageFormat;
+    use super::MAGIC;
+    use std::io::Write;
+
+    const SECTOR: usize = 512;
+    const GRAIN: usize = 8 * SECTOR;
+
+    fn header(
+        flags: u32,
+        capacity: u64,
+        gd_offset: u64,
+        overhead: u64,
+    ) -> Vec<u8> {
+        let mut header = vec![0u8; SECTOR];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&3u32.to_le_bytes());
+        header[8..12].copy_from_slice(&flags.to_le_bytes());
+        header[12..20].copy_from_slice(&capacity.to_le_bytes());
+        header[20..28].copy_from_slice(&8u64.to_le_bytes());
+        header[44..48].copy_from_slice(&512u32.to_le_bytes());
+        header[56..64].copy_from_slice(&gd_offset.to_le_bytes());
+        header[64..72].copy_from_slice(&overhead.to_le_bytes());
+        header[73..77].copy_from_slice(b"\n \r\n");
+        header[77..79].copy_from_slice(&1u16.to_le_bytes());
+        header
+    }
+
+    #[tokio::test]
+    async fn test_vmdk_sparse() {
+        // The header, the grain directory at sector 1, a grain table at
+        // sectors 2-5, then grains from sector 8
+        let expected = pattern(8 * GRAIN, &[(0, GRAIN), (3 * GRAIN, GRAIN)]);
+        let mut image = header(1, 64, 1, 8);
+        image.resize(24 * SECTOR, 0);
+        image[SECTOR..SECTOR + 4].copy_from_slice(&2u32.to_le_bytes());
+        let mut set_gte = |grain: usize, sector: u32| {
+            let at = 2 * SECTOR + grain * 4;
+            image[at..at + 4].copy_from_slice(&sector.to_le_bytes());
+        };
+        set_gte(0, 8);
+        set_gte(3, 16);
+        set_gte(5, 1);
+        image[8 * SECTOR..16 * SECTOR].copy_from_slice(&expected[..GRAIN]);
+        image[16 * SECTOR..].copy_from_slice(&expected[3 * GRAIN..4 * GRAIN]);
+
+        let data = convert_image(image, ImageFormat::Vmdk).await.unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn test_vmdk_stream_optimized() {
+        let expected =
+            pattern(8 * GRAIN, &[(GRAIN, GRAIN), (6 * GRAIN, GRAIN)]);
+        let flags = 1 | (1 << 16) | (1 << 17);
+        let mut image = header(flags, 64, u64::MAX, 1);
+
+        // Grains are written out of order, as nothing requires otherwise.
+        for grain in [6usize, 1] {
+            let mut encoder = flate2::write::ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            );
+            encoder
+                .write_all(&expected[grain * GRAIN..(grain + 1) * GRAIN])
+                .unwrap();
+            let compressed = encoder.finish().unwrap();
+            image.extend_from_slice(&(grain as u64 * 8).to_le_bytes());
+            image.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            image.extend_from_slice(&compressed);
+            image.resize(
+                super::super::round_up(image.len() as u64, 512) as usize,
+                0,
+            );
+        }
+
+        // A grain table marker and its table, which are skipped over
+        let mut marker = vec![0u8; SECTOR];
+        marker[..8].copy_from_slice(&4u64.to_le_bytes());
+        marker[12..16].copy_from_slice(&super::MARKER_GT.to_le_bytes());
+        image.extend_from_slice(&marker);
+        image.resize(image.len() + 4 * SECTOR, 0xff);
+
+        // End of stream
+        image.extend_from_slice(&[0u8; SECTOR]);
+
+        let data = convert_image(image, ImageFormat::Vmdk).await.unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn test_vmdk_unsupported() {
+        let mut descriptor = b"# Disk DescriptorFile\nversion=1\n".to_vec();
+        descriptor.resize(SECTOR, 0);
+        let error =
+            convert_image(descriptor, ImageFormat::Vmdk).await.unwrap_err();
+        assert!(matches!(error, ConversionError::Unsupported { .. }));
+
+        // A delta image, with a parent named in its embedded descriptor
+        let mut image = header(1, 64, 4, 8);
+        image[28..36].copy_from_slice(&1u64.to_le_bytes());
+        image[36..44].copy_from_slice(&1u64.to_le_bytes());
+        image.resize(8 * SECTOR, 0);
+        let text = b"parentFileNameHint=\"base.vmdk\"\n";
+        image[SECTOR..SECTOR + text.len()].copy_from_slice(text);
+        let error = convert_image(image, ImageFormat::Vmdk).await.unwrap_err();
+        assert!(error.to_string().contains("parent"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_vmdk_malformed() {
+        // A grain directory offset that overflows when made a byte offset
+        let mut image = header(1, 64, u64::MAX / 2, 8);
+        image.resize(8 * SECTOR, 0);
+        let error = convert_image(image, ImageFormat::Vmdk).await.unwrap_err();
+        assert!(matches!(error, ConversionError::Invalid { .. }));
+        assert!(error.to_string().contains("out of range"), "{}", error);
+
+        // A capacity needing a grain directory far larger than the image
+        let mut image = header(1, u64::MAX / 512, 1, 8);
+        image.resize(8 * SECTOR, 0);
+        let error = convert_image(image, ImageFormat::Vmdk).await.unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+
+        // A grain table past the end of the image
+        let mut image = header(1, 64, 1, 8);
+        image.resize(8 * SECTOR, 0);
+        image[SECTOR..SECTOR + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = convert_image(image, ImageFormat::Vmdk).await.unwrap_err();
+        assert!(error.to_string().contains("past the end"), "{}", error);
+
+        // A stream optimized grain claiming 4 GiB of compressed data
+        let flags = 1 | (1 << 16) | (1 << 17);
+        let mut image = header(flags, 64, u64::MAX, 1);
+        image.extend_from_slice(&0u64.to_le_bytes());
+        image.extend_from_slice(&u32::MAX.to_le_bytes());
+        image.resize(4 * SECTOR, 0);
+        let error = convert_image(image, ImageFormat::Vmdk).await.unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+
+        // A metadata marker whose size overflows
+        let mut image = header(flags, 64, u64::MAX, 1);
+        let mut marker = vec![0u8; SECTOR];
+        marker[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        marker[12..16].copy_from_slice(&super::MARKER_GT.to_le_bytes());
+        image.extend_from_slice(&marker);
+        let error = convert_image(image, ImageFormat::Vmdk).await.unwrap_err();
+        assert!(error.to_string().contains("out of range"), "{}", error);
+    }
+}
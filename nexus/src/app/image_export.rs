@@ -0,0 +1,677 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Exporting the blocks of disks and snapshots through a Crucible Pantry
+
+use crate::authz;
+use crate::db::lookup;
+use crate::db::model::SnapshotState;
+use hyper::body::Bytes;
+use hyper::Body;
+use internal_dns::ServiceName;
+use nexus_db_queries::context::OpContext;
+use nexus_types::external_api::params::ImageExportFormat;
+use nexus_types::identity::Resource;
+use omicron_common::api::external::Digest;
+use omicron_common::api::external::DiskState;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::LookupResult;
+use std::net::SocketAddrV6;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// How many bytes are read from the Pantry at a time. This must be a multiple
+/// of the qcow2 cluster size.
+const EXPORT_READ_SIZE: u64 = 4 * 1024 * 1024;
+
+/// qcow2 images are written with 64 KiB clusters, qemu-img's default
+const QCOW2_CLUSTER_BITS: u32 = 16;
+const QCOW2_CLUSTER_SIZE: u64 = 1 << QCOW2_CLUSTER_BITS;
+
+fn div_ceil(n: u64, d: u64) -> u64 {
+    (n + d - 1) / d
+}
+
+/// An image being streamed out of a Pantry
+pub struct ExportedImage {
+    /// Suggested file name for the image
+    pub filename: String,
+    /// Size of the image in bytes, if it's known before the image is sent. A
+    /// qcow2 image's size depends on which of the volume's clusters hold any
+    /// data, which isn't known until they've been read.
+    pub size: Option<u64>,
+    pub body: Body,
+}
+
+/// A volume attached to a Pantry, read from in order to export it
+struct PantryVolume {
+    log: slog::Logger,
+    client: crucible_pantry_client::Client,
+    /// The id the volume was attached to the Pantry with
+    attach_id: String,
+    size: u64,
+    /// Whether the volume was attached only for this export, and should be
+    /// detached when it's done
+    detach_when_done: bool,
+}
+
+impl PantryVolume {
+    async fn read(&self, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let request = crucible_pantry_client::types::BulkReadRequest {
+            offset,
+            size: size as usize,
+        };
+
+        let response = self
+            .client
+            .bulk_read(&self.attach_id, &request)
+            .await
+            .map_err(|e| {
+                Error::internal_error(&format!(
+                    "error sending bulk read to pantry: {}",
+                    e,
+                ))
+            })?
+            .into_inner();
+
+        base64::Engine::decode(
+            &base64::engine::general_purpose::STANDARD,
+            &response.base64_encoded_data,
+        )
+        .map_err(|e| {
+            Error::internal_error(&format!(
+                "error base64 decoding data from pantry: {}",
+                e
+            ))
+        })
+    }
+
+    async fn detach(&self) {
+        if let Err(e) = self.client.detach(&self.attach_id).await {
+            warn!(
+                self.log,
+                "failed to detach exported volume from pantry";
+                "attach_id" => &self.attach_id,
+                "error" => %e,
+            );
+        }
+    }
+
+    /// Reads the whole volume, one chunk at a time
+    async fn for_each_chunk<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(Vec<u8>) -> Result<(), Error>,
+    {
+        let mut offset = 0;
+        while offset < self.size {
+            let size = std::cmp::min(EXPORT_READ_SIZE, self.size - offset);
+            f(self.read(offset, size).await?)?;
+            offset += size;
+        }
+        Ok(())
+    }
+}
+
+/// Find the SHA-256 digest of the raw contents of a volume
+async fn volume_digest(volume: &PantryVolume) -> Result<Digest, Error> {
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    volume
+        .for_each_chunk(|data| {
+            context.update(&data);
+            Ok(())
+        })
+        .await?;
+    Ok(Digest::Sha256(hex::encode(context.finish())))
+}
+
+/// Find which qcow2 clusters of a volume have any nonzero bytes in them
+async fn volume_allocation(volume: &PantryVolume) -> Result<Vec<bool>, Error> {
+    let mut allocated =
+        Vec::with_capacity(div_ceil(volume.size, QCOW2_CLUSTER_SIZE) as usize);
+    volume
+        .for_each_chunk(|data| {
+            allocated.extend(
+                data.chunks(QCOW2_CLUSTER_SIZE as usize)
+                    .map(|cluster| cluster.iter().any(|b| *b != 0)),
+            );
+            Ok(())
+        })
+        .await?;
+    Ok(allocated)
+}
+
+/// Where everything goes in a sparse qcow2 (version 2) image. The image is
+/// laid out as the header, the L1 table, the refcount table, the refcount
+/// blocks, each allocated cluster in guest order, and then the L2 tables. With
+/// the L2 tables at the end, the offset of each data cluster is found as it's
+/// sent rather than being kept for every cluster of the volume.
+#[derive(Debug)]
+struct Qcow2Layout {
+    size: u64,
+    l1_offset: u64,
+    l1_size: u64,
+    refcount_table_offset: u64,
+    refcount_table_clusters: u64,
+    refcount_blocks_offset: u64,
+    refcount_blocks: u64,
+    /// Image offset of the first data cluster
+    data_offset: u64,
+    /// For each guest cluster, whether it's allocated in the image
+    allocated: Vec<bool>,
+    /// Image offset of the L2 table for each L1 entry, if it has one
+    l2_offsets: Vec<Option<u64>>,
+    total_clusters: u64,
+}
+
+impl Qcow2Layout {
+    /// Entries in each L1, L2 or refcount table cluster
+    const TABLE_ENTRIES: u64 = QCOW2_CLUSTER_SIZE / 8;
+    /// Entries in each refcount block, with 16 bit refcounts
+    const REFCOUNT_ENTRIES: u64 = QCOW2_CLUSTER_SIZE / 2;
+
+    /// Every cluster in the image is referenced exactly once, so the "copied"
+    /// flag can be set on all of the L1 and L2 entries.
+    const COPIED: u64 = 1 << 63;
+
+    fn new(size: u64, allocated: Vec<bool>) -> Self {
+        let clusters_for = |bytes: u64| div_ceil(bytes, QCOW2_CLUSTER_SIZE);
+
+        let l1_size = div_ceil(allocated.len() as u64, Self::TABLE_ENTRIES);
+        let l1_clusters = clusters_for(l1_size * 8);
+        let l2_tables = allocated
+            .chunks(Self::TABLE_ENTRIES as usize)
+            .filter(|l2| l2.iter().any(|a| *a))
+            .count() as u64;
+        let data_clusters = allocated.iter().filter(|a| **a).count() as u64;
+
+        // The refcount blocks have to account for themselves and the refcount
+        // table, so grow them until they cover every cluster in the image.
+        let fixed_clusters = 1 + l1_clusters + l2_tables + data_clusters;
+        let mut refcount_blocks = 0;
+        let mut refcount_table_clusters = 0;
+        loop {
+            let total =
+                fixed_clusters + refcount_table_clusters + refcount_blocks;
+            let blocks = div_ceil(total, Self::REFCOUNT_ENTRIES);
+            let table_clusters = clusters_for(blocks * 8);
+            if blocks == refcount_blocks
+                && table_clusters == refcount_table_clusters
+            {
+                break;
+            }
+            refcount_blocks = blocks;
+            refcount_table_clusters = table_clusters;
+        }
+
+        let l1_offset = QCOW2_CLUSTER_SIZE;
+        let refcount_table_offset =
+            l1_offset + l1_clusters * QCOW2_CLUSTER_SIZE;
+        let refcount_blocks_offset = refcount_table_offset
+            + refcount_table_clusters * QCOW2_CLUSTER_SIZE;
+        let data_offset =
+            refcount_blocks_offset + refcount_blocks * QCOW2_CLUSTER_SIZE;
+
+        let mut next_offset = data_offset + data_clusters * QCOW2_CLUSTER_SIZE;
+        let l2_offsets = allocated
+            .chunks(Self::TABLE_ENTRIES as usize)
+            .map(|l2| {
+                l2.iter().any(|a| *a).then(|| {
+                    let offset = next_offset;
+                    next_offset += QCOW2_CLUSTER_SIZE;
+                    offset
+                })
+            })
+            .collect();
+
+        Qcow2Layout {
+            size,
+            l1_offset,
+            l1_size,
+            refcount_table_offset,
+            refcount_table_clusters,
+            refcount_blocks_offset,
+            refcount_blocks,
+            data_offset,
+            allocated,
+            l2_offsets,
+            total_clusters: next_offset / QCOW2_CLUSTER_SIZE,
+        }
+    }
+
+    fn image_size(&self) -> u64 {
+        self.total_clusters * QCOW2_CLUSTER_SIZE
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(QCOW2_CLUSTER_SIZE as usize);
+        header.extend_from_slice(b"QFI\xfb");
+        header.extend_from_slice(&2u32.to_be_bytes()); // version
+        header.extend_from_slice(&0u64.to_be_bytes()); // backing_file_offset
+        header.extend_from_slice(&0u32.to_be_bytes()); // backing_file_size
+        header.extend_from_slice(&QCOW2_CLUSTER_BITS.to_be_bytes());
+        header.extend_from_slice(&self.size.to_be_bytes());
+        header.extend_from_slice(&0u32.to_be_bytes()); // crypt_method
+        header.extend_from_slice(&(self.l1_size as u32).to_be_bytes());
+        header.extend_from_slice(&self.l1_offset.to_be_bytes());
+        header.extend_from_slice(&self.refcount_table_offset.to_be_bytes());
+        header.extend_from_slice(
+            &(self.refcount_table_clusters as u32).to_be_bytes(),
+        );
+        header.extend_from_slice(&0u32.to_be_bytes()); // nb_snapshots
+        header.extend_from_slice(&0u64.to_be_bytes()); // snapshots_offset
+        header.resize(QCOW2_CLUSTER_SIZE as usize, 0);
+        header
+    }
+
+    /// Builds the `index`th cluster of a table of 64 bit entries
+    fn table_cluster(
+        index: u64,
+        entries: u64,
+        entry: impl Fn(u64) -> u64,
+    ) -> Vec<u8> {
+        let mut cluster = Vec::with_capacity(QCOW2_CLUSTER_SIZE as usize);
+        let start = index * Self::TABLE_ENTRIES;
+        let end = std::cmp::min(start + Self::TABLE_ENTRIES, entries);
+        for i in start..end {
+            cluster.extend_from_slice(&entry(i).to_be_bytes());
+        }
+        cluster.resize(QCOW2_CLUSTER_SIZE as usize, 0);
+        cluster
+    }
+
+    /// Builds the `index`th cluster of the image, which must come before the
+    /// first data cluster.
+    fn metadata_cluster(&self, index: u64) -> Vec<u8> {
+        let offset = index * QCOW2_CLUSTER_SIZE;
+        assert!(offset < self.data_offset);
+        if offset < self.l1_offset {
+            self.header()
+        } else if offset < self.refcount_table_offset {
+            Self::table_cluster(
+                (offset - self.l1_offset) / QCOW2_CLUSTER_SIZE,
+                self.l1_size,
+                |i| self.l2_offsets[i as usize].map_or(0, |o| o | Self::COPIED),
+            )
+        } else if offset < self.refcount_blocks_offset {
+            Self::table_cluster(
+                (offset - self.refcount_table_offset) / QCOW2_CLUSTER_SIZE,
+                self.refcount_blocks,
+                |i| self.refcount_blocks_offset + i * QCOW2_CLUSTER_SIZE,
+            )
+        } else {
+            let block =
+                (offset - self.refcount_blocks_offset) / QCOW2_CLUSTER_SIZE;
+            let mut cluster = Vec::with_capacity(QCOW2_CLUSTER_SIZE as usize);
+            let start = block * Self::REFCOUNT_ENTRIES;
+            let end = std::cmp::min(
+                start + Self::REFCOUNT_ENTRIES,
+                self.total_clusters,
+            );
+            for _ in start..end {
+                cluster.extend_from_slice(&1u16.to_be_bytes());
+            }
+            cluster.resize(QCOW2_CLUSTER_SIZE as usize, 0);
+            cluster
+        }
+    }
+
+    /// Builds the L2 tables, in the order they're laid out after the data
+    /// clusters.
+    fn l2_tables(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let mut next_data_offset = self.data_offset;
+        self.allocated
+            .chunks(Self::TABLE_ENTRIES as usize)
+            .filter(|l2| l2.iter().any(|a| *a))
+            .map(move |l2| {
+                let mut table = Vec::with_capacity(QCOW2_CLUSTER_SIZE as usize);
+                for allocated in l2 {
+                    let entry = if *allocated {
+                        let offset = next_data_offset;
+                        next_data_offset += QCOW2_CLUSTER_SIZE;
+                        offset | Self::COPIED
+                    } else {
+                        0
+                    };
+                    table.extend_from_slice(&entry.to_be_bytes());
+                }
+                table.resize(QCOW2_CLUSTER_SIZE as usize, 0);
+                table
+            })
+    }
+}
+
+async fn stream_raw(
+    volume: &PantryVolume,
+    sender: &mut hyper::body::Sender,
+) -> Result<(), Error> {
+    let mut offset = 0;
+    while offset < volume.size {
+        let size = std::cmp::min(EXPORT_READ_SIZE, volume.size - offset);
+        let data = volume.read(offset, size).await?;
+        send(sender, data).await?;
+        offset += size;
+    }
+    Ok(())
+}
+
+async fn stream_qcow2(
+    volume: &PantryVolume,
+    sender: &mut hyper::body::Sender,
+) -> Result<(), Error> {
+    // The tables at the start of the image depend on how many clusters are
+    // allocated, so that has to be found before anything is sent.
+    let layout =
+        Qcow2Layout::new(volume.size, volume_allocation(volume).await?);
+
+    for index in 0..layout.data_offset / QCOW2_CLUSTER_SIZE {
+        send(sender, layout.metadata_cluster(index)).await?;
+    }
+
+    // Data clusters are written in guest order, so only the allocated ones
+    // need to be sent as the volume is read through again.
+    let mut offset = 0;
+    while offset < volume.size {
+        let size = std::cmp::min(EXPORT_READ_SIZE, volume.size - offset);
+        let first_cluster = (offset / QCOW2_CLUSTER_SIZE) as usize;
+        let allocated = &layout.allocated[first_cluster
+            ..first_cluster + div_ceil(size, QCOW2_CLUSTER_SIZE) as usize];
+        if allocated.iter().any(|a| *a) {
+            let data = volume.read(offset, size).await?;
+            for (cluster, allocated) in
+                data.chunks(QCOW2_CLUSTER_SIZE as usize).zip(allocated)
+            {
+                if *allocated {
+                    let mut cluster = cluster.to_vec();
+                    cluster.resize(QCOW2_CLUSTER_SIZE as usize, 0);
+                    send(sender, cluster).await?;
+                }
+            }
+        }
+        offset += size;
+    }
+
+    for table in layout.l2_tables() {
+        send(sender, table).await?;
+    }
+    Ok(())
+}
+
+async fn send(
+    sender: &mut hyper::body::Sender,
+    data: Vec<u8>,
+) -> Result<(), Error> {
+    sender
+        .send_data(Bytes::from(data))
+        .await
+        .map_err(|e| Error::unavail(&format!("export client went away: {}", e)))
+}
+
+impl super::Nexus {
+    /// Stream the blocks of a disk in state Exporting out of the Pantry it is
+    /// attached to
+    pub async fn disk_export(
+        self: &Arc<Self>,
+        disk_lookup: &lookup::Disk<'_>,
+        format: ImageExportFormat,
+    ) -> LookupResult<ExportedImage> {
+        let (volume, name) = self.disk_export_volume(disk_lookup).await?;
+        Ok(image_export(volume, name, format))
+    }
+
+    /// Find the SHA-256 digest of the raw contents of a disk in state
+    /// Exporting. This matches what an import from a URL expects, whatever
+    /// the format the disk is exported in.
+    pub async fn disk_export_digest(
+        self: &Arc<Self>,
+        disk_lookup: &lookup::Disk<'_>,
+    ) -> LookupResult<Digest> {
+        let (volume, _) = self.disk_export_volume(disk_lookup).await?;
+        volume_digest(&volume).await
+    }
+
+    async fn disk_export_volume(
+        &self,
+        disk_lookup: &lookup::Disk<'_>,
+    ) -> LookupResult<(PantryVolume, String)> {
+        let (.., db_disk) = disk_lookup.fetch_for(authz::Action::Read).await?;
+
+        let disk_state: DiskState = db_disk.state().into();
+        if disk_state != DiskState::Exporting {
+            return Err(Error::invalid_request(&format!(
+                "cannot export blocks of disk in state {:?}",
+                disk_state,
+            )));
+        }
+
+        let Some(pantry_address) = db_disk.pantry_address() else {
+            error!(self.log, "disk {} has no pantry address!", db_disk.id());
+            return Err(Error::internal_error(&format!(
+                "disk {} has no pantry address!",
+                db_disk.id(),
+            )));
+        };
+
+        let volume = PantryVolume {
+            log: self.log.new(o!("disk_id" => db_disk.id().to_string())),
+            client: pantry_client(pantry_address),
+            attach_id: db_disk.id().to_string(),
+            size: db_disk.size.to_bytes(),
+            detach_when_done: false,
+        };
+
+        Ok((volume, db_disk.name().to_string()))
+    }
+
+    /// Stream the blocks of a snapshot out of a Pantry. Unlike disks, which
+    /// are explicitly attached to a Pantry for the duration of an export,
+    /// snapshots are attached here and detached when the export is done.
+    pub async fn snapshot_export(
+        self: &Arc<Self>,
+        opctx: &OpContext,
+        snapshot_lookup: &lookup::Snapshot<'_>,
+        format: ImageExportFormat,
+    ) -> LookupResult<ExportedImage> {
+        let (volume, name) =
+            self.snapshot_export_volume(opctx, snapshot_lookup).await?;
+        Ok(image_export(volume, name, format))
+    }
+
+    /// Find the SHA-256 digest of the raw contents of a snapshot. This matches
+    /// what an import from a URL expects, whatever the format the snapshot is
+    /// exported in.
+    pub async fn snapshot_export_digest(
+        self: &Arc<Self>,
+        opctx: &OpContext,
+        snapshot_lookup: &lookup::Snapshot<'_>,
+    ) -> LookupResult<Digest> {
+        let (volume, _) =
+            self.snapshot_export_volume(opctx, snapshot_lookup).await?;
+        let result = volume_digest(&volume).await;
+        volume.detach().await;
+        result
+    }
+
+    /// Attach a snapshot's volume to a Pantry so that it can be read
+    async fn snapshot_export_volume(
+        &self,
+        opctx: &OpContext,
+        snapshot_lookup: &lookup::Snapshot<'_>,
+    ) -> LookupResult<(PantryVolume, String)> {
+        let (.., db_snapshot) =
+            snapshot_lookup.fetch_for(authz::Action::Read).await?;
+
+        if db_snapshot.state != SnapshotState::Ready {
+            return Err(Error::invalid_request(&format!(
+                "cannot export snapshot in state {:?}",
+                db_snapshot.state,
+            )));
+        }
+
+        let volume =
+            self.db_datastore.volume_checkout(db_snapshot.volume_id).await?;
+        let volume_construction_request: crucible_pantry_client::types::VolumeConstructionRequest =
+            serde_json::from_str(volume.data()).map_err(|e| {
+                Error::internal_error(&format!(
+                    "failed to deserialize snapshot {} volume data: {}",
+                    db_snapshot.id(),
+                    e,
+                ))
+            })?;
+
+        let pantry_address = self
+            .resolver()
+            .await
+            .lookup_socket_v6(ServiceName::CruciblePantry)
+            .await
+            .map_err(|e| {
+                Error::unavail(&format!("no pantry available: {}", e))
+            })?;
+
+        // Attach with a fresh id, so that concurrent exports of the same
+        // snapshot don't collide in the Pantry.
+        let attach_id = Uuid::new_v4().to_string();
+        let client = pantry_client(pantry_address);
+
+        info!(
+            opctx.log,
+            "attaching snapshot {} volume {} to pantry {} for export",
+            db_snapshot.id(),
+            db_snapshot.volume_id,
+            pantry_address;
+            "attach_id" => &attach_id,
+        );
+
+        client
+            .attach(
+                &attach_id,
+                &crucible_pantry_client::types::AttachRequest {
+                    volume_construction_request,
+                },
+            )
+            .await
+            .map_err(|e| {
+                Error::internal_error(&format!(
+                    "error attaching snapshot to pantry: {}",
+                    e
+                ))
+            })?;
+
+        // If Nexus goes away before the export is done, nothing will detach
+        // the volume from the Pantry, but a Pantry restart will clear it.
+        let volume = PantryVolume {
+            log: self
+                .log
+                .new(o!("snapshot_id" => db_snapshot.id().to_string())),
+            client,
+            attach_id,
+            size: db_snapshot.size.to_bytes(),
+            detach_when_done: true,
+        };
+
+        Ok((volume, db_snapshot.name().to_string()))
+    }
+}
+
+/// Stream an image of a volume. Nothing is read from the volume until the
+/// response has been sent, and the raw contents are read only once; a qcow2
+/// image also needs a pass to find which clusters hold data before its tables
+/// can be sent.
+fn image_export(
+    volume: PantryVolume,
+    name: String,
+    format: ImageExportFormat,
+) -> ExportedImage {
+    let (filename, size) = match format {
+        ImageExportFormat::Raw => (format!("{}.raw", name), Some(volume.size)),
+        ImageExportFormat::Qcow2 => (format!("{}.qcow2", name), None),
+    };
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let result = match format {
+            ImageExportFormat::Raw => stream_raw(&volume, &mut sender).await,
+            ImageExportFormat::Qcow2 => {
+                stream_qcow2(&volume, &mut sender).await
+            }
+        };
+
+        if let Err(e) = result {
+            warn!(volume.log, "image export failed"; "error" => %e);
+            sender.abort();
+        }
+
+        if volume.detach_when_done {
+            volume.detach().await;
+        }
+    });
+
+    ExportedImage { filename, size, body }
+}
+
+fn pantry_client(address: SocketAddrV6) -> crucible_pantry_client::Client {
+    crucible_pantry_client::Client::new(&format!("http://{}", address))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Qcow2Layout;
+    use super::QCOW2_CLUSTER_SIZE;
+
+    #[test]
+    fn test_qcow2_layout_empty() {
+        let size = 1 << 30;
+        let allocated = vec![false; (size / QCOW2_CLUSTER_SIZE) as usize];
+        let layout = Qcow2Layout::new(size, allocated);
+
+        // header, L1, refcount table, refcount block
+        assert_eq!(layout.l1_size, 2);
+        assert_eq!(layout.data_offset, 4 * QCOW2_CLUSTER_SIZE);
+        assert_eq!(layout.total_clusters, 4);
+        assert!(layout.l2_offsets.iter().all(|o| o.is_none()));
+        assert_eq!(layout.l2_tables().count(), 0);
+
+        let header = layout.metadata_cluster(0);
+        assert_eq!(&header[0..4], b"QFI\xfb");
+        assert_eq!(&header[24..32], &size.to_be_bytes());
+    }
+
+    #[test]
+    fn test_qcow2_layout_sparse() {
+        let size = 1 << 30;
+        let mut allocated = vec![false; (size / QCOW2_CLUSTER_SIZE) as usize];
+        allocated[0] = true;
+        allocated[8192] = true;
+        allocated[8193] = true;
+        let layout = Qcow2Layout::new(size, allocated);
+
+        // header, L1, refcount table, refcount block, three data clusters,
+        // and two L2 tables
+        assert_eq!(layout.data_offset, 4 * QCOW2_CLUSTER_SIZE);
+        assert_eq!(layout.total_clusters, 9);
+        assert_eq!(
+            layout.l2_offsets[..2],
+            [Some(7 * QCOW2_CLUSTER_SIZE), Some(8 * QCOW2_CLUSTER_SIZE)]
+        );
+
+        // The second L1 entry points at the second L2 table, which points at
+        // the last two data clusters.
+        let l1 = layout.metadata_cluster(1);
+        let entry = u64::from_be_bytes(l1[8..16].try_into().unwrap());
+        assert_eq!(entry & !(1 << 63), 8 * QCOW2_CLUSTER_SIZE);
+
+        let l2_tables = layout.l2_tables().collect::<Vec<_>>();
+        assert_eq!(l2_tables.len(), 2);
+        let entry = u64::from_be_bytes(l2_tables[0][0..8].try_into().unwrap());
+        assert_eq!(entry & !(1 << 63), 4 * QCOW2_CLUSTER_SIZE);
+        let entry = u64::from_be_bytes(l2_tables[0][8..16].try_into().unwrap());
+        assert_eq!(entry, 0);
+        let entry = u64::from_be_bytes(l2_tables[1][8..16].try_into().unwrap());
+        assert_eq!(entry & !(1 << 63), 6 * QCOW2_CLUSTER_SIZE);
+
+        // Every cluster in the image has a refcount of one.
+        let refcounts = layout.metadata_cluster(3);
+        assert_eq!(&refcounts[0..18], &[0, 1].repeat(9)[..]);
+        assert_eq!(refcounts[18], 0);
+    }
+}
@@ -29,9 +29,12 @@ use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
 use omicron_common::api::external::InstanceState;
 use omicron_common::api::external::InternalContext;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::LookupType;
 use omicron_common::api::external::NameOrId;
+use omicron_common::api::external::ResourceType;
 use omicron_common::api::external::UpdateResult;
 use omicron_common::api::external::Vni;
 use omicron_common::api::internal::nexus;
@@ -53,6 +56,34 @@ use uuid::Uuid;
 
 const MAX_KEYS_PER_INSTANCE: u32 = 8;
 
+fn validate_instance_memory(memory: &ByteCount) -> Result<(), Error> {
+    // Reject instances where the memory is not at least
+    // MIN_MEMORY_SIZE_BYTES
+    if memory.to_bytes() < params::MIN_MEMORY_SIZE_BYTES as u64 {
+        return Err(Error::InvalidValue {
+            label: String::from("size"),
+            message: format!(
+                "memory must be at least {}",
+                ByteCount::from(params::MIN_MEMORY_SIZE_BYTES)
+            ),
+        });
+    }
+
+    // Reject instances where the memory is not divisible by
+    // MIN_MEMORY_SIZE_BYTES
+    if (memory.to_bytes() % params::MIN_MEMORY_SIZE_BYTES as u64) != 0 {
+        return Err(Error::InvalidValue {
+            label: String::from("size"),
+            message: format!(
+                "memory must be divisible by {}",
+                ByteCount::from(params::MIN_MEMORY_SIZE_BYTES)
+            ),
+        });
+    }
+
+    Ok(())
+}
+
 pub(crate) enum WriteBackUpdatedInstance {
     WriteBack,
     Drop,
@@ -146,31 +177,7 @@ impl super::Nexus {
             }
         }
 
-        // Reject instances where the memory is not at least
-        // MIN_MEMORY_SIZE_BYTES
-        if params.memory.to_bytes() < params::MIN_MEMORY_SIZE_BYTES as u64 {
-            return Err(Error::InvalidValue {
-                label: String::from("size"),
-                message: format!(
-                    "memory must be at least {}",
-                    ByteCount::from(params::MIN_MEMORY_SIZE_BYTES)
-                ),
-            });
-        }
-
-        // Reject instances where the memory is not divisible by
-        // MIN_MEMORY_SIZE_BYTES
-        if (params.memory.to_bytes() % params::MIN_MEMORY_SIZE_BYTES as u64)
-            != 0
-        {
-            return Err(Error::InvalidValue {
-                label: String::from("size"),
-                message: format!(
-                    "memory must be divisible by {}",
-                    ByteCount::from(params::MIN_MEMORY_SIZE_BYTES)
-                ),
-            });
-        }
+        validate_instance_memory(&params.memory)?;
 
         let saga_params = sagas::instance_create::Params {
             serialized_authn: authn::saga::Serialized::for_opctx(opctx),
@@ -236,10 +243,13 @@ impl super::Nexus {
         opctx: &OpContext,
         project_lookup: &lookup::Project<'_>,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<db::model::Instance> {
         let (.., authz_project) =
             project_lookup.lookup_for(authz::Action::ListChildren).await?;
-        self.db_datastore.instance_list(opctx, &authz_project, pagparams).await
+        self.db_datastore
+            .instance_list(opctx, &authz_project, pagparams, label_selector)
+            .await
     }
 
     // This operation may only occur on stopped instances, which implies that
@@ -413,6 +423,68 @@ impl super::Nexus {
         Ok(())
     }
 
+    /// Change the CPU count, memory, or hostname of a stopped instance.
+    ///
+    /// The new configuration takes effect the next time the instance starts.
+    pub async fn instance_update(
+        &self,
+        opctx: &OpContext,
+        instance_lookup: &lookup::Instance<'_>,
+        params: &params::InstanceUpdate,
+    ) -> UpdateResult<db::model::Instance> {
+        let (.., authz_instance, mut db_instance) =
+            instance_lookup.fetch_for(authz::Action::Modify).await?;
+
+        // Labels may be changed at any time, but the rest of the instance's
+        // configuration may only be changed while it's stopped.
+        let reconfigure = params.ncpus.is_some()
+            || params.memory.is_some()
+            || params.hostname.is_some();
+        if reconfigure {
+            let instance_state = db_instance.runtime().state.state();
+            if instance_state != &InstanceState::Stopped {
+                return Err(Error::invalid_request(&format!(
+                    "instance must be stopped to be updated (currently \"{}\")",
+                    instance_state,
+                )));
+            }
+
+            let runtime = db_instance.runtime();
+            let ncpus = params.ncpus.unwrap_or(runtime.ncpus.0);
+            let memory = params.memory.unwrap_or(runtime.memory.0);
+            let hostname = params
+                .hostname
+                .clone()
+                .unwrap_or_else(|| runtime.hostname.clone());
+            validate_instance_memory(&memory)?;
+
+            db_instance = self
+                .db_datastore
+                .instance_reconfigure(
+                    opctx,
+                    &authz_instance,
+                    &db_instance,
+                    ncpus.into(),
+                    memory.into(),
+                    hostname,
+                )
+                .await?;
+        }
+
+        if let Some(labels) = &params.labels {
+            db_instance = self
+                .db_datastore
+                .instance_update_labels(
+                    opctx,
+                    &authz_instance,
+                    labels.clone().into(),
+                )
+                .await?;
+        }
+
+        Ok(db_instance)
+    }
+
     /// Reboot the specified instance.
     pub async fn instance_reboot(
         &self,
@@ -441,6 +513,10 @@ impl super::Nexus {
         // between registration and instance start).
         let (.., authz_instance, mut db_instance) =
             instance_lookup.fetch().await?;
+        let stopped = matches!(
+            db_instance.runtime().state.state(),
+            InstanceState::Stopped
+        );
 
         // The instance is not really being "created" (it already exists from
         // the caller's perspective), but if it does not exist on its sled, the
@@ -455,9 +531,78 @@ impl super::Nexus {
             ..db_instance.runtime_state
         };
         db_instance.runtime_state = initial_runtime;
+
+        // Make sure the instance's sled has room for it. This is a no-op if
+        // the instance still holds its reservation; reconfiguring a stopped
+        // instance releases it, so this is where capacity for the new
+        // configuration gets checked. A sled that's being drained or is out
+        // of service doesn't take a stopped instance back even if it does
+        // still hold a reservation there. (An instance that isn't stopped
+        // can't be moved, and stays where it is.)
+        let runtime = db_instance.runtime();
+        let sled_usable = !stopped
+            || match self.sled_lookup(&self.opctx_alloc, &runtime.sled_id).await
+            {
+                Ok(sled) => {
+                    sled.state == db::model::SledState::Active
+                        && sled.schedulable
+                }
+                Err(Error::ObjectNotFound { .. }) => false,
+                Err(error) => return Err(error),
+            };
+        let reserved = if sled_usable {
+            let reserved = self
+                .reserve_on_random_sled(
+                    runtime.propolis_id,
+                    db::model::SledResourceKind::Instance,
+                    Some(authz_instance.id()),
+                    db::model::Resources::new(
+                        runtime.ncpus.0 .0.into(),
+                        runtime.memory,
+                        ByteCount::from(0).into(),
+                    ),
+                    db::model::SledReservationConstraintBuilder::new()
+                        .must_select_from(&[runtime.sled_id])
+                        .build(),
+                )
+                .await;
+            match reserved {
+                Ok(_) => true,
+                Err(Error::ServiceUnavailable { .. }) if stopped => false,
+                Err(error) => return Err(error),
+            }
+        } else {
+            false
+        };
+
+        // Otherwise, start the instance on another sled instead, the same way
+        // as an automatic restart would.
+        if !reserved {
+            let old_sled_id = runtime.sled_id;
+            let Some(new_runtime) = self
+                .instance_move_to_new_sled(opctx, &authz_instance, &db_instance)
+                .await?
+            else {
+                return Err(Error::conflict(
+                    "instance changed while it was being started",
+                ));
+            };
+            info!(opctx.log, "starting instance on a new sled";
+                "old_sled_id" => %old_sled_id,
+                "sled_id" => %new_runtime.sled_id,
+                "propolis_id" => %new_runtime.propolis_id);
+            db_instance.runtime_state = nexus_db_model::InstanceRuntimeState {
+                state: nexus_db_model::InstanceState(InstanceState::Creating),
+                ..new_runtime
+            };
+        }
+
         self.instance_ensure_registered(opctx, &authz_instance, &db_instance)
             .await?;
 
+        self.db_datastore
+            .instance_set_stop_requested(opctx, &authz_instance, false)
+            .await?;
         self.instance_request_state(
             opctx,
             &authz_instance,
@@ -475,6 +620,13 @@ impl super::Nexus {
         instance_lookup: &lookup::Instance<'_>,
     ) -> UpdateResult<db::model::Instance> {
         let (.., authz_instance, db_instance) = instance_lookup.fetch().await?;
+
+        // Record that this stop was asked for before asking for it, so that
+        // an "always" auto-restart policy never sees the instance stopped
+        // without knowing why.
+        self.db_datastore
+            .instance_set_stop_requested(opctx, &authz_instance, true)
+            .await?;
         self.instance_request_state(
             opctx,
             &authz_instance,
@@ -485,6 +637,232 @@ impl super::Nexus {
         self.db_datastore.instance_refetch(opctx, &authz_instance).await
     }
 
+    /// Starts an instance again according to its auto-restart policy, on
+    /// whichever sled has room for it
+    ///
+    /// This is driven by the instance auto-restart background task.  The
+    /// instance's old sled (if it's still around) is told to forget about it,
+    /// and the instance is given a new Propolis on a sled chosen the same way
+    /// as at creation, honoring its affinity groups.  Returns `false` without
+    /// doing anything if the instance turns out not to need a restart after
+    /// all.
+    pub(crate) async fn instance_auto_restart(
+        &self,
+        instance_id: Uuid,
+    ) -> Result<bool, Error> {
+        let opctx = OpContext::for_background(
+            self.log.new(o!(
+                "component" => "InstanceAutoRestart",
+                "instance_id" => instance_id.to_string(),
+            )),
+            Arc::clone(&self.authz),
+            authn::Context::internal_api(),
+            Arc::clone(&self.db_datastore),
+        );
+        let instance_lookup = LookupPath::new(&opctx, &self.db_datastore)
+            .instance_id(instance_id);
+        let (.., authz_instance, db_instance) =
+            instance_lookup.fetch_for(authz::Action::Modify).await?;
+        if !db_instance.needs_auto_restart() {
+            return Ok(false);
+        }
+
+        let old_runtime = db_instance.runtime().clone();
+        let Some(new_runtime) = self
+            .instance_move_to_new_sled(&opctx, &authz_instance, &db_instance)
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        info!(opctx.log, "restarting instance";
+            "old_sled_id" => %old_runtime.sled_id,
+            "sled_id" => %new_runtime.sled_id,
+            "propolis_id" => %new_runtime.propolis_id);
+        self.instance_start(&opctx, &instance_lookup).await?;
+        Ok(true)
+    }
+
+    /// Moves a stopped or failed instance to a new Propolis on whichever sled
+    /// has room for it, chosen the same way as at creation and honoring its
+    /// affinity groups
+    ///
+    /// The instance's old sled (if it's still around) is told to forget about
+    /// it first.  Returns the instance's new runtime state, or `None` if the
+    /// instance changed while it was being moved, in which case it's left
+    /// alone.
+    async fn instance_move_to_new_sled(
+        &self,
+        opctx: &OpContext,
+        authz_instance: &authz::Instance,
+        db_instance: &db::model::Instance,
+    ) -> Result<Option<db::model::InstanceRuntimeState>, Error> {
+        // If the old sled is gone or has been expunged, there's nothing there
+        // to clean up.
+        let old_sled_in_service = match self
+            .sled_lookup(&self.opctx_alloc, &db_instance.runtime().sled_id)
+            .await
+        {
+            Ok(sled) => sled.state != db::model::SledState::Expunged,
+            Err(Error::ObjectNotFound { .. }) => false,
+            Err(error) => return Err(error),
+        };
+        if old_sled_in_service {
+            match self
+                .instance_ensure_unregistered(
+                    opctx,
+                    authz_instance,
+                    db_instance,
+                    WriteBackUpdatedInstance::Drop,
+                )
+                .await
+            {
+                Ok(()) | Err(Error::ObjectNotFound { .. }) => (),
+                Err(error) => return Err(error),
+            }
+        }
+
+        let old_runtime = db_instance.runtime().clone();
+        let propolis_id = Uuid::new_v4();
+        let affinity_group_ids = self
+            .db_datastore
+            .instance_affinity_group_ids(opctx, authz_instance)
+            .await?;
+        let constraints = db::model::SledReservationConstraintBuilder::new()
+            .affinity_groups(&affinity_group_ids)
+            .build();
+        let resource = self
+            .reserve_on_random_sled(
+                propolis_id,
+                db::model::SledResourceKind::Instance,
+                Some(authz_instance.id()),
+                db::model::Resources::new(
+                    old_runtime.ncpus.0 .0.into(),
+                    old_runtime.memory,
+                    ByteCount::from(0).into(),
+                ),
+                constraints,
+            )
+            .await?;
+
+        let relocated = async {
+            let propolis_ip = self
+                .db_datastore
+                .next_ipv6_address(opctx, resource.sled_id)
+                .await?;
+            let new_runtime = db::model::InstanceRuntimeState {
+                state: db::model::InstanceState::new(InstanceState::Stopped),
+                time_updated: chrono::Utc::now(),
+                gen: old_runtime.gen.next().into(),
+                sled_id: resource.sled_id,
+                propolis_id,
+                propolis_ip: Some(
+                    ipnetwork::Ipv6Network::from(propolis_ip).into(),
+                ),
+                dst_propolis_id: None,
+                migration_id: None,
+                propolis_gen: old_runtime.propolis_gen.next().into(),
+                ..old_runtime.clone()
+            };
+            let relocated = self
+                .db_datastore
+                .instance_relocate(
+                    opctx,
+                    authz_instance,
+                    &old_runtime,
+                    &new_runtime,
+                )
+                .await?;
+            Ok::<_, Error>(relocated.then_some(new_runtime))
+        }
+        .await;
+        if !matches!(relocated, Ok(Some(_))) {
+            // Either the move failed or somebody else changed the instance
+            // while we were working on it (in which case it's theirs to deal
+            // with).  Either way, the new Propolis won't be used.
+            if let Err(error) = self.delete_sled_reservation(propolis_id).await
+            {
+                warn!(opctx.log, "failed to release sled reservation";
+                    "propolis_id" => %propolis_id,
+                    "error" => ?error);
+            }
+        }
+        relocated
+    }
+
+    /// Migrates an instance off of a sled that's being drained
+    ///
+    /// Returns `Ok(false)` without doing anything if the instance isn't running
+    /// on a draining sled (any more) or is already migrating.
+    pub(crate) async fn instance_migrate_off_sled(
+        self: &Arc<Self>,
+        instance_id: Uuid,
+    ) -> Result<bool, Error> {
+        let opctx = OpContext::for_background(
+            self.log.new(o!(
+                "component" => "SledDrain",
+                "instance_id" => instance_id.to_string(),
+            )),
+            Arc::clone(&self.authz),
+            authn::Context::internal_api(),
+            Arc::clone(&self.db_datastore),
+        );
+        let instance_lookup = LookupPath::new(&opctx, &self.db_datastore)
+            .instance_id(instance_id);
+        let (.., authz_instance, db_instance) =
+            instance_lookup.fetch_for(authz::Action::Modify).await?;
+        let runtime = db_instance.runtime();
+        if runtime.state.0 != InstanceState::Running
+            || runtime.migration_id.is_some()
+        {
+            return Ok(false);
+        }
+        let src_sled = self.sled_lookup(&opctx, &runtime.sled_id).await?;
+        if src_sled.schedulable {
+            return Ok(false);
+        }
+
+        // Find a destination by reserving room for the instance on some other
+        // sled.  The migration saga makes its own reservation on the chosen
+        // sled, so this one is released before the saga runs.  If the room is
+        // taken in the meantime, the saga fails and the migration is tried
+        // again later.
+        let affinity_group_ids = self
+            .db_datastore
+            .instance_affinity_group_ids(&opctx, &authz_instance)
+            .await?;
+        let constraints = db::model::SledReservationConstraintBuilder::new()
+            .affinity_groups(&affinity_group_ids)
+            .must_not_select_from(&[runtime.sled_id])
+            .build();
+        let probe_id = Uuid::new_v4();
+        let resource = self
+            .reserve_on_random_sled(
+                probe_id,
+                db::model::SledResourceKind::Instance,
+                Some(authz_instance.id()),
+                db::model::Resources::new(
+                    runtime.ncpus.0 .0.into(),
+                    runtime.memory,
+                    ByteCount::from(0).into(),
+                ),
+                constraints,
+            )
+            .await?;
+        self.delete_sled_reservation(probe_id).await?;
+
+        info!(opctx.log, "migrating instance off of draining sled";
+            "src_sled_id" => %runtime.sled_id,
+            "dst_sled_id" => %resource.sled_id);
+        self.project_instance_migrate(
+            &opctx,
+            &instance_lookup,
+            params::InstanceMigrate { dst_sled_id: resource.sled_id },
+        )
+        .await?;
+        Ok(true)
+    }
+
     /// Idempotently ensures that the sled specified in `db_instance` does not
     /// have a record of the instance. If the instance is currently running on
     /// this sled, this operation rudely terminates it.
@@ -639,9 +1017,8 @@ impl super::Nexus {
             .derive_guest_network_interface_info(&opctx, &authz_instance)
             .await?;
 
-        // Collect the external IPs for the instance.
-        // TODO-correctness: Handle Floating IPs, see
-        //  https://github.com/oxidecomputer/omicron/issues/1334
+        // Collect the external IPs for the instance, including any attached
+        // Floating IPs.
         let (snat_ip, external_ips): (Vec<_>, Vec<_>) = self
             .db_datastore
             .instance_lookup_external_ips(&opctx, authz_instance.id())
@@ -679,7 +1056,7 @@ impl super::Nexus {
         // so we fetch it via the first interface's VNI. (It doesn't
         // matter which one we use because all NICs must be in the
         // same VPC; see the check in project_create_instance.)
-        let firewall_rules = if let Some(nic) = nics.first() {
+        let (firewall_rules, vpc) = if let Some(nic) = nics.first() {
             let vni = Vni::try_from(nic.vni.0)?;
             let vpc = self
                 .db_datastore
@@ -693,10 +1070,12 @@ impl super::Nexus {
                 .db_datastore
                 .vpc_list_firewall_rules(opctx, &authz_vpc)
                 .await?;
-            self.resolve_firewall_rules_for_sled_agent(opctx, &vpc, &rules)
-                .await?
+            let rules = self
+                .resolve_firewall_rules_for_sled_agent(opctx, &vpc, &rules)
+                .await?;
+            (rules, Some(vpc))
         } else {
-            vec![]
+            (vec![], None)
         };
 
         // Gather the SSH public keys of the actor make the request so
@@ -727,6 +1106,18 @@ impl super::Nexus {
             .map(|ssh_key| ssh_key.public_key)
             .collect::<Vec<String>>();
 
+        // Number this Propolis as the instance's next boot, and remember which
+        // sled it's on, so that its serial console output can be found later.
+        let boot = self
+            .db_datastore
+            .instance_boot_create(
+                opctx,
+                authz_instance,
+                db_instance.runtime().sled_id,
+                db_instance.runtime().propolis_id,
+            )
+            .await?;
+
         // Ask the sled agent to begin the state change.  Then update the
         // database to reflect the new intermediate state.  If this update is
         // not the newest one, that's fine.  That might just mean the sled agent
@@ -745,8 +1136,20 @@ impl super::Nexus {
                 &base64::engine::general_purpose::STANDARD,
                 db_instance.generate_cidata(&public_keys)?,
             )),
+            boot_generation: boot.generation(),
         };
 
+        // Send the route tables of the instance's VPC to its sled first, so
+        // that they're applied to the instance's ports as they're created.
+        if let Some(vpc) = &vpc {
+            self.send_sled_agents_vpc_routes_to(
+                opctx,
+                vpc,
+                &[db_instance.runtime().sled_id],
+            )
+            .await?;
+        }
+
         let sa = self.instance_sled(&db_instance).await?;
 
         let instance_register_result = sa
@@ -1013,6 +1416,16 @@ impl super::Nexus {
                     "instance_id" => %id,
                     "propolis_id" => %new_runtime_state.propolis_id,
                     "new_state" => %new_runtime_state.run_state);
+                // An instance that failed or stopped may have a policy that
+                // says to start it again.
+                if matches!(
+                    new_runtime_state.run_state,
+                    InstanceState::Failed | InstanceState::Stopped
+                ) {
+                    self.background_tasks.activate(
+                        &self.background_tasks.task_instance_auto_restart,
+                    );
+                }
                 Ok(())
             }
 
@@ -1087,6 +1500,60 @@ impl super::Nexus {
         })
     }
 
+    /// Returns serial console output recorded by the sled agent on the sled
+    /// that the requested boot of the instance ran on. Unlike the live buffer,
+    /// this survives the buffer wrapping and the instance stopping, and covers
+    /// previous boots, wherever they ran.
+    pub(crate) async fn instance_serial_console_history(
+        &self,
+        opctx: &OpContext,
+        instance_lookup: &lookup::Instance<'_>,
+        params: &params::InstanceSerialConsoleHistoryRequest,
+    ) -> Result<params::InstanceSerialConsoleHistory, Error> {
+        let (.., authz_instance, db_instance) =
+            instance_lookup.fetch_for(authz::Action::Read).await?;
+        let boots = self
+            .db_datastore
+            .instance_boot_list(opctx, &authz_instance)
+            .await?;
+        let boot = match params.boot_generation {
+            Some(boot_generation) => {
+                boots.iter().find(|boot| boot.generation() == boot_generation)
+            }
+            None => boots.last(),
+        };
+        let Some(boot) = boot else {
+            return Err(LookupType::ByCompositeId(format!(
+                "instance {} boot {}",
+                db_instance.id(),
+                params
+                    .boot_generation
+                    .map_or_else(|| String::from("(latest)"), |b| b.to_string())
+            ))
+            .into_not_found(ResourceType::Instance));
+        };
+
+        let sa = self.sled_client(&boot.sled_id).await?;
+        let history = sa
+            .instance_serial_console_history(
+                &db_instance.id(),
+                Some(boot.generation()),
+                params.from_start,
+                params.max_bytes,
+                params.most_recent,
+            )
+            .await
+            .map_err(Error::from)?
+            .into_inner();
+        Ok(params::InstanceSerialConsoleHistory {
+            boot_generations: boots.iter().map(|b| b.generation()).collect(),
+            boot_generation: boot.generation(),
+            data: history.data,
+            first_byte_offset: history.first_byte_offset,
+            last_byte_offset: history.last_byte_offset,
+        })
+    }
+
     pub(crate) async fn instance_serial_console_stream(
         &self,
         conn: dropshot::WebsocketConnection,
@@ -26,32 +26,43 @@ use uuid::Uuid;
 
 // The implementation of Nexus is large, and split into a number of submodules
 // by resource.
+mod affinity_group;
+mod audit_log;
 pub mod background;
 mod certificate;
 mod device_auth;
 mod disk;
 mod external_ip;
+mod floating_ip;
 mod iam;
 mod image;
+mod image_conversion;
+mod image_export;
 mod instance;
 mod ip_pool;
 mod metrics;
 mod network_interface;
 mod oximeter;
 mod project;
+mod quota;
 mod rack;
 pub mod saga;
+mod scim;
 mod session;
 mod silo;
 mod sled;
 mod snapshot;
+mod snapshot_schedule;
 pub mod test_interfaces;
 mod update;
 mod volume;
 mod vpc;
+mod vpc_peering;
 mod vpc_router;
 mod vpc_subnet;
 
+pub use image_export::ExportedImage;
+
 // Sagas are not part of the "Nexus" implementation, but they are
 // application logic.
 pub mod sagas;
@@ -155,6 +166,9 @@ pub struct Nexus {
     /// Operational context used for external request authentication
     opctx_external_authn: OpContext,
 
+    /// Operational context used for recording requests in the audit log
+    opctx_audit_log: OpContext,
+
     /// Max issue delay for samael crate - used only for testing
     // the samael crate has an extra check (beyond the check against the SAML
     // response NotOnOrAfter) that fails if the issue instant was too long ago.
@@ -163,6 +177,10 @@ pub struct Nexus {
     // Nexus to not all fail.
     samael_max_issue_delay: std::sync::Mutex<Option<chrono::Duration>>,
 
+    /// Client for OIDC identity providers' token and JWKS endpoints, which
+    /// also caches providers' ID token signing keys
+    oidc_client: authn::oidc::OidcClient,
+
     resolver: Arc<Mutex<internal_dns::resolver::Resolver>>,
 
     /// Client for dataplane daemon / switch management API
@@ -259,12 +277,19 @@ impl Nexus {
             authn::Context::internal_api(),
             Arc::clone(&db_datastore),
         );
+        let (saga_request, mut saga_request_recv) =
+            sagas::SagaRequest::channel();
         let background_tasks = background::BackgroundTasks::start(
             &background_ctx,
             Arc::clone(&db_datastore),
             &config.pkg.background_tasks,
+            saga_request,
         );
 
+        let oidc_client =
+            authn::oidc::OidcClient::new(std::time::Duration::from_secs(5))
+                .map_err(|e| format!("{:#}", e))?;
+
         let nexus = Nexus {
             id: config.deployment.id,
             rack_id,
@@ -294,7 +319,14 @@ impl Nexus {
                 authn::Context::external_authn(),
                 Arc::clone(&db_datastore),
             ),
+            opctx_audit_log: OpContext::for_background(
+                log.new(o!("component" => "AuditLog")),
+                Arc::clone(&authz),
+                authn::Context::internal_api(),
+                Arc::clone(&db_datastore),
+            ),
             samael_max_issue_delay: std::sync::Mutex::new(None),
+            oidc_client,
             resolver,
             dpd_client,
             background_tasks,
@@ -324,6 +356,20 @@ impl Nexus {
 
         *nexus.recovery_task.lock().unwrap() = Some(recovery_task);
 
+        // Carry out requests from background tasks to run sagas.  Each request
+        // is handled in its own task so that a slow saga doesn't hold up the
+        // others.
+        let request_nexus = nexus.clone();
+        tokio::spawn(async move {
+            while let Some(request) = saga_request_recv.recv().await {
+                let nexus = request_nexus.clone();
+                tokio::spawn(async move {
+                    nexus.handle_saga_request(request).await;
+                });
+            }
+            error!(request_nexus.log, "saga request channel closed");
+        });
+
         // Kick all background tasks once the populate step finishes.  Among
         // other things, the populate step installs role assignments for
         // internal identities that are used by the background tasks.  If we
@@ -351,6 +397,33 @@ impl Nexus {
         Ok(nexus)
     }
 
+    /// Carries out a request from a background task to run sagas
+    async fn handle_saga_request(
+        self: &Arc<Self>,
+        request: sagas::SagaRequest,
+    ) {
+        match request {
+            sagas::SagaRequest::SnapshotSchedule { schedule, reply } => {
+                let result = self.snapshot_schedule_run(&schedule).await;
+                // If the background task has gone away, there's nobody left
+                // to report the result to.
+                let _ = reply.send(result);
+            }
+            sagas::SagaRequest::InstanceAutoRestart { instance_id, reply } => {
+                let result = self.instance_auto_restart(instance_id).await;
+                let _ = reply.send(result);
+            }
+            sagas::SagaRequest::SledDrainMigrate { instance_id, reply } => {
+                let result = self.instance_migrate_off_sled(instance_id).await;
+                let _ = reply.send(result);
+            }
+            sagas::SagaRequest::RegionReplace { replacement_id, reply } => {
+                let result = self.region_replace(replacement_id).await;
+                let _ = reply.send(result);
+            }
+        }
+    }
+
     /// Return the tunable configuration parameters, e.g. for use in tests.
     pub fn tunables(&self) -> &config::Tunables {
         &self.tunables
@@ -648,6 +721,10 @@ impl Nexus {
         &self.db_datastore
     }
 
+    pub fn oidc_client(&self) -> &authn::oidc::OidcClient {
+        &self.oidc_client
+    }
+
     pub fn samael_max_issue_delay(&self) -> Option<chrono::Duration> {
         let mid = self.samael_max_issue_delay.lock().unwrap();
         *mid
@@ -0,0 +1,78 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Silo and project quotas
+
+use crate::authz;
+use crate::db;
+use crate::db::lookup;
+use crate::external_api::params;
+use nexus_db_queries::context::OpContext;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::UpdateResult;
+
+impl super::Nexus {
+    pub async fn silo_quotas_view(
+        &self,
+        opctx: &OpContext,
+        silo_lookup: &lookup::Silo<'_>,
+    ) -> LookupResult<db::model::SiloQuotas> {
+        let (.., authz_silo) =
+            silo_lookup.lookup_for(authz::Action::Read).await?;
+        self.db_datastore.silo_quotas_view(opctx, &authz_silo).await
+    }
+
+    pub async fn silo_quotas_update(
+        &self,
+        opctx: &OpContext,
+        silo_lookup: &lookup::Silo<'_>,
+        updates: &params::QuotasUpdate,
+    ) -> UpdateResult<db::model::SiloQuotas> {
+        validate_quotas(updates)?;
+        let (.., authz_silo) =
+            silo_lookup.lookup_for(authz::Action::Read).await?;
+        let quotas =
+            db::model::SiloQuotas::new(authz_silo.id(), updates.clone());
+        self.db_datastore.silo_quotas_update(opctx, &authz_silo, quotas).await
+    }
+
+    pub async fn project_quotas_view(
+        &self,
+        opctx: &OpContext,
+        project_lookup: &lookup::Project<'_>,
+    ) -> LookupResult<db::model::ProjectQuotas> {
+        let (.., authz_project) =
+            project_lookup.lookup_for(authz::Action::Read).await?;
+        self.db_datastore.project_quotas_view(opctx, &authz_project).await
+    }
+
+    pub async fn project_quotas_update(
+        &self,
+        opctx: &OpContext,
+        project_lookup: &lookup::Project<'_>,
+        updates: &params::QuotasUpdate,
+    ) -> UpdateResult<db::model::ProjectQuotas> {
+        validate_quotas(updates)?;
+        let (authz_silo, authz_project) =
+            project_lookup.lookup_for(authz::Action::Read).await?;
+        let quotas =
+            db::model::ProjectQuotas::new(authz_project.id(), updates.clone());
+        self.db_datastore
+            .project_quotas_update(opctx, &authz_silo, &authz_project, quotas)
+            .await
+    }
+}
+
+fn validate_quotas(quotas: &params::QuotasUpdate) -> Result<(), Error> {
+    if let Some(cpus) = quotas.cpus {
+        if cpus < 0 {
+            return Err(Error::InvalidValue {
+                label: String::from("cpus"),
+                message: String::from("quota must not be negative"),
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,742 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Convert an image that was written to a disk with bulk writes into raw
+//! blocks.
+//!
+//! The conversion never writes to the disk's volume: a new volume of the same
+//! size is created and attached to the disk's Pantry, the image is converted
+//! into it, and it then replaces the original volume, which is deleted. Until
+//! the swap, a failure unwinds to the disk as it was, still in state
+//! "ImportingFromBulkWrites" with the image's bytes intact; after it, the disk
+//! is "ImportReady" and backed by the converted volume.
+
+use super::{
+    common_storage::{
+        call_pantry_attach_for_disk, call_pantry_attach_for_volume,
+        call_pantry_detach_for_disk, delete_crucible_regions,
+        ensure_all_datasets_and_regions,
+    },
+    ActionRegistry, NexusActionContext, NexusSaga, SagaInitError,
+    ACTION_GENERATE_ID,
+};
+use crate::app::image_conversion::{self, ImageFormat, PantryDisk};
+use crate::app::sagas::declare_saga_actions;
+use crate::db::lookup::LookupPath;
+use crate::external_api::params;
+use crate::{authn, authz, db};
+use omicron_common::api::external::ByteCount;
+use omicron_common::api::external::DiskState;
+use omicron_common::api::external::Error;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use serde::Deserialize;
+use serde::Serialize;
+use sled_agent_client::types::{CrucibleOpts, VolumeConstructionRequest};
+use std::net::SocketAddrV6;
+use steno::ActionError;
+use steno::Node;
+use uuid::Uuid;
+
+// disk convert image saga: input parameters
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Params {
+    pub serialized_authn: authn::saga::Serialized,
+    pub disk_id: Uuid,
+    /// The volume the image was written to
+    pub volume_id: Uuid,
+    pub pantry_address: SocketAddrV6,
+    pub format: ImageFormat,
+    pub block_size: params::BlockSize,
+    pub size: ByteCount,
+}
+
+// disk convert image saga: actions
+
+declare_saga_actions! {
+    disk_convert_image;
+    REGIONS_ALLOC -> "datasets_and_regions" {
+        + sdci_alloc_regions
+        - sdci_alloc_regions_undo
+    }
+    REGIONS_ENSURE -> "regions_ensure" {
+        + sdci_regions_ensure
+        - sdci_regions_ensure_undo
+    }
+    CREATE_VOLUME_RECORD -> "converted_volume" {
+        + sdci_create_volume_record
+        - sdci_create_volume_record_undo
+    }
+    ATTACH_CONVERTED_VOLUME -> "attach_converted_volume" {
+        + sdci_attach_converted_volume
+        - sdci_attach_converted_volume_undo
+    }
+    CONVERT_IMAGE -> "convert_image" {
+        + sdci_convert_image
+    }
+    DETACH_CONVERTED_VOLUME -> "detach_converted_volume" {
+        + sdci_detach_converted_volume
+    }
+    REATTACH_DISK -> "reattach_disk" {
+        + sdci_reattach_disk
+        - sdci_reattach_disk_undo
+    }
+    SWAP_VOLUME -> "converted_disk" {
+        + sdci_swap_volume
+        - sdci_swap_volume_undo
+    }
+    DELETE_OLD_VOLUME -> "delete_old_volume" {
+        + sdci_delete_old_volume
+    }
+}
+
+// disk convert image saga: definition
+
+#[derive(Debug)]
+pub struct SagaDiskConvertImage;
+impl NexusSaga for SagaDiskConvertImage {
+    const NAME: &'static str = "disk-convert-image";
+    type Params = Params;
+
+    fn register_actions(registry: &mut ActionRegistry) {
+        disk_convert_image_register_actions(registry);
+    }
+
+    fn make_saga_dag(
+        _params: &Self::Params,
+        mut builder: steno::DagBuilder,
+    ) -> Result<steno::Dag, SagaInitError> {
+        builder.append(Node::action(
+            "converted_volume_id",
+            "GenerateConvertedVolumeId",
+            ACTION_GENERATE_ID.as_ref(),
+        ));
+
+        builder.append(regions_alloc_action());
+        builder.append(regions_ensure_action());
+        builder.append(create_volume_record_action());
+        builder.append(attach_converted_volume_action());
+        builder.append(convert_image_action());
+        builder.append(detach_converted_volume_action());
+        builder.append(reattach_disk_action());
+        builder.append(swap_volume_action());
+        builder.append(delete_old_volume_action());
+
+        Ok(builder.build()?)
+    }
+}
+
+// disk convert image saga: action implementations
+
+async fn sdci_alloc_regions(
+    sagactx: NexusActionContext,
+) -> Result<Vec<(db::model::Dataset, db::model::Region)>, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let datasets_and_regions = osagactx
+        .datastore()
+        .region_allocate(
+            &opctx,
+            converted_volume_id,
+            &params::DiskSource::Blank { block_size: params.block_size },
+            params.size,
+        )
+        .await
+        .map_err(ActionError::action_failed)?;
+    Ok(datasets_and_regions)
+}
+
+async fn sdci_alloc_regions_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+
+    let region_ids = sagactx
+        .lookup::<Vec<(db::model::Dataset, db::model::Region)>>(
+            "datasets_and_regions",
+        )?
+        .into_iter()
+        .map(|(_, region)| region.id())
+        .collect::<Vec<Uuid>>();
+
+    osagactx.datastore().regions_hard_delete(region_ids).await?;
+    Ok(())
+}
+
+/// Call out to Crucible agent and perform region creation, returning the
+/// construction request of the volume the image will be converted into.
+async fn sdci_regions_ensure(
+    sagactx: NexusActionContext,
+) -> Result<String, ActionError> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    let datasets_and_regions = ensure_all_datasets_and_regions(
+        &log,
+        sagactx.lookup::<Vec<(db::model::Dataset, db::model::Region)>>(
+            "datasets_and_regions",
+        )?,
+    )
+    .await?;
+
+    let block_size = datasets_and_regions[0].1.block_size;
+    let blocks_per_extent = datasets_and_regions[0].1.extent_size;
+    let extent_count = datasets_and_regions[0].1.extent_count;
+    let extent_count = extent_count.try_into().map_err(|_| {
+        ActionError::action_failed(Error::internal_error(&format!(
+            "region extent count {} is out of range",
+            extent_count,
+        )))
+    })?;
+
+    let mut rng = StdRng::from_entropy();
+    let volume_construction_request = VolumeConstructionRequest::Volume {
+        id: params.disk_id,
+        block_size,
+        sub_volumes: vec![VolumeConstructionRequest::Region {
+            block_size,
+            blocks_per_extent,
+            extent_count,
+            gen: 1,
+            opts: CrucibleOpts {
+                id: converted_volume_id,
+                target: datasets_and_regions
+                    .iter()
+                    .map(|(dataset, region)| {
+                        dataset
+                            .address_with_port(region.port_number)
+                            .to_string()
+                    })
+                    .collect(),
+
+                lossy: false,
+                flush_timeout: None,
+
+                // all downstairs will expect encrypted blocks
+                key: Some(base64::Engine::encode(
+                    &base64::engine::general_purpose::STANDARD,
+                    {
+                        // TODO the current encryption key
+                        // requirement is 32 bytes, what if that
+                        // changes?
+                        let mut random_bytes: [u8; 32] = [0; 32];
+                        rng.fill_bytes(&mut random_bytes);
+                        random_bytes
+                    },
+                )),
+
+                // TODO TLS, which requires sending X509 stuff during
+                // downstairs region allocation too.
+                cert_pem: None,
+                key_pem: None,
+                root_cert_pem: None,
+
+                control: None,
+
+                read_only: false,
+            },
+        }],
+        read_only_parent: None,
+    };
+
+    let volume_data = serde_json::to_string(&volume_construction_request)
+        .map_err(|e| {
+            ActionError::action_failed(Error::internal_error(&e.to_string()))
+        })?;
+
+    Ok(volume_data)
+}
+
+async fn sdci_regions_ensure_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    warn!(log, "sdci_regions_ensure_undo: Deleting crucible regions");
+    delete_crucible_regions(
+        sagactx.lookup::<Vec<(db::model::Dataset, db::model::Region)>>(
+            "datasets_and_regions",
+        )?,
+    )
+    .await?;
+    info!(log, "sdci_regions_ensure_undo: Deleted crucible regions");
+    Ok(())
+}
+
+async fn sdci_create_volume_record(
+    sagactx: NexusActionContext,
+) -> Result<db::model::Volume, ActionError> {
+    let osagactx = sagactx.user_data();
+
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+    let volume_data = sagactx.lookup::<String>("regions_ensure")?;
+
+    let volume = db::model::Volume::new(converted_volume_id, volume_data);
+
+    osagactx
+        .datastore()
+        .volume_create(volume)
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+async fn sdci_create_volume_record_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+    osagactx.nexus().volume_delete(&opctx, converted_volume_id).await?;
+    Ok(())
+}
+
+/// Attach the new volume to the disk's Pantry, under its own volume id so as
+/// not to collide with the disk's attachment.
+async fn sdci_attach_converted_volume(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    call_pantry_attach_for_volume(
+        &log,
+        &osagactx.nexus(),
+        converted_volume_id,
+        converted_volume_id,
+        params.pantry_address,
+    )
+    .await
+}
+
+async fn sdci_attach_converted_volume_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    call_pantry_detach_for_disk(
+        &log,
+        converted_volume_id,
+        params.pantry_address,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Read the image out of the disk's volume and write its guest-visible disk
+/// to the new volume. The disk's volume is only read, so this can be run
+/// again from the start.
+async fn sdci_convert_image(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    let size = params.size.to_bytes();
+    let block_size = u64::from(params.block_size.0);
+    let source = PantryDisk::new(
+        params.pantry_address,
+        params.disk_id.to_string(),
+        size,
+        block_size,
+    );
+    let converted = PantryDisk::new(
+        params.pantry_address,
+        converted_volume_id.to_string(),
+        size,
+        block_size,
+    );
+
+    let map = image_conversion::map_image(&source, params.format)
+        .await
+        .map_err(|e| ActionError::action_failed(Error::from(e)))?;
+    map.check_fits(size).map_err(ActionError::action_failed)?;
+
+    info!(
+        log,
+        "converting {} image with virtual size {} written to disk {}",
+        map.format(),
+        map.virtual_size(),
+        params.disk_id,
+    );
+
+    image_conversion::convert(&source, &map, &converted)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    info!(
+        log,
+        "converted image written to disk {} into volume {}",
+        params.disk_id,
+        converted_volume_id,
+    );
+
+    Ok(())
+}
+
+async fn sdci_detach_converted_volume(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    call_pantry_detach_for_disk(
+        &log,
+        converted_volume_id,
+        params.pantry_address,
+    )
+    .await
+}
+
+/// Attach the disk to its Pantry with the converted volume in place of the
+/// original one, so that finalizing the import detaches (and snapshots) the
+/// converted volume.
+async fn sdci_reattach_disk(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    call_pantry_detach_for_disk(&log, params.disk_id, params.pantry_address)
+        .await?;
+
+    call_pantry_attach_for_volume(
+        &log,
+        &osagactx.nexus(),
+        params.disk_id,
+        converted_volume_id,
+        params.pantry_address,
+    )
+    .await
+}
+
+async fn sdci_reattach_disk_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    // Attach whichever volume backs the disk now, which is the original one
+    // unless another conversion of the same disk has since completed.
+    call_pantry_detach_for_disk(&log, params.disk_id, params.pantry_address)
+        .await?;
+    call_pantry_attach_for_disk(
+        &log,
+        &opctx,
+        &osagactx.nexus(),
+        params.disk_id,
+        params.pantry_address,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn sdci_swap_volume(
+    sagactx: NexusActionContext,
+) -> Result<db::model::Disk, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    let (.., authz_disk, db_disk) =
+        LookupPath::new(&opctx, &osagactx.datastore())
+            .disk_id(params.disk_id)
+            .fetch_for(authz::Action::Modify)
+            .await
+            .map_err(ActionError::action_failed)?;
+
+    if db_disk.volume_id == params.volume_id {
+        let disk_state: DiskState = db_disk.state().into();
+        if disk_state != DiskState::ImportingFromBulkWrites {
+            return Err(ActionError::action_failed(Error::conflict(&format!(
+                "disk {} is in state {:?}, not {:?}",
+                params.disk_id,
+                disk_state.label(),
+                DiskState::ImportingFromBulkWrites.label(),
+            ))));
+        }
+    }
+
+    osagactx
+        .datastore()
+        .disk_set_volume(
+            &opctx,
+            &authz_disk,
+            params.volume_id,
+            converted_volume_id,
+            &db_disk.runtime().import_ready(),
+        )
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+async fn sdci_swap_volume_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let converted_volume_id = sagactx.lookup::<Uuid>("converted_volume_id")?;
+
+    let (.., authz_disk, db_disk) =
+        LookupPath::new(&opctx, &osagactx.datastore())
+            .disk_id(params.disk_id)
+            .fetch_for(authz::Action::Modify)
+            .await?;
+
+    osagactx
+        .datastore()
+        .disk_set_volume(
+            &opctx,
+            &authz_disk,
+            converted_volume_id,
+            params.volume_id,
+            &db_disk.runtime().importing_from_bulk_writes(),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn sdci_delete_old_volume(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    osagactx
+        .nexus()
+        .volume_delete(&opctx, params.volume_id)
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        app::image_conversion::ImageFormat, app::saga::create_saga_dag,
+        app::sagas::disk_convert_image::Params,
+        app::sagas::disk_convert_image::SagaDiskConvertImage,
+        app::sagas::disk_create::test::new_disk_create_params,
+        app::sagas::disk_create::test::test_opctx, authn::saga::Serialized,
+        db::lookup::LookupPath, db::model::Disk, external_api::params,
+    };
+    use dropshot::test_util::ClientTestContext;
+    use nexus_db_queries::context::OpContext;
+    use nexus_test_utils::resource_helpers::create_ip_pool;
+    use nexus_test_utils::resource_helpers::create_project;
+    use nexus_test_utils::resource_helpers::DiskTest;
+    use nexus_test_utils_macros::nexus_test;
+    use nexus_types::identity::Resource;
+    use omicron_common::api::external::DiskState;
+    use omicron_common::api::external::Name;
+
+    type ControlPlaneTestContext =
+        nexus_test_utils::ControlPlaneTestContext<crate::Server>;
+
+    const PROJECT_NAME: &str = "springfield-squidport";
+
+    async fn create_org_and_project(client: &ClientTestContext) {
+        create_ip_pool(&client, "p0", None).await;
+        create_project(client, PROJECT_NAME).await;
+    }
+
+    /// The blocks written to the disk
+    fn written() -> Vec<u8> {
+        (0..4096u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Creates a disk and bulk writes some blocks to it, leaving it in state
+    /// "ImportingFromBulkWrites"
+    async fn create_importing_disk(
+        cptestctx: &ControlPlaneTestContext,
+    ) -> Disk {
+        let nexus = &cptestctx.server.apictx().nexus;
+        let opctx = test_opctx(&cptestctx);
+
+        let project_selector = params::ProjectSelector {
+            project: Name::try_from(PROJECT_NAME.to_string()).unwrap().into(),
+        };
+        let project_lookup =
+            nexus.project_lookup(&opctx, project_selector).unwrap();
+
+        let mut create_params = new_disk_create_params();
+        create_params.disk_source = params::DiskSource::ImportingBlocks {
+            block_size: params::BlockSize(512),
+        };
+        let disk = nexus
+            .project_create_disk(&opctx, &project_lookup, &create_params)
+            .await
+            .expect("Failed to create disk");
+
+        let disk_lookup =
+            LookupPath::new(&opctx, nexus.datastore()).disk_id(disk.id());
+        nexus.disk_manual_import_start(&opctx, &disk_lookup).await.unwrap();
+        nexus
+            .disk_manual_import(
+                &disk_lookup,
+                params::ImportBlocksBulkWrite {
+                    offset: 0,
+                    base64_encoded_data: base64::Engine::encode(
+                        &base64::engine::general_purpose::STANDARD,
+                        written(),
+                    ),
+                },
+            )
+            .await
+            .unwrap();
+
+        let (.., disk) = disk_lookup.fetch().await.unwrap();
+        disk
+    }
+
+    fn new_test_params(opctx: &OpContext, disk: &Disk) -> Params {
+        Params {
+            serialized_authn: Serialized::for_opctx(opctx),
+            disk_id: disk.id(),
+            volume_id: disk.volume_id,
+            pantry_address: disk.pantry_address().unwrap(),
+            // Raw images convert to a copy of themselves.
+            format: ImageFormat::Raw,
+            block_size: params::BlockSize(disk.block_size.to_bytes()),
+            size: disk.size.0,
+        }
+    }
+
+    /// Reads back the blocks written, through the disk's Pantry attachment
+    async fn read_written(disk: &Disk) -> Vec<u8> {
+        let client = crucible_pantry_client::Client::new(&format!(
+            "http://{}",
+            disk.pantry_address().unwrap()
+        ));
+        let response = client
+            .bulk_read(
+                &disk.id().to_string(),
+                &crucible_pantry_client::types::BulkReadRequest {
+                    offset: 0,
+                    size: written().len(),
+                },
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        base64::Engine::decode(
+            &base64::engine::general_purpose::STANDARD,
+            response.base64_encoded_data,
+        )
+        .unwrap()
+    }
+
+    #[nexus_test(server = crate::Server)]
+    async fn test_saga_basic_usage_succeeds(
+        cptestctx: &ControlPlaneTestContext,
+    ) {
+        DiskTest::new(cptestctx).await;
+
+        let client = &cptestctx.external_client;
+        let nexus = &cptestctx.server.apictx().nexus;
+        create_org_and_project(&client).await;
+        let disk = create_importing_disk(&cptestctx).await;
+
+        let opctx = test_opctx(cptestctx);
+        let params = new_test_params(&opctx, &disk);
+        let dag = create_saga_dag::<SagaDiskConvertImage>(params).unwrap();
+        let runnable_saga = nexus.create_runnable_saga(dag).await.unwrap();
+        let output = nexus.run_saga(runnable_saga).await.unwrap();
+
+        let converted =
+            output.lookup_node_output::<Disk>("converted_disk").unwrap();
+        assert_ne!(converted.volume_id, disk.volume_id);
+        assert_eq!(*converted.state().state(), DiskState::ImportReady);
+
+        // The disk is attached to its Pantry with the converted volume.
+        assert_eq!(read_written(&converted).await, written());
+    }
+
+    #[nexus_test(server = crate::Server)]
+    async fn test_action_failure_can_unwind(
+        cptestctx: &ControlPlaneTestContext,
+    ) {
+        DiskTest::new(cptestctx).await;
+        let log = &cptestctx.logctx.log;
+
+        let client = &cptestctx.external_client;
+        let nexus = &cptestctx.server.apictx().nexus;
+        create_org_and_project(&client).await;
+        let disk = create_importing_disk(&cptestctx).await;
+        let datastore = nexus.datastore();
+
+        let opctx = test_opctx(cptestctx);
+        let params = new_test_params(&opctx, &disk);
+        let dag = create_saga_dag::<SagaDiskConvertImage>(params).unwrap();
+
+        for node in dag.get_nodes() {
+            info!(
+                log,
+                "Creating new saga which will fail at index {:?}", node.index();
+                "node_name" => node.name().as_ref(),
+                "label" => node.label(),
+            );
+            let runnable_saga =
+                nexus.create_runnable_saga(dag.clone()).await.unwrap();
+
+            nexus
+                .sec()
+                .saga_inject_error(runnable_saga.id(), node.index())
+                .await
+                .unwrap();
+            nexus
+                .run_saga(runnable_saga)
+                .await
+                .expect_err("Saga should have failed");
+
+            // The disk should be exactly as it was before the saga ran, with
+            // the image it was written still readable.
+            let (.., db_disk) = LookupPath::new(&opctx, &datastore)
+                .disk_id(disk.id())
+                .fetch()
+                .await
+                .unwrap();
+            assert_eq!(db_disk.volume_id, disk.volume_id);
+            assert_eq!(
+                *db_disk.state().state(),
+                DiskState::ImportingFromBulkWrites
+            );
+            assert_eq!(read_written(&db_disk).await, written());
+        }
+    }
+}
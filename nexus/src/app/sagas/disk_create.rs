@@ -858,6 +858,7 @@ pub(crate) mod test {
                 name: DISK_NAME.parse().expect("Invalid disk name"),
                 description: "My disk".to_string(),
             },
+            labels: Default::default(),
             disk_source: params::DiskSource::Blank {
                 block_size: params::BlockSize(512),
             },
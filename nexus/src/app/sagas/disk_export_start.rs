@@ -0,0 +1,274 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! For disks in state Detached, start an export: set them to Exporting and
+//! attach them to a Pantry, from which their blocks can be read out.
+
+use super::common_storage::{
+    call_pantry_attach_for_disk, call_pantry_detach_for_disk,
+    get_pantry_address,
+};
+use super::declare_saga_actions;
+use super::ActionRegistry;
+use super::NexusActionContext;
+use super::NexusSaga;
+use super::SagaInitError;
+use crate::db::lookup::LookupPath;
+use crate::{authn, authz};
+use nexus_db_model::Generation;
+use omicron_common::api::external;
+use omicron_common::api::external::Error;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::SocketAddrV6;
+use steno::ActionError;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Params {
+    pub serialized_authn: authn::saga::Serialized,
+    pub disk_id: Uuid,
+}
+
+declare_saga_actions! {
+    disk_export_start;
+    SET_EXPORTING_STATE -> "disk_generation_number" {
+        + sdes_set_exporting_state
+        - sdes_set_exporting_state_undo
+    }
+    GET_PANTRY_ADDRESS -> "pantry_address" {
+        + sdes_get_pantry_address
+    }
+    CALL_PANTRY_ATTACH_FOR_DISK -> "call_pantry_attach_for_disk" {
+        + sdes_call_pantry_attach_for_disk
+        - sdes_call_pantry_attach_for_disk_undo
+    }
+    SET_PANTRY_ADDRESS -> "set_pantry_address" {
+        + sdes_set_pantry_address
+    }
+}
+
+#[derive(Debug)]
+pub struct SagaDiskExportStart;
+impl NexusSaga for SagaDiskExportStart {
+    const NAME: &'static str = "disk-export-start";
+    type Params = Params;
+
+    fn register_actions(registry: &mut ActionRegistry) {
+        disk_export_start_register_actions(registry);
+    }
+
+    fn make_saga_dag(
+        _params: &Self::Params,
+        mut builder: steno::DagBuilder,
+    ) -> Result<steno::Dag, SagaInitError> {
+        builder.append(set_exporting_state_action());
+        builder.append(get_pantry_address_action());
+        builder.append(call_pantry_attach_for_disk_action());
+        builder.append(set_pantry_address_action());
+        Ok(builder.build()?)
+    }
+}
+
+async fn sdes_set_exporting_state(
+    sagactx: NexusActionContext,
+) -> Result<Generation, ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., authz_disk, db_disk) =
+        LookupPath::new(&opctx, &osagactx.datastore())
+            .disk_id(params.disk_id)
+            .fetch_for(authz::Action::Modify)
+            .await
+            .map_err(ActionError::action_failed)?;
+
+    match db_disk.state().into() {
+        external::DiskState::Detached => {
+            info!(log, "setting disk {} to state exporting", db_disk.id());
+
+            osagactx
+                .datastore()
+                .disk_update_runtime(
+                    &opctx,
+                    &authz_disk,
+                    &db_disk.runtime().exporting(),
+                )
+                .await
+                .map_err(ActionError::action_failed)?;
+
+            // Record the disk's new generation number as this saga node's
+            // output, so that the undo only moves the disk out of exporting if
+            // nothing else has changed it since.
+            let (.., db_disk) = LookupPath::new(&opctx, &osagactx.datastore())
+                .disk_id(params.disk_id)
+                .fetch_for(authz::Action::Read)
+                .await
+                .map_err(ActionError::action_failed)?;
+
+            Ok(db_disk.runtime().gen)
+        }
+
+        _ => Err(ActionError::action_failed(Error::invalid_request(&format!(
+            "cannot export disk in state {:?}",
+            db_disk.state()
+        )))),
+    }
+}
+
+async fn sdes_set_exporting_state_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., authz_disk, db_disk) =
+        LookupPath::new(&opctx, &osagactx.datastore())
+            .disk_id(params.disk_id)
+            .fetch_for(authz::Action::Modify)
+            .await
+            .map_err(ActionError::action_failed)?;
+
+    let expected_disk_generation_number =
+        sagactx.lookup::<Generation>("disk_generation_number")?;
+
+    match db_disk.state().into() {
+        external::DiskState::Exporting => {
+            if expected_disk_generation_number == db_disk.runtime().gen {
+                info!(
+                    log,
+                    "undo: setting disk {} state from exporting to detached",
+                    params.disk_id
+                );
+
+                osagactx
+                    .datastore()
+                    .disk_update_runtime(
+                        &opctx,
+                        &authz_disk,
+                        &db_disk.runtime().detach(),
+                    )
+                    .await
+                    .map_err(ActionError::action_failed)?;
+            } else {
+                info!(
+                    log,
+                    "disk {} has generation number {:?}, which doesn't match the expected {:?}: skip setting to detached",
+                    params.disk_id,
+                    db_disk.runtime().gen,
+                    expected_disk_generation_number,
+                );
+            }
+        }
+
+        external::DiskState::Detached => {
+            info!(log, "disk {} already detached", params.disk_id);
+        }
+
+        _ => {
+            warn!(log, "disk is in state {:?}", db_disk.state());
+        }
+    }
+
+    Ok(())
+}
+
+async fn sdes_get_pantry_address(
+    sagactx: NexusActionContext,
+) -> Result<SocketAddrV6, ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+
+    // Pick a random Pantry and use it for this disk. This will be the Pantry
+    // used for all reads until the export is stopped.
+    let pantry_address = get_pantry_address(osagactx.nexus()).await?;
+
+    info!(
+        log,
+        "using pantry at {} for exporting disk {}",
+        pantry_address,
+        params.disk_id
+    );
+
+    Ok(pantry_address)
+}
+
+async fn sdes_call_pantry_attach_for_disk(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let pantry_address = sagactx.lookup::<SocketAddrV6>("pantry_address")?;
+
+    call_pantry_attach_for_disk(
+        &log,
+        &opctx,
+        &osagactx.nexus(),
+        params.disk_id,
+        pantry_address,
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn sdes_call_pantry_attach_for_disk_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+
+    let pantry_address = sagactx.lookup::<SocketAddrV6>("pantry_address")?;
+
+    call_pantry_detach_for_disk(&log, params.disk_id, pantry_address).await?;
+
+    Ok(())
+}
+
+async fn sdes_set_pantry_address(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let datastore = osagactx.datastore();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let pantry_address = sagactx.lookup::<SocketAddrV6>("pantry_address")?;
+
+    info!(log, "setting disk {} pantry to {}", params.disk_id, pantry_address);
+
+    let (.., authz_disk) = LookupPath::new(&opctx, &datastore)
+        .disk_id(params.disk_id)
+        .lookup_for(authz::Action::Modify)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    datastore
+        .disk_set_pantry(&opctx, &authz_disk, pantry_address)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    Ok(())
+}
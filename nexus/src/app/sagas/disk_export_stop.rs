@@ -0,0 +1,245 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! For disks in state Exporting, stop the export: detach them from their
+//! attached Pantry, and set to Detached.
+
+use super::declare_saga_actions;
+use super::ActionRegistry;
+use super::NexusActionContext;
+use super::NexusSaga;
+use super::SagaInitError;
+use crate::db::lookup::LookupPath;
+use crate::retry_until_known_result;
+use crate::{authn, authz};
+use nexus_db_model::Generation;
+use omicron_common::api::external;
+use omicron_common::api::external::Error;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::SocketAddrV6;
+use steno::ActionError;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Params {
+    pub serialized_authn: authn::saga::Serialized,
+    pub disk_id: Uuid,
+}
+
+declare_saga_actions! {
+    disk_export_stop;
+    CHECK_EXPORTING_STATE -> "disk_generation_number" {
+        + sdxs_check_exporting_state
+    }
+    GET_PANTRY_ADDRESS -> "pantry_address" {
+        + sdxs_get_pantry_address
+    }
+    CALL_PANTRY_DETACH_FOR_DISK -> "call_pantry_detach_for_disk" {
+        + sdxs_call_pantry_detach_for_disk
+    }
+    CLEAR_PANTRY_ADDRESS -> "clear_pantry_address" {
+        + sdxs_clear_pantry_address
+    }
+    SET_DETACHED_STATE -> "set_detached_state" {
+        + sdxs_set_detached_state
+    }
+}
+
+#[derive(Debug)]
+pub struct SagaDiskExportStop;
+impl NexusSaga for SagaDiskExportStop {
+    const NAME: &'static str = "disk-export-stop";
+    type Params = Params;
+
+    fn register_actions(registry: &mut ActionRegistry) {
+        disk_export_stop_register_actions(registry);
+    }
+
+    fn make_saga_dag(
+        _params: &Self::Params,
+        mut builder: steno::DagBuilder,
+    ) -> Result<steno::Dag, SagaInitError> {
+        builder.append(check_exporting_state_action());
+        builder.append(get_pantry_address_action());
+        builder.append(call_pantry_detach_for_disk_action());
+        builder.append(clear_pantry_address_action());
+        builder.append(set_detached_state_action());
+        Ok(builder.build()?)
+    }
+}
+
+async fn sdxs_check_exporting_state(
+    sagactx: NexusActionContext,
+) -> Result<Generation, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., db_disk) = LookupPath::new(&opctx, &osagactx.datastore())
+        .disk_id(params.disk_id)
+        .fetch_for(authz::Action::Modify)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    // Record the disk's generation number: the disk is only moved back to
+    // detached at the end of this saga if nothing else changed it since.
+    match db_disk.state().into() {
+        external::DiskState::Exporting => Ok(db_disk.runtime().gen),
+
+        _ => Err(ActionError::action_failed(Error::invalid_request(&format!(
+            "cannot stop export of disk in state {:?}",
+            db_disk.state()
+        )))),
+    }
+}
+
+async fn sdxs_get_pantry_address(
+    sagactx: NexusActionContext,
+) -> Result<SocketAddrV6, ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., db_disk) = LookupPath::new(&opctx, &osagactx.datastore())
+        .disk_id(params.disk_id)
+        .fetch_for(authz::Action::Modify)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    // At any stage of executing this saga, if the disk moves from state
+    // exporting to detached, it will be detached from the corresponding Pantry.
+    // Any subsequent saga nodes will fail because the pantry address is stored
+    // as part of the saga state, and requests sent to that Pantry with the
+    // disk's id will fail.
+    let pantry_address = db_disk.pantry_address().ok_or_else(|| {
+        ActionError::action_failed(String::from("disk not attached to pantry!"))
+    })?;
+
+    info!(log, "disk {} is using pantry at {}", db_disk.id(), pantry_address);
+
+    Ok(pantry_address)
+}
+
+async fn sdxs_call_pantry_detach_for_disk(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+
+    let pantry_address = sagactx.lookup::<SocketAddrV6>("pantry_address")?;
+    let endpoint = format!("http://{}", pantry_address);
+
+    info!(
+        log,
+        "sending detach request for disk {} to pantry endpoint {}",
+        params.disk_id,
+        endpoint,
+    );
+
+    let disk_id = params.disk_id.to_string();
+
+    let client = crucible_pantry_client::Client::new(&endpoint);
+
+    retry_until_known_result!(log, { client.detach(&disk_id) })?;
+
+    Ok(())
+}
+
+async fn sdxs_clear_pantry_address(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let datastore = osagactx.datastore();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    info!(log, "setting disk {} pantry to None", params.disk_id);
+
+    let (.., authz_disk) = LookupPath::new(&opctx, &datastore)
+        .disk_id(params.disk_id)
+        .lookup_for(authz::Action::Modify)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    datastore
+        .disk_clear_pantry(&opctx, &authz_disk)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    Ok(())
+}
+
+async fn sdxs_set_detached_state(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., authz_disk, db_disk) =
+        LookupPath::new(&opctx, &osagactx.datastore())
+            .disk_id(params.disk_id)
+            .fetch_for(authz::Action::Modify)
+            .await
+            .map_err(ActionError::action_failed)?;
+
+    let expected_disk_generation_number =
+        sagactx.lookup::<Generation>("disk_generation_number")?;
+
+    match db_disk.state().into() {
+        external::DiskState::Exporting => {
+            if expected_disk_generation_number == db_disk.runtime().gen {
+                info!(
+                    log,
+                    "setting disk {} state from exporting to detached",
+                    params.disk_id
+                );
+
+                osagactx
+                    .datastore()
+                    .disk_update_runtime(
+                        &opctx,
+                        &authz_disk,
+                        &db_disk.runtime().detach(),
+                    )
+                    .await
+                    .map_err(ActionError::action_failed)?;
+            } else {
+                info!(
+                    log,
+                    "disk {} has generation number {:?}, which doesn't match the expected {:?}: skip setting to detached",
+                    params.disk_id,
+                    db_disk.runtime().gen,
+                    expected_disk_generation_number,
+                );
+            }
+        }
+
+        external::DiskState::Detached => {
+            info!(log, "disk {} already detached", params.disk_id);
+        }
+
+        _ => {
+            warn!(log, "disk is in state {:?}", db_disk.state());
+        }
+    }
+
+    Ok(())
+}
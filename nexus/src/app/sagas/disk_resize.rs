@@ -0,0 +1,753 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Grow a disk.
+//!
+//! Crucible regions are fixed in size once created, so a disk is grown by
+//! allocating a new set of regions large enough to hold the additional blocks
+//! and appending them to the disk's volume as another sub-volume. The regions
+//! are first allocated under a freshly-generated "extension" volume ID, which
+//! also identifies the new sub-volume; once the Crucible agents have created
+//! them, they are moved into the disk's volume in the same transaction that
+//! appends the sub-volume.
+//!
+//! Propolis can't yet grow a disk that's in use, so Nexus only resizes disks
+//! that are detached or attached to a stopped instance. If the instance
+//! starts while the disk is being grown anyway, its sled agent is sent the
+//! grown volume to use the next time it creates a Propolis for the instance,
+//! and the guest doesn't see the additional space until then.
+
+use super::{
+    common_storage::{
+        delete_crucible_regions, ensure_all_datasets_and_regions,
+    },
+    ActionRegistry, NexusActionContext, NexusSaga, SagaInitError,
+    ACTION_GENERATE_ID,
+};
+use crate::app::sagas::declare_saga_actions;
+use crate::db::identity::Asset;
+use crate::db::lookup::LookupPath;
+use crate::external_api::params;
+use crate::{authn, authz, db};
+use omicron_common::api::external::ByteCount;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use serde::Deserialize;
+use serde::Serialize;
+use sled_agent_client::types::{CrucibleOpts, VolumeConstructionRequest};
+use steno::ActionError;
+use steno::Node;
+use uuid::Uuid;
+
+// disk resize saga: input parameters
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Params {
+    pub serialized_authn: authn::saga::Serialized,
+    pub project_id: Uuid,
+    pub disk_id: Uuid,
+    pub volume_id: Uuid,
+    pub block_size: params::BlockSize,
+    pub old_size: ByteCount,
+    pub new_size: ByteCount,
+}
+
+// disk resize saga: actions
+
+declare_saga_actions! {
+    disk_resize;
+    SPACE_ACCOUNT -> "no_result" {
+        + sdr_account_space
+        - sdr_account_space_undo
+    }
+    REGIONS_ALLOC -> "datasets_and_regions" {
+        + sdr_alloc_regions
+        - sdr_alloc_regions_undo
+    }
+    REGIONS_ENSURE -> "sub_volume" {
+        + sdr_regions_ensure
+        - sdr_regions_ensure_undo
+    }
+    EXTEND_VOLUME -> "extended_volume" {
+        + sdr_extend_volume
+        - sdr_extend_volume_undo
+    }
+    UPDATE_DISK_SIZE -> "resized_disk" {
+        + sdr_update_disk_size
+        - sdr_update_disk_size_undo
+    }
+    SEND_RESIZE_REQUEST_TO_SLED_AGENT -> "resize_request_to_sled_agent" {
+        + sdr_send_resize_request_to_sled_agent
+    }
+}
+
+// disk resize saga: definition
+
+#[derive(Debug)]
+pub struct SagaDiskResize;
+impl NexusSaga for SagaDiskResize {
+    const NAME: &'static str = "disk-resize";
+    type Params = Params;
+
+    fn register_actions(registry: &mut ActionRegistry) {
+        disk_resize_register_actions(registry);
+    }
+
+    fn make_saga_dag(
+        _params: &Self::Params,
+        mut builder: steno::DagBuilder,
+    ) -> Result<steno::Dag, SagaInitError> {
+        builder.append(Node::action(
+            "extension_volume_id",
+            "GenerateExtensionVolumeId",
+            ACTION_GENERATE_ID.as_ref(),
+        ));
+
+        builder.append(space_account_action());
+        builder.append(regions_alloc_action());
+        builder.append(regions_ensure_action());
+        builder.append(extend_volume_action());
+        builder.append(update_disk_size_action());
+        builder.append(send_resize_request_to_sled_agent_action());
+
+        Ok(builder.build()?)
+    }
+}
+
+// disk resize saga: action implementations
+
+async fn sdr_account_space(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    osagactx
+        .datastore()
+        .virtual_provisioning_collection_resize_disk(
+            &opctx,
+            params.disk_id,
+            params.project_id,
+            params.old_size.into(),
+            params.new_size.into(),
+        )
+        .await
+        .map_err(ActionError::action_failed)?;
+    Ok(())
+}
+
+async fn sdr_account_space_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    osagactx
+        .datastore()
+        .virtual_provisioning_collection_resize_disk(
+            &opctx,
+            params.disk_id,
+            params.project_id,
+            params.new_size.into(),
+            params.old_size.into(),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn sdr_alloc_regions(
+    sagactx: NexusActionContext,
+) -> Result<Vec<(db::model::Dataset, db::model::Region)>, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let extension_volume_id = sagactx.lookup::<Uuid>("extension_volume_id")?;
+
+    // Allocate enough regions to back the additional blocks only. These are
+    // allocated against the extension volume ID so that the allocation
+    // remains idempotent and doesn't collide with the disk's existing
+    // regions.
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let additional_size = ByteCount::try_from(
+        params.new_size.to_bytes() - params.old_size.to_bytes(),
+    )
+    .map_err(|e| {
+        ActionError::action_failed(Error::internal_error(&e.to_string()))
+    })?;
+    let datasets_and_regions = osagactx
+        .datastore()
+        .region_allocate(
+            &opctx,
+            extension_volume_id,
+            &params::DiskSource::Blank { block_size: params.block_size },
+            additional_size,
+        )
+        .await
+        .map_err(ActionError::action_failed)?;
+    Ok(datasets_and_regions)
+}
+
+async fn sdr_alloc_regions_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+
+    let region_ids = sagactx
+        .lookup::<Vec<(db::model::Dataset, db::model::Region)>>(
+            "datasets_and_regions",
+        )?
+        .into_iter()
+        .map(|(_, region)| region.id())
+        .collect::<Vec<Uuid>>();
+
+    osagactx.datastore().regions_hard_delete(region_ids).await?;
+    Ok(())
+}
+
+/// Call out to Crucible agent and perform region creation, returning the
+/// sub-volume that will be appended to the disk's volume.
+async fn sdr_regions_ensure(
+    sagactx: NexusActionContext,
+) -> Result<VolumeConstructionRequest, ActionError> {
+    let log = sagactx.user_data().log();
+    let extension_volume_id = sagactx.lookup::<Uuid>("extension_volume_id")?;
+
+    let datasets_and_regions = ensure_all_datasets_and_regions(
+        &log,
+        sagactx.lookup::<Vec<(db::model::Dataset, db::model::Region)>>(
+            "datasets_and_regions",
+        )?,
+    )
+    .await?;
+
+    let block_size = datasets_and_regions[0].1.block_size;
+    let blocks_per_extent = datasets_and_regions[0].1.extent_size;
+    let extent_count = datasets_and_regions[0].1.extent_count;
+    let extent_count = extent_count.try_into().map_err(|_| {
+        ActionError::action_failed(Error::internal_error(&format!(
+            "region extent count {} is out of range",
+            extent_count,
+        )))
+    })?;
+
+    let mut rng = StdRng::from_entropy();
+    Ok(VolumeConstructionRequest::Region {
+        block_size,
+        blocks_per_extent,
+        extent_count,
+        gen: 1,
+        opts: CrucibleOpts {
+            id: extension_volume_id,
+            target: datasets_and_regions
+                .iter()
+                .map(|(dataset, region)| {
+                    dataset.address_with_port(region.port_number).to_string()
+                })
+                .collect(),
+
+            lossy: false,
+            flush_timeout: None,
+
+            // all downstairs will expect encrypted blocks
+            key: Some(base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                {
+                    // TODO the current encryption key
+                    // requirement is 32 bytes, what if that
+                    // changes?
+                    let mut random_bytes: [u8; 32] = [0; 32];
+                    rng.fill_bytes(&mut random_bytes);
+                    random_bytes
+                },
+            )),
+
+            // TODO TLS, which requires sending X509 stuff during
+            // downstairs region allocation too.
+            cert_pem: None,
+            key_pem: None,
+            root_cert_pem: None,
+
+            control: None,
+
+            read_only: false,
+        },
+    })
+}
+
+async fn sdr_regions_ensure_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    warn!(log, "sdr_regions_ensure_undo: Deleting crucible regions");
+    delete_crucible_regions(
+        sagactx.lookup::<Vec<(db::model::Dataset, db::model::Region)>>(
+            "datasets_and_regions",
+        )?,
+    )
+    .await?;
+    info!(log, "sdr_regions_ensure_undo: Deleted crucible regions");
+    Ok(())
+}
+
+async fn sdr_extend_volume(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let extension_volume_id = sagactx.lookup::<Uuid>("extension_volume_id")?;
+    let sub_volume =
+        sagactx.lookup::<VolumeConstructionRequest>("sub_volume")?;
+
+    osagactx
+        .datastore()
+        .volume_extend(params.volume_id, extension_volume_id, sub_volume)
+        .await
+        .map_err(ActionError::action_failed)?;
+    Ok(())
+}
+
+async fn sdr_extend_volume_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let extension_volume_id = sagactx.lookup::<Uuid>("extension_volume_id")?;
+
+    // Move the new regions back under the extension volume ID, so that the
+    // undo actions of the earlier nodes can find and delete them.
+    let region_ids = sagactx
+        .lookup::<Vec<(db::model::Dataset, db::model::Region)>>(
+            "datasets_and_regions",
+        )?
+        .into_iter()
+        .map(|(_, region)| region.id())
+        .collect::<Vec<Uuid>>();
+
+    osagactx
+        .datastore()
+        .volume_extend_undo(params.volume_id, extension_volume_id, region_ids)
+        .await?;
+    Ok(())
+}
+
+async fn sdr_update_disk_size(
+    sagactx: NexusActionContext,
+) -> Result<db::model::Disk, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., authz_disk) = LookupPath::new(&opctx, &osagactx.datastore())
+        .disk_id(params.disk_id)
+        .lookup_for(authz::Action::Modify)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    osagactx
+        .datastore()
+        .disk_set_size(&opctx, &authz_disk, params.old_size, params.new_size)
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+async fn sdr_update_disk_size_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., authz_disk) = LookupPath::new(&opctx, &osagactx.datastore())
+        .disk_id(params.disk_id)
+        .lookup_for(authz::Action::Modify)
+        .await?;
+
+    osagactx
+        .datastore()
+        .disk_set_size(&opctx, &authz_disk, params.new_size, params.old_size)
+        .await?;
+    Ok(())
+}
+
+async fn sdr_send_resize_request_to_sled_agent(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let disk = sagactx.lookup::<db::model::Disk>("resized_disk")?;
+
+    // If the disk isn't attached to a running instance, there's nobody to
+    // tell: the next instance start builds its disk requests from the
+    // (now larger) volume. The instance was stopped when the resize was
+    // requested, so it's only running here if it started in the meantime.
+    let Some(instance_id) = disk.runtime().attach_instance_id else {
+        return Ok(());
+    };
+
+    let (.., instance) = LookupPath::new(&opctx, &osagactx.datastore())
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    match instance.runtime().state.0 {
+        InstanceState::Running
+        | InstanceState::Rebooting
+        | InstanceState::Migrating => {}
+        _ => {
+            info!(
+                log,
+                "disk {} instance {} not running, skipping resize request",
+                disk.id(),
+                instance_id,
+            );
+            return Ok(());
+        }
+    }
+
+    let sled_agent_client = osagactx
+        .nexus()
+        .instance_sled(&instance)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    let volume = osagactx
+        .datastore()
+        .volume_checkout(params.volume_id)
+        .await
+        .map_err(ActionError::action_failed)?;
+    let volume_construction_request: VolumeConstructionRequest =
+        serde_json::from_str(volume.data()).map_err(|e| {
+            ActionError::action_failed(Error::internal_error(&format!(
+                "failed to deserialize volume data: {}",
+                e,
+            )))
+        })?;
+
+    info!(
+        log,
+        "sending resize request for disk {} to instance {}",
+        disk.id(),
+        instance_id,
+    );
+
+    sled_agent_client
+        .instance_issue_disk_resize_request(
+            &instance.id(),
+            &disk.id(),
+            &sled_agent_client::types::InstanceIssueDiskResizeRequestBody {
+                volume_construction_request,
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())
+        .map_err(ActionError::action_failed)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        app::saga::create_saga_dag,
+        app::sagas::disk_create::test::new_disk_create_params,
+        app::sagas::disk_create::test::test_opctx,
+        app::sagas::disk_resize::Params,
+        app::sagas::disk_resize::SagaDiskResize, authn::saga::Serialized,
+        db::model::Disk, external_api::params,
+    };
+    use dropshot::test_util::ClientTestContext;
+    use nexus_db_queries::context::OpContext;
+    use nexus_test_utils::resource_helpers::create_ip_pool;
+    use nexus_test_utils::resource_helpers::create_project;
+    use nexus_test_utils::resource_helpers::DiskTest;
+    use nexus_test_utils_macros::nexus_test;
+    use nexus_types::identity::Resource;
+    use omicron_common::api::external::ByteCount;
+    use omicron_common::api::external::Error;
+    use omicron_common::api::external::Name;
+    use uuid::Uuid;
+
+    type ControlPlaneTestContext =
+        nexus_test_utils::ControlPlaneTestContext<crate::Server>;
+
+    const PROJECT_NAME: &str = "springfield-squidport";
+
+    async fn create_org_and_project(client: &ClientTestContext) -> Uuid {
+        create_ip_pool(&client, "p0", None).await;
+        let project = create_project(client, PROJECT_NAME).await;
+        project.identity.id
+    }
+
+    async fn create_disk(cptestctx: &ControlPlaneTestContext) -> Disk {
+        let nexus = &cptestctx.server.apictx().nexus;
+        let opctx = test_opctx(&cptestctx);
+
+        let project_selector = params::ProjectSelector {
+            project: Name::try_from(PROJECT_NAME.to_string()).unwrap().into(),
+        };
+        let project_lookup =
+            nexus.project_lookup(&opctx, project_selector).unwrap();
+
+        nexus
+            .project_create_disk(
+                &opctx,
+                &project_lookup,
+                &new_disk_create_params(),
+            )
+            .await
+            .expect("Failed to create disk")
+    }
+
+    fn new_test_params(
+        opctx: &OpContext,
+        project_id: Uuid,
+        disk: &Disk,
+    ) -> Params {
+        Params {
+            serialized_authn: Serialized::for_opctx(opctx),
+            project_id,
+            disk_id: disk.id(),
+            volume_id: disk.volume_id,
+            block_size: params::BlockSize(disk.block_size.to_bytes()),
+            old_size: disk.size.0,
+            new_size: ByteCount::from_gibibytes_u32(2),
+        }
+    }
+
+    #[nexus_test(server = crate::Server)]
+    async fn test_saga_basic_usage_succeeds(
+        cptestctx: &ControlPlaneTestContext,
+    ) {
+        DiskTest::new(cptestctx).await;
+
+        let client = &cptestctx.external_client;
+        let nexus = &cptestctx.server.apictx().nexus;
+        let project_id = create_org_and_project(&client).await;
+        let disk = create_disk(&cptestctx).await;
+
+        let opctx = test_opctx(cptestctx);
+        let params = new_test_params(&opctx, project_id, &disk);
+        let dag = create_saga_dag::<SagaDiskResize>(params).unwrap();
+        let runnable_saga = nexus.create_runnable_saga(dag).await.unwrap();
+        let output = nexus.run_saga(runnable_saga).await.unwrap();
+
+        let resized =
+            output.lookup_node_output::<Disk>("resized_disk").unwrap();
+        assert_eq!(resized.size.0, ByteCount::from_gibibytes_u32(2));
+
+        // Both the original and the additional regions belong to the disk's
+        // volume now.
+        let regions = nexus
+            .datastore()
+            .get_allocated_regions(disk.volume_id)
+            .await
+            .unwrap();
+        assert_eq!(regions.len(), 6);
+    }
+
+    #[nexus_test(server = crate::Server)]
+    async fn test_action_failure_can_unwind(
+        cptestctx: &ControlPlaneTestContext,
+    ) {
+        DiskTest::new(cptestctx).await;
+        let log = &cptestctx.logctx.log;
+
+        let client = &cptestctx.external_client;
+        let nexus = &cptestctx.server.apictx().nexus;
+        let project_id = create_org_and_project(&client).await;
+        let disk = create_disk(&cptestctx).await;
+        let datastore = nexus.datastore();
+
+        let opctx = test_opctx(cptestctx);
+        let params = new_test_params(&opctx, project_id, &disk);
+        let dag = create_saga_dag::<SagaDiskResize>(params).unwrap();
+
+        for node in dag.get_nodes() {
+            info!(
+                log,
+                "Creating new saga which will fail at index {:?}", node.index();
+                "node_name" => node.name().as_ref(),
+                "label" => node.label(),
+            );
+            let runnable_saga =
+                nexus.create_runnable_saga(dag.clone()).await.unwrap();
+
+            nexus
+                .sec()
+                .saga_inject_error(runnable_saga.id(), node.index())
+                .await
+                .unwrap();
+            nexus
+                .run_saga(runnable_saga)
+                .await
+                .expect_err("Saga should have failed");
+
+            // The disk should be exactly as it was before the saga ran.
+            let (.., db_disk) =
+                crate::db::lookup::LookupPath::new(&opctx, &datastore)
+                    .disk_id(disk.id())
+                    .fetch()
+                    .await
+                    .unwrap();
+            assert_eq!(db_disk.size.0, disk.size.0);
+
+            let regions =
+                datastore.get_allocated_regions(disk.volume_id).await.unwrap();
+            assert_eq!(regions.len(), 3);
+
+            let provisioned = datastore
+                .virtual_provisioning_collection_get(&opctx, project_id)
+                .await
+                .unwrap();
+            assert_eq!(
+                provisioned.virtual_disk_bytes_provisioned.to_bytes(),
+                disk.size.to_bytes(),
+            );
+        }
+    }
+
+    #[nexus_test(server = crate::Server)]
+    async fn test_stale_resize_conflicts(cptestctx: &ControlPlaneTestContext) {
+        DiskTest::new(cptestctx).await;
+
+        let client = &cptestctx.external_client;
+        let nexus = &cptestctx.server.apictx().nexus;
+        let project_id = create_org_and_project(&client).await;
+        let disk = create_disk(&cptestctx).await;
+        let datastore = nexus.datastore();
+        let opctx = test_opctx(cptestctx);
+
+        // Grow the disk, then try to grow it again from its original size,
+        // as if two resizes had raced.
+        let params = new_test_params(&opctx, project_id, &disk);
+        let dag = create_saga_dag::<SagaDiskResize>(params).unwrap();
+        let runnable_saga = nexus.create_runnable_saga(dag).await.unwrap();
+        nexus.run_saga(runnable_saga).await.unwrap();
+
+        let mut params = new_test_params(&opctx, project_id, &disk);
+        params.new_size = ByteCount::from_gibibytes_u32(3);
+        let dag = create_saga_dag::<SagaDiskResize>(params).unwrap();
+        let runnable_saga = nexus.create_runnable_saga(dag).await.unwrap();
+        let error = nexus
+            .run_saga(runnable_saga)
+            .await
+            .expect_err("stale resize should fail");
+        assert!(matches!(error, Error::Conflict { .. }), "{:?}", error);
+
+        // The first resize stands, and the second didn't add any regions or
+        // provisioned space.
+        let (.., db_disk) =
+            crate::db::lookup::LookupPath::new(&opctx, &datastore)
+                .disk_id(disk.id())
+                .fetch()
+                .await
+                .unwrap();
+        assert_eq!(db_disk.size.0, ByteCount::from_gibibytes_u32(2));
+        let regions =
+            datastore.get_allocated_regions(disk.volume_id).await.unwrap();
+        assert_eq!(regions.len(), 6);
+        let provisioned = datastore
+            .virtual_provisioning_collection_get(&opctx, project_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            provisioned.virtual_disk_bytes_provisioned.to_bytes(),
+            ByteCount::from_gibibytes_u32(2).to_bytes(),
+        );
+
+        // Setting the size the disk already has succeeds, for idempotency.
+        let (.., authz_disk) =
+            crate::db::lookup::LookupPath::new(&opctx, &datastore)
+                .disk_id(disk.id())
+                .lookup_for(crate::authz::Action::Modify)
+                .await
+                .unwrap();
+        datastore
+            .disk_set_size(
+                &opctx,
+                &authz_disk,
+                disk.size.0,
+                ByteCount::from_gibibytes_u32(2),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[nexus_test(server = crate::Server)]
+    async fn test_concurrent_resizes_conflict(
+        cptestctx: &ControlPlaneTestContext,
+    ) {
+        DiskTest::new(cptestctx).await;
+
+        let client = &cptestctx.external_client;
+        let nexus = &cptestctx.server.apictx().nexus;
+        let project_id = create_org_and_project(&client).await;
+        let disk = create_disk(&cptestctx).await;
+        let datastore = nexus.datastore();
+        let opctx = test_opctx(cptestctx);
+
+        // Account for two resizes from the disk's original size at once.
+        // Exactly one of them can win, and the other must fail rather than
+        // appear to succeed without changing anything.
+        let old_size = disk.size;
+        let (first, second) = tokio::join!(
+            datastore.virtual_provisioning_collection_resize_disk(
+                &opctx,
+                disk.id(),
+                project_id,
+                old_size,
+                ByteCount::from_gibibytes_u32(2).into(),
+            ),
+            datastore.virtual_provisioning_collection_resize_disk(
+                &opctx,
+                disk.id(),
+                project_id,
+                old_size,
+                ByteCount::from_gibibytes_u32(3).into(),
+            ),
+        );
+        let expected_size = match (first, second) {
+            (Ok(_), Err(Error::Conflict { .. })) => {
+                ByteCount::from_gibibytes_u32(2)
+            }
+            (Err(Error::Conflict { .. }), Ok(_)) => {
+                ByteCount::from_gibibytes_u32(3)
+            }
+            results => panic!("unexpected results: {:?}", results),
+        };
+
+        let provisioned = datastore
+            .virtual_provisioning_collection_get(&opctx, project_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            provisioned.virtual_disk_bytes_provisioned.to_bytes(),
+            expected_size.to_bytes(),
+        );
+    }
+}
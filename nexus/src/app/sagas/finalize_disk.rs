@@ -89,8 +89,10 @@ impl NexusSaga for SagaFinalizeDisk {
                             params.disk_name
                         ),
                     },
+                    labels: Default::default(),
                     disk: params.disk_name.clone(),
                 },
+                snapshot_schedule_id: None,
             };
 
             let subsaga_dag = {
@@ -4,12 +4,18 @@
 
 //! For disks in state ImportReady, send a request to import blocks from a URL.
 //! Note the Pantry they're attached to must have addressability to the URL!
+//!
+//! Raw images are imported by the Pantry itself. Images in a container format
+//! (qcow2, VMDK, VHD or VHDX) are instead converted to raw blocks by Nexus
+//! while streaming them from the URL, and written to the disk through the
+//! Pantry. Either way a failure leaves the disk in state ImportReady.
 
 use super::declare_saga_actions;
 use super::ActionRegistry;
 use super::NexusActionContext;
 use super::NexusSaga;
 use super::SagaInitError;
+use crate::app::image_conversion::{self, ImageFormat, PantryDisk, UrlSource};
 use crate::db::lookup::LookupPath;
 use crate::retry_until_known_result;
 use crate::{authn, authz};
@@ -40,12 +46,18 @@ declare_saga_actions! {
     GET_PANTRY_ADDRESS -> "pantry_address" {
         + sibfu_get_pantry_address
     }
+    DETECT_IMAGE_FORMAT -> "image_format" {
+        + sibfu_detect_image_format
+    }
     CALL_PANTRY_IMPORT_FROM_URL_FOR_DISK -> "call_pantry_import_from_url_for_disk" {
         + sibfu_call_pantry_import_from_url_for_disk
     }
     WAIT_FOR_IMPORT_FROM_URL -> "wait_for_import_from_url" {
         + sibfu_wait_for_import_from_url
     }
+    CONVERT_IMAGE_FROM_URL -> "convert_image_from_url" {
+        + sibfu_convert_image_from_url
+    }
     SET_IMPORT_READY_STATE -> "set_import_ready_state" {
         + sibfu_get_import_ready_state
     }
@@ -69,12 +81,17 @@ impl NexusSaga for SagaImportBlocksFromUrl {
 
         builder.append(get_pantry_address_action());
 
-        // Call the Pantry's /import_from_url
+        builder.append(detect_image_format_action());
+
+        // Call the Pantry's /import_from_url, for raw images
         builder.append(call_pantry_import_from_url_for_disk_action());
 
         // Wait for import_from_url job to complete
         builder.append(wait_for_import_from_url_action());
 
+        // Convert images in any other format
+        builder.append(convert_image_from_url_action());
+
         // Set ImportReady state
         builder.append(set_import_ready_state_action());
 
@@ -234,12 +251,65 @@ async fn sibfu_get_pantry_address(
     Ok(pantry_address)
 }
 
+async fn sibfu_detect_image_format(
+    sagactx: NexusActionContext,
+) -> Result<ImageFormat, ActionError> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+    let url = &params.import_params.url;
+
+    // Nexus may not be able to reach every URL the Pantry can. If the image
+    // can't be probed from here, leave it to the Pantry to import as raw
+    // blocks, as it always has.
+    let format = match UrlSource::new(url).await {
+        Ok(source) => image_conversion::detect_format(&source).await,
+        Err(e) => Err(e),
+    };
+
+    match format {
+        // Conversion reads the image in pieces with range requests, so the
+        // bytes converted can't be checked against a digest of the whole
+        // image: the server could serve different ones to a download made to
+        // check it. The Pantry checks raw images as it imports them.
+        Ok(format)
+            if format != ImageFormat::Raw
+                && params.import_params.expected_digest.is_some() =>
+        {
+            Err(ActionError::action_failed(Error::invalid_request(&format!(
+                "image at {} is in {} format, and only raw images can be \
+                checked against an expected digest",
+                url, format,
+            ))))
+        }
+
+        Ok(format) => {
+            info!(log, "image at {} is in {} format", url, format);
+            Ok(format)
+        }
+
+        Err(e) => {
+            warn!(
+                log,
+                "could not detect format of image at {}, assuming raw: {}",
+                url,
+                e,
+            );
+            Ok(ImageFormat::Raw)
+        }
+    }
+}
+
 async fn sibfu_call_pantry_import_from_url_for_disk(
     sagactx: NexusActionContext,
-) -> Result<String, ActionError> {
+) -> Result<Option<String>, ActionError> {
     let log = sagactx.user_data().log();
     let params = sagactx.saga_params::<Params>()?;
 
+    let image_format = sagactx.lookup::<ImageFormat>("image_format")?;
+    if image_format != ImageFormat::Raw {
+        return Ok(None);
+    }
+
     let pantry_address = sagactx.lookup::<SocketAddrV6>("pantry_address")?;
     let endpoint = format!("http://{}", pantry_address);
 
@@ -276,7 +346,7 @@ async fn sibfu_call_pantry_import_from_url_for_disk(
         client.import_from_url(&disk_id, &request)
     })?;
 
-    Ok(response.job_id.clone())
+    Ok(Some(response.job_id.clone()))
 }
 
 async fn sibfu_wait_for_import_from_url(
@@ -285,9 +355,13 @@ async fn sibfu_wait_for_import_from_url(
     let log = sagactx.user_data().log();
     let params = sagactx.saga_params::<Params>()?;
 
+    let Some(job_id) = sagactx
+        .lookup::<Option<String>>("call_pantry_import_from_url_for_disk")?
+    else {
+        // The image is being converted instead
+        return Ok(());
+    };
     let pantry_address = sagactx.lookup::<SocketAddrV6>("pantry_address")?;
-    let job_id =
-        sagactx.lookup::<String>("call_pantry_import_from_url_for_disk")?;
 
     let endpoint = format!("http://{}", pantry_address);
 
@@ -325,6 +399,65 @@ async fn sibfu_wait_for_import_from_url(
     Ok(())
 }
 
+async fn sibfu_convert_image_from_url(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let image_format = sagactx.lookup::<ImageFormat>("image_format")?;
+    if image_format == ImageFormat::Raw {
+        return Ok(());
+    }
+
+    let pantry_address = sagactx.lookup::<SocketAddrV6>("pantry_address")?;
+
+    let (.., db_disk) = LookupPath::new(&opctx, &osagactx.datastore())
+        .disk_id(params.disk_id)
+        .fetch_for(authz::Action::Read)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    let url = &params.import_params.url;
+    let source = UrlSource::new(url)
+        .await
+        .map_err(|e| ActionError::action_failed(Error::from(e)))?;
+
+    let map = image_conversion::map_image(&source, image_format)
+        .await
+        .map_err(|e| ActionError::action_failed(Error::from(e)))?;
+    map.check_fits(db_disk.size.to_bytes())
+        .map_err(ActionError::action_failed)?;
+
+    info!(
+        log,
+        "converting {} image at {} with virtual size {} into disk {}",
+        map.format(),
+        url,
+        map.virtual_size(),
+        params.disk_id,
+    );
+
+    let disk = PantryDisk::new(
+        pantry_address,
+        params.disk_id.to_string(),
+        db_disk.size.to_bytes(),
+        db_disk.block_size.to_bytes().into(),
+    );
+    image_conversion::convert(&source, &map, &disk)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    info!(log, "converted image at {} into disk {}", url, params.disk_id);
+
+    Ok(())
+}
+
 async fn sibfu_get_import_ready_state(
     sagactx: NexusActionContext,
 ) -> Result<(), ActionError> {
@@ -26,6 +26,7 @@ use omicron_common::api::external::Generation;
 use omicron_common::api::external::IdentityMetadataCreateParams;
 use omicron_common::api::external::InstanceState;
 use omicron_common::api::external::Name;
+use omicron_common::api::external::NameOrId;
 use omicron_common::api::internal::nexus::InstanceRuntimeState;
 use serde::Deserialize;
 use serde::Serialize;
@@ -79,6 +80,13 @@ struct DiskAttachParams {
 
 declare_saga_actions! {
     instance_create;
+    RESOLVE_AFFINITY_GROUPS -> "affinity_group_ids" {
+        + sic_resolve_affinity_groups
+    }
+    JOIN_AFFINITY_GROUPS -> "no_result" {
+        + sic_join_affinity_groups
+        - sic_join_affinity_groups_undo
+    }
     ALLOC_SERVER -> "server_id" {
         + sic_alloc_server
         - sic_alloc_server_undo
@@ -161,6 +169,10 @@ impl NexusSaga for SagaInstanceCreate {
             ACTION_GENERATE_ID.as_ref(),
         ));
 
+        builder.append(resolve_affinity_groups_action());
+        // The instance joins its affinity groups before it's placed, so that
+        // instances placed at the same time see each other.
+        builder.append(join_affinity_groups_action());
         builder.append(alloc_server_action());
         builder.append(virtual_resources_account_action());
         builder.append(alloc_propolis_ip_action());
@@ -565,6 +577,27 @@ async fn sic_remove_network_config(
     Ok(())
 }
 
+async fn sic_resolve_affinity_groups(
+    sagactx: NexusActionContext,
+) -> Result<Vec<Uuid>, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    osagactx
+        .nexus()
+        .affinity_groups_resolve_for_instance(
+            &opctx,
+            params.project_id,
+            &params.create_params.affinity_groups,
+        )
+        .await
+        .map_err(ActionError::action_failed)
+}
+
 async fn sic_alloc_server(
     sagactx: NexusActionContext,
 ) -> Result<Uuid, ActionError> {
@@ -589,9 +622,10 @@ async fn sic_alloc_server(
     //   multi-rack, this is going to fling the sled to an arbitrary system.
     //   Maybe that's okay, but worth knowing about explicitly.
     //
-    // - This doesn't take into account anti-affinity - users will want to
-    //   schedule instances that belong to a cluster on different failure
-    //   domains. See https://github.com/oxidecomputer/omicron/issues/1705.
+    // - Affinity groups only consider individual sleds. Users will eventually
+    //   want to schedule instances that belong to a cluster on different
+    //   failure domains (e.g., racks or power zones). See
+    //   https://github.com/oxidecomputer/omicron/issues/1705.
 
     // TODO: Fix these values. They're wrong now, but they let us move
     // forward with plumbing.
@@ -610,13 +644,21 @@ async fn sic_alloc_server(
         reservoir_ram.into(),
     );
 
+    let instance_id = sagactx.lookup::<Uuid>("instance_id")?;
+    let affinity_group_ids =
+        sagactx.lookup::<Vec<Uuid>>("affinity_group_ids")?;
+    let constraints = db::model::SledReservationConstraintBuilder::new()
+        .affinity_groups(&affinity_group_ids)
+        .build();
+
     let resource = osagactx
         .nexus()
         .reserve_on_random_sled(
             propolis_id,
             db::model::SledResourceKind::Instance,
+            Some(instance_id),
             resources,
-            db::model::SledReservationConstraints::none(),
+            constraints,
         )
         .await
         .map_err(ActionError::action_failed)?;
@@ -964,19 +1006,76 @@ async fn sic_allocate_instance_external_ip(
     let instance_id = repeat_saga_params.instance_id;
     let ip_id = repeat_saga_params.new_id;
 
-    // Collect the possible pool name for this IP address
-    let pool_name = match ip_params {
+    match ip_params {
         params::ExternalIpCreate::Ephemeral { ref pool_name } => {
-            pool_name.as_ref().map(|name| db::model::Name(name.clone()))
+            let pool_name =
+                pool_name.as_ref().map(|name| db::model::Name(name.clone()));
+            datastore
+                .allocate_instance_ephemeral_ip(
+                    &opctx,
+                    ip_id,
+                    instance_id,
+                    pool_name,
+                )
+                .await
+                .map_err(ActionError::action_failed)?;
         }
-    };
-    datastore
-        .allocate_instance_ephemeral_ip(&opctx, ip_id, instance_id, pool_name)
-        .await
-        .map_err(ActionError::action_failed)?;
+        params::ExternalIpCreate::Floating { ref floating_ip } => {
+            let (authz_instance, authz_fip) = sic_floating_ip_lookup(
+                &opctx,
+                &datastore,
+                saga_params.project_id,
+                instance_id,
+                floating_ip,
+            )
+            .await
+            .map_err(ActionError::action_failed)?;
+            datastore
+                .floating_ip_attach(
+                    &opctx,
+                    &authz_fip,
+                    &authz_instance,
+                    MAX_EXTERNAL_IPS_PER_INSTANCE,
+                )
+                .await
+                .map_err(ActionError::action_failed)?;
+        }
+    }
     Ok(())
 }
 
+/// Look up the instance being created and the Floating IP to attach to it,
+/// which must be in the instance's project.
+async fn sic_floating_ip_lookup(
+    opctx: &OpContext,
+    datastore: &db::DataStore,
+    project_id: Uuid,
+    instance_id: Uuid,
+    floating_ip: &NameOrId,
+) -> Result<(authz::Instance, authz::FloatingIp), Error> {
+    let (.., authz_instance) = LookupPath::new(opctx, datastore)
+        .instance_id(instance_id)
+        .lookup_for(authz::Action::Modify)
+        .await?;
+    let fip_lookup = match floating_ip {
+        NameOrId::Id(id) => {
+            LookupPath::new(opctx, datastore).floating_ip_id(*id)
+        }
+        NameOrId::Name(name) => LookupPath::new(opctx, datastore)
+            .project_id(project_id)
+            .floating_ip_name_owned(name.clone().into()),
+    };
+    let (.., authz_project, authz_fip) =
+        fip_lookup.lookup_for(authz::Action::Modify).await?;
+    if authz_project.id() != project_id {
+        return Err(Error::invalid_request(&format!(
+            "floating IP {} is not in the instance's project",
+            floating_ip
+        )));
+    }
+    Ok((authz_instance, authz_fip))
+}
+
 async fn sic_allocate_instance_external_ip_undo(
     sagactx: NexusActionContext,
 ) -> Result<(), anyhow::Error> {
@@ -993,8 +1092,25 @@ async fn sic_allocate_instance_external_ip_undo(
         &sagactx,
         &saga_params.serialized_authn,
     );
-    let ip_id = repeat_saga_params.new_id;
-    datastore.deallocate_external_ip(&opctx, ip_id).await?;
+    match &saga_params.create_params.external_ips[ip_index] {
+        params::ExternalIpCreate::Ephemeral { .. } => {
+            let ip_id = repeat_saga_params.new_id;
+            datastore.deallocate_external_ip(&opctx, ip_id).await?;
+        }
+        params::ExternalIpCreate::Floating { floating_ip } => {
+            let (authz_instance, authz_fip) = sic_floating_ip_lookup(
+                &opctx,
+                &datastore,
+                saga_params.project_id,
+                repeat_saga_params.instance_id,
+                floating_ip,
+            )
+            .await?;
+            datastore
+                .floating_ip_detach(&opctx, &authz_fip, &authz_instance)
+                .await?;
+        }
+    }
     Ok(())
 }
 
@@ -1204,6 +1320,68 @@ async fn sic_create_instance_record(
     Ok(instance.name().clone().into())
 }
 
+async fn sic_join_affinity_groups(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let instance_id = sagactx.lookup::<Uuid>("instance_id")?;
+    let affinity_group_ids =
+        sagactx.lookup::<Vec<Uuid>>("affinity_group_ids")?;
+
+    for group_id in affinity_group_ids {
+        let (.., authz_group) = LookupPath::new(&opctx, &osagactx.datastore())
+            .affinity_group_id(group_id)
+            .lookup_for(authz::Action::Modify)
+            .await
+            .map_err(ActionError::action_failed)?;
+        osagactx
+            .datastore()
+            .affinity_group_member_add(&opctx, &authz_group, instance_id)
+            .await
+            .map_err(ActionError::action_failed)?;
+    }
+
+    Ok(())
+}
+
+async fn sic_join_affinity_groups_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let instance_id = sagactx.lookup::<Uuid>("instance_id")?;
+    let affinity_group_ids =
+        sagactx.lookup::<Vec<Uuid>>("affinity_group_ids")?;
+
+    for group_id in affinity_group_ids {
+        let result = LookupPath::new(&opctx, &osagactx.datastore())
+            .affinity_group_id(group_id)
+            .lookup_for(authz::Action::Modify)
+            .await;
+        // If the group has since been deleted, its memberships went with it.
+        let authz_group = match result {
+            Ok((.., authz_group)) => authz_group,
+            Err(Error::ObjectNotFound { .. }) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        osagactx
+            .datastore()
+            .affinity_group_member_remove(&opctx, &authz_group, instance_id)
+            .await?;
+    }
+
+    Ok(())
+}
+
 async fn sic_delete_instance_record(
     sagactx: NexusActionContext,
 ) -> Result<(), anyhow::Error> {
@@ -1499,6 +1677,7 @@ pub mod test {
                     name: INSTANCE_NAME.parse().unwrap(),
                     description: "My instance".to_string(),
                 },
+                labels: Default::default(),
                 ncpus: InstanceCpuCount::try_from(2).unwrap(),
                 memory: ByteCount::from_gibibytes_u32(4),
                 hostname: String::from("inst"),
@@ -1513,6 +1692,8 @@ pub mod test {
                         name: DISK_NAME.parse().unwrap(),
                     },
                 )],
+                affinity_groups: Vec::new(),
+                auto_restart_policy: Default::default(),
                 start: false,
             },
         }
@@ -383,6 +383,7 @@ mod test {
                 name: INSTANCE_NAME.parse().unwrap(),
                 description: "My instance".to_string(),
             },
+            labels: Default::default(),
             ncpus: InstanceCpuCount::try_from(2).unwrap(),
             memory: ByteCount::from_gibibytes_u32(4),
             hostname: String::from("inst"),
@@ -395,6 +396,8 @@ mod test {
             disks: vec![params::InstanceDiskAttachment::Attach(
                 params::InstanceDiskAttach { name: DISK_NAME.parse().unwrap() },
             )],
+            affinity_groups: Vec::new(),
+            auto_restart_policy: Default::default(),
             start: false,
         }
     }
@@ -0,0 +1,153 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Attach a Floating IP address to an instance.
+//!
+//! The attachment is first recorded in the database, and then dpd is told to
+//! NAT traffic for the address to the instance's sled. The instance must be
+//! stopped: OPTE ports can't change their external IP, so the address is
+//! only given to the instance's port when it next starts.
+
+use super::{ActionRegistry, NexusActionContext, NexusSaga};
+use crate::app::sagas::declare_saga_actions;
+use crate::app::MAX_EXTERNAL_IPS_PER_INSTANCE;
+use crate::db::lookup::LookupPath;
+use crate::{authn, authz, db};
+use serde::Deserialize;
+use serde::Serialize;
+use steno::ActionError;
+
+// instance ip attach saga: input parameters
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Params {
+    pub serialized_authn: authn::saga::Serialized,
+    pub authz_instance: authz::Instance,
+    pub authz_fip: authz::FloatingIp,
+}
+
+// instance ip attach saga: actions
+
+declare_saga_actions! {
+    instance_ip_attach;
+    ATTACH_FLOATING_IP -> "floating_ip" {
+        + siia_attach_ip
+        - siia_attach_ip_undo
+    }
+    NAT_ENSURE -> "no_result1" {
+        + siia_nat_ensure
+        - siia_nat_ensure_undo
+    }
+}
+
+// instance ip attach saga: definition
+
+#[derive(Debug)]
+pub struct SagaInstanceIpAttach;
+impl NexusSaga for SagaInstanceIpAttach {
+    const NAME: &'static str = "instance-ip-attach";
+    type Params = Params;
+
+    fn register_actions(registry: &mut ActionRegistry) {
+        instance_ip_attach_register_actions(registry);
+    }
+
+    fn make_saga_dag(
+        _params: &Self::Params,
+        mut builder: steno::DagBuilder,
+    ) -> Result<steno::Dag, super::SagaInitError> {
+        builder.append(attach_floating_ip_action());
+        builder.append(nat_ensure_action());
+        Ok(builder.build()?)
+    }
+}
+
+// instance ip attach saga: action implementations
+
+async fn siia_attach_ip(
+    sagactx: NexusActionContext,
+) -> Result<db::model::FloatingIp, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    osagactx
+        .datastore()
+        .floating_ip_attach(
+            &opctx,
+            &params.authz_fip,
+            &params.authz_instance,
+            MAX_EXTERNAL_IPS_PER_INSTANCE,
+        )
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+async fn siia_attach_ip_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    osagactx
+        .datastore()
+        .floating_ip_detach(&opctx, &params.authz_fip, &params.authz_instance)
+        .await?;
+    Ok(())
+}
+
+async fn siia_nat_ensure(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let datastore = osagactx.datastore();
+
+    let (.., instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(params.authz_instance.id())
+        .fetch()
+        .await
+        .map_err(ActionError::action_failed)?;
+    let target_ip = datastore
+        .floating_ip_fetch_external_ip(&opctx, &params.authz_fip)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    osagactx
+        .nexus()
+        .instance_ensure_dpd_nat(
+            &opctx,
+            &params.authz_instance,
+            instance.runtime().sled_id,
+            &target_ip,
+        )
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+async fn siia_nat_ensure_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let target_ip = osagactx
+        .datastore()
+        .floating_ip_fetch_external_ip(&opctx, &params.authz_fip)
+        .await?;
+    osagactx.nexus().instance_delete_dpd_nat(&target_ip).await?;
+    Ok(())
+}
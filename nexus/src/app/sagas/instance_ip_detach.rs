@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Detach a Floating IP address from an instance.
+//!
+//! This undoes the steps of the `instance_ip_attach` saga in reverse: dpd's
+//! NAT entry is deleted, and then the attachment is removed from the
+//! database. As with attaching, the instance must be stopped.
+
+use super::{ActionRegistry, NexusActionContext, NexusSaga};
+use crate::app::sagas::declare_saga_actions;
+use crate::db::lookup::LookupPath;
+use crate::{authn, authz, db};
+use serde::Deserialize;
+use serde::Serialize;
+use steno::ActionError;
+
+// instance ip detach saga: input parameters
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Params {
+    pub serialized_authn: authn::saga::Serialized,
+    pub authz_instance: authz::Instance,
+    pub authz_fip: authz::FloatingIp,
+}
+
+// instance ip detach saga: actions
+
+declare_saga_actions! {
+    instance_ip_detach;
+    NAT_REMOVE -> "no_result1" {
+        + siid_nat_remove
+        - siid_nat_remove_undo
+    }
+    DETACH_FLOATING_IP -> "floating_ip" {
+        + siid_detach_ip
+    }
+}
+
+// instance ip detach saga: definition
+
+#[derive(Debug)]
+pub struct SagaInstanceIpDetach;
+impl NexusSaga for SagaInstanceIpDetach {
+    const NAME: &'static str = "instance-ip-detach";
+    type Params = Params;
+
+    fn register_actions(registry: &mut ActionRegistry) {
+        instance_ip_detach_register_actions(registry);
+    }
+
+    fn make_saga_dag(
+        _params: &Self::Params,
+        mut builder: steno::DagBuilder,
+    ) -> Result<steno::Dag, super::SagaInitError> {
+        builder.append(nat_remove_action());
+        builder.append(detach_floating_ip_action());
+        Ok(builder.build()?)
+    }
+}
+
+// instance ip detach saga: action implementations
+
+async fn siid_nat_remove(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let target_ip = osagactx
+        .datastore()
+        .floating_ip_fetch_external_ip(&opctx, &params.authz_fip)
+        .await
+        .map_err(ActionError::action_failed)?;
+    osagactx
+        .nexus()
+        .instance_delete_dpd_nat(&target_ip)
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+async fn siid_nat_remove_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let datastore = osagactx.datastore();
+    let (.., instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(params.authz_instance.id())
+        .fetch()
+        .await?;
+    let target_ip = datastore
+        .floating_ip_fetch_external_ip(&opctx, &params.authz_fip)
+        .await?;
+    osagactx
+        .nexus()
+        .instance_ensure_dpd_nat(
+            &opctx,
+            &params.authz_instance,
+            instance.runtime().sled_id,
+            &target_ip,
+        )
+        .await?;
+    Ok(())
+}
+
+async fn siid_detach_ip(
+    sagactx: NexusActionContext,
+) -> Result<db::model::FloatingIp, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    osagactx
+        .datastore()
+        .floating_ip_detach(&opctx, &params.authz_fip, &params.authz_instance)
+        .await
+        .map_err(ActionError::action_failed)
+}
@@ -235,6 +235,7 @@ async fn sim_reserve_sled_resources(
         .reserve_on_random_sled(
             propolis_id,
             db::model::SledResourceKind::Instance,
+            Some(params.instance.id()),
             resources,
             constraints,
         )
@@ -547,6 +548,7 @@ mod tests {
                     name: INSTANCE_NAME.parse().unwrap(),
                     description: format!("instance {:?}", INSTANCE_NAME),
                 },
+                labels: Default::default(),
                 ncpus: InstanceCpuCount(2),
                 memory: ByteCount::from_gibibytes_u32(2),
                 hostname: String::from(INSTANCE_NAME),
@@ -555,6 +557,8 @@ mod tests {
                     params::InstanceNetworkInterfaceAttachment::None,
                 external_ips: vec![],
                 disks: vec![],
+                affinity_groups: Vec::new(),
+                auto_restart_policy: Default::default(),
                 start: true,
             },
         )
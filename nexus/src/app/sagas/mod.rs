@@ -10,6 +10,8 @@
 // easier it will be to test, version, and update in deployed systems.
 
 use crate::saga_interface::SagaContext;
+use chrono::DateTime;
+use chrono::Utc;
 use once_cell::sync::Lazy;
 use std::sync::Arc;
 use steno::new_action_noop_undo;
@@ -17,16 +19,25 @@ use steno::ActionContext;
 use steno::ActionError;
 use steno::SagaType;
 use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 use uuid::Uuid;
 
+pub mod disk_convert_image;
 pub mod disk_create;
 pub mod disk_delete;
+pub mod disk_export_start;
+pub mod disk_export_stop;
+pub mod disk_resize;
 pub mod finalize_disk;
 pub mod import_blocks_from_url;
 pub mod instance_create;
 pub mod instance_delete;
+pub mod instance_ip_attach;
+pub mod instance_ip_detach;
 pub mod instance_migrate;
 pub mod project_create;
+pub mod region_replace;
 pub mod snapshot_create;
 pub mod snapshot_delete;
 pub mod volume_delete;
@@ -98,6 +109,59 @@ impl From<SagaInitError> for omicron_common::api::external::Error {
     }
 }
 
+/// A request from a background task for Nexus to carry out work that is
+/// driven by sagas
+///
+/// Background tasks don't have a reference to Nexus, so they can't run sagas
+/// themselves.  Instead they send these requests over a channel that Nexus
+/// services.
+#[derive(Debug)]
+pub enum SagaRequest {
+    /// Take a snapshot for a snapshot schedule, then delete the snapshots
+    /// taken by that schedule beyond its retention count
+    SnapshotSchedule {
+        schedule: crate::db::model::SnapshotSchedule,
+        /// receives the time at which the snapshot was taken, or the error
+        reply: oneshot::Sender<
+            Result<DateTime<Utc>, omicron_common::api::external::Error>,
+        >,
+    },
+    /// Start an instance again according to its auto-restart policy
+    InstanceAutoRestart {
+        instance_id: Uuid,
+        /// receives whether the instance was restarted, or the error
+        reply:
+            oneshot::Sender<Result<bool, omicron_common::api::external::Error>>,
+    },
+    /// Migrate an instance off of the sled that it's running on because that
+    /// sled is being drained
+    SledDrainMigrate {
+        instance_id: Uuid,
+        /// receives whether the instance started migrating, or the error
+        reply:
+            oneshot::Sender<Result<bool, omicron_common::api::external::Error>>,
+    },
+    /// Replace a region that was lost along with its dataset
+    RegionReplace {
+        replacement_id: Uuid,
+        /// receives whether the region was replaced, or the error
+        reply:
+            oneshot::Sender<Result<bool, omicron_common::api::external::Error>>,
+    },
+}
+
+impl SagaRequest {
+    /// Returns the two ends of a channel for sending `SagaRequest`s
+    pub fn channel() -> (mpsc::Sender<SagaRequest>, mpsc::Receiver<SagaRequest>)
+    {
+        mpsc::channel(SAGA_REQUEST_QUEUE_SIZE)
+    }
+}
+
+/// Number of requests that can be queued for Nexus before background tasks
+/// sending more requests have to wait
+const SAGA_REQUEST_QUEUE_SIZE: usize = 16;
+
 pub(super) static ACTION_GENERATE_ID: Lazy<NexusAction> = Lazy::new(|| {
     new_action_noop_undo("common.uuid_generate", saga_generate_uuid)
 });
@@ -108,8 +172,18 @@ fn make_action_registry() -> ActionRegistry {
     let mut registry = steno::ActionRegistry::new();
     registry.register(Arc::clone(&*ACTION_GENERATE_ID));
 
+    <disk_convert_image::SagaDiskConvertImage as NexusSaga>::register_actions(
+        &mut registry,
+    );
     <disk_create::SagaDiskCreate as NexusSaga>::register_actions(&mut registry);
     <disk_delete::SagaDiskDelete as NexusSaga>::register_actions(&mut registry);
+    <disk_export_start::SagaDiskExportStart as NexusSaga>::register_actions(
+        &mut registry,
+    );
+    <disk_export_stop::SagaDiskExportStop as NexusSaga>::register_actions(
+        &mut registry,
+    );
+    <disk_resize::SagaDiskResize as NexusSaga>::register_actions(&mut registry);
     <finalize_disk::SagaFinalizeDisk as NexusSaga>::register_actions(
         &mut registry,
     );
@@ -120,12 +194,21 @@ fn make_action_registry() -> ActionRegistry {
     <instance_delete::SagaInstanceDelete as NexusSaga>::register_actions(
         &mut registry,
     );
+    <instance_ip_attach::SagaInstanceIpAttach as NexusSaga>::register_actions(
+        &mut registry,
+    );
+    <instance_ip_detach::SagaInstanceIpDetach as NexusSaga>::register_actions(
+        &mut registry,
+    );
     <instance_migrate::SagaInstanceMigrate as NexusSaga>::register_actions(
         &mut registry,
     );
     <project_create::SagaProjectCreate as NexusSaga>::register_actions(
         &mut registry,
     );
+    <region_replace::SagaRegionReplace as NexusSaga>::register_actions(
+        &mut registry,
+    );
     <snapshot_create::SagaSnapshotCreate as NexusSaga>::register_actions(
         &mut registry,
     );
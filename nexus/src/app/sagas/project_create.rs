@@ -137,6 +137,7 @@ async fn spc_create_vpc_params(
             name: "default".parse().unwrap(),
             description: "Default VPC".to_string(),
         },
+        labels: Default::default(),
         ipv6_prefix,
         // TODO-robustness this will need to be None if we decide to
         // handle the logic around name and dns_name by making
@@ -179,6 +180,7 @@ mod test {
                     name: "my-project".parse().unwrap(),
                     description: "My Project".to_string(),
                 },
+                labels: Default::default(),
             },
             authz_silo,
         }
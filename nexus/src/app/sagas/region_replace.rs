@@ -0,0 +1,772 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Replace a Crucible region that was lost along with its dataset.
+//!
+//! When a physical disk, zpool or sled goes away, each region on it leaves
+//! its volume one downstairs short. This saga allocates a region of the same
+//! size on another dataset, has the Crucible agent create it, and swaps the
+//! lost region's target for the new region's in the volume construction
+//! request. The new region starts out empty; the upstairs fills it in from
+//! the volume's other regions when it next activates the volume.
+//!
+//! How soon that happens depends on what's using the disk:
+//!
+//! - A detached disk is attached to a Pantry and detached again straight
+//!   away, which repairs the new region.
+//! - For a disk attached to a running instance, the instance's sled agent is
+//!   sent the new volume construction request. The running Propolis keeps
+//!   using the old targets, so the new region is only repaired once the
+//!   instance is restarted or migrated, and the replacement is left awaiting
+//!   that.  The saga's output, and the replacement's record, name the
+//!   instance that needs restarting.
+//! - Otherwise nothing has the volume open, and whichever upstairs activates
+//!   it next repairs the new region.
+//!
+//! The database doesn't record which port each region's downstairs listens
+//! on, and the lost region's Crucible agent can't be asked, so the lost
+//! region's target is found by elimination: it's the target that doesn't
+//! belong to any of the volume's regions that are still around.
+
+use super::{
+    common_storage::{
+        call_pantry_attach_for_disk, call_pantry_detach_for_disk,
+        delete_crucible_regions, ensure_region_in_dataset, get_pantry_address,
+    },
+    ActionRegistry, NexusActionContext, NexusSaga, SagaInitError,
+    ACTION_GENERATE_ID,
+};
+use crate::app::sagas::declare_saga_actions;
+use crate::db::identity::{Asset, Resource};
+use crate::db::lookup::LookupPath;
+use crate::{authn, authz, db};
+use crucible_agent_client::{types::RegionId, Client as CrucibleAgentClient};
+use nexus_db_model::Generation;
+use nexus_db_model::RegionReplacementRestart;
+use nexus_db_model::RegionReplacementState;
+use omicron_common::api::external;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
+use serde::Deserialize;
+use serde::Serialize;
+use sled_agent_client::types::VolumeConstructionRequest;
+use std::collections::BTreeSet;
+use std::net::SocketAddrV6;
+use steno::ActionError;
+use steno::Node;
+use uuid::Uuid;
+
+// region replace saga: input parameters
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Params {
+    pub serialized_authn: authn::saga::Serialized,
+    pub replacement_id: Uuid,
+    pub disk_id: Uuid,
+    pub old_region: db::model::Region,
+}
+
+// region replace saga: actions
+
+declare_saga_actions! {
+    region_replace;
+    SET_RUNNING -> "set_running" {
+        + srr_set_running
+        - srr_set_running_undo
+    }
+    NEW_REGION_ALLOC -> "new_dataset_and_region" {
+        + srr_alloc_new_region
+        - srr_alloc_new_region_undo
+    }
+    NEW_REGION_ENSURE -> "new_target" {
+        + srr_ensure_new_region
+        - srr_ensure_new_region_undo
+    }
+    FIND_OLD_TARGET -> "old_target" {
+        + srr_find_old_target
+    }
+    REPLACE_TARGET -> "replaced_target" {
+        + srr_replace_target
+        - srr_replace_target_undo
+    }
+    SET_DISK_MAINTENANCE -> "pantry_repair" {
+        + srr_set_disk_maintenance
+        - srr_set_disk_maintenance_undo
+    }
+    CALL_PANTRY_ATTACH_FOR_DISK -> "call_pantry_attach_for_disk" {
+        + srr_call_pantry_attach_for_disk
+        - srr_call_pantry_attach_for_disk_undo
+    }
+    CALL_PANTRY_DETACH_FOR_DISK -> "call_pantry_detach_for_disk" {
+        + srr_call_pantry_detach_for_disk
+    }
+    SET_DISK_DETACHED -> "set_disk_detached" {
+        + srr_set_disk_detached
+    }
+    SEND_VOLUME_TO_SLED_AGENT -> "restart" {
+        + srr_send_volume_to_sled_agent
+    }
+    FINISH -> "output" {
+        + srr_finish
+    }
+}
+
+// region replace saga: definition
+
+#[derive(Debug)]
+pub struct SagaRegionReplace;
+impl NexusSaga for SagaRegionReplace {
+    const NAME: &'static str = "region-replace";
+    type Params = Params;
+
+    fn register_actions(registry: &mut ActionRegistry) {
+        region_replace_register_actions(registry);
+    }
+
+    fn make_saga_dag(
+        _params: &Self::Params,
+        mut builder: steno::DagBuilder,
+    ) -> Result<steno::Dag, SagaInitError> {
+        builder.append(Node::action(
+            "new_region_id",
+            "GenerateNewRegionId",
+            ACTION_GENERATE_ID.as_ref(),
+        ));
+
+        builder.append(set_running_action());
+        builder.append(new_region_alloc_action());
+        builder.append(new_region_ensure_action());
+        builder.append(find_old_target_action());
+        builder.append(replace_target_action());
+        builder.append(set_disk_maintenance_action());
+        builder.append(call_pantry_attach_for_disk_action());
+        builder.append(call_pantry_detach_for_disk_action());
+        builder.append(set_disk_detached_action());
+        builder.append(send_volume_to_sled_agent_action());
+        builder.append(finish_action());
+
+        Ok(builder.build()?)
+    }
+}
+
+// region replace saga: action implementations
+
+async fn srr_set_running(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    // This is a no-op when replaying this node.
+    osagactx
+        .datastore()
+        .region_replacement_set_state(
+            &opctx,
+            params.replacement_id,
+            RegionReplacementState::Requested,
+            RegionReplacementState::Running,
+            None,
+            None,
+        )
+        .await
+        .map_err(ActionError::action_failed)?;
+    Ok(())
+}
+
+async fn srr_set_running_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    osagactx
+        .datastore()
+        .region_replacement_set_state(
+            &opctx,
+            params.replacement_id,
+            RegionReplacementState::Running,
+            RegionReplacementState::Requested,
+            None,
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+async fn srr_alloc_new_region(
+    sagactx: NexusActionContext,
+) -> Result<(db::model::Dataset, db::model::Region), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let new_region_id = sagactx.lookup::<Uuid>("new_region_id")?;
+
+    osagactx
+        .datastore()
+        .region_replacement_allocate(&opctx, new_region_id, &params.old_region)
+        .await
+        .map_err(ActionError::action_failed)
+}
+
+async fn srr_alloc_new_region_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let new_region_id = sagactx.lookup::<Uuid>("new_region_id")?;
+
+    osagactx.datastore().regions_hard_delete(vec![new_region_id]).await?;
+    Ok(())
+}
+
+/// Call out to the Crucible agent to create the new region, returning the
+/// target that the upstairs uses to reach it.
+async fn srr_ensure_new_region(
+    sagactx: NexusActionContext,
+) -> Result<SocketAddrV6, ActionError> {
+    let log = sagactx.user_data().log();
+    let (dataset, region) = sagactx
+        .lookup::<(db::model::Dataset, db::model::Region)>(
+            "new_dataset_and_region",
+        )?;
+
+    let crucible_region = ensure_region_in_dataset(log, &dataset, &region)
+        .await
+        .map_err(ActionError::action_failed)?;
+    Ok(dataset.address_with_port(crucible_region.port_number))
+}
+
+async fn srr_ensure_new_region_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    warn!(log, "srr_ensure_new_region_undo: Deleting crucible region");
+    delete_crucible_regions(vec![sagactx
+        .lookup::<(db::model::Dataset, db::model::Region)>(
+            "new_dataset_and_region",
+        )?])
+    .await?;
+    info!(log, "srr_ensure_new_region_undo: Deleted crucible region");
+    Ok(())
+}
+
+/// Find the lost region's target in the volume construction request.
+async fn srr_find_old_target(
+    sagactx: NexusActionContext,
+) -> Result<SocketAddrV6, ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let volume_id = params.old_region.volume_id();
+    let new_target = sagactx.lookup::<SocketAddrV6>("new_target")?;
+
+    // Ask the Crucible agents of the volume's regions that are still around
+    // where those regions can be reached. Regions that have been lost
+    // themselves are skipped: their agents are gone too.
+    let lost_region_ids: BTreeSet<Uuid> = osagactx
+        .datastore()
+        .region_replacement_old_region_ids(&opctx, volume_id)
+        .await
+        .map_err(ActionError::action_failed)?
+        .into_iter()
+        .collect();
+    let datasets_and_regions = osagactx
+        .datastore()
+        .get_allocated_regions(volume_id)
+        .await
+        .map_err(ActionError::action_failed)?;
+    let mut live_targets = BTreeSet::from([new_target]);
+    for (dataset, region) in datasets_and_regions {
+        if lost_region_ids.contains(&region.id()) {
+            continue;
+        }
+
+        let url = format!("http://{}", dataset.address());
+        let client = CrucibleAgentClient::new(&url);
+        let crucible_region = client
+            .region_get(&RegionId(region.id().to_string()))
+            .await
+            .map_err(|e| e.to_string())
+            .map_err(ActionError::action_failed)?;
+        live_targets
+            .insert(dataset.address_with_port(crucible_region.port_number));
+    }
+
+    let volume = osagactx
+        .datastore()
+        .volume_checkout(volume_id)
+        .await
+        .map_err(ActionError::action_failed)?;
+    let vcr: VolumeConstructionRequest = serde_json::from_str(volume.data())
+        .map_err(|e| {
+            ActionError::action_failed(Error::internal_error(&format!(
+                "failed to deserialize volume data: {}",
+                e,
+            )))
+        })?;
+
+    let old_target = find_lost_target(&vcr, &params.old_region, &live_targets)
+        .ok_or_else(|| {
+            ActionError::action_failed(Error::internal_error(&format!(
+                "volume {} has no target that could be lost region {}",
+                volume_id,
+                params.old_region.id(),
+            )))
+        })?;
+    info!(
+        log,
+        "region {} was reached at {}",
+        params.old_region.id(),
+        old_target,
+    );
+    Ok(old_target)
+}
+
+/// Returns a target of one of the read-write region sub-volumes of `vcr` that
+/// are shaped like `old_region`, and that isn't in `live_targets`
+///
+/// If more than one of the volume's regions were lost, there may be several
+/// such targets to choose from. Any of them will do, because the regions
+/// they belong to are equally gone, and each gets replaced in turn.
+fn find_lost_target(
+    vcr: &VolumeConstructionRequest,
+    old_region: &db::model::Region,
+    live_targets: &BTreeSet<SocketAddrV6>,
+) -> Option<SocketAddrV6> {
+    match vcr {
+        VolumeConstructionRequest::Volume { sub_volumes, .. } => sub_volumes
+            .iter()
+            .find_map(|sv| find_lost_target(sv, old_region, live_targets)),
+
+        VolumeConstructionRequest::Region {
+            block_size,
+            blocks_per_extent,
+            extent_count,
+            opts,
+            ..
+        } => {
+            if opts.read_only
+                || *block_size != old_region.block_size().to_bytes()
+                || *blocks_per_extent != old_region.blocks_per_extent() as u64
+                || u64::from(*extent_count) != old_region.extent_count() as u64
+            {
+                return None;
+            }
+            opts.target
+                .iter()
+                .filter_map(|target| target.parse::<SocketAddrV6>().ok())
+                .find(|target| !live_targets.contains(target))
+        }
+
+        VolumeConstructionRequest::Url { .. }
+        | VolumeConstructionRequest::File { .. } => None,
+    }
+}
+
+async fn srr_replace_target(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let old_target = sagactx.lookup::<SocketAddrV6>("old_target")?;
+    let new_target = sagactx.lookup::<SocketAddrV6>("new_target")?;
+
+    osagactx
+        .datastore()
+        .volume_replace_region_target(
+            params.old_region.volume_id(),
+            old_target,
+            new_target,
+        )
+        .await
+        .map_err(ActionError::action_failed)?;
+    Ok(())
+}
+
+async fn srr_replace_target_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let old_target = sagactx.lookup::<SocketAddrV6>("old_target")?;
+    let new_target = sagactx.lookup::<SocketAddrV6>("new_target")?;
+
+    osagactx
+        .datastore()
+        .volume_replace_region_target(
+            params.old_region.volume_id(),
+            new_target,
+            old_target,
+        )
+        .await?;
+    Ok(())
+}
+
+/// If the disk is detached, put it into maintenance so that nothing else
+/// attaches it while a Pantry repairs the new region, returning the Pantry to
+/// use and the disk's new generation number.
+async fn srr_set_disk_maintenance(
+    sagactx: NexusActionContext,
+) -> Result<Option<(SocketAddrV6, Generation)>, ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., authz_disk, db_disk) =
+        LookupPath::new(&opctx, &osagactx.datastore())
+            .disk_id(params.disk_id)
+            .fetch_for(authz::Action::Modify)
+            .await
+            .map_err(ActionError::action_failed)?;
+
+    let disk_state: external::DiskState = db_disk.state().into();
+    if disk_state != external::DiskState::Detached {
+        info!(
+            log,
+            "disk {} is in state {:?}, not repairing it with a pantry",
+            params.disk_id,
+            db_disk.state(),
+        );
+        return Ok(None);
+    }
+
+    let pantry_address = get_pantry_address(osagactx.nexus()).await?;
+
+    // If the disk is attached to an instance after it was fetched, this
+    // update fails because the generation number is too low.
+    info!(log, "setting state of {} to maintenance", params.disk_id);
+    osagactx
+        .datastore()
+        .disk_update_runtime(
+            &opctx,
+            &authz_disk,
+            &db_disk.runtime().maintenance(),
+        )
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    let (.., db_disk) = LookupPath::new(&opctx, &osagactx.datastore())
+        .disk_id(params.disk_id)
+        .fetch_for(authz::Action::Read)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    Ok(Some((pantry_address, db_disk.runtime().gen)))
+}
+
+async fn srr_set_disk_maintenance_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let Some((_, disk_generation)) = sagactx
+        .lookup::<Option<(SocketAddrV6, Generation)>>("pantry_repair")?
+    else {
+        return Ok(());
+    };
+
+    let (.., authz_disk, db_disk) =
+        LookupPath::new(&opctx, &osagactx.datastore())
+            .disk_id(params.disk_id)
+            .fetch_for(authz::Action::Modify)
+            .await?;
+
+    let disk_state: external::DiskState = db_disk.state().into();
+    if disk_state == external::DiskState::Maintenance
+        && db_disk.runtime().gen == disk_generation
+    {
+        info!(
+            log,
+            "undo: setting disk {} state from maintenance to detached",
+            params.disk_id
+        );
+        osagactx
+            .datastore()
+            .disk_update_runtime(
+                &opctx,
+                &authz_disk,
+                &db_disk.runtime().detach(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Attach the volume to a Pantry. Activating the volume there makes the
+/// upstairs repair the new region from the volume's other regions.
+async fn srr_call_pantry_attach_for_disk(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let Some((pantry_address, _)) = sagactx
+        .lookup::<Option<(SocketAddrV6, Generation)>>("pantry_repair")?
+    else {
+        return Ok(());
+    };
+
+    info!(
+        log,
+        "attaching disk {} to pantry at {} to repair it",
+        params.disk_id,
+        pantry_address,
+    );
+    call_pantry_attach_for_disk(
+        &log,
+        &opctx,
+        &osagactx.nexus(),
+        params.disk_id,
+        pantry_address,
+    )
+    .await
+}
+
+async fn srr_call_pantry_attach_for_disk_undo(
+    sagactx: NexusActionContext,
+) -> Result<(), anyhow::Error> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+
+    let Some((pantry_address, _)) = sagactx
+        .lookup::<Option<(SocketAddrV6, Generation)>>("pantry_repair")?
+    else {
+        return Ok(());
+    };
+
+    info!(
+        log,
+        "undo: detaching disk {} from pantry at {}",
+        params.disk_id,
+        pantry_address,
+    );
+    call_pantry_detach_for_disk(&log, params.disk_id, pantry_address).await?;
+    Ok(())
+}
+
+async fn srr_call_pantry_detach_for_disk(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let params = sagactx.saga_params::<Params>()?;
+
+    let Some((pantry_address, _)) = sagactx
+        .lookup::<Option<(SocketAddrV6, Generation)>>("pantry_repair")?
+    else {
+        return Ok(());
+    };
+
+    call_pantry_detach_for_disk(&log, params.disk_id, pantry_address).await
+}
+
+async fn srr_set_disk_detached(
+    sagactx: NexusActionContext,
+) -> Result<(), ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let Some((_, disk_generation)) = sagactx
+        .lookup::<Option<(SocketAddrV6, Generation)>>("pantry_repair")?
+    else {
+        return Ok(());
+    };
+
+    let (.., authz_disk, db_disk) =
+        LookupPath::new(&opctx, &osagactx.datastore())
+            .disk_id(params.disk_id)
+            .fetch_for(authz::Action::Modify)
+            .await
+            .map_err(ActionError::action_failed)?;
+
+    // Only take the disk out of maintenance if this saga is the one that put
+    // it there: when replaying this node, another saga may have put it back.
+    let disk_state: external::DiskState = db_disk.state().into();
+    if disk_state == external::DiskState::Maintenance
+        && db_disk.runtime().gen == disk_generation
+    {
+        info!(
+            log,
+            "setting disk {} state from maintenance to detached",
+            params.disk_id
+        );
+        osagactx
+            .datastore()
+            .disk_update_runtime(
+                &opctx,
+                &authz_disk,
+                &db_disk.runtime().detach(),
+            )
+            .await
+            .map_err(ActionError::action_failed)?;
+    }
+    Ok(())
+}
+
+/// If the disk is attached to a running instance, send the instance's sled
+/// agent the new volume construction request, returning the instance, which
+/// must be restarted because its Propolis is still using the old one.
+async fn srr_send_volume_to_sled_agent(
+    sagactx: NexusActionContext,
+) -> Result<Option<RegionReplacementRestart>, ActionError> {
+    let log = sagactx.user_data().log();
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+
+    let (.., disk) = LookupPath::new(&opctx, &osagactx.datastore())
+        .disk_id(params.disk_id)
+        .fetch()
+        .await
+        .map_err(ActionError::action_failed)?;
+    let Some(instance_id) = disk.runtime().attach_instance_id else {
+        return Ok(None);
+    };
+
+    let (.., instance) = LookupPath::new(&opctx, &osagactx.datastore())
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .map_err(ActionError::action_failed)?;
+    match instance.runtime().state.0 {
+        InstanceState::Running
+        | InstanceState::Rebooting
+        | InstanceState::Migrating => {}
+        _ => {
+            info!(
+                log,
+                "disk {} instance {} not running, the next Propolis will \
+                repair the new region",
+                disk.id(),
+                instance_id,
+            );
+            return Ok(None);
+        }
+    }
+
+    let sled_agent_client = osagactx
+        .nexus()
+        .instance_sled(&instance)
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    let volume = osagactx
+        .datastore()
+        .volume_checkout(params.old_region.volume_id())
+        .await
+        .map_err(ActionError::action_failed)?;
+    let volume_construction_request: VolumeConstructionRequest =
+        serde_json::from_str(volume.data()).map_err(|e| {
+            ActionError::action_failed(Error::internal_error(&format!(
+                "failed to deserialize volume data: {}",
+                e,
+            )))
+        })?;
+
+    info!(
+        log,
+        "sending volume with replaced region for disk {} to instance {}",
+        disk.id(),
+        instance_id,
+    );
+
+    // The sled agent records the new volume construction request for the next
+    // Propolis it starts for the instance.  The running Propolis keeps using
+    // the regions it was started with, so the replacement awaits a restart.
+    sled_agent_client
+        .instance_issue_disk_replace_request(
+            &instance.id(),
+            &disk.id(),
+            &sled_agent_client::types::InstanceIssueDiskReplaceRequestBody {
+                volume_construction_request,
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())
+        .map_err(ActionError::action_failed)?;
+
+    Ok(Some(RegionReplacementRestart {
+        instance_id: instance.id(),
+        propolis_id: instance.runtime().propolis_id,
+    }))
+}
+
+/// Record the outcome of the replacement, returning the instance that must be
+/// restarted before the new region is repaired, if there is one
+async fn srr_finish(
+    sagactx: NexusActionContext,
+) -> Result<Option<RegionReplacementRestart>, ActionError> {
+    let osagactx = sagactx.user_data();
+    let params = sagactx.saga_params::<Params>()?;
+    let opctx = crate::context::op_context_for_saga_action(
+        &sagactx,
+        &params.serialized_authn,
+    );
+    let new_region_id = sagactx.lookup::<Uuid>("new_region_id")?;
+    let restart =
+        sagactx.lookup::<Option<RegionReplacementRestart>>("restart")?;
+
+    // The lost region's record goes away now that it's no longer part of the
+    // volume; its Crucible agent is gone, so there's nothing else to clean up.
+    osagactx
+        .datastore()
+        .regions_hard_delete(vec![params.old_region.id()])
+        .await
+        .map_err(ActionError::action_failed)?;
+
+    let state = if restart.is_some() {
+        RegionReplacementState::AwaitingRestart
+    } else {
+        RegionReplacementState::Complete
+    };
+    osagactx
+        .datastore()
+        .region_replacement_set_state(
+            &opctx,
+            params.replacement_id,
+            RegionReplacementState::Running,
+            state,
+            Some(new_region_id),
+            restart,
+        )
+        .await
+        .map_err(ActionError::action_failed)?;
+    Ok(restart)
+}
@@ -130,6 +130,9 @@ pub struct Params {
     pub disk_id: Uuid,
     pub use_the_pantry: bool,
     pub create_params: params::SnapshotCreate,
+    /// the snapshot schedule on whose behalf the snapshot is being taken, if
+    /// any
+    pub snapshot_schedule_id: Option<Uuid>,
 }
 
 // snapshot create saga: actions
@@ -527,6 +530,7 @@ async fn ssc_create_snapshot_record(
             snapshot_id,
             params.create_params.identity.clone(),
         ),
+        labels: params.create_params.labels.clone().into(),
 
         project_id: params.project_id,
         disk_id: disk.id(),
@@ -537,6 +541,7 @@ async fn ssc_create_snapshot_record(
         state: db::model::SnapshotState::Creating,
         block_size: disk.block_size,
         size: disk.size,
+        snapshot_schedule_id: params.snapshot_schedule_id,
     };
 
     let (.., authz_project) = LookupPath::new(&opctx, &osagactx.datastore())
@@ -1790,8 +1795,10 @@ mod test {
                     name: "my-snapshot".parse().expect("Invalid disk name"),
                     description: "My snapshot".to_string(),
                 },
+                labels: Default::default(),
                 disk,
             },
+            snapshot_schedule_id: None,
         }
     }
 
@@ -2213,6 +2220,7 @@ mod test {
                     name: instance_name.parse().unwrap(),
                     description: format!("instance {:?}", instance_name),
                 },
+                labels: Default::default(),
                 ncpus: InstanceCpuCount(2),
                 memory: ByteCount::from_gibibytes_u32(1),
                 hostname: String::from("base_instance"),
@@ -2225,6 +2233,8 @@ mod test {
                     params::InstanceDiskAttach { name: Name::from_str(DISK_NAME).unwrap() },
                 )],
                 external_ips: vec![],
+                affinity_groups: Vec::new(),
+                auto_restart_policy: Default::default(),
                 start: true,
             },
         )
@@ -481,6 +481,7 @@ pub(crate) mod test {
                     name: "my-vpc".parse().unwrap(),
                     description: "My VPC".to_string(),
                 },
+                labels: Default::default(),
                 ipv6_prefix: None,
                 dns_name: "abc".parse().unwrap(),
             },
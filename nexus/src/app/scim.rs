@@ -0,0 +1,614 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! SCIM 2.0 provisioning of Silo users and groups, and the tokens with which
+//! SCIM clients authenticate.
+
+use crate::authz;
+use crate::authz::ApiResource;
+use crate::db;
+use crate::db::identity::Asset;
+use crate::db::lookup;
+use crate::db::lookup::LookupPath;
+use crate::external_api::params;
+use crate::external_api::scim::{ScimGroupUpdate, ScimUserUpdate};
+use chrono::Utc;
+use nexus_db_model::UserProvisionType;
+use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::datastore::SiloGroupMembershipUpdate;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DataPageParams;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::NameOrId;
+use omicron_common::api::external::ResourceType;
+use omicron_common::api::external::UpdateResult;
+use std::num::NonZeroU32;
+use uuid::Uuid;
+
+/// Number of users or groups to fetch from the database at a time when
+/// assembling a SCIM list response
+const SCIM_LIST_BATCH_SIZE: u32 = 100;
+
+impl super::Nexus {
+    // SCIM client tokens
+
+    pub fn scim_client_token_lookup<'a>(
+        &'a self,
+        opctx: &'a OpContext,
+        scim_client_token_selector: params::ScimClientTokenSelector,
+    ) -> LookupResult<lookup::ScimClientToken<'a>> {
+        match scim_client_token_selector {
+            params::ScimClientTokenSelector {
+                token: NameOrId::Id(id),
+                silo: None,
+            } => {
+                let token = LookupPath::new(opctx, &self.db_datastore)
+                    .scim_client_token_id(id);
+                Ok(token)
+            }
+            params::ScimClientTokenSelector {
+                token: NameOrId::Name(name),
+                silo: Some(silo),
+            } => {
+                let token = self
+                    .silo_lookup(opctx, silo)?
+                    .scim_client_token_name_owned(name.into());
+                Ok(token)
+            }
+            params::ScimClientTokenSelector {
+                token: NameOrId::Id(_),
+                silo: _,
+            } => Err(Error::invalid_request(
+                "when providing token as an ID, silo should not be specified",
+            )),
+            _ => Err(Error::invalid_request(
+                "token should either be a UUID or silo should be specified",
+            )),
+        }
+    }
+
+    /// Create a token with which a SCIM client can manage a Silo's users and
+    /// groups
+    ///
+    /// Only Silos whose users are provisioned through the API support SCIM.
+    pub async fn scim_client_token_create(
+        &self,
+        opctx: &OpContext,
+        silo_lookup: &lookup::Silo<'_>,
+        params: params::ScimClientTokenCreate,
+    ) -> CreateResult<db::model::ScimClientToken> {
+        let (authz_silo, db_silo) =
+            silo_lookup.fetch_for(authz::Action::Modify).await?;
+        if db_silo.user_provision_type != UserProvisionType::ApiOnly {
+            return Err(Error::invalid_request(
+                "SCIM provisioning is only supported in Silos whose users \
+                are provisioned via the API",
+            ));
+        }
+        if let Some(time_expires) = params.time_expires {
+            if time_expires <= Utc::now() {
+                return Err(Error::InvalidValue {
+                    label: String::from("time_expires"),
+                    message: String::from(
+                        "expiration time must be in the future",
+                    ),
+                });
+            }
+        }
+        let token = db::model::ScimClientToken::new(authz_silo.id(), params);
+        self.db_datastore
+            .scim_client_token_create(opctx, &authz_silo, token)
+            .await
+    }
+
+    pub async fn scim_client_tokens_list(
+        &self,
+        opctx: &OpContext,
+        silo_lookup: &lookup::Silo<'_>,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<db::model::ScimClientToken> {
+        let (authz_silo,) =
+            silo_lookup.lookup_for(authz::Action::Modify).await?;
+        self.db_datastore
+            .scim_client_tokens_list(opctx, &authz_silo, pagparams)
+            .await
+    }
+
+    pub async fn scim_client_token_delete(
+        &self,
+        opctx: &OpContext,
+        scim_client_token_lookup: &lookup::ScimClientToken<'_>,
+    ) -> DeleteResult {
+        let (.., authz_token) =
+            scim_client_token_lookup.lookup_for(authz::Action::Delete).await?;
+        self.db_datastore.scim_client_token_delete(opctx, &authz_token).await
+    }
+
+    // SCIM provisioning
+    //
+    // SCIM clients are not users, so requests from them are carried out using
+    // the Nexus "external authentication" context once the client's token has
+    // been used to determine which Silo it may manage (see
+    // `scim_silo_for_token()`).  Every function below is given that Silo, and
+    // only ever operates on users and groups inside it.
+
+    /// Determine the Silo whose users and groups a SCIM client token may
+    /// manage
+    pub async fn scim_silo_for_token(
+        &self,
+        opctx: &OpContext,
+        token: String,
+    ) -> LookupResult<(authz::Silo, db::model::Silo)> {
+        let db_token = self
+            .db_datastore
+            .scim_client_token_fetch_by_token(opctx, token)
+            .await
+            .map_err(|e| match e {
+                Error::ObjectNotFound { .. } => Error::Unauthenticated {
+                    internal_message: String::from("unknown SCIM client token"),
+                },
+                e => e,
+            })?;
+        if db_token.is_expired(Utc::now()) {
+            return Err(Error::Unauthenticated {
+                internal_message: String::from("SCIM client token expired"),
+            });
+        }
+
+        let (authz_silo, db_silo) = LookupPath::new(opctx, &self.db_datastore)
+            .silo_id(db_token.silo_id)
+            .fetch()
+            .await
+            .map_err(|e| match e {
+                Error::ObjectNotFound { .. } => Error::Unauthenticated {
+                    internal_message: format!(
+                        "Silo {} for SCIM client token not found",
+                        db_token.silo_id
+                    ),
+                },
+                e => e,
+            })?;
+        if db_silo.user_provision_type != UserProvisionType::ApiOnly {
+            return Err(Error::Unauthenticated {
+                internal_message: format!(
+                    "Silo {} does not support SCIM provisioning",
+                    authz_silo.id()
+                ),
+            });
+        }
+
+        Ok((authz_silo, db_silo))
+    }
+
+    /// List all of a Silo's users, or just the one with the given user name
+    pub async fn scim_users_list(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        user_name: Option<&str>,
+    ) -> ListResultVec<db::model::SiloUser> {
+        if let Some(user_name) = user_name {
+            return Ok(self
+                .db_datastore
+                .silo_user_fetch_by_external_id(opctx, authz_silo, user_name)
+                .await?
+                .into_iter()
+                .map(|(_, db_silo_user)| db_silo_user)
+                .collect());
+        }
+
+        let authz_silo_user_list = authz::SiloUserList::new(authz_silo.clone());
+        let mut users = Vec::new();
+        let mut marker = None;
+        loop {
+            let batch = self
+                .db_datastore
+                .silo_users_list(
+                    opctx,
+                    &authz_silo_user_list,
+                    &DataPageParams {
+                        marker: marker.as_ref(),
+                        direction: dropshot::PaginationOrder::Ascending,
+                        limit: NonZeroU32::new(SCIM_LIST_BATCH_SIZE).unwrap(),
+                    },
+                )
+                .await?;
+            let done = batch.len() < SCIM_LIST_BATCH_SIZE as usize;
+            marker = batch.last().map(|user| user.id());
+            users.extend(batch);
+            if done {
+                return Ok(users);
+            }
+        }
+    }
+
+    /// Fetch one of a Silo's users, along with the ids of the groups they're
+    /// in
+    pub async fn scim_user_fetch(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        silo_user_id: Uuid,
+    ) -> LookupResult<(db::model::SiloUser, Vec<Uuid>)> {
+        let (_, db_silo_user) = self
+            .silo_user_lookup_by_id(
+                opctx,
+                authz_silo,
+                silo_user_id,
+                authz::Action::Read,
+            )
+            .await?;
+        let group_ids =
+            self.scim_user_group_ids(opctx, authz_silo, silo_user_id).await?;
+        Ok((db_silo_user, group_ids))
+    }
+
+    /// Returns the ids of the groups that a user is in
+    pub async fn scim_user_group_ids(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        silo_user_id: Uuid,
+    ) -> ListResultVec<Uuid> {
+        Ok(self
+            .db_datastore
+            .silo_group_membership_for_user(opctx, authz_silo, silo_user_id)
+            .await?
+            .into_iter()
+            .map(|membership| membership.silo_group_id)
+            .collect())
+    }
+
+    pub async fn scim_user_create(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        db_silo: &db::model::Silo,
+        user_name: String,
+        update: ScimUserUpdate,
+    ) -> CreateResult<db::model::SiloUser> {
+        opctx.authorize(authz::Action::CreateChild, authz_silo).await?;
+        if user_name.is_empty() {
+            return Err(Error::invalid_request("userName must not be empty"));
+        }
+
+        let mut silo_user = db::model::SiloUser::new(
+            authz_silo.id(),
+            Uuid::new_v4(),
+            user_name,
+        );
+        silo_user.active = update.active.unwrap_or(true);
+        let (authz_silo_user, db_silo_user) =
+            self.db_datastore.silo_user_create(authz_silo, silo_user).await?;
+        if let Some(password) = update.password {
+            self.silo_user_password_set_internal(
+                opctx,
+                db_silo,
+                &authz_silo_user,
+                &db_silo_user,
+                params::UserPassword::Password(password),
+            )
+            .await?;
+        }
+        Ok(db_silo_user)
+    }
+
+    /// Update a user's name, whether they're active, and/or their password
+    pub async fn scim_user_update(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        db_silo: &db::model::Silo,
+        silo_user_id: Uuid,
+        update: ScimUserUpdate,
+    ) -> UpdateResult<db::model::SiloUser> {
+        let (authz_silo_user, mut db_silo_user) = self
+            .silo_user_lookup_by_id(
+                opctx,
+                authz_silo,
+                silo_user_id,
+                authz::Action::Modify,
+            )
+            .await?;
+
+        if let Some(user_name) = &update.user_name {
+            if user_name.is_empty() {
+                return Err(Error::invalid_request(
+                    "userName must not be empty",
+                ));
+            }
+        }
+        if update.user_name.is_some() || update.active.is_some() {
+            db_silo_user = self
+                .db_datastore
+                .silo_user_update(
+                    opctx,
+                    &authz_silo_user,
+                    db::model::SiloUserUpdate {
+                        external_id: update.user_name,
+                        active: update.active,
+                        time_modified: Utc::now(),
+                    },
+                )
+                .await?;
+        }
+        if let Some(password) = update.password {
+            self.silo_user_password_set_internal(
+                opctx,
+                db_silo,
+                &authz_silo_user,
+                &db_silo_user,
+                params::UserPassword::Password(password),
+            )
+            .await?;
+        }
+        Ok(db_silo_user)
+    }
+
+    pub async fn scim_user_delete(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        silo_user_id: Uuid,
+    ) -> DeleteResult {
+        let (authz_silo_user, _) = self
+            .silo_user_lookup_by_id(
+                opctx,
+                authz_silo,
+                silo_user_id,
+                authz::Action::Delete,
+            )
+            .await?;
+        self.db_datastore.silo_user_delete(opctx, &authz_silo_user).await
+    }
+
+    /// List all of a Silo's groups, or just the one with the given name
+    pub async fn scim_groups_list(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        display_name: Option<&str>,
+    ) -> ListResultVec<db::model::SiloGroup> {
+        if let Some(display_name) = display_name {
+            return Ok(self
+                .db_datastore
+                .silo_group_optional_lookup(
+                    opctx,
+                    authz_silo,
+                    display_name.to_string(),
+                )
+                .await?
+                .into_iter()
+                .collect());
+        }
+
+        let mut groups = Vec::new();
+        let mut marker = None;
+        loop {
+            let batch = self
+                .db_datastore
+                .silo_groups_list_by_id(
+                    opctx,
+                    authz_silo,
+                    &DataPageParams {
+                        marker: marker.as_ref(),
+                        direction: dropshot::PaginationOrder::Ascending,
+                        limit: NonZeroU32::new(SCIM_LIST_BATCH_SIZE).unwrap(),
+                    },
+                )
+                .await?;
+            let done = batch.len() < SCIM_LIST_BATCH_SIZE as usize;
+            marker = batch.last().map(|group| group.id());
+            groups.extend(batch);
+            if done {
+                return Ok(groups);
+            }
+        }
+    }
+
+    /// Fetch one of a Silo's groups, along with the ids of its members
+    pub async fn scim_group_fetch(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        silo_group_id: Uuid,
+    ) -> LookupResult<(db::model::SiloGroup, Vec<Uuid>)> {
+        let (authz_silo_group, db_silo_group) = self
+            .scim_group_lookup_by_id(
+                opctx,
+                authz_silo,
+                silo_group_id,
+                authz::Action::Read,
+            )
+            .await?;
+        let member_ids =
+            self.scim_group_member_ids(opctx, &authz_silo_group).await?;
+        Ok((db_silo_group, member_ids))
+    }
+
+    /// Returns the ids of a group's members
+    pub async fn scim_group_member_ids(
+        &self,
+        opctx: &OpContext,
+        authz_silo_group: &authz::SiloGroup,
+    ) -> ListResultVec<Uuid> {
+        Ok(self
+            .db_datastore
+            .silo_group_membership_for_group(opctx, authz_silo_group)
+            .await?
+            .into_iter()
+            .map(|membership| membership.silo_user_id)
+            .collect())
+    }
+
+    pub async fn scim_group_create(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        display_name: String,
+        member_ids: Vec<Uuid>,
+    ) -> CreateResult<(db::model::SiloGroup, Vec<Uuid>)> {
+        if display_name.is_empty() {
+            return Err(Error::invalid_request(
+                "displayName must not be empty",
+            ));
+        }
+
+        // `silo_group_ensure()` quietly returns any existing group with the
+        // same name, but SCIM clients expect to be told about the conflict.
+        if self
+            .db_datastore
+            .silo_group_optional_lookup(opctx, authz_silo, display_name.clone())
+            .await?
+            .is_some()
+        {
+            return Err(Error::ObjectAlreadyExists {
+                type_name: ResourceType::SiloGroup,
+                object_name: display_name,
+            });
+        }
+
+        let db_silo_group = self
+            .db_datastore
+            .silo_group_ensure(
+                opctx,
+                authz_silo,
+                db::model::SiloGroup::new(
+                    Uuid::new_v4(),
+                    authz_silo.id(),
+                    display_name,
+                ),
+            )
+            .await?;
+        let (authz_silo_group, _) = self
+            .scim_group_lookup_by_id(
+                opctx,
+                authz_silo,
+                db_silo_group.id(),
+                authz::Action::Modify,
+            )
+            .await?;
+        // If any of the members are invalid, don't leave the group behind, or
+        // the client's retry will fail with a conflict.
+        if let Err(error) = self
+            .db_datastore
+            .silo_group_membership_update(
+                opctx,
+                authz_silo,
+                &authz_silo_group,
+                SiloGroupMembershipUpdate::Replace(member_ids),
+            )
+            .await
+        {
+            self.db_datastore
+                .silo_group_delete(opctx, &authz_silo_group)
+                .await?;
+            return Err(error);
+        }
+        let member_ids =
+            self.scim_group_member_ids(opctx, &authz_silo_group).await?;
+        Ok((db_silo_group, member_ids))
+    }
+
+    /// Update a group's name and/or members
+    pub async fn scim_group_update(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        silo_group_id: Uuid,
+        update: ScimGroupUpdate,
+    ) -> UpdateResult<(db::model::SiloGroup, Vec<Uuid>)> {
+        let (authz_silo_group, mut db_silo_group) = self
+            .scim_group_lookup_by_id(
+                opctx,
+                authz_silo,
+                silo_group_id,
+                authz::Action::Modify,
+            )
+            .await?;
+
+        if let Some(display_name) = update.display_name {
+            if display_name.is_empty() {
+                return Err(Error::invalid_request(
+                    "displayName must not be empty",
+                ));
+            }
+            db_silo_group = self
+                .db_datastore
+                .silo_group_update_external_id(
+                    opctx,
+                    &authz_silo_group,
+                    display_name,
+                )
+                .await?;
+        }
+        if let Some(members) = update.members {
+            self.db_datastore
+                .silo_group_membership_update(
+                    opctx,
+                    authz_silo,
+                    &authz_silo_group,
+                    members,
+                )
+                .await?;
+        }
+
+        let member_ids =
+            self.scim_group_member_ids(opctx, &authz_silo_group).await?;
+        Ok((db_silo_group, member_ids))
+    }
+
+    /// Delete a group, removing all of its members first
+    pub async fn scim_group_delete(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        silo_group_id: Uuid,
+    ) -> DeleteResult {
+        let (authz_silo_group, _) = self
+            .scim_group_lookup_by_id(
+                opctx,
+                authz_silo,
+                silo_group_id,
+                authz::Action::Delete,
+            )
+            .await?;
+        self.db_datastore
+            .silo_group_membership_update(
+                opctx,
+                authz_silo,
+                &authz_silo_group,
+                SiloGroupMembershipUpdate::Replace(vec![]),
+            )
+            .await?;
+        self.db_datastore.silo_group_delete(opctx, &authz_silo_group).await
+    }
+
+    /// Helper function for looking up a group in a Silo
+    ///
+    /// Like `silo_user_lookup_by_id()`, this validates that the group is in
+    /// the expected Silo.
+    async fn scim_group_lookup_by_id(
+        &self,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        silo_group_id: Uuid,
+        action: authz::Action,
+    ) -> LookupResult<(authz::SiloGroup, db::model::SiloGroup)> {
+        let (_, authz_silo_group, db_silo_group) =
+            LookupPath::new(opctx, &self.db_datastore)
+                .silo_group_id(silo_group_id)
+                .fetch_for(action)
+                .await?;
+        if db_silo_group.silo_id != authz_silo.id() {
+            return Err(authz_silo_group.not_found());
+        }
+
+        Ok((authz_silo_group, db_silo_group))
+    }
+}
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-//! Silos, Users, and SSH Keys.
+//! Silos, Users, SSH Keys, and Access Tokens.
 
 use crate::authz::ApiResource;
 use crate::db::identity::{Asset, Resource};
@@ -14,6 +14,7 @@ use crate::external_api::params;
 use crate::external_api::shared;
 use crate::{authn, authz};
 use anyhow::Context;
+use chrono::Utc;
 use nexus_db_model::{DnsGroup, UserProvisionType};
 use nexus_db_queries::context::OpContext;
 use nexus_db_queries::db::datastore::DnsVersionUpdateBuilder;
@@ -198,7 +199,7 @@ impl super::Nexus {
     ///
     /// `LookupPath` lets you look up users directly, regardless of what Silo
     /// they're in.  This helper validates that they're in the expected Silo.
-    async fn silo_user_lookup_by_id(
+    pub(super) async fn silo_user_lookup_by_id(
         &self,
         opctx: &OpContext,
         authz_silo: &authz::Silo,
@@ -353,6 +354,10 @@ impl super::Nexus {
 
         let (authz_silo_user, db_silo_user) =
             if let Some(existing_silo_user) = fetch_result {
+                // Users that have been deactivated cannot log in.
+                if !existing_silo_user.1.active {
+                    return Ok(None);
+                }
                 existing_silo_user
             } else {
                 // In this branch, no user exists for the authenticated subject
@@ -469,7 +474,7 @@ impl super::Nexus {
     ///
     /// The caller should have already verified that this is a `LocalOnly` Silo
     /// and that the specified user is in that Silo.
-    async fn silo_user_password_set_internal(
+    pub(super) async fn silo_user_password_set_internal(
         &self,
         opctx: &OpContext,
         db_silo: &db::model::Silo,
@@ -585,6 +590,10 @@ impl super::Nexus {
                 "passed password verification without a valid user"
             );
             let db_user = fetch_user.unwrap().1;
+            // Deactivated users cannot log in, even with the right password.
+            if !db_user.active {
+                return Ok(None);
+            }
             Ok(Some(db_user))
         } else {
             Ok(None)
@@ -690,6 +699,112 @@ impl super::Nexus {
         self.db_datastore.ssh_key_delete(opctx, &authz_ssh_key).await
     }
 
+    // Access tokens
+
+    pub fn access_token_lookup<'a>(
+        &'a self,
+        opctx: &'a OpContext,
+        access_token_selector: &'a params::AccessTokenSelector,
+    ) -> LookupResult<lookup::AccessToken<'a>> {
+        match access_token_selector {
+            params::AccessTokenSelector {
+                silo_user_id: _,
+                access_token: NameOrId::Id(id),
+            } => {
+                let access_token = LookupPath::new(opctx, &self.db_datastore)
+                    .access_token_id(*id);
+                Ok(access_token)
+            }
+            params::AccessTokenSelector {
+                silo_user_id,
+                access_token: NameOrId::Name(name),
+            } => {
+                let access_token = LookupPath::new(opctx, &self.db_datastore)
+                    .silo_user_id(*silo_user_id)
+                    .access_token_name(Name::ref_cast(name));
+                Ok(access_token)
+            }
+        }
+    }
+
+    pub async fn access_token_create(
+        &self,
+        opctx: &OpContext,
+        silo_user_id: Uuid,
+        params: params::AccessTokenCreate,
+    ) -> CreateResult<db::model::AccessToken> {
+        if let Some(time_expires) = params.time_expires {
+            if time_expires <= Utc::now() {
+                return Err(Error::InvalidValue {
+                    label: String::from("time_expires"),
+                    message: String::from(
+                        "expiration time must be in the future",
+                    ),
+                });
+            }
+        }
+        let access_token = db::model::AccessToken::new(silo_user_id, params);
+        let (.., authz_user) = LookupPath::new(opctx, &self.datastore())
+            .silo_user_id(silo_user_id)
+            .lookup_for(authz::Action::CreateChild)
+            .await?;
+        assert_eq!(authz_user.id(), silo_user_id);
+        self.db_datastore
+            .access_token_create(opctx, &authz_user, access_token)
+            .await
+    }
+
+    /// List a user's access tokens
+    ///
+    /// Unlike SSH keys, access tokens are only visible to their owner and to
+    /// those who can modify the user (i.e., Silo administrators).
+    pub async fn access_tokens_list(
+        &self,
+        opctx: &OpContext,
+        silo_user_id: Uuid,
+        page_params: &PaginatedBy<'_>,
+    ) -> ListResultVec<db::model::AccessToken> {
+        let (authz_silo,) = self
+            .current_silo_lookup(opctx)?
+            .lookup_for(authz::Action::Read)
+            .await?;
+        let (authz_user, _) = self
+            .silo_user_lookup_by_id(
+                opctx,
+                &authz_silo,
+                silo_user_id,
+                authz::Action::Modify,
+            )
+            .await?;
+        self.db_datastore
+            .access_tokens_list(opctx, &authz_user, page_params)
+            .await
+    }
+
+    /// Delete (revoke) one of a user's access tokens
+    ///
+    /// The token must belong to the given user, which must be in the current
+    /// Silo.
+    pub async fn access_token_delete(
+        &self,
+        opctx: &OpContext,
+        silo_user_id: Uuid,
+        access_token_lookup: &lookup::AccessToken<'_>,
+    ) -> DeleteResult {
+        let (authz_silo, authz_silo_user, authz_access_token) =
+            access_token_lookup.lookup_for(authz::Action::Delete).await?;
+        let current_silo = opctx
+            .authn
+            .silo_required()
+            .internal_context("deleting access token")?;
+        if authz_silo_user.id() != silo_user_id
+            || authz_silo.id() != current_silo.id()
+        {
+            return Err(authz_access_token.not_found());
+        }
+        self.db_datastore.access_token_delete(opctx, &authz_access_token).await
+    }
+
     // identity providers
 
     pub fn saml_identity_provider_lookup<'a>(
@@ -724,6 +839,41 @@ impl super::Nexus {
         }
     }
 
+    pub fn oidc_identity_provider_lookup<'a>(
+        &'a self,
+        opctx: &'a OpContext,
+        oidc_identity_provider_selector: params::OidcIdentityProviderSelector,
+    ) -> LookupResult<lookup::OidcIdentityProvider<'a>> {
+        match oidc_identity_provider_selector {
+            params::OidcIdentityProviderSelector {
+                oidc_identity_provider: NameOrId::Id(id),
+                silo: None,
+            } => {
+                let oidc_provider = LookupPath::new(opctx, &self.db_datastore)
+                    .oidc_identity_provider_id(id);
+                Ok(oidc_provider)
+            }
+            params::OidcIdentityProviderSelector {
+                oidc_identity_provider: NameOrId::Name(name),
+                silo: Some(silo),
+            } => {
+                let oidc_provider = self
+                    .silo_lookup(opctx, silo)?
+                    .oidc_identity_provider_name_owned(name.into());
+                Ok(oidc_provider)
+            }
+            params::OidcIdentityProviderSelector {
+                oidc_identity_provider: NameOrId::Id(_),
+                silo: _,
+            } => Err(Error::invalid_request(
+                "when providing provider as an ID, silo should not be specified",
+            )),
+            _ => Err(Error::invalid_request(
+                "provider should either be a UUID or silo should be specified",
+            )),
+        }
+    }
+
     pub async fn identity_provider_list(
         &self,
         opctx: &OpContext,
@@ -872,6 +1022,81 @@ impl super::Nexus {
             .await
     }
 
+    pub async fn oidc_identity_provider_create(
+        &self,
+        opctx: &OpContext,
+        silo_lookup: &lookup::Silo<'_>,
+        params: params::OidcIdentityProviderCreate,
+    ) -> CreateResult<db::model::OidcIdentityProvider> {
+        // TODO-security: This should likely be fetch_for CreateChild on the silo
+        let (authz_silo, db_silo) = silo_lookup.fetch().await?;
+        let authz_idp_list = authz::SiloIdentityProviderList::new(authz_silo);
+
+        if db_silo.user_provision_type != UserProvisionType::Jit {
+            return Err(Error::invalid_request(
+                "cannot create identity providers in this kind of Silo",
+            ));
+        }
+
+        // The authentication mode is immutable so it's safe to check this here
+        // and bail out.
+        if db_silo.authentication_mode
+            != nexus_db_model::AuthenticationMode::Oidc
+        {
+            return Err(Error::invalid_request(&format!(
+                "cannot create OIDC identity provider for this Silo type \
+                (expected authentication mode {:?}, found {:?})",
+                nexus_db_model::AuthenticationMode::Oidc,
+                &db_silo.authentication_mode,
+            )));
+        }
+
+        // "openid" is what makes this an OpenID Connect request rather than a
+        // plain OAuth 2.0 one, so it is always requested.
+        let mut scopes = vec![String::from("openid")];
+        for scope in params.scopes {
+            if scope.is_empty() || scope.contains(char::is_whitespace) {
+                return Err(Error::InvalidValue {
+                    label: String::from("scopes"),
+                    message: format!("invalid scope {:?}", scope),
+                });
+            }
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+
+        let provider = db::model::OidcIdentityProvider {
+            identity: db::model::OidcIdentityProviderIdentity::new(
+                Uuid::new_v4(),
+                params.identity,
+            ),
+            silo_id: db_silo.id(),
+
+            issuer: params.issuer,
+            client_id: params.client_id,
+            client_secret: params.client_secret,
+            authorization_endpoint: params.authorization_endpoint,
+            token_endpoint: params.token_endpoint,
+            jwks_uri: params.jwks_uri,
+            redirect_uri: params.redirect_uri,
+            scopes: scopes.join(" "),
+
+            group_claim_name: params.group_claim_name,
+        };
+
+        let _authn_provider: authn::silos::OidcIdentityProvider =
+            provider.clone().try_into().map_err(|e: anyhow::Error|
+                // If an error is encountered converting from the model to the
+                // authn type here, this is a request error: something about the
+                // parameters of this request doesn't work.
+                Error::invalid_request(&e.to_string()))?;
+
+        self.db_datastore
+            .oidc_identity_provider_create(opctx, &authz_idp_list, provider)
+            .await
+    }
+
     pub fn silo_group_lookup<'a>(
         &'a self,
         opctx: &'a OpContext,
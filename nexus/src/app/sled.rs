@@ -10,6 +10,7 @@ use crate::db::identity::Asset;
 use crate::db::lookup::LookupPath;
 use crate::db::model::DatasetKind;
 use crate::db::model::ServiceKind;
+use crate::external_api::views;
 use crate::internal_api::params::{
     PhysicalDiskDeleteRequest, PhysicalDiskPutRequest, SledAgentStartupInfo,
     SledRole, ZpoolPutRequest,
@@ -17,8 +18,10 @@ use crate::internal_api::params::{
 use nexus_db_queries::context::OpContext;
 use omicron_common::api::external::DataPageParams;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::InstanceState;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::UpdateResult;
 use sled_agent_client::types::SetVirtualNetworkInterfaceHost;
 use sled_agent_client::Client as SledAgentClient;
 use std::net::SocketAddrV6;
@@ -81,6 +84,117 @@ impl super::Nexus {
         Ok(db_sled)
     }
 
+    /// Permanently removes a sled from service
+    ///
+    /// Instances that were on the sled are marked failed and the sled's
+    /// reservations are released.  Failed instances with an auto-restart
+    /// policy are then started elsewhere, and Crucible regions on the sled are
+    /// replaced with regions on other sleds.
+    pub async fn sled_expunge(
+        &self,
+        opctx: &OpContext,
+        sled_id: Uuid,
+    ) -> UpdateResult<db::model::Sled> {
+        let (authz_sled,) = LookupPath::new(opctx, &self.db_datastore)
+            .sled_id(sled_id)
+            .lookup_for(authz::Action::Modify)
+            .await?;
+        let (sled, failed_instances) =
+            self.db_datastore.sled_expunge(opctx, &authz_sled).await?;
+        warn!(self.log, "expunged sled";
+            "sled_id" => %sled_id,
+            "failed_instances" => ?failed_instances);
+        if !failed_instances.is_empty() {
+            self.background_tasks
+                .activate(&self.background_tasks.task_instance_auto_restart);
+        }
+        self.background_tasks
+            .activate(&self.background_tasks.task_region_replacement);
+        Ok(sled)
+    }
+
+    /// Stops placing new instances on a sled and starts migrating the
+    /// instances running on it to other sleds
+    pub async fn sled_drain(
+        &self,
+        opctx: &OpContext,
+        sled_id: Uuid,
+    ) -> UpdateResult<views::SledDrainProgress> {
+        let (authz_sled,) = LookupPath::new(opctx, &self.db_datastore)
+            .sled_id(sled_id)
+            .lookup_for(authz::Action::Modify)
+            .await?;
+        let sled = self
+            .db_datastore
+            .sled_set_schedulable(opctx, &authz_sled, false)
+            .await?;
+        info!(self.log, "draining sled"; "sled_id" => %sled_id);
+        self.background_tasks.activate(&self.background_tasks.task_sled_drain);
+        self.sled_drain_progress_for(opctx, &authz_sled, &sled).await
+    }
+
+    /// Reports how far along a sled is in being drained of its instances
+    pub async fn sled_drain_progress(
+        &self,
+        opctx: &OpContext,
+        sled_id: Uuid,
+    ) -> LookupResult<views::SledDrainProgress> {
+        let (authz_sled, sled) = LookupPath::new(opctx, &self.db_datastore)
+            .sled_id(sled_id)
+            .fetch()
+            .await?;
+        self.sled_drain_progress_for(opctx, &authz_sled, &sled).await
+    }
+
+    async fn sled_drain_progress_for(
+        &self,
+        opctx: &OpContext,
+        authz_sled: &authz::Sled,
+        sled: &db::model::Sled,
+    ) -> LookupResult<views::SledDrainProgress> {
+        let instances =
+            self.db_datastore.sled_instance_list(opctx, authz_sled).await?;
+        let mut progress = views::SledDrainProgress {
+            schedulable: sled.schedulable,
+            instances_pending: 0,
+            instances_migrating: 0,
+            instances_not_running: 0,
+        };
+        for instance in instances {
+            let runtime = instance.runtime();
+            if runtime.migration_id.is_some() {
+                progress.instances_migrating += 1;
+                continue;
+            }
+            match runtime.state.0 {
+                InstanceState::Starting
+                | InstanceState::Running
+                | InstanceState::Rebooting => progress.instances_pending += 1,
+                _ => progress.instances_not_running += 1,
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Returns a drained sled to service, so that new instances may be placed
+    /// on it again
+    pub async fn sled_undrain(
+        &self,
+        opctx: &OpContext,
+        sled_id: Uuid,
+    ) -> UpdateResult<db::model::Sled> {
+        let (authz_sled,) = LookupPath::new(opctx, &self.db_datastore)
+            .sled_id(sled_id)
+            .lookup_for(authz::Action::Modify)
+            .await?;
+        let sled = self
+            .db_datastore
+            .sled_set_schedulable(opctx, &authz_sled, true)
+            .await?;
+        info!(self.log, "returned sled to service"; "sled_id" => %sled_id);
+        Ok(sled)
+    }
+
     pub async fn sled_client(
         &self,
         id: &Uuid,
@@ -112,6 +226,7 @@ impl super::Nexus {
         &self,
         resource_id: Uuid,
         resource_kind: db::model::SledResourceKind,
+        instance_id: Option<Uuid>,
         resources: db::model::Resources,
         constraints: db::model::SledReservationConstraints,
     ) -> Result<db::model::SledResource, Error> {
@@ -120,6 +235,7 @@ impl super::Nexus {
                 &self.opctx_alloc,
                 resource_id,
                 resource_kind,
+                instance_id,
                 resources,
                 constraints,
             )
@@ -180,7 +296,8 @@ impl super::Nexus {
         Ok(())
     }
 
-    /// Removes a physical disk from the database.
+    /// Removes a physical disk from the database, and starts replacing the
+    /// Crucible regions that were on it.
     ///
     /// TODO: Remove Zpools and datasets contained within this disk.
     pub async fn delete_physical_disk(
@@ -204,6 +321,8 @@ impl super::Nexus {
                 request.sled_id,
             )
             .await?;
+        self.background_tasks
+            .activate(&self.background_tasks.task_region_replacement);
         Ok(())
     }
 
@@ -12,9 +12,11 @@ use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
 use omicron_common::api::external::InstanceState;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::NameOrId;
+use uuid::Uuid;
 
 use super::sagas;
 
@@ -63,16 +65,35 @@ impl super::Nexus {
         project_lookup: lookup::Project<'_>,
         params: &params::SnapshotCreate,
     ) -> CreateResult<db::model::Snapshot> {
-        let authz_silo: authz::Silo;
-        let authz_project: authz::Project;
-        let authz_disk: authz::Disk;
-        let db_disk: db::model::Disk;
-
-        (authz_silo, authz_project, authz_disk, db_disk) = project_lookup
+        let (authz_silo, _, authz_disk, db_disk) = project_lookup
             .disk_name(&db::model::Name(params.disk.clone()))
             .fetch_for(authz::Action::Read)
             .await?;
 
+        self.snapshot_create_for_disk(
+            opctx,
+            &authz_silo,
+            &authz_disk,
+            &db_disk,
+            params,
+            None,
+        )
+        .await
+    }
+
+    /// Takes a snapshot of a disk that has already been looked up
+    ///
+    /// `snapshot_schedule_id` identifies the snapshot schedule on whose behalf
+    /// the snapshot is being taken, if any.
+    pub(crate) async fn snapshot_create_for_disk(
+        self: &Arc<Self>,
+        opctx: &OpContext,
+        authz_silo: &authz::Silo,
+        authz_disk: &authz::Disk,
+        db_disk: &db::model::Disk,
+        params: &params::SnapshotCreate,
+        snapshot_schedule_id: Option<Uuid>,
+    ) -> CreateResult<db::model::Snapshot> {
         // If there isn't a running propolis, Nexus needs to use the Crucible
         // Pantry to make this snapshot
         let use_the_pantry = if let Some(attach_instance_id) =
@@ -115,10 +136,11 @@ impl super::Nexus {
         let saga_params = sagas::snapshot_create::Params {
             serialized_authn: authn::saga::Serialized::for_opctx(opctx),
             silo_id: authz_silo.id(),
-            project_id: authz_project.id(),
+            project_id: db_disk.project_id,
             disk_id: authz_disk.id(),
             use_the_pantry,
             create_params: params.clone(),
+            snapshot_schedule_id,
         };
 
         let saga_outputs = self
@@ -141,11 +163,14 @@ impl super::Nexus {
         opctx: &OpContext,
         project_lookup: &lookup::Project<'_>,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<db::model::Snapshot> {
         let (.., authz_project) =
             project_lookup.lookup_for(authz::Action::ListChildren).await?;
 
-        self.db_datastore.snapshot_list(opctx, &authz_project, pagparams).await
+        self.db_datastore
+            .snapshot_list(opctx, &authz_project, pagparams, label_selector)
+            .await
     }
 
     pub async fn snapshot_delete(
@@ -0,0 +1,259 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Snapshot schedules
+
+use crate::authn;
+use crate::authz;
+use crate::db;
+use crate::db::lookup;
+use crate::db::lookup::LookupPath;
+use crate::external_api::params;
+use chrono::DateTime;
+use chrono::Utc;
+use nexus_db_queries::context::OpContext;
+use nexus_types::identity::Resource;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::Name;
+use omicron_common::api::external::NameOrId;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Maximum length of the part of a scheduled snapshot's name that comes from
+/// the schedule's name
+///
+/// The rest of the name is a suffix like "-20230401-120000", and the whole
+/// name must fit in 63 characters.
+const SNAPSHOT_NAME_PREFIX_MAX_LEN: usize = 63 - 16;
+
+impl super::Nexus {
+    pub fn snapshot_schedule_lookup<'a>(
+        &'a self,
+        opctx: &'a OpContext,
+        schedule_selector: params::SnapshotScheduleSelector,
+    ) -> LookupResult<lookup::SnapshotSchedule<'a>> {
+        match schedule_selector {
+            params::SnapshotScheduleSelector {
+                snapshot_schedule: NameOrId::Id(id),
+                project: None,
+            } => {
+                let schedule = LookupPath::new(opctx, &self.db_datastore)
+                    .snapshot_schedule_id(id);
+                Ok(schedule)
+            }
+            params::SnapshotScheduleSelector {
+                snapshot_schedule: NameOrId::Name(name),
+                project: Some(project),
+            } => {
+                let schedule = self
+                    .project_lookup(opctx, params::ProjectSelector { project })?
+                    .snapshot_schedule_name_owned(name.into());
+                Ok(schedule)
+            }
+            params::SnapshotScheduleSelector {
+                snapshot_schedule: NameOrId::Id(_),
+                ..
+            } => Err(Error::invalid_request(
+                "when providing snapshot_schedule as an ID, project should \
+                not be specified",
+            )),
+            _ => Err(Error::invalid_request(
+                "snapshot_schedule should either be an ID or project should \
+                be specified",
+            )),
+        }
+    }
+
+    pub async fn snapshot_schedule_create(
+        &self,
+        opctx: &OpContext,
+        project_lookup: &lookup::Project<'_>,
+        params: &params::SnapshotScheduleCreate,
+    ) -> CreateResult<db::model::SnapshotSchedule> {
+        let (authz_silo, authz_project) =
+            project_lookup.lookup_for(authz::Action::CreateChild).await?;
+
+        if params.interval_minutes == 0 {
+            return Err(Error::InvalidValue {
+                label: String::from("interval_minutes"),
+                message: String::from("must be at least 1"),
+            });
+        }
+        if params.retention_count == 0
+            || params.retention_count > params::MAX_SNAPSHOT_SCHEDULE_RETENTION
+        {
+            return Err(Error::InvalidValue {
+                label: String::from("retention_count"),
+                message: format!(
+                    "must be between 1 and {}",
+                    params::MAX_SNAPSHOT_SCHEDULE_RETENTION
+                ),
+            });
+        }
+
+        // Snapshots are taken on behalf of the user creating the schedule, so
+        // only Silo users can create schedules.
+        let silo_user_id =
+            opctx.authn.actor_required()?.silo_user_id().ok_or_else(|| {
+                Error::invalid_request(
+                    "snapshot schedules can only be created by Silo users",
+                )
+            })?;
+
+        let disk_lookup = match &params.disk {
+            NameOrId::Id(id) => {
+                LookupPath::new(opctx, &self.db_datastore).disk_id(*id)
+            }
+            NameOrId::Name(name) => LookupPath::new(opctx, &self.db_datastore)
+                .project_id(authz_project.id())
+                .disk_name_owned(name.clone().into()),
+        };
+        let (.., disk_project, authz_disk) =
+            disk_lookup.lookup_for(authz::Action::Read).await?;
+        if disk_project.id() != authz_project.id() {
+            return Err(Error::invalid_request(&format!(
+                "disk {} is not in the snapshot schedule's project",
+                params.disk
+            )));
+        }
+
+        let schedule = db::model::SnapshotSchedule::new(
+            Uuid::new_v4(),
+            authz_project.id(),
+            authz_disk.id(),
+            authz_silo.id(),
+            silo_user_id,
+            params.clone(),
+        );
+        let schedule = self
+            .db_datastore
+            .snapshot_schedule_create(opctx, &authz_project, schedule)
+            .await?;
+
+        // The first snapshot is due right away.
+        self.background_tasks
+            .activate(&self.background_tasks.task_snapshot_scheduler);
+        Ok(schedule)
+    }
+
+    pub async fn snapshot_schedule_list(
+        &self,
+        opctx: &OpContext,
+        project_lookup: &lookup::Project<'_>,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<db::model::SnapshotSchedule> {
+        let (.., authz_project) =
+            project_lookup.lookup_for(authz::Action::ListChildren).await?;
+        self.db_datastore
+            .snapshot_schedule_list(opctx, &authz_project, pagparams)
+            .await
+    }
+
+    pub async fn snapshot_schedule_delete(
+        &self,
+        opctx: &OpContext,
+        schedule_lookup: &lookup::SnapshotSchedule<'_>,
+    ) -> DeleteResult {
+        let (.., authz_schedule) =
+            schedule_lookup.lookup_for(authz::Action::Delete).await?;
+        self.db_datastore.snapshot_schedule_delete(opctx, &authz_schedule).await
+    }
+
+    /// Takes a snapshot for a snapshot schedule that is due, then deletes the
+    /// oldest snapshots taken by the schedule beyond its retention count.
+    ///
+    /// This is done on behalf of the user who created the schedule.  Returns
+    /// the time at which the snapshot was taken.
+    pub(crate) async fn snapshot_schedule_run(
+        self: &Arc<Self>,
+        schedule: &db::model::SnapshotSchedule,
+    ) -> Result<DateTime<Utc>, Error> {
+        let opctx = OpContext::for_background(
+            self.log.new(o!(
+                "component" => "SnapshotScheduler",
+                "snapshot_schedule_id" => schedule.id().to_string(),
+            )),
+            Arc::clone(&self.authz),
+            authn::Context::silo_user_background(
+                schedule.silo_user_id,
+                schedule.silo_id,
+            ),
+            Arc::clone(&self.db_datastore),
+        );
+
+        let (authz_silo, _, authz_disk, db_disk) =
+            LookupPath::new(&opctx, &self.db_datastore)
+                .disk_id(schedule.disk_id)
+                .fetch_for(authz::Action::Read)
+                .await?;
+        let time_snapshot = Utc::now();
+        let snapshot_params = params::SnapshotCreate {
+            identity: IdentityMetadataCreateParams {
+                name: scheduled_snapshot_name(schedule.name(), time_snapshot)?,
+                description: format!(
+                    "taken by snapshot schedule {:?}",
+                    schedule.name().as_str()
+                ),
+            },
+            labels: Default::default(),
+            disk: db_disk.name().clone(),
+        };
+        self.snapshot_create_for_disk(
+            &opctx,
+            &authz_silo,
+            &authz_disk,
+            &db_disk,
+            &snapshot_params,
+            Some(schedule.id()),
+        )
+        .await?;
+
+        let (.., authz_schedule) = LookupPath::new(&opctx, &self.db_datastore)
+            .snapshot_schedule_id(schedule.id())
+            .lookup_for(authz::Action::Read)
+            .await?;
+        let expired = self
+            .db_datastore
+            .snapshot_schedule_expired_snapshots(
+                &opctx,
+                &authz_schedule,
+                *schedule.retention_count,
+            )
+            .await?;
+        for snapshot in expired {
+            let snapshot_lookup = LookupPath::new(&opctx, &self.db_datastore)
+                .snapshot_id(snapshot.id());
+            self.snapshot_delete(&opctx, &snapshot_lookup).await?;
+        }
+
+        Ok(time_snapshot)
+    }
+}
+
+/// Returns the name of the snapshot taken at `time` by the snapshot schedule
+/// called `schedule_name`
+///
+/// The schedule's name is truncated as needed to keep the result a valid
+/// name.
+fn scheduled_snapshot_name(
+    schedule_name: &Name,
+    time: DateTime<Utc>,
+) -> Result<Name, Error> {
+    let prefix = schedule_name.as_str();
+    let prefix = &prefix[..prefix.len().min(SNAPSHOT_NAME_PREFIX_MAX_LEN)];
+    format!("{}-{}", prefix.trim_end_matches('-'), time.format("%Y%m%d-%H%M%S"))
+        .parse()
+        .map_err(|e| {
+            Error::internal_error(&format!(
+                "generating name for scheduled snapshot: {}",
+                e
+            ))
+        })
+}
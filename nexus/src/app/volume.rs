@@ -6,9 +6,16 @@
 
 use crate::app::sagas;
 use crate::authn;
+use crate::db;
+use nexus_db_model::RegionReplacementRestart;
+use nexus_db_model::RegionReplacementState;
 use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::identity::Asset;
+use omicron_common::api::external::DataPageParams;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::InternalContext;
+use omicron_common::api::external::ListResultVec;
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -65,4 +72,107 @@ impl super::Nexus {
 
         Ok(())
     }
+
+    /// Lists the replacements of regions that were lost along with their
+    /// physical disks, zpools or sleds
+    pub async fn region_replacement_list(
+        &self,
+        opctx: &OpContext,
+        pagparams: &DataPageParams<'_, Uuid>,
+    ) -> ListResultVec<db::model::RegionReplacement> {
+        self.db_datastore.region_replacement_list(opctx, pagparams).await
+    }
+
+    /// Run a saga to replace the lost region of a region replacement request,
+    /// returning whether the saga ran
+    ///
+    /// If the saga fails, the error is recorded with the request, which stays
+    /// requested so that it's tried again later.
+    pub(crate) async fn region_replace(
+        self: &Arc<Self>,
+        replacement_id: Uuid,
+    ) -> Result<bool, Error> {
+        let opctx = OpContext::for_background(
+            self.log.new(o!(
+                "component" => "RegionReplacement",
+                "replacement_id" => replacement_id.to_string(),
+            )),
+            Arc::clone(&self.authz),
+            authn::Context::internal_api(),
+            Arc::clone(&self.db_datastore),
+        );
+        let replacement = self
+            .db_datastore
+            .region_replacement_get(&opctx, replacement_id)
+            .await?;
+        if replacement.state != RegionReplacementState::Requested {
+            return Ok(false);
+        }
+
+        // If the region is gone, so is the volume that depended on it (for
+        // example because the disk was deleted), and there's nothing left to
+        // replace.
+        let old_region = self
+            .db_datastore
+            .get_allocated_regions(replacement.volume_id)
+            .await?
+            .into_iter()
+            .map(|(_, region)| region)
+            .find(|region| region.id() == replacement.old_region_id);
+        let Some(old_region) = old_region else {
+            info!(opctx.log, "lost region no longer exists";
+                "region_id" => %replacement.old_region_id);
+            self.db_datastore
+                .region_replacement_set_state(
+                    &opctx,
+                    replacement_id,
+                    RegionReplacementState::Requested,
+                    RegionReplacementState::Complete,
+                    None,
+                    None,
+                )
+                .await?;
+            return Ok(false);
+        };
+
+        let saga_params = sagas::region_replace::Params {
+            serialized_authn: authn::saga::Serialized::for_opctx(&opctx),
+            replacement_id,
+            disk_id: replacement.disk_id,
+            old_region,
+        };
+        let output = match self
+            .execute_saga::<sagas::region_replace::SagaRegionReplace>(
+                saga_params,
+            )
+            .await
+        {
+            Ok(output) => output,
+            Err(error) => {
+                self.db_datastore
+                    .region_replacement_set_error(
+                        &opctx,
+                        replacement_id,
+                        format!("{:#}", error),
+                    )
+                    .await?;
+                return Err(error);
+            }
+        };
+
+        let restart = output
+            .lookup_node_output::<Option<RegionReplacementRestart>>("output")
+            .map_err(|e| Error::internal_error(&format!("{:#}", &e)))
+            .internal_context("looking up output from region replace saga")?;
+        if let Some(restart) = restart {
+            warn!(
+                opctx.log,
+                "instance must be restarted to repair the replacement region";
+                "instance_id" => %restart.instance_id,
+                "propolis_id" => %restart.propolis_id,
+            );
+        }
+
+        Ok(true)
+    }
 }
@@ -23,6 +23,7 @@ use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
 use omicron_common::api::external::InternalContext;
 use omicron_common::api::external::IpNet;
+use omicron_common::api::external::LabelSelector;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::LookupType;
@@ -108,10 +109,13 @@ impl super::Nexus {
         opctx: &OpContext,
         project_lookup: &lookup::Project<'_>,
         pagparams: &PaginatedBy<'_>,
+        label_selector: Option<&LabelSelector>,
     ) -> ListResultVec<db::model::Vpc> {
         let (.., authz_project) =
             project_lookup.lookup_for(authz::Action::ListChildren).await?;
-        self.db_datastore.vpc_list(&opctx, &authz_project, pagparams).await
+        self.db_datastore
+            .vpc_list(&opctx, &authz_project, pagparams, label_selector)
+            .await
     }
 
     pub async fn project_update_vpc(
@@ -444,6 +448,24 @@ impl super::Nexus {
             .map(|(name, v)| (name.0, v))
             .collect();
 
+        // Instances in peered VPCs are treated as part of this VPC when it's
+        // named as a host filter, so that they can reach each other.  Their
+        // traffic is encapsulated with the VNI of the VPC it's sent to rather
+        // than that of their own, so they're identified by the address ranges
+        // of their subnets.
+        let peer_networks = self
+            .db_datastore
+            .vpc_resolve_peer_subnets(vpc.id())
+            .await?
+            .into_iter()
+            .flat_map(|subnet| {
+                [
+                    IpNet::from(subnet.ipv4_block.0),
+                    IpNet::from(subnet.ipv6_block.0),
+                ]
+            })
+            .collect::<Vec<_>>();
+
         debug!(
             self.log,
             "resolved names for firewall rules";
@@ -451,6 +473,7 @@ impl super::Nexus {
             "vpc_interfaces" => ?vpc_interfaces,
             "subnet_interfaces" => ?subnet_interfaces,
             "subnet_networks" => ?subnet_networks,
+            "peer_networks" => ?peer_networks,
         );
 
         // Compile resolved rules for the sled agents.
@@ -590,6 +613,13 @@ impl super::Nexus {
                                         .into(),
                                     )
                                 }
+                                if name == vpc.name() {
+                                    for net in &peer_networks {
+                                        host_addrs.push(
+                                            HostIdentifier::Ip(*net).into(),
+                                        );
+                                    }
+                                }
                             }
                         }
                     }
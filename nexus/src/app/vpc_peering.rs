@@ -0,0 +1,205 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Peerings between VPCs in the same Silo
+
+use crate::authz;
+use crate::db;
+use crate::db::identity::Resource;
+use crate::db::lookup;
+use crate::db::lookup::LookupPath;
+use crate::external_api::params;
+use nexus_db_queries::context::OpContext;
+use omicron_common::api::external::http_pagination::PaginatedBy;
+use omicron_common::api::external::CreateResult;
+use omicron_common::api::external::DeleteResult;
+use omicron_common::api::external::Error;
+use omicron_common::api::external::ListResultVec;
+use omicron_common::api::external::LookupResult;
+use omicron_common::api::external::NameOrId;
+use uuid::Uuid;
+
+impl super::Nexus {
+    pub fn vpc_peering_lookup<'a>(
+        &'a self,
+        opctx: &'a OpContext,
+        peering_selector: params::PeeringSelector,
+    ) -> LookupResult<lookup::VpcPeering<'a>> {
+        match peering_selector {
+            params::PeeringSelector {
+                peering: NameOrId::Id(id),
+                vpc: None,
+                project: None,
+            } => {
+                let peering = LookupPath::new(opctx, &self.db_datastore)
+                    .vpc_peering_id(id);
+                Ok(peering)
+            }
+            params::PeeringSelector {
+                peering: NameOrId::Name(name),
+                vpc: Some(vpc),
+                project,
+            } => {
+                let peering = self
+                    .vpc_lookup(opctx, params::VpcSelector { project, vpc })?
+                    .vpc_peering_name_owned(name.into());
+                Ok(peering)
+            }
+            params::PeeringSelector {
+                peering: NameOrId::Id(_),
+                vpc: _,
+                project: _,
+            } => Err(Error::invalid_request(
+                "when providing peering as an ID, vpc and project should not be specified",
+            )),
+            _ => Err(Error::invalid_request(
+                "peering should either be an ID or vpc should be specified",
+            )),
+        }
+    }
+
+    pub async fn vpc_peering_list(
+        &self,
+        opctx: &OpContext,
+        vpc_lookup: &lookup::Vpc<'_>,
+        pagparams: &PaginatedBy<'_>,
+    ) -> ListResultVec<db::model::VpcPeering> {
+        let (.., authz_vpc) =
+            vpc_lookup.lookup_for(authz::Action::ListChildren).await?;
+        self.db_datastore.vpc_peering_list(opctx, &authz_vpc, pagparams).await
+    }
+
+    pub async fn vpc_create_peering(
+        &self,
+        opctx: &OpContext,
+        vpc_lookup: &lookup::Vpc<'_>,
+        params: &params::VpcPeeringCreate,
+    ) -> CreateResult<db::model::VpcPeering> {
+        let (authz_silo, _, authz_vpc, db_vpc) =
+            vpc_lookup.fetch_for(authz::Action::CreateChild).await?;
+
+        let peer_selector = match (&params.peer_vpc, &params.peer_project) {
+            (NameOrId::Id(_), Some(_)) => {
+                return Err(Error::invalid_request(
+                    "when providing peer_vpc as an ID, peer_project should \
+                    not be specified",
+                ))
+            }
+            (NameOrId::Id(_), None) => params::VpcSelector {
+                project: None,
+                vpc: params.peer_vpc.clone(),
+            },
+            (NameOrId::Name(_), project) => params::VpcSelector {
+                project: Some(
+                    project.clone().unwrap_or(NameOrId::Id(db_vpc.project_id)),
+                ),
+                vpc: params.peer_vpc.clone(),
+            },
+        };
+        let (peer_authz_silo, _, authz_peer_vpc, db_peer_vpc) = self
+            .vpc_lookup(opctx, peer_selector)?
+            .fetch_for(authz::Action::Modify)
+            .await?;
+
+        if peer_authz_silo.id() != authz_silo.id() {
+            return Err(Error::invalid_request(
+                "only VPCs in the same silo may be peered",
+            ));
+        }
+        if authz_peer_vpc.id() == authz_vpc.id() {
+            return Err(Error::invalid_request(
+                "a VPC cannot be peered with itself",
+            ));
+        }
+
+        let peering = db::model::VpcPeering::new(
+            Uuid::new_v4(),
+            authz_vpc.id(),
+            authz_peer_vpc.id(),
+            params.clone(),
+        );
+        let (authz_peering, peering) = self
+            .db_datastore
+            .vpc_create_peering(opctx, &authz_vpc, &authz_peer_vpc, peering)
+            .await?;
+
+        // If the sleds can't be told about the peering, remove it again, so
+        // that it doesn't appear to exist while instances in the two VPCs
+        // can't reach each other.
+        let vpcs = ((&authz_vpc, &db_vpc), (&authz_peer_vpc, &db_peer_vpc));
+        if let Err(error) = self.vpc_peering_propagate(opctx, vpcs).await {
+            warn!(
+                self.log,
+                "failed to propagate VPC peering; deleting it";
+                "peering_id" => %peering.id(),
+                "error" => %error,
+            );
+            self.db_datastore.vpc_delete_peering(opctx, &authz_peering).await?;
+            if let Err(undo_error) =
+                self.vpc_peering_propagate(opctx, vpcs).await
+            {
+                error!(
+                    self.log,
+                    "failed to propagate deletion of VPC peering";
+                    "peering_id" => %peering.id(),
+                    "error" => %undo_error,
+                );
+            }
+            return Err(error);
+        }
+
+        Ok(peering)
+    }
+
+    pub async fn vpc_delete_peering(
+        &self,
+        opctx: &OpContext,
+        peering_lookup: &lookup::VpcPeering<'_>,
+    ) -> DeleteResult {
+        let (.., authz_vpc, authz_peering, db_peering) =
+            peering_lookup.fetch_for(authz::Action::Delete).await?;
+        let (.., db_vpc) = LookupPath::new(opctx, &self.db_datastore)
+            .vpc_id(authz_vpc.id())
+            .fetch()
+            .await?;
+        let (.., authz_peer_vpc, db_peer_vpc) =
+            LookupPath::new(opctx, &self.db_datastore)
+                .vpc_id(db_peering.peer_vpc_id)
+                .fetch_for(authz::Action::Modify)
+                .await?;
+
+        self.db_datastore.vpc_delete_peering(opctx, &authz_peering).await?;
+
+        self.vpc_peering_propagate(
+            opctx,
+            ((&authz_vpc, &db_vpc), (&authz_peer_vpc, &db_peer_vpc)),
+        )
+        .await
+    }
+
+    /// Re-send the route tables and firewall rules of both VPCs in a peering
+    /// to the sleds, after the peering has been created or deleted
+    ///
+    /// The subnets of each VPC have routes to those of the other while the
+    /// VPCs are peered.  Traffic along those routes is encapsulated with the
+    /// VNI of the destination's VPC, so it's delivered using the V2P mappings
+    /// that each instance already has under its own VPC's VNI.  Rules whose
+    /// host filters name a VPC also match instances in the VPCs peered with
+    /// it, so both sets of rules change with the peering.
+    async fn vpc_peering_propagate(
+        &self,
+        opctx: &OpContext,
+        vpcs: ((&authz::Vpc, &db::model::Vpc), (&authz::Vpc, &db::model::Vpc)),
+    ) -> Result<(), Error> {
+        for (authz_vpc, db_vpc) in [vpcs.0, vpcs.1] {
+            self.send_sled_agents_vpc_routes(opctx, db_vpc).await?;
+            let rules = self
+                .db_datastore
+                .vpc_list_firewall_rules(opctx, authz_vpc)
+                .await?;
+            self.send_sled_agents_firewall_rules(opctx, db_vpc, &rules).await?;
+        }
+        Ok(())
+    }
+}
@@ -6,22 +6,35 @@
 
 use crate::authz;
 use crate::db;
+use crate::db::identity::Asset;
+use crate::db::identity::Resource;
 use crate::db::lookup;
 use crate::db::lookup::LookupPath;
+use crate::db::model::Name;
 use crate::db::model::RouterRoute;
 use crate::db::model::VpcRouter;
 use crate::db::model::VpcRouterKind;
 use crate::external_api::params;
+use futures::future::join_all;
 use nexus_db_queries::context::OpContext;
+use omicron_common::api::external;
 use omicron_common::api::external::http_pagination::PaginatedBy;
 use omicron_common::api::external::CreateResult;
 use omicron_common::api::external::DeleteResult;
 use omicron_common::api::external::Error;
+use omicron_common::api::external::IpNet;
 use omicron_common::api::external::ListResultVec;
 use omicron_common::api::external::LookupResult;
 use omicron_common::api::external::NameOrId;
+use omicron_common::api::external::RouteDestination;
+use omicron_common::api::external::RouteTarget;
 use omicron_common::api::external::RouterRouteKind;
 use omicron_common::api::external::UpdateResult;
+use omicron_common::api::internal::shared::ResolvedVpcRoute;
+use omicron_common::api::internal::shared::RouterTarget;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::net::IpAddr;
 use uuid::Uuid;
 
 impl super::Nexus {
@@ -115,8 +128,9 @@ impl super::Nexus {
     }
 
     // TODO: When a router is deleted all its routes should be deleted
-    // TODO: When a router is deleted it should be unassociated w/ any subnets it may be associated with
-    //       or trigger an error
+    //
+    // A router can't be deleted while subnets use it, so deleting it doesn't
+    // change the routes of any subnet.
     pub async fn vpc_delete_router(
         &self,
         opctx: &OpContext,
@@ -187,7 +201,7 @@ impl super::Nexus {
         kind: &RouterRouteKind,
         params: &params::RouterRouteCreate,
     ) -> CreateResult<db::model::RouterRoute> {
-        let (.., authz_router) =
+        let (.., authz_vpc, authz_router) =
             router_lookup.lookup_for(authz::Action::CreateChild).await?;
         let id = Uuid::new_v4();
         let route = db::model::RouterRoute::new(
@@ -196,10 +210,18 @@ impl super::Nexus {
             *kind,
             params.clone(),
         );
+        let db_vpc = self.vpc_router_fetch_vpc(opctx, &authz_vpc).await?;
+        self.vpc_routes_check_change(
+            opctx,
+            &db_vpc,
+            &VpcRoutesChange::CreateRoute(&route),
+        )
+        .await?;
         let route = self
             .db_datastore
             .router_create_route(&opctx, &authz_router, route)
             .await?;
+        self.send_sled_agents_vpc_routes(opctx, &db_vpc).await?;
         Ok(route)
     }
 
@@ -222,7 +244,7 @@ impl super::Nexus {
         route_lookup: &lookup::RouterRoute<'_>,
         params: &params::RouterRouteUpdate,
     ) -> UpdateResult<RouterRoute> {
-        let (.., vpc, _, authz_route, db_route) =
+        let (.., authz_vpc, _, authz_route, db_route) =
             route_lookup.fetch_for(authz::Action::Modify).await?;
         // TODO: Write a test for this once there's a way to test it (i.e.
         // subnets automatically register to the system router table)
@@ -234,14 +256,28 @@ impl super::Nexus {
                         "routes of type {} from the system table of VPC {:?} \
                         are not modifiable",
                         db_route.kind.0,
-                        vpc.id()
+                        authz_vpc.id()
                     ),
                 })
             }
         }
-        self.db_datastore
-            .router_update_route(&opctx, &authz_route, params.clone().into())
-            .await
+        let updates: db::model::RouterRouteUpdate = params.clone().into();
+        let mut new_route = db_route.clone();
+        new_route.target = updates.target.clone();
+        new_route.destination = updates.destination.clone();
+        let db_vpc = self.vpc_router_fetch_vpc(opctx, &authz_vpc).await?;
+        self.vpc_routes_check_change(
+            opctx,
+            &db_vpc,
+            &VpcRoutesChange::UpdateRoute(&new_route),
+        )
+        .await?;
+        let route = self
+            .db_datastore
+            .router_update_route(&opctx, &authz_route, updates)
+            .await?;
+        self.send_sled_agents_vpc_routes(opctx, &db_vpc).await?;
+        Ok(route)
     }
 
     pub async fn router_delete_route(
@@ -249,7 +285,7 @@ impl super::Nexus {
         opctx: &OpContext,
         route_lookup: &lookup::RouterRoute<'_>,
     ) -> DeleteResult {
-        let (.., authz_route, db_route) =
+        let (.., authz_vpc, _, authz_route, db_route) =
             route_lookup.fetch_for(authz::Action::Delete).await?;
 
         // Only custom routes can be deleted
@@ -260,6 +296,407 @@ impl super::Nexus {
                     .to_string(),
             });
         }
-        self.db_datastore.router_delete_route(opctx, &authz_route).await
+        let db_vpc = self.vpc_router_fetch_vpc(opctx, &authz_vpc).await?;
+        self.vpc_routes_check_change(
+            opctx,
+            &db_vpc,
+            &VpcRoutesChange::DeleteRoute(authz_route.id()),
+        )
+        .await?;
+        self.db_datastore.router_delete_route(opctx, &authz_route).await?;
+        self.send_sled_agents_vpc_routes(opctx, &db_vpc).await
+    }
+
+    // Route tables
+
+    /// Fetch the VPC a router is in, whose route tables its routes are part
+    /// of
+    async fn vpc_router_fetch_vpc(
+        &self,
+        opctx: &OpContext,
+        authz_vpc: &authz::Vpc,
+    ) -> LookupResult<db::model::Vpc> {
+        let (.., db_vpc) = LookupPath::new(opctx, &self.db_datastore)
+            .vpc_id(authz_vpc.id())
+            .fetch()
+            .await?;
+        Ok(db_vpc)
+    }
+
+    /// Re-send the route tables of a VPC and of every VPC peered with it,
+    /// after the VPC's subnets have changed
+    ///
+    /// Each subnet has a route to every subnet of its own VPC and of the VPCs
+    /// peered with it.
+    pub(crate) async fn send_sled_agents_vpc_and_peer_routes(
+        &self,
+        opctx: &OpContext,
+        vpc: &db::model::Vpc,
+    ) -> Result<(), Error> {
+        self.send_sled_agents_vpc_routes(opctx, vpc).await?;
+        for peer_vpc in self.db_datastore.vpc_resolve_peers(vpc.id()).await? {
+            self.send_sled_agents_vpc_routes(opctx, &peer_vpc).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the route table of each subnet in the VPC to every sled hosting
+    /// an instance in the VPC
+    pub(crate) async fn send_sled_agents_vpc_routes(
+        &self,
+        opctx: &OpContext,
+        vpc: &db::model::Vpc,
+    ) -> Result<(), Error> {
+        let sled_ids: Vec<Uuid> = self
+            .db_datastore
+            .vpc_resolve_to_sleds(vpc.id())
+            .await?
+            .iter()
+            .map(|sled| sled.id())
+            .collect();
+        self.send_sled_agents_vpc_routes_to(opctx, vpc, &sled_ids).await
+    }
+
+    /// Send the route table of each subnet in the VPC to the provided sleds
+    pub(crate) async fn send_sled_agents_vpc_routes_to(
+        &self,
+        opctx: &OpContext,
+        vpc: &db::model::Vpc,
+        sled_ids: &[Uuid],
+    ) -> Result<(), Error> {
+        if sled_ids.is_empty() {
+            return Ok(());
+        }
+        let subnets =
+            self.resolve_vpc_routes_for_sled_agent(opctx, vpc).await?;
+        let sled_routes_request =
+            sled_agent_client::types::VpcRoutesEnsureBody {
+                vni: vpc.vni.0.into(),
+                subnets,
+            };
+
+        let mut sled_requests = Vec::with_capacity(sled_ids.len());
+        for &sled_id in sled_ids {
+            let vpc_id = vpc.id();
+            let sled_routes_request = sled_routes_request.clone();
+            sled_requests.push(async move {
+                self.sled_client(&sled_id)
+                    .await?
+                    .vpc_routes_put(&vpc_id, &sled_routes_request)
+                    .await
+                    .map_err(|e| Error::internal_error(&e.to_string()))
+            });
+        }
+
+        debug!(self.log, "sending VPC routes to sled agents");
+        let results = join_all(sled_requests).await;
+        // TODO-correctness: handle more than one failure in the sled-agent requests
+        //   https://github.com/oxidecomputer/omicron/issues/1791
+        for (sled_id, result) in sled_ids.iter().zip(results) {
+            if let Err(e) = result {
+                warn!(self.log, "failed to update VPC routes on sled agent";
+                      "sled_id" => %sled_id,
+                      "vpc_id" => %vpc.id(),
+                      "error" => %e);
+                return Err(e);
+            }
+        }
+        info!(self.log, "updated VPC routes on {} sleds", sled_ids.len());
+
+        Ok(())
+    }
+
+    /// Check that a change to a VPC's routes can be made
+    ///
+    /// OPTE can't yet remove an entry from a port's router table, so while
+    /// any instance in the VPC is running, a change may only add routes to
+    /// the tables of its subnets: it mustn't remove a route or change its
+    /// target.
+    pub(crate) async fn vpc_routes_check_change(
+        &self,
+        opctx: &OpContext,
+        vpc: &db::model::Vpc,
+        change: &VpcRoutesChange,
+    ) -> Result<(), Error> {
+        if !self.db_datastore.vpc_has_active_instances(vpc.id()).await? {
+            return Ok(());
+        }
+        let before = self.resolve_vpc_route_tables(opctx, vpc, None).await?;
+        let after =
+            self.resolve_vpc_route_tables(opctx, vpc, Some(change)).await?;
+        for (subnet, routes) in &before {
+            let new_routes = after
+                .iter()
+                .find(|(s, _)| s.id() == subnet.id())
+                .map(|(_, routes)| routes.as_slice())
+                .unwrap_or_default();
+            if routes.iter().any(|route| !new_routes.contains(route)) {
+                return Err(Error::invalid_request(
+                    "routes can't be removed or changed while instances in \
+                    the VPC are running; stop the instances first",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compute the effective route table of each subnet in the VPC
+    pub(crate) async fn resolve_vpc_routes_for_sled_agent(
+        &self,
+        opctx: &OpContext,
+        vpc: &db::model::Vpc,
+    ) -> Result<Vec<sled_agent_client::types::VpcSubnetRoutes>, Error> {
+        let tables: Vec<_> = self
+            .resolve_vpc_route_tables(opctx, vpc, None)
+            .await?
+            .into_iter()
+            .map(|(subnet, routes)| sled_agent_client::types::VpcSubnetRoutes {
+                ipv4_block: subnet.ipv4_block.0.into(),
+                ipv6_block: subnet.ipv6_block.0.into(),
+                routes: routes.into_iter().map(Into::into).collect(),
+            })
+            .collect();
+        debug!(
+            self.log,
+            "resolved VPC routes for sleds";
+            "vpc_id" => %vpc.id(),
+            "tables" => ?tables,
+        );
+        Ok(tables)
     }
+
+    /// Compute the effective route table of each subnet in the VPC, as it
+    /// would be after `change`, if one is given
+    ///
+    /// Every subnet has a route to each subnet of its own VPC and of the VPCs
+    /// peered with it (see [`RouterTarget::VpcPeerSubnet`]), followed by the
+    /// routes of the VPC's system router and then those of the subnet's custom
+    /// router, if it has one. A later route replaces an earlier one with the
+    /// same destination.
+    async fn resolve_vpc_route_tables(
+        &self,
+        opctx: &OpContext,
+        vpc: &db::model::Vpc,
+        change: Option<&VpcRoutesChange>,
+    ) -> Result<Vec<(db::model::VpcSubnet, Vec<ResolvedVpcRoute>)>, Error> {
+        // TODO-correctness: As with firewall rules, these queries may produce
+        // inconsistent results due to concurrent changes.
+        let mut subnets =
+            self.db_datastore.vpc_resolve_subnets(vec![vpc.id()]).await?;
+        if let Some(VpcRoutesChange::SetSubnetRouter {
+            subnet_id,
+            custom_router_id,
+        }) = change
+        {
+            for subnet in subnets.iter_mut().filter(|s| s.id() == *subnet_id) {
+                subnet.custom_router_id = *custom_router_id;
+            }
+        }
+        let peer_vpcs = self.db_datastore.vpc_resolve_peers(vpc.id()).await?;
+        let peer_subnets = self
+            .db_datastore
+            .vpc_resolve_subnets(peer_vpcs.iter().map(|v| v.id()).collect())
+            .await?;
+
+        let mut router_ids = vec![vpc.system_router_id];
+        router_ids.extend(
+            subnets
+                .iter()
+                .filter_map(|s| s.custom_router_id)
+                .collect::<HashSet<_>>(),
+        );
+        let mut routes =
+            self.db_datastore.vpc_resolve_router_routes(router_ids).await?;
+        match change {
+            Some(VpcRoutesChange::CreateRoute(route)) => {
+                routes.push((*route).clone())
+            }
+            Some(VpcRoutesChange::UpdateRoute(route)) => {
+                for existing in routes.iter_mut() {
+                    if existing.id() == route.id() {
+                        *existing = (*route).clone();
+                    }
+                }
+            }
+            Some(VpcRoutesChange::DeleteRoute(id)) => {
+                routes.retain(|route| route.id() != *id)
+            }
+            Some(VpcRoutesChange::SetSubnetRouter { .. }) | None => {}
+        }
+
+        // Resolve the names that routes may refer to: the subnets of this VPC,
+        // the subnets of this VPC and its peers by VPC name, and instances in
+        // this VPC's project.
+        let subnet_blocks: HashMap<&external::Name, [IpNet; 2]> =
+            subnets.iter().map(|s| (s.name(), subnet_ip_nets(s))).collect();
+        let mut vpc_blocks: HashMap<&external::Name, Vec<IpNet>> =
+            HashMap::new();
+        vpc_blocks.insert(
+            vpc.name(),
+            subnets.iter().flat_map(subnet_ip_nets).collect(),
+        );
+        for peer_vpc in &peer_vpcs {
+            vpc_blocks.insert(
+                peer_vpc.name(),
+                peer_subnets
+                    .iter()
+                    .filter(|s| s.vpc_id == peer_vpc.id())
+                    .flat_map(subnet_ip_nets)
+                    .collect(),
+            );
+        }
+
+        let instance_names: HashSet<&external::Name> = routes
+            .iter()
+            .filter_map(|route| match &route.target.0 {
+                RouteTarget::Instance(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        let mut instance_ips: HashMap<&external::Name, IpAddr> = HashMap::new();
+        for instance_name in instance_names {
+            if let Ok((.., authz_instance)) =
+                LookupPath::new(opctx, &self.db_datastore)
+                    .project_id(vpc.project_id)
+                    .instance_name(&Name::from(instance_name.clone()))
+                    .lookup_for(authz::Action::ListChildren)
+                    .await
+            {
+                let nics = self
+                    .db_datastore
+                    .derive_guest_network_interface_info(opctx, &authz_instance)
+                    .await?;
+                if let Some(nic) = nics.iter().find(|nic| nic.primary) {
+                    instance_ips.insert(instance_name, nic.ip);
+                }
+            }
+        }
+
+        // Routes whose names can't be resolved, or whose target can't be
+        // reached from their destination, are skipped, as with firewall
+        // rules.
+        let resolve = |route: &RouterRoute| -> Vec<ResolvedVpcRoute> {
+            let dests: Vec<IpNet> = match route.kind.0 {
+                // The destination of the system router's default route names
+                // the VPC, but it applies to all traffic that has no more
+                // specific route.
+                RouterRouteKind::Default => vec![
+                    "0.0.0.0/0".parse::<ipnetwork::IpNetwork>().unwrap().into(),
+                    "::/0".parse::<ipnetwork::IpNetwork>().unwrap().into(),
+                ],
+                _ => match route.destination.state() {
+                    RouteDestination::Ip(ip) => vec![IpNet::from(*ip)],
+                    RouteDestination::IpNet(net) => vec![*net],
+                    RouteDestination::Subnet(name) => subnet_blocks
+                        .get(name)
+                        .map(|blocks| blocks.to_vec())
+                        .unwrap_or_default(),
+                    RouteDestination::Vpc(name) => {
+                        vpc_blocks.get(name).cloned().unwrap_or_default()
+                    }
+                },
+            };
+            dests
+                .into_iter()
+                .filter_map(|dest| {
+                    let target = match &route.target.0 {
+                        RouteTarget::Ip(ip) => RouterTarget::Ip(*ip),
+                        RouteTarget::Instance(name) => {
+                            RouterTarget::Ip(*instance_ips.get(name)?)
+                        }
+                        RouteTarget::Subnet(name) => RouterTarget::VpcSubnet(
+                            *subnet_blocks.get(name)?.iter().find(|block| {
+                                same_family(block.first_address(), dest)
+                            })?,
+                        ),
+                        RouteTarget::InternetGateway(_) => {
+                            RouterTarget::InternetGateway
+                        }
+                        // TODO-completeness: VPCs aren't yet supported as
+                        // route targets.
+                        RouteTarget::Vpc(_) => return None,
+                    };
+                    if let RouterTarget::Ip(ip) = target {
+                        if !same_family(ip, dest) {
+                            return None;
+                        }
+                    }
+                    Some(ResolvedVpcRoute { dest, target })
+                })
+                .collect()
+        };
+        let system_routes: Vec<ResolvedVpcRoute> = routes
+            .iter()
+            .filter(|route| route.vpc_router_id == vpc.system_router_id)
+            .flat_map(resolve)
+            .collect();
+
+        // Traffic to the subnets of peered VPCs must be encapsulated with the
+        // peer VPC's VNI, so that it's delivered to interfaces in that VPC.
+        let peer_vnis: HashMap<Uuid, external::Vni> =
+            peer_vpcs.iter().map(|v| (v.id(), v.vni.0)).collect();
+        let implicit_routes: Vec<ResolvedVpcRoute> = subnets
+            .iter()
+            .flat_map(subnet_ip_nets)
+            .map(|block| ResolvedVpcRoute {
+                dest: block,
+                target: RouterTarget::VpcSubnet(block),
+            })
+            .chain(peer_subnets.iter().flat_map(|subnet| {
+                let vni = peer_vnis[&subnet.vpc_id];
+                subnet_ip_nets(subnet).map(|block| ResolvedVpcRoute {
+                    dest: block,
+                    target: RouterTarget::VpcPeerSubnet { vni, subnet: block },
+                })
+            }))
+            .collect();
+
+        Ok(subnets
+            .iter()
+            .map(|subnet| {
+                let custom_routes = routes
+                    .iter()
+                    .filter(|route| {
+                        Some(route.vpc_router_id) == subnet.custom_router_id
+                    })
+                    .flat_map(resolve);
+                let mut table: Vec<ResolvedVpcRoute> = Vec::new();
+                for route in implicit_routes
+                    .iter()
+                    .copied()
+                    .chain(system_routes.iter().copied())
+                    .chain(custom_routes)
+                {
+                    match table.iter_mut().find(|r| r.dest == route.dest) {
+                        Some(existing) => *existing = route,
+                        None => table.push(route),
+                    }
+                }
+                (subnet.clone(), table)
+            })
+            .collect())
+    }
+}
+
+/// A change to the routes of a VPC that's about to be made
+pub(crate) enum VpcRoutesChange<'a> {
+    /// A new route is added to a router
+    CreateRoute(&'a RouterRoute),
+    /// A route is replaced by this one, with the same ID
+    UpdateRoute(&'a RouterRoute),
+    /// The route with this ID is deleted
+    DeleteRoute(Uuid),
+    /// A subnet's custom router is changed
+    SetSubnetRouter { subnet_id: Uuid, custom_router_id: Option<Uuid> },
+}
+
+fn subnet_ip_nets(subnet: &db::model::VpcSubnet) -> [IpNet; 2] {
+    [subnet.ipv4_block.0.into(), subnet.ipv6_block.0.into()]
+}
+
+fn same_family(ip: IpAddr, net: IpNet) -> bool {
+    matches!(
+        (ip, net),
+        (IpAddr::V4(_), IpNet::V4(_)) | (IpAddr::V6(_), IpNet::V6(_))
+    )
 }
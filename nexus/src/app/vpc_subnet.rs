@@ -4,11 +4,13 @@
 
 //! VPC Subnets and their network interfaces
 
+use crate::app::vpc_router::VpcRoutesChange;
 use crate::authz;
 use crate::db;
 use crate::db::identity::Resource;
 use crate::db::lookup;
 use crate::db::lookup::LookupPath;
+use crate::db::model::VpcRouterKind;
 use crate::db::model::VpcSubnet;
 use crate::db::queries::vpc_subnet::SubnetError;
 use crate::external_api::params;
@@ -72,6 +74,13 @@ impl super::Nexus {
         params: &params::VpcSubnetCreate,
     ) -> CreateResult<db::model::VpcSubnet> {
         let (.., authz_vpc, db_vpc) = vpc_lookup.fetch().await?;
+        let custom_router_id = match &params.custom_router {
+            Some(router) => Some(
+                self.vpc_subnet_custom_router_id(opctx, &authz_vpc, router)
+                    .await?,
+            ),
+            None => None,
+        };
 
         // Validate IPv4 range
         if !params.ipv4_block.network().is_private() {
@@ -93,6 +102,34 @@ impl super::Nexus {
             )));
         }
 
+        // Subnets of peered VPCs must not overlap, since instances in each VPC
+        // can reach those in the other.  IPv6 ranges we generate ourselves are
+        // drawn from this VPC's own prefix, so only the ranges provided by the
+        // client need to be checked.
+        //
+        // TODO-correctness: This races with the creation of peerings and of
+        // subnets in the peered VPCs.
+        for peer_subnet in
+            self.db_datastore.vpc_resolve_peer_subnets(authz_vpc.id()).await?
+        {
+            let (ours4, theirs4) =
+                (params.ipv4_block.0, peer_subnet.ipv4_block.0 .0);
+            let ipv4_overlaps = ours4.contains(theirs4.network())
+                || theirs4.contains(ours4.network());
+            let theirs6 = peer_subnet.ipv6_block.0 .0;
+            let ipv6_overlaps = params.ipv6_block.map_or(false, |ours6| {
+                ours6.0.contains(theirs6.network())
+                    || theirs6.contains(ours6.0.network())
+            });
+            if ipv4_overlaps || ipv6_overlaps {
+                return Err(external::Error::invalid_request(&format!(
+                    "VPC Subnet address range overlaps with subnet \"{}\" \
+                    of a peered VPC",
+                    peer_subnet.name(),
+                )));
+            }
+        }
+
         // Allocate an ID and insert the record.
         //
         // If the client provided an IPv6 range, we try to insert that or fail
@@ -108,7 +145,7 @@ impl super::Nexus {
         // See <https://github.com/oxidecomputer/omicron/issues/685> for
         // details.
         let subnet_id = Uuid::new_v4();
-        match params.ipv6_block {
+        let subnet = match params.ipv6_block {
             None => {
                 const NUM_RETRIES: usize = 2;
                 let mut retry = 0;
@@ -124,13 +161,14 @@ impl super::Nexus {
                                 "Failed to create random IPv6 subnet",
                             )
                         })?;
-                    let subnet = db::model::VpcSubnet::new(
+                    let mut subnet = db::model::VpcSubnet::new(
                         subnet_id,
                         authz_vpc.id(),
                         params.identity.clone(),
                         params.ipv4_block,
                         ipv6_block,
                     );
+                    subnet.custom_router_id = custom_router_id;
                     let result = self
                         .db_datastore
                         .vpc_create_subnet(opctx, &authz_vpc, subnet)
@@ -199,20 +237,26 @@ impl super::Nexus {
                         ipv6_block, db_vpc.ipv6_prefix.0 .0,
                     )));
                 }
-                let subnet = db::model::VpcSubnet::new(
+                let mut subnet = db::model::VpcSubnet::new(
                     subnet_id,
                     db_vpc.id(),
                     params.identity.clone(),
                     params.ipv4_block,
                     ipv6_block,
                 );
+                subnet.custom_router_id = custom_router_id;
                 self.db_datastore
                     .vpc_create_subnet(opctx, &authz_vpc, subnet)
                     .await
                     .map(|(.., subnet)| subnet)
                     .map_err(SubnetError::into_external)
             }
-        }
+        }?;
+
+        // The new subnet can be reached from the other subnets of this VPC
+        // and those of the VPCs peered with it.
+        self.send_sled_agents_vpc_and_peer_routes(opctx, &db_vpc).await?;
+        Ok(subnet)
     }
 
     pub async fn vpc_subnet_list(
@@ -232,11 +276,45 @@ impl super::Nexus {
         vpc_subnet_lookup: &lookup::VpcSubnet<'_>,
         params: &params::VpcSubnetUpdate,
     ) -> UpdateResult<VpcSubnet> {
-        let (.., authz_subnet) =
-            vpc_subnet_lookup.lookup_for(authz::Action::Modify).await?;
-        self.db_datastore
-            .vpc_update_subnet(&opctx, &authz_subnet, params.clone().into())
-            .await
+        let (.., authz_vpc, authz_subnet, db_subnet) =
+            vpc_subnet_lookup.fetch_for(authz::Action::Modify).await?;
+        let custom_router_id = match &params.custom_router {
+            Some(router) => Some(
+                self.vpc_subnet_custom_router_id(opctx, &authz_vpc, router)
+                    .await?,
+            ),
+            None => None,
+        };
+        let router_changed = custom_router_id != db_subnet.custom_router_id;
+        let db_vpc = if router_changed {
+            let (.., db_vpc) = LookupPath::new(opctx, &self.db_datastore)
+                .vpc_id(authz_vpc.id())
+                .fetch()
+                .await?;
+            self.vpc_routes_check_change(
+                opctx,
+                &db_vpc,
+                &VpcRoutesChange::SetSubnetRouter {
+                    subnet_id: authz_subnet.id(),
+                    custom_router_id,
+                },
+            )
+            .await?;
+            Some(db_vpc)
+        } else {
+            None
+        };
+
+        let updates =
+            db::model::VpcSubnetUpdate::new(params.clone(), custom_router_id);
+        let subnet = self
+            .db_datastore
+            .vpc_update_subnet(&opctx, &authz_subnet, updates)
+            .await?;
+        if let Some(db_vpc) = db_vpc {
+            self.send_sled_agents_vpc_routes(opctx, &db_vpc).await?;
+        }
+        Ok(subnet)
     }
 
     // TODO: When a subnet is deleted it should remove its entry from the VPC's
@@ -246,11 +324,48 @@ impl super::Nexus {
         opctx: &OpContext,
         vpc_subnet_lookup: &lookup::VpcSubnet<'_>,
     ) -> DeleteResult {
-        let (.., authz_subnet, db_subnet) =
+        let (.., authz_vpc, authz_subnet, db_subnet) =
             vpc_subnet_lookup.fetch_for(authz::Action::Delete).await?;
         self.db_datastore
             .vpc_delete_subnet(opctx, &db_subnet, &authz_subnet)
-            .await
+            .await?;
+
+        let (.., db_vpc) = LookupPath::new(opctx, &self.db_datastore)
+            .vpc_id(authz_vpc.id())
+            .fetch()
+            .await?;
+        self.send_sled_agents_vpc_and_peer_routes(opctx, &db_vpc).await
+    }
+
+    /// Look up the custom router a VPC Subnet's routes are taken from, which
+    /// must be a custom router in the subnet's own VPC.
+    async fn vpc_subnet_custom_router_id(
+        &self,
+        opctx: &OpContext,
+        authz_vpc: &authz::Vpc,
+        router: &NameOrId,
+    ) -> LookupResult<Uuid> {
+        let router_lookup = match router {
+            NameOrId::Id(id) => {
+                LookupPath::new(opctx, &self.db_datastore).vpc_router_id(*id)
+            }
+            NameOrId::Name(name) => LookupPath::new(opctx, &self.db_datastore)
+                .vpc_id(authz_vpc.id())
+                .vpc_router_name_owned(name.clone().into()),
+        };
+        let (.., authz_router_vpc, authz_router, db_router) =
+            router_lookup.fetch().await?;
+        if authz_router_vpc.id() != authz_vpc.id() {
+            return Err(Error::invalid_request(
+                "a VPC Subnet's custom router must be in the same VPC",
+            ));
+        }
+        if db_router.kind != VpcRouterKind::Custom {
+            return Err(Error::invalid_request(
+                "a VPC Subnet's custom router must be of kind \"custom\"",
+            ));
+        }
+        Ok(authz_router.id())
     }
 
     pub async fn subnet_list_instance_network_interfaces(
@@ -25,6 +25,7 @@ use oximeter::types::ProducerRegistry;
 use oximeter_instruments::http::{HttpService, LatencyTracker};
 use slog::Logger;
 use std::env;
+use std::future::Future;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -197,6 +198,179 @@ impl ServerContext {
             },
         }))
     }
+
+    /// Runs the handler for an external API request, recording its latency
+    /// and, if it's a request that may modify the system, an entry in the
+    /// audit log
+    ///
+    /// Every request other than GET and HEAD is recorded, whether or not it
+    /// succeeds (see [`ServerContext::audit_external_handler()`]).  The
+    /// request is authenticated up front so that the actor can be recorded no
+    /// matter what the handler does (e.g., logging out deletes the session
+    /// that authenticated the request), and the result is handed to the
+    /// handler's [`op_context_for_external_api()`] so that it isn't
+    /// authenticated again.
+    pub async fn instrument_external_handler<H, R>(
+        &self,
+        rqctx: &dropshot::RequestContext<Arc<ServerContext>>,
+        handler: H,
+    ) -> Result<R, dropshot::HttpError>
+    where
+        R: dropshot::HttpResponse,
+        H: Future<Output = Result<R, dropshot::HttpError>>,
+    {
+        // Every typed response declares the status code it succeeds with.
+        // Untyped ones should use `instrument_external_response_handler()`,
+        // but if they don't, 200 is the most likely code.
+        self.instrument_external_handler_with(rqctx, handler, |_| {
+            R::response_metadata().success.unwrap_or(http::StatusCode::OK)
+        })
+        .await
+    }
+
+    /// Like [`ServerContext::instrument_external_handler()`], for handlers
+    /// that build their own `Response<Body>` (and so choose their own status
+    /// codes, even when they succeed)
+    pub async fn instrument_external_response_handler<H>(
+        &self,
+        rqctx: &dropshot::RequestContext<Arc<ServerContext>>,
+        handler: H,
+    ) -> Result<http::Response<hyper::Body>, dropshot::HttpError>
+    where
+        H: Future<
+            Output = Result<http::Response<hyper::Body>, dropshot::HttpError>,
+        >,
+    {
+        self.instrument_external_handler_with(rqctx, handler, |response| {
+            response.status()
+        })
+        .await
+    }
+
+    async fn instrument_external_handler_with<H, R>(
+        &self,
+        rqctx: &dropshot::RequestContext<Arc<ServerContext>>,
+        handler: H,
+        success_code: impl FnOnce(&R) -> http::StatusCode,
+    ) -> Result<R, dropshot::HttpError>
+    where
+        R: dropshot::HttpResponse,
+        H: Future<Output = Result<R, dropshot::HttpError>>,
+    {
+        let request = &rqctx.request;
+        let method = request.method();
+        if method == http::Method::GET || method == http::Method::HEAD {
+            return self
+                .external_latencies
+                .instrument_dropshot_handler(rqctx, handler)
+                .await;
+        }
+
+        let authn =
+            self.external_authn.authn_request(rqctx).await.map(Arc::new);
+        let actor =
+            authn.as_ref().ok().and_then(|authn| authn.actor().copied());
+        let handler = REQUEST_AUTHN.scope(
+            std::cell::RefCell::new(Some(authn)),
+            self.external_latencies.instrument_dropshot_handler(rqctx, handler),
+        );
+        self.audit_external_handler(
+            rqctx,
+            actor.map(|a| a.actor_id()),
+            actor.and_then(|a| a.silo_id()),
+            handler,
+            success_code,
+        )
+        .await
+    }
+
+    /// Runs the handler for an external API request that may modify the
+    /// system, recording an entry for it in the audit log
+    ///
+    /// The entry is recorded before the handler runs, and if that fails, the
+    /// request is rejected without being handled.  `success_code` gives the
+    /// status code of a successful response.
+    pub async fn audit_external_handler<H, R>(
+        &self,
+        rqctx: &dropshot::RequestContext<Arc<ServerContext>>,
+        actor_id: Option<Uuid>,
+        actor_silo_id: Option<Uuid>,
+        handler: H,
+        success_code: impl FnOnce(&R) -> http::StatusCode,
+    ) -> Result<R, dropshot::HttpError>
+    where
+        H: Future<Output = Result<R, dropshot::HttpError>>,
+    {
+        let request = &rqctx.request;
+        let entry = db::model::AuditLogEntryInit {
+            request_id: rqctx.request_id.clone(),
+            time_started: chrono::Utc::now(),
+            http_method: request.method().to_string(),
+            request_uri: request.uri().to_string(),
+            actor_id,
+            actor_silo_id,
+            resource_ids: resource_ids_in_uri(request.uri()),
+        };
+        let entry = match self.nexus.audit_log_begin(entry).await {
+            Ok(entry) => entry,
+            Err(error) => {
+                error!(
+                    rqctx.log,
+                    "failed to record request in audit log";
+                    "error" => %error
+                );
+                return Err(omicron_common::api::external::Error::unavail(
+                    "failed to record request in audit log",
+                )
+                .into());
+            }
+        };
+
+        let result = handler.await;
+        let result_code = match &result {
+            Ok(response) => success_code(response),
+            Err(error) => error.status_code,
+        };
+
+        if let Err(error) =
+            self.nexus.audit_log_complete(entry, result_code.as_u16()).await
+        {
+            // The request has already been handled, so there's nothing to
+            // be gained by failing it now.  Its pending entry remains in the
+            // log as a record that it was made.
+            error!(
+                rqctx.log,
+                "failed to record result of request in audit log";
+                "error" => %error
+            );
+        }
+
+        result
+    }
+}
+
+tokio::task_local! {
+    /// The result of authenticating the external API request being handled,
+    /// if [`ServerContext::instrument_external_handler()`] has already done
+    /// so
+    static REQUEST_AUTHN: std::cell::RefCell<
+        Option<Result<Arc<authn::Context>, authn::Error>>,
+    >;
+}
+
+/// Returns the ids of the resources referenced by a request URI, either as
+/// components of its path or as values of its query parameters
+fn resource_ids_in_uri(uri: &http::Uri) -> Vec<Uuid> {
+    let path_components = uri.path().split('/');
+    let query_values = uri
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter_map(|param| param.split_once('=').map(|(_, value)| value));
+    path_components
+        .chain(query_values)
+        .filter_map(|s| Uuid::try_parse(s).ok())
+        .collect()
 }
 
 /// Authenticates an incoming request to the external API and produces a new
@@ -208,8 +382,17 @@ pub async fn op_context_for_external_api(
     OpContext::new_async(
         &rqctx.log,
         async {
-            let authn =
-                Arc::new(apictx.external_authn.authn_request(rqctx).await?);
+            // Use the result of authenticating the request up front, if there
+            // was one.  It's consumed by the first operation context created
+            // for the request.
+            let authn = match REQUEST_AUTHN
+                .try_with(|authn| authn.borrow_mut().take())
+            {
+                Ok(Some(authn)) => authn?,
+                _ => {
+                    Arc::new(apictx.external_authn.authn_request(rqctx).await?)
+                }
+            };
             let datastore = Arc::clone(apictx.nexus.datastore());
             let authz = authz::Context::new(
                 Arc::clone(&authn),
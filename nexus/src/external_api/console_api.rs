@@ -8,7 +8,8 @@
 //! external API, but in order to avoid CORS issues for now, we are serving
 //! these routes directly from the external API.
 use crate::authn::{
-    silos::IdentityProviderType, USER_TEST_PRIVILEGED, USER_TEST_UNPRIVILEGED,
+    oidc, silos::IdentityProviderType, USER_TEST_PRIVILEGED,
+    USER_TEST_UNPRIVILEGED,
 };
 use crate::ServerContext;
 use crate::{
@@ -103,7 +104,7 @@ pub async fn login_spoof(
         };
         Ok(response)
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Silos have one or more identity providers, and an unauthenticated user will
@@ -317,10 +318,14 @@ pub async fn login_saml_begin(
 
                 http_response_found(sign_in_url)
             }
+            IdentityProviderType::Oidc(_) => Err(HttpError::for_bad_request(
+                None,
+                "identity provider is not a SAML provider".to_string(),
+            )),
         }
     };
 
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Authenticate a user via SAML
@@ -362,6 +367,12 @@ pub async fn login_saml(
                         nexus.samael_max_issue_delay(),
                     )?
                 }
+                IdentityProviderType::Oidc(_) => {
+                    return Err(HttpError::for_bad_request(
+                        None,
+                        "identity provider is not a SAML provider".to_string(),
+                    ));
+                }
             };
 
         let relay_state: Option<RelayState> =
@@ -385,7 +396,308 @@ pub async fn login_saml(
         login_finish(&opctx, apictx, user, relay_state.and_then(|r| r.referer))
             .await
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// OIDC login flow
+// ---------------
+//
+// For identity provider type OIDC, Nexus is the relying party and uses the
+// authorization code flow with PKCE (RFC 7636).  Login begins with
+//
+//   GET /login/{silo_name}/oidc/{provider_name}
+//
+// Nexus generates a random `state`, `nonce`, and PKCE code verifier, stores
+// them (plus the referer) in a short-lived cookie scoped to this provider's
+// login path, and redirects the user's browser to the provider's authorization
+// endpoint with the `state`, `nonce`, and S256 code challenge.
+//
+// After the user authenticates, the provider redirects them to the configured
+// redirect URI, which should be
+//
+//   GET /login/{silo_name}/oidc/{provider_name}/callback?code=...&state=...
+//
+// Nexus checks `state` against the cookie, exchanges the code (and the code
+// verifier) for an ID token at the provider's token endpoint, validates that
+// token against the provider's published signing keys and the `nonce`, and then
+// proceeds exactly as SAML login does from the authenticated subject.
+
+/// Name of the cookie holding the state of an in-progress OIDC login
+const OIDC_LOGIN_COOKIE_NAME: &str = "oidc_login";
+
+/// How long a user has to complete login at an OIDC provider
+const OIDC_LOGIN_COOKIE_MAX_AGE_SECONDS: i64 = 600;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct OidcLoginState {
+    state: String,
+    nonce: String,
+    code_verifier: String,
+    referer: Option<String>,
+}
+
+impl OidcLoginState {
+    fn to_encoded(&self) -> Result<String, anyhow::Error> {
+        Ok(base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            serde_json::to_string(&self).context("encoding login state")?,
+        ))
+    }
+
+    fn from_encoded(encoded: &str) -> Result<Self, anyhow::Error> {
+        serde_json::from_slice(
+            &base64::Engine::decode(
+                &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+                encoded,
+            )
+            .context("base64 decoding login state")?,
+        )
+        .context("json from login state string")
+    }
+}
+
+fn oidc_login_cookie_header_value(
+    path_params: &LoginToProviderPathParam,
+    value: &str,
+    max_age: i64,
+) -> Result<http::HeaderValue, HttpError> {
+    // Scope the cookie to this provider's login path so that it is sent only
+    // with the callback request.
+    // TODO-security:(https://github.com/oxidecomputer/omicron/issues/249): As
+    // with the session cookie, we should insert "Secure;" here.
+    http::HeaderValue::from_str(&format!(
+        "{}={}; Path=/login/{}/oidc/{}; HttpOnly; SameSite=Lax; Max-Age={}",
+        OIDC_LOGIN_COOKIE_NAME,
+        value,
+        path_params.silo_name.as_str(),
+        path_params.provider_name.as_str(),
+        max_age,
+    ))
+    .map_err(|error| {
+        HttpError::for_internal_error(format!(
+            "unsupported cookie value: {:#}",
+            error
+        ))
+    })
+}
+
+/// Prompt user login via OIDC
+///
+/// Redirect the user to their OpenID Connect identity provider.
+#[endpoint {
+   method = GET,
+   path = "/login/{silo_name}/oidc/{provider_name}",
+   tags = ["login"],
+}]
+pub async fn login_oidc_begin(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<LoginToProviderPathParam>,
+) -> Result<HttpResponseFound, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let path_params = path_params.into_inner();
+        let request = &rqctx.request;
+
+        // Use opctx_external_authn because this request will be
+        // unauthenticated.
+        let opctx = nexus.opctx_external_authn();
+
+        let (.., identity_provider) = IdentityProviderType::lookup(
+            &nexus.datastore(),
+            &opctx,
+            &path_params.silo_name,
+            &path_params.provider_name,
+        )
+        .await?;
+
+        let oidc_identity_provider = match identity_provider {
+            IdentityProviderType::Oidc(oidc_identity_provider) => {
+                oidc_identity_provider
+            }
+            IdentityProviderType::Saml(_) => {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    "identity provider is not an OIDC provider".to_string(),
+                ));
+            }
+        };
+
+        let referer = request
+            .headers()
+            .get(hyper::header::REFERER)
+            .map(|value| {
+                value.to_str().map(String::from).map_err(|e| {
+                    HttpError::for_bad_request(
+                        None,
+                        format!("referer header to_str failed! {}", e),
+                    )
+                })
+            })
+            .transpose()?;
+
+        let login_state = OidcLoginState {
+            state: oidc::random_token(),
+            nonce: oidc::random_token(),
+            code_verifier: oidc::random_token(),
+            referer,
+        };
+
+        let authorization_url = oidc_identity_provider
+            .authorization_url(
+                &login_state.state,
+                &login_state.nonce,
+                &oidc::pkce_code_challenge(&login_state.code_verifier),
+            )
+            .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+
+        let encoded_state = login_state.to_encoded().map_err(|e| {
+            HttpError::for_internal_error(format!(
+                "encoding login state failed: {}",
+                e
+            ))
+        })?;
+
+        let mut response = http_response_found(authorization_url)?;
+        response.headers_mut().append(
+            header::SET_COOKIE,
+            oidc_login_cookie_header_value(
+                &path_params,
+                &encoded_state,
+                OIDC_LOGIN_COOKIE_MAX_AGE_SECONDS,
+            )?,
+        );
+        Ok(response)
+    };
+
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Authenticate a user via OIDC
+///
+/// This is the redirect URI to which the identity provider sends the user
+/// after they authenticate.
+#[endpoint {
+   method = GET,
+   path = "/login/{silo_name}/oidc/{provider_name}/callback",
+   tags = ["login"],
+}]
+pub async fn login_oidc(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<LoginToProviderPathParam>,
+    query_params: Query<OidcCallbackQuery>,
+    cookies: Cookies,
+) -> Result<HttpResponseSeeOther, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let path_params = path_params.into_inner();
+        let query = query_params.into_inner();
+
+        if let Some(error) = query.error {
+            return Err(HttpError::for_bad_request(
+                None,
+                format!(
+                    "identity provider returned error {}: {}",
+                    error,
+                    query.error_description.unwrap_or_default()
+                ),
+            ));
+        }
+
+        let (code, state) = match (query.code, query.state) {
+            (Some(code), Some(state)) => (code, state),
+            _ => {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    "missing code or state parameter".to_string(),
+                ));
+            }
+        };
+
+        let login_state = cookies
+            .get(OIDC_LOGIN_COOKIE_NAME)
+            .ok_or_else(|| {
+                HttpError::for_bad_request(
+                    None,
+                    "no OIDC login in progress".to_string(),
+                )
+            })
+            .and_then(|cookie| {
+                OidcLoginState::from_encoded(cookie.value()).map_err(|e| {
+                    HttpError::for_bad_request(
+                        None,
+                        format!("invalid OIDC login state: {:#}", e),
+                    )
+                })
+            })?;
+
+        if login_state.state != state {
+            return Err(HttpError::for_bad_request(
+                None,
+                "state does not match OIDC login in progress".to_string(),
+            ));
+        }
+
+        // By definition, this request is not authenticated.  These operations
+        // happen using the Nexus "external authentication" context, which we
+        // keep specifically for this purpose.
+        let opctx = nexus.opctx_external_authn();
+
+        let (authz_silo, db_silo, identity_provider) =
+            IdentityProviderType::lookup(
+                &nexus.datastore(),
+                &opctx,
+                &path_params.silo_name,
+                &path_params.provider_name,
+            )
+            .await?;
+
+        let authenticated_subject = match identity_provider {
+            IdentityProviderType::Oidc(oidc_identity_provider) => {
+                oidc_identity_provider
+                    .authenticated_subject(
+                        nexus.oidc_client(),
+                        &code,
+                        &login_state.code_verifier,
+                        &login_state.nonce,
+                    )
+                    .await?
+            }
+            IdentityProviderType::Saml(_) => {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    "identity provider is not an OIDC provider".to_string(),
+                ));
+            }
+        };
+
+        let user = nexus
+            .silo_user_from_authenticated_subject(
+                &opctx,
+                &authz_silo,
+                &db_silo,
+                &authenticated_subject,
+            )
+            .await?;
+
+        let mut response =
+            login_finish(&opctx, apictx, user, login_state.referer).await?;
+        response.headers_mut().append(
+            header::SET_COOKIE,
+            oidc_login_cookie_header_value(&path_params, "", 0)?,
+        );
+        Ok(response)
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 #[derive(Deserialize, JsonSchema)]
@@ -419,7 +731,7 @@ pub async fn login_local(
         let user = nexus.login_local(&opctx, &silo_lookup, credentials).await?;
         login_finish(&opctx, apictx, user, None).await
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 async fn login_finish(
@@ -518,7 +830,7 @@ pub async fn logout(
         Ok(response)
     };
 
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 #[derive(Deserialize, JsonSchema)]
@@ -591,7 +903,7 @@ pub async fn login_begin(
         let login_url = get_login_url(redirect_url);
         http_response_found(login_url)
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 pub async fn console_index_or_login_redirect(
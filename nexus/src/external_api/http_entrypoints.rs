@@ -5,13 +5,17 @@
 //! Handler functions (entrypoints) for external HTTP APIs
 
 use super::{
-    console_api, device_auth, params,
+    console_api, device_auth, params, scim,
     views::{
-        self, Certificate, GlobalImage, Group, IdentityProvider, Image, IpPool,
-        IpPoolRange, PhysicalDisk, Project, Rack, Role, Silo, Sled, Snapshot,
-        SshKey, User, UserBuiltin, Vpc, VpcRouter, VpcSubnet,
+        self, AccessToken, AccessTokenCreated, AffinityGroup, AuditLogEntry,
+        Certificate, GlobalImage, Group, IdentityProvider, Image, IpPool,
+        IpPoolRange, PhysicalDisk, Project, ProjectQuotas, Rack,
+        RegionReplacement, Role, Silo, SiloQuotas, Sled, SledDrainProgress,
+        Snapshot, SnapshotSchedule, SshKey, User, UserBuiltin, Vpc, VpcPeering,
+        VpcRouter, VpcSubnet,
     },
 };
+use crate::app::ExportedImage;
 use crate::authz;
 use crate::db;
 use crate::db::identity::Resource;
@@ -38,6 +42,8 @@ use dropshot::WhichPage;
 use dropshot::{
     channel, endpoint, WebsocketChannelResult, WebsocketConnection,
 };
+use http::{Response, StatusCode};
+use hyper::Body;
 use ipnetwork::IpNetwork;
 use nexus_db_queries::db::lookup::ImageLookup;
 use nexus_db_queries::db::lookup::ImageParentLookup;
@@ -46,14 +52,18 @@ use omicron_common::api::external::http_pagination::data_page_params_for;
 use omicron_common::api::external::http_pagination::marker_for_name;
 use omicron_common::api::external::http_pagination::marker_for_name_or_id;
 use omicron_common::api::external::http_pagination::name_or_id_pagination;
+use omicron_common::api::external::http_pagination::LabelFiltered;
 use omicron_common::api::external::http_pagination::PaginatedById;
 use omicron_common::api::external::http_pagination::PaginatedByName;
 use omicron_common::api::external::http_pagination::PaginatedByNameOrId;
+use omicron_common::api::external::http_pagination::PaginatedBySeq;
 use omicron_common::api::external::http_pagination::ScanById;
 use omicron_common::api::external::http_pagination::ScanByName;
 use omicron_common::api::external::http_pagination::ScanByNameOrId;
+use omicron_common::api::external::http_pagination::ScanBySeq;
 use omicron_common::api::external::http_pagination::ScanParams;
 use omicron_common::api::external::DataPageParams;
+use omicron_common::api::external::Digest;
 use omicron_common::api::external::Disk;
 use omicron_common::api::external::Error;
 use omicron_common::api::external::Instance;
@@ -91,6 +101,8 @@ pub fn external_api() -> NexusApiDescription {
         api.register(project_update)?;
         api.register(project_policy_view)?;
         api.register(project_policy_update)?;
+        api.register(project_quotas_view)?;
+        api.register(project_quotas_update)?;
 
         // Operator-Accessible IP Pools API
         api.register(ip_pool_list)?;
@@ -114,6 +126,7 @@ pub fn external_api() -> NexusApiDescription {
         api.register(disk_create)?;
         api.register(disk_view)?;
         api.register(disk_delete)?;
+        api.register(disk_resize)?;
         api.register(disk_metrics_list)?;
 
         api.register(disk_bulk_write_import_start)?;
@@ -121,10 +134,15 @@ pub fn external_api() -> NexusApiDescription {
         api.register(disk_bulk_write_import_stop)?;
         api.register(disk_import_blocks_from_url)?;
         api.register(disk_finalize_import)?;
+        api.register(disk_export_start)?;
+        api.register(disk_export)?;
+        api.register(disk_export_digest)?;
+        api.register(disk_export_stop)?;
 
         api.register(instance_list)?;
         api.register(instance_view)?;
         api.register(instance_create)?;
+        api.register(instance_update)?;
         api.register(instance_delete)?;
         api.register(instance_migrate)?;
         api.register(instance_reboot)?;
@@ -134,6 +152,7 @@ pub fn external_api() -> NexusApiDescription {
         api.register(instance_disk_attach)?;
         api.register(instance_disk_detach)?;
         api.register(instance_serial_console)?;
+        api.register(instance_serial_console_history)?;
         api.register(instance_serial_console_stream)?;
 
         api.register(image_list)?;
@@ -146,6 +165,19 @@ pub fn external_api() -> NexusApiDescription {
         api.register(snapshot_create)?;
         api.register(snapshot_view)?;
         api.register(snapshot_delete)?;
+        api.register(snapshot_export)?;
+        api.register(snapshot_export_digest)?;
+
+        api.register(snapshot_schedule_list)?;
+        api.register(snapshot_schedule_create)?;
+        api.register(snapshot_schedule_view)?;
+        api.register(snapshot_schedule_delete)?;
+
+        api.register(affinity_group_list)?;
+        api.register(affinity_group_create)?;
+        api.register(affinity_group_view)?;
+        api.register(affinity_group_update)?;
+        api.register(affinity_group_delete)?;
 
         api.register(vpc_list)?;
         api.register(vpc_create)?;
@@ -160,6 +192,11 @@ pub fn external_api() -> NexusApiDescription {
         api.register(vpc_subnet_update)?;
         api.register(vpc_subnet_list_network_interfaces)?;
 
+        api.register(vpc_peering_list)?;
+        api.register(vpc_peering_create)?;
+        api.register(vpc_peering_view)?;
+        api.register(vpc_peering_delete)?;
+
         api.register(instance_network_interface_create)?;
         api.register(instance_network_interface_list)?;
         api.register(instance_network_interface_view)?;
@@ -168,6 +205,13 @@ pub fn external_api() -> NexusApiDescription {
 
         api.register(instance_external_ip_list)?;
 
+        api.register(floating_ip_list)?;
+        api.register(floating_ip_create)?;
+        api.register(floating_ip_view)?;
+        api.register(floating_ip_delete)?;
+        api.register(floating_ip_attach)?;
+        api.register(floating_ip_detach)?;
+
         api.register(vpc_router_list)?;
         api.register(vpc_router_view)?;
         api.register(vpc_router_create)?;
@@ -187,8 +231,13 @@ pub fn external_api() -> NexusApiDescription {
         api.register(rack_view)?;
         api.register(sled_list)?;
         api.register(sled_view)?;
+        api.register(sled_expunge)?;
+        api.register(sled_drain)?;
+        api.register(sled_drain_view)?;
+        api.register(sled_undrain)?;
         api.register(sled_physical_disk_list)?;
         api.register(physical_disk_list)?;
+        api.register(region_replacement_list)?;
 
         api.register(user_builtin_list)?;
         api.register(user_builtin_view)?;
@@ -202,6 +251,9 @@ pub fn external_api() -> NexusApiDescription {
         api.register(current_user_ssh_key_view)?;
         api.register(current_user_ssh_key_create)?;
         api.register(current_user_ssh_key_delete)?;
+        api.register(current_user_access_token_list)?;
+        api.register(current_user_access_token_create)?;
+        api.register(current_user_access_token_delete)?;
 
         // Fleet-wide API operations
         api.register(silo_list)?;
@@ -210,12 +262,22 @@ pub fn external_api() -> NexusApiDescription {
         api.register(silo_delete)?;
         api.register(silo_policy_view)?;
         api.register(silo_policy_update)?;
+        api.register(silo_quotas_view)?;
+        api.register(silo_quotas_update)?;
 
         api.register(silo_identity_provider_list)?;
 
         api.register(saml_identity_provider_create)?;
         api.register(saml_identity_provider_view)?;
 
+        api.register(oidc_identity_provider_create)?;
+        api.register(oidc_identity_provider_view)?;
+
+        api.register(scim_client_token_list)?;
+        api.register(scim_client_token_create)?;
+        api.register(scim_client_token_view)?;
+        api.register(scim_client_token_delete)?;
+
         api.register(local_idp_user_create)?;
         api.register(local_idp_user_delete)?;
         api.register(local_idp_user_set_password)?;
@@ -233,6 +295,8 @@ pub fn external_api() -> NexusApiDescription {
 
         api.register(system_metric)?;
 
+        api.register(audit_log_list)?;
+
         api.register(system_update_refresh)?;
         api.register(system_version)?;
         api.register(system_component_version_list)?;
@@ -245,6 +309,8 @@ pub fn external_api() -> NexusApiDescription {
         api.register(update_deployment_view)?;
 
         api.register(user_list)?;
+        api.register(user_access_token_list)?;
+        api.register(user_access_token_delete)?;
         api.register(silo_user_list)?;
         api.register(silo_user_view)?;
         api.register(group_list)?;
@@ -257,6 +323,8 @@ pub fn external_api() -> NexusApiDescription {
         api.register(console_api::login_spoof)?;
         api.register(console_api::login_saml_begin)?;
         api.register(console_api::login_saml)?;
+        api.register(console_api::login_oidc_begin)?;
+        api.register(console_api::login_oidc)?;
         api.register(console_api::logout)?;
 
         api.register(console_api::console_projects)?;
@@ -274,6 +342,19 @@ pub fn external_api() -> NexusApiDescription {
         api.register(device_auth::device_auth_confirm)?;
         api.register(device_auth::device_access_token)?;
 
+        api.register(scim::scim_user_list)?;
+        api.register(scim::scim_user_create)?;
+        api.register(scim::scim_user_view)?;
+        api.register(scim::scim_user_replace)?;
+        api.register(scim::scim_user_patch)?;
+        api.register(scim::scim_user_delete)?;
+        api.register(scim::scim_group_list)?;
+        api.register(scim::scim_group_create)?;
+        api.register(scim::scim_group_view)?;
+        api.register(scim::scim_group_replace)?;
+        api.register(scim::scim_group_patch)?;
+        api.register(scim::scim_group_delete)?;
+
         Ok(())
     }
 
@@ -339,7 +420,7 @@ async fn system_policy_view(
         let policy = nexus.fleet_fetch_policy(&opctx).await?;
         Ok(HttpResponseOk(policy))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Path parameters for `/by-id/` endpoints
@@ -369,7 +450,7 @@ async fn system_policy_update(
         let policy = nexus.fleet_update_policy(&opctx, &new_policy).await?;
         Ok(HttpResponseOk(policy))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch the current silo's IAM policy
@@ -396,7 +477,7 @@ pub async fn policy_view(
         let policy = nexus.silo_fetch_policy(&opctx, &silo_lookup).await?;
         Ok(HttpResponseOk(policy))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update the current silo's IAM policy
@@ -428,7 +509,7 @@ async fn policy_update(
             nexus.silo_update_policy(&opctx, &silo_lookup, &new_policy).await?;
         Ok(HttpResponseOk(policy))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List silos
@@ -463,7 +544,7 @@ async fn silo_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create a silo
@@ -484,7 +565,7 @@ async fn silo_create(
             nexus.silo_create(&opctx, new_silo_params.into_inner()).await?;
         Ok(HttpResponseCreated(silo.try_into()?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a silo
@@ -508,7 +589,7 @@ async fn silo_view(
         let (.., silo) = silo_lookup.fetch().await?;
         Ok(HttpResponseOk(silo.try_into()?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a silo
@@ -532,7 +613,7 @@ async fn silo_delete(
         nexus.silo_delete(&opctx, &silo_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a silo's IAM policy
@@ -554,7 +635,7 @@ async fn silo_policy_view(
         let policy = nexus.silo_fetch_policy(&opctx, &silo_lookup).await?;
         Ok(HttpResponseOk(policy))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update a silo's IAM policy
@@ -582,7 +663,56 @@ async fn silo_policy_update(
             nexus.silo_update_policy(&opctx, &silo_lookup, &new_policy).await?;
         Ok(HttpResponseOk(policy))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch a silo's resource quotas
+#[endpoint {
+    method = GET,
+    path = "/v1/system/silos/{silo}/quotas",
+    tags = ["system"],
+}]
+async fn silo_quotas_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::SiloPath>,
+) -> Result<HttpResponseOk<SiloQuotas>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let silo_lookup = nexus.silo_lookup(&opctx, path.silo)?;
+        let quotas = nexus.silo_quotas_view(&opctx, &silo_lookup).await?;
+        Ok(HttpResponseOk(quotas.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Update a silo's resource quotas
+///
+/// Any limit which is not specified is removed.
+#[endpoint {
+    method = PUT,
+    path = "/v1/system/silos/{silo}/quotas",
+    tags = ["system"],
+}]
+async fn silo_quotas_update(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::SiloPath>,
+    new_quotas: TypedBody<params::QuotasUpdate>,
+) -> Result<HttpResponseOk<SiloQuotas>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let silo_lookup = nexus.silo_lookup(&opctx, path.silo)?;
+        let quotas = nexus
+            .silo_quotas_update(&opctx, &silo_lookup, &new_quotas.into_inner())
+            .await?;
+        Ok(HttpResponseOk(quotas.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Silo-specific user endpoints
@@ -618,7 +748,7 @@ async fn silo_user_list(
             &|_, user: &User| user.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Path parameters for Silo User requests
@@ -650,7 +780,7 @@ async fn silo_user_view(
             nexus.silo_user_fetch(&opctx, &silo_lookup, path.user_id).await?;
         Ok(HttpResponseOk(user.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Silo identity providers
@@ -687,7 +817,7 @@ async fn silo_identity_provider_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Silo SAML identity providers
@@ -718,7 +848,7 @@ async fn saml_identity_provider_create(
             .await?;
         Ok(HttpResponseCreated(provider.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a SAML IdP
@@ -752,11 +882,208 @@ async fn saml_identity_provider_view(
             .await?;
         Ok(HttpResponseOk(provider.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// Silo OIDC identity providers
+
+/// Create an OIDC IdP
+#[endpoint {
+    method = POST,
+    path = "/v1/system/identity-providers/oidc",
+    tags = ["system"],
+}]
+async fn oidc_identity_provider_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<params::SiloSelector>,
+    new_provider: TypedBody<params::OidcIdentityProviderCreate>,
+) -> Result<HttpResponseCreated<views::OidcIdentityProvider>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let silo_lookup = nexus.silo_lookup(&opctx, query.silo)?;
+        let provider = nexus
+            .oidc_identity_provider_create(
+                &opctx,
+                &silo_lookup,
+                new_provider.into_inner(),
+            )
+            .await?;
+        Ok(HttpResponseCreated(provider.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch an OIDC IdP
+#[endpoint {
+    method = GET,
+    path = "/v1/system/identity-providers/oidc/{provider}",
+    tags = ["system"],
+}]
+async fn oidc_identity_provider_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::OidcProviderPath>,
+    query_params: Query<params::SiloSelector>,
+) -> Result<HttpResponseOk<views::OidcIdentityProvider>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let oidc_identity_provider_selector =
+            params::OidcIdentityProviderSelector {
+                silo: Some(query.silo),
+                oidc_identity_provider: path.provider,
+            };
+        let (.., provider) = nexus
+            .oidc_identity_provider_lookup(
+                &opctx,
+                oidc_identity_provider_selector,
+            )?
+            .fetch()
+            .await?;
+        Ok(HttpResponseOk(provider.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // TODO: no DELETE for identity providers?
 
+// Silo SCIM client tokens
+
+/// List a silo's SCIM client tokens
+#[endpoint {
+    method = GET,
+    path = "/v1/system/scim/tokens",
+    tags = ["system"],
+}]
+async fn scim_client_token_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<PaginatedByNameOrId<params::SiloSelector>>,
+) -> Result<HttpResponseOk<ResultsPage<views::ScimClientToken>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let silo_lookup =
+            nexus.silo_lookup(&opctx, scan_params.selector.silo.clone())?;
+        let tokens = nexus
+            .scim_client_tokens_list(&opctx, &silo_lookup, &paginated_by)
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect();
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            tokens,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Create a SCIM client token
+///
+/// SCIM clients (e.g., an enterprise directory) present the token to
+/// provision the silo's users and groups via the SCIM 2.0 endpoints under
+/// `/scim/v2`.  The silo's users must be provisioned via the API.  The
+/// token's secret is only included in this response.
+#[endpoint {
+    method = POST,
+    path = "/v1/system/scim/tokens",
+    tags = ["system"],
+}]
+async fn scim_client_token_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<params::SiloSelector>,
+    new_token: TypedBody<params::ScimClientTokenCreate>,
+) -> Result<HttpResponseCreated<views::ScimClientTokenCreated>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let silo_lookup = nexus.silo_lookup(&opctx, query.silo)?;
+        let token = nexus
+            .scim_client_token_create(
+                &opctx,
+                &silo_lookup,
+                new_token.into_inner(),
+            )
+            .await?;
+        Ok(HttpResponseCreated(token.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch a SCIM client token
+#[endpoint {
+    method = GET,
+    path = "/v1/system/scim/tokens/{token}",
+    tags = ["system"],
+}]
+async fn scim_client_token_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::ScimClientTokenPath>,
+    query_params: Query<params::SiloSelector>,
+) -> Result<HttpResponseOk<views::ScimClientToken>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let scim_client_token_selector = params::ScimClientTokenSelector {
+            silo: Some(query.silo),
+            token: path.token,
+        };
+        let (.., token) = nexus
+            .scim_client_token_lookup(&opctx, scim_client_token_selector)?
+            .fetch_for(authz::Action::Read)
+            .await?;
+        Ok(HttpResponseOk(token.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Delete a SCIM client token
+///
+/// The token is no longer accepted once it has been deleted.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/system/scim/tokens/{token}",
+    tags = ["system"],
+}]
+async fn scim_client_token_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::ScimClientTokenPath>,
+    query_params: Query<params::SiloSelector>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let scim_client_token_selector = params::ScimClientTokenSelector {
+            silo: Some(query.silo),
+            token: path.token,
+        };
+        let token_lookup = nexus
+            .scim_client_token_lookup(&opctx, scim_client_token_selector)?;
+        nexus.scim_client_token_delete(&opctx, &token_lookup).await?;
+        Ok(HttpResponseDeleted())
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
 // "Local" Identity Provider
 
 /// Create a user
@@ -789,7 +1116,7 @@ async fn local_idp_user_create(
             .await?;
         Ok(HttpResponseCreated(user.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a user
@@ -813,7 +1140,7 @@ async fn local_idp_user_delete(
         nexus.local_idp_delete_user(&opctx, &silo_lookup, path.user_id).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Set or invalidate a user's password
@@ -848,7 +1175,7 @@ async fn local_idp_user_set_password(
             .await?;
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List projects
@@ -859,7 +1186,7 @@ async fn local_idp_user_set_password(
 }]
 async fn project_list(
     rqctx: RequestContext<Arc<ServerContext>>,
-    query_params: Query<PaginatedByNameOrId>,
+    query_params: Query<PaginatedByNameOrId<LabelFiltered>>,
 ) -> Result<HttpResponseOk<ResultsPage<Project>>, HttpError> {
     let apictx = rqctx.context();
     let handler = async {
@@ -870,7 +1197,11 @@ async fn project_list(
         let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
         let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
         let projects = nexus
-            .project_list(&opctx, &paginated_by)
+            .project_list(
+                &opctx,
+                &paginated_by,
+                scan_params.selector.label.as_ref(),
+            )
             .await?
             .into_iter()
             .map(|p| p.into())
@@ -881,7 +1212,7 @@ async fn project_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create a project
@@ -902,7 +1233,7 @@ async fn project_create(
             nexus.project_create(&opctx, &new_project.into_inner()).await?;
         Ok(HttpResponseCreated(project.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a project
@@ -926,7 +1257,7 @@ async fn project_view(
             nexus.project_lookup(&opctx, project_selector)?.fetch().await?;
         Ok(HttpResponseOk(project.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a project
@@ -950,7 +1281,7 @@ async fn project_delete(
         nexus.project_delete(&opctx, &project_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // TODO-correctness: Is it valid for PUT to accept application/json that's a
@@ -983,7 +1314,7 @@ async fn project_update(
             .await?;
         Ok(HttpResponseOk(project.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a project's IAM policy
@@ -1008,7 +1339,7 @@ async fn project_policy_view(
             nexus.project_fetch_policy(&opctx, &project_lookup).await?;
         Ok(HttpResponseOk(policy))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update a project's IAM policy
@@ -1036,7 +1367,62 @@ async fn project_policy_update(
             .await?;
         Ok(HttpResponseOk(new_policy))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch a project's resource quotas
+#[endpoint {
+    method = GET,
+    path = "/v1/projects/{project}/quotas",
+    tags = ["projects"],
+}]
+async fn project_quotas_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::ProjectPath>,
+) -> Result<HttpResponseOk<ProjectQuotas>, HttpError> {
+    let apictx = rqctx.context();
+    let nexus = &apictx.nexus;
+    let path = path_params.into_inner();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let project_selector =
+            params::ProjectSelector { project: path.project };
+        let project_lookup = nexus.project_lookup(&opctx, project_selector)?;
+        let quotas = nexus.project_quotas_view(&opctx, &project_lookup).await?;
+        Ok(HttpResponseOk(quotas.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Update a project's resource quotas
+///
+/// Any limit which is not specified is removed. Updating a project's quotas
+/// requires permission to modify its silo.
+#[endpoint {
+    method = PUT,
+    path = "/v1/projects/{project}/quotas",
+    tags = ["projects"],
+}]
+async fn project_quotas_update(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::ProjectPath>,
+    new_quotas: TypedBody<params::QuotasUpdate>,
+) -> Result<HttpResponseOk<ProjectQuotas>, HttpError> {
+    let apictx = rqctx.context();
+    let nexus = &apictx.nexus;
+    let path = path_params.into_inner();
+    let new_quotas = new_quotas.into_inner();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let project_selector =
+            params::ProjectSelector { project: path.project };
+        let project_lookup = nexus.project_lookup(&opctx, project_selector)?;
+        let quotas = nexus
+            .project_quotas_update(&opctx, &project_lookup, &new_quotas)
+            .await?;
+        Ok(HttpResponseOk(quotas.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // IP Pools
@@ -1071,7 +1457,7 @@ async fn ip_pool_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 #[derive(Deserialize, JsonSchema)]
@@ -1097,7 +1483,7 @@ async fn ip_pool_create(
         let pool = nexus.ip_pool_create(&opctx, &pool_params).await?;
         Ok(HttpResponseCreated(IpPool::from(pool)))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch an IP pool
@@ -1119,7 +1505,7 @@ async fn ip_pool_view(
             nexus.ip_pool_lookup(&opctx, &pool_selector)?.fetch().await?;
         Ok(HttpResponseOk(IpPool::from(pool)))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete an IP Pool
@@ -1141,7 +1527,7 @@ async fn ip_pool_delete(
         nexus.ip_pool_delete(&opctx, &pool_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update an IP Pool
@@ -1165,7 +1551,7 @@ async fn ip_pool_update(
         let pool = nexus.ip_pool_update(&opctx, &pool_lookup, &updates).await?;
         Ok(HttpResponseOk(pool.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch the IP pool used for Oxide services
@@ -1184,7 +1570,7 @@ async fn ip_pool_service_view(
         let pool = nexus.ip_pool_service_fetch(&opctx).await?;
         Ok(HttpResponseOk(IpPool::from(pool)))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 type IpPoolRangePaginationParams = PaginationParams<EmptyScanParams, IpNetwork>;
@@ -1232,7 +1618,7 @@ async fn ip_pool_range_list(
             },
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Add a range to an IP pool
@@ -1256,7 +1642,7 @@ async fn ip_pool_range_add(
         let out = nexus.ip_pool_add_range(&opctx, &pool_lookup, &range).await?;
         Ok(HttpResponseCreated(out.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Remove a range from an IP pool
@@ -1280,7 +1666,7 @@ async fn ip_pool_range_remove(
         nexus.ip_pool_delete_range(&opctx, &pool_lookup, &range).await?;
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List ranges for the IP pool used for Oxide services
@@ -1324,7 +1710,7 @@ async fn ip_pool_service_range_list(
             },
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Add a range to an IP pool used for Oxide services
@@ -1345,7 +1731,7 @@ async fn ip_pool_service_range_add(
         let out = nexus.ip_pool_service_add_range(&opctx, &range).await?;
         Ok(HttpResponseCreated(out.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Remove a range from an IP pool used for Oxide services
@@ -1366,7 +1752,7 @@ async fn ip_pool_service_range_remove(
         nexus.ip_pool_service_delete_range(&opctx, &range).await?;
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Disks
@@ -1379,7 +1765,9 @@ async fn ip_pool_service_range_remove(
 }]
 async fn disk_list(
     rqctx: RequestContext<Arc<ServerContext>>,
-    query_params: Query<PaginatedByNameOrId<params::ProjectSelector>>,
+    query_params: Query<
+        PaginatedByNameOrId<LabelFiltered<params::ProjectSelector>>,
+    >,
 ) -> Result<HttpResponseOk<ResultsPage<Disk>>, HttpError> {
     let apictx = rqctx.context();
     let handler = async {
@@ -1389,10 +1777,15 @@ async fn disk_list(
         let pag_params = data_page_params_for(&rqctx, &query)?;
         let scan_params = ScanByNameOrId::from_query(&query)?;
         let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
-        let project_lookup =
-            nexus.project_lookup(&opctx, scan_params.selector.clone())?;
+        let project_lookup = nexus
+            .project_lookup(&opctx, scan_params.selector.selector.clone())?;
         let disks = nexus
-            .disk_list(&opctx, &project_lookup, &paginated_by)
+            .disk_list(
+                &opctx,
+                &project_lookup,
+                &paginated_by,
+                scan_params.selector.label.as_ref(),
+            )
             .await?
             .into_iter()
             .map(|disk| disk.into())
@@ -1403,7 +1796,7 @@ async fn disk_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // TODO-correctness See note about instance create.  This should be async.
@@ -1429,7 +1822,7 @@ async fn disk_create(
             nexus.project_create_disk(&opctx, &project_lookup, &params).await?;
         Ok(HttpResponseCreated(disk.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a disk
@@ -1455,7 +1848,7 @@ async fn disk_view(
             nexus.disk_lookup(&opctx, disk_selector)?.fetch().await?;
         Ok(HttpResponseOk(disk.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a disk
@@ -1481,7 +1874,39 @@ async fn disk_delete(
         nexus.project_delete_disk(&opctx, &disk_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Resize a disk
+///
+/// The disk can only be grown, and must be detached or attached to a stopped
+/// instance. The guest sees the additional space when the instance next
+/// starts.
+#[endpoint {
+    method = POST,
+    path = "/v1/disks/{disk}/resize",
+    tags = ["disks"],
+}]
+async fn disk_resize(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::DiskPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+    resize_params: TypedBody<params::DiskResize>,
+) -> Result<HttpResponseOk<Disk>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let params = resize_params.into_inner();
+        let disk_selector =
+            params::DiskSelector { disk: path.disk, project: query.project };
+        let disk_lookup = nexus.disk_lookup(&opctx, disk_selector)?;
+        let disk = nexus.disk_resize(&opctx, &disk_lookup, &params).await?;
+        Ok(HttpResponseOk(disk.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 #[derive(Display, Serialize, Deserialize, JsonSchema)]
@@ -1543,7 +1968,7 @@ async fn disk_metrics_list(
 
         Ok(HttpResponseOk(result))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Start importing blocks into a disk
@@ -1574,7 +1999,7 @@ async fn disk_bulk_write_import_start(
 
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Import blocks into a disk
@@ -1605,12 +2030,15 @@ async fn disk_bulk_write_import(
 
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Stop importing blocks into a disk
 ///
-/// Stop the process of importing blocks into a disk
+/// Stop the process of importing blocks into a disk. If the blocks written
+/// are a qcow2, VMDK, or dynamic VHD or VHDX image, it is converted to raw
+/// blocks, which replace the image. If the conversion fails, the image is
+/// left as it was written.
 #[endpoint {
     method = POST,
     path = "/v1/disks/{disk}/bulk-write-stop",
@@ -1636,10 +2064,13 @@ async fn disk_bulk_write_import_stop(
 
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Request to import blocks from URL
+///
+/// Images in qcow2, VMDK, VHD or VHDX format are converted to raw blocks as
+/// they are imported. Any other image is imported as raw blocks.
 #[endpoint {
     method = POST,
     path = "/v1/disks/{disk}/import",
@@ -1669,7 +2100,7 @@ async fn disk_import_blocks_from_url(
 
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Confirm disk block import completion
@@ -1699,33 +2130,189 @@ async fn disk_finalize_import(
 
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
-// Instances
-
-/// List instances
+/// Start exporting blocks from a disk
+///
+/// Attach a detached disk to a Crucible Pantry so that its blocks can be
+/// exported. The disk cannot be attached to an instance until the export is
+/// stopped.
 #[endpoint {
-    method = GET,
-    path = "/v1/instances",
-    tags = ["instances"],
+    method = POST,
+    path = "/v1/disks/{disk}/export-start",
+    tags = ["disks"],
 }]
-async fn instance_list(
+async fn disk_export_start(
     rqctx: RequestContext<Arc<ServerContext>>,
-    query_params: Query<PaginatedByNameOrId<params::ProjectSelector>>,
-) -> Result<HttpResponseOk<ResultsPage<Instance>>, HttpError> {
-    let apictx = rqctx.context();
-    let handler = async {
+    path_params: Path<params::DiskPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+
+        let disk_selector =
+            params::DiskSelector { disk: path.disk, project: query.project };
+        let disk_lookup = nexus.disk_lookup(&opctx, disk_selector)?;
+
+        nexus.disk_export_start(&opctx, &disk_lookup).await?;
+
+        Ok(HttpResponseUpdatedNoContent())
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Export blocks from a disk
+///
+/// Stream the contents of a disk that is being exported, either as a raw image
+/// or as a sparse QCOW2 image. The digest of the disk's raw contents can be
+/// found with `export-digest`.
+#[endpoint {
+    method = GET,
+    path = "/v1/disks/{disk}/export",
+    tags = ["disks"],
+}]
+async fn disk_export(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::DiskPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+    export_params: Query<params::ImageExport>,
+) -> Result<Response<Body>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let params = export_params.into_inner();
+
+        let disk_selector =
+            params::DiskSelector { disk: path.disk, project: query.project };
+        let disk_lookup = nexus.disk_lookup(&opctx, disk_selector)?;
+
+        let image = nexus.disk_export(&disk_lookup, params.format).await?;
+        image_export_response(image)
+    };
+    // TODO: instrumentation doesn't work because we use `Response<Body>`
+    //apictx.instrument_external_handler(&rqctx, handler).await
+    handler.await
+}
+
+/// Fetch the digest of an exported disk
+///
+/// Read the whole of a disk that is being exported to find the SHA-256 digest
+/// of its raw contents, which an exported image can be checked against.
+#[endpoint {
+    method = GET,
+    path = "/v1/disks/{disk}/export-digest",
+    tags = ["disks"],
+}]
+async fn disk_export_digest(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::DiskPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseOk<Digest>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+
+        let disk_selector =
+            params::DiskSelector { disk: path.disk, project: query.project };
+        let disk_lookup = nexus.disk_lookup(&opctx, disk_selector)?;
+
+        let digest = nexus.disk_export_digest(&disk_lookup).await?;
+        Ok(HttpResponseOk(digest))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Stop exporting blocks from a disk
+///
+/// Detach a disk from the Crucible Pantry it was being exported from, making it
+/// available for use again.
+#[endpoint {
+    method = POST,
+    path = "/v1/disks/{disk}/export-stop",
+    tags = ["disks"],
+}]
+async fn disk_export_stop(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::DiskPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+
+        let disk_selector =
+            params::DiskSelector { disk: path.disk, project: query.project };
+        let disk_lookup = nexus.disk_lookup(&opctx, disk_selector)?;
+
+        nexus.disk_export_stop(&opctx, &disk_lookup).await?;
+
+        Ok(HttpResponseUpdatedNoContent())
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Build the response for an exported disk or snapshot image
+fn image_export_response(
+    image: ExportedImage,
+) -> Result<Response<Body>, HttpError> {
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "application/octet-stream")
+        .header(
+            http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", image.filename),
+        );
+    if let Some(size) = image.size {
+        response = response.header(http::header::CONTENT_LENGTH, size);
+    }
+    Ok(response.body(image.body)?)
+}
+
+// Instances
+
+/// List instances
+#[endpoint {
+    method = GET,
+    path = "/v1/instances",
+    tags = ["instances"],
+}]
+async fn instance_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<
+        PaginatedByNameOrId<LabelFiltered<params::ProjectSelector>>,
+    >,
+) -> Result<HttpResponseOk<ResultsPage<Instance>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
         let nexus = &apictx.nexus;
         let query = query_params.into_inner();
         let pag_params = data_page_params_for(&rqctx, &query)?;
         let scan_params = ScanByNameOrId::from_query(&query)?;
         let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
         let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
-        let project_lookup =
-            nexus.project_lookup(&opctx, scan_params.selector.clone())?;
+        let project_lookup = nexus
+            .project_lookup(&opctx, scan_params.selector.selector.clone())?;
         let instances = nexus
-            .instance_list(&opctx, &project_lookup, &paginated_by)
+            .instance_list(
+                &opctx,
+                &project_lookup,
+                &paginated_by,
+                scan_params.selector.label.as_ref(),
+            )
             .await?
             .into_iter()
             .map(|i| i.into())
@@ -1736,7 +2323,7 @@ async fn instance_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create an instance
@@ -1766,7 +2353,7 @@ async fn instance_create(
             .await?;
         Ok(HttpResponseCreated(instance.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch an instance
@@ -1795,7 +2382,43 @@ async fn instance_view(
         let (.., instance) = instance_lookup.fetch().await?;
         Ok(HttpResponseOk(instance.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Update an instance
+///
+/// The instance must be stopped. Changes take effect the next time the
+/// instance starts.
+#[endpoint {
+    method = PUT,
+    path = "/v1/instances/{instance}",
+    tags = ["instances"],
+}]
+async fn instance_update(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<params::OptionalProjectSelector>,
+    path_params: Path<params::InstancePath>,
+    updated_instance: TypedBody<params::InstanceUpdate>,
+) -> Result<HttpResponseOk<Instance>, HttpError> {
+    let apictx = rqctx.context();
+    let nexus = &apictx.nexus;
+    let path = path_params.into_inner();
+    let query = query_params.into_inner();
+    let updated_instance = updated_instance.into_inner();
+    let instance_selector = params::InstanceSelector {
+        project: query.project,
+        instance: path.instance,
+    };
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let instance_lookup =
+            nexus.instance_lookup(&opctx, instance_selector)?;
+        let instance = nexus
+            .instance_update(&opctx, &instance_lookup, &updated_instance)
+            .await?;
+        Ok(HttpResponseOk(instance.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete an instance
@@ -1824,7 +2447,7 @@ async fn instance_delete(
         nexus.project_destroy_instance(&opctx, &instance_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // TODO should this be in the public API?
@@ -1862,7 +2485,7 @@ async fn instance_migrate(
             .await?;
         Ok(HttpResponseOk(instance.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Reboot an instance
@@ -1891,7 +2514,7 @@ async fn instance_reboot(
         let instance = nexus.instance_reboot(&opctx, &instance_lookup).await?;
         Ok(HttpResponseAccepted(instance.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Boot an instance
@@ -1920,7 +2543,7 @@ async fn instance_start(
         let instance = nexus.instance_start(&opctx, &instance_lookup).await?;
         Ok(HttpResponseAccepted(instance.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Stop an instance
@@ -1949,7 +2572,7 @@ async fn instance_stop(
         let instance = nexus.instance_stop(&opctx, &instance_lookup).await?;
         Ok(HttpResponseAccepted(instance.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch an instance's serial console
@@ -1980,7 +2603,42 @@ async fn instance_serial_console(
             .await?;
         Ok(HttpResponseOk(data))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch an instance's recorded serial console output
+///
+/// Output is recorded as the instance runs, so this covers output that has
+/// since left the live serial console buffer, as well as previous boots of the
+/// instance.
+#[endpoint {
+    method = GET,
+    path = "/v1/instances/{instance}/serial-console/history",
+    tags = ["instances"],
+}]
+async fn instance_serial_console_history(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::InstancePath>,
+    query_params: Query<params::InstanceSerialConsoleHistoryRequest>,
+) -> Result<HttpResponseOk<params::InstanceSerialConsoleHistory>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let instance_selector = params::InstanceSelector {
+            project: query.project.clone(),
+            instance: path.instance,
+        };
+        let instance_lookup =
+            nexus.instance_lookup(&opctx, instance_selector)?;
+        let history = nexus
+            .instance_serial_console_history(&opctx, &instance_lookup, &query)
+            .await?;
+        Ok(HttpResponseOk(history))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Stream an instance's serial console
@@ -2049,7 +2707,7 @@ async fn instance_disk_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Attach a disk to an instance
@@ -2081,7 +2739,7 @@ async fn instance_disk_attach(
             nexus.instance_attach_disk(&opctx, &instance_lookup, disk).await?;
         Ok(HttpResponseAccepted(disk.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Detach a disk from an instance
@@ -2113,7 +2771,7 @@ async fn instance_disk_detach(
             nexus.instance_detach_disk(&opctx, &instance_lookup, disk).await?;
         Ok(HttpResponseAccepted(disk.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Certificates
@@ -2152,7 +2810,7 @@ async fn certificate_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create a new system-wide x.509 certificate
@@ -2176,7 +2834,7 @@ async fn certificate_create(
         let cert = nexus.certificate_create(&opctx, new_cert_params).await?;
         Ok(HttpResponseCreated(cert.try_into()?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Path parameters for Certificate requests
@@ -2206,7 +2864,7 @@ async fn certificate_view(
             nexus.certificate_lookup(&opctx, &path.certificate).fetch().await?;
         Ok(HttpResponseOk(cert.try_into()?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a certificate
@@ -2234,7 +2892,7 @@ async fn certificate_delete(
             .await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Images
@@ -2273,7 +2931,7 @@ async fn system_image_list(
             &marker_for_name,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create a system-wide image
@@ -2298,7 +2956,7 @@ async fn system_image_create(
         let image = nexus.global_image_create(&opctx, new_image_params).await?;
         Ok(HttpResponseCreated(image.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Path parameters for Image requests
@@ -2329,7 +2987,7 @@ async fn system_image_view(
         let image = nexus.global_image_fetch(&opctx, &image_name).await?;
         Ok(HttpResponseOk(image.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a system-wide image by id
@@ -2352,7 +3010,7 @@ async fn system_image_view_by_id(
         let image = nexus.global_image_fetch_by_id(&opctx, id).await?;
         Ok(HttpResponseOk(image.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a system-wide image
@@ -2379,7 +3037,7 @@ async fn system_image_delete(
         nexus.global_image_delete(&opctx, &image_name).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List images
@@ -2393,7 +3051,9 @@ async fn system_image_delete(
 }]
 async fn image_list(
     rqctx: RequestContext<Arc<ServerContext>>,
-    query_params: Query<PaginatedByNameOrId<params::ImageListSelector>>,
+    query_params: Query<
+        PaginatedByNameOrId<LabelFiltered<params::ImageListSelector>>,
+    >,
 ) -> Result<HttpResponseOk<ResultsPage<Image>>, HttpError> {
     let apictx = rqctx.context();
     let handler = async {
@@ -2403,7 +3063,8 @@ async fn image_list(
         let pag_params = data_page_params_for(&rqctx, &query)?;
         let scan_params = ScanByNameOrId::from_query(&query)?;
         let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
-        let parent_lookup = match scan_params.selector.project.clone() {
+        let LabelFiltered { selector, label } = &scan_params.selector;
+        let parent_lookup = match selector.project.clone() {
             Some(project) => {
                 let project_lookup = nexus.project_lookup(
                     &opctx,
@@ -2420,8 +3081,9 @@ async fn image_list(
             .image_list(
                 &opctx,
                 &parent_lookup,
-                scan_params.selector.include_silo_images.unwrap_or(false),
+                selector.include_silo_images.unwrap_or(false),
                 &paginated_by,
+                label.as_ref(),
             )
             .await?
             .into_iter()
@@ -2433,7 +3095,7 @@ async fn image_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create an image
@@ -2471,7 +3133,7 @@ async fn image_create(
         let image = nexus.image_create(&opctx, &parent_lookup, &params).await?;
         Ok(HttpResponseCreated(image.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch an image
@@ -2514,7 +3176,7 @@ async fn image_view(
         };
         Ok(HttpResponseOk(image.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete an image
@@ -2550,7 +3212,7 @@ async fn image_delete(
         nexus.image_delete(&opctx, &image_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Promote a project image
@@ -2584,7 +3246,7 @@ async fn image_promote(
         let image = nexus.image_promote(&opctx, &image_lookup).await?;
         Ok(HttpResponseAccepted(image.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List network interfaces
@@ -2623,7 +3285,7 @@ async fn instance_network_interface_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create a network interface
@@ -2652,7 +3314,7 @@ async fn instance_network_interface_create(
             .await?;
         Ok(HttpResponseCreated(iface.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a network interface
@@ -2689,7 +3351,7 @@ async fn instance_network_interface_delete(
             .await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a network interface
@@ -2720,7 +3382,7 @@ async fn instance_network_interface_view(
             .await?;
         Ok(HttpResponseOk(interface.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update a network interface
@@ -2762,7 +3424,7 @@ async fn instance_network_interface_update(
             .await?;
         Ok(HttpResponseOk(InstanceNetworkInterface::from(interface)))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // External IP addresses for instances
@@ -2794,21 +3456,21 @@ async fn instance_external_ip_list(
             nexus.instance_list_external_ips(&opctx, &instance_lookup).await?;
         Ok(HttpResponseOk(ResultsPage { items: ips, next_page: None }))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
-// Snapshots
+// Floating IP addresses
 
-/// List snapshots
+/// List floating IPs
 #[endpoint {
     method = GET,
-    path = "/v1/snapshots",
-    tags = ["snapshots"],
+    path = "/v1/floating-ips",
+    tags = ["floating-ips"],
 }]
-async fn snapshot_list(
+async fn floating_ip_list(
     rqctx: RequestContext<Arc<ServerContext>>,
     query_params: Query<PaginatedByNameOrId<params::ProjectSelector>>,
-) -> Result<HttpResponseOk<ResultsPage<Snapshot>>, HttpError> {
+) -> Result<HttpResponseOk<ResultsPage<views::FloatingIp>>, HttpError> {
     let apictx = rqctx.context();
     let handler = async {
         let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
@@ -2819,86 +3481,91 @@ async fn snapshot_list(
         let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
         let project_lookup =
             nexus.project_lookup(&opctx, scan_params.selector.clone())?;
-        let snapshots = nexus
-            .snapshot_list(&opctx, &project_lookup, &paginated_by)
+        let ips = nexus
+            .floating_ip_list(&opctx, &project_lookup, &paginated_by)
             .await?
             .into_iter()
-            .map(|d| d.into())
+            .map(|ip| ip.into())
             .collect();
         Ok(HttpResponseOk(ScanByNameOrId::results_page(
             &query,
-            snapshots,
+            ips,
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
-/// Create a snapshot
+/// Create a floating IP
 ///
-/// Creates a point-in-time snapshot from a disk.
+/// The address is allocated from the given IP pool, or the default pool, and
+/// isn't attached to any instance.
 #[endpoint {
     method = POST,
-    path = "/v1/snapshots",
-    tags = ["snapshots"],
+    path = "/v1/floating-ips",
+    tags = ["floating-ips"],
 }]
-async fn snapshot_create(
+async fn floating_ip_create(
     rqctx: RequestContext<Arc<ServerContext>>,
     query_params: Query<params::ProjectSelector>,
-    new_snapshot: TypedBody<params::SnapshotCreate>,
-) -> Result<HttpResponseCreated<Snapshot>, HttpError> {
+    floating_params: TypedBody<params::FloatingIpCreate>,
+) -> Result<HttpResponseCreated<views::FloatingIp>, HttpError> {
     let apictx = rqctx.context();
     let handler = async {
         let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
         let nexus = &apictx.nexus;
         let query = query_params.into_inner();
-        let new_snapshot_params = &new_snapshot.into_inner();
+        let floating_params = floating_params.into_inner();
         let project_lookup = nexus.project_lookup(&opctx, query)?;
-        let snapshot = nexus
-            .snapshot_create(&opctx, project_lookup, &new_snapshot_params)
+        let ip = nexus
+            .floating_ip_create(&opctx, &project_lookup, &floating_params)
             .await?;
-        Ok(HttpResponseCreated(snapshot.into()))
+        Ok(HttpResponseCreated(ip.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
-/// Fetch a snapshot
+/// Fetch a floating IP
 #[endpoint {
     method = GET,
-    path = "/v1/snapshots/{snapshot}",
-    tags = ["snapshots"],
+    path = "/v1/floating-ips/{floating_ip}",
+    tags = ["floating-ips"],
 }]
-async fn snapshot_view(
+async fn floating_ip_view(
     rqctx: RequestContext<Arc<ServerContext>>,
-    path_params: Path<params::SnapshotPath>,
+    path_params: Path<params::FloatingIpPath>,
     query_params: Query<params::OptionalProjectSelector>,
-) -> Result<HttpResponseOk<Snapshot>, HttpError> {
+) -> Result<HttpResponseOk<views::FloatingIp>, HttpError> {
     let apictx = rqctx.context();
     let handler = async {
         let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
         let nexus = &apictx.nexus;
         let path = path_params.into_inner();
         let query = query_params.into_inner();
-        let snapshot_selector = params::SnapshotSelector {
+        let floating_ip_selector = params::FloatingIpSelector {
             project: query.project,
-            snapshot: path.snapshot,
+            floating_ip: path.floating_ip,
         };
-        let (.., snapshot) =
-            nexus.snapshot_lookup(&opctx, snapshot_selector)?.fetch().await?;
-        Ok(HttpResponseOk(snapshot.into()))
+        let (.., ip) = nexus
+            .floating_ip_lookup(&opctx, floating_ip_selector)?
+            .fetch()
+            .await?;
+        Ok(HttpResponseOk(ip.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
-/// Delete a snapshot
+/// Delete a floating IP
+///
+/// The floating IP must not be attached to an instance.
 #[endpoint {
     method = DELETE,
-    path = "/v1/snapshots/{snapshot}",
-    tags = ["snapshots"],
+    path = "/v1/floating-ips/{floating_ip}",
+    tags = ["floating-ips"],
 }]
-async fn snapshot_delete(
+async fn floating_ip_delete(
     rqctx: RequestContext<Arc<ServerContext>>,
-    path_params: Path<params::SnapshotPath>,
+    path_params: Path<params::FloatingIpPath>,
     query_params: Query<params::OptionalProjectSelector>,
 ) -> Result<HttpResponseDeleted, HttpError> {
     let apictx = rqctx.context();
@@ -2907,80 +3574,648 @@ async fn snapshot_delete(
         let nexus = &apictx.nexus;
         let path = path_params.into_inner();
         let query = query_params.into_inner();
-        let snapshot_selector = params::SnapshotSelector {
+        let floating_ip_selector = params::FloatingIpSelector {
             project: query.project,
-            snapshot: path.snapshot,
+            floating_ip: path.floating_ip,
         };
-        let snapshot_lookup =
-            nexus.snapshot_lookup(&opctx, snapshot_selector)?;
-        nexus.snapshot_delete(&opctx, &snapshot_lookup).await?;
+        let fip_lookup =
+            nexus.floating_ip_lookup(&opctx, floating_ip_selector)?;
+        nexus.floating_ip_delete(&opctx, &fip_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
-// VPCs
-
-/// List VPCs
+/// Attach a floating IP to an instance
+///
+/// The instance must be stopped.  An instance's external addresses are fixed
+/// when its network interfaces are set up on its sled, so it starts using the
+/// floating IP the next time it starts.
 #[endpoint {
-    method = GET,
-    path = "/v1/vpcs",
-    tags = ["vpcs"],
+    method = POST,
+    path = "/v1/floating-ips/{floating_ip}/attach",
+    tags = ["floating-ips"],
 }]
-async fn vpc_list(
+async fn floating_ip_attach(
     rqctx: RequestContext<Arc<ServerContext>>,
-    query_params: Query<PaginatedByNameOrId<params::ProjectSelector>>,
-) -> Result<HttpResponseOk<ResultsPage<Vpc>>, HttpError> {
+    path_params: Path<params::FloatingIpPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+    target: TypedBody<params::FloatingIpAttach>,
+) -> Result<HttpResponseAccepted<views::FloatingIp>, HttpError> {
     let apictx = rqctx.context();
     let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
         let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
         let query = query_params.into_inner();
-        let pag_params = data_page_params_for(&rqctx, &query)?;
-        let scan_params = ScanByNameOrId::from_query(&query)?;
-        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
-        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
-        let project_lookup =
-            nexus.project_lookup(&opctx, scan_params.selector.clone())?;
-        let vpcs = nexus
-            .vpc_list(&opctx, &project_lookup, &paginated_by)
-            .await?
-            .into_iter()
-            .map(|p| p.into())
-            .collect();
-
-        Ok(HttpResponseOk(ScanByNameOrId::results_page(
-            &query,
-            vpcs,
-            &marker_for_name_or_id,
-        )?))
+        let floating_ip_selector = params::FloatingIpSelector {
+            project: query.project,
+            floating_ip: path.floating_ip,
+        };
+        let fip_lookup =
+            nexus.floating_ip_lookup(&opctx, floating_ip_selector)?;
+        let ip = nexus
+            .floating_ip_attach(&opctx, &fip_lookup, &target.into_inner())
+            .await?;
+        Ok(HttpResponseAccepted(ip.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
-/// Create a VPC
+/// Detach a floating IP from an instance
+///
+/// The instance must be stopped, for the same reason as when attaching the
+/// floating IP.
 #[endpoint {
     method = POST,
-    path = "/v1/vpcs",
-    tags = ["vpcs"],
+    path = "/v1/floating-ips/{floating_ip}/detach",
+    tags = ["floating-ips"],
 }]
-async fn vpc_create(
+async fn floating_ip_detach(
     rqctx: RequestContext<Arc<ServerContext>>,
-    query_params: Query<params::ProjectSelector>,
-    body: TypedBody<params::VpcCreate>,
-) -> Result<HttpResponseCreated<Vpc>, HttpError> {
+    path_params: Path<params::FloatingIpPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseAccepted<views::FloatingIp>, HttpError> {
     let apictx = rqctx.context();
-    let nexus = &apictx.nexus;
-    let query = query_params.into_inner();
-    let new_vpc_params = body.into_inner();
     let handler = async {
         let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
-        let project_lookup = nexus.project_lookup(&opctx, query)?;
-        let vpc = nexus
-            .project_create_vpc(&opctx, &project_lookup, &new_vpc_params)
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let floating_ip_selector = params::FloatingIpSelector {
+            project: query.project,
+            floating_ip: path.floating_ip,
+        };
+        let fip_lookup =
+            nexus.floating_ip_lookup(&opctx, floating_ip_selector)?;
+        let ip = nexus.floating_ip_detach(&opctx, &fip_lookup).await?;
+        Ok(HttpResponseAccepted(ip.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// Snapshots
+
+/// List snapshots
+#[endpoint {
+    method = GET,
+    path = "/v1/snapshots",
+    tags = ["snapshots"],
+}]
+async fn snapshot_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<
+        PaginatedByNameOrId<LabelFiltered<params::ProjectSelector>>,
+    >,
+) -> Result<HttpResponseOk<ResultsPage<Snapshot>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let project_lookup = nexus
+            .project_lookup(&opctx, scan_params.selector.selector.clone())?;
+        let snapshots = nexus
+            .snapshot_list(
+                &opctx,
+                &project_lookup,
+                &paginated_by,
+                scan_params.selector.label.as_ref(),
+            )
+            .await?
+            .into_iter()
+            .map(|d| d.into())
+            .collect();
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            snapshots,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Create a snapshot
+///
+/// Creates a point-in-time snapshot from a disk.
+#[endpoint {
+    method = POST,
+    path = "/v1/snapshots",
+    tags = ["snapshots"],
+}]
+async fn snapshot_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<params::ProjectSelector>,
+    new_snapshot: TypedBody<params::SnapshotCreate>,
+) -> Result<HttpResponseCreated<Snapshot>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let new_snapshot_params = &new_snapshot.into_inner();
+        let project_lookup = nexus.project_lookup(&opctx, query)?;
+        let snapshot = nexus
+            .snapshot_create(&opctx, project_lookup, &new_snapshot_params)
+            .await?;
+        Ok(HttpResponseCreated(snapshot.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch a snapshot
+#[endpoint {
+    method = GET,
+    path = "/v1/snapshots/{snapshot}",
+    tags = ["snapshots"],
+}]
+async fn snapshot_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::SnapshotPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseOk<Snapshot>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let snapshot_selector = params::SnapshotSelector {
+            project: query.project,
+            snapshot: path.snapshot,
+        };
+        let (.., snapshot) =
+            nexus.snapshot_lookup(&opctx, snapshot_selector)?.fetch().await?;
+        Ok(HttpResponseOk(snapshot.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Delete a snapshot
+#[endpoint {
+    method = DELETE,
+    path = "/v1/snapshots/{snapshot}",
+    tags = ["snapshots"],
+}]
+async fn snapshot_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::SnapshotPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let snapshot_selector = params::SnapshotSelector {
+            project: query.project,
+            snapshot: path.snapshot,
+        };
+        let snapshot_lookup =
+            nexus.snapshot_lookup(&opctx, snapshot_selector)?;
+        nexus.snapshot_delete(&opctx, &snapshot_lookup).await?;
+        Ok(HttpResponseDeleted())
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Export blocks from a snapshot
+///
+/// Stream the contents of a snapshot, either as a raw image or as a sparse
+/// QCOW2 image. The digest of the snapshot's raw contents can be found with
+/// `export-digest`.
+#[endpoint {
+    method = GET,
+    path = "/v1/snapshots/{snapshot}/export",
+    tags = ["snapshots"],
+}]
+async fn snapshot_export(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::SnapshotPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+    export_params: Query<params::ImageExport>,
+) -> Result<Response<Body>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let params = export_params.into_inner();
+        let snapshot_selector = params::SnapshotSelector {
+            project: query.project,
+            snapshot: path.snapshot,
+        };
+        let snapshot_lookup =
+            nexus.snapshot_lookup(&opctx, snapshot_selector)?;
+        let image = nexus
+            .snapshot_export(&opctx, &snapshot_lookup, params.format)
+            .await?;
+        image_export_response(image)
+    };
+    // TODO: instrumentation doesn't work because we use `Response<Body>`
+    //apictx.instrument_external_handler(&rqctx, handler).await
+    handler.await
+}
+
+/// Fetch the digest of a snapshot
+///
+/// Read the whole of a snapshot to find the SHA-256 digest of its raw contents,
+/// which an exported image can be checked against.
+#[endpoint {
+    method = GET,
+    path = "/v1/snapshots/{snapshot}/export-digest",
+    tags = ["snapshots"],
+}]
+async fn snapshot_export_digest(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::SnapshotPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseOk<Digest>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let snapshot_selector = params::SnapshotSelector {
+            project: query.project,
+            snapshot: path.snapshot,
+        };
+        let snapshot_lookup =
+            nexus.snapshot_lookup(&opctx, snapshot_selector)?;
+        let digest =
+            nexus.snapshot_export_digest(&opctx, &snapshot_lookup).await?;
+        Ok(HttpResponseOk(digest))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// Snapshot Schedules
+
+/// List snapshot schedules
+#[endpoint {
+    method = GET,
+    path = "/v1/snapshot-schedules",
+    tags = ["snapshots"],
+}]
+async fn snapshot_schedule_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<PaginatedByNameOrId<params::ProjectSelector>>,
+) -> Result<HttpResponseOk<ResultsPage<SnapshotSchedule>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let project_lookup =
+            nexus.project_lookup(&opctx, scan_params.selector.clone())?;
+        let schedules = nexus
+            .snapshot_schedule_list(&opctx, &project_lookup, &paginated_by)
+            .await?
+            .into_iter()
+            .map(|s| s.into())
+            .collect();
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            schedules,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Create a snapshot schedule
+///
+/// Snapshots of the disk are taken periodically on behalf of the user who
+/// creates the schedule, and the oldest ones are deleted once the schedule
+/// has taken more than its retention count.
+#[endpoint {
+    method = POST,
+    path = "/v1/snapshot-schedules",
+    tags = ["snapshots"],
+}]
+async fn snapshot_schedule_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<params::ProjectSelector>,
+    new_schedule: TypedBody<params::SnapshotScheduleCreate>,
+) -> Result<HttpResponseCreated<SnapshotSchedule>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let new_schedule_params = &new_schedule.into_inner();
+        let project_lookup = nexus.project_lookup(&opctx, query)?;
+        let schedule = nexus
+            .snapshot_schedule_create(
+                &opctx,
+                &project_lookup,
+                &new_schedule_params,
+            )
+            .await?;
+        Ok(HttpResponseCreated(schedule.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch a snapshot schedule
+#[endpoint {
+    method = GET,
+    path = "/v1/snapshot-schedules/{snapshot_schedule}",
+    tags = ["snapshots"],
+}]
+async fn snapshot_schedule_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::SnapshotSchedulePath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseOk<SnapshotSchedule>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let schedule_selector = params::SnapshotScheduleSelector {
+            project: query.project,
+            snapshot_schedule: path.snapshot_schedule,
+        };
+        let (.., schedule) = nexus
+            .snapshot_schedule_lookup(&opctx, schedule_selector)?
+            .fetch()
+            .await?;
+        Ok(HttpResponseOk(schedule.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Delete a snapshot schedule
+///
+/// Snapshots that the schedule has already taken are not deleted.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/snapshot-schedules/{snapshot_schedule}",
+    tags = ["snapshots"],
+}]
+async fn snapshot_schedule_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::SnapshotSchedulePath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let schedule_selector = params::SnapshotScheduleSelector {
+            project: query.project,
+            snapshot_schedule: path.snapshot_schedule,
+        };
+        let schedule_lookup =
+            nexus.snapshot_schedule_lookup(&opctx, schedule_selector)?;
+        nexus.snapshot_schedule_delete(&opctx, &schedule_lookup).await?;
+        Ok(HttpResponseDeleted())
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// Affinity Groups
+
+/// List affinity groups
+#[endpoint {
+    method = GET,
+    path = "/v1/affinity-groups",
+    tags = ["affinity"],
+}]
+async fn affinity_group_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<PaginatedByNameOrId<params::ProjectSelector>>,
+) -> Result<HttpResponseOk<ResultsPage<AffinityGroup>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let project_lookup =
+            nexus.project_lookup(&opctx, scan_params.selector.clone())?;
+        let groups = nexus
+            .affinity_group_list(&opctx, &project_lookup, &paginated_by)
+            .await?
+            .into_iter()
+            .map(|g| g.into())
+            .collect();
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            groups,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Create an affinity group
+///
+/// Instances that name the group when they are created are placed on sleds
+/// according to the group's kind and policy.
+#[endpoint {
+    method = POST,
+    path = "/v1/affinity-groups",
+    tags = ["affinity"],
+}]
+async fn affinity_group_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<params::ProjectSelector>,
+    new_group: TypedBody<params::AffinityGroupCreate>,
+) -> Result<HttpResponseCreated<AffinityGroup>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let new_group_params = &new_group.into_inner();
+        let project_lookup = nexus.project_lookup(&opctx, query)?;
+        let group = nexus
+            .affinity_group_create(&opctx, &project_lookup, &new_group_params)
+            .await?;
+        Ok(HttpResponseCreated(group.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch an affinity group
+#[endpoint {
+    method = GET,
+    path = "/v1/affinity-groups/{affinity_group}",
+    tags = ["affinity"],
+}]
+async fn affinity_group_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::AffinityGroupPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseOk<AffinityGroup>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let group_selector = params::AffinityGroupSelector {
+            project: query.project,
+            affinity_group: path.affinity_group,
+        };
+        let (.., group) = nexus
+            .affinity_group_lookup(&opctx, group_selector)?
+            .fetch()
+            .await?;
+        Ok(HttpResponseOk(group.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Update an affinity group
+#[endpoint {
+    method = PUT,
+    path = "/v1/affinity-groups/{affinity_group}",
+    tags = ["affinity"],
+}]
+async fn affinity_group_update(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::AffinityGroupPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+    updated_group: TypedBody<params::AffinityGroupUpdate>,
+) -> Result<HttpResponseOk<AffinityGroup>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let updated_group_params = &updated_group.into_inner();
+        let group_selector = params::AffinityGroupSelector {
+            project: query.project,
+            affinity_group: path.affinity_group,
+        };
+        let group_lookup =
+            nexus.affinity_group_lookup(&opctx, group_selector)?;
+        let group = nexus
+            .affinity_group_update(&opctx, &group_lookup, &updated_group_params)
+            .await?;
+        Ok(HttpResponseOk(group.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Delete an affinity group
+///
+/// Instances in the group are removed from it but are otherwise unaffected.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/affinity-groups/{affinity_group}",
+    tags = ["affinity"],
+}]
+async fn affinity_group_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::AffinityGroupPath>,
+    query_params: Query<params::OptionalProjectSelector>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let group_selector = params::AffinityGroupSelector {
+            project: query.project,
+            affinity_group: path.affinity_group,
+        };
+        let group_lookup =
+            nexus.affinity_group_lookup(&opctx, group_selector)?;
+        nexus.affinity_group_delete(&opctx, &group_lookup).await?;
+        Ok(HttpResponseDeleted())
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// VPCs
+
+/// List VPCs
+#[endpoint {
+    method = GET,
+    path = "/v1/vpcs",
+    tags = ["vpcs"],
+}]
+async fn vpc_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<
+        PaginatedByNameOrId<LabelFiltered<params::ProjectSelector>>,
+    >,
+) -> Result<HttpResponseOk<ResultsPage<Vpc>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let project_lookup = nexus
+            .project_lookup(&opctx, scan_params.selector.selector.clone())?;
+        let vpcs = nexus
+            .vpc_list(
+                &opctx,
+                &project_lookup,
+                &paginated_by,
+                scan_params.selector.label.as_ref(),
+            )
+            .await?
+            .into_iter()
+            .map(|p| p.into())
+            .collect();
+
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            vpcs,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Create a VPC
+#[endpoint {
+    method = POST,
+    path = "/v1/vpcs",
+    tags = ["vpcs"],
+}]
+async fn vpc_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<params::ProjectSelector>,
+    body: TypedBody<params::VpcCreate>,
+) -> Result<HttpResponseCreated<Vpc>, HttpError> {
+    let apictx = rqctx.context();
+    let nexus = &apictx.nexus;
+    let query = query_params.into_inner();
+    let new_vpc_params = body.into_inner();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let project_lookup = nexus.project_lookup(&opctx, query)?;
+        let vpc = nexus
+            .project_create_vpc(&opctx, &project_lookup, &new_vpc_params)
             .await?;
         Ok(HttpResponseCreated(vpc.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a VPC
@@ -3005,7 +4240,7 @@ async fn vpc_view(
         let (.., vpc) = nexus.vpc_lookup(&opctx, vpc_selector)?.fetch().await?;
         Ok(HttpResponseOk(vpc.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update a VPC
@@ -3035,7 +4270,7 @@ async fn vpc_update(
             .await?;
         Ok(HttpResponseOk(vpc.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a VPC
@@ -3061,7 +4296,7 @@ async fn vpc_delete(
         nexus.project_delete_vpc(&opctx, &vpc_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List subnets
@@ -3096,7 +4331,7 @@ async fn vpc_subnet_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create a subnet
@@ -3121,7 +4356,7 @@ async fn vpc_subnet_create(
             nexus.vpc_create_subnet(&opctx, &vpc_lookup, &create).await?;
         Ok(HttpResponseCreated(subnet.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a subnet
@@ -3150,7 +4385,7 @@ async fn vpc_subnet_view(
             nexus.vpc_subnet_lookup(&opctx, subnet_selector)?.fetch().await?;
         Ok(HttpResponseOk(subnet.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a subnet
@@ -3179,10 +4414,15 @@ async fn vpc_subnet_delete(
         nexus.vpc_delete_subnet(&opctx, &subnet_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update a subnet
+///
+/// A subnet's custom router can only be changed while no instance in the VPC
+/// is running, unless the change only adds routes to the subnet's table.
+/// Running instances can't yet have routes removed from their network
+/// interfaces, so such a change is rejected until the instances are stopped.
 #[endpoint {
     method = PUT,
     path = "/v1/vpc-subnets/{subnet}",
@@ -3212,7 +4452,7 @@ async fn vpc_subnet_update(
             .await?;
         Ok(HttpResponseOk(subnet.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // This endpoint is likely temporary. We would rather list all IPs allocated in
@@ -3233,35 +4473,165 @@ async fn vpc_subnet_list_network_interfaces(
     let apictx = rqctx.context();
     let handler = async {
         let nexus = &apictx.nexus;
-        let query = query_params.into_inner();
+        let query = query_params.into_inner();
+        let path = path_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let subnet_selector = params::SubnetSelector {
+            project: scan_params.selector.project.clone(),
+            vpc: scan_params.selector.vpc.clone(),
+            subnet: path.subnet,
+        };
+        let subnet_lookup = nexus.vpc_subnet_lookup(&opctx, subnet_selector)?;
+        let interfaces = nexus
+            .subnet_list_instance_network_interfaces(
+                &opctx,
+                &subnet_lookup,
+                &paginated_by,
+            )
+            .await?
+            .into_iter()
+            .map(|interfaces| interfaces.into())
+            .collect();
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            interfaces,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// VPC Peerings
+
+/// List peerings
+///
+/// Lists the peerings created in a VPC.  Peerings created in other VPCs that
+/// peer with this one are listed under those VPCs.
+#[endpoint {
+    method = GET,
+    path = "/v1/vpc-peerings",
+    tags = ["vpcs"],
+}]
+async fn vpc_peering_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<PaginatedByNameOrId<params::VpcSelector>>,
+) -> Result<HttpResponseOk<ResultsPage<VpcPeering>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let vpc_lookup =
+            nexus.vpc_lookup(&opctx, scan_params.selector.clone())?;
+        let peerings = nexus
+            .vpc_peering_list(&opctx, &vpc_lookup, &paginated_by)
+            .await?
+            .into_iter()
+            .map(|peering| peering.into())
+            .collect();
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            peerings,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Create a peering
+///
+/// Peers the VPC with another VPC in the same silo, so that instances in
+/// either VPC can reach instances in the other.
+#[endpoint {
+    method = POST,
+    path = "/v1/vpc-peerings",
+    tags = ["vpcs"],
+}]
+async fn vpc_peering_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<params::VpcSelector>,
+    create_params: TypedBody<params::VpcPeeringCreate>,
+) -> Result<HttpResponseCreated<VpcPeering>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let create = create_params.into_inner();
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let vpc_lookup = nexus.vpc_lookup(&opctx, query)?;
+        let peering =
+            nexus.vpc_create_peering(&opctx, &vpc_lookup, &create).await?;
+        Ok(HttpResponseCreated(peering.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch a peering
+#[endpoint {
+    method = GET,
+    path = "/v1/vpc-peerings/{peering}",
+    tags = ["vpcs"],
+}]
+async fn vpc_peering_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::PeeringPath>,
+    query_params: Query<params::OptionalVpcSelector>,
+) -> Result<HttpResponseOk<VpcPeering>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let peering_selector = params::PeeringSelector {
+            project: query.project,
+            vpc: query.vpc,
+            peering: path.peering,
+        };
+        let (.., peering) =
+            nexus.vpc_peering_lookup(&opctx, peering_selector)?.fetch().await?;
+        Ok(HttpResponseOk(peering.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Delete a peering
+///
+/// Instances in the two VPCs can no longer reach each other once the peering
+/// is deleted.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/vpc-peerings/{peering}",
+    tags = ["vpcs"],
+}]
+async fn vpc_peering_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::PeeringPath>,
+    query_params: Query<params::OptionalVpcSelector>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
         let path = path_params.into_inner();
-        let pag_params = data_page_params_for(&rqctx, &query)?;
-        let scan_params = ScanByNameOrId::from_query(&query)?;
-        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let query = query_params.into_inner();
         let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
-        let subnet_selector = params::SubnetSelector {
-            project: scan_params.selector.project.clone(),
-            vpc: scan_params.selector.vpc.clone(),
-            subnet: path.subnet,
+        let peering_selector = params::PeeringSelector {
+            project: query.project,
+            vpc: query.vpc,
+            peering: path.peering,
         };
-        let subnet_lookup = nexus.vpc_subnet_lookup(&opctx, subnet_selector)?;
-        let interfaces = nexus
-            .subnet_list_instance_network_interfaces(
-                &opctx,
-                &subnet_lookup,
-                &paginated_by,
-            )
-            .await?
-            .into_iter()
-            .map(|interfaces| interfaces.into())
-            .collect();
-        Ok(HttpResponseOk(ScanByNameOrId::results_page(
-            &query,
-            interfaces,
-            &marker_for_name_or_id,
-        )?))
+        let peering_lookup =
+            nexus.vpc_peering_lookup(&opctx, peering_selector)?;
+        nexus.vpc_delete_peering(&opctx, &peering_lookup).await?;
+        Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // VPC Firewalls
@@ -3291,7 +4661,7 @@ async fn vpc_firewall_rules_view(
             rules: rules.into_iter().map(|rule| rule.into()).collect(),
         }))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Replace firewall rules
@@ -3321,7 +4691,7 @@ async fn vpc_firewall_rules_update(
             rules: rules.into_iter().map(|rule| rule.into()).collect(),
         }))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // VPC Routers
@@ -3358,7 +4728,7 @@ async fn vpc_router_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a router
@@ -3387,7 +4757,7 @@ async fn vpc_router_view(
             nexus.vpc_router_lookup(&opctx, router_selector)?.fetch().await?;
         Ok(HttpResponseOk(vpc_router.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create a VPC router
@@ -3418,7 +4788,7 @@ async fn vpc_router_create(
             .await?;
         Ok(HttpResponseCreated(router.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a router
@@ -3447,7 +4817,7 @@ async fn vpc_router_delete(
         nexus.vpc_delete_router(&opctx, &router_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update a router
@@ -3480,7 +4850,7 @@ async fn vpc_router_update(
             .await?;
         Ok(HttpResponseOk(router.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List routes
@@ -3517,7 +4887,7 @@ async fn vpc_router_route_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Vpc Router Routes
@@ -3551,7 +4921,7 @@ async fn vpc_router_route_view(
             .await?;
         Ok(HttpResponseOk(route.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create a router
@@ -3582,10 +4952,14 @@ async fn vpc_router_route_create(
             .await?;
         Ok(HttpResponseCreated(route.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete a route
+///
+/// A route can only be deleted while no instance in its VPC is running.
+/// Running instances can't yet have routes removed from their network
+/// interfaces, so the request is rejected until the instances are stopped.
 #[endpoint {
     method = DELETE,
     path = "/v1/vpc-router-routes/{route}",
@@ -3613,10 +4987,15 @@ async fn vpc_router_route_delete(
         nexus.router_delete_route(&opctx, &route_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Update a route
+///
+/// A route's destination or target can only be changed while no instance in
+/// its VPC is running. Running instances can't yet have routes removed from
+/// their network interfaces, so such a change is rejected until the instances
+/// are stopped.
 #[endpoint {
     method = PUT,
     path = "/v1/vpc-router-routes/{route}",
@@ -3648,7 +5027,7 @@ async fn vpc_router_route_update(
             .await?;
         Ok(HttpResponseOk(route.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Racks
@@ -3680,7 +5059,7 @@ async fn rack_list(
             &|_, rack: &Rack| rack.identity.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Path parameters for Rack requests
@@ -3708,7 +5087,7 @@ async fn rack_view(
         let rack_info = nexus.rack_lookup(&opctx, &path.rack_id).await?;
         Ok(HttpResponseOk(rack_info.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Sleds
@@ -3740,7 +5119,7 @@ async fn sled_list(
             &|_, sled: &Sled| sled.identity.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Path parameters for Sled requests
@@ -3768,7 +5147,101 @@ async fn sled_view(
         let sled_info = nexus.sled_lookup(&opctx, &path.sled_id).await?;
         Ok(HttpResponseOk(sled_info.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Expunge a sled
+///
+/// Permanently removes the sled from service.  Instances on the sled are
+/// marked failed and the resources they held on the sled are released.  An
+/// expunged sled is never chosen to run instances again.
+#[endpoint {
+    method = POST,
+    path = "/v1/system/hardware/sleds/{sled_id}/expunge",
+    tags = ["system"],
+}]
+async fn sled_expunge(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<SledPathParam>,
+) -> Result<HttpResponseOk<Sled>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let sled = nexus.sled_expunge(&opctx, path.sled_id).await?;
+        Ok(HttpResponseOk(sled.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Drain a sled
+///
+/// Stops placing new instances on the sled and migrates the instances running
+/// on it to other sleds, a few at a time.  Returns how far along draining is.
+#[endpoint {
+    method = POST,
+    path = "/v1/system/hardware/sleds/{sled_id}/drain",
+    tags = ["system"],
+}]
+async fn sled_drain(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<SledPathParam>,
+) -> Result<HttpResponseOk<SledDrainProgress>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let progress = nexus.sled_drain(&opctx, path.sled_id).await?;
+        Ok(HttpResponseOk(progress))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Fetch the progress of draining a sled
+#[endpoint {
+    method = GET,
+    path = "/v1/system/hardware/sleds/{sled_id}/drain",
+    tags = ["system"],
+}]
+async fn sled_drain_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<SledPathParam>,
+) -> Result<HttpResponseOk<SledDrainProgress>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let progress = nexus.sled_drain_progress(&opctx, path.sled_id).await?;
+        Ok(HttpResponseOk(progress))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Return a drained sled to service
+///
+/// New instances may be placed on the sled again.  Instances that were
+/// migrated away while it was drained stay where they are.
+#[endpoint {
+    method = POST,
+    path = "/v1/system/hardware/sleds/{sled_id}/undrain",
+    tags = ["system"],
+}]
+async fn sled_undrain(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<SledPathParam>,
+) -> Result<HttpResponseOk<Sled>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let sled = nexus.sled_undrain(&opctx, path.sled_id).await?;
+        Ok(HttpResponseOk(sled.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Physical disks
@@ -3800,7 +5273,7 @@ async fn physical_disk_list(
             &|_, disk: &PhysicalDisk| disk.identity.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List physical disks attached to sleds
@@ -3836,7 +5309,46 @@ async fn sled_physical_disk_list(
             &|_, disk: &PhysicalDisk| disk.identity.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// Region replacements
+
+/// List replacements of Crucible regions
+///
+/// A region is replaced when the physical disk, zpool or sled that it was on
+/// is lost. The volume using the region is degraded until the replacement is
+/// complete.
+#[endpoint {
+    method = GET,
+    path = "/v1/system/hardware/region-replacements",
+    tags = ["system"],
+}]
+async fn region_replacement_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<PaginatedById>,
+) -> Result<HttpResponseOk<ResultsPage<RegionReplacement>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let replacements = nexus
+            .region_replacement_list(
+                &opctx,
+                &data_page_params_for(&rqctx, &query)?,
+            )
+            .await?
+            .into_iter()
+            .map(|r| r.into())
+            .collect();
+        Ok(HttpResponseOk(ScanById::results_page(
+            &query,
+            replacements,
+            &|_, replacement: &RegionReplacement| replacement.id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Metrics
@@ -3894,7 +5406,44 @@ async fn system_metric(
 
         Ok(HttpResponseOk(result))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// Audit log
+
+/// List audit log entries
+///
+/// The audit log records every API request other than `GET` requests, whether
+/// or not it succeeded.
+#[endpoint {
+    method = GET,
+    path = "/v1/system/audit-log",
+    tags = ["system"],
+}]
+async fn audit_log_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<PaginatedBySeq<params::AuditLogSelector>>,
+) -> Result<HttpResponseOk<ResultsPage<AuditLogEntry>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanBySeq::from_query(&query)?;
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let entries = nexus
+            .audit_log_list(&opctx, &scan_params.selector, &pag_params)
+            .await?
+            .into_iter()
+            .map(|e| e.into())
+            .collect();
+        Ok(HttpResponseOk(ScanBySeq::results_page(
+            &query,
+            entries,
+            &|_, entry: &AuditLogEntry| entry.seq,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Updates
@@ -3915,7 +5464,7 @@ async fn system_update_refresh(
         nexus.updates_refresh_metadata(&opctx).await?;
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// View system version and update status
@@ -3952,7 +5501,7 @@ async fn system_version(
             status,
         }))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// View version and update status of component tree
@@ -3984,7 +5533,7 @@ async fn system_component_version_list(
             &|_, u: &views::UpdateableComponent| u.identity.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List all updates
@@ -4015,7 +5564,7 @@ async fn system_update_list(
             &|_, u: &views::SystemUpdate| u.identity.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// View system update
@@ -4037,7 +5586,7 @@ async fn system_update_view(
             nexus.system_update_fetch_by_version(&opctx, &path.version).await?;
         Ok(HttpResponseOk(system_update.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// View system update component tree
@@ -4063,7 +5612,7 @@ async fn system_update_components_list(
             .collect();
         Ok(HttpResponseOk(ResultsPage { items: components, next_page: None }))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Start system update
@@ -4110,7 +5659,7 @@ async fn system_update_start(
             status: views::UpdateStatus::Updating,
         }))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Stop system update
@@ -4147,7 +5696,7 @@ async fn system_update_stop(
 
         Ok(HttpResponseUpdatedNoContent())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// List all update deployments
@@ -4178,7 +5727,7 @@ async fn update_deployments_list(
             &|_, u: &views::UpdateDeployment| u.identity.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a system update deployment
@@ -4201,7 +5750,7 @@ async fn update_deployment_view(
             nexus.update_deployment_fetch_by_id(&opctx, id).await?;
         Ok(HttpResponseOk(deployment.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 // Silo users
 
@@ -4241,7 +5790,77 @@ async fn user_list(
             &|_, user: &User| user.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// List a user's access tokens
+///
+/// Lists the access tokens belonging to a user in the current silo.  This
+/// requires permission to modify the user.
+#[endpoint {
+    method = GET,
+    path = "/v1/users/{user_id}/access-tokens",
+    tags = ["silos"],
+}]
+async fn user_access_token_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<UserParam>,
+    query_params: Query<PaginatedByNameOrId>,
+) -> Result<HttpResponseOk<ResultsPage<AccessToken>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let access_tokens = nexus
+            .access_tokens_list(&opctx, path.user_id, &paginated_by)
+            .await?
+            .into_iter()
+            .map(AccessToken::from)
+            .collect::<Vec<AccessToken>>();
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            access_tokens,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Revoke a user's access token
+///
+/// Deletes an access token belonging to a user in the current silo.  This
+/// requires permission to modify the user.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/users/{user_id}/access-tokens/{access_token}",
+    tags = ["silos"],
+}]
+async fn user_access_token_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::UserAccessTokenPath>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let access_token_selector = params::AccessTokenSelector {
+            silo_user_id: path.user_id,
+            access_token: path.access_token,
+        };
+        let access_token_lookup =
+            nexus.access_token_lookup(&opctx, &access_token_selector)?;
+        nexus
+            .access_token_delete(&opctx, path.user_id, &access_token_lookup)
+            .await?;
+        Ok(HttpResponseDeleted())
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Silo groups
@@ -4274,7 +5893,7 @@ async fn group_list(
             &|_, group: &Group| group.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch group
@@ -4296,7 +5915,7 @@ async fn group_view(
             nexus.silo_group_lookup(&opctx, &path.group).fetch().await?;
         Ok(HttpResponseOk(group.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Built-in (system) users
@@ -4330,7 +5949,7 @@ async fn user_builtin_list(
             &marker_for_name,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a built-in user
@@ -4352,7 +5971,7 @@ async fn user_builtin_view(
             nexus.user_builtin_lookup(&opctx, &user_selector)?.fetch().await?;
         Ok(HttpResponseOk(user.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Built-in roles
@@ -4415,7 +6034,7 @@ async fn role_list(
             |role: &Role, _| RolePage { last_seen: role.name.to_string() },
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch a built-in role
@@ -4437,7 +6056,7 @@ async fn role_view(
         let role = nexus.role_builtin_fetch(&opctx, &role_name).await?;
         Ok(HttpResponseOk(role.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Current user
@@ -4462,7 +6081,7 @@ pub async fn current_user_view(
             silo_name: silo.name().clone(),
         }))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch the silo groups the current user belongs to
@@ -4495,7 +6114,7 @@ pub async fn current_user_groups(
             &|_, group: &views::Group| group.id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 // Per-user SSH public keys
@@ -4536,7 +6155,7 @@ async fn current_user_ssh_key_list(
             &marker_for_name_or_id,
         )?))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Create an SSH public key
@@ -4564,7 +6183,7 @@ async fn current_user_ssh_key_create(
             .await?;
         Ok(HttpResponseCreated(ssh_key.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Fetch an SSH public key
@@ -4598,7 +6217,7 @@ async fn current_user_ssh_key_view(
         assert_eq!(silo_user.id(), actor.actor_id());
         Ok(HttpResponseOk(ssh_key.into()))
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 /// Delete an SSH public key
@@ -4630,7 +6249,119 @@ async fn current_user_ssh_key_delete(
         nexus.ssh_key_delete(&opctx, actor.actor_id(), &ssh_key_lookup).await?;
         Ok(HttpResponseDeleted())
     };
-    apictx.external_latencies.instrument_dropshot_handler(&rqctx, handler).await
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+// Per-user access tokens
+
+/// List access tokens
+///
+/// Lists access tokens for the currently authenticated user.  The tokens
+/// themselves are not included.
+#[endpoint {
+    method = GET,
+    path = "/v1/me/access-tokens",
+    tags = ["session"],
+}]
+async fn current_user_access_token_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<PaginatedByNameOrId>,
+) -> Result<HttpResponseOk<ResultsPage<AccessToken>>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let query = query_params.into_inner();
+        let pag_params = data_page_params_for(&rqctx, &query)?;
+        let scan_params = ScanByNameOrId::from_query(&query)?;
+        let paginated_by = name_or_id_pagination(&pag_params, scan_params)?;
+        let &actor = opctx
+            .authn
+            .actor_required()
+            .internal_context("listing current user's access tokens")?;
+        let access_tokens = nexus
+            .access_tokens_list(&opctx, actor.actor_id(), &paginated_by)
+            .await?
+            .into_iter()
+            .map(AccessToken::from)
+            .collect::<Vec<AccessToken>>();
+        Ok(HttpResponseOk(ScanByNameOrId::results_page(
+            &query,
+            access_tokens,
+            &marker_for_name_or_id,
+        )?))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Create an access token
+///
+/// Create a long-lived access token for the currently authenticated user,
+/// e.g., for use by automation.  The token is only returned in this response
+/// and cannot be retrieved again later.
+#[endpoint {
+    method = POST,
+    path = "/v1/me/access-tokens",
+    tags = ["session"],
+}]
+async fn current_user_access_token_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    new_token: TypedBody<params::AccessTokenCreate>,
+) -> Result<HttpResponseCreated<AccessTokenCreated>, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let &actor = opctx
+            .authn
+            .actor_required()
+            .internal_context("creating access token for current user")?;
+        let access_token = nexus
+            .access_token_create(
+                &opctx,
+                actor.actor_id(),
+                new_token.into_inner(),
+            )
+            .await?;
+        Ok(HttpResponseCreated(access_token.into()))
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
+}
+
+/// Delete an access token
+///
+/// Delete (revoke) an access token belonging to the currently authenticated
+/// user.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/me/access-tokens/{access_token}",
+    tags = ["session"],
+}]
+async fn current_user_access_token_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<params::AccessTokenPath>,
+) -> Result<HttpResponseDeleted, HttpError> {
+    let apictx = rqctx.context();
+    let handler = async {
+        let opctx = crate::context::op_context_for_external_api(&rqctx).await?;
+        let nexus = &apictx.nexus;
+        let path = path_params.into_inner();
+        let &actor = opctx
+            .authn
+            .actor_required()
+            .internal_context("deleting one of current user's access tokens")?;
+        let access_token_selector = params::AccessTokenSelector {
+            silo_user_id: actor.actor_id(),
+            access_token: path.access_token,
+        };
+        let access_token_lookup =
+            nexus.access_token_lookup(&opctx, &access_token_selector)?;
+        nexus
+            .access_token_delete(&opctx, actor.actor_id(), &access_token_lookup)
+            .await?;
+        Ok(HttpResponseDeleted())
+    };
+    apictx.instrument_external_handler(&rqctx, handler).await
 }
 
 #[cfg(test)]
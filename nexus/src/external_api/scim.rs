@@ -0,0 +1,1070 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Entrypoints for SCIM 2.0 provisioning (RFCs 7643 and 7644).
+//!
+//! These are endpoints used by a Silo's external identity provider (the SCIM
+//! client) to create, update, deactivate, and delete the Silo's users and
+//! groups, so that the Silo tracks an enterprise directory.  They are *not*
+//! used by Oxide API clients: the request and response formats are defined by
+//! the RFCs rather than by us, so the endpoints are left out of the OpenAPI
+//! document.
+//!
+//! The SCIM client authenticates with a bearer token created through
+//! `/v1/system/scim/tokens`, and the token determines which Silo the client
+//! manages.  Only Silos whose users are provisioned via the API support SCIM.
+//!
+//! SCIM users map onto Silo users, with `userName` as the user's external id.
+//! SCIM groups map onto Silo groups, with `displayName` as the group's
+//! external id.  Other attributes that clients send (e.g., names and email
+//! addresses) are accepted but not stored.
+//!
+//! Requests other than GETs are recorded in the audit log like other external
+//! API requests, with the client's Silo in place of the actor's.
+//!
+//! TODO: latency instrumentation doesn't work because we use
+//! `Response<Body>`, which we need in order to send SCIM's content type and
+//! error bodies.
+
+use crate::authz;
+use crate::db;
+use crate::db::identity::Asset;
+use crate::external_api::params;
+use crate::ServerContext;
+use chrono::{DateTime, Utc};
+use dropshot::{endpoint, HttpError, Path, Query, RequestContext, UntypedBody};
+use headers::authorization::{Authorization, Bearer};
+use headers::HeaderMapExt;
+use http::{header, Response, StatusCode};
+use hyper::Body;
+use nexus_db_queries::db::datastore::SiloGroupMembershipUpdate;
+use omicron_common::api::external::Error;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+/// Prefix of the bearer tokens that SCIM clients present (see
+/// `views::ScimClientTokenCreated`)
+const SCIM_TOKEN_PREFIX: &str = "oxide-scim-";
+
+const SCHEMA_USER: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const SCHEMA_GROUP: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const SCHEMA_LIST_RESPONSE: &str =
+    "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const SCHEMA_ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+/// Number of results in a list response when the client doesn't say
+const SCIM_DEFAULT_COUNT: usize = 100;
+/// Maximum number of results in a list response
+const SCIM_MAX_COUNT: usize = 1000;
+
+// Resources and messages, as sent to the client
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimMeta {
+    resource_type: &'static str,
+    created: DateTime<Utc>,
+    last_modified: DateTime<Utc>,
+    location: String,
+}
+
+/// Refers to a user (as a group member) or a group (that a user is in)
+#[derive(Deserialize, Serialize)]
+struct ScimReference {
+    value: Uuid,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimUser {
+    schemas: Vec<&'static str>,
+    id: Uuid,
+    user_name: String,
+    active: bool,
+    groups: Vec<ScimReference>,
+    meta: ScimMeta,
+}
+
+impl ScimUser {
+    fn new(user: db::model::SiloUser, group_ids: Vec<Uuid>) -> Self {
+        let id = user.id();
+        Self {
+            schemas: vec![SCHEMA_USER],
+            id,
+            meta: ScimMeta {
+                resource_type: "User",
+                created: user.time_created(),
+                last_modified: user.time_modified(),
+                location: format!("/scim/v2/Users/{}", id),
+            },
+            user_name: user.external_id,
+            active: user.active,
+            groups: group_ids
+                .into_iter()
+                .map(|value| ScimReference { value })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimGroup {
+    schemas: Vec<&'static str>,
+    id: Uuid,
+    display_name: String,
+    members: Vec<ScimReference>,
+    meta: ScimMeta,
+}
+
+impl ScimGroup {
+    fn new(group: db::model::SiloGroup, member_ids: Vec<Uuid>) -> Self {
+        let id = group.id();
+        Self {
+            schemas: vec![SCHEMA_GROUP],
+            id,
+            meta: ScimMeta {
+                resource_type: "Group",
+                created: group.time_created(),
+                last_modified: group.time_modified(),
+                location: format!("/scim/v2/Groups/{}", id),
+            },
+            display_name: group.external_id,
+            members: member_ids
+                .into_iter()
+                .map(|value| ScimReference { value })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimListResponse<T> {
+    schemas: Vec<&'static str>,
+    total_results: usize,
+    start_index: usize,
+    items_per_page: usize,
+    #[serde(rename = "Resources")]
+    resources: Vec<T>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimError {
+    schemas: Vec<&'static str>,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scim_type: Option<String>,
+    detail: String,
+}
+
+// Requests, as sent by the client
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimUserRequest {
+    user_name: String,
+    active: Option<bool>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimGroupRequest {
+    display_name: String,
+    #[serde(default)]
+    members: Vec<ScimReference>,
+}
+
+#[derive(Deserialize)]
+struct ScimPatchRequest {
+    #[serde(rename = "Operations")]
+    operations: Vec<ScimPatchOperation>,
+}
+
+#[derive(Deserialize)]
+struct ScimPatchOperation {
+    op: String,
+    path: Option<String>,
+    value: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ScimPatchOp {
+    Add,
+    Remove,
+    Replace,
+}
+
+impl ScimPatchOperation {
+    /// Operation names are case-insensitive (and some clients capitalize them)
+    fn kind(&self) -> Result<ScimPatchOp, HttpError> {
+        match self.op.to_ascii_lowercase().as_str() {
+            "add" => Ok(ScimPatchOp::Add),
+            "remove" => Ok(ScimPatchOp::Remove),
+            "replace" => Ok(ScimPatchOp::Replace),
+            _ => Err(scim_bad_request(
+                "invalidSyntax",
+                format!("unsupported PATCH operation: {:?}", self.op),
+            )),
+        }
+    }
+
+    fn value_required(self) -> Result<serde_json::Value, HttpError> {
+        self.value.ok_or_else(|| {
+            scim_bad_request(
+                "invalidValue",
+                String::from("PATCH operation is missing a value"),
+            )
+        })
+    }
+}
+
+/// Changes to a user requested by a SCIM client
+#[derive(Default)]
+pub struct ScimUserUpdate {
+    pub user_name: Option<String>,
+    pub active: Option<bool>,
+    pub password: Option<params::Password>,
+}
+
+impl ScimUserUpdate {
+    /// Applies the value of one of the user's attributes from a PUT or PATCH
+    /// request.  Attribute names are case-insensitive.  We don't store most
+    /// of the attributes that clients send, so we ignore those.
+    fn set_attribute(
+        &mut self,
+        name: &str,
+        value: serde_json::Value,
+    ) -> Result<(), HttpError> {
+        if name.eq_ignore_ascii_case("userName") {
+            self.user_name = Some(scim_string(name, value)?);
+        } else if name.eq_ignore_ascii_case("active") {
+            self.active = Some(scim_bool(name, value)?);
+        } else if name.eq_ignore_ascii_case("password") {
+            self.password = Some(scim_password(scim_string(name, value)?)?);
+        }
+        Ok(())
+    }
+
+    fn from_patch(patch: ScimPatchRequest) -> Result<Self, HttpError> {
+        let mut update = ScimUserUpdate::default();
+        for operation in patch.operations {
+            let kind = operation.kind()?;
+            match (kind, operation.path.clone()) {
+                (ScimPatchOp::Remove, Some(path)) => {
+                    if ["userName", "active", "password"]
+                        .iter()
+                        .any(|attr| path.eq_ignore_ascii_case(attr))
+                    {
+                        return Err(scim_bad_request(
+                            "mutability",
+                            format!("attribute {:?} cannot be removed", path),
+                        ));
+                    }
+                }
+                (ScimPatchOp::Remove, None) => {
+                    return Err(scim_bad_request(
+                        "noTarget",
+                        String::from("PATCH \"remove\" requires a path"),
+                    ));
+                }
+                (_, Some(path)) => {
+                    update.set_attribute(&path, operation.value_required()?)?;
+                }
+                (_, None) => {
+                    for (name, value) in
+                        scim_object(operation.value_required()?)?
+                    {
+                        update.set_attribute(&name, value)?;
+                    }
+                }
+            }
+        }
+        Ok(update)
+    }
+}
+
+/// Changes to a group requested by a SCIM client
+#[derive(Default)]
+pub struct ScimGroupUpdate {
+    pub display_name: Option<String>,
+    pub members: Option<SiloGroupMembershipUpdate>,
+}
+
+impl ScimGroupUpdate {
+    fn from_patch(patch: ScimPatchRequest) -> Result<Self, HttpError> {
+        let mut update = ScimGroupUpdate::default();
+        for operation in patch.operations {
+            let kind = operation.kind()?;
+            match operation.path.clone() {
+                None => {
+                    if kind == ScimPatchOp::Remove {
+                        return Err(scim_bad_request(
+                            "noTarget",
+                            String::from("PATCH \"remove\" requires a path"),
+                        ));
+                    }
+                    for (name, value) in
+                        scim_object(operation.value_required()?)?
+                    {
+                        update.set_attribute(kind, &name, Some(value))?;
+                    }
+                }
+                Some(path) => {
+                    if let Some(member_id) = scim_member_filter_path(&path) {
+                        // e.g., `members[value eq "..."]`, which clients use
+                        // to remove individual members
+                        if kind != ScimPatchOp::Remove {
+                            return Err(scim_bad_request(
+                                "invalidPath",
+                                format!("unsupported path: {:?}", path),
+                            ));
+                        }
+                        update.members_remove(vec![member_id]);
+                    } else {
+                        update.set_attribute(kind, &path, operation.value)?;
+                    }
+                }
+            }
+        }
+        Ok(update)
+    }
+
+    /// Applies one PATCH operation on one of the group's attributes.
+    /// Attribute names are case-insensitive, and we ignore those that we
+    /// don't store.
+    fn set_attribute(
+        &mut self,
+        kind: ScimPatchOp,
+        name: &str,
+        value: Option<serde_json::Value>,
+    ) -> Result<(), HttpError> {
+        let value_required = |value: Option<serde_json::Value>| {
+            value.ok_or_else(|| {
+                scim_bad_request(
+                    "invalidValue",
+                    String::from("PATCH operation is missing a value"),
+                )
+            })
+        };
+
+        if name.eq_ignore_ascii_case("displayName") {
+            if kind == ScimPatchOp::Remove {
+                return Err(scim_bad_request(
+                    "mutability",
+                    String::from("attribute \"displayName\" cannot be removed"),
+                ));
+            }
+            self.display_name =
+                Some(scim_string(name, value_required(value)?)?);
+        } else if name.eq_ignore_ascii_case("members") {
+            match (kind, value) {
+                (ScimPatchOp::Add, value) => {
+                    self.members_add(scim_member_ids(value_required(value)?)?)
+                }
+                (ScimPatchOp::Replace, value) => {
+                    self.members = Some(SiloGroupMembershipUpdate::Replace(
+                        scim_member_ids(value_required(value)?)?,
+                    ));
+                }
+                (ScimPatchOp::Remove, Some(value)) => {
+                    self.members_remove(scim_member_ids(value)?)
+                }
+                (ScimPatchOp::Remove, None) => {
+                    self.members =
+                        Some(SiloGroupMembershipUpdate::Replace(vec![]));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // A PATCH request may contain several operations on the group's members.
+    // We fold them into one change so that it can be made atomically.
+
+    fn members_add(&mut self, ids: Vec<Uuid>) {
+        match self.members.get_or_insert_with(|| {
+            SiloGroupMembershipUpdate::Modify { add: vec![], remove: vec![] }
+        }) {
+            SiloGroupMembershipUpdate::Replace(members) => members.extend(ids),
+            SiloGroupMembershipUpdate::Modify { add, remove } => {
+                remove.retain(|id| !ids.contains(id));
+                add.extend(ids);
+            }
+        }
+    }
+
+    fn members_remove(&mut self, ids: Vec<Uuid>) {
+        match self.members.get_or_insert_with(|| {
+            SiloGroupMembershipUpdate::Modify { add: vec![], remove: vec![] }
+        }) {
+            SiloGroupMembershipUpdate::Replace(members) => {
+                members.retain(|id| !ids.contains(id))
+            }
+            SiloGroupMembershipUpdate::Modify { add, remove } => {
+                add.retain(|id| !ids.contains(id));
+                remove.extend(ids);
+            }
+        }
+    }
+}
+
+// Helpers for parsing requests and building responses
+
+fn scim_bad_request(scim_type: &str, message: String) -> HttpError {
+    HttpError::for_bad_request(Some(String::from(scim_type)), message)
+}
+
+fn scim_request_body<T: DeserializeOwned>(
+    body: &UntypedBody,
+) -> Result<T, HttpError> {
+    serde_json::from_slice(body.as_bytes()).map_err(|e| {
+        scim_bad_request(
+            "invalidSyntax",
+            format!("unable to parse request body: {}", e),
+        )
+    })
+}
+
+fn scim_object(
+    value: serde_json::Value,
+) -> Result<serde_json::Map<String, serde_json::Value>, HttpError> {
+    match value {
+        serde_json::Value::Object(map) => Ok(map),
+        _ => Err(scim_bad_request(
+            "invalidValue",
+            String::from("expected an object of attributes"),
+        )),
+    }
+}
+
+fn scim_string(
+    name: &str,
+    value: serde_json::Value,
+) -> Result<String, HttpError> {
+    match value {
+        serde_json::Value::String(s) => Ok(s),
+        _ => Err(scim_bad_request(
+            "invalidValue",
+            format!("attribute {:?} must be a string", name),
+        )),
+    }
+}
+
+/// Parses a boolean, which some clients send as the string "True" or "False"
+fn scim_bool(name: &str, value: serde_json::Value) -> Result<bool, HttpError> {
+    match value {
+        serde_json::Value::Bool(b) => Ok(b),
+        serde_json::Value::String(s) if s.eq_ignore_ascii_case("true") => {
+            Ok(true)
+        }
+        serde_json::Value::String(s) if s.eq_ignore_ascii_case("false") => {
+            Ok(false)
+        }
+        _ => Err(scim_bad_request(
+            "invalidValue",
+            format!("attribute {:?} must be a boolean", name),
+        )),
+    }
+}
+
+fn scim_password(password: String) -> Result<params::Password, HttpError> {
+    params::Password::try_from(password)
+        .map_err(|message| scim_bad_request("invalidValue", message))
+}
+
+fn scim_member_ids(value: serde_json::Value) -> Result<Vec<Uuid>, HttpError> {
+    let members: Vec<ScimReference> =
+        serde_json::from_value(value).map_err(|e| {
+            scim_bad_request("invalidValue", format!("invalid members: {}", e))
+        })?;
+    Ok(members.into_iter().map(|member| member.value).collect())
+}
+
+/// Parses the only kind of filter we support, `<attribute> eq "<value>"`,
+/// which is what clients use to find out whether a user or group exists
+fn scim_eq_filter(filter: &str, attribute: &str) -> Result<String, HttpError> {
+    let invalid = || {
+        scim_bad_request(
+            "invalidFilter",
+            format!("unsupported filter: {:?}", filter),
+        )
+    };
+    let mut parts = filter.trim().splitn(3, ' ');
+    let (Some(name), Some(op), Some(value)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if !name.eq_ignore_ascii_case(attribute) || !op.eq_ignore_ascii_case("eq") {
+        return Err(invalid());
+    }
+    // The value is a JSON string literal.
+    serde_json::from_str(value.trim()).map_err(|_| invalid())
+}
+
+/// Parses a path like `members[value eq "<id>"]`, returning the member id
+fn scim_member_filter_path(path: &str) -> Option<Uuid> {
+    let prefix = "members[";
+    if path.len() <= prefix.len()
+        || !path[..prefix.len()].eq_ignore_ascii_case(prefix)
+    {
+        return None;
+    }
+    let filter = path[prefix.len()..].strip_suffix(']')?;
+    scim_eq_filter(filter, "value").ok()?.parse().ok()
+}
+
+fn scim_response<T: Serialize>(
+    status: StatusCode,
+    body: &T,
+) -> Result<Response<Body>, HttpError> {
+    let body = serde_json::to_string(body)
+        .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, SCIM_CONTENT_TYPE)
+        .body(body.into())?)
+}
+
+fn scim_created_response<T: Serialize>(
+    location: &str,
+    body: &T,
+) -> Result<Response<Body>, HttpError> {
+    let mut response = scim_response(StatusCode::CREATED, body)?;
+    response.headers_mut().insert(
+        header::LOCATION,
+        http::HeaderValue::from_str(location)
+            .map_err(|e| HttpError::for_internal_error(e.to_string()))?,
+    );
+    Ok(response)
+}
+
+fn scim_no_content_response() -> Result<Response<Body>, HttpError> {
+    Ok(Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty())?)
+}
+
+/// Converts an error into the form that SCIM clients expect (RFC 7644 §3.12)
+fn scim_error_response(error: HttpError) -> Result<Response<Body>, HttpError> {
+    // Elsewhere in the API, names that are already in use are reported as a
+    // 400 (Bad Request), but SCIM clients look for a 409 (Conflict).
+    let (status, scim_type) = match error.error_code.as_deref() {
+        Some("ObjectAlreadyExists") => {
+            (StatusCode::CONFLICT, Some(String::from("uniqueness")))
+        }
+        Some(
+            code @ ("invalidFilter" | "invalidPath" | "invalidSyntax"
+            | "invalidValue" | "mutability" | "noTarget"),
+        ) => (error.status_code, Some(String::from(code))),
+        _ => (error.status_code, None),
+    };
+    scim_response(
+        status,
+        &ScimError {
+            schemas: vec![SCHEMA_ERROR],
+            status: status.as_u16().to_string(),
+            scim_type,
+            detail: error.external_message,
+        },
+    )
+}
+
+tokio::task_local! {
+    /// The result of authenticating the SCIM request being handled, if
+    /// [`scim_handle()`] has already done so
+    static SCIM_SILO: RefCell<
+        Option<Result<(authz::Silo, db::model::Silo), HttpError>>,
+    >;
+}
+
+/// Runs the handler for a SCIM request, turning errors into SCIM error
+/// responses and recording requests other than GETs in the audit log
+///
+/// Such requests are authenticated up front so that the client's Silo can be
+/// recorded, and the result is handed to the handler's
+/// [`scim_silo_for_request()`] so that it isn't authenticated again.
+async fn scim_handle<H>(
+    rqctx: &RequestContext<Arc<ServerContext>>,
+    handler: H,
+) -> Result<Response<Body>, HttpError>
+where
+    H: Future<Output = Result<Response<Body>, HttpError>>,
+{
+    let handler = async { handler.await.or_else(scim_error_response) };
+    if rqctx.request.method() == http::Method::GET {
+        return handler.await;
+    }
+
+    let silo = scim_silo_for_request(rqctx).await;
+    let silo_id = silo.as_ref().ok().map(|(authz_silo, _)| authz_silo.id());
+    rqctx
+        .context()
+        .audit_external_handler(
+            rqctx,
+            None,
+            silo_id,
+            SCIM_SILO.scope(RefCell::new(Some(silo)), handler),
+            |response| response.status(),
+        )
+        .await
+}
+
+/// Authenticates a SCIM request by its bearer token, returning the Silo whose
+/// users and groups the client may manage
+async fn scim_silo_for_request(
+    rqctx: &RequestContext<Arc<ServerContext>>,
+) -> Result<(authz::Silo, db::model::Silo), HttpError> {
+    if let Ok(Some(silo)) = SCIM_SILO.try_with(|silo| silo.borrow_mut().take())
+    {
+        return silo;
+    }
+
+    let nexus = &rqctx.context().nexus;
+    let token = rqctx
+        .request
+        .headers()
+        .typed_get::<Authorization<Bearer>>()
+        .and_then(|bearer| {
+            bearer.token().strip_prefix(SCIM_TOKEN_PREFIX).map(String::from)
+        })
+        .ok_or_else(|| Error::Unauthenticated {
+            internal_message: String::from("missing SCIM client token"),
+        })?;
+    Ok(nexus.scim_silo_for_token(nexus.opctx_external_authn(), token).await?)
+}
+
+/// Returns the 1-based index of the first result and the number of results
+/// that a list request asks for
+fn scim_page(query: &ScimListQuery) -> (usize, usize) {
+    let start_index = query.start_index.unwrap_or(1).max(1);
+    let count = query.count.unwrap_or(SCIM_DEFAULT_COUNT).min(SCIM_MAX_COUNT);
+    (start_index, count)
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListQuery {
+    /// Filter expression.  Only `userName eq "..."` (for users) and
+    /// `displayName eq "..."` (for groups) are supported.
+    filter: Option<String>,
+    /// 1-based index of the first result to return
+    start_index: Option<usize>,
+    /// Maximum number of results to return
+    count: Option<usize>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ScimUserPath {
+    user_id: Uuid,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ScimGroupPath {
+    group_id: Uuid,
+}
+
+// Users
+
+/// List users
+#[endpoint {
+    method = GET,
+    path = "/scim/v2/Users",
+    unpublished = true,
+}]
+pub async fn scim_user_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<ScimListQuery>,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let query = query_params.into_inner();
+        let user_name = query
+            .filter
+            .as_deref()
+            .map(|filter| scim_eq_filter(filter, "userName"))
+            .transpose()?;
+
+        let users = nexus
+            .scim_users_list(opctx, &authz_silo, user_name.as_deref())
+            .await?;
+        let total_results = users.len();
+        let (start_index, count) = scim_page(&query);
+        let mut resources = Vec::new();
+        for user in users.into_iter().skip(start_index - 1).take(count) {
+            let group_ids = nexus
+                .scim_user_group_ids(opctx, &authz_silo, user.id())
+                .await?;
+            resources.push(ScimUser::new(user, group_ids));
+        }
+
+        scim_response(
+            StatusCode::OK,
+            &ScimListResponse {
+                schemas: vec![SCHEMA_LIST_RESPONSE],
+                total_results,
+                start_index,
+                items_per_page: resources.len(),
+                resources,
+            },
+        )
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Create a user
+#[endpoint {
+    method = POST,
+    path = "/scim/v2/Users",
+    unpublished = true,
+}]
+pub async fn scim_user_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    body: UntypedBody,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, db_silo) = scim_silo_for_request(&rqctx).await?;
+        let request: ScimUserRequest = scim_request_body(&body)?;
+        let update = ScimUserUpdate {
+            user_name: None,
+            active: request.active,
+            password: request.password.map(scim_password).transpose()?,
+        };
+
+        let user = nexus
+            .scim_user_create(
+                opctx,
+                &authz_silo,
+                &db_silo,
+                request.user_name,
+                update,
+            )
+            .await?;
+        let user = ScimUser::new(user, vec![]);
+        scim_created_response(&user.meta.location, &user)
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Fetch a user
+#[endpoint {
+    method = GET,
+    path = "/scim/v2/Users/{user_id}",
+    unpublished = true,
+}]
+pub async fn scim_user_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<ScimUserPath>,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let path = path_params.into_inner();
+        let (user, group_ids) =
+            nexus.scim_user_fetch(opctx, &authz_silo, path.user_id).await?;
+        scim_response(StatusCode::OK, &ScimUser::new(user, group_ids))
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Replace a user
+///
+/// A user that's left out `active` is active.
+#[endpoint {
+    method = PUT,
+    path = "/scim/v2/Users/{user_id}",
+    unpublished = true,
+}]
+pub async fn scim_user_replace(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<ScimUserPath>,
+    body: UntypedBody,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, db_silo) = scim_silo_for_request(&rqctx).await?;
+        let path = path_params.into_inner();
+        let request: ScimUserRequest = scim_request_body(&body)?;
+        let update = ScimUserUpdate {
+            user_name: Some(request.user_name),
+            active: Some(request.active.unwrap_or(true)),
+            password: request.password.map(scim_password).transpose()?,
+        };
+
+        let user = nexus
+            .scim_user_update(
+                opctx,
+                &authz_silo,
+                &db_silo,
+                path.user_id,
+                update,
+            )
+            .await?;
+        let group_ids =
+            nexus.scim_user_group_ids(opctx, &authz_silo, user.id()).await?;
+        scim_response(StatusCode::OK, &ScimUser::new(user, group_ids))
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Update a user
+///
+/// This is how clients usually deactivate users, by replacing `active` with
+/// `false`.  Deactivated users are logged out and cannot log in again until
+/// they're reactivated.
+#[endpoint {
+    method = PATCH,
+    path = "/scim/v2/Users/{user_id}",
+    unpublished = true,
+}]
+pub async fn scim_user_patch(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<ScimUserPath>,
+    body: UntypedBody,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, db_silo) = scim_silo_for_request(&rqctx).await?;
+        let path = path_params.into_inner();
+        let update = ScimUserUpdate::from_patch(scim_request_body(&body)?)?;
+
+        let user = nexus
+            .scim_user_update(
+                opctx,
+                &authz_silo,
+                &db_silo,
+                path.user_id,
+                update,
+            )
+            .await?;
+        let group_ids =
+            nexus.scim_user_group_ids(opctx, &authz_silo, user.id()).await?;
+        scim_response(StatusCode::OK, &ScimUser::new(user, group_ids))
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Delete a user
+#[endpoint {
+    method = DELETE,
+    path = "/scim/v2/Users/{user_id}",
+    unpublished = true,
+}]
+pub async fn scim_user_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<ScimUserPath>,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let path = path_params.into_inner();
+        nexus.scim_user_delete(opctx, &authz_silo, path.user_id).await?;
+        scim_no_content_response()
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+// Groups
+
+/// List groups
+#[endpoint {
+    method = GET,
+    path = "/scim/v2/Groups",
+    unpublished = true,
+}]
+pub async fn scim_group_list(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    query_params: Query<ScimListQuery>,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let query = query_params.into_inner();
+        let display_name = query
+            .filter
+            .as_deref()
+            .map(|filter| scim_eq_filter(filter, "displayName"))
+            .transpose()?;
+
+        let groups = nexus
+            .scim_groups_list(opctx, &authz_silo, display_name.as_deref())
+            .await?;
+        let total_results = groups.len();
+        let (start_index, count) = scim_page(&query);
+        let mut resources = Vec::new();
+        for group in groups.into_iter().skip(start_index - 1).take(count) {
+            let (_, member_ids) =
+                nexus.scim_group_fetch(opctx, &authz_silo, group.id()).await?;
+            resources.push(ScimGroup::new(group, member_ids));
+        }
+
+        scim_response(
+            StatusCode::OK,
+            &ScimListResponse {
+                schemas: vec![SCHEMA_LIST_RESPONSE],
+                total_results,
+                start_index,
+                items_per_page: resources.len(),
+                resources,
+            },
+        )
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Create a group
+#[endpoint {
+    method = POST,
+    path = "/scim/v2/Groups",
+    unpublished = true,
+}]
+pub async fn scim_group_create(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    body: UntypedBody,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let request: ScimGroupRequest = scim_request_body(&body)?;
+        let member_ids =
+            request.members.into_iter().map(|member| member.value).collect();
+
+        let (group, member_ids) = nexus
+            .scim_group_create(
+                opctx,
+                &authz_silo,
+                request.display_name,
+                member_ids,
+            )
+            .await?;
+        let group = ScimGroup::new(group, member_ids);
+        scim_created_response(&group.meta.location, &group)
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Fetch a group
+#[endpoint {
+    method = GET,
+    path = "/scim/v2/Groups/{group_id}",
+    unpublished = true,
+}]
+pub async fn scim_group_view(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<ScimGroupPath>,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let path = path_params.into_inner();
+        let (group, member_ids) =
+            nexus.scim_group_fetch(opctx, &authz_silo, path.group_id).await?;
+        scim_response(StatusCode::OK, &ScimGroup::new(group, member_ids))
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Replace a group, including all of its members
+#[endpoint {
+    method = PUT,
+    path = "/scim/v2/Groups/{group_id}",
+    unpublished = true,
+}]
+pub async fn scim_group_replace(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<ScimGroupPath>,
+    body: UntypedBody,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let path = path_params.into_inner();
+        let request: ScimGroupRequest = scim_request_body(&body)?;
+        let update = ScimGroupUpdate {
+            display_name: Some(request.display_name),
+            members: Some(SiloGroupMembershipUpdate::Replace(
+                request
+                    .members
+                    .into_iter()
+                    .map(|member| member.value)
+                    .collect(),
+            )),
+        };
+
+        let (group, member_ids) = nexus
+            .scim_group_update(opctx, &authz_silo, path.group_id, update)
+            .await?;
+        scim_response(StatusCode::OK, &ScimGroup::new(group, member_ids))
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Update a group
+///
+/// This is how clients usually add members to a group and remove them.
+#[endpoint {
+    method = PATCH,
+    path = "/scim/v2/Groups/{group_id}",
+    unpublished = true,
+}]
+pub async fn scim_group_patch(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<ScimGroupPath>,
+    body: UntypedBody,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let path = path_params.into_inner();
+        let update = ScimGroupUpdate::from_patch(scim_request_body(&body)?)?;
+
+        let (group, member_ids) = nexus
+            .scim_group_update(opctx, &authz_silo, path.group_id, update)
+            .await?;
+        scim_response(StatusCode::OK, &ScimGroup::new(group, member_ids))
+    };
+    scim_handle(&rqctx, handler).await
+}
+
+/// Delete a group
+#[endpoint {
+    method = DELETE,
+    path = "/scim/v2/Groups/{group_id}",
+    unpublished = true,
+}]
+pub async fn scim_group_delete(
+    rqctx: RequestContext<Arc<ServerContext>>,
+    path_params: Path<ScimGroupPath>,
+) -> Result<Response<Body>, HttpError> {
+    let handler = async {
+        let nexus = &rqctx.context().nexus;
+        let opctx = nexus.opctx_external_authn();
+        let (authz_silo, _) = scim_silo_for_request(&rqctx).await?;
+        let path = path_params.into_inner();
+        nexus.scim_group_delete(opctx, &authz_silo, path.group_id).await?;
+        scim_no_content_response()
+    };
+    scim_handle(&rqctx, handler).await
+}
@@ -576,6 +576,7 @@ pub async fn start_dns_server(
         store,
         &dns_server::dns_server::Config {
             bind_address: "[::1]:0".parse().unwrap(),
+            limits: Default::default(),
         },
         &dropshot::ConfigDropshot {
             bind_address: "[::1]:0".parse().unwrap(),
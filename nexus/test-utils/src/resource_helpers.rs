@@ -273,6 +273,7 @@ pub async fn create_project(
                 name: project_name.parse().unwrap(),
                 description: "a pier".to_string(),
             },
+            labels: Default::default(),
         },
     )
     .await
@@ -292,6 +293,7 @@ pub async fn create_disk(
                 name: disk_name.parse().unwrap(),
                 description: String::from("sells rainsticks"),
             },
+            labels: Default::default(),
             disk_source: params::DiskSource::Blank {
                 block_size: params::BlockSize::try_from(512).unwrap(),
             },
@@ -346,6 +348,7 @@ pub async fn create_instance_with(
                 name: instance_name.parse().unwrap(),
                 description: format!("instance {:?}", instance_name),
             },
+            labels: Default::default(),
             ncpus: InstanceCpuCount(4),
             memory: ByteCount::from_gibibytes_u32(1),
             hostname: String::from("the_host"),
@@ -355,6 +358,8 @@ pub async fn create_instance_with(
             network_interfaces: nics.clone(),
             external_ips: vec![],
             disks,
+            affinity_groups: Vec::new(),
+            auto_restart_policy: Default::default(),
             start: true,
         },
     )
@@ -374,6 +379,7 @@ pub async fn create_vpc(
                 name: vpc_name.parse().unwrap(),
                 description: "vpc description".to_string(),
             },
+            labels: Default::default(),
             ipv6_prefix: None,
             dns_name: "abc".parse().unwrap(),
         },
@@ -400,6 +406,7 @@ pub async fn create_vpc_with_error(
                 name: vpc_name.parse().unwrap(),
                 description: String::from("vpc description"),
             },
+            labels: Default::default(),
             ipv6_prefix: None,
             dns_name: "abc".parse().unwrap(),
         }))
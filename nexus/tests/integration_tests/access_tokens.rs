@@ -0,0 +1,223 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integration tests for user-created access tokens
+
+use chrono::Utc;
+use dropshot::test_util::ClientTestContext;
+use dropshot::HttpErrorResponseBody;
+use http::header::AUTHORIZATION;
+use http::method::Method;
+use http::StatusCode;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_nexus::authn::USER_TEST_PRIVILEGED;
+use omicron_nexus::authn::USER_TEST_UNPRIVILEGED;
+use omicron_nexus::db::identity::Asset;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::views::AccessToken;
+use omicron_nexus::external_api::views::AccessTokenCreated;
+use omicron_nexus::external_api::views::CurrentUser;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const ACCESS_TOKENS_URL: &str = "/v1/me/access-tokens";
+
+fn token_create_params(name: &str) -> params::AccessTokenCreate {
+    params::AccessTokenCreate {
+        identity: IdentityMetadataCreateParams {
+            name: name.parse().unwrap(),
+            description: format!("token {:?}", name),
+        },
+        time_expires: None,
+    }
+}
+
+async fn create_token(
+    client: &ClientTestContext,
+    authn_mode: AuthnMode,
+    params: &params::AccessTokenCreate,
+) -> AccessTokenCreated {
+    NexusRequest::objects_post(client, ACCESS_TOKENS_URL, params)
+        .authn_as(authn_mode)
+        .execute()
+        .await
+        .expect("failed to create access token")
+        .parsed_body()
+        .unwrap()
+}
+
+async fn list_tokens(
+    client: &ClientTestContext,
+    url: &str,
+) -> Vec<AccessToken> {
+    NexusRequest::iter_collection_authn(client, url, "", None)
+        .await
+        .expect("failed to list access tokens")
+        .all_items
+}
+
+/// Makes a request to `/v1/me` using `access_token` to authenticate.
+async fn current_user_with_token(
+    client: &ClientTestContext,
+    access_token: &str,
+    expected_status: StatusCode,
+) -> Option<CurrentUser> {
+    let response = RequestBuilder::new(client, Method::GET, "/v1/me")
+        .header(AUTHORIZATION, format!("Bearer {}", access_token))
+        .expect_status(Some(expected_status))
+        .execute()
+        .await
+        .expect("unexpected response to request with access token");
+    (expected_status == StatusCode::OK).then(|| response.parsed_body().unwrap())
+}
+
+#[nexus_test]
+async fn test_access_tokens(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    // A new token is returned only once, and can be used to authenticate as
+    // the user who created it.
+    let created = create_token(
+        client,
+        AuthnMode::PrivilegedUser,
+        &token_create_params("ci"),
+    )
+    .await;
+    assert_eq!(created.identity.name, "ci");
+    assert_eq!(created.silo_user_id, USER_TEST_PRIVILEGED.id());
+    assert_eq!(created.time_expires, None);
+    assert!(created.access_token.starts_with("oxide-token-"));
+    let me =
+        current_user_with_token(client, &created.access_token, StatusCode::OK)
+            .await
+            .unwrap();
+    assert_eq!(me.user.id, USER_TEST_PRIVILEGED.id());
+
+    // Listing tokens doesn't reveal the token itself.
+    let tokens = list_tokens(client, ACCESS_TOKENS_URL).await;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].identity.id, created.identity.id);
+    let body = serde_json::to_string(&tokens).unwrap();
+    assert!(!body.contains(&created.access_token["oxide-token-".len()..]));
+
+    // Token names are unique per user.
+    NexusRequest::expect_failure_with_body(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::POST,
+        ACCESS_TOKENS_URL,
+        &token_create_params("ci"),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // Tokens can't be created already expired.
+    let error: HttpErrorResponseBody = NexusRequest::expect_failure_with_body(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::POST,
+        ACCESS_TOKENS_URL,
+        &params::AccessTokenCreate {
+            time_expires: Some(Utc::now()),
+            ..token_create_params("expired")
+        },
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "unsupported value for \"time_expires\": expiration time must be in \
+        the future"
+    );
+
+    // Deleting the token revokes it.
+    NexusRequest::object_delete(
+        client,
+        &format!("{}/{}", ACCESS_TOKENS_URL, created.identity.name),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    current_user_with_token(
+        client,
+        &created.access_token,
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+    assert!(list_tokens(client, ACCESS_TOKENS_URL).await.is_empty());
+}
+
+#[nexus_test]
+async fn test_access_tokens_revoked_by_admin(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+
+    // An unprivileged user creates a token for themselves.
+    let created = create_token(
+        client,
+        AuthnMode::UnprivilegedUser,
+        &params::AccessTokenCreate {
+            time_expires: Some(Utc::now() + chrono::Duration::days(30)),
+            ..token_create_params("automation")
+        },
+    )
+    .await;
+    assert!(created.time_expires.is_some());
+    let me =
+        current_user_with_token(client, &created.access_token, StatusCode::OK)
+            .await
+            .unwrap();
+    assert_eq!(me.user.id, USER_TEST_UNPRIVILEGED.id());
+
+    // That user can't see other users' tokens.
+    let other_user_tokens_url =
+        format!("/v1/users/{}/access-tokens", USER_TEST_PRIVILEGED.id());
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::FORBIDDEN,
+        Method::GET,
+        &other_user_tokens_url,
+    )
+    .authn_as(AuthnMode::UnprivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // An administrator can see the user's token and revoke it.
+    let user_tokens_url =
+        format!("/v1/users/{}/access-tokens", USER_TEST_UNPRIVILEGED.id());
+    let tokens = list_tokens(client, &user_tokens_url).await;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].identity.id, created.identity.id);
+    assert_eq!(tokens[0].time_expires, created.time_expires);
+
+    NexusRequest::object_delete(
+        client,
+        &format!("{}/{}", user_tokens_url, created.identity.id),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    current_user_with_token(
+        client,
+        &created.access_token,
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+    assert!(list_tokens(client, &user_tokens_url).await.is_empty());
+}
@@ -0,0 +1,415 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integration tests for affinity and anti-affinity groups
+
+use camino::Utf8Path;
+use dropshot::test_util::ClientTestContext;
+use dropshot::HttpErrorResponseBody;
+use http::method::Method;
+use http::StatusCode;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::resource_helpers::create_project;
+use nexus_test_utils::resource_helpers::object_create;
+use nexus_test_utils::resource_helpers::object_delete;
+use nexus_test_utils::resource_helpers::object_put;
+use nexus_test_utils::resource_helpers::objects_list_page_authz;
+use nexus_test_utils::resource_helpers::populate_ip_pool;
+use nexus_test_utils::start_sled_agent;
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::ByteCount;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::IdentityMetadataUpdateParams;
+use omicron_common::api::external::Instance;
+use omicron_common::api::external::InstanceCpuCount;
+use omicron_common::api::external::NameOrId;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::shared::AffinityGroupKind;
+use omicron_nexus::external_api::shared::AffinityPolicy;
+use omicron_nexus::external_api::views::AffinityGroup;
+use omicron_sled_agent::sim;
+use uuid::Uuid;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const PROJECT_NAME: &str = "pachyderm";
+
+fn get_affinity_groups_url() -> String {
+    format!("/v1/affinity-groups?project={}", PROJECT_NAME)
+}
+
+fn get_affinity_group_url(name: &str) -> String {
+    format!("/v1/affinity-groups/{}?project={}", name, PROJECT_NAME)
+}
+
+async fn create_affinity_group(
+    client: &ClientTestContext,
+    name: &str,
+    kind: AffinityGroupKind,
+    policy: AffinityPolicy,
+) -> AffinityGroup {
+    object_create(
+        client,
+        &get_affinity_groups_url(),
+        &params::AffinityGroupCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: format!("affinity group {:?}", name),
+            },
+            kind,
+            policy,
+        },
+    )
+    .await
+}
+
+/// Returns parameters for creating an instance in the given affinity groups
+///
+/// The instance is started, since a stopped instance doesn't count toward
+/// where its groups' other members may be placed.
+fn instance_params(
+    name: &str,
+    affinity_groups: Vec<NameOrId>,
+) -> params::InstanceCreate {
+    params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: name.parse().unwrap(),
+            description: format!("instance {:?}", name),
+        },
+        labels: Default::default(),
+        ncpus: InstanceCpuCount(1),
+        memory: ByteCount::from_gibibytes_u32(1),
+        hostname: String::from("the_host"),
+        user_data: vec![],
+        network_interfaces: params::InstanceNetworkInterfaceAttachment::None,
+        external_ips: vec![],
+        disks: vec![],
+        affinity_groups,
+        auto_restart_policy: Default::default(),
+        start: true,
+    }
+}
+
+#[nexus_test]
+async fn test_affinity_group_crud(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    create_project(client, PROJECT_NAME).await;
+
+    // There are no groups to start with.
+    let groups = objects_list_page_authz::<AffinityGroup>(
+        client,
+        &get_affinity_groups_url(),
+    )
+    .await
+    .items;
+    assert!(groups.is_empty());
+
+    // Create a group and fetch it back.
+    let group = create_affinity_group(
+        client,
+        "db-replicas",
+        AffinityGroupKind::AntiAffinity,
+        AffinityPolicy::Fail,
+    )
+    .await;
+    assert_eq!(group.identity.name, "db-replicas");
+    assert_eq!(group.kind, AffinityGroupKind::AntiAffinity);
+    assert_eq!(group.policy, AffinityPolicy::Fail);
+
+    let fetched: AffinityGroup = NexusRequest::object_get(
+        client,
+        &get_affinity_group_url("db-replicas"),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(fetched.identity.id, group.identity.id);
+
+    // It can also be fetched by ID without a project.
+    let fetched: AffinityGroup = NexusRequest::object_get(
+        client,
+        &format!("/v1/affinity-groups/{}", group.identity.id),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(fetched.identity.name, "db-replicas");
+
+    // Names must be unique within the project.
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &get_affinity_groups_url())
+            .body(Some(&params::AffinityGroupCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: "db-replicas".parse().unwrap(),
+                    description: String::from("again"),
+                },
+                kind: AffinityGroupKind::Affinity,
+                policy: AffinityPolicy::Allow,
+            }))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(error.message, "already exists: affinity-group \"db-replicas\"");
+
+    // Rename the group.
+    let updated: AffinityGroup = object_put(
+        client,
+        &get_affinity_group_url("db-replicas"),
+        &params::AffinityGroupUpdate {
+            identity: IdentityMetadataUpdateParams {
+                name: Some("db-servers".parse().unwrap()),
+                description: Some(String::from("the database servers")),
+            },
+        },
+    )
+    .await;
+    assert_eq!(updated.identity.id, group.identity.id);
+    assert_eq!(updated.identity.name, "db-servers");
+    assert_eq!(updated.identity.description, "the database servers");
+    assert_eq!(updated.kind, AffinityGroupKind::AntiAffinity);
+
+    let groups = objects_list_page_authz::<AffinityGroup>(
+        client,
+        &get_affinity_groups_url(),
+    )
+    .await
+    .items;
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].identity.name, "db-servers");
+
+    // Delete the group.
+    object_delete(client, &get_affinity_group_url("db-servers")).await;
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::NOT_FOUND,
+        Method::GET,
+        &get_affinity_group_url("db-servers"),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+}
+
+#[nexus_test]
+async fn test_affinity_group_placement(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    populate_ip_pool(client, "default", None).await;
+    create_project(client, PROJECT_NAME).await;
+    let instances_url = format!("/v1/instances?project={}", PROJECT_NAME);
+
+    // The test environment has a single sled, so a second member of a strict
+    // anti-affinity group has nowhere to go.
+    create_affinity_group(
+        client,
+        "strict",
+        AffinityGroupKind::AntiAffinity,
+        AffinityPolicy::Fail,
+    )
+    .await;
+    let strict = NameOrId::Name("strict".parse().unwrap());
+    let _: Instance = object_create(
+        client,
+        &instances_url,
+        &instance_params("strict-1", vec![strict.clone()]),
+    )
+    .await;
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &instances_url)
+            .body(Some(&instance_params("strict-2", vec![strict.clone()])))
+            .expect_status(Some(StatusCode::SERVICE_UNAVAILABLE)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(error.message, "No sleds can fit the requested instance");
+
+    // Instances outside the group are unaffected.
+    let _: Instance = object_create(
+        client,
+        &instances_url,
+        &instance_params("loner", vec![]),
+    )
+    .await;
+
+    // A permissive anti-affinity group only expresses a preference, so both
+    // members can be placed on the same sled.
+    let relaxed_group = create_affinity_group(
+        client,
+        "relaxed",
+        AffinityGroupKind::AntiAffinity,
+        AffinityPolicy::Allow,
+    )
+    .await;
+    let relaxed = NameOrId::Name("relaxed".parse().unwrap());
+    for name in ["relaxed-1", "relaxed-2"] {
+        let _: Instance = object_create(
+            client,
+            &instances_url,
+            &instance_params(name, vec![relaxed.clone()]),
+        )
+        .await;
+    }
+
+    // Once the strict group is deleted, its former rule no longer applies.
+    object_delete(client, &get_affinity_group_url("strict")).await;
+    let _: Instance = object_create(
+        client,
+        &instances_url,
+        &instance_params("strict-2", vec![]),
+    )
+    .await;
+
+    // Groups that don't exist are reported as such.
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &instances_url)
+            .body(Some(&instance_params("orphan", vec![strict])))
+            .expect_status(Some(StatusCode::NOT_FOUND)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // Groups must be in the same project as the instance.
+    let other_project = "other-project";
+    create_project(client, other_project).await;
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/instances?project={}", other_project),
+        )
+        .body(Some(&instance_params("interloper", vec![relaxed])))
+        .expect_status(Some(StatusCode::NOT_FOUND)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "not found: affinity-group with name \"relaxed\""
+    );
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/instances?project={}", other_project),
+        )
+        .body(Some(&instance_params(
+            "interloper",
+            vec![NameOrId::Id(relaxed_group.identity.id)],
+        )))
+        .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        format!(
+            "affinity group {} is not in the instance's project",
+            relaxed_group.identity.id
+        )
+    );
+}
+
+#[nexus_test]
+async fn test_affinity_group_placement_intersection(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    populate_ip_pool(client, "default", None).await;
+    create_project(client, PROJECT_NAME).await;
+    let instances_url = format!("/v1/instances?project={}", PROJECT_NAME);
+
+    // Start a second sled so that instances can be kept apart.
+    let sa_id = Uuid::new_v4();
+    let log = cptestctx.logctx.log.new(o!( "sled_id" => sa_id.to_string() ));
+    let addr = cptestctx.server.get_http_server_internal_address().await;
+    let update_directory = Utf8Path::new("/should/not/be/used");
+    let sa = start_sled_agent(
+        log,
+        addr,
+        sa_id,
+        &update_directory,
+        sim::SimMode::Explicit,
+    )
+    .await
+    .unwrap();
+
+    // Put the first members of two strict affinity groups on different sleds
+    // by also putting them in a strict anti-affinity group.
+    for (name, kind) in [
+        ("left", AffinityGroupKind::Affinity),
+        ("right", AffinityGroupKind::Affinity),
+        ("apart", AffinityGroupKind::AntiAffinity),
+    ] {
+        create_affinity_group(client, name, kind, AffinityPolicy::Fail).await;
+    }
+    let left = NameOrId::Name("left".parse().unwrap());
+    let right = NameOrId::Name("right".parse().unwrap());
+    let apart = NameOrId::Name("apart".parse().unwrap());
+    for (name, group) in [("left-1", &left), ("right-1", &right)] {
+        let _: Instance = object_create(
+            client,
+            &instances_url,
+            &instance_params(name, vec![group.clone(), apart.clone()]),
+        )
+        .await;
+    }
+
+    // Either group on its own still admits new members.
+    for (name, group) in [("left-2", &left), ("right-2", &right)] {
+        let _: Instance = object_create(
+            client,
+            &instances_url,
+            &instance_params(name, vec![group.clone()]),
+        )
+        .await;
+    }
+
+    // An instance in both groups would have to be on both sleds at once.
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &instances_url)
+            .body(Some(&instance_params("both", vec![left, right])))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "the instance's affinity groups with the \"fail\" policy have no \
+        sled in common"
+    );
+
+    sa.http_server.close().await.unwrap();
+}
@@ -0,0 +1,196 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integration tests for the audit log
+
+use chrono::SecondsFormat;
+use dropshot::test_util::ClientTestContext;
+use http::method::Method;
+use http::StatusCode;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::resource_helpers::create_project;
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_nexus::authn::USER_TEST_PRIVILEGED;
+use omicron_nexus::authn::USER_TEST_UNPRIVILEGED;
+use omicron_nexus::db::fixed_data::FLEET_ID;
+use omicron_nexus::db::identity::Asset;
+use omicron_nexus::external_api::device_auth::DeviceAccessTokenRequest;
+use omicron_nexus::external_api::device_auth::DeviceAuthRequest;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::views::AuditLogEntry;
+use omicron_nexus::external_api::views::DeviceAuthResponse;
+use uuid::Uuid;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const AUDIT_LOG_URL: &str = "/v1/system/audit-log";
+
+async fn audit_log_list(
+    client: &ClientTestContext,
+    params: &str,
+) -> Vec<AuditLogEntry> {
+    NexusRequest::iter_collection_authn(client, AUDIT_LOG_URL, params, Some(3))
+        .await
+        .expect("failed to list audit log")
+        .all_items
+}
+
+#[nexus_test]
+async fn test_audit_log(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    // Make a successful request, and one that fails, as different users.
+    let project = create_project(client, "audited").await;
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::NOT_FOUND,
+        Method::DELETE,
+        &format!("/v1/projects/{}", project.identity.id),
+    )
+    .authn_as(AuthnMode::UnprivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // Reads aren't recorded.
+    NexusRequest::object_get(client, "/v1/projects/audited")
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap();
+
+    let entries = audit_log_list(client, "").await;
+    assert_eq!(entries.len(), 2, "unexpected entries: {:?}", entries);
+    assert!(entries.iter().all(|e| e.http_method != "GET"));
+
+    let created = &entries[0];
+    assert_eq!(created.http_method, "POST");
+    assert_eq!(created.request_uri, "/v1/projects");
+    assert_eq!(created.result_code, Some(StatusCode::CREATED.as_u16()));
+    assert_eq!(created.actor_id, Some(USER_TEST_PRIVILEGED.id()));
+    assert_eq!(created.actor_silo_id, Some(USER_TEST_PRIVILEGED.silo_id));
+    assert!(created.resource_ids.is_empty());
+    assert!(Some(created.time_started) <= created.time_completed);
+    assert_eq!(created.chain_id, USER_TEST_PRIVILEGED.silo_id);
+
+    let failed = &entries[1];
+    assert_eq!(failed.http_method, "DELETE");
+    assert_eq!(failed.result_code, Some(StatusCode::NOT_FOUND.as_u16()));
+    assert_eq!(failed.actor_id, Some(USER_TEST_UNPRIVILEGED.id()));
+    assert_eq!(failed.resource_ids, vec![project.identity.id]);
+
+    // Both users are in the same Silo, so their entries are chained
+    // together.
+    assert!(failed.seq > created.seq);
+    assert_eq!(failed.chain_id, created.chain_id);
+    assert_eq!(failed.chain_seq, created.chain_seq.map(|seq| seq + 1));
+    assert_eq!(failed.prev_hash, created.hash);
+    assert_ne!(failed.request_id, created.request_id);
+
+    // Newest entries can be listed first.
+    let newest_first = audit_log_list(client, "sort_by=seq_descending").await;
+    assert_eq!(newest_first.len(), 2);
+    assert_eq!(&newest_first[0], failed);
+    assert_eq!(&newest_first[1], created);
+
+    // Filter by actor.
+    let by_actor = audit_log_list(
+        client,
+        &format!("actor_id={}", USER_TEST_UNPRIVILEGED.id()),
+    )
+    .await;
+    assert_eq!(by_actor, vec![failed.clone()]);
+
+    // Filter by time.
+    let time = failed.time_started.to_rfc3339_opts(SecondsFormat::Micros, true);
+    let by_time = audit_log_list(client, &format!("start_time={}", time)).await;
+    assert_eq!(by_time, vec![failed.clone()]);
+    let by_time = audit_log_list(client, &format!("end_time={}", time)).await;
+    assert_eq!(by_time, vec![created.clone()]);
+
+    // Requests from unauthenticated clients are recorded too, in the fleet's
+    // chain, and listing the audit log itself requires privileges on the
+    // fleet.
+    NexusRequest::expect_failure_with_body(
+        client,
+        StatusCode::UNAUTHORIZED,
+        Method::POST,
+        "/v1/projects",
+        &params::ProjectCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "unaudited".parse().unwrap(),
+                description: String::new(),
+            },
+            labels: Default::default(),
+        },
+    )
+    .execute()
+    .await
+    .unwrap();
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::FORBIDDEN,
+        Method::GET,
+        AUDIT_LOG_URL,
+    )
+    .authn_as(AuthnMode::UnprivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    let entries = audit_log_list(client, "").await;
+    assert_eq!(entries.len(), 3);
+    let unauthenticated = &entries[2];
+    assert_eq!(
+        unauthenticated.result_code,
+        Some(StatusCode::UNAUTHORIZED.as_u16())
+    );
+    assert_eq!(unauthenticated.actor_id, None);
+    assert_eq!(unauthenticated.actor_silo_id, None);
+    assert_eq!(unauthenticated.chain_id, *FLEET_ID);
+    assert!(unauthenticated.hash.is_some());
+}
+
+#[nexus_test]
+async fn test_audit_log_device_auth(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    // The device authorization endpoints build their own responses, whose
+    // status codes are recorded as they are.
+    let client_id = Uuid::new_v4();
+    let auth_response: DeviceAuthResponse =
+        RequestBuilder::new(client, Method::POST, "/device/auth")
+            .allow_non_dropshot_errors()
+            .body_urlencoded(Some(&DeviceAuthRequest { client_id }))
+            .expect_status(Some(StatusCode::OK))
+            .execute()
+            .await
+            .expect("failed to start client authentication flow")
+            .parsed_body()
+            .expect("client authentication response");
+    RequestBuilder::new(client, Method::POST, "/device/token")
+        .allow_non_dropshot_errors()
+        .body_urlencoded(Some(&DeviceAccessTokenRequest {
+            grant_type: "urn:ietf:params:oauth:grant-type:device_code"
+                .to_string(),
+            device_code: auth_response.device_code,
+            client_id,
+        }))
+        .expect_status(Some(StatusCode::BAD_REQUEST))
+        .execute()
+        .await
+        .expect("failed to get OAuth error on unconfirmed token request");
+
+    let entries = audit_log_list(client, "").await;
+    assert_eq!(entries.len(), 2, "unexpected entries: {:?}", entries);
+    assert_eq!(entries[0].request_uri, "/device/auth");
+    assert_eq!(entries[0].result_code, Some(StatusCode::OK.as_u16()));
+    assert_eq!(entries[0].actor_id, None);
+    assert_eq!(entries[1].request_uri, "/device/token");
+    assert_eq!(entries[1].result_code, Some(StatusCode::BAD_REQUEST.as_u16()));
+}
@@ -166,6 +166,7 @@ async fn test_projects_basic(cptestctx: &ControlPlaneTestContext) {
                             "<auto-generated by test suite>",
                         ),
                     },
+                    labels: Default::default(),
                 },
             )
             .authn_as(AuthnMode::PrivilegedUser)
@@ -262,6 +263,7 @@ async fn test_projects_basic(cptestctx: &ControlPlaneTestContext) {
                     name: None,
                     description: None,
                 },
+                labels: None,
             }))
             .expect_status(Some(StatusCode::NOT_FOUND)),
     )
@@ -303,6 +305,7 @@ async fn test_projects_basic(cptestctx: &ControlPlaneTestContext) {
             name: None,
             description: Some("Li'l lightnin'".to_string()),
         },
+        labels: None,
     };
     let project = NexusRequest::object_put(
         client,
@@ -333,6 +336,7 @@ async fn test_projects_basic(cptestctx: &ControlPlaneTestContext) {
             name: Some("lil-lightnin".parse().unwrap()),
             description: Some("little lightning".to_string()),
         },
+        labels: None,
     };
     let project = NexusRequest::object_put(
         client,
@@ -366,6 +370,7 @@ async fn test_projects_basic(cptestctx: &ControlPlaneTestContext) {
             name: "simproject1".parse().unwrap(),
             description: "a duplicate of simproject1".to_string(),
         },
+        labels: Default::default(),
     };
     let error = NexusRequest::new(
         RequestBuilder::new(client, Method::POST, &projects_url)
@@ -414,6 +419,7 @@ async fn test_projects_basic(cptestctx: &ControlPlaneTestContext) {
             name: "honor-roller".parse().unwrap(),
             description: "a soapbox racer".to_string(),
         },
+        labels: Default::default(),
     };
     let project: Project =
         NexusRequest::objects_post(client, projects_url, &project_create)
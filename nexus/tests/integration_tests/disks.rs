@@ -319,6 +319,7 @@ async fn test_disk_create_disk_that_already_exists_fails(
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -600,6 +601,7 @@ async fn test_disk_region_creation_failure(
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -680,6 +682,7 @@ async fn test_disk_invalid_block_size_rejected(
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize(1024),
         },
@@ -723,6 +726,7 @@ async fn test_disk_reject_total_size_not_divisible_by_block_size(
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -757,6 +761,7 @@ async fn test_disk_reject_total_size_less_than_one_gibibyte(
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -801,6 +806,7 @@ async fn test_disk_reject_total_size_not_divisible_by_min_disk_size(
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -855,6 +861,7 @@ async fn test_disk_backed_by_multiple_region_sets(
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -891,6 +898,7 @@ async fn test_disk_too_big(cptestctx: &ControlPlaneTestContext) {
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -978,6 +986,7 @@ async fn test_disk_virtual_provisioning_collection(
             name: "disk-one".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -1037,6 +1046,7 @@ async fn test_disk_virtual_provisioning_collection(
             name: "disk-two".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -1152,6 +1162,7 @@ async fn test_disk_size_accounting(cptestctx: &ControlPlaneTestContext) {
             name: "disk-one".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -1190,6 +1201,7 @@ async fn test_disk_size_accounting(cptestctx: &ControlPlaneTestContext) {
             name: "disk-two".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -1250,6 +1262,7 @@ async fn test_disk_size_accounting(cptestctx: &ControlPlaneTestContext) {
             name: "disk-three".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -1308,6 +1321,7 @@ async fn test_multiple_disks_multiple_zpools(
             name: "disk-one".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -1331,6 +1345,7 @@ async fn test_multiple_disks_multiple_zpools(
             name: "disk-two".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -1515,6 +1530,7 @@ async fn test_disk_create_for_importing(cptestctx: &ControlPlaneTestContext) {
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::ImportingBlocks {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -1544,6 +1560,156 @@ async fn test_disk_create_for_importing(cptestctx: &ControlPlaneTestContext) {
     disks_eq(&disks[0], &disk);
 }
 
+#[nexus_test]
+async fn test_disk_resize(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    let datastore = nexus.datastore();
+    DiskTest::new(&cptestctx).await;
+    let project_id = create_org_and_project(client).await;
+    let disks_url = get_disks_url();
+
+    let disk = create_disk(&client, PROJECT_NAME, DISK_NAME).await;
+    let disk_url = get_disk_url(DISK_NAME);
+    let resize_url =
+        format!("/v1/disks/{}/resize?project={}", DISK_NAME, PROJECT_NAME);
+
+    // Shrinking a disk, or "resizing" it to its current size, is rejected.
+    for size in [disk.size, ByteCount::from(params::MIN_DISK_SIZE_BYTES / 2)] {
+        let error = NexusRequest::new(
+            RequestBuilder::new(client, Method::POST, &resize_url)
+                .body(Some(&params::DiskResize { size }))
+                .expect_status(Some(StatusCode::BAD_REQUEST)),
+        )
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap()
+        .parsed_body::<dropshot::HttpErrorResponseBody>()
+        .unwrap();
+        assert_eq!(
+            error.message,
+            format!(
+                "unsupported value for \"size\": new size must be larger \
+                than the current size {}",
+                disk.size,
+            )
+        );
+    }
+
+    // So is a size that isn't a multiple of MIN_DISK_SIZE_BYTES.
+    let size = ByteCount::try_from(
+        disk.size.to_bytes() + params::MIN_DISK_SIZE_BYTES as u64 / 2,
+    )
+    .unwrap();
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &resize_url)
+            .body(Some(&params::DiskResize { size }))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // Growing the disk succeeds, and the new size is reflected both in the
+    // disk itself and in the space accounted to the project.
+    let new_size = ByteCount::try_from(disk.size.to_bytes() * 2).unwrap();
+    let resized_disk: Disk = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &resize_url)
+            .body(Some(&params::DiskResize { size: new_size }))
+            .expect_status(Some(StatusCode::OK)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(resized_disk.size, new_size);
+    assert_eq!(resized_disk.state, DiskState::Detached);
+
+    let fetched_disk = disk_get(&client, &disk_url).await;
+    disks_eq(&resized_disk, &fetched_disk);
+    let disks = disks_list(&client, &disks_url).await;
+    assert_eq!(disks.len(), 1);
+    disks_eq(&disks[0], &fetched_disk);
+
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+    for id in [project_id, *SILO_ID, *FLEET_ID] {
+        let virtual_provisioning_collection = datastore
+            .virtual_provisioning_collection_get(&opctx, id)
+            .await
+            .unwrap();
+        assert_eq!(
+            virtual_provisioning_collection
+                .virtual_disk_bytes_provisioned
+                .to_bytes(),
+            new_size.to_bytes(),
+        );
+    }
+}
+
+#[nexus_test]
+async fn test_disk_resize_attached(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    DiskTest::new(&cptestctx).await;
+    create_org_and_project(client).await;
+
+    let disk = create_disk(&client, PROJECT_NAME, DISK_NAME).await;
+    let resize_url =
+        format!("/v1/disks/{}/resize?project={}", DISK_NAME, PROJECT_NAME);
+    let new_size = ByteCount::try_from(disk.size.to_bytes() * 2).unwrap();
+
+    // A disk attached to a running instance can't be resized.
+    let instance = create_instance_with(
+        &client,
+        PROJECT_NAME,
+        INSTANCE_NAME,
+        &params::InstanceNetworkInterfaceAttachment::Default,
+        vec![params::InstanceDiskAttachment::Attach(
+            params::InstanceDiskAttach { name: DISK_NAME.parse().unwrap() },
+        )],
+    )
+    .await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let error = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &resize_url)
+            .body(Some(&params::DiskResize { size: new_size }))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body::<dropshot::HttpErrorResponseBody>()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "cannot resize a disk attached to an instance in state running; \
+        stop the instance first"
+    );
+
+    // Once the instance is stopped, the disk can be resized.
+    set_instance_state(&client, INSTANCE_NAME, "stop").await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let resized_disk: Disk = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &resize_url)
+            .body(Some(&params::DiskResize { size: new_size }))
+            .expect_status(Some(StatusCode::OK)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(resized_disk.size, new_size);
+    assert_eq!(resized_disk.state, DiskState::Attached(instance.identity.id));
+}
+
 async fn disk_get(client: &ClientTestContext, disk_url: &str) -> Disk {
     NexusRequest::object_get(client, disk_url)
         .authn_as(AuthnMode::PrivilegedUser)
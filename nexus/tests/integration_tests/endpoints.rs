@@ -21,6 +21,7 @@ use omicron_common::api::external::IdentityMetadataUpdateParams;
 use omicron_common::api::external::InstanceCpuCount;
 use omicron_common::api::external::Ipv4Net;
 use omicron_common::api::external::Name;
+use omicron_common::api::external::NameOrId;
 use omicron_common::api::external::RouteDestination;
 use omicron_common::api::external::RouteTarget;
 use omicron_common::api::external::SemverVersion;
@@ -28,6 +29,7 @@ use omicron_common::api::external::VpcFirewallRuleUpdateParams;
 use omicron_nexus::authn;
 use omicron_nexus::authz;
 use omicron_nexus::db::fixed_data::silo::DEFAULT_SILO;
+use omicron_nexus::db::identity::Asset;
 use omicron_nexus::db::identity::Resource;
 use omicron_nexus::external_api::params;
 use omicron_nexus::external_api::shared;
@@ -46,6 +48,14 @@ lazy_static! {
         format!("/v1/system/hardware/disks");
     pub static ref HARDWARE_SLED_DISK_URL: String =
         format!("/v1/system/hardware/sleds/{}/disks", SLED_AGENT_UUID);
+    pub static ref HARDWARE_REGION_REPLACEMENTS_URL: String =
+        format!("/v1/system/hardware/region-replacements");
+    pub static ref HARDWARE_SLED_EXPUNGE_URL: String =
+        format!("/v1/system/hardware/sleds/{}/expunge", SLED_AGENT_UUID);
+    pub static ref HARDWARE_SLED_DRAIN_URL: String =
+        format!("/v1/system/hardware/sleds/{}/drain", SLED_AGENT_UUID);
+    pub static ref HARDWARE_SLED_UNDRAIN_URL: String =
+        format!("/v1/system/hardware/sleds/{}/undrain", SLED_AGENT_UUID);
 
     // Global policy
     pub static ref SYSTEM_POLICY_URL: &'static str = "/v1/system/policy";
@@ -56,6 +66,8 @@ lazy_static! {
         format!("/v1/system/silos/{}", *DEMO_SILO_NAME);
     pub static ref DEMO_SILO_POLICY_URL: String =
         format!("/v1/system/silos/{}/policy", *DEMO_SILO_NAME);
+    pub static ref DEMO_SILO_QUOTAS_URL: String =
+        format!("/v1/system/silos/{}/quotas", *DEMO_SILO_NAME);
     pub static ref DEMO_SILO_CREATE: params::SiloCreate =
         params::SiloCreate {
             identity: IdentityMetadataCreateParams {
@@ -66,6 +78,12 @@ lazy_static! {
             identity_mode: shared::SiloIdentityMode::SamlJit,
             admin_group_name: None,
         };
+    pub static ref DEMO_QUOTAS_UPDATE: params::QuotasUpdate =
+        params::QuotasUpdate {
+            cpus: Some(16),
+            memory: Some(ByteCount::from_gibibytes_u32(64)),
+            storage: None,
+        };
     // Use the default Silo for testing the local IdP
     pub static ref DEMO_SILO_USERS_CREATE_URL: String = format!(
         "/v1/system/identity-providers/local/users?silo={}",
@@ -96,12 +114,17 @@ lazy_static! {
         format!("project={}", *DEMO_PROJECT_NAME);
     pub static ref DEMO_PROJECT_POLICY_URL: String =
         format!("/v1/projects/{}/policy", *DEMO_PROJECT_NAME);
+    pub static ref DEMO_PROJECT_QUOTAS_URL: String =
+        format!("/v1/projects/{}/quotas", *DEMO_PROJECT_NAME);
     pub static ref DEMO_PROJECT_URL_DISKS: String =
         format!("/v1/disks?project={}",  *DEMO_PROJECT_NAME);
     pub static ref DEMO_PROJECT_URL_IMAGES: String =
         format!("/v1/images?project={}", *DEMO_PROJECT_NAME);
     pub static ref DEMO_PROJECT_URL_INSTANCES: String = format!("/v1/instances?project={}", *DEMO_PROJECT_NAME);
     pub static ref DEMO_PROJECT_URL_SNAPSHOTS: String = format!("/v1/snapshots?project={}", *DEMO_PROJECT_NAME);
+    pub static ref DEMO_PROJECT_URL_SNAPSHOT_SCHEDULES: String = format!("/v1/snapshot-schedules?project={}", *DEMO_PROJECT_NAME);
+    pub static ref DEMO_PROJECT_URL_FLOATING_IPS: String = format!("/v1/floating-ips?project={}", *DEMO_PROJECT_NAME);
+    pub static ref DEMO_PROJECT_URL_AFFINITY_GROUPS: String = format!("/v1/affinity-groups?project={}", *DEMO_PROJECT_NAME);
     pub static ref DEMO_PROJECT_URL_VPCS: String = format!("/v1/vpcs?project={}", *DEMO_PROJECT_NAME);
     pub static ref DEMO_PROJECT_CREATE: params::ProjectCreate =
         params::ProjectCreate {
@@ -109,6 +132,7 @@ lazy_static! {
                 name: DEMO_PROJECT_NAME.clone(),
                 description: String::from(""),
             },
+            labels: Default::default(),
         };
 
     // VPC used for testing
@@ -130,6 +154,7 @@ lazy_static! {
                 name: DEMO_VPC_NAME.clone(),
                 description: String::from(""),
             },
+            labels: Default::default(),
             ipv6_prefix: None,
             dns_name: DEMO_VPC_NAME.clone(),
         };
@@ -149,6 +174,40 @@ lazy_static! {
             },
             ipv4_block: Ipv4Net("10.1.2.3/8".parse().unwrap()),
             ipv6_block: None,
+            custom_router: None,
+        };
+
+    // VPC peered with the demo VPC.  Its default subnet overlaps with that of
+    // the demo VPC, so it must be deleted before the VPCs can be peered.
+    pub static ref DEMO_PEER_VPC_NAME: Name = "demo-peer-vpc".parse().unwrap();
+    pub static ref DEMO_PEER_VPC_CREATE: params::VpcCreate =
+        params::VpcCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_PEER_VPC_NAME.clone(),
+                description: String::from(""),
+            },
+            labels: Default::default(),
+            ipv6_prefix: None,
+            dns_name: DEMO_PEER_VPC_NAME.clone(),
+        };
+    pub static ref DEMO_PEER_VPC_DEFAULT_SUBNET_URL: String =
+        format!("/v1/vpc-subnets/default?project={}&vpc={}", *DEMO_PROJECT_NAME, *DEMO_PEER_VPC_NAME);
+
+    // VPC Peering used for testing
+    pub static ref DEMO_VPC_PEERING_NAME: Name =
+        "demo-vpc-peering".parse().unwrap();
+    pub static ref DEMO_VPC_URL_PEERINGS: String =
+        format!("/v1/vpc-peerings?{}", *DEMO_VPC_SELECTOR);
+    pub static ref DEMO_VPC_PEERING_URL: String =
+        format!("/v1/vpc-peerings/{}?{}", *DEMO_VPC_PEERING_NAME, *DEMO_VPC_SELECTOR);
+    pub static ref DEMO_VPC_PEERING_CREATE: params::VpcPeeringCreate =
+        params::VpcPeeringCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_VPC_PEERING_NAME.clone(),
+                description: String::from(""),
+            },
+            peer_vpc: NameOrId::Name(DEMO_PEER_VPC_NAME.clone()),
+            peer_project: None,
         };
 
     // VPC Router used for testing
@@ -194,6 +253,7 @@ lazy_static! {
                 name: DEMO_DISK_NAME.clone(),
                 description: "".parse().unwrap(),
             },
+            labels: Default::default(),
             disk_source: params::DiskSource::Blank {
                 block_size: params::BlockSize::try_from(4096).unwrap(),
             },
@@ -202,6 +262,14 @@ lazy_static! {
                 DiskTest::DEFAULT_ZPOOL_SIZE_GIB / 5
             ),
         };
+    pub static ref DEMO_DISK_RESIZE_URL: String =
+        format!("/v1/disks/{}/resize?{}", *DEMO_DISK_NAME, *DEMO_PROJECT_SELECTOR);
+    pub static ref DEMO_DISK_RESIZE: params::DiskResize =
+        params::DiskResize {
+            size: ByteCount::from_gibibytes_u32(
+                2 * (DiskTest::DEFAULT_ZPOOL_SIZE_GIB / 5)
+            ),
+        };
     pub static ref DEMO_DISK_METRICS_URL: String =
         format!(
             "/v1/disks/{}/metrics/activated?start_time={:?}&end_time={:?}&{}",
@@ -221,6 +289,7 @@ lazy_static! {
                 name: DEMO_IMPORT_DISK_NAME.clone(),
                 description: "".parse().unwrap(),
             },
+            labels: Default::default(),
             disk_source: params::DiskSource::ImportingBlocks {
                 block_size: params::BlockSize::try_from(4096).unwrap(),
             },
@@ -240,6 +309,14 @@ lazy_static! {
         format!("/v1/disks/{}/bulk-write-stop?{}", *DEMO_IMPORT_DISK_NAME, *DEMO_PROJECT_SELECTOR);
     pub static ref DEMO_IMPORT_DISK_FINALIZE_URL: String =
         format!("/v1/disks/{}/finalize?{}", *DEMO_IMPORT_DISK_NAME, *DEMO_PROJECT_SELECTOR);
+    pub static ref DEMO_DISK_EXPORT_START_URL: String =
+        format!("/v1/disks/{}/export-start?{}", *DEMO_DISK_NAME, *DEMO_PROJECT_SELECTOR);
+    pub static ref DEMO_DISK_EXPORT_URL: String =
+        format!("/v1/disks/{}/export?{}", *DEMO_DISK_NAME, *DEMO_PROJECT_SELECTOR);
+    pub static ref DEMO_DISK_EXPORT_DIGEST_URL: String =
+        format!("/v1/disks/{}/export-digest?{}", *DEMO_DISK_NAME, *DEMO_PROJECT_SELECTOR);
+    pub static ref DEMO_DISK_EXPORT_STOP_URL: String =
+        format!("/v1/disks/{}/export-stop?{}", *DEMO_DISK_NAME, *DEMO_PROJECT_SELECTOR);
 }
 
 // Separate lazy_static! blocks to avoid hitting some recursion limit when
@@ -260,6 +337,8 @@ lazy_static! {
         format!("/v1/instances/{}/migrate?{}", *DEMO_INSTANCE_NAME, *DEMO_PROJECT_SELECTOR);
     pub static ref DEMO_INSTANCE_SERIAL_URL: String =
         format!("/v1/instances/{}/serial-console?{}", *DEMO_INSTANCE_NAME, *DEMO_PROJECT_SELECTOR);
+    pub static ref DEMO_INSTANCE_SERIAL_HISTORY_URL: String =
+        format!("/v1/instances/{}/serial-console/history?{}", *DEMO_INSTANCE_NAME, *DEMO_PROJECT_SELECTOR);
     pub static ref DEMO_INSTANCE_SERIAL_STREAM_URL: String =
         format!("/v1/instances/{}/serial-console/stream?{}", *DEMO_INSTANCE_NAME, *DEMO_PROJECT_SELECTOR);
 
@@ -280,6 +359,7 @@ lazy_static! {
                 name: DEMO_INSTANCE_NAME.clone(),
                 description: String::from(""),
             },
+            labels: Default::default(),
             ncpus: InstanceCpuCount(1),
             memory: ByteCount::from_gibibytes_u32(16),
             hostname: String::from("demo-instance"),
@@ -290,8 +370,17 @@ lazy_static! {
                 params::ExternalIpCreate::Ephemeral { pool_name: Some(DEMO_IP_POOL_NAME.clone()) }
             ],
             disks: vec![],
+            affinity_groups: Vec::new(),
+            auto_restart_policy: Default::default(),
             start: true,
         };
+    pub static ref DEMO_INSTANCE_UPDATE: params::InstanceUpdate =
+        params::InstanceUpdate {
+            ncpus: Some(InstanceCpuCount(2)),
+            memory: None,
+            hostname: None,
+            labels: None,
+        };
 
     // The instance needs a network interface, too.
     pub static ref DEMO_INSTANCE_NIC_NAME: Name =
@@ -354,6 +443,7 @@ lazy_static! {
                 name: DEMO_IMAGE_NAME.clone(),
                 description: String::from(""),
             },
+            labels: Default::default(),
             source: params::ImageSource::Url { url: HTTP_SERVER.url("/image.raw").to_string() },
             block_size: params::BlockSize::try_from(4096).unwrap(),
             os: "fake-os".to_string(),
@@ -396,15 +486,77 @@ lazy_static! {
     pub static ref DEMO_SNAPSHOT_NAME: Name = "demo-snapshot".parse().unwrap();
     pub static ref DEMO_SNAPSHOT_URL: String =
         format!("/v1/snapshots/{}?project={}", *DEMO_SNAPSHOT_NAME, *DEMO_PROJECT_NAME);
+    pub static ref DEMO_SNAPSHOT_EXPORT_URL: String =
+        format!("/v1/snapshots/{}/export?project={}", *DEMO_SNAPSHOT_NAME, *DEMO_PROJECT_NAME);
+    pub static ref DEMO_SNAPSHOT_EXPORT_DIGEST_URL: String =
+        format!("/v1/snapshots/{}/export-digest?project={}", *DEMO_SNAPSHOT_NAME, *DEMO_PROJECT_NAME);
     pub static ref DEMO_SNAPSHOT_CREATE: params::SnapshotCreate =
         params::SnapshotCreate {
             identity: IdentityMetadataCreateParams {
                 name: DEMO_SNAPSHOT_NAME.clone(),
                 description: String::from(""),
             },
+            labels: Default::default(),
             disk: DEMO_DISK_NAME.clone(),
         };
 
+    // Snapshot schedules
+    pub static ref DEMO_SNAPSHOT_SCHEDULE_NAME: Name = "demo-snapshot-schedule".parse().unwrap();
+    pub static ref DEMO_SNAPSHOT_SCHEDULE_URL: String =
+        format!("/v1/snapshot-schedules/{}?project={}", *DEMO_SNAPSHOT_SCHEDULE_NAME, *DEMO_PROJECT_NAME);
+    pub static ref DEMO_SNAPSHOT_SCHEDULE_CREATE: params::SnapshotScheduleCreate =
+        params::SnapshotScheduleCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_SNAPSHOT_SCHEDULE_NAME.clone(),
+                description: String::from(""),
+            },
+            disk: DEMO_DISK_NAME.clone().into(),
+            interval_minutes: 60,
+            retention_count: 4,
+        };
+
+    // Floating IPs
+    pub static ref DEMO_FLOATING_IP_NAME: Name = "demo-floating-ip".parse().unwrap();
+    pub static ref DEMO_FLOATING_IP_URL: String =
+        format!("/v1/floating-ips/{}?project={}", *DEMO_FLOATING_IP_NAME, *DEMO_PROJECT_NAME);
+    pub static ref DEMO_FLOATING_IP_ATTACH_URL: String =
+        format!("/v1/floating-ips/{}/attach?project={}", *DEMO_FLOATING_IP_NAME, *DEMO_PROJECT_NAME);
+    pub static ref DEMO_FLOATING_IP_DETACH_URL: String =
+        format!("/v1/floating-ips/{}/detach?project={}", *DEMO_FLOATING_IP_NAME, *DEMO_PROJECT_NAME);
+    pub static ref DEMO_FLOATING_IP_CREATE: params::FloatingIpCreate =
+        params::FloatingIpCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_FLOATING_IP_NAME.clone(),
+                description: String::from("a floating IP"),
+            },
+            pool: None,
+        };
+    pub static ref DEMO_FLOATING_IP_ATTACH: params::FloatingIpAttach =
+        params::FloatingIpAttach {
+            instance: DEMO_INSTANCE_NAME.clone().into(),
+        };
+
+    // Affinity groups
+    pub static ref DEMO_AFFINITY_GROUP_NAME: Name = "demo-affinity-group".parse().unwrap();
+    pub static ref DEMO_AFFINITY_GROUP_URL: String =
+        format!("/v1/affinity-groups/{}?project={}", *DEMO_AFFINITY_GROUP_NAME, *DEMO_PROJECT_NAME);
+    pub static ref DEMO_AFFINITY_GROUP_CREATE: params::AffinityGroupCreate =
+        params::AffinityGroupCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_AFFINITY_GROUP_NAME.clone(),
+                description: String::from(""),
+            },
+            kind: shared::AffinityGroupKind::AntiAffinity,
+            policy: shared::AffinityPolicy::Fail,
+        };
+    pub static ref DEMO_AFFINITY_GROUP_UPDATE: params::AffinityGroupUpdate =
+        params::AffinityGroupUpdate {
+            identity: IdentityMetadataUpdateParams {
+                name: None,
+                description: Some(String::from("an updated description")),
+            },
+        };
+
     // SSH keys
     pub static ref DEMO_SSHKEYS_URL: &'static str = "/v1/me/ssh-keys";
     pub static ref DEMO_SSHKEY_NAME: Name = "aaaaa-ssh-key".parse().unwrap();
@@ -420,6 +572,29 @@ lazy_static! {
     pub static ref DEMO_SPECIFIC_SSHKEY_URL: String =
         format!("{}/{}", *DEMO_SSHKEYS_URL, *DEMO_SSHKEY_NAME);
 
+    // Access tokens
+    pub static ref DEMO_ACCESS_TOKENS_URL: &'static str = "/v1/me/access-tokens";
+    pub static ref DEMO_ACCESS_TOKEN_NAME: Name =
+        "demo-access-token".parse().unwrap();
+    pub static ref DEMO_ACCESS_TOKEN_CREATE: params::AccessTokenCreate =
+        params::AccessTokenCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_ACCESS_TOKEN_NAME.clone(),
+                description: "a demo token".to_string(),
+            },
+            time_expires: None,
+        };
+    pub static ref DEMO_SPECIFIC_ACCESS_TOKEN_URL: String =
+        format!("{}/{}", *DEMO_ACCESS_TOKENS_URL, *DEMO_ACCESS_TOKEN_NAME);
+    pub static ref DEMO_USER_ACCESS_TOKENS_URL: String = format!(
+        "/v1/users/{}/access-tokens",
+        authn::USER_TEST_PRIVILEGED.id()
+    );
+    pub static ref DEMO_USER_SPECIFIC_ACCESS_TOKEN_URL: String = format!(
+        "{}/{}",
+        *DEMO_USER_ACCESS_TOKENS_URL, *DEMO_ACCESS_TOKEN_NAME
+    );
+
     // System update
 
     pub static ref DEMO_SYSTEM_UPDATE_PARAMS: params::SystemUpdatePath = params::SystemUpdatePath {
@@ -455,6 +630,70 @@ lazy_static! {
             group_attribute_name: None,
         };
 
+    // OIDC identity providers can only be created in a Silo using OIDC
+    // authentication, which the demo Silo does not.
+    pub static ref DEMO_OIDC_SILO_NAME: Name = "demo-oidc-silo".parse().unwrap();
+    pub static ref DEMO_OIDC_SILO_CREATE: params::SiloCreate =
+        params::SiloCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_OIDC_SILO_NAME.clone(),
+                description: String::from(""),
+            },
+            discoverable: true,
+            identity_mode: shared::SiloIdentityMode::OidcJit,
+            admin_group_name: None,
+        };
+    pub static ref OIDC_IDENTITY_PROVIDERS_URL: String = format!("/v1/system/identity-providers/oidc?silo={}", *DEMO_OIDC_SILO_NAME);
+
+    pub static ref DEMO_OIDC_IDENTITY_PROVIDER_NAME: Name = "demo-oidc-provider".parse().unwrap();
+    pub static ref SPECIFIC_OIDC_IDENTITY_PROVIDER_URL: String = format!("/v1/system/identity-providers/oidc/{}?silo={}", *DEMO_OIDC_IDENTITY_PROVIDER_NAME, *DEMO_OIDC_SILO_NAME);
+
+    pub static ref OIDC_IDENTITY_PROVIDER: params::OidcIdentityProviderCreate =
+        params::OidcIdentityProviderCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_OIDC_IDENTITY_PROVIDER_NAME.clone(),
+                description: "a demo provider".to_string(),
+            },
+
+            issuer: "https://idp.test".to_string(),
+            client_id: "client_id".to_string(),
+            client_secret: None,
+            authorization_endpoint: "https://idp.test/authorize".to_string(),
+            token_endpoint: "https://idp.test/token".to_string(),
+            jwks_uri: "https://idp.test/jwks".to_string(),
+            redirect_uri: "http://nexus/login/demo-oidc-silo/oidc/demo-oidc-provider/callback".to_string(),
+            scopes: vec!["email".to_string()],
+
+            group_claim_name: None,
+        };
+
+    // SCIM client tokens can only be created in a Silo whose users are
+    // provisioned via the API, which the demo Silo's are not.
+    pub static ref DEMO_SCIM_SILO_NAME: Name = "demo-scim-silo".parse().unwrap();
+    pub static ref DEMO_SCIM_SILO_CREATE: params::SiloCreate =
+        params::SiloCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_SCIM_SILO_NAME.clone(),
+                description: String::from(""),
+            },
+            discoverable: true,
+            identity_mode: shared::SiloIdentityMode::LocalOnly,
+            admin_group_name: None,
+        };
+    pub static ref SCIM_CLIENT_TOKENS_URL: String = format!("/v1/system/scim/tokens?silo={}", *DEMO_SCIM_SILO_NAME);
+
+    pub static ref DEMO_SCIM_CLIENT_TOKEN_NAME: Name = "demo-scim-client-token".parse().unwrap();
+    pub static ref SPECIFIC_SCIM_CLIENT_TOKEN_URL: String = format!("/v1/system/scim/tokens/{}?silo={}", *DEMO_SCIM_CLIENT_TOKEN_NAME, *DEMO_SCIM_SILO_NAME);
+
+    pub static ref SCIM_CLIENT_TOKEN_CREATE: params::ScimClientTokenCreate =
+        params::ScimClientTokenCreate {
+            identity: IdentityMetadataCreateParams {
+                name: DEMO_SCIM_CLIENT_TOKEN_NAME.clone(),
+                description: "a demo token".to_string(),
+            },
+            time_expires: None,
+        };
+
     pub static ref DEMO_SYSTEM_METRICS_URL: String =
         format!(
             "/v1/system/metrics/virtual_disk_space_provisioned?start_time={:?}&end_time={:?}&id={}",
@@ -770,6 +1009,17 @@ lazy_static! {
                 ),
             ],
         },
+        VerifyEndpoint {
+            url: &DEMO_SILO_QUOTAS_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Put(
+                    serde_json::to_value(&*DEMO_QUOTAS_UPDATE).unwrap()
+                ),
+            ],
+        },
         VerifyEndpoint {
             url: "/v1/policy",
             visibility: Visibility::Public,
@@ -899,6 +1149,7 @@ lazy_static! {
                             name: None,
                             description: Some("different".to_string())
                         },
+                        labels: None,
                     }).unwrap()
                 ),
             ],
@@ -920,6 +1171,18 @@ lazy_static! {
             ],
         },
 
+        VerifyEndpoint {
+            url: &DEMO_PROJECT_QUOTAS_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Put(
+                    serde_json::to_value(&*DEMO_QUOTAS_UPDATE).unwrap()
+                ),
+            ],
+        },
+
         /* VPCs */
         VerifyEndpoint {
             url: &DEMO_PROJECT_URL_VPCS,
@@ -945,6 +1208,7 @@ lazy_static! {
                             name: None,
                             description: Some("different".to_string())
                         },
+                        labels: None,
                         dns_name: None,
                     }).unwrap()
                 ),
@@ -992,6 +1256,7 @@ lazy_static! {
                             name: None,
                             description: Some("different".to_string())
                         },
+                        custom_router: None,
                     }).unwrap()
                 ),
                 AllowedMethod::Delete,
@@ -1007,6 +1272,29 @@ lazy_static! {
             ],
         },
 
+        /* VPC Peerings */
+        VerifyEndpoint {
+            url: &DEMO_VPC_URL_PEERINGS,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Post(
+                    serde_json::to_value(&*DEMO_VPC_PEERING_CREATE).unwrap()
+                ),
+            ],
+        },
+
+        VerifyEndpoint {
+            url: &DEMO_VPC_PEERING_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Delete,
+            ],
+        },
+
         /* VPC Routers */
 
         VerifyEndpoint {
@@ -1099,6 +1387,17 @@ lazy_static! {
             ],
         },
 
+        VerifyEndpoint {
+            url: &DEMO_DISK_RESIZE_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Post(
+                    serde_json::to_value(&*DEMO_DISK_RESIZE).unwrap()
+                ),
+            ],
+        },
+
         VerifyEndpoint {
             url: &DEMO_DISK_METRICS_URL,
             visibility: Visibility::Protected,
@@ -1197,6 +1496,45 @@ lazy_static! {
             ],
         },
 
+        VerifyEndpoint {
+            url: &DEMO_DISK_EXPORT_START_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Post(serde_json::value::Value::Null),
+            ],
+        },
+
+        // The privileged GET would stream the disk's contents rather than
+        // return JSON, and only works once an export has been started.
+        VerifyEndpoint {
+            url: &DEMO_DISK_EXPORT_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::GetNonexistent,
+            ],
+        },
+
+        // The privileged GET only works once an export has been started.
+        VerifyEndpoint {
+            url: &DEMO_DISK_EXPORT_DIGEST_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::GetNonexistent,
+            ],
+        },
+
+        VerifyEndpoint {
+            url: &DEMO_DISK_EXPORT_STOP_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Post(serde_json::value::Value::Null),
+            ],
+        },
+
         /* Project images */
 
         VerifyEndpoint {
@@ -1254,6 +1592,122 @@ lazy_static! {
             ]
         },
 
+        // The privileged GET would stream the snapshot's contents rather than
+        // return JSON.
+        VerifyEndpoint {
+            url: &DEMO_SNAPSHOT_EXPORT_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::GetNonexistent,
+            ]
+        },
+
+        // The privileged GET would read through the whole snapshot.
+        VerifyEndpoint {
+            url: &DEMO_SNAPSHOT_EXPORT_DIGEST_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::GetNonexistent,
+            ]
+        },
+
+        /* Snapshot schedules */
+
+        VerifyEndpoint {
+            url: &DEMO_PROJECT_URL_SNAPSHOT_SCHEDULES,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Post(
+                    serde_json::to_value(DEMO_SNAPSHOT_SCHEDULE_CREATE.clone()).unwrap(),
+                )
+            ]
+        },
+
+        VerifyEndpoint {
+            url: &DEMO_SNAPSHOT_SCHEDULE_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Delete,
+            ]
+        },
+
+        /* Floating IPs */
+
+        VerifyEndpoint {
+            url: &DEMO_PROJECT_URL_FLOATING_IPS,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Post(
+                    serde_json::to_value(DEMO_FLOATING_IP_CREATE.clone()).unwrap(),
+                )
+            ]
+        },
+
+        VerifyEndpoint {
+            url: &DEMO_FLOATING_IP_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Delete,
+            ]
+        },
+
+        VerifyEndpoint {
+            url: &DEMO_FLOATING_IP_ATTACH_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Post(
+                    serde_json::to_value(DEMO_FLOATING_IP_ATTACH.clone()).unwrap(),
+                )
+            ]
+        },
+
+        VerifyEndpoint {
+            url: &DEMO_FLOATING_IP_DETACH_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Post(serde_json::value::Value::Null),
+            ]
+        },
+
+        /* Affinity groups */
+
+        VerifyEndpoint {
+            url: &DEMO_PROJECT_URL_AFFINITY_GROUPS,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Post(
+                    serde_json::to_value(DEMO_AFFINITY_GROUP_CREATE.clone()).unwrap(),
+                )
+            ]
+        },
+
+        VerifyEndpoint {
+            url: &DEMO_AFFINITY_GROUP_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Put(
+                    serde_json::to_value(DEMO_AFFINITY_GROUP_UPDATE.clone()).unwrap(),
+                ),
+                AllowedMethod::Delete,
+            ]
+        },
+
         /* Instances */
         VerifyEndpoint {
             url: &DEMO_PROJECT_URL_INSTANCES,
@@ -1273,6 +1727,9 @@ lazy_static! {
             unprivileged_access: UnprivilegedAccess::None,
             allowed_methods: vec![
                 AllowedMethod::Get,
+                AllowedMethod::Put(
+                    serde_json::to_value(&*DEMO_INSTANCE_UPDATE).unwrap()
+                ),
                 AllowedMethod::Delete,
             ],
         },
@@ -1321,6 +1778,14 @@ lazy_static! {
                 AllowedMethod::GetNonexistent // has required query parameters
             ],
         },
+        VerifyEndpoint {
+            url: &DEMO_INSTANCE_SERIAL_HISTORY_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::GetNonexistent // no output is recorded
+            ],
+        },
         VerifyEndpoint {
             url: &DEMO_INSTANCE_SERIAL_STREAM_URL,
             visibility: Visibility::Protected,
@@ -1422,6 +1887,34 @@ lazy_static! {
             allowed_methods: vec![AllowedMethod::Get],
         },
 
+        VerifyEndpoint {
+            url: &HARDWARE_SLED_EXPUNGE_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![AllowedMethod::Post(
+                serde_json::Value::Null
+            )],
+        },
+
+        VerifyEndpoint {
+            url: &HARDWARE_SLED_DRAIN_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Post(serde_json::Value::Null),
+            ],
+        },
+
+        VerifyEndpoint {
+            url: &HARDWARE_SLED_UNDRAIN_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![AllowedMethod::Post(
+                serde_json::Value::Null
+            )],
+        },
+
         VerifyEndpoint {
             url: &HARDWARE_DISK_URL,
             visibility: Visibility::Public,
@@ -1436,6 +1929,13 @@ lazy_static! {
             allowed_methods: vec![AllowedMethod::Get],
         },
 
+        VerifyEndpoint {
+            url: &HARDWARE_REGION_REPLACEMENTS_URL,
+            visibility: Visibility::Public,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![AllowedMethod::Get],
+        },
+
         /* Updates */
 
         VerifyEndpoint {
@@ -1527,6 +2027,17 @@ lazy_static! {
             ],
         },
 
+        /* Audit log */
+
+        VerifyEndpoint {
+            url: "/v1/system/audit-log",
+            visibility: Visibility::Public,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+            ],
+        },
+
         /* Silo identity providers */
 
         VerifyEndpoint {
@@ -1560,6 +2071,41 @@ lazy_static! {
             allowed_methods: vec![AllowedMethod::Get],
         },
 
+        VerifyEndpoint {
+            url: &OIDC_IDENTITY_PROVIDERS_URL,
+            // See the SAML identity provider case above.
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![AllowedMethod::Post(
+                serde_json::to_value(&*OIDC_IDENTITY_PROVIDER).unwrap(),
+            )],
+        },
+        VerifyEndpoint {
+            url: &SPECIFIC_OIDC_IDENTITY_PROVIDER_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![AllowedMethod::Get],
+        },
+
+        VerifyEndpoint {
+            url: &SCIM_CLIENT_TOKENS_URL,
+            // See the SAML identity provider case above.
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Post(
+                    serde_json::to_value(&*SCIM_CLIENT_TOKEN_CREATE).unwrap(),
+                ),
+            ],
+        },
+        VerifyEndpoint {
+            url: &SPECIFIC_SCIM_CLIENT_TOKEN_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![AllowedMethod::Get, AllowedMethod::Delete],
+        },
+
         /* Misc */
 
         VerifyEndpoint {
@@ -1602,6 +2148,44 @@ lazy_static! {
             ],
         },
 
+        /* Access tokens */
+
+        VerifyEndpoint {
+            url: &DEMO_ACCESS_TOKENS_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::Full,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+                AllowedMethod::Post(
+                    serde_json::to_value(&*DEMO_ACCESS_TOKEN_CREATE).unwrap(),
+                ),
+            ],
+        },
+        VerifyEndpoint {
+            url: &DEMO_SPECIFIC_ACCESS_TOKEN_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::Full,
+            allowed_methods: vec![
+                AllowedMethod::Delete,
+            ],
+        },
+        VerifyEndpoint {
+            url: &DEMO_USER_ACCESS_TOKENS_URL,
+            visibility: Visibility::Public,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Get,
+            ],
+        },
+        VerifyEndpoint {
+            url: &DEMO_USER_SPECIFIC_ACCESS_TOKEN_URL,
+            visibility: Visibility::Protected,
+            unprivileged_access: UnprivilegedAccess::None,
+            allowed_methods: vec![
+                AllowedMethod::Delete,
+            ],
+        },
+
         /* Certificates */
         VerifyEndpoint {
             url: &DEMO_CERTIFICATES_URL,
@@ -0,0 +1,517 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integration tests for floating IPs
+
+use crate::integration_tests::instances::instance_post;
+use crate::integration_tests::instances::instance_simulate;
+use crate::integration_tests::instances::InstanceOp;
+use dropshot::test_util::ClientTestContext;
+use dropshot::HttpErrorResponseBody;
+use http::method::Method;
+use http::StatusCode;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::http_testing::TestResponse;
+use nexus_test_utils::resource_helpers::create_instance;
+use nexus_test_utils::resource_helpers::create_project;
+use nexus_test_utils::resource_helpers::object_create;
+use nexus_test_utils::resource_helpers::object_delete;
+use nexus_test_utils::resource_helpers::objects_list_page_authz;
+use nexus_test_utils::resource_helpers::populate_ip_pool;
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::ByteCount;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::InstanceCpuCount;
+use omicron_common::api::external::NameOrId;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::views::ExternalIp;
+use omicron_nexus::external_api::views::FloatingIp;
+use omicron_nexus::external_api::views::Instance;
+use uuid::Uuid;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const PROJECT_NAME: &str = "springfield-squidport";
+const FIP_NAME: &str = "flying-rainsticks";
+
+fn get_floating_ips_url() -> String {
+    format!("/v1/floating-ips?project={}", PROJECT_NAME)
+}
+
+fn get_floating_ip_url(name: &str) -> String {
+    format!("/v1/floating-ips/{}?project={}", name, PROJECT_NAME)
+}
+
+async fn floating_ip_create(
+    client: &ClientTestContext,
+    name: &str,
+) -> FloatingIp {
+    object_create(
+        client,
+        &get_floating_ips_url(),
+        &params::FloatingIpCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: format!("floating IP {:?}", name),
+            },
+            pool: None,
+        },
+    )
+    .await
+}
+
+async fn floating_ip_get(client: &ClientTestContext, name: &str) -> FloatingIp {
+    NexusRequest::object_get(client, &get_floating_ip_url(name))
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute_and_parse_unwrap::<FloatingIp>()
+        .await
+}
+
+async fn floating_ip_post(
+    client: &ClientTestContext,
+    name: &str,
+    action: &str,
+    body: Option<&params::FloatingIpAttach>,
+    status: StatusCode,
+) -> TestResponse {
+    let url = format!(
+        "/v1/floating-ips/{}/{}?project={}",
+        name, action, PROJECT_NAME
+    );
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &url)
+            .body(body)
+            .expect_status(Some(status)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+}
+
+async fn floating_ip_attach(
+    client: &ClientTestContext,
+    name: &str,
+    instance: &str,
+) -> FloatingIp {
+    let body = params::FloatingIpAttach {
+        instance: NameOrId::Name(instance.parse().unwrap()),
+    };
+    floating_ip_post(client, name, "attach", Some(&body), StatusCode::ACCEPTED)
+        .await
+        .parsed_body()
+        .unwrap()
+}
+
+async fn floating_ip_detach(
+    client: &ClientTestContext,
+    name: &str,
+) -> FloatingIp {
+    floating_ip_post(client, name, "detach", None, StatusCode::ACCEPTED)
+        .await
+        .parsed_body()
+        .unwrap()
+}
+
+async fn instance_external_ips(
+    client: &ClientTestContext,
+    instance: &str,
+) -> Vec<ExternalIp> {
+    let url = format!(
+        "/v1/instances/{}/external-ips?project={}",
+        instance, PROJECT_NAME
+    );
+    objects_list_page_authz::<ExternalIp>(client, &url).await.items
+}
+
+async fn sled_agent_external_ips(
+    cptestctx: &ControlPlaneTestContext,
+    instance_id: Uuid,
+) -> Vec<std::net::IpAddr> {
+    let sled_agent = &cptestctx.sled_agent.sled_agent;
+    sled_agent
+        .external_ips
+        .lock()
+        .await
+        .get(&instance_id)
+        .map(|ips| ips.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+async fn instance_create(
+    client: &ClientTestContext,
+    name: &str,
+    external_ips: Vec<params::ExternalIpCreate>,
+    status: StatusCode,
+) -> TestResponse {
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/instances?project={}", PROJECT_NAME),
+        )
+        .body(Some(&params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: String::from("an instance"),
+            },
+            labels: Default::default(),
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_gibibytes_u32(1),
+            hostname: String::from("inst"),
+            user_data: vec![],
+            network_interfaces:
+                params::InstanceNetworkInterfaceAttachment::Default,
+            external_ips,
+            disks: vec![],
+            affinity_groups: Vec::new(),
+            auto_restart_policy: Default::default(),
+            start: true,
+        }))
+        .expect_status(Some(status)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+}
+
+#[nexus_test]
+async fn test_floating_ip_create_list_delete(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    populate_ip_pool(client, "default", None).await;
+    create_project(client, PROJECT_NAME).await;
+
+    let fip = floating_ip_create(client, FIP_NAME).await;
+    assert_eq!(fip.identity.name.as_str(), FIP_NAME);
+    assert_eq!(fip.instance_id, None);
+
+    // A second floating IP gets a different address.
+    let other = floating_ip_create(client, "other").await;
+    assert_ne!(fip.ip, other.ip);
+
+    let fips =
+        objects_list_page_authz::<FloatingIp>(client, &get_floating_ips_url())
+            .await
+            .items;
+    assert_eq!(fips.len(), 2);
+    assert_eq!(floating_ip_get(client, FIP_NAME).await.ip, fip.ip);
+
+    // Names are unique within a project.
+    let error: HttpErrorResponseBody = NexusRequest::expect_failure_with_body(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::POST,
+        &get_floating_ips_url(),
+        &params::FloatingIpCreate {
+            identity: IdentityMetadataCreateParams {
+                name: FIP_NAME.parse().unwrap(),
+                description: String::new(),
+            },
+            pool: None,
+        },
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        format!("already exists: floating-ip \"{}\"", FIP_NAME)
+    );
+
+    // The project can't be deleted while it has floating IPs.
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::DELETE,
+        &format!("/v1/projects/{}", PROJECT_NAME),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    object_delete(client, &get_floating_ip_url(FIP_NAME)).await;
+    object_delete(client, &get_floating_ip_url("other")).await;
+    let fips =
+        objects_list_page_authz::<FloatingIp>(client, &get_floating_ips_url())
+            .await
+            .items;
+    assert!(fips.is_empty());
+    NexusRequest::object_delete(
+        client,
+        &format!("/v1/projects/{}", PROJECT_NAME),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+}
+
+#[nexus_test]
+async fn test_floating_ip_attach_detach(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx();
+    let nexus = &apictx.nexus;
+    populate_ip_pool(client, "default", None).await;
+    create_project(client, PROJECT_NAME).await;
+
+    let instance_name = "inst";
+    let instance = create_instance(client, PROJECT_NAME, instance_name).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let fip = floating_ip_create(client, FIP_NAME).await;
+
+    // A running instance's floating IPs can't be changed.
+    let body = params::FloatingIpAttach {
+        instance: NameOrId::Name(instance_name.parse().unwrap()),
+    };
+    let error: HttpErrorResponseBody = floating_ip_post(
+        client,
+        FIP_NAME,
+        "attach",
+        Some(&body),
+        StatusCode::BAD_REQUEST,
+    )
+    .await
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "cannot change the floating IPs of an instance in state running; \
+        stop the instance first"
+    );
+    assert!(instance_external_ips(client, instance_name).await.is_empty());
+
+    // Once it's stopped, attaching the floating IP adds it to the instance,
+    // and attaching it again is a no-op. The sled is given the address when
+    // the instance starts.
+    instance_post(client, instance_name, InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let attached = floating_ip_attach(client, FIP_NAME, instance_name).await;
+    assert_eq!(attached.instance_id, Some(instance.identity.id));
+    let attached = floating_ip_attach(client, FIP_NAME, instance_name).await;
+    assert_eq!(attached.instance_id, Some(instance.identity.id));
+    let ips = instance_external_ips(client, instance_name).await;
+    assert_eq!(ips.len(), 1);
+    assert_eq!(ips[0].ip, fip.ip);
+
+    instance_post(client, instance_name, InstanceOp::Start).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    assert_eq!(
+        sled_agent_external_ips(cptestctx, instance.identity.id).await,
+        vec![fip.ip]
+    );
+
+    // Nor can it be detached while the instance is running.
+    let error: HttpErrorResponseBody = floating_ip_post(
+        client,
+        FIP_NAME,
+        "detach",
+        None,
+        StatusCode::BAD_REQUEST,
+    )
+    .await
+    .parsed_body()
+    .unwrap();
+    assert!(error.message.contains("stop the instance first"));
+
+    // An attached floating IP can't be deleted.
+    let error: HttpErrorResponseBody = NexusRequest::expect_failure(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::DELETE,
+        &get_floating_ip_url(FIP_NAME),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "Floating IP cannot be deleted while attached to an instance"
+    );
+
+    // Detaching it from the stopped instance removes it, and the next start
+    // leaves it off the sled.
+    instance_post(client, instance_name, InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let detached = floating_ip_detach(client, FIP_NAME).await;
+    assert_eq!(detached.instance_id, None);
+    assert!(instance_external_ips(client, instance_name).await.is_empty());
+    let detached = floating_ip_detach(client, FIP_NAME).await;
+    assert_eq!(detached.instance_id, None);
+
+    instance_post(client, instance_name, InstanceOp::Start).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    assert!(sled_agent_external_ips(cptestctx, instance.identity.id)
+        .await
+        .is_empty());
+
+    object_delete(client, &get_floating_ip_url(FIP_NAME)).await;
+}
+
+#[nexus_test]
+async fn test_floating_ip_multiple_external_ips(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx();
+    let nexus = &apictx.nexus;
+    populate_ip_pool(client, "default", None).await;
+    create_project(client, PROJECT_NAME).await;
+    floating_ip_create(client, FIP_NAME).await;
+    floating_ip_create(client, "other").await;
+    let max_ips_error =
+        "An instance may not have more than 1 external IP addresses";
+
+    // OPTE ports have a single external IP, so an instance can't be created
+    // with an ephemeral and a floating IP, or with two floating IPs.
+    let ephemeral = params::ExternalIpCreate::Ephemeral { pool_name: None };
+    let floating = |name: &str| params::ExternalIpCreate::Floating {
+        floating_ip: NameOrId::Name(name.parse().unwrap()),
+    };
+    for external_ips in [
+        vec![ephemeral.clone(), floating(FIP_NAME)],
+        vec![floating(FIP_NAME), floating("other")],
+    ] {
+        let error: HttpErrorResponseBody = instance_create(
+            client,
+            "inst",
+            external_ips,
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .parsed_body()
+        .unwrap();
+        assert_eq!(error.message, max_ips_error);
+    }
+    assert_eq!(floating_ip_get(client, FIP_NAME).await.instance_id, None);
+    assert_eq!(floating_ip_get(client, "other").await.instance_id, None);
+
+    // Nor can a floating IP be attached to an instance that has an ephemeral
+    // IP, which would leave dpd sending the floating IP's traffic to a port
+    // that doesn't know about it.
+    let instance: Instance =
+        instance_create(client, "inst", vec![ephemeral], StatusCode::CREATED)
+            .await
+            .parsed_body()
+            .unwrap();
+    instance_simulate(nexus, &instance.identity.id).await;
+    instance_post(client, "inst", InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let ephemeral_ips = instance_external_ips(client, "inst").await;
+    assert_eq!(ephemeral_ips.len(), 1);
+
+    let body = params::FloatingIpAttach {
+        instance: NameOrId::Name("inst".parse().unwrap()),
+    };
+    let error: HttpErrorResponseBody = floating_ip_post(
+        client,
+        FIP_NAME,
+        "attach",
+        Some(&body),
+        StatusCode::BAD_REQUEST,
+    )
+    .await
+    .parsed_body()
+    .unwrap();
+    assert_eq!(error.message, max_ips_error);
+    assert_eq!(floating_ip_get(client, FIP_NAME).await.instance_id, None);
+
+    // An instance with a floating IP can't be given a second one either.
+    // Only the address it has reaches the sled when it starts.
+    let instance: Instance = instance_create(
+        client,
+        "inst2",
+        vec![floating(FIP_NAME)],
+        StatusCode::CREATED,
+    )
+    .await
+    .parsed_body()
+    .unwrap();
+    instance_simulate(nexus, &instance.identity.id).await;
+    instance_post(client, "inst2", InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let error: HttpErrorResponseBody = floating_ip_post(
+        client,
+        "other",
+        "attach",
+        Some(&params::FloatingIpAttach {
+            instance: NameOrId::Name("inst2".parse().unwrap()),
+        }),
+        StatusCode::BAD_REQUEST,
+    )
+    .await
+    .parsed_body()
+    .unwrap();
+    assert_eq!(error.message, max_ips_error);
+
+    instance_post(client, "inst2", InstanceOp::Start).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let fip = floating_ip_get(client, FIP_NAME).await;
+    assert_eq!(
+        sled_agent_external_ips(cptestctx, instance.identity.id).await,
+        vec![fip.ip]
+    );
+}
+
+#[nexus_test]
+async fn test_floating_ip_instance_create_delete(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx();
+    let nexus = &apictx.nexus;
+    populate_ip_pool(client, "default", None).await;
+    create_project(client, PROJECT_NAME).await;
+    let fip = floating_ip_create(client, FIP_NAME).await;
+
+    // Create an instance with the floating IP attached.
+    let instance_name = "inst";
+    let instance: Instance = instance_create(
+        client,
+        instance_name,
+        vec![params::ExternalIpCreate::Floating {
+            floating_ip: NameOrId::Name(FIP_NAME.parse().unwrap()),
+        }],
+        StatusCode::CREATED,
+    )
+    .await
+    .parsed_body()
+    .unwrap();
+    instance_simulate(nexus, &instance.identity.id).await;
+    assert_eq!(
+        floating_ip_get(client, FIP_NAME).await.instance_id,
+        Some(instance.identity.id)
+    );
+    assert_eq!(
+        sled_agent_external_ips(cptestctx, instance.identity.id).await,
+        vec![fip.ip]
+    );
+
+    // Deleting the instance detaches the floating IP, but doesn't delete it.
+    instance_post(client, instance_name, InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    NexusRequest::object_delete(
+        client,
+        &format!("/v1/instances/{}?project={}", instance_name, PROJECT_NAME),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    let fip = floating_ip_get(client, FIP_NAME).await;
+    assert_eq!(fip.instance_id, None);
+    object_delete(client, &get_floating_ip_url(FIP_NAME)).await;
+}
@@ -42,6 +42,7 @@ fn get_image_create(source: params::ImageSource) -> params::ImageCreate {
                 "you can boot any image, as long as it's alpine",
             ),
         },
+        labels: Default::default(),
         os: "alpine".to_string(),
         version: "edge".to_string(),
         block_size: params::BlockSize::try_from(512).unwrap(),
@@ -404,6 +405,7 @@ async fn test_make_disk_from_image(cptestctx: &ControlPlaneTestContext) {
             name: "disk".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Image {
             image_id: alpine_image.identity.id,
         },
@@ -455,6 +457,7 @@ async fn test_make_disk_from_image_too_small(
             name: "disk".parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Image {
             image_id: alpine_image.identity.id,
         },
@@ -31,6 +31,7 @@ use omicron_common::api::external::DiskState;
 use omicron_common::api::external::IdentityMetadataCreateParams;
 use omicron_common::api::external::IdentityMetadataUpdateParams;
 use omicron_common::api::external::Instance;
+use omicron_common::api::external::InstanceAutoRestartPolicy;
 use omicron_common::api::external::InstanceCpuCount;
 use omicron_common::api::external::InstanceNetworkInterface;
 use omicron_common::api::external::InstanceState;
@@ -47,9 +48,12 @@ use omicron_nexus::external_api::views;
 use omicron_nexus::TestInterfaces as _;
 use omicron_nexus::{external_api::params, Nexus};
 use omicron_sled_agent::sim::SledAgent;
+use omicron_test_utils::dev::poll::wait_for_condition;
+use omicron_test_utils::dev::poll::CondCheckError;
 use sled_agent_client::TestInterfaces as _;
 use std::convert::TryFrom;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 use dropshot::test_util::ClientTestContext;
@@ -223,6 +227,7 @@ async fn test_instances_create_reboot_halt(
                         &instance.identity.name
                     ),
                 },
+                labels: Default::default(),
                 ncpus: instance.ncpus,
                 memory: instance.memory,
                 hostname: instance.hostname.clone(),
@@ -231,6 +236,8 @@ async fn test_instances_create_reboot_halt(
                     params::InstanceNetworkInterfaceAttachment::Default,
                 external_ips: vec![],
                 disks: vec![],
+                affinity_groups: Vec::new(),
+                auto_restart_policy: Default::default(),
                 start: true,
             }))
             .expect_status(Some(StatusCode::BAD_REQUEST)),
@@ -681,6 +688,7 @@ async fn test_instances_create_stopped_start(
                 name: instance_name.parse().unwrap(),
                 description: format!("instance {}", instance_name),
             },
+            labels: Default::default(),
             ncpus: InstanceCpuCount(4),
             memory: ByteCount::from_gibibytes_u32(1),
             hostname: String::from("the_host"),
@@ -689,6 +697,8 @@ async fn test_instances_create_stopped_start(
                 params::InstanceNetworkInterfaceAttachment::Default,
             external_ips: vec![],
             disks: vec![],
+            affinity_groups: Vec::new(),
+            auto_restart_policy: Default::default(),
             start: false,
         },
     )
@@ -711,6 +721,174 @@ async fn test_instances_create_stopped_start(
     );
 }
 
+#[nexus_test]
+async fn test_instance_update(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx();
+    let nexus = &apictx.nexus;
+    let datastore = nexus.datastore();
+    let instance_name = "just-rainsticks";
+    let instance_url = get_instance_url(instance_name);
+
+    let project_id = create_org_and_project(&client).await;
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    // Create and start an instance. Its configuration can't be changed while
+    // it's running.
+    let instance = create_instance(client, PROJECT_NAME, instance_name).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let update = params::InstanceUpdate {
+        ncpus: Some(InstanceCpuCount(2)),
+        memory: Some(ByteCount::from_gibibytes_u32(2)),
+        hostname: Some(String::from("new-host")),
+        labels: None,
+    };
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::PUT, &instance_url)
+            .body(Some(&update))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "instance must be stopped to be updated (currently \"running\")"
+    );
+
+    // Stop the instance, then reconfigure it.
+    let instance =
+        instance_post(&client, instance_name, InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance: Instance =
+        NexusRequest::object_put(client, &instance_url, Some(&update))
+            .authn_as(AuthnMode::PrivilegedUser)
+            .execute()
+            .await
+            .unwrap()
+            .parsed_body()
+            .unwrap();
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+    assert_eq!(instance.ncpus.0, 2);
+    assert_eq!(instance.memory, ByteCount::from_gibibytes_u32(2));
+    assert_eq!(instance.hostname, "new-host");
+
+    // The new configuration is reflected in the resources provisioned to
+    // the project and silo.
+    for id in [project_id, *SILO_ID] {
+        let virtual_provisioning_collection = datastore
+            .virtual_provisioning_collection_get(&opctx, id)
+            .await
+            .unwrap();
+        assert_eq!(virtual_provisioning_collection.cpus_provisioned, 2);
+        assert_eq!(
+            virtual_provisioning_collection.ram_provisioned.0,
+            ByteCount::from_gibibytes_u32(2),
+        );
+    }
+
+    // Fields that aren't specified are left alone, and invalid memory sizes
+    // are rejected just as they are at creation time.
+    let instance: Instance = NexusRequest::object_put(
+        client,
+        &instance_url,
+        Some(&params::InstanceUpdate {
+            ncpus: Some(InstanceCpuCount(1)),
+            memory: None,
+            hostname: None,
+            labels: None,
+        }),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(instance.ncpus.0, 1);
+    assert_eq!(instance.memory, ByteCount::from_gibibytes_u32(2));
+    assert_eq!(instance.hostname, "new-host");
+
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::PUT, &instance_url)
+            .body(Some(&params::InstanceUpdate {
+                ncpus: None,
+                memory: Some(ByteCount::from(
+                    params::MIN_MEMORY_SIZE_BYTES / 2,
+                )),
+                hostname: None,
+                labels: None,
+            }))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // A configuration that doesn't fit on the instance's sled is accepted
+    // while the instance is stopped, but the instance can't start with it.
+    let too_many_cpus = InstanceCpuCount::try_from(i64::from(
+        nexus_test_utils::TEST_HARDWARE_THREADS + 1,
+    ))
+    .unwrap();
+    NexusRequest::object_put(
+        client,
+        &instance_url,
+        Some(&params::InstanceUpdate {
+            ncpus: Some(too_many_cpus),
+            memory: None,
+            hostname: None,
+            labels: None,
+        }),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &get_instance_url(&format!("{}/start", instance_name)),
+        )
+        .body(None as Option<&serde_json::Value>)
+        .expect_status(Some(StatusCode::SERVICE_UNAVAILABLE)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Stopped);
+
+    // Once the configuration fits again, the instance starts.
+    NexusRequest::object_put(
+        client,
+        &instance_url,
+        Some(&params::InstanceUpdate {
+            ncpus: Some(InstanceCpuCount(2)),
+            memory: None,
+            hostname: None,
+            labels: None,
+        }),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    let instance =
+        instance_post(&client, instance_name, InstanceOp::Start).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+    assert_eq!(instance.ncpus.0, 2);
+}
+
 #[nexus_test]
 async fn test_instances_delete_fails_when_running_succeeds_when_stopped(
     cptestctx: &ControlPlaneTestContext,
@@ -853,6 +1031,7 @@ async fn test_instance_create_saga_removes_instance_database_record(
             name: Name::try_from(String::from("unwind-test-inst")).unwrap(),
             description: String::from("instance to test saga unwind"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("inst"),
@@ -860,6 +1039,8 @@ async fn test_instance_create_saga_removes_instance_database_record(
         network_interfaces: interface_params.clone(),
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let response = NexusRequest::objects_post(
@@ -880,6 +1061,7 @@ async fn test_instance_create_saga_removes_instance_database_record(
             name: Name::try_from(String::from("unwind-test-inst2")).unwrap(),
             description: String::from("instance to test saga unwind 2"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("inst2"),
@@ -887,6 +1069,8 @@ async fn test_instance_create_saga_removes_instance_database_record(
         network_interfaces: interface_params,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let _ = NexusRequest::objects_post(
@@ -968,6 +1152,7 @@ async fn test_instance_with_single_explicit_ip_address(
             name: Name::try_from(String::from("nic-test-inst")).unwrap(),
             description: String::from("instance to test multiple nics"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nic-test"),
@@ -975,6 +1160,8 @@ async fn test_instance_with_single_explicit_ip_address(
         network_interfaces: interface_params,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let response = NexusRequest::objects_post(
@@ -1032,6 +1219,7 @@ async fn test_instance_with_new_custom_network_interfaces(
         },
         ipv4_block: Ipv4Net("172.31.0.0/24".parse().unwrap()),
         ipv6_block: None,
+        custom_router: None,
     };
     let _response = NexusRequest::objects_post(
         client,
@@ -1081,6 +1269,7 @@ async fn test_instance_with_new_custom_network_interfaces(
             name: Name::try_from(String::from("nic-test-inst")).unwrap(),
             description: String::from("instance to test multiple nics"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nic-test"),
@@ -1088,6 +1277,8 @@ async fn test_instance_with_new_custom_network_interfaces(
         network_interfaces: interface_params,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let response = NexusRequest::objects_post(
@@ -1177,6 +1368,7 @@ async fn test_instance_create_delete_network_interface(
         },
         ipv4_block: Ipv4Net("172.31.0.0/24".parse().unwrap()),
         ipv6_block: None,
+        custom_router: None,
     };
     let _response = NexusRequest::objects_post(
         client,
@@ -1194,6 +1386,7 @@ async fn test_instance_create_delete_network_interface(
             name: instance_name.parse().unwrap(),
             description: String::from("instance to test attaching new nic"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nic-test"),
@@ -1201,6 +1394,8 @@ async fn test_instance_create_delete_network_interface(
         network_interfaces: params::InstanceNetworkInterfaceAttachment::None,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let response = NexusRequest::objects_post(
@@ -1417,6 +1612,7 @@ async fn test_instance_update_network_interfaces(
         },
         ipv4_block: Ipv4Net("172.31.0.0/24".parse().unwrap()),
         ipv6_block: None,
+        custom_router: None,
     };
     let _response = NexusRequest::objects_post(
         client,
@@ -1434,6 +1630,7 @@ async fn test_instance_update_network_interfaces(
             name: instance_name.parse().unwrap(),
             description: String::from("instance to test updatin nics"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nic-test"),
@@ -1441,6 +1638,8 @@ async fn test_instance_update_network_interfaces(
         network_interfaces: params::InstanceNetworkInterfaceAttachment::None,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let response = NexusRequest::objects_post(
@@ -1826,6 +2025,7 @@ async fn test_instance_with_multiple_nics_unwinds_completely(
             name: Name::try_from(String::from("nic-fail-test-inst")).unwrap(),
             description: String::from("instance to test multiple bad nics"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nic-test"),
@@ -1833,6 +2033,8 @@ async fn test_instance_with_multiple_nics_unwinds_completely(
         network_interfaces: interface_params,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let builder =
@@ -1891,6 +2093,7 @@ async fn test_attach_one_disk_to_instance(cptestctx: &ControlPlaneTestContext) {
             name: instance_name.parse().unwrap(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nfs"),
@@ -1902,6 +2105,8 @@ async fn test_attach_one_disk_to_instance(cptestctx: &ControlPlaneTestContext) {
                 name: Name::try_from(String::from("probablydata")).unwrap(),
             },
         )],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -1950,6 +2155,7 @@ async fn test_instance_create_attach_disks(
             name: Name::try_from(String::from("nfs")).unwrap(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(3),
         hostname: String::from("nfs"),
@@ -1968,6 +2174,7 @@ async fn test_instance_create_attach_disks(
                 disk_source: params::DiskSource::Blank {
                     block_size: params::BlockSize::try_from(512).unwrap(),
                 },
+                labels: Default::default(),
             }),
             params::InstanceDiskAttachment::Attach(
                 params::InstanceDiskAttach {
@@ -1975,6 +2182,8 @@ async fn test_instance_create_attach_disks(
                 },
             ),
         ],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2046,6 +2255,7 @@ async fn test_instance_create_attach_disks_undo(
             name: Name::try_from(String::from("nfs")).unwrap(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nfs"),
@@ -2062,6 +2272,7 @@ async fn test_instance_create_attach_disks_undo(
                 disk_source: params::DiskSource::Blank {
                     block_size: params::BlockSize::try_from(512).unwrap(),
                 },
+                labels: Default::default(),
             }),
             params::InstanceDiskAttachment::Attach(
                 params::InstanceDiskAttach { name: regular_disk.identity.name },
@@ -2070,6 +2281,8 @@ async fn test_instance_create_attach_disks_undo(
                 params::InstanceDiskAttach { name: faulted_disk.identity.name },
             ),
         ],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2130,6 +2343,7 @@ async fn test_attach_eight_disks_to_instance(
             name: Name::try_from(String::from("nfs")).unwrap(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nfs"),
@@ -2146,6 +2360,8 @@ async fn test_attach_eight_disks_to_instance(
                 )
             })
             .collect(),
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2210,6 +2426,7 @@ async fn test_cannot_attach_nine_disks_to_instance(
             name: Name::try_from(String::from("nfs")).unwrap(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nfs"),
@@ -2226,6 +2443,8 @@ async fn test_cannot_attach_nine_disks_to_instance(
                 )
             })
             .collect(),
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2304,6 +2523,7 @@ async fn test_cannot_attach_faulted_disks(cptestctx: &ControlPlaneTestContext) {
             name: Name::try_from(String::from("nfs")).unwrap(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nfs"),
@@ -2320,6 +2540,8 @@ async fn test_cannot_attach_faulted_disks(cptestctx: &ControlPlaneTestContext) {
                 )
             })
             .collect(),
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2387,6 +2609,7 @@ async fn test_disks_detached_when_instance_destroyed(
             name: instance_name.parse().unwrap(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nfs"),
@@ -2403,6 +2626,8 @@ async fn test_disks_detached_when_instance_destroyed(
                 )
             })
             .collect(),
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2471,6 +2696,7 @@ async fn test_disks_detached_when_instance_destroyed(
             name: "nfsv2".parse().unwrap(),
             description: String::from("probably serving data too!"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("nfsv2"),
@@ -2487,6 +2713,8 @@ async fn test_disks_detached_when_instance_destroyed(
                 )
             })
             .collect(),
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2530,6 +2758,7 @@ async fn test_instances_memory_rejected_less_than_min_memory_size(
             name: instance_name.parse().unwrap(),
             description: format!("instance {:?}", &instance_name),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount(1),
         memory: ByteCount::from(params::MIN_MEMORY_SIZE_BYTES / 2),
         hostname: String::from("inst"),
@@ -2539,6 +2768,8 @@ async fn test_instances_memory_rejected_less_than_min_memory_size(
         network_interfaces: params::InstanceNetworkInterfaceAttachment::Default,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2579,6 +2810,7 @@ async fn test_instances_memory_not_divisible_by_min_memory_size(
             name: instance_name.parse().unwrap(),
             description: format!("instance {:?}", &instance_name),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount(1),
         memory: ByteCount::from(1024 * 1024 * 1024 + 300),
         hostname: String::from("inst"),
@@ -2588,6 +2820,8 @@ async fn test_instances_memory_not_divisible_by_min_memory_size(
         network_interfaces: params::InstanceNetworkInterfaceAttachment::Default,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -2680,6 +2914,7 @@ async fn test_cannot_provision_instance_beyond_cpu_capacity(
             name: name1.clone(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: too_many_cpus,
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("test"),
@@ -2687,6 +2922,8 @@ async fn test_cannot_provision_instance_beyond_cpu_capacity(
         network_interfaces: params::InstanceNetworkInterfaceAttachment::Default,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: false,
     };
     let url_instances = get_instances_url();
@@ -2742,6 +2979,7 @@ async fn test_cannot_provision_instance_beyond_ram_capacity(
             name: name1.clone(),
             description: String::from("probably serving data"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: too_much_ram,
         hostname: String::from("test"),
@@ -2749,6 +2987,8 @@ async fn test_cannot_provision_instance_beyond_ram_capacity(
         network_interfaces: params::InstanceNetworkInterfaceAttachment::Default,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: false,
     };
     let url_instances = get_instances_url();
@@ -2857,6 +3097,56 @@ async fn test_instance_serial(cptestctx: &ControlPlaneTestContext) {
     }
     assert_eq!(&actual[..expected.len()], expected);
 
+    // The same output is available from the recorded history.
+    let instance_serial_history_url = get_instance_url(
+        format!("{}/serial-console/history", instance_name).as_str(),
+    );
+    let history: params::InstanceSerialConsoleHistory =
+        NexusRequest::object_get(
+            client,
+            &format!(
+                "{}&from_start=0&max_bytes={}",
+                instance_serial_history_url,
+                expected.len()
+            ),
+        )
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .expect("failed to make request")
+        .parsed_body()
+        .unwrap();
+    assert_eq!(history.boot_generations, vec![1]);
+    assert_eq!(history.boot_generation, 1);
+    assert_eq!(history.first_byte_offset, 0);
+    assert_eq!(history.last_byte_offset, history.data.len() as u64);
+    assert!(history.data.len() <= expected.len());
+    assert_eq!(history.data, &expected[..history.data.len()]);
+
+    // Nexus knows which boots the instance has had.
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::NOT_FOUND,
+        Method::GET,
+        &format!("{}&boot_generation=2", instance_serial_history_url),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // Asking for both ends of the output at once is an error.
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::GET,
+        &format!("{}&from_start=0&most_recent=1", instance_serial_history_url),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
     // Request a halt and verify both the immediate state and the finished state.
     let instance = instance_next;
     let instance_next =
@@ -2920,6 +3210,7 @@ async fn test_instance_ephemeral_ip_from_correct_pool(
             name: Name::try_from(String::from("ip-pool-test")).unwrap(),
             description: String::from("instance to test IP Pool restriction"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("inst"),
@@ -2931,6 +3222,8 @@ async fn test_instance_ephemeral_ip_from_correct_pool(
             ),
         }],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let response = NexusRequest::objects_post(
@@ -3003,6 +3296,7 @@ async fn test_instance_create_in_silo(cptestctx: &ControlPlaneTestContext) {
                 name: PROJECT_NAME.parse().unwrap(),
                 description: String::new(),
             },
+            labels: Default::default(),
         },
     )
     .authn_as(AuthnMode::SiloUser(user_id))
@@ -3019,6 +3313,7 @@ async fn test_instance_create_in_silo(cptestctx: &ControlPlaneTestContext) {
             name: Name::try_from(String::from("ip-pool-test")).unwrap(),
             description: String::from("instance to test IP Pool authz"),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount::try_from(2).unwrap(),
         memory: ByteCount::from_gibibytes_u32(4),
         hostname: String::from("inst"),
@@ -3028,6 +3323,8 @@ async fn test_instance_create_in_silo(cptestctx: &ControlPlaneTestContext) {
             pool_name: Some(Name::try_from(String::from("default")).unwrap()),
         }],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
     let url_instances = format!("/v1/instances?project={}", PROJECT_NAME);
@@ -3153,6 +3450,141 @@ async fn test_instance_v2p_mappings(cptestctx: &ControlPlaneTestContext) {
     }
 }
 
+#[nexus_test]
+async fn test_instance_auto_restart(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx();
+    let nexus = &apictx.nexus;
+    let datastore = nexus.datastore();
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    populate_ip_pool(&client, "default", None).await;
+    create_project(client, PROJECT_NAME).await;
+
+    // Create an instance that should be restarted if it fails.
+    let instance_name = "restartable";
+    let instance: Instance = object_create(
+        client,
+        &get_instances_url(),
+        &params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: instance_name.parse().unwrap(),
+                description: String::from("an instance that restarts"),
+            },
+            labels: Default::default(),
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_gibibytes_u32(1),
+            hostname: String::from("restartable"),
+            user_data: vec![],
+            network_interfaces:
+                params::InstanceNetworkInterfaceAttachment::Default,
+            external_ips: vec![],
+            disks: vec![],
+            affinity_groups: Vec::new(),
+            auto_restart_policy: InstanceAutoRestartPolicy::OnFailure,
+            start: true,
+        },
+    )
+    .await;
+    assert_eq!(
+        instance.auto_restart_policy,
+        InstanceAutoRestartPolicy::OnFailure
+    );
+    let instance_id = instance.identity.id;
+    instance_simulate(nexus, &instance_id).await;
+    let instance_url = get_instance_url(instance_name);
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    let old_propolis_id = db_instance.runtime().propolis_id;
+
+    // Make the sled forget about the instance, as it would if it rebooted.
+    // The auto-restart task should notice that the instance has been lost and
+    // start it again with a new Propolis.
+    cptestctx
+        .sled_agent
+        .sled_agent
+        .instance_unregister(instance_id)
+        .await
+        .unwrap();
+    wait_for_condition(
+        || async {
+            let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+                .instance_id(instance_id)
+                .fetch()
+                .await
+                .unwrap();
+            let runtime = db_instance.runtime();
+            if runtime.propolis_id != old_propolis_id
+                && runtime.state.0 == InstanceState::Starting
+            {
+                Ok(())
+            } else {
+                Err(CondCheckError::<()>::NotYet)
+            }
+        },
+        &Duration::from_millis(100),
+        &Duration::from_secs(60),
+    )
+    .await
+    .expect("instance was not restarted");
+
+    instance_simulate(nexus, &instance_id).await;
+    let instance = instance_get(&client, &instance_url).await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+
+    // The restart should have been recorded, so that the next one backs off.
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    assert_eq!(*db_instance.auto_restart_count, 1);
+    assert!(db_instance.time_auto_restart_next.is_some());
+    let old_propolis_id = db_instance.runtime().propolis_id;
+
+    // Pretend that the instance has already used up its restarts (the test
+    // config allows three in a row) and lose it again.  This time it should be
+    // left failed.
+    let gave_up_after = Utc::now();
+    datastore
+        .instance_set_auto_restart_backoff(&instance_id, 3, gave_up_after)
+        .await
+        .unwrap();
+    cptestctx
+        .sled_agent
+        .sled_agent
+        .instance_unregister(instance_id)
+        .await
+        .unwrap();
+    let db_instance = wait_for_condition(
+        || async {
+            let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+                .instance_id(instance_id)
+                .fetch()
+                .await
+                .unwrap();
+            match db_instance.time_auto_restart_next {
+                Some(next) if next > gave_up_after => Ok(db_instance),
+                _ => Err(CondCheckError::<()>::NotYet),
+            }
+        },
+        &Duration::from_millis(100),
+        &Duration::from_secs(60),
+    )
+    .await
+    .expect("instance restarts were not given up on");
+    assert_eq!(*db_instance.auto_restart_count, 3);
+    assert_eq!(db_instance.runtime().propolis_id, old_propolis_id);
+    assert_eq!(db_instance.runtime().state.0, InstanceState::Failed);
+}
+
 async fn instance_get(
     client: &ClientTestContext,
     instance_url: &str,
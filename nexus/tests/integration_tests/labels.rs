@@ -0,0 +1,210 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integration tests for labels on projects and project-scoped resources
+
+use dropshot::test_util::ClientTestContext;
+use dropshot::HttpErrorResponseBody;
+use http::method::Method;
+use http::StatusCode;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::resource_helpers::create_instance;
+use nexus_test_utils::resource_helpers::create_project;
+use nexus_test_utils::resource_helpers::object_create;
+use nexus_test_utils::resource_helpers::object_put;
+use nexus_test_utils::resource_helpers::objects_list_page_authz;
+use nexus_test_utils::resource_helpers::populate_ip_pool;
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::IdentityMetadataUpdateParams;
+use omicron_common::api::external::Instance;
+use omicron_common::api::external::InstanceState;
+use omicron_common::api::external::Labels;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::views::Project;
+use omicron_nexus::TestInterfaces as _;
+use sled_agent_client::TestInterfaces as _;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+fn labels<const N: usize>(labels: [(&str, &str); N]) -> Labels {
+    Labels::try_from(labels).unwrap()
+}
+
+async fn create_labeled_project(
+    client: &ClientTestContext,
+    name: &str,
+    labels: Labels,
+) -> Project {
+    object_create(
+        client,
+        "/v1/projects",
+        &params::ProjectCreate {
+            identity: IdentityMetadataCreateParams {
+                name: name.parse().unwrap(),
+                description: String::from("labeled project"),
+            },
+            labels,
+        },
+    )
+    .await
+}
+
+async fn project_names(client: &ClientTestContext, url: &str) -> Vec<String> {
+    objects_list_page_authz::<Project>(client, url)
+        .await
+        .items
+        .into_iter()
+        .map(|p| p.identity.name.to_string())
+        .collect()
+}
+
+#[nexus_test]
+async fn test_project_labels(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    let storage = create_labeled_project(
+        client,
+        "storage",
+        labels([("team", "storage"), ("env", "prod")]),
+    )
+    .await;
+    assert_eq!(storage.labels.get("team"), Some("storage"));
+    assert_eq!(storage.labels.get("env"), Some("prod"));
+    create_labeled_project(client, "compute", labels([("team", "compute")]))
+        .await;
+    let unlabeled = create_project(client, "unlabeled").await;
+    assert!(unlabeled.labels.is_empty());
+
+    // Without a selector, every project is listed.
+    assert_eq!(
+        project_names(client, "/v1/projects").await,
+        ["compute", "storage", "unlabeled"]
+    );
+
+    // Selectors may match a label's value or just its presence, and every
+    // requirement must be satisfied.
+    assert_eq!(
+        project_names(client, "/v1/projects?label=team=storage").await,
+        ["storage"]
+    );
+    assert_eq!(
+        project_names(client, "/v1/projects?label=team").await,
+        ["compute", "storage"]
+    );
+    assert_eq!(
+        project_names(client, "/v1/projects?label=team,env=prod").await,
+        ["storage"]
+    );
+    assert!(project_names(client, "/v1/projects?label=team=network")
+        .await
+        .is_empty());
+
+    // Invalid selectors are rejected.
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::GET,
+        "/v1/projects?label=Team=storage",
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // So are invalid labels.
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, "/v1/projects")
+            .body(Some(&serde_json::json!({
+                "name": "invalid",
+                "description": "invalid labels",
+                "labels": { "-team": "storage" },
+            })))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert!(
+        error.message.contains("must begin and end with"),
+        "unexpected error: {}",
+        error.message
+    );
+
+    // Updating a project's labels replaces all of them.
+    let storage: Project = object_put(
+        client,
+        "/v1/projects/storage",
+        &params::ProjectUpdate {
+            identity: IdentityMetadataUpdateParams {
+                name: None,
+                description: None,
+            },
+            labels: Some(labels([("team", "archive")])),
+        },
+    )
+    .await;
+    assert_eq!(storage.labels, labels([("team", "archive")]));
+    assert!(project_names(client, "/v1/projects?label=env").await.is_empty());
+
+    // Leaving the labels out of an update keeps them.
+    let storage: Project = object_put(
+        client,
+        "/v1/projects/storage",
+        &params::ProjectUpdate {
+            identity: IdentityMetadataUpdateParams {
+                name: None,
+                description: Some(String::from("archived")),
+            },
+            labels: None,
+        },
+    )
+    .await;
+    assert_eq!(storage.labels, labels([("team", "archive")]));
+}
+
+#[nexus_test]
+async fn test_instance_labels(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+
+    populate_ip_pool(&client, "default", None).await;
+    create_project(client, "springfield").await;
+    let instance = create_instance(client, "springfield", "plant").await;
+    assert!(instance.labels.is_empty());
+    let sa = nexus.instance_sled_by_id(&instance.identity.id).await.unwrap();
+    sa.instance_finish_transition(instance.identity.id).await;
+
+    // Labels can be changed even while the instance is running.
+    let instance_url = "/v1/instances/plant?project=springfield";
+    let instance: Instance = object_put(
+        client,
+        instance_url,
+        &params::InstanceUpdate {
+            ncpus: None,
+            memory: None,
+            hostname: None,
+            labels: Some(labels([("role", "reactor")])),
+        },
+    )
+    .await;
+    assert_eq!(instance.runtime.run_state, InstanceState::Running);
+    assert_eq!(instance.labels, labels([("role", "reactor")]));
+
+    create_instance(client, "springfield", "tavern").await;
+    let instances = objects_list_page_authz::<Instance>(
+        client,
+        "/v1/instances?project=springfield&label=role=reactor",
+    )
+    .await
+    .items;
+    assert_eq!(instances.len(), 1);
+    assert_eq!(instances[0].identity.id, instance.identity.id);
+}
@@ -3,6 +3,9 @@
 //! See the driver in the parent directory for how and why this is structured
 //! the way it is.
 
+mod access_tokens;
+mod affinity_groups;
+mod audit_log;
 mod authn_http;
 mod authz;
 mod basic;
@@ -11,22 +14,28 @@ mod commands;
 mod console_api;
 mod device_auth;
 mod disks;
+mod floating_ips;
 mod images;
 mod instances;
 mod ip_pools;
+mod labels;
 mod metrics;
+mod oidc;
 mod oximeter;
 mod pantry;
 mod password_login;
 mod projects;
+mod quotas;
 mod rack;
 mod role_assignments;
 mod roles_builtin;
 mod router_routes;
 mod saml;
+mod scim;
 mod silo_users;
 mod silos;
 mod sleds;
+mod snapshot_schedules;
 mod snapshots;
 mod ssh_keys;
 mod subnet_allocation;
@@ -37,6 +46,7 @@ mod updates;
 mod users_builtin;
 mod volume_management;
 mod vpc_firewall;
+mod vpc_peerings;
 mod vpc_routers;
 mod vpc_subnets;
 mod vpcs;
@@ -0,0 +1,350 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use nexus_test_utils::assert_same_items;
+use nexus_test_utils::http_testing::{AuthnMode, NexusRequest, RequestBuilder};
+use nexus_test_utils::resource_helpers::{create_silo, object_create};
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_nexus::external_api::views;
+use omicron_nexus::external_api::{params, shared};
+
+use base64::Engine;
+use dropshot::ResultsPage;
+use http::method::Method;
+use http::StatusCode;
+use httptest::{matchers::*, responders::*, Expectation, Server};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::sign::Signer;
+use std::collections::HashMap;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const SILO_NAME: &str = "oidc-silo";
+const PROVIDER_NAME: &str = "some-totally-real-oidc-provider";
+const ISSUER: &str = "https://some.idp.test";
+const CLIENT_ID: &str = "oxide-rack";
+
+fn b64(data: &[u8]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data)
+}
+
+fn oidc_idp_create(server: &Server) -> params::OidcIdentityProviderCreate {
+    params::OidcIdentityProviderCreate {
+        identity: IdentityMetadataCreateParams {
+            name: PROVIDER_NAME.parse().unwrap(),
+            description: "a demo provider".to_string(),
+        },
+
+        issuer: ISSUER.to_string(),
+        client_id: CLIENT_ID.to_string(),
+        client_secret: Some("shh".to_string()),
+        authorization_endpoint: server.url("/authorize").to_string(),
+        token_endpoint: server.url("/token").to_string(),
+        jwks_uri: server.url("/jwks").to_string(),
+        redirect_uri: format!(
+            "https://customer.site/login/{}/oidc/{}/callback",
+            SILO_NAME, PROVIDER_NAME
+        ),
+        scopes: vec!["email".to_string(), "groups".to_string()],
+
+        group_claim_name: Some("groups".to_string()),
+    }
+}
+
+fn jwks(key: &PKey<Private>) -> serde_json::Value {
+    let rsa = key.rsa().unwrap();
+    serde_json::json!({
+        "keys": [{
+            "kty": "RSA",
+            "kid": "key1",
+            "alg": "RS256",
+            "use": "sig",
+            "n": b64(&rsa.n().to_vec()),
+            "e": b64(&rsa.e().to_vec()),
+        }]
+    })
+}
+
+fn sign_id_token(key: &PKey<Private>, claims: &serde_json::Value) -> String {
+    let header = b64(br#"{"alg":"RS256","typ":"JWT","kid":"key1"}"#);
+    let payload = b64(claims.to_string().as_bytes());
+    let signing_input = format!("{}.{}", header, payload);
+    let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+    signer.update(signing_input.as_bytes()).unwrap();
+    format!("{}.{}", signing_input, b64(&signer.sign_to_vec().unwrap()))
+}
+
+// Create an OIDC IdP, and view it
+#[nexus_test]
+async fn test_create_an_oidc_idp(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    create_silo(&client, SILO_NAME, true, shared::SiloIdentityMode::OidcJit)
+        .await;
+
+    let server = Server::run();
+    let created: views::OidcIdentityProvider = object_create(
+        client,
+        &format!("/v1/system/identity-providers/oidc?silo={}", SILO_NAME),
+        &oidc_idp_create(&server),
+    )
+    .await;
+
+    // "openid" is always requested, and the client secret is never shown
+    assert_eq!(created.scopes, vec!["openid", "email", "groups"]);
+    assert_eq!(created.issuer, ISSUER);
+
+    let fetched: views::OidcIdentityProvider = NexusRequest::object_get(
+        client,
+        &format!(
+            "/v1/system/identity-providers/oidc/{}?silo={}",
+            PROVIDER_NAME, SILO_NAME
+        ),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("failed to make request")
+    .parsed_body()
+    .unwrap();
+    assert_eq!(fetched.identity.id, created.identity.id);
+
+    // The provider also appears in the list of all of the Silo's providers
+    let providers: ResultsPage<views::IdentityProvider> =
+        NexusRequest::object_get(
+            client,
+            &format!("/v1/system/identity-providers?silo={}", SILO_NAME),
+        )
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .expect("failed to make request")
+        .parsed_body()
+        .unwrap();
+    assert_eq!(providers.items.len(), 1);
+    assert_eq!(
+        providers.items[0].provider_type,
+        views::IdentityProviderType::Oidc
+    );
+}
+
+// OIDC identity providers can only be created in Silos using OIDC
+#[nexus_test]
+async fn test_create_an_oidc_idp_in_saml_silo(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+
+    create_silo(&client, SILO_NAME, true, shared::SiloIdentityMode::SamlJit)
+        .await;
+
+    let server = Server::run();
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/system/identity-providers/oidc?silo={}", SILO_NAME),
+        )
+        .body(Some(&oidc_idp_create(&server)))
+        .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("unexpected success");
+}
+
+// Create an OIDC IdP with an invalid endpoint URL
+#[nexus_test]
+async fn test_create_an_oidc_idp_invalid_url(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+
+    create_silo(&client, SILO_NAME, true, shared::SiloIdentityMode::OidcJit)
+        .await;
+
+    let server = Server::run();
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/system/identity-providers/oidc?silo={}", SILO_NAME),
+        )
+        .body(Some(&params::OidcIdentityProviderCreate {
+            jwks_uri: "not a url".to_string(),
+            ..oidc_idp_create(&server)
+        }))
+        .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("unexpected success");
+}
+
+// Log in through the whole authorization code flow, with JIT provisioning of
+// the user and their groups
+#[nexus_test]
+async fn test_oidc_login(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    create_silo(&client, SILO_NAME, true, shared::SiloIdentityMode::OidcJit)
+        .await;
+
+    let server = Server::run();
+    let _oidc_idp: views::OidcIdentityProvider = object_create(
+        client,
+        &format!("/v1/system/identity-providers/oidc?silo={}", SILO_NAME),
+        &oidc_idp_create(&server),
+    )
+    .await;
+
+    // Begin login: expect a redirect to the provider's authorization endpoint
+    let result = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::GET,
+            &format!("/login/{}/oidc/{}", SILO_NAME, PROVIDER_NAME),
+        )
+        .header(http::header::REFERER, "https://customer.site/projects")
+        .expect_status(Some(StatusCode::FOUND)),
+    )
+    .execute()
+    .await
+    .expect("expected success");
+
+    let location =
+        reqwest::Url::parse(result.headers["Location"].to_str().unwrap())
+            .unwrap();
+    assert_eq!(location.path(), "/authorize");
+    let query: HashMap<String, String> =
+        location.query_pairs().into_owned().collect();
+    assert_eq!(query["response_type"], "code");
+    assert_eq!(query["client_id"], CLIENT_ID);
+    assert_eq!(query["scope"], "openid email groups");
+    assert_eq!(query["code_challenge_method"], "S256");
+    assert!(!query["code_challenge"].is_empty());
+    let state = query["state"].clone();
+    let nonce = query["nonce"].clone();
+
+    // The login state is kept in a cookie, scoped to this provider
+    let login_cookie = result.headers["Set-Cookie"].to_str().unwrap();
+    assert!(login_cookie.starts_with("oidc_login="));
+    assert!(login_cookie.contains(&format!(
+        "Path=/login/{}/oidc/{}",
+        SILO_NAME, PROVIDER_NAME
+    )));
+    let login_cookie = login_cookie.split(';').next().unwrap().to_string();
+
+    // The provider hands back a code, which Nexus exchanges (with the PKCE
+    // code verifier) for a signed ID token
+    let signing_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let now = chrono::Utc::now().timestamp();
+    let id_token = sign_id_token(
+        &signing_key,
+        &serde_json::json!({
+            "iss": ISSUER,
+            "aud": CLIENT_ID,
+            "sub": "some@customer.com",
+            "nonce": nonce,
+            "iat": now,
+            "exp": now + 300,
+            "groups": ["SRE", "Admins"],
+        }),
+    );
+    server.expect(
+        Expectation::matching(httptest::all_of![
+            request::method_path("POST", "/token"),
+            request::body(url_decoded(contains((
+                "grant_type",
+                "authorization_code"
+            )))),
+            request::body(url_decoded(contains(("code", "the-code")))),
+            request::body(url_decoded(contains(key("code_verifier")))),
+            request::body(url_decoded(contains(("client_secret", "shh")))),
+        ])
+        .respond_with(json_encoded(serde_json::json!({
+            "access_token": "unused",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        }))),
+    );
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/jwks"))
+            .respond_with(json_encoded(jwks(&signing_key))),
+    );
+
+    // A callback with the wrong state is rejected
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::GET,
+            &format!(
+                "/login/{}/oidc/{}/callback?code=the-code&state=wrong",
+                SILO_NAME, PROVIDER_NAME
+            ),
+        )
+        .header(http::header::COOKIE, login_cookie.clone())
+        .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .execute()
+    .await
+    .expect("unexpected success");
+
+    let result = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::GET,
+            &format!(
+                "/login/{}/oidc/{}/callback?code=the-code&state={}",
+                SILO_NAME, PROVIDER_NAME, state
+            ),
+        )
+        .header(http::header::COOKIE, login_cookie)
+        .expect_status(Some(StatusCode::SEE_OTHER)),
+    )
+    .execute()
+    .await
+    .expect("expected success");
+
+    // The user is sent back to where they started
+    assert_eq!(
+        result.headers["Location"].to_str().unwrap(),
+        "https://customer.site/projects"
+    );
+
+    let session_cookie_value =
+        result.headers["Set-Cookie"].to_str().unwrap().to_string();
+    assert!(session_cookie_value.starts_with("session="));
+
+    let session_me = NexusRequest::new(
+        RequestBuilder::new(client, Method::GET, "/v1/me")
+            .header(http::header::COOKIE, session_cookie_value.clone())
+            .expect_status(Some(StatusCode::OK)),
+    )
+    .execute_and_parse_unwrap::<views::CurrentUser>()
+    .await;
+
+    assert_eq!(session_me.user.display_name, "some@customer.com");
+
+    let groups: ResultsPage<views::Group> = NexusRequest::new(
+        RequestBuilder::new(client, Method::GET, "/v1/me/groups")
+            .header(http::header::COOKIE, session_cookie_value)
+            .expect_status(Some(StatusCode::OK)),
+    )
+    .execute()
+    .await
+    .expect("expected success")
+    .parsed_body()
+    .unwrap();
+
+    let group_names: Vec<&str> =
+        groups.items.iter().map(|g| g.display_name.as_str()).collect();
+    assert_same_items(group_names, vec!["SRE", "Admins"]);
+}
@@ -4,12 +4,21 @@
 
 //! Tests Nexus' interactions with Crucible's pantry
 
+use dropshot::endpoint;
 use dropshot::test_util::ClientTestContext;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpServer;
+use dropshot::HttpServerStarter;
+use dropshot::RequestContext;
 use http::method::Method;
+use http::Response;
 use http::StatusCode;
+use hyper::Body;
 use nexus_test_utils::http_testing::AuthnMode;
 use nexus_test_utils::http_testing::NexusRequest;
 use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::http_testing::TestResponse;
 use nexus_test_utils::identity_eq;
 use nexus_test_utils::resource_helpers::create_instance;
 use nexus_test_utils::resource_helpers::create_project;
@@ -18,6 +27,7 @@ use nexus_test_utils::resource_helpers::populate_ip_pool;
 use nexus_test_utils::resource_helpers::DiskTest;
 use nexus_test_utils_macros::nexus_test;
 use omicron_common::api::external::ByteCount;
+use omicron_common::api::external::Digest;
 use omicron_common::api::external::Disk;
 use omicron_common::api::external::DiskState;
 use omicron_common::api::external::IdentityMetadataCreateParams;
@@ -127,6 +137,7 @@ async fn create_disk_with_state_importing_blocks(client: &ClientTestContext) {
                 name: DISK_NAME.parse().unwrap(),
                 description: String::from("sells rainsticks"),
             },
+            labels: Default::default(),
             disk_source: params::DiskSource::ImportingBlocks {
                 block_size: params::BlockSize::try_from(512).unwrap(),
             },
@@ -299,6 +310,21 @@ async fn bulk_write_stop(
 }
 
 async fn import_blocks_from_url(client: &ClientTestContext) {
+    import_blocks_from_url_manual(
+        client,
+        "http://fake.endpoint/image.iso",
+        None,
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+}
+
+async fn import_blocks_from_url_manual(
+    client: &ClientTestContext,
+    url: &str,
+    expected_digest: Option<params::ExpectedDigest>,
+    expected_status: StatusCode,
+) {
     // Import blocks from a URL
     let import_blocks_from_url_url =
         format!("/v1/disks/{}/import?project={}", DISK_NAME, PROJECT_NAME,);
@@ -306,10 +332,10 @@ async fn import_blocks_from_url(client: &ClientTestContext) {
     NexusRequest::new(
         RequestBuilder::new(client, Method::POST, &import_blocks_from_url_url)
             .body(Some(&params::ImportBlocksFromUrl {
-                url: "http://fake.endpoint/image.iso".to_string(),
-                expected_digest: None,
+                url: url.to_string(),
+                expected_digest,
             }))
-            .expect_status(Some(StatusCode::NO_CONTENT)),
+            .expect_status(Some(expected_status)),
     )
     .authn_as(AuthnMode::PrivilegedUser)
     .execute()
@@ -353,6 +379,223 @@ async fn finalize_import_take_snapshot(
     .unwrap();
 }
 
+async fn export_start(client: &ClientTestContext, expected_status: StatusCode) {
+    let export_start_url = format!(
+        "/v1/disks/{}/export-start?project={}",
+        DISK_NAME, PROJECT_NAME,
+    );
+
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &export_start_url)
+            .expect_status(Some(expected_status)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+}
+
+async fn export_stop(client: &ClientTestContext, expected_status: StatusCode) {
+    let export_stop_url = format!(
+        "/v1/disks/{}/export-stop?project={}",
+        DISK_NAME, PROJECT_NAME,
+    );
+
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &export_stop_url)
+            .expect_status(Some(expected_status)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+}
+
+async fn export(
+    client: &ClientTestContext,
+    url: &str,
+    format: &str,
+    expected_status: StatusCode,
+) -> TestResponse {
+    let export_url = format!("{}&format={}", url, format);
+
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::GET, &export_url)
+            .expect_status(Some(expected_status)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+}
+
+fn get_disk_export_url() -> String {
+    format!("/v1/disks/{}/export?project={}", DISK_NAME, PROJECT_NAME)
+}
+
+fn get_disk_export_digest_url() -> String {
+    format!("/v1/disks/{}/export-digest?project={}", DISK_NAME, PROJECT_NAME)
+}
+
+async fn export_digest(client: &ClientTestContext, url: &str) -> String {
+    let digest: Digest = NexusRequest::object_get(client, url)
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap()
+        .parsed_body()
+        .unwrap();
+
+    match digest {
+        Digest::Sha256(digest) => digest,
+    }
+}
+
+fn read_be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Finds the cluster of an exported qcow2 image that holds the given guest
+/// offset, if one is allocated
+fn qcow2_cluster(image: &[u8], guest_offset: u64) -> Option<&[u8]> {
+    const CLUSTER: u64 = 65536;
+    let offset_mask = !(1 << 63);
+
+    let l1_offset = read_be_u64(image, 40);
+    let l1_index = guest_offset / (CLUSTER * 8192);
+    let l2_offset =
+        read_be_u64(image, (l1_offset + l1_index * 8) as usize) & offset_mask;
+    if l2_offset == 0 {
+        return None;
+    }
+
+    let l2_index = (guest_offset / CLUSTER) % 8192;
+    let data_offset =
+        read_be_u64(image, (l2_offset + l2_index * 8) as usize) & offset_mask;
+    if data_offset == 0 {
+        return None;
+    }
+
+    Some(&image[data_offset as usize..(data_offset + CLUSTER) as usize])
+}
+
+/// The digest of a disk of `size` bytes which is all zeroes apart from
+/// `writes`, found without holding the whole disk in memory
+fn sparse_disk_digest(size: u64, writes: &[(u64, &[u8])]) -> String {
+    const CHUNK: u64 = 4 * 1024 * 1024;
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut chunk = vec![0; CHUNK as usize];
+    for offset in (0..size).step_by(CHUNK as usize) {
+        chunk.fill(0);
+        for (write_offset, data) in writes {
+            let start = std::cmp::max(offset, *write_offset);
+            let end =
+                std::cmp::min(offset + CHUNK, write_offset + data.len() as u64);
+            if start < end {
+                chunk[(start - offset) as usize..(end - offset) as usize]
+                    .copy_from_slice(
+                        &data[(start - write_offset) as usize
+                            ..(end - write_offset) as usize],
+                    );
+            }
+        }
+        context.update(&chunk);
+    }
+    hex::encode(context.finish())
+}
+
+/// Builds a qcow2 image with 64 KiB clusters, whose only data is 4 KiB of
+/// 0x55 at guest offset 64 KiB
+fn qcow2_image(virtual_size: u64) -> Vec<u8> {
+    const CLUSTER: usize = 65536;
+    const COPIED: u64 = 1 << 63;
+
+    // header, L1 table, L2 table, and one data cluster
+    let mut image = vec![0; 4 * CLUSTER];
+    let l1_size = (virtual_size + (CLUSTER as u64) * 8192 - 1)
+        / ((CLUSTER as u64) * 8192);
+    image[0..4].copy_from_slice(b"QFI\xfb");
+    image[4..8].copy_from_slice(&2u32.to_be_bytes());
+    image[20..24].copy_from_slice(&16u32.to_be_bytes());
+    image[24..32].copy_from_slice(&virtual_size.to_be_bytes());
+    image[36..40].copy_from_slice(&(l1_size as u32).to_be_bytes());
+    image[40..48].copy_from_slice(&(CLUSTER as u64).to_be_bytes());
+
+    let l2_entry = (2 * CLUSTER as u64) | COPIED;
+    image[CLUSTER..CLUSTER + 8].copy_from_slice(&l2_entry.to_be_bytes());
+    let data_entry = (3 * CLUSTER as u64) | COPIED;
+    image[2 * CLUSTER + 8..2 * CLUSTER + 16]
+        .copy_from_slice(&data_entry.to_be_bytes());
+    image[3 * CLUSTER..3 * CLUSTER + 4096].fill(0x55);
+
+    image
+}
+
+/// The digest of the 1 GiB disk that `qcow2_image` converts to
+fn qcow2_image_converted_digest() -> String {
+    sparse_disk_digest(1024 * 1024 * 1024, &[(65536, &[0x55; 4096][..])])
+}
+
+/// Checks that the finalized disk has the given digest
+async fn validate_disk_digest(client: &ClientTestContext, digest: &str) {
+    export_start(client, StatusCode::NO_CONTENT).await;
+    assert_eq!(
+        export_digest(client, &get_disk_export_digest_url()).await,
+        digest
+    );
+    export_stop(client, StatusCode::NO_CONTENT).await;
+}
+
+/// Serves an image, honouring range requests
+#[endpoint(method = GET, path = "/image", unpublished = true)]
+async fn serve_image(
+    rqctx: RequestContext<Vec<u8>>,
+) -> Result<Response<Body>, HttpError> {
+    let image = rqctx.context();
+    let range = rqctx
+        .request
+        .headers()
+        .get(http::header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes="))
+        .and_then(|v| v.split_once('-'))
+        .map(|(start, end)| {
+            let start: usize = start.parse().unwrap();
+            let end: usize = end.parse().unwrap();
+            (start, std::cmp::min(end, image.len() - 1))
+        });
+
+    Ok(match range {
+        Some((start, end)) => Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                http::header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, image.len()),
+            )
+            .body(image[start..=end].to_vec().into())?,
+        None => Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_LENGTH, image.len())
+            .body(image.clone().into())?,
+    })
+}
+
+fn start_image_server(
+    cptestctx: &ControlPlaneTestContext,
+    image: Vec<u8>,
+) -> HttpServer<Vec<u8>> {
+    let mut api = ApiDescription::new();
+    api.register(serve_image).unwrap();
+    HttpServerStarter::new(
+        &Default::default(),
+        api,
+        image,
+        &cptestctx.logctx.log,
+    )
+    .unwrap()
+    .start()
+}
+
 async fn validate_disk_state(client: &ClientTestContext, state: DiskState) {
     let disk_url = get_disk_url(DISK_NAME);
     let disk = disk_get(&client, &disk_url).await;
@@ -373,6 +616,7 @@ async fn test_disk_create_for_importing(cptestctx: &ControlPlaneTestContext) {
             name: DISK_NAME.parse().unwrap(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::ImportingBlocks {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -885,3 +1129,294 @@ async fn test_cannot_finalize_attached_disk(
     // Validate that a user cannot finalize
     finalize_import(client, StatusCode::BAD_REQUEST).await;
 }
+
+// Test exporting a disk's blocks, both raw and as a qcow2 image
+#[nexus_test]
+async fn test_export_disk(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+
+    DiskTest::new(&cptestctx).await;
+    create_org_and_project(client).await;
+
+    // This is the smallest disk that can be created.
+    create_disk_with_state_importing_blocks(client).await;
+    const DISK_SIZE: u64 = 1024 * 1024 * 1024;
+
+    // Write some blocks into the first and second 512 MiB of the disk, so that
+    // the qcow2 image needs two L2 tables.
+    const SECOND_OFFSET: u64 = 512 * 1024 * 1024 + 65536;
+    let first_data = vec![0x55; 4096];
+    let second_data = vec![0xaa; 512];
+    bulk_write_start(client, StatusCode::NO_CONTENT).await;
+    bulk_write_bytes_manual(
+        client,
+        0,
+        first_data.clone(),
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+    bulk_write_bytes_manual(
+        client,
+        SECOND_OFFSET,
+        second_data.clone(),
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+    bulk_write_stop(client, StatusCode::NO_CONTENT).await;
+    finalize_import(client, StatusCode::NO_CONTENT).await;
+
+    // A disk can't be exported before an export is started
+    let export_url = get_disk_export_url();
+    let digest_url = get_disk_export_digest_url();
+    export(client, &export_url, "raw", StatusCode::BAD_REQUEST).await;
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::GET,
+        &digest_url,
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    export_start(client, StatusCode::NO_CONTENT).await;
+    validate_disk_state(client, DiskState::Exporting).await;
+
+    // An export can't be started twice
+    export_start(client, StatusCode::BAD_REQUEST).await;
+
+    // The digest is of the disk's contents
+    let expected_digest = sparse_disk_digest(
+        DISK_SIZE,
+        &[(0, &first_data[..]), (SECOND_OFFSET, &second_data[..])],
+    );
+    assert_eq!(export_digest(client, &digest_url).await, expected_digest);
+
+    // The raw image is the disk's contents, which the digest matches
+    let response = export(client, &export_url, "raw", StatusCode::OK).await;
+    assert_eq!(response.body.len() as u64, DISK_SIZE);
+    assert_eq!(
+        hex::encode(ring::digest::digest(
+            &ring::digest::SHA256,
+            &response.body
+        )),
+        expected_digest,
+    );
+    drop(response);
+
+    // The qcow2 image only holds the two clusters with data in them
+    let response = export(client, &export_url, "qcow2", StatusCode::OK).await;
+    let image = &response.body;
+    assert_eq!(&image[0..4], b"QFI\xfb");
+    assert_eq!(read_be_u64(image, 24), DISK_SIZE);
+
+    // header, L1 table, refcount table, refcount block, two data clusters,
+    // and two L2 tables
+    const CLUSTER: usize = 65536;
+    assert_eq!(image.len(), 8 * CLUSTER);
+
+    let cluster = qcow2_cluster(image, 0).unwrap();
+    assert_eq!(&cluster[..4096], &first_data[..]);
+    assert!(cluster[4096..].iter().all(|b| *b == 0));
+    assert!(qcow2_cluster(image, CLUSTER as u64).is_none());
+
+    let cluster = qcow2_cluster(image, SECOND_OFFSET).unwrap();
+    assert_eq!(&cluster[..512], &second_data[..]);
+    assert!(cluster[512..].iter().all(|b| *b == 0));
+    assert!(qcow2_cluster(image, SECOND_OFFSET - CLUSTER as u64).is_none());
+
+    // The disk can't be attached to an instance while being exported
+    create_instance_and_attach_disk(client, nexus, StatusCode::BAD_REQUEST)
+        .await;
+
+    export_stop(client, StatusCode::NO_CONTENT).await;
+    validate_disk_state(client, DiskState::Detached).await;
+
+    // The export is over
+    export(client, &export_url, "raw", StatusCode::BAD_REQUEST).await;
+    export_stop(client, StatusCode::BAD_REQUEST).await;
+
+    // Can now attach disk ok
+    attach_disk_to_instance(client).await;
+
+    // An attached disk can't be exported
+    export_start(client, StatusCode::BAD_REQUEST).await;
+}
+
+// Test exporting a snapshot's blocks
+#[nexus_test]
+async fn test_export_snapshot(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    DiskTest::new(&cptestctx).await;
+    create_org_and_project(client).await;
+
+    create_disk_with_state_importing_blocks(client).await;
+
+    const DATA_OFFSET: u64 = 3 * 65536;
+    let data = vec![0x5a; 1024];
+    bulk_write_start(client, StatusCode::NO_CONTENT).await;
+    bulk_write_bytes_manual(
+        client,
+        DATA_OFFSET,
+        data.clone(),
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+    bulk_write_stop(client, StatusCode::NO_CONTENT).await;
+    finalize_import_take_snapshot(client, StatusCode::NO_CONTENT).await;
+
+    // The snapshot holds what was written to the disk
+    let export_url =
+        format!("/v1/snapshots/a-snapshot/export?project={}", PROJECT_NAME);
+    let response = export(client, &export_url, "qcow2", StatusCode::OK).await;
+    let image = &response.body;
+    assert_eq!(&image[0..4], b"QFI\xfb");
+    assert_eq!(read_be_u64(image, 24), 1024 * 1024 * 1024);
+
+    // header, L1 table, refcount table, refcount block, one data cluster, and
+    // one L2 table
+    assert_eq!(image.len(), 6 * 65536);
+    let cluster = qcow2_cluster(image, DATA_OFFSET).unwrap();
+    assert_eq!(&cluster[..1024], &data[..]);
+    assert!(cluster[1024..].iter().all(|b| *b == 0));
+
+    let digest_url = format!(
+        "/v1/snapshots/a-snapshot/export-digest?project={}",
+        PROJECT_NAME
+    );
+    assert_eq!(
+        export_digest(client, &digest_url).await,
+        sparse_disk_digest(1024 * 1024 * 1024, &[(DATA_OFFSET, &data[..])]),
+    );
+
+    // Exporting a snapshot doesn't change the disk it was taken from
+    validate_disk_state(client, DiskState::Detached).await;
+}
+
+// Test importing a qcow2 image with bulk writes, which is converted to raw
+// blocks when the writes are stopped
+#[nexus_test]
+async fn test_import_qcow2_with_bulk_write(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+
+    DiskTest::new(&cptestctx).await;
+    create_org_and_project(client).await;
+
+    create_disk_with_state_importing_blocks(client).await;
+
+    bulk_write_start(client, StatusCode::NO_CONTENT).await;
+    bulk_write_bytes_manual(
+        client,
+        0,
+        qcow2_image(1024 * 1024 * 1024),
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+    bulk_write_stop(client, StatusCode::NO_CONTENT).await;
+    validate_disk_state(client, DiskState::ImportReady).await;
+
+    finalize_import(client, StatusCode::NO_CONTENT).await;
+    validate_disk_digest(client, &qcow2_image_converted_digest()).await;
+}
+
+// Test that an image too large for the disk is rejected when bulk writes are
+// stopped, leaving the disk ready for more writes
+#[nexus_test]
+async fn test_cannot_import_too_large_qcow2_with_bulk_write(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+
+    DiskTest::new(&cptestctx).await;
+    create_org_and_project(client).await;
+
+    create_disk_with_state_importing_blocks(client).await;
+
+    bulk_write_start(client, StatusCode::NO_CONTENT).await;
+    bulk_write_bytes_manual(
+        client,
+        0,
+        qcow2_image(2 * 1024 * 1024 * 1024),
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+    bulk_write_stop(client, StatusCode::BAD_REQUEST).await;
+    validate_disk_state(client, DiskState::ImportingFromBulkWrites).await;
+
+    // Writing a smaller image over the top works
+    bulk_write_bytes_manual(
+        client,
+        0,
+        qcow2_image(1024 * 1024 * 1024),
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+    bulk_write_stop(client, StatusCode::NO_CONTENT).await;
+    validate_disk_state(client, DiskState::ImportReady).await;
+}
+
+// Test importing a qcow2 image from a URL, which is converted to raw blocks
+// as it's imported
+#[nexus_test]
+async fn test_import_qcow2_from_url(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    DiskTest::new(&cptestctx).await;
+    create_org_and_project(client).await;
+
+    create_disk_with_state_importing_blocks(client).await;
+
+    let image = qcow2_image(1024 * 1024 * 1024);
+    let image_digest =
+        hex::encode(ring::digest::digest(&ring::digest::SHA256, &image));
+    let server = start_image_server(cptestctx, image);
+    let url = format!("http://{}/image", server.local_addr());
+
+    // Converted images can't be checked against a digest
+    import_blocks_from_url_manual(
+        client,
+        &url,
+        Some(params::ExpectedDigest::Sha256(image_digest)),
+        StatusCode::BAD_REQUEST,
+    )
+    .await;
+    validate_disk_state(client, DiskState::ImportReady).await;
+
+    import_blocks_from_url_manual(client, &url, None, StatusCode::NO_CONTENT)
+        .await;
+    validate_disk_state(client, DiskState::ImportReady).await;
+
+    finalize_import(client, StatusCode::NO_CONTENT).await;
+    validate_disk_digest(client, &qcow2_image_converted_digest()).await;
+
+    server.close().await.unwrap();
+}
+
+// Test that a qcow2 image from a URL that's too large for the disk is
+// rejected, and the disk left ready for another import
+#[nexus_test]
+async fn test_cannot_import_too_large_qcow2_from_url(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+
+    DiskTest::new(&cptestctx).await;
+    create_org_and_project(client).await;
+
+    create_disk_with_state_importing_blocks(client).await;
+
+    let server =
+        start_image_server(cptestctx, qcow2_image(2 * 1024 * 1024 * 1024));
+    let url = format!("http://{}/image", server.local_addr());
+
+    import_blocks_from_url_manual(client, &url, None, StatusCode::BAD_REQUEST)
+        .await;
+    validate_disk_state(client, DiskState::ImportReady).await;
+
+    server.close().await.unwrap();
+}
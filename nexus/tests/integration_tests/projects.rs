@@ -19,6 +19,7 @@ use omicron_common::api::external::IdentityMetadataCreateParams;
 use omicron_common::api::external::Instance;
 use omicron_common::api::external::InstanceCpuCount;
 use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::shared;
 use omicron_nexus::external_api::views;
 use omicron_nexus::external_api::views::Project;
 
@@ -158,6 +159,7 @@ async fn test_project_deletion_with_instance(
                 name: "my-instance".parse().unwrap(),
                 description: "description".to_string(),
             },
+            labels: Default::default(),
             ncpus: InstanceCpuCount(4),
             memory: ByteCount::from_gibibytes_u32(1),
             hostname: String::from("the_host"),
@@ -166,6 +168,8 @@ async fn test_project_deletion_with_instance(
                 params::InstanceNetworkInterfaceAttachment::None,
             external_ips: vec![],
             disks: vec![],
+            affinity_groups: Vec::new(),
+            auto_restart_policy: Default::default(),
             start: false,
         },
     )
@@ -215,6 +219,49 @@ async fn test_project_deletion_with_disk(cptestctx: &ControlPlaneTestContext) {
     delete_project(&url, &client).await;
 }
 
+#[nexus_test]
+async fn test_project_deletion_with_affinity_group(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+
+    // Create a project that we'll use for testing.
+    let name = "springfield-squidport";
+    let url = format!("/v1/projects/{}", name);
+
+    create_project(&client, &name).await;
+    delete_project_default_subnet(&name, &client).await;
+    delete_project_default_vpc(&name, &client).await;
+
+    let _: views::AffinityGroup = object_create(
+        client,
+        &format!("/v1/affinity-groups?project={}", name),
+        &params::AffinityGroupCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "my-group".parse().unwrap(),
+                description: String::from("description"),
+            },
+            kind: shared::AffinityGroupKind::AntiAffinity,
+            policy: shared::AffinityPolicy::Fail,
+        },
+    )
+    .await;
+    assert_eq!(
+        "project to be deleted contains an affinity group: my-group",
+        delete_project_expect_fail(&url, &client).await,
+    );
+
+    NexusRequest::object_delete(
+        client,
+        &format!("/v1/affinity-groups/my-group?project={}", name),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .expect("failed to delete affinity group");
+    delete_project(&url, &client).await;
+}
+
 #[nexus_test]
 async fn test_project_deletion_with_image(cptestctx: &ControlPlaneTestContext) {
     let client = &cptestctx.external_client;
@@ -234,6 +281,7 @@ async fn test_project_deletion_with_image(cptestctx: &ControlPlaneTestContext) {
                 "you can boot any image, as long as it's alpine",
             ),
         },
+        labels: Default::default(),
         os: "alpine".to_string(),
         version: "edge".to_string(),
         block_size: params::BlockSize::try_from(512).unwrap(),
@@ -305,6 +353,7 @@ async fn test_project_deletion_with_snapshot(
                 name: "my-snapshot".parse().unwrap(),
                 description: "not attached to instance".into(),
             },
+            labels: Default::default(),
             disk: "my-disk".parse().unwrap(),
         },
     )
@@ -0,0 +1,290 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integration tests for silo and project quotas
+
+use dropshot::test_util::ClientTestContext;
+use dropshot::HttpErrorResponseBody;
+use http::method::Method;
+use http::StatusCode;
+use nexus_db_queries::context::OpContext;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::resource_helpers::create_disk;
+use nexus_test_utils::resource_helpers::create_instance;
+use nexus_test_utils::resource_helpers::create_project;
+use nexus_test_utils::resource_helpers::object_put;
+use nexus_test_utils::resource_helpers::populate_ip_pool;
+use nexus_test_utils::resource_helpers::DiskTest;
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::ByteCount;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::InstanceCpuCount;
+use omicron_nexus::db::fixed_data::silo::DEFAULT_SILO;
+use omicron_nexus::db::identity::Resource;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::views::ProjectQuotas;
+use omicron_nexus::external_api::views::SiloQuotas;
+use omicron_nexus::TestInterfaces as _;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const PROJECT_NAME: &str = "quota-project";
+
+fn get_silo_quotas_url() -> String {
+    format!("/v1/system/silos/{}/quotas", DEFAULT_SILO.identity().name)
+}
+
+fn get_project_quotas_url() -> String {
+    format!("/v1/projects/{}/quotas", PROJECT_NAME)
+}
+
+async fn get_silo_quotas(client: &ClientTestContext) -> SiloQuotas {
+    NexusRequest::object_get(client, &get_silo_quotas_url())
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap()
+        .parsed_body()
+        .unwrap()
+}
+
+async fn get_project_quotas(client: &ClientTestContext) -> ProjectQuotas {
+    NexusRequest::object_get(client, &get_project_quotas_url())
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap()
+        .parsed_body()
+        .unwrap()
+}
+
+async fn set_silo_quotas(
+    client: &ClientTestContext,
+    quotas: params::QuotasUpdate,
+) -> SiloQuotas {
+    object_put(client, &get_silo_quotas_url(), &quotas).await
+}
+
+async fn set_project_quotas(
+    client: &ClientTestContext,
+    quotas: params::QuotasUpdate,
+) -> ProjectQuotas {
+    object_put(client, &get_project_quotas_url(), &quotas).await
+}
+
+/// Attempts to create an instance which is expected to exceed a quota,
+/// returning the error message.
+async fn create_instance_expect_quota_error(
+    client: &ClientTestContext,
+    instance_name: &str,
+) -> String {
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/instances?project={}", PROJECT_NAME),
+        )
+        .body(Some(&params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: instance_name.parse().unwrap(),
+                description: format!("instance {:?}", instance_name),
+            },
+            labels: Default::default(),
+            ncpus: InstanceCpuCount(4),
+            memory: ByteCount::from_gibibytes_u32(1),
+            hostname: String::from("the_host"),
+            user_data: vec![],
+            network_interfaces:
+                params::InstanceNetworkInterfaceAttachment::Default,
+            external_ips: vec![],
+            disks: vec![],
+            affinity_groups: vec![],
+            auto_restart_policy: Default::default(),
+            start: true,
+        }))
+        .expect_status(Some(StatusCode::INSUFFICIENT_STORAGE)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(error.error_code, Some(String::from("InsufficientCapacity")));
+    error.message
+}
+
+#[nexus_test]
+async fn test_quotas_crud(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    create_project(client, PROJECT_NAME).await;
+
+    // Neither the silo nor the project have any quotas to start with.
+    let silo_quotas = get_silo_quotas(client).await;
+    assert_eq!(silo_quotas.silo_id, DEFAULT_SILO.id());
+    assert_eq!(silo_quotas.cpus, None);
+    assert_eq!(silo_quotas.memory, None);
+    assert_eq!(silo_quotas.storage, None);
+    let project_quotas = get_project_quotas(client).await;
+    assert_eq!(project_quotas.cpus, None);
+    assert_eq!(project_quotas.memory, None);
+    assert_eq!(project_quotas.storage, None);
+
+    // Set some quotas and read them back.
+    let quotas = params::QuotasUpdate {
+        cpus: Some(32),
+        memory: Some(ByteCount::from_gibibytes_u32(128)),
+        storage: None,
+    };
+    let updated = set_silo_quotas(client, quotas.clone()).await;
+    assert_eq!(updated.cpus, Some(32));
+    assert_eq!(updated.memory, Some(ByteCount::from_gibibytes_u32(128)));
+    assert_eq!(updated.storage, None);
+    assert_eq!(get_silo_quotas(client).await, updated);
+
+    let updated = set_project_quotas(client, quotas).await;
+    assert_eq!(updated.project_id, project_quotas.project_id);
+    assert_eq!(updated.cpus, Some(32));
+    assert_eq!(get_project_quotas(client).await, updated);
+
+    // Setting quotas replaces all of them.
+    let updated = set_silo_quotas(
+        client,
+        params::QuotasUpdate {
+            storage: Some(ByteCount::from_gibibytes_u32(10)),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert_eq!(updated.cpus, None);
+    assert_eq!(updated.memory, None);
+    assert_eq!(updated.storage, Some(ByteCount::from_gibibytes_u32(10)));
+
+    // Quotas can't be negative.
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::PUT, &get_project_quotas_url())
+            .body(Some(&params::QuotasUpdate {
+                cpus: Some(-1),
+                ..Default::default()
+            }))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "unsupported value for \"cpus\": quota must not be negative"
+    );
+}
+
+#[nexus_test]
+async fn test_instance_quotas(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    let datastore = nexus.datastore();
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    populate_ip_pool(client, "default", None).await;
+    let project_id = create_project(client, PROJECT_NAME).await.identity.id;
+
+    // Each instance uses 4 CPUs and 1 GiB of memory, so only one fits in the
+    // project's quota.
+    set_project_quotas(
+        client,
+        params::QuotasUpdate { cpus: Some(6), ..Default::default() },
+    )
+    .await;
+    create_instance(client, PROJECT_NAME, "inst1").await;
+    assert_eq!(
+        create_instance_expect_quota_error(client, "inst2").await,
+        "Not enough CPUs remaining in the project quota"
+    );
+
+    // Nothing was provisioned by the failed attempt.
+    let usage = datastore
+        .virtual_provisioning_collection_get(&opctx, project_id)
+        .await
+        .unwrap();
+    assert_eq!(usage.cpus_provisioned, 4);
+
+    // Raising the quota lets the instance be created.
+    set_project_quotas(
+        client,
+        params::QuotasUpdate { cpus: Some(8), ..Default::default() },
+    )
+    .await;
+    create_instance(client, PROJECT_NAME, "inst2").await;
+
+    // The silo's quotas are enforced too, even when they're already below
+    // the silo's usage.
+    set_silo_quotas(
+        client,
+        params::QuotasUpdate {
+            memory: Some(ByteCount::from_gibibytes_u32(1)),
+            ..Default::default()
+        },
+    )
+    .await;
+    set_project_quotas(client, params::QuotasUpdate::default()).await;
+    assert_eq!(
+        create_instance_expect_quota_error(client, "inst3").await,
+        "Not enough memory remaining in the silo quota"
+    );
+
+    // Removing the quota lifts the limit.
+    set_silo_quotas(client, params::QuotasUpdate::default()).await;
+    create_instance(client, PROJECT_NAME, "inst3").await;
+}
+
+#[nexus_test]
+async fn test_storage_quotas(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    DiskTest::new(&cptestctx).await;
+    create_project(client, PROJECT_NAME).await;
+
+    set_silo_quotas(
+        client,
+        params::QuotasUpdate {
+            storage: Some(ByteCount::from_gibibytes_u32(1)),
+            ..Default::default()
+        },
+    )
+    .await;
+    create_disk(client, PROJECT_NAME, "disk1").await;
+
+    let error: HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/disks?project={}", PROJECT_NAME),
+        )
+        .body(Some(&params::DiskCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "disk2".parse().unwrap(),
+                description: String::from("one disk too many"),
+            },
+            labels: Default::default(),
+            disk_source: params::DiskSource::Blank {
+                block_size: params::BlockSize::try_from(512).unwrap(),
+            },
+            size: ByteCount::from_gibibytes_u32(1),
+        }))
+        .expect_status(Some(StatusCode::INSUFFICIENT_STORAGE)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(error.message, "Not enough storage remaining in the silo quota");
+}
@@ -2,23 +2,30 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::integration_tests::instances::instance_post;
+use crate::integration_tests::instances::instance_simulate;
+use crate::integration_tests::instances::InstanceOp;
 use dropshot::Method;
 use http::StatusCode;
 use nexus_test_utils::http_testing::AuthnMode;
 use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
 use nexus_test_utils::identity_eq;
 use nexus_test_utils::resource_helpers::objects_list_page_authz;
 use nexus_test_utils_macros::nexus_test;
 use omicron_common::api::external::{
-    IdentityMetadataCreateParams, IdentityMetadataUpdateParams,
-    RouteDestination, RouteTarget, RouterRoute, RouterRouteKind,
+    IdentityMetadataCreateParams, IdentityMetadataUpdateParams, IpNet,
+    NameOrId, RouteDestination, RouteTarget, RouterRoute, RouterRouteKind,
 };
+use omicron_common::api::internal::shared::{ResolvedVpcRoute, RouterTarget};
 use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::views::{Vpc, VpcSubnet};
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 
 use nexus_test_utils::resource_helpers::{
-    create_project, create_router, create_vpc,
+    create_instance, create_project, create_router, create_vpc,
+    populate_ip_pool,
 };
 
 type ControlPlaneTestContext =
@@ -189,3 +196,243 @@ async fn test_router_routes(cptestctx: &ControlPlaneTestContext) {
     .await
     .unwrap();
 }
+
+/// Test that the routes of a subnet's custom router are sent to the sleds
+/// hosting instances in its VPC, and re-sent as they change, and that routes
+/// are only removed or changed while the instances are stopped
+#[nexus_test]
+async fn test_router_routes_sent_to_sleds(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let apictx = &cptestctx.server.apictx();
+    let nexus = &apictx.nexus;
+    let sled_agent = &cptestctx.sled_agent.sled_agent;
+
+    let project_name = "springfield-squidport";
+    let vpc_name = "default";
+    let router_name = "router1";
+    let route_name = "custom-route";
+    let routes_url = format!(
+        "/v1/vpc-router-routes?project={}&vpc={}&router={}",
+        project_name, vpc_name, router_name
+    );
+    let route_url = format!(
+        "/v1/vpc-router-routes/{}?project={}&vpc={}&router={}",
+        route_name, project_name, vpc_name, router_name
+    );
+    let subnet_url = format!(
+        "/v1/vpc-subnets/default?project={}&vpc={}",
+        project_name, vpc_name
+    );
+
+    populate_ip_pool(&client, "default", None).await;
+    create_project(&client, project_name).await;
+    let vpc: Vpc = NexusRequest::object_get(
+        client,
+        &format!("/v1/vpcs/{}?project={}", vpc_name, project_name),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    let router =
+        create_router(&client, project_name, vpc_name, router_name).await;
+
+    let custom_route = |target: Ipv4Addr| ResolvedVpcRoute {
+        dest: "10.0.0.0/8".parse::<ipnetwork::IpNetwork>().unwrap().into(),
+        target: RouterTarget::Ip(IpAddr::from(target)),
+    };
+    let route_params = |target: Ipv4Addr| params::RouterRouteCreate {
+        identity: IdentityMetadataCreateParams {
+            name: route_name.parse().unwrap(),
+            description: "route to another network".to_string(),
+        },
+        target: RouteTarget::Ip(IpAddr::from(target)),
+        destination: RouteDestination::IpNet(
+            "10.0.0.0/8".parse::<ipnetwork::IpNetwork>().unwrap().into(),
+        ),
+    };
+    let _: RouterRoute = NexusRequest::objects_post(
+        client,
+        &routes_url,
+        &route_params(Ipv4Addr::new(172, 30, 0, 10)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+
+    // Only custom routers can be attached to a subnet.
+    let attach = |router: &str| params::VpcSubnetUpdate {
+        identity: IdentityMetadataUpdateParams {
+            name: None,
+            description: None,
+        },
+        custom_router: Some(NameOrId::Name(router.parse().unwrap())),
+    };
+    let error: dropshot::HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::PUT, &subnet_url)
+            .body(Some(&attach("system")))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "a VPC Subnet's custom router must be of kind \"custom\""
+    );
+
+    let subnet: VpcSubnet = NexusRequest::object_put(
+        client,
+        &subnet_url,
+        Some(&attach(router_name)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(subnet.custom_router_id, Some(router.identity.id));
+
+    // Starting an instance in the VPC sends the subnet's route table to its
+    // sled, with the custom route alongside the system router's default
+    // route and the route to the subnet itself.
+    let instance = create_instance(client, project_name, "inst").await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    let subnet_routes = || async {
+        let vpc_routes = sled_agent.vpc_routes.lock().await;
+        let body = vpc_routes.get(&vpc.identity.id).expect("no VPC routes");
+        body.subnets
+            .iter()
+            .find(|table| table.ipv4_block == subnet.ipv4_block)
+            .expect("no routes for subnet")
+            .routes
+            .clone()
+    };
+    let routes = subnet_routes().await;
+    let ipv4_block = IpNet::from(subnet.ipv4_block);
+    assert!(routes.contains(&custom_route(Ipv4Addr::new(172, 30, 0, 10))));
+    assert!(routes.contains(&ResolvedVpcRoute {
+        dest: ipv4_block,
+        target: RouterTarget::VpcSubnet(ipv4_block),
+    }));
+    assert!(routes.contains(&ResolvedVpcRoute {
+        dest: "0.0.0.0/0".parse::<ipnetwork::IpNetwork>().unwrap().into(),
+        target: RouterTarget::InternetGateway,
+    }));
+
+    // While the instance is running, routes can be added, but not changed,
+    // nor removed by detaching the router from the subnet.
+    let mut other_route = route_params(Ipv4Addr::new(172, 30, 0, 10));
+    other_route.identity.name = "other-route".parse().unwrap();
+    other_route.destination = RouteDestination::IpNet(
+        "192.168.0.0/16".parse::<ipnetwork::IpNetwork>().unwrap().into(),
+    );
+    let _: RouterRoute =
+        NexusRequest::objects_post(client, &routes_url, &other_route)
+            .authn_as(AuthnMode::PrivilegedUser)
+            .execute()
+            .await
+            .unwrap()
+            .parsed_body()
+            .unwrap();
+    assert!(subnet_routes().await.contains(&ResolvedVpcRoute {
+        dest: "192.168.0.0/16".parse::<ipnetwork::IpNetwork>().unwrap().into(),
+        target: RouterTarget::Ip(IpAddr::from(Ipv4Addr::new(172, 30, 0, 10))),
+    }));
+
+    let route_update = params::RouterRouteUpdate {
+        identity: IdentityMetadataUpdateParams {
+            name: None,
+            description: None,
+        },
+        target: RouteTarget::Ip(IpAddr::from(Ipv4Addr::new(172, 30, 0, 11))),
+        destination: route_params(Ipv4Addr::new(172, 30, 0, 11)).destination,
+    };
+    let running_error = "routes can't be removed or changed while instances \
+        in the VPC are running; stop the instances first";
+    let error: dropshot::HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::PUT, &route_url)
+            .body(Some(&route_update))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(error.message, running_error);
+    let error: dropshot::HttpErrorResponseBody = NexusRequest::new(
+        RequestBuilder::new(client, Method::PUT, &subnet_url)
+            .body(Some(&params::VpcSubnetUpdate {
+                identity: IdentityMetadataUpdateParams {
+                    name: None,
+                    description: None,
+                },
+                custom_router: None,
+            }))
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(error.message, running_error);
+    assert!(subnet_routes()
+        .await
+        .contains(&custom_route(Ipv4Addr::new(172, 30, 0, 10))));
+
+    // Once it's stopped, updating the route re-sends the table.
+    instance_post(client, "inst", InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance.identity.id).await;
+    NexusRequest::object_put(client, &route_url, Some(&route_update))
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap();
+    let routes = subnet_routes().await;
+    assert!(!routes.contains(&custom_route(Ipv4Addr::new(172, 30, 0, 10))));
+    assert!(routes.contains(&custom_route(Ipv4Addr::new(172, 30, 0, 11))));
+
+    // The router can't be deleted while the subnet uses it.
+    let router_url = format!(
+        "/v1/vpc-routers/{}?project={}&vpc={}",
+        router_name, project_name, vpc_name
+    );
+    let error: dropshot::HttpErrorResponseBody = NexusRequest::expect_failure(
+        client,
+        StatusCode::BAD_REQUEST,
+        Method::DELETE,
+        &router_url,
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(
+        error.message,
+        "VPC Router cannot be deleted while VPC Subnets use it"
+    );
+
+    // Deleting the route re-sends the table without it.
+    NexusRequest::object_delete(client, &route_url)
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap();
+    let routes = subnet_routes().await;
+    assert!(!routes.contains(&custom_route(Ipv4Addr::new(172, 30, 0, 11))));
+}
@@ -121,6 +121,7 @@ async fn test_create_a_saml_idp(cptestctx: &ControlPlaneTestContext) {
         IdentityProviderType::Saml(_) => {
             // ok
         }
+        IdentityProviderType::Oidc(_) => panic!("expected a SAML provider"),
     }
 
     // Expect the SSO redirect when trying to log in unauthenticated
@@ -0,0 +1,525 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Tests for SCIM 2.0 provisioning of Silo users and groups
+
+use dropshot::test_util::ClientTestContext;
+use dropshot::ResultsPage;
+use http::{header, method::Method, StatusCode};
+use nexus_test_utils::http_testing::{AuthnMode, NexusRequest, RequestBuilder};
+use nexus_test_utils::resource_helpers::{
+    create_silo, object_create, objects_list_page_authz,
+};
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_nexus::external_api::{params, shared, views};
+use serde_json::json;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const SILO_NAME: &str = "scim-silo";
+
+/// Creates a Silo whose users are provisioned via the API, plus a SCIM client
+/// token for it, returning the bearer token
+async fn create_scim_silo(client: &ClientTestContext) -> String {
+    create_silo(client, SILO_NAME, true, shared::SiloIdentityMode::LocalOnly)
+        .await;
+    let token: views::ScimClientTokenCreated = object_create(
+        client,
+        &format!("/v1/system/scim/tokens?silo={}", SILO_NAME),
+        &params::ScimClientTokenCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "directory".parse().unwrap(),
+                description: "the enterprise directory".to_string(),
+            },
+            time_expires: None,
+        },
+    )
+    .await;
+    token.bearer_token
+}
+
+/// Makes a SCIM request, returning the parsed response body (if any)
+async fn scim_request(
+    client: &ClientTestContext,
+    bearer_token: &str,
+    method: Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+    expected_status: StatusCode,
+) -> serde_json::Value {
+    let response = RequestBuilder::new(client, method, path)
+        .header(header::AUTHORIZATION, format!("Bearer {}", bearer_token))
+        .body(body.as_ref())
+        .expect_status(Some(expected_status))
+        .allow_non_dropshot_errors()
+        .execute()
+        .await
+        .unwrap();
+    if expected_status == StatusCode::NO_CONTENT {
+        return serde_json::Value::Null;
+    }
+    assert_eq!(
+        response.headers.get(header::CONTENT_TYPE).unwrap(),
+        "application/scim+json"
+    );
+    response.parsed_body().unwrap()
+}
+
+#[nexus_test]
+async fn test_scim_client_tokens(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let tokens_url = format!("/v1/system/scim/tokens?silo={}", SILO_NAME);
+
+    // Tokens can't be created for Silos whose users are provisioned some
+    // other way.
+    create_silo(client, "jit-silo", true, shared::SiloIdentityMode::SamlJit)
+        .await;
+    let token_create = params::ScimClientTokenCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "directory".parse().unwrap(),
+            description: "the enterprise directory".to_string(),
+        },
+        time_expires: None,
+    };
+    let error: dropshot::HttpErrorResponseBody =
+        NexusRequest::expect_failure_with_body(
+            client,
+            StatusCode::BAD_REQUEST,
+            Method::POST,
+            "/v1/system/scim/tokens?silo=jit-silo",
+            &token_create,
+        )
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap()
+        .parsed_body()
+        .unwrap();
+    assert_eq!(
+        error.message,
+        "SCIM provisioning is only supported in Silos whose users are \
+        provisioned via the API"
+    );
+
+    let bearer_token = create_scim_silo(client).await;
+    assert!(bearer_token.starts_with("oxide-scim-"));
+    let tokens =
+        objects_list_page_authz::<views::ScimClientToken>(client, &tokens_url)
+            .await
+            .items;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].identity.name, "directory");
+
+    // Ordinary Silo users can't see the tokens.
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::NOT_FOUND,
+        Method::GET,
+        &tokens_url,
+    )
+    .authn_as(AuthnMode::UnprivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // The token works until it's deleted.
+    scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        "/scim/v2/Users",
+        None,
+        StatusCode::OK,
+    )
+    .await;
+    NexusRequest::object_delete(
+        client,
+        &format!("/v1/system/scim/tokens/directory?silo={}", SILO_NAME),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    let error = scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        "/scim/v2/Users",
+        None,
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+    assert_eq!(error["status"], "401");
+    assert_eq!(
+        error["schemas"],
+        json!(["urn:ietf:params:scim:api:messages:2.0:Error"])
+    );
+    let tokens: ResultsPage<views::ScimClientToken> =
+        objects_list_page_authz(client, &tokens_url).await;
+    assert!(tokens.items.is_empty());
+
+    // Tokens for other schemes aren't accepted either.
+    scim_request(
+        client,
+        "oxide-token-abc123",
+        Method::GET,
+        "/scim/v2/Users",
+        None,
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+}
+
+#[nexus_test]
+async fn test_scim_users(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let bearer_token = create_scim_silo(client).await;
+
+    let user = scim_request(
+        client,
+        &bearer_token,
+        Method::POST,
+        "/scim/v2/Users",
+        Some(json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+            "userName": "alice",
+            "name": { "givenName": "Alice" },
+            "active": true,
+            "password": "oxide-scim-test",
+        })),
+        StatusCode::CREATED,
+    )
+    .await;
+    assert_eq!(user["userName"], "alice");
+    assert_eq!(user["active"], true);
+    let user_id = user["id"].as_str().unwrap().to_string();
+    let user_url = format!("/scim/v2/Users/{}", user_id);
+
+    // The user shows up as a user of the Silo, and can log in.
+    let login_url = format!("/login/{}/local", SILO_NAME);
+    let credentials = params::UsernamePasswordCredentials {
+        username: "alice".parse().unwrap(),
+        password: "oxide-scim-test".parse().unwrap(),
+    };
+    RequestBuilder::new(client, Method::POST, &login_url)
+        .body(Some(&credentials))
+        .expect_status(Some(StatusCode::SEE_OTHER))
+        .execute()
+        .await
+        .unwrap();
+
+    // Creating the same user again is a conflict.
+    let error = scim_request(
+        client,
+        &bearer_token,
+        Method::POST,
+        "/scim/v2/Users",
+        Some(json!({ "userName": "alice" })),
+        StatusCode::CONFLICT,
+    )
+    .await;
+    assert_eq!(error["scimType"], "uniqueness");
+
+    // Clients find users by filtering on their user name.
+    scim_request(
+        client,
+        &bearer_token,
+        Method::POST,
+        "/scim/v2/Users",
+        Some(json!({ "userName": "bob" })),
+        StatusCode::CREATED,
+    )
+    .await;
+    let list = scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        "/scim/v2/Users?filter=userName%20eq%20%22alice%22",
+        None,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(list["totalResults"], 1);
+    assert_eq!(list["Resources"][0]["id"], user_id.as_str());
+    let list = scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        "/scim/v2/Users?startIndex=2&count=1",
+        None,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(list["totalResults"], 2);
+    assert_eq!(list["startIndex"], 2);
+    assert_eq!(list["itemsPerPage"], 1);
+    scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        "/scim/v2/Users?filter=emails%20co%20%22example%22",
+        None,
+        StatusCode::BAD_REQUEST,
+    )
+    .await;
+
+    // Deactivating the user keeps them from logging in.
+    let user = scim_request(
+        client,
+        &bearer_token,
+        Method::PATCH,
+        &user_url,
+        Some(json!({
+            "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+            "Operations": [{ "op": "Replace", "path": "active", "value": "False" }],
+        })),
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(user["active"], false);
+    NexusRequest::expect_failure_with_body(
+        client,
+        StatusCode::UNAUTHORIZED,
+        Method::POST,
+        &login_url,
+        &credentials,
+    )
+    .execute()
+    .await
+    .unwrap();
+
+    // Replacing the user without `active` reactivates them.
+    let user = scim_request(
+        client,
+        &bearer_token,
+        Method::PUT,
+        &user_url,
+        Some(json!({ "userName": "alice.smith" })),
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(user["userName"], "alice.smith");
+    assert_eq!(user["active"], true);
+
+    scim_request(
+        client,
+        &bearer_token,
+        Method::DELETE,
+        &user_url,
+        None,
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+    scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        &user_url,
+        None,
+        StatusCode::NOT_FOUND,
+    )
+    .await;
+
+    // Changes made by the client are recorded in the audit log under its
+    // Silo, whether or not they succeeded.  Reads aren't.
+    let silo: views::Silo = NexusRequest::object_get(
+        client,
+        &format!("/v1/system/silos/{}", SILO_NAME),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    let entries = NexusRequest::iter_collection_authn::<views::AuditLogEntry>(
+        client,
+        "/v1/system/audit-log",
+        "",
+        None,
+    )
+    .await
+    .unwrap()
+    .all_items;
+    let scim_entries: Vec<_> = entries
+        .iter()
+        .filter(|e| e.request_uri.starts_with("/scim/"))
+        .map(|e| (e.http_method.as_str(), e.result_code))
+        .collect();
+    assert_eq!(
+        scim_entries,
+        vec![
+            ("POST", Some(StatusCode::CREATED.as_u16())),
+            ("POST", Some(StatusCode::CONFLICT.as_u16())),
+            ("POST", Some(StatusCode::CREATED.as_u16())),
+            ("PATCH", Some(StatusCode::OK.as_u16())),
+            ("PUT", Some(StatusCode::OK.as_u16())),
+            ("DELETE", Some(StatusCode::NO_CONTENT.as_u16())),
+        ]
+    );
+    for entry in entries.iter().filter(|e| e.request_uri.starts_with("/scim/"))
+    {
+        assert_eq!(entry.actor_id, None);
+        assert_eq!(entry.actor_silo_id, Some(silo.identity.id));
+        assert_eq!(entry.chain_id, silo.identity.id);
+    }
+}
+
+#[nexus_test]
+async fn test_scim_groups(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let bearer_token = create_scim_silo(client).await;
+
+    let mut user_ids = Vec::new();
+    for user_name in ["alice", "bob", "carol"] {
+        let user = scim_request(
+            client,
+            &bearer_token,
+            Method::POST,
+            "/scim/v2/Users",
+            Some(json!({ "userName": user_name })),
+            StatusCode::CREATED,
+        )
+        .await;
+        user_ids.push(user["id"].as_str().unwrap().to_string());
+    }
+    let member_ids = |group: &serde_json::Value| {
+        let mut ids: Vec<String> = group["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|member| member["value"].as_str().unwrap().to_string())
+            .collect();
+        ids.sort();
+        ids
+    };
+    let sorted = |mut ids: Vec<String>| {
+        ids.sort();
+        ids
+    };
+
+    let group = scim_request(
+        client,
+        &bearer_token,
+        Method::POST,
+        "/scim/v2/Groups",
+        Some(json!({
+            "displayName": "engineering",
+            "members": [{ "value": user_ids[0] }, { "value": user_ids[1] }],
+        })),
+        StatusCode::CREATED,
+    )
+    .await;
+    assert_eq!(group["displayName"], "engineering");
+    assert_eq!(
+        member_ids(&group),
+        sorted(vec![user_ids[0].clone(), user_ids[1].clone()])
+    );
+    let group_url =
+        format!("/scim/v2/Groups/{}", group["id"].as_str().unwrap());
+
+    // Users report the groups they're in.
+    let user = scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        &format!("/scim/v2/Users/{}", user_ids[0]),
+        None,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(user["groups"][0]["value"], group["id"]);
+
+    // Groups can't contain users from other Silos (or that don't exist).
+    scim_request(
+        client,
+        &bearer_token,
+        Method::POST,
+        "/scim/v2/Groups",
+        Some(json!({
+            "displayName": "bogus",
+            "members": [{ "value": "7f6eab4d-9ad1-4a7a-9ad0-e2d0d2f29aef" }],
+        })),
+        StatusCode::BAD_REQUEST,
+    )
+    .await;
+
+    // Add and remove members, and rename the group, all at once.
+    let group = scim_request(
+        client,
+        &bearer_token,
+        Method::PATCH,
+        &group_url,
+        Some(json!({
+            "Operations": [
+                {
+                    "op": "add",
+                    "path": "members",
+                    "value": [{ "value": user_ids[2] }],
+                },
+                {
+                    "op": "remove",
+                    "path": format!("members[value eq \"{}\"]", user_ids[0]),
+                },
+                { "op": "replace", "value": { "displayName": "eng" } },
+            ],
+        })),
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(group["displayName"], "eng");
+    assert_eq!(
+        member_ids(&group),
+        sorted(vec![user_ids[1].clone(), user_ids[2].clone()])
+    );
+
+    // Replacing the group replaces its members.
+    let group = scim_request(
+        client,
+        &bearer_token,
+        Method::PUT,
+        &group_url,
+        Some(json!({
+            "displayName": "eng",
+            "members": [{ "value": user_ids[0] }],
+        })),
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(member_ids(&group), vec![user_ids[0].clone()]);
+
+    let list = scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        "/scim/v2/Groups?filter=displayName%20eq%20%22eng%22",
+        None,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(list["totalResults"], 1);
+    assert_eq!(list["Resources"][0]["id"], group["id"]);
+
+    scim_request(
+        client,
+        &bearer_token,
+        Method::DELETE,
+        &group_url,
+        None,
+        StatusCode::NO_CONTENT,
+    )
+    .await;
+    let user = scim_request(
+        client,
+        &bearer_token,
+        Method::GET,
+        &format!("/scim/v2/Users/{}", user_ids[0]),
+        None,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(user["groups"], json!([]));
+}
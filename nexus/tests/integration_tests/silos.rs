@@ -185,6 +185,7 @@ async fn test_silos(cptestctx: &ControlPlaneTestContext) {
                 name: project_name.parse().unwrap(),
                 description: String::new(),
             },
+            labels: Default::default(),
         },
     )
     .authn_as(AuthnMode::SiloUser(new_silo_user_id))
@@ -343,6 +344,7 @@ async fn test_silo_admin_group(cptestctx: &ControlPlaneTestContext) {
                 name: "myproj".parse().unwrap(),
                 description: "some proj".into(),
             },
+            labels: Default::default(),
         },
     )
     .authn_as(AuthnMode::SiloUser(admin_group_user.id()))
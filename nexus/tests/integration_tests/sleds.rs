@@ -4,21 +4,49 @@
 
 //! Tests for APIs against sled-based endpoints.
 
+use crate::integration_tests::instances::instance_post;
+use crate::integration_tests::instances::instance_simulate;
+use crate::integration_tests::instances::InstanceOp;
 use camino::Utf8Path;
 use dropshot::test_util::ClientTestContext;
+use http::Method;
+use http::StatusCode;
+use nexus_db_queries::context::OpContext;
 use nexus_test_interface::NexusServer;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::resource_helpers::create_instance;
 use nexus_test_utils::resource_helpers::create_physical_disk;
+use nexus_test_utils::resource_helpers::create_project;
 use nexus_test_utils::resource_helpers::delete_physical_disk;
+use nexus_test_utils::resource_helpers::object_create;
 use nexus_test_utils::resource_helpers::objects_list_page_authz;
+use nexus_test_utils::resource_helpers::populate_ip_pool;
 use nexus_test_utils::start_sled_agent;
 use nexus_test_utils::SLED_AGENT_UUID;
 use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::ByteCount;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::Instance;
+use omicron_common::api::external::InstanceAutoRestartPolicy;
+use omicron_common::api::external::InstanceCpuCount;
+use omicron_common::api::external::InstanceState;
+use omicron_common::api::external::NameOrId;
+use omicron_nexus::db::lookup::LookupPath;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::shared::AffinityGroupKind;
+use omicron_nexus::external_api::shared::AffinityPolicy;
 use omicron_nexus::external_api::views::{
-    PhysicalDisk, PhysicalDiskType, Sled,
+    AffinityGroup, PhysicalDisk, PhysicalDiskType, Sled, SledDrainProgress,
+    SledState,
 };
 use omicron_nexus::internal_api::params as internal_params;
 use omicron_sled_agent::sim;
+use omicron_test_utils::dev::poll::wait_for_condition;
+use omicron_test_utils::dev::poll::CondCheckError;
 use std::str::FromStr;
+use std::time::Duration;
 use uuid::Uuid;
 
 type ControlPlaneTestContext =
@@ -68,6 +96,7 @@ async fn test_sleds_list(cptestctx: &ControlPlaneTestContext) {
     // List sleds again.
     let sleds_found = sleds_list(&client, &sleds_url).await;
     assert_eq!(sleds_found.len(), nsleds + 1);
+    assert!(sleds_found.iter().all(|sled| sled.state == SledState::Active));
 
     let sledids_found =
         sleds_found.iter().map(|sv| sv.identity.id).collect::<Vec<Uuid>>();
@@ -119,3 +148,506 @@ async fn test_physical_disk_create_list_delete(
     delete_physical_disk(&internal_client, "v", "s", "m", sled_id).await;
     assert!(physical_disks_list(&external_client, &disks_url).await.is_empty());
 }
+
+#[nexus_test]
+async fn test_sled_expunge(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+
+    populate_ip_pool(&client, "default", None).await;
+    create_project(client, "springfield-squidport").await;
+
+    // With only one sled, a new instance has to land on it.
+    let instance =
+        create_instance(client, "springfield-squidport", "inst").await;
+    let instance_url = format!(
+        "/v1/instances/{}?project=springfield-squidport",
+        instance.identity.name
+    );
+
+    // Expunge the sled.
+    let sled_url = format!("/v1/system/hardware/sleds/{SLED_AGENT_UUID}");
+    let sled: Sled = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("{sled_url}/expunge"),
+        )
+        .expect_status(Some(StatusCode::OK)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert_eq!(sled.state, SledState::Expunged);
+    let sled: Sled = NexusRequest::object_get(client, &sled_url)
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap()
+        .parsed_body()
+        .unwrap();
+    assert_eq!(sled.state, SledState::Expunged);
+
+    // The instance that was on the sled has failed.
+    let instance: Instance = NexusRequest::object_get(client, &instance_url)
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap()
+        .parsed_body()
+        .unwrap();
+    assert_eq!(instance.runtime.run_state, InstanceState::Failed);
+
+    // There's nowhere left to put a new instance.
+    let instance_params = params::InstanceCreate {
+        identity: IdentityMetadataCreateParams {
+            name: "homeless".parse().unwrap(),
+            description: String::from("an instance with no sled to run on"),
+        },
+        labels: Default::default(),
+        ncpus: InstanceCpuCount(1),
+        memory: ByteCount::from_gibibytes_u32(1),
+        hostname: String::from("homeless"),
+        user_data: vec![],
+        network_interfaces: params::InstanceNetworkInterfaceAttachment::None,
+        external_ips: vec![],
+        disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
+        start: true,
+    };
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            "/v1/instances?project=springfield-squidport",
+        )
+        .body(Some(&instance_params))
+        .expect_status(Some(StatusCode::SERVICE_UNAVAILABLE)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+}
+
+#[nexus_test]
+async fn test_sled_drain(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    let datastore = nexus.datastore();
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    populate_ip_pool(&client, "default", None).await;
+    create_project(client, "springfield-squidport").await;
+
+    // Start a second sled for instances to migrate to.
+    let sa_id = Uuid::new_v4();
+    let log = cptestctx.logctx.log.new(o!( "sled_id" => sa_id.to_string() ));
+    let addr = cptestctx.server.get_http_server_internal_address().await;
+    let update_directory = Utf8Path::new("/should/not/be/used");
+    let sa = start_sled_agent(
+        log,
+        addr,
+        sa_id,
+        &update_directory,
+        sim::SimMode::Explicit,
+    )
+    .await
+    .unwrap();
+
+    // Create a running instance and drain the sled that it landed on.
+    let instance =
+        create_instance(client, "springfield-squidport", "inst").await;
+    let instance_id = instance.identity.id;
+    instance_simulate(nexus, &instance_id).await;
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    let drained_sled_id = db_instance.runtime().sled_id;
+
+    let drain_url =
+        format!("/v1/system/hardware/sleds/{drained_sled_id}/drain");
+    let progress: SledDrainProgress = NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &drain_url)
+            .expect_status(Some(StatusCode::OK)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert!(!progress.schedulable);
+    assert_eq!(progress.instances_pending + progress.instances_migrating, 1);
+    assert_eq!(progress.instances_not_running, 0);
+
+    // The drain task should start migrating the instance to the other sled.
+    wait_for_condition(
+        || async {
+            let progress: SledDrainProgress =
+                NexusRequest::object_get(client, &drain_url)
+                    .authn_as(AuthnMode::PrivilegedUser)
+                    .execute()
+                    .await
+                    .unwrap()
+                    .parsed_body()
+                    .unwrap();
+            if progress.instances_migrating == 1 {
+                Ok(())
+            } else {
+                Err(CondCheckError::<()>::NotYet)
+            }
+        },
+        &Duration::from_millis(100),
+        &Duration::from_secs(60),
+    )
+    .await
+    .expect("instance did not start migrating off of the drained sled");
+
+    // New instances don't land on the drained sled.
+    let instance =
+        create_instance(client, "springfield-squidport", "inst2").await;
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance.identity.id)
+        .fetch()
+        .await
+        .unwrap();
+    assert_ne!(db_instance.runtime().sled_id, drained_sled_id);
+
+    // Return the sled to service.
+    let sled: Sled = NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/system/hardware/sleds/{drained_sled_id}/undrain"),
+        )
+        .expect_status(Some(StatusCode::OK)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body()
+    .unwrap();
+    assert!(sled.schedulable);
+
+    sa.http_server.close().await.unwrap();
+}
+
+#[nexus_test]
+async fn test_sled_drain_strict_affinity_member(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    let datastore = nexus.datastore();
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    populate_ip_pool(&client, "default", None).await;
+    create_project(client, "springfield-squidport").await;
+
+    // Start a second sled for the instance to migrate to.
+    let sa_id = Uuid::new_v4();
+    let log = cptestctx.logctx.log.new(o!( "sled_id" => sa_id.to_string() ));
+    let addr = cptestctx.server.get_http_server_internal_address().await;
+    let update_directory = Utf8Path::new("/should/not/be/used");
+    let sa = start_sled_agent(
+        log,
+        addr,
+        sa_id,
+        &update_directory,
+        sim::SimMode::Explicit,
+    )
+    .await
+    .unwrap();
+
+    // Create a running instance in a strict affinity group.  Its own sled
+    // mustn't count as one the group requires, or there'd be nowhere for it
+    // to go once that sled is drained.
+    let _: AffinityGroup = object_create(
+        client,
+        "/v1/affinity-groups?project=springfield-squidport",
+        &params::AffinityGroupCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "together".parse().unwrap(),
+                description: String::from("a strict affinity group"),
+            },
+            kind: AffinityGroupKind::Affinity,
+            policy: AffinityPolicy::Fail,
+        },
+    )
+    .await;
+    let instance: Instance = object_create(
+        client,
+        "/v1/instances?project=springfield-squidport",
+        &params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "inst".parse().unwrap(),
+                description: String::from("an instance in a group"),
+            },
+            labels: Default::default(),
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_gibibytes_u32(1),
+            hostname: String::from("inst"),
+            user_data: vec![],
+            network_interfaces:
+                params::InstanceNetworkInterfaceAttachment::None,
+            external_ips: vec![],
+            disks: vec![],
+            affinity_groups: vec![NameOrId::Name("together".parse().unwrap())],
+            auto_restart_policy: Default::default(),
+            start: true,
+        },
+    )
+    .await;
+    let instance_id = instance.identity.id;
+    instance_simulate(nexus, &instance_id).await;
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    let drained_sled_id = db_instance.runtime().sled_id;
+
+    let drain_url =
+        format!("/v1/system/hardware/sleds/{drained_sled_id}/drain");
+    NexusRequest::new(
+        RequestBuilder::new(client, Method::POST, &drain_url)
+            .expect_status(Some(StatusCode::OK)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // The drain task should start migrating the instance to the other sled.
+    wait_for_condition(
+        || async {
+            let progress: SledDrainProgress =
+                NexusRequest::object_get(client, &drain_url)
+                    .authn_as(AuthnMode::PrivilegedUser)
+                    .execute()
+                    .await
+                    .unwrap()
+                    .parsed_body()
+                    .unwrap();
+            if progress.instances_migrating == 1 {
+                Ok(())
+            } else {
+                Err(CondCheckError::<()>::NotYet)
+            }
+        },
+        &Duration::from_millis(100),
+        &Duration::from_secs(60),
+    )
+    .await
+    .expect("instance did not start migrating off of the drained sled");
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    assert!(db_instance.runtime().migration_id.is_some());
+
+    sa.http_server.close().await.unwrap();
+}
+
+#[nexus_test]
+async fn test_sled_expunge_restarts_strict_affinity_member(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    let datastore = nexus.datastore();
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    populate_ip_pool(&client, "default", None).await;
+    create_project(client, "springfield-squidport").await;
+
+    // Start a second sled for the instance to be restarted on.
+    let sa_id = Uuid::new_v4();
+    let log = cptestctx.logctx.log.new(o!( "sled_id" => sa_id.to_string() ));
+    let addr = cptestctx.server.get_http_server_internal_address().await;
+    let update_directory = Utf8Path::new("/should/not/be/used");
+    let sa = start_sled_agent(
+        log,
+        addr,
+        sa_id,
+        &update_directory,
+        sim::SimMode::Explicit,
+    )
+    .await
+    .unwrap();
+
+    // Create a running instance in a strict affinity group that should be
+    // restarted if it fails.  Once its sled is expunged, the instance is the
+    // group's only member and it has failed, so the group shouldn't hold it
+    // to the old sled.
+    let _: AffinityGroup = object_create(
+        client,
+        "/v1/affinity-groups?project=springfield-squidport",
+        &params::AffinityGroupCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "together".parse().unwrap(),
+                description: String::from("a strict affinity group"),
+            },
+            kind: AffinityGroupKind::Affinity,
+            policy: AffinityPolicy::Fail,
+        },
+    )
+    .await;
+    let instance: Instance = object_create(
+        client,
+        "/v1/instances?project=springfield-squidport",
+        &params::InstanceCreate {
+            identity: IdentityMetadataCreateParams {
+                name: "inst".parse().unwrap(),
+                description: String::from("an instance in a group"),
+            },
+            labels: Default::default(),
+            ncpus: InstanceCpuCount(1),
+            memory: ByteCount::from_gibibytes_u32(1),
+            hostname: String::from("inst"),
+            user_data: vec![],
+            network_interfaces:
+                params::InstanceNetworkInterfaceAttachment::None,
+            external_ips: vec![],
+            disks: vec![],
+            affinity_groups: vec![NameOrId::Name("together".parse().unwrap())],
+            auto_restart_policy: InstanceAutoRestartPolicy::OnFailure,
+            start: true,
+        },
+    )
+    .await;
+    let instance_id = instance.identity.id;
+    instance_simulate(nexus, &instance_id).await;
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    let expunged_sled_id = db_instance.runtime().sled_id;
+
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/system/hardware/sleds/{expunged_sled_id}/expunge"),
+        )
+        .expect_status(Some(StatusCode::OK)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // The auto-restart task should start the instance again on the other
+    // sled.
+    wait_for_condition(
+        || async {
+            let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+                .instance_id(instance_id)
+                .fetch()
+                .await
+                .unwrap();
+            let runtime = db_instance.runtime();
+            if runtime.sled_id != expunged_sled_id
+                && runtime.state.0 == InstanceState::Starting
+            {
+                Ok(())
+            } else {
+                Err(CondCheckError::<()>::NotYet)
+            }
+        },
+        &Duration::from_millis(100),
+        &Duration::from_secs(60),
+    )
+    .await
+    .expect("instance was not restarted on another sled");
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    assert_eq!(db_instance.runtime().sled_id, sa_id);
+
+    sa.http_server.close().await.unwrap();
+}
+
+#[nexus_test]
+async fn test_start_stopped_instance_on_drained_sled(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    let datastore = nexus.datastore();
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    populate_ip_pool(&client, "default", None).await;
+    create_project(client, "springfield-squidport").await;
+
+    // Start a second sled for the instance to move to.
+    let sa_id = Uuid::new_v4();
+    let log = cptestctx.logctx.log.new(o!( "sled_id" => sa_id.to_string() ));
+    let addr = cptestctx.server.get_http_server_internal_address().await;
+    let update_directory = Utf8Path::new("/should/not/be/used");
+    let sa = start_sled_agent(
+        log,
+        addr,
+        sa_id,
+        &update_directory,
+        sim::SimMode::Explicit,
+    )
+    .await
+    .unwrap();
+
+    // Stop an instance, then drain the sled that it was on.
+    let instance =
+        create_instance(client, "springfield-squidport", "inst").await;
+    let instance_id = instance.identity.id;
+    instance_simulate(nexus, &instance_id).await;
+    instance_post(client, "inst", InstanceOp::Stop).await;
+    instance_simulate(nexus, &instance_id).await;
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    assert_eq!(db_instance.runtime().state.state(), &InstanceState::Stopped);
+    let drained_sled_id = db_instance.runtime().sled_id;
+
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!("/v1/system/hardware/sleds/{drained_sled_id}/drain"),
+        )
+        .expect_status(Some(StatusCode::OK)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // Starting the instance again puts it on the other sled.
+    instance_post(client, "inst", InstanceOp::Start).await;
+    instance_simulate(nexus, &instance_id).await;
+    let (.., db_instance) = LookupPath::new(&opctx, &datastore)
+        .instance_id(instance_id)
+        .fetch()
+        .await
+        .unwrap();
+    assert_eq!(db_instance.runtime().state.state(), &InstanceState::Running);
+    assert_ne!(db_instance.runtime().sled_id, drained_sled_id);
+
+    sa.http_server.close().await.unwrap();
+}
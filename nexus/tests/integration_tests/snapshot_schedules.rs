@@ -0,0 +1,292 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integration tests for snapshot schedules
+
+use async_bb8_diesel::AsyncRunQueryDsl;
+use chrono::DateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+use dropshot::test_util::ClientTestContext;
+use http::method::Method;
+use http::StatusCode;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::resource_helpers::create_disk;
+use nexus_test_utils::resource_helpers::create_project;
+use nexus_test_utils::resource_helpers::object_create;
+use nexus_test_utils::resource_helpers::object_delete;
+use nexus_test_utils::resource_helpers::objects_list_page_authz;
+use nexus_test_utils::resource_helpers::DiskTest;
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::NameOrId;
+use omicron_nexus::db;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::views::Snapshot;
+use omicron_nexus::external_api::views::SnapshotSchedule;
+use omicron_test_utils::dev::poll::wait_for_condition;
+use omicron_test_utils::dev::poll::CondCheckError;
+use std::convert::Infallible;
+use std::time::Duration;
+use uuid::Uuid;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const PROJECT_NAME: &str = "springfield-squidport";
+const DISK_NAME: &str = "rainsticks";
+
+fn get_snapshot_schedules_url() -> String {
+    format!("/v1/snapshot-schedules?project={}", PROJECT_NAME)
+}
+
+fn get_snapshot_schedule_url(name: &str) -> String {
+    format!("/v1/snapshot-schedules/{}?project={}", name, PROJECT_NAME)
+}
+
+fn schedule_params(
+    name: &str,
+    disk: &str,
+    interval_minutes: u32,
+    retention_count: u32,
+) -> params::SnapshotScheduleCreate {
+    params::SnapshotScheduleCreate {
+        identity: IdentityMetadataCreateParams {
+            name: name.parse().unwrap(),
+            description: format!("snapshot schedule {:?}", name),
+        },
+        disk: NameOrId::Name(disk.parse().unwrap()),
+        interval_minutes,
+        retention_count,
+    }
+}
+
+async fn snapshot_schedule_get(
+    client: &ClientTestContext,
+    name: &str,
+) -> SnapshotSchedule {
+    NexusRequest::object_get(client, &get_snapshot_schedule_url(name))
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute_and_parse_unwrap::<SnapshotSchedule>()
+        .await
+}
+
+/// Waits for the schedule called `name` to take a snapshot after
+/// `time_after`, returning the updated schedule
+async fn wait_for_scheduled_snapshot(
+    client: &ClientTestContext,
+    name: &str,
+    time_after: Option<DateTime<Utc>>,
+) -> SnapshotSchedule {
+    let schedule = wait_for_condition(
+        || async {
+            let schedule = snapshot_schedule_get(client, name).await;
+            assert_eq!(schedule.last_error, None);
+            match schedule.time_last_snapshot {
+                Some(time) if Some(time) > time_after => Ok(schedule),
+                _ => Err(CondCheckError::<Infallible>::NotYet),
+            }
+        },
+        &Duration::from_millis(100),
+        &Duration::from_secs(60),
+    )
+    .await
+    .expect("snapshot schedule did not take a snapshot");
+
+    // Scheduled snapshots are named with one-second resolution, so make sure
+    // that the next one can't be taken within the same second.
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    schedule
+}
+
+async fn scheduled_snapshots(
+    client: &ClientTestContext,
+    schedule_id: Uuid,
+) -> Vec<Snapshot> {
+    let url = format!("/v1/snapshots?project={}", PROJECT_NAME);
+    objects_list_page_authz::<Snapshot>(client, &url)
+        .await
+        .items
+        .into_iter()
+        .filter(|s| s.snapshot_schedule_id == Some(schedule_id))
+        .collect()
+}
+
+/// Makes the schedule's next snapshot due right away
+async fn make_snapshot_due(
+    cptestctx: &ControlPlaneTestContext,
+    schedule_id: Uuid,
+) {
+    use db::schema::snapshot_schedule::dsl;
+
+    let datastore = cptestctx.server.apictx().nexus.datastore();
+    diesel::update(dsl::snapshot_schedule)
+        .filter(dsl::id.eq(schedule_id))
+        .set(dsl::time_next_snapshot.eq(Utc::now()))
+        .execute_async(datastore.pool_for_tests().await.unwrap())
+        .await
+        .unwrap();
+}
+
+#[nexus_test]
+async fn test_snapshot_schedule_crud(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    DiskTest::new(&cptestctx).await;
+    create_project(client, PROJECT_NAME).await;
+    let disk = create_disk(client, PROJECT_NAME, DISK_NAME).await;
+
+    let schedule: SnapshotSchedule = object_create(
+        client,
+        &get_snapshot_schedules_url(),
+        &schedule_params("hourly", DISK_NAME, 60, 4),
+    )
+    .await;
+    assert_eq!(schedule.disk_id, disk.identity.id);
+    assert_eq!(schedule.interval_minutes, 60);
+    assert_eq!(schedule.retention_count, 4);
+
+    // The first snapshot is taken right away, and the next one is due an
+    // interval later.
+    let schedule = wait_for_scheduled_snapshot(client, "hourly", None).await;
+    assert!(
+        schedule.time_next_snapshot
+            >= schedule.time_created + chrono::Duration::minutes(60)
+    );
+    let snapshots = scheduled_snapshots(client, schedule.identity.id).await;
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].disk_id, disk.identity.id);
+    assert!(snapshots[0].identity.name.as_str().starts_with("hourly-"));
+
+    let schedules = objects_list_page_authz::<SnapshotSchedule>(
+        client,
+        &get_snapshot_schedules_url(),
+    )
+    .await
+    .items;
+    assert_eq!(schedules.len(), 1);
+    assert_eq!(schedules[0].identity.id, schedule.identity.id);
+
+    // Schedule names are unique within the project.
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &get_snapshot_schedules_url(),
+        )
+        .body(Some(&schedule_params("hourly", DISK_NAME, 60, 4)))
+        .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+
+    // Deleting the schedule leaves its snapshots alone.
+    object_delete(client, &get_snapshot_schedule_url("hourly")).await;
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::NOT_FOUND,
+        Method::GET,
+        &get_snapshot_schedule_url("hourly"),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    assert_eq!(
+        scheduled_snapshots(client, schedule.identity.id).await.len(),
+        1
+    );
+}
+
+#[nexus_test]
+async fn test_snapshot_schedule_invalid(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    DiskTest::new(&cptestctx).await;
+    create_project(client, PROJECT_NAME).await;
+    create_disk(client, PROJECT_NAME, DISK_NAME).await;
+
+    for (params, status) in [
+        (schedule_params("never", DISK_NAME, 0, 4), StatusCode::BAD_REQUEST),
+        (schedule_params("none", DISK_NAME, 60, 0), StatusCode::BAD_REQUEST),
+        (schedule_params("hoard", DISK_NAME, 60, 257), StatusCode::BAD_REQUEST),
+        (
+            schedule_params("missing", "no-such-disk", 60, 4),
+            StatusCode::NOT_FOUND,
+        ),
+    ] {
+        NexusRequest::new(
+            RequestBuilder::new(
+                client,
+                Method::POST,
+                &get_snapshot_schedules_url(),
+            )
+            .body(Some(&params))
+            .expect_status(Some(status)),
+        )
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute()
+        .await
+        .unwrap();
+    }
+
+    assert!(objects_list_page_authz::<SnapshotSchedule>(
+        client,
+        &get_snapshot_schedules_url(),
+    )
+    .await
+    .items
+    .is_empty());
+}
+
+#[nexus_test]
+async fn test_snapshot_schedule_retention(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    DiskTest::new(&cptestctx).await;
+    create_project(client, PROJECT_NAME).await;
+    create_disk(client, PROJECT_NAME, DISK_NAME).await;
+
+    let schedule: SnapshotSchedule = object_create(
+        client,
+        &get_snapshot_schedules_url(),
+        &schedule_params("daily", DISK_NAME, 24 * 60, 2),
+    )
+    .await;
+    let schedule_id = schedule.identity.id;
+    let mut schedule = wait_for_scheduled_snapshot(client, "daily", None).await;
+    let mut kept = scheduled_snapshots(client, schedule_id).await;
+    assert_eq!(kept.len(), 1);
+
+    // Take three more snapshots.  Only the newest two are kept.
+    for _ in 0..3 {
+        make_snapshot_due(cptestctx, schedule_id).await;
+        schedule = wait_for_scheduled_snapshot(
+            client,
+            "daily",
+            schedule.time_last_snapshot,
+        )
+        .await;
+        let snapshots = scheduled_snapshots(client, schedule_id).await;
+        assert!(snapshots.len() <= 2);
+        kept.extend(
+            snapshots.into_iter().filter(|s| {
+                !kept.iter().any(|k| k.identity.id == s.identity.id)
+            }),
+        );
+    }
+    assert_eq!(kept.len(), 4);
+
+    let mut remaining: Vec<Uuid> = scheduled_snapshots(client, schedule_id)
+        .await
+        .into_iter()
+        .map(|s| s.identity.id)
+        .collect();
+    remaining.sort();
+    let mut newest: Vec<Uuid> =
+        kept[2..].iter().map(|s| s.identity.id).collect();
+    newest.sort();
+    assert_eq!(remaining, newest);
+}
@@ -78,6 +78,7 @@ async fn test_snapshot_basic(cptestctx: &ControlPlaneTestContext) {
                 "you can boot any image, as long as it's alpine",
             ),
         },
+        labels: Default::default(),
         source: params::ImageSource::Url {
             url: server.url("/image.raw").to_string(),
         },
@@ -101,6 +102,7 @@ async fn test_snapshot_basic(cptestctx: &ControlPlaneTestContext) {
             name: base_disk_name.clone(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Image { image_id: image.identity.id },
         size: disk_size,
     };
@@ -129,6 +131,7 @@ async fn test_snapshot_basic(cptestctx: &ControlPlaneTestContext) {
                 name: instance_name.parse().unwrap(),
                 description: format!("instance {:?}", instance_name),
             },
+            labels: Default::default(),
             ncpus: InstanceCpuCount(2),
             memory: ByteCount::from_gibibytes_u32(1),
             hostname: String::from("base_instance"),
@@ -141,6 +144,8 @@ async fn test_snapshot_basic(cptestctx: &ControlPlaneTestContext) {
                 params::InstanceDiskAttach { name: base_disk_name.clone() },
             )],
             external_ips: vec![],
+            affinity_groups: Vec::new(),
+            auto_restart_policy: Default::default(),
             start: true,
         },
     )
@@ -161,6 +166,7 @@ async fn test_snapshot_basic(cptestctx: &ControlPlaneTestContext) {
                 name: instance_name.parse().unwrap(),
                 description: format!("instance {:?}", instance_name),
             },
+            labels: Default::default(),
             disk: base_disk_name,
         },
     )
@@ -198,6 +204,7 @@ async fn test_snapshot_without_instance(cptestctx: &ControlPlaneTestContext) {
                 "you can boot any image, as long as it's alpine",
             ),
         },
+        labels: Default::default(),
         source: params::ImageSource::Url {
             url: server.url("/image.raw").to_string(),
         },
@@ -221,6 +228,7 @@ async fn test_snapshot_without_instance(cptestctx: &ControlPlaneTestContext) {
             name: base_disk_name.clone(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Image { image_id: image.identity.id },
         size: disk_size,
     };
@@ -261,6 +269,7 @@ async fn test_snapshot_without_instance(cptestctx: &ControlPlaneTestContext) {
                 name: "not-attached".parse().unwrap(),
                 description: "not attached to instance".into(),
             },
+            labels: Default::default(),
             disk: base_disk_name.clone(),
         },
     )
@@ -301,6 +310,7 @@ async fn test_delete_snapshot(cptestctx: &ControlPlaneTestContext) {
             name: base_disk_name.clone(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -338,6 +348,7 @@ async fn test_delete_snapshot(cptestctx: &ControlPlaneTestContext) {
                 name: "not-attached".parse().unwrap(),
                 description: "not attached to instance".into(),
             },
+            labels: Default::default(),
             disk: base_disk_name.clone(),
         },
     )
@@ -362,6 +373,7 @@ async fn test_delete_snapshot(cptestctx: &ControlPlaneTestContext) {
             name: snap_disk_name.clone(),
             description: String::from("snapshot of 'sells rainsticks'"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -476,6 +488,7 @@ async fn test_reject_creating_disk_from_snapshot(
                     time_modified: Utc::now(),
                     time_deleted: None,
                 },
+                labels: Default::default(),
 
                 project_id,
                 disk_id: Uuid::new_v4(),
@@ -491,6 +504,7 @@ async fn test_reject_creating_disk_from_snapshot(
                 )
                 .unwrap()
                 .into(),
+                snapshot_schedule_id: None,
             },
         )
         .await
@@ -506,6 +520,7 @@ async fn test_reject_creating_disk_from_snapshot(
                     name: "bad-disk".parse().unwrap(),
                     description: String::from("bad disk"),
                 },
+                labels: Default::default(),
 
                 disk_source: params::DiskSource::Snapshot {
                     snapshot_id: snapshot.id(),
@@ -538,6 +553,7 @@ async fn test_reject_creating_disk_from_snapshot(
                     name: "bad-disk".parse().unwrap(),
                     description: String::from("bad disk"),
                 },
+                labels: Default::default(),
 
                 disk_source: params::DiskSource::Snapshot {
                     snapshot_id: snapshot.id(),
@@ -571,6 +587,7 @@ async fn test_reject_creating_disk_from_snapshot(
                     name: "bad-disk".parse().unwrap(),
                     description: String::from("bad disk"),
                 },
+                labels: Default::default(),
 
                 disk_source: params::DiskSource::Snapshot {
                     snapshot_id: snapshot.id(),
@@ -631,6 +648,7 @@ async fn test_reject_creating_disk_from_illegal_snapshot(
                     time_modified: Utc::now(),
                     time_deleted: None,
                 },
+                labels: Default::default(),
 
                 project_id,
                 disk_id: Uuid::new_v4(),
@@ -646,6 +664,7 @@ async fn test_reject_creating_disk_from_illegal_snapshot(
                 )
                 .unwrap()
                 .into(),
+                snapshot_schedule_id: None,
             },
         )
         .await
@@ -668,6 +687,7 @@ async fn test_reject_creating_disk_from_illegal_snapshot(
                     name: "bad-disk".parse().unwrap(),
                     description: String::from("bad disk"),
                 },
+                labels: Default::default(),
 
                 disk_source: params::DiskSource::Snapshot {
                     snapshot_id: snapshot.id(),
@@ -711,6 +731,7 @@ async fn test_cannot_snapshot_if_no_space(cptestctx: &ControlPlaneTestContext) {
             name: base_disk_name.clone(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -737,6 +758,7 @@ async fn test_cannot_snapshot_if_no_space(cptestctx: &ControlPlaneTestContext) {
                     name: "not-attached".parse().unwrap(),
                     description: "not attached to instance".into(),
                 },
+                labels: Default::default(),
                 disk: base_disk_name,
             }))
             .expect_status(Some(StatusCode::SERVICE_UNAVAILABLE)),
@@ -771,6 +793,7 @@ async fn test_create_snapshot_record_idempotent(
             time_modified: Utc::now(),
             time_deleted: None,
         },
+        labels: Default::default(),
 
         project_id,
         disk_id: Uuid::new_v4(),
@@ -781,6 +804,7 @@ async fn test_create_snapshot_record_idempotent(
         state: db::model::SnapshotState::Creating,
         block_size: db::model::BlockSize::Traditional,
         size: external::ByteCount::try_from(1024u32).unwrap().into(),
+        snapshot_schedule_id: None,
     };
 
     let opctx =
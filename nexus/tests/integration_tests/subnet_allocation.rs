@@ -54,6 +54,7 @@ async fn create_instance_expect_failure(
             name: name.parse().unwrap(),
             description: "".to_string(),
         },
+        labels: Default::default(),
         ncpus: InstanceCpuCount(1),
         memory: ByteCount::from_gibibytes_u32(1),
         hostname: name.to_string(),
@@ -61,6 +62,8 @@ async fn create_instance_expect_failure(
         network_interfaces,
         external_ips: vec![],
         disks: vec![],
+        affinity_groups: Vec::new(),
+        auto_restart_policy: Default::default(),
         start: true,
     };
 
@@ -106,6 +109,7 @@ async fn test_subnet_allocation(cptestctx: &ControlPlaneTestContext) {
         // Use the minimum subnet size
         ipv4_block: Ipv4Net(subnet),
         ipv6_block: None,
+        custom_router: None,
     };
     NexusRequest::objects_post(client, &subnets_url, &Some(&subnet_create))
         .authn_as(AuthnMode::PrivilegedUser)
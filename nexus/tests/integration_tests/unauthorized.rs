@@ -83,6 +83,14 @@ async fn test_unauthorized(cptestctx: &ControlPlaneTestContext) {
                     .unwrap_or_else(|_| panic!("Failed to POST to URL: {url}")),
                 id_routes,
             ),
+            SetupReq::Delete { url } => {
+                NexusRequest::object_delete(client, url)
+                    .authn_as(AuthnMode::PrivilegedUser)
+                    .execute()
+                    .await
+                    .unwrap_or_else(|_| panic!("Failed to DELETE URL: {url}"));
+                continue;
+            }
         };
 
         setup_results.insert(url, result.clone());
@@ -156,6 +164,9 @@ enum SetupReq {
         body: serde_json::Value,
         id_routes: Vec<&'static str>,
     },
+    Delete {
+        url: &'static str,
+    },
 }
 
 lazy_static! {
@@ -231,6 +242,19 @@ lazy_static! {
             body: serde_json::to_value(&*DEMO_VPC_SUBNET_CREATE).unwrap(),
             id_routes: vec!["/by-id/vpc-subnets/{id}"],
         },
+        // Create a second VPC in the Project, without its default subnet, and
+        // peer the first VPC with it
+        SetupReq::Post {
+            url: &DEMO_PROJECT_URL_VPCS,
+            body: serde_json::to_value(&*DEMO_PEER_VPC_CREATE).unwrap(),
+            id_routes: vec![],
+        },
+        SetupReq::Delete { url: &DEMO_PEER_VPC_DEFAULT_SUBNET_URL },
+        SetupReq::Post {
+            url: &DEMO_VPC_URL_PEERINGS,
+            body: serde_json::to_value(&*DEMO_VPC_PEERING_CREATE).unwrap(),
+            id_routes: vec!["/by-id/vpc-peerings/{id}"],
+        },
         // Create a VPC Router in the Vpc
         SetupReq::Post {
             url: &DEMO_VPC_URL_ROUTERS,
@@ -272,6 +296,24 @@ lazy_static! {
             body: serde_json::to_value(&*DEMO_SNAPSHOT_CREATE).unwrap(),
             id_routes: vec!["/by-id/snapshots/{id}"],
         },
+        // Create a Snapshot Schedule in the Project
+        SetupReq::Post {
+            url: &DEMO_PROJECT_URL_SNAPSHOT_SCHEDULES,
+            body: serde_json::to_value(&*DEMO_SNAPSHOT_SCHEDULE_CREATE).unwrap(),
+            id_routes: vec!["/v1/snapshot-schedules/{id}"],
+        },
+        // Create a Floating IP in the Project
+        SetupReq::Post {
+            url: &DEMO_PROJECT_URL_FLOATING_IPS,
+            body: serde_json::to_value(&*DEMO_FLOATING_IP_CREATE).unwrap(),
+            id_routes: vec!["/v1/floating-ips/{id}"],
+        },
+        // Create an Affinity Group in the Project
+        SetupReq::Post {
+            url: &DEMO_PROJECT_URL_AFFINITY_GROUPS,
+            body: serde_json::to_value(&*DEMO_AFFINITY_GROUP_CREATE).unwrap(),
+            id_routes: vec!["/v1/affinity-groups/{id}"],
+        },
         // Create an Image in the Project
         SetupReq::Post {
             url: &DEMO_PROJECT_IMAGES_URL,
@@ -284,12 +326,41 @@ lazy_static! {
             body: serde_json::to_value(&*SAML_IDENTITY_PROVIDER).unwrap(),
             id_routes: vec![],
         },
+        // Create a Silo using OIDC, and an OIDC identity provider in it
+        SetupReq::Post {
+            url: "/v1/system/silos",
+            body: serde_json::to_value(&*DEMO_OIDC_SILO_CREATE).unwrap(),
+            id_routes: vec![],
+        },
+        SetupReq::Post {
+            url: &OIDC_IDENTITY_PROVIDERS_URL,
+            body: serde_json::to_value(&*OIDC_IDENTITY_PROVIDER).unwrap(),
+            id_routes: vec![],
+        },
+        // Create a Silo whose users are provisioned via the API, and a SCIM
+        // client token for it
+        SetupReq::Post {
+            url: "/v1/system/silos",
+            body: serde_json::to_value(&*DEMO_SCIM_SILO_CREATE).unwrap(),
+            id_routes: vec![],
+        },
+        SetupReq::Post {
+            url: &SCIM_CLIENT_TOKENS_URL,
+            body: serde_json::to_value(&*SCIM_CLIENT_TOKEN_CREATE).unwrap(),
+            id_routes: vec![],
+        },
         // Create a SSH key
         SetupReq::Post {
             url: &DEMO_SSHKEYS_URL,
             body: serde_json::to_value(&*DEMO_SSHKEY_CREATE).unwrap(),
             id_routes: vec![],
         },
+        // Create an access token
+        SetupReq::Post {
+            url: &DEMO_ACCESS_TOKENS_URL,
+            body: serde_json::to_value(&*DEMO_ACCESS_TOKEN_CREATE).unwrap(),
+            id_routes: vec![],
+        },
         // Create a Certificate
         SetupReq::Post {
             url: &DEMO_CERTIFICATES_URL,
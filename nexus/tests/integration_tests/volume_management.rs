@@ -5,14 +5,23 @@
 //! Tests that Nexus properly manages and cleans up Crucible resources
 //! associated with Volumes
 
+use crate::integration_tests::instances::instance_simulate;
+use async_bb8_diesel::AsyncRunQueryDsl;
+use chrono::Utc;
+use diesel::prelude::*;
 use dropshot::test_util::ClientTestContext;
 use http::method::Method;
 use http::StatusCode;
+use nexus_db_queries::context::OpContext;
+use nexus_db_queries::db::identity::Asset;
 use nexus_test_utils::http_testing::AuthnMode;
 use nexus_test_utils::http_testing::NexusRequest;
 use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::resource_helpers::create_disk;
+use nexus_test_utils::resource_helpers::create_instance_with;
 use nexus_test_utils::resource_helpers::create_project;
 use nexus_test_utils::resource_helpers::object_create;
+use nexus_test_utils::resource_helpers::objects_list_page_authz;
 use nexus_test_utils::resource_helpers::populate_ip_pool;
 use nexus_test_utils::resource_helpers::DiskTest;
 use nexus_test_utils_macros::nexus_test;
@@ -20,13 +29,17 @@ use omicron_common::api::external::ByteCount;
 use omicron_common::api::external::Disk;
 use omicron_common::api::external::IdentityMetadataCreateParams;
 use omicron_common::api::external::Name;
+use omicron_nexus::db::lookup::LookupPath;
 use omicron_nexus::db::DataStore;
 use omicron_nexus::external_api::params;
 use omicron_nexus::external_api::views;
+use omicron_test_utils::dev::poll::wait_for_condition;
+use omicron_test_utils::dev::poll::CondCheckError;
 use rand::prelude::SliceRandom;
 use rand::{rngs::StdRng, SeedableRng};
 use sled_agent_client::types::{CrucibleOpts, VolumeConstructionRequest};
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 use httptest::{matchers::*, responders::*, Expectation, ServerBuilder};
@@ -81,6 +94,7 @@ async fn create_image(client: &ClientTestContext) -> views::Image {
                 "you can boot any image, as long as it's alpine",
             ),
         },
+        labels: Default::default(),
         source: params::ImageSource::Url {
             url: server.url("/image.raw").to_string(),
         },
@@ -111,6 +125,7 @@ async fn create_base_disk(
             name: base_disk_name.clone(),
             description: String::from("sells rainsticks"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Image { image_id: image.identity.id },
         size: disk_size,
     };
@@ -157,6 +172,7 @@ async fn test_snapshot_then_delete_disk(cptestctx: &ControlPlaneTestContext) {
                 name: "a-snapshot".parse().unwrap(),
                 description: "a snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: base_disk_name.clone(),
         },
     )
@@ -218,6 +234,7 @@ async fn test_delete_snapshot_then_disk(cptestctx: &ControlPlaneTestContext) {
                 name: "a-snapshot".parse().unwrap(),
                 description: "a snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: base_disk_name.clone(),
         },
     )
@@ -279,6 +296,7 @@ async fn test_multiple_snapshots(cptestctx: &ControlPlaneTestContext) {
                     name: format!("a-snapshot-{}", i).parse().unwrap(),
                     description: "a snapshot!".to_string(),
                 },
+                labels: Default::default(),
                 disk: base_disk_name.clone(),
             },
         )
@@ -341,6 +359,7 @@ async fn test_snapshot_prevents_other_disk(
                 name: "a-snapshot".parse().unwrap(),
                 description: "a snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: base_disk_name.clone(),
         },
     )
@@ -371,6 +390,7 @@ async fn test_snapshot_prevents_other_disk(
             name: next_disk_name.clone(),
             description: String::from("will fail"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Image { image_id: image.identity.id },
         size: disk_size,
     };
@@ -438,6 +458,7 @@ async fn test_multiple_disks_multiple_snapshots_order_1(
             name: first_disk_name.clone(),
             description: String::from("disk 1"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -465,6 +486,7 @@ async fn test_multiple_disks_multiple_snapshots_order_1(
                 name: "first-snapshot".parse().unwrap(),
                 description: "first snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: first_disk_name.clone(),
         },
     )
@@ -480,6 +502,7 @@ async fn test_multiple_disks_multiple_snapshots_order_1(
             name: second_disk_name.clone(),
             description: String::from("disk 1"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -507,6 +530,7 @@ async fn test_multiple_disks_multiple_snapshots_order_1(
                 name: "second-snapshot".parse().unwrap(),
                 description: "second snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: second_disk_name.clone(),
         },
     )
@@ -574,6 +598,7 @@ async fn test_multiple_disks_multiple_snapshots_order_2(
             name: first_disk_name.clone(),
             description: String::from("disk 1"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -601,6 +626,7 @@ async fn test_multiple_disks_multiple_snapshots_order_2(
                 name: "first-snapshot".parse().unwrap(),
                 description: "first snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: first_disk_name.clone(),
         },
     )
@@ -616,6 +642,7 @@ async fn test_multiple_disks_multiple_snapshots_order_2(
             name: second_disk_name.clone(),
             description: String::from("disk 1"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -643,6 +670,7 @@ async fn test_multiple_disks_multiple_snapshots_order_2(
                 name: "second-snapshot".parse().unwrap(),
                 description: "second snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: second_disk_name.clone(),
         },
     )
@@ -704,6 +732,7 @@ async fn prepare_for_test_multiple_layers_of_snapshots(
             name: layer_1_disk_name.clone(),
             description: String::from("layer 1"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Blank {
             block_size: params::BlockSize::try_from(512).unwrap(),
         },
@@ -731,6 +760,7 @@ async fn prepare_for_test_multiple_layers_of_snapshots(
                 name: "layer-1-snapshot".parse().unwrap(),
                 description: "layer 1 snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: layer_1_disk_name.clone(),
         },
     )
@@ -746,6 +776,7 @@ async fn prepare_for_test_multiple_layers_of_snapshots(
             name: layer_2_disk_name.clone(),
             description: String::from("layer 2"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Snapshot {
             snapshot_id: layer_1_snapshot.identity.id,
         },
@@ -773,6 +804,7 @@ async fn prepare_for_test_multiple_layers_of_snapshots(
                 name: "layer-2-snapshot".parse().unwrap(),
                 description: "layer 2 snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: layer_2_disk_name.clone(),
         },
     )
@@ -788,6 +820,7 @@ async fn prepare_for_test_multiple_layers_of_snapshots(
             name: layer_3_disk_name.clone(),
             description: String::from("layer 3"),
         },
+        labels: Default::default(),
         disk_source: params::DiskSource::Snapshot {
             snapshot_id: layer_2_snapshot.identity.id,
         },
@@ -815,6 +848,7 @@ async fn prepare_for_test_multiple_layers_of_snapshots(
                 name: "layer-3-snapshot".parse().unwrap(),
                 description: "layer 3 snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: layer_3_disk_name.clone(),
         },
     )
@@ -996,6 +1030,7 @@ async fn test_create_image_from_snapshot(cptestctx: &ControlPlaneTestContext) {
                 name: "a-snapshot".parse().unwrap(),
                 description: "a snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: base_disk_name.clone(),
         },
     )
@@ -1062,6 +1097,7 @@ async fn test_create_image_from_snapshot_delete(
                 name: "a-snapshot".parse().unwrap(),
                 description: "a snapshot!".to_string(),
             },
+            labels: Default::default(),
             disk: base_disk_name.clone(),
         },
     )
@@ -1924,3 +1960,190 @@ async fn test_volume_hard_delete_idempotent(
     datastore.volume_hard_delete(volume_id).await.unwrap();
     datastore.volume_hard_delete(volume_id).await.unwrap();
 }
+
+// region replacement tests
+
+/// Marks a zpool as deleted, as though its physical disk had been lost
+async fn lose_zpool(datastore: &DataStore, pool_id: Uuid) {
+    use omicron_nexus::db::schema::zpool::dsl;
+    diesel::update(dsl::zpool)
+        .filter(dsl::id.eq(pool_id))
+        .set(dsl::time_deleted.eq(Utc::now()))
+        .execute_async(datastore.pool_for_tests().await.unwrap())
+        .await
+        .unwrap();
+}
+
+/// Waits for the replacement of `old_region_id` to reach `state`
+async fn wait_for_region_replacement(
+    client: &ClientTestContext,
+    old_region_id: Uuid,
+    state: views::RegionReplacementState,
+) -> views::RegionReplacement {
+    wait_for_condition(
+        || async {
+            let replacements =
+                objects_list_page_authz::<views::RegionReplacement>(
+                    client,
+                    "/v1/system/hardware/region-replacements",
+                )
+                .await
+                .items;
+            match replacements
+                .into_iter()
+                .find(|r| r.old_region_id == old_region_id)
+            {
+                Some(r) if r.state == state => Ok(r),
+                _ => Err(CondCheckError::<()>::NotYet),
+            }
+        },
+        &Duration::from_millis(100),
+        &Duration::from_secs(60),
+    )
+    .await
+    .unwrap_or_else(|_| {
+        panic!(
+            "replacement of region {} never reached {:?}",
+            old_region_id, state
+        )
+    })
+}
+
+#[nexus_test]
+async fn test_region_replacement_after_zpool_lost(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    let datastore = nexus.datastore();
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    // Create a fourth zpool, so that there's somewhere to put the
+    // replacement region.
+    let mut disk_test = DiskTest::new(&cptestctx).await;
+    disk_test
+        .add_zpool_with_dataset(cptestctx, DiskTest::DEFAULT_ZPOOL_SIZE_GIB)
+        .await;
+
+    create_org_and_project(client).await;
+    let disk = create_disk(client, PROJECT_NAME, "lost-region-disk").await;
+    let (.., db_disk) = LookupPath::new(&opctx, &datastore)
+        .disk_id(disk.identity.id)
+        .fetch()
+        .await
+        .unwrap();
+    let volume_id = db_disk.volume_id;
+
+    let regions = datastore.get_allocated_regions(volume_id).await.unwrap();
+    assert_eq!(regions.len(), 3);
+    let (old_dataset, old_region) = &regions[0];
+
+    // Lose the zpool holding one of the disk's regions.
+    lose_zpool(&datastore, old_dataset.pool_id).await;
+
+    // The region replacement task should notice, and replace the region.
+    let replacement = wait_for_region_replacement(
+        client,
+        old_region.id(),
+        views::RegionReplacementState::Complete,
+    )
+    .await;
+    assert_eq!(replacement.disk_id, disk.identity.id);
+    assert_eq!(replacement.volume_id, volume_id);
+    assert!(!replacement.degraded);
+    assert!(replacement.instance_id.is_none());
+    assert!(replacement.error.is_none());
+
+    // The volume should still have three regions, none of them on the lost
+    // zpool.
+    let regions = datastore.get_allocated_regions(volume_id).await.unwrap();
+    assert_eq!(regions.len(), 3);
+    assert!(regions
+        .iter()
+        .all(|(dataset, _)| dataset.id() != old_dataset.id()));
+    assert!(regions
+        .iter()
+        .any(|(_, region)| Some(region.id()) == replacement.new_region_id));
+}
+
+#[nexus_test]
+async fn test_region_replacement_awaiting_restart(
+    cptestctx: &ControlPlaneTestContext,
+) {
+    let client = &cptestctx.external_client;
+    let nexus = &cptestctx.server.apictx().nexus;
+    let datastore = nexus.datastore();
+    let opctx =
+        OpContext::for_tests(cptestctx.logctx.log.new(o!()), datastore.clone());
+
+    let mut disk_test = DiskTest::new(&cptestctx).await;
+    disk_test
+        .add_zpool_with_dataset(cptestctx, DiskTest::DEFAULT_ZPOOL_SIZE_GIB)
+        .await;
+    populate_ip_pool(&client, "default", None).await;
+
+    create_org_and_project(client).await;
+    let disk = create_disk(client, PROJECT_NAME, "lost-region-disk").await;
+    let instance = create_instance_with(
+        client,
+        PROJECT_NAME,
+        "lost-region-instance",
+        &params::InstanceNetworkInterfaceAttachment::None,
+        vec![params::InstanceDiskAttachment::Attach(
+            params::InstanceDiskAttach { name: disk.identity.name.clone() },
+        )],
+    )
+    .await;
+    instance_simulate(nexus, &instance.identity.id).await;
+
+    let (.., db_disk) = LookupPath::new(&opctx, &datastore)
+        .disk_id(disk.identity.id)
+        .fetch()
+        .await
+        .unwrap();
+    let regions =
+        datastore.get_allocated_regions(db_disk.volume_id).await.unwrap();
+    let (old_dataset, old_region) = &regions[0];
+    lose_zpool(&datastore, old_dataset.pool_id).await;
+
+    // The running Propolis keeps using the old regions, so the replacement
+    // can't complete, and the volume remains degraded, until the instance is
+    // restarted.
+    let replacement = wait_for_region_replacement(
+        client,
+        old_region.id(),
+        views::RegionReplacementState::AwaitingRestart,
+    )
+    .await;
+    assert!(replacement.degraded);
+    assert_eq!(replacement.instance_id, Some(instance.identity.id));
+    assert!(replacement.new_region_id.is_some());
+
+    NexusRequest::new(
+        RequestBuilder::new(
+            client,
+            Method::POST,
+            &format!(
+                "/v1/instances/{}/stop?project={}",
+                instance.identity.name, PROJECT_NAME
+            ),
+        )
+        .body(None as Option<&serde_json::Value>)
+        .expect_status(Some(StatusCode::ACCEPTED)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    instance_simulate(nexus, &instance.identity.id).await;
+
+    let replacement = wait_for_region_replacement(
+        client,
+        old_region.id(),
+        views::RegionReplacementState::Complete,
+    )
+    .await;
+    assert!(!replacement.degraded);
+    assert!(replacement.instance_id.is_none());
+}
@@ -0,0 +1,433 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integration tests for VPC peerings
+
+use camino::Utf8Path;
+use dropshot::test_util::ClientTestContext;
+use dropshot::HttpErrorResponseBody;
+use http::method::Method;
+use http::StatusCode;
+use ipnetwork::IpNetwork;
+use nexus_test_utils::http_testing::AuthnMode;
+use nexus_test_utils::http_testing::NexusRequest;
+use nexus_test_utils::http_testing::RequestBuilder;
+use nexus_test_utils::resource_helpers::create_instance;
+use nexus_test_utils::resource_helpers::create_instance_with;
+use nexus_test_utils::resource_helpers::create_project;
+use nexus_test_utils::resource_helpers::create_vpc;
+use nexus_test_utils::resource_helpers::object_create;
+use nexus_test_utils::resource_helpers::object_delete;
+use nexus_test_utils::resource_helpers::objects_list_page_authz;
+use nexus_test_utils::resource_helpers::populate_ip_pool;
+use nexus_test_utils::start_sled_agent;
+use nexus_test_utils_macros::nexus_test;
+use omicron_common::api::external::IdentityMetadataCreateParams;
+use omicron_common::api::external::InstanceNetworkInterface;
+use omicron_common::api::external::Ipv4Net;
+use omicron_common::api::external::NameOrId;
+use omicron_common::api::internal::shared::RouterTarget;
+use omicron_nexus::external_api::params;
+use omicron_nexus::external_api::views::Instance;
+use omicron_nexus::external_api::views::Vpc;
+use omicron_nexus::external_api::views::VpcPeering;
+use omicron_nexus::external_api::views::VpcSubnet;
+use omicron_sled_agent::sim;
+use omicron_sled_agent::sim::SledAgent;
+use std::net::IpAddr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+type ControlPlaneTestContext =
+    nexus_test_utils::ControlPlaneTestContext<omicron_nexus::Server>;
+
+const PROJECT_NAME: &str = "springfield-squidport";
+const PEER_VPC_NAME: &str = "peer";
+
+fn get_peerings_url(vpc: &str) -> String {
+    format!("/v1/vpc-peerings?project={}&vpc={}", PROJECT_NAME, vpc)
+}
+
+fn get_peering_url(vpc: &str, name: &str) -> String {
+    format!("/v1/vpc-peerings/{}?project={}&vpc={}", name, PROJECT_NAME, vpc)
+}
+
+fn get_subnets_url(vpc: &str) -> String {
+    format!("/v1/vpc-subnets?project={}&vpc={}", PROJECT_NAME, vpc)
+}
+
+fn peering_params(name: &str, peer_vpc: &str) -> params::VpcPeeringCreate {
+    params::VpcPeeringCreate {
+        identity: IdentityMetadataCreateParams {
+            name: name.parse().unwrap(),
+            description: format!("peering {:?}", name),
+        },
+        peer_vpc: NameOrId::Name(peer_vpc.parse().unwrap()),
+        peer_project: None,
+    }
+}
+
+fn subnet_params(name: &str, ipv4_block: &str) -> params::VpcSubnetCreate {
+    params::VpcSubnetCreate {
+        identity: IdentityMetadataCreateParams {
+            name: name.parse().unwrap(),
+            description: format!("subnet {:?}", name),
+        },
+        ipv4_block: Ipv4Net(ipv4_block.parse().unwrap()),
+        ipv6_block: None,
+        custom_router: None,
+    }
+}
+
+async fn expect_bad_request<B: serde::Serialize>(
+    client: &ClientTestContext,
+    method: Method,
+    url: &str,
+    body: Option<&B>,
+) -> String {
+    NexusRequest::new(
+        RequestBuilder::new(client, method, url)
+            .body(body)
+            .expect_status(Some(StatusCode::BAD_REQUEST)),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap()
+    .parsed_body::<HttpErrorResponseBody>()
+    .unwrap()
+    .message
+}
+
+/// Creates the peer VPC, with its overlapping default subnet replaced by one
+/// that can be peered with the project's default VPC
+async fn create_peer_vpc(client: &ClientTestContext) {
+    create_vpc(client, PROJECT_NAME, PEER_VPC_NAME).await;
+    object_delete(
+        client,
+        &format!(
+            "/v1/vpc-subnets/default?project={}&vpc={}",
+            PROJECT_NAME, PEER_VPC_NAME
+        ),
+    )
+    .await;
+    let _: VpcSubnet = object_create(
+        client,
+        &get_subnets_url(PEER_VPC_NAME),
+        &subnet_params("peer-subnet", "192.168.0.0/24"),
+    )
+    .await;
+}
+
+#[nexus_test]
+async fn test_vpc_peering_crud(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    create_project(client, PROJECT_NAME).await;
+
+    // Every VPC starts out with the same default subnet, so a new VPC can't
+    // be peered with the default one as it is.
+    create_vpc(client, PROJECT_NAME, "overlapping").await;
+    let message = expect_bad_request(
+        client,
+        Method::POST,
+        &get_peerings_url("default"),
+        Some(&peering_params("overlap", "overlapping")),
+    )
+    .await;
+    assert_eq!(
+        message,
+        "subnet \"default\" overlaps with subnet \"default\" of the peer VPC"
+    );
+
+    // Nor can a VPC be peered with itself.
+    let message = expect_bad_request(
+        client,
+        Method::POST,
+        &get_peerings_url("default"),
+        Some(&peering_params("self", "default")),
+    )
+    .await;
+    assert_eq!(message, "a VPC cannot be peered with itself");
+
+    create_peer_vpc(client).await;
+    let peering: VpcPeering = object_create(
+        client,
+        &get_peerings_url("default"),
+        &peering_params("to-peer", PEER_VPC_NAME),
+    )
+    .await;
+    let peer_vpc_url =
+        format!("/v1/vpcs/{}?project={}", PEER_VPC_NAME, PROJECT_NAME);
+    let peer_vpc = NexusRequest::object_get(client, &peer_vpc_url)
+        .authn_as(AuthnMode::PrivilegedUser)
+        .execute_and_parse_unwrap::<Vpc>()
+        .await;
+    assert_eq!(peering.peer_vpc_id, peer_vpc.identity.id);
+
+    let peerings = objects_list_page_authz::<VpcPeering>(
+        client,
+        &get_peerings_url("default"),
+    )
+    .await
+    .items;
+    assert_eq!(peerings.len(), 1);
+    assert_eq!(peerings[0].identity.id, peering.identity.id);
+
+    // Peerings are listed under the VPC they were created in.
+    assert!(objects_list_page_authz::<VpcPeering>(
+        client,
+        &get_peerings_url(PEER_VPC_NAME)
+    )
+    .await
+    .items
+    .is_empty());
+
+    // The VPCs can't be peered again, from either side.
+    for (vpc, peer_vpc) in
+        [("default", PEER_VPC_NAME), (PEER_VPC_NAME, "default")]
+    {
+        let message = expect_bad_request(
+            client,
+            Method::POST,
+            &get_peerings_url(vpc),
+            Some(&peering_params("again", peer_vpc)),
+        )
+        .await;
+        assert_eq!(message, "the VPCs are already peered");
+    }
+
+    // Subnets created in either VPC must not overlap with those of the other.
+    let message = expect_bad_request(
+        client,
+        Method::POST,
+        &get_subnets_url(PEER_VPC_NAME),
+        Some(&subnet_params("overlapping", "172.30.0.0/24")),
+    )
+    .await;
+    assert_eq!(
+        message,
+        "VPC Subnet address range overlaps with subnet \"default\" of a \
+        peered VPC"
+    );
+
+    // Neither VPC can be deleted while they're peered.
+    object_delete(
+        client,
+        &format!(
+            "/v1/vpc-subnets/peer-subnet?project={}&vpc={}",
+            PROJECT_NAME, PEER_VPC_NAME
+        ),
+    )
+    .await;
+    let message =
+        expect_bad_request::<()>(client, Method::DELETE, &peer_vpc_url, None)
+            .await;
+    assert_eq!(
+        message,
+        "VPC cannot be deleted while it is peered with other VPCs"
+    );
+
+    // Once the peering is deleted, it's gone and the VPC can be deleted.
+    object_delete(client, &get_peering_url("default", "to-peer")).await;
+    NexusRequest::expect_failure(
+        client,
+        StatusCode::NOT_FOUND,
+        Method::GET,
+        &get_peering_url("default", "to-peer"),
+    )
+    .authn_as(AuthnMode::PrivilegedUser)
+    .execute()
+    .await
+    .unwrap();
+    object_delete(client, &peer_vpc_url).await;
+}
+
+/// An instance's network interface, for checking reachability
+struct Endpoint {
+    instance_id: Uuid,
+    nic: InstanceNetworkInterface,
+}
+
+async fn instance_endpoint(
+    client: &ClientTestContext,
+    instance: &Instance,
+) -> Endpoint {
+    let nics = objects_list_page_authz::<InstanceNetworkInterface>(
+        client,
+        &format!("/v1/network-interfaces?instance={}", instance.identity.id),
+    )
+    .await
+    .items;
+    assert_eq!(nics.len(), 1);
+    Endpoint {
+        instance_id: instance.identity.id,
+        nic: nics.into_iter().next().unwrap(),
+    }
+}
+
+/// Returns the simulated sled hosting an instance
+async fn instance_sled<'a>(
+    sled_agents: &[&'a Arc<SledAgent>],
+    instance_id: Uuid,
+) -> &'a Arc<SledAgent> {
+    for sled_agent in sled_agents {
+        if sled_agent.instances_list().await.contains(&instance_id) {
+            return sled_agent;
+        }
+    }
+    panic!("instance {} isn't on any sled", instance_id);
+}
+
+/// Returns whether `from` can send traffic to `to`, given the routes and V2P
+/// mappings that the simulated sleds have been sent
+///
+/// The most specific route for `to`'s address in the table of `from`'s subnet
+/// must send the traffic to `to`'s subnet, encapsulated with the VNI of `to`'s
+/// VPC.  Unless the instances share a sled, `from`'s sled must then map `to`'s
+/// address to a physical host under that VNI.
+async fn reachable(
+    sled_agents: &[&Arc<SledAgent>],
+    from: &Endpoint,
+    to: &Endpoint,
+) -> bool {
+    let from_sled = instance_sled(sled_agents, from.instance_id).await;
+    let to_sled = instance_sled(sled_agents, to.instance_id).await;
+    let (IpAddr::V4(from_ip), IpAddr::V4(to_ip)) = (from.nic.ip, to.nic.ip)
+    else {
+        panic!("expected IPv4 addresses");
+    };
+
+    let mut to_vni = None;
+    for sled_agent in sled_agents {
+        if let Some(body) =
+            sled_agent.vpc_routes.lock().await.get(&to.nic.vpc_id)
+        {
+            to_vni = Some(body.vni);
+        }
+    }
+    let to_vni = to_vni.expect("no routes for destination VPC");
+
+    let vpc_routes = from_sled.vpc_routes.lock().await;
+    let Some(from_routes) = vpc_routes.get(&from.nic.vpc_id) else {
+        return false;
+    };
+    let table = from_routes
+        .subnets
+        .iter()
+        .find(|table| table.ipv4_block.0.contains(from_ip))
+        .expect("no route table for source subnet");
+    let route = table
+        .routes
+        .iter()
+        .filter_map(|route| match IpNetwork::from(route.dest) {
+            IpNetwork::V4(dest) if dest.contains(to_ip) => Some((dest, route)),
+            _ => None,
+        })
+        .max_by_key(|(dest, _)| dest.prefix())
+        .map(|(_, route)| route.target);
+    let vni = match route {
+        Some(RouterTarget::VpcSubnet(subnet))
+            if IpNetwork::from(subnet).contains(to.nic.ip) =>
+        {
+            from_routes.vni
+        }
+        Some(RouterTarget::VpcPeerSubnet { vni, subnet })
+            if IpNetwork::from(subnet).contains(to.nic.ip) =>
+        {
+            vni
+        }
+        _ => return false,
+    };
+    if vni != to_vni {
+        return false;
+    }
+
+    if Arc::ptr_eq(from_sled, to_sled) {
+        return true;
+    }
+    from_sled.v2p_mappings.lock().await.get(&to.nic.identity.id).map_or(
+        false,
+        |mappings| {
+            mappings.iter().any(|m| m.vni == vni && m.virtual_ip == to.nic.ip)
+        },
+    )
+}
+
+/// Test that instances in peered VPCs can reach each other, and only while
+/// the VPCs are peered
+#[nexus_test]
+async fn test_vpc_peering_reachability(cptestctx: &ControlPlaneTestContext) {
+    let client = &cptestctx.external_client;
+    populate_ip_pool(&client, "default", None).await;
+    create_project(client, PROJECT_NAME).await;
+    create_peer_vpc(client).await;
+
+    // Add another sled, so that the instances may be on different sleds.
+    let sa_id = Uuid::new_v4();
+    let log = cptestctx.logctx.log.new(o!( "sled_id" => sa_id.to_string() ));
+    let addr = cptestctx.server.get_http_server_internal_address().await;
+    let update_directory = Utf8Path::new("/should/not/be/used");
+    let additional_sled = start_sled_agent(
+        log,
+        addr,
+        sa_id,
+        &update_directory,
+        sim::SimMode::Explicit,
+    )
+    .await
+    .unwrap();
+    let sled_agents: Vec<&Arc<SledAgent>> =
+        vec![&additional_sled.sled_agent, &cptestctx.sled_agent.sled_agent];
+
+    // One instance is in the default VPC, and the other in the peer VPC.
+    let instance = create_instance(client, PROJECT_NAME, "inst").await;
+    let endpoint = instance_endpoint(client, &instance).await;
+    let peer_instance = create_instance_with(
+        client,
+        PROJECT_NAME,
+        "peer-inst",
+        &params::InstanceNetworkInterfaceAttachment::Create(vec![
+            params::InstanceNetworkInterfaceCreate {
+                identity: IdentityMetadataCreateParams {
+                    name: "peer-nic".parse().unwrap(),
+                    description: String::from("a NIC in the peer VPC"),
+                },
+                vpc_name: PEER_VPC_NAME.parse().unwrap(),
+                subnet_name: "peer-subnet".parse().unwrap(),
+                ip: None,
+            },
+        ]),
+        vec![],
+    )
+    .await;
+    let peer_endpoint = instance_endpoint(client, &peer_instance).await;
+
+    // The instances can't reach each other until the VPCs are peered.
+    assert!(!reachable(&sled_agents, &endpoint, &peer_endpoint).await);
+    assert!(!reachable(&sled_agents, &peer_endpoint, &endpoint).await);
+
+    let _: VpcPeering = object_create(
+        client,
+        &get_peerings_url("default"),
+        &peering_params("to-peer", PEER_VPC_NAME),
+    )
+    .await;
+    assert!(reachable(&sled_agents, &endpoint, &peer_endpoint).await);
+    assert!(reachable(&sled_agents, &peer_endpoint, &endpoint).await);
+
+    // Peering doesn't add any V2P mappings: each instance is reached through
+    // the mappings it already has under its own VPC's VNI.
+    for sled_agent in &sled_agents {
+        let v2p_mappings = sled_agent.v2p_mappings.lock().await;
+        for endpoint in [&endpoint, &peer_endpoint] {
+            assert!(v2p_mappings
+                .get(&endpoint.nic.identity.id)
+                .map_or(true, |mappings| mappings.len() <= 1));
+        }
+    }
+
+    object_delete(client, &get_peering_url("default", "to-peer")).await;
+    assert!(!reachable(&sled_agents, &endpoint, &peer_endpoint).await);
+    assert!(!reachable(&sled_agents, &peer_endpoint, &endpoint).await);
+}
@@ -178,6 +178,7 @@ async fn test_vpc_subnets(cptestctx: &ControlPlaneTestContext) {
         },
         ipv4_block,
         ipv6_block,
+        custom_router: None,
     };
     let subnet: VpcSubnet =
         NexusRequest::objects_post(client, &subnets_url, &new_subnet)
@@ -229,6 +230,7 @@ async fn test_vpc_subnets(cptestctx: &ControlPlaneTestContext) {
         },
         ipv4_block,
         ipv6_block,
+        custom_router: None,
     };
     let expected_error = format!(
         "IP address range '{}' conflicts with an existing subnet",
@@ -256,6 +258,7 @@ async fn test_vpc_subnets(cptestctx: &ControlPlaneTestContext) {
         },
         ipv4_block: other_ipv4_block,
         ipv6_block: other_ipv6_block,
+        custom_router: None,
     };
     let error: dropshot::HttpErrorResponseBody = NexusRequest::new(
         RequestBuilder::new(client, Method::POST, &subnets_url)
@@ -300,6 +303,7 @@ async fn test_vpc_subnets(cptestctx: &ControlPlaneTestContext) {
         },
         ipv4_block,
         ipv6_block: None,
+        custom_router: None,
     };
     let subnet2: VpcSubnet =
         NexusRequest::objects_post(client, &subnets_url, &new_subnet)
@@ -328,6 +332,7 @@ async fn test_vpc_subnets(cptestctx: &ControlPlaneTestContext) {
             name: Some("new-name".parse().unwrap()),
             description: Some("another description".to_string()),
         },
+        custom_router: None,
     };
     NexusRequest::object_put(client, &subnet_url, Some(&update_params))
         .authn_as(AuthnMode::PrivilegedUser)
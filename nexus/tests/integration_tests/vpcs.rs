@@ -85,6 +85,7 @@ async fn test_vpcs(cptestctx: &ControlPlaneTestContext) {
                     name: "just-rainsticks".parse().unwrap(),
                     description: String::from("vpc description"),
                 },
+                labels: Default::default(),
                 ipv6_prefix: Some(bad_prefix),
                 dns_name: "abc".parse().unwrap(),
             })),
@@ -140,6 +141,7 @@ async fn test_vpcs(cptestctx: &ControlPlaneTestContext) {
             name: Some("new-name".parse().unwrap()),
             description: Some("another description".to_string()),
         },
+        labels: None,
         dns_name: Some("def".parse().unwrap()),
     };
     let updated_vpc = vpc_put(&client, &vpc_url, update_params).await;
@@ -9,8 +9,8 @@ use base64::Engine;
 use chrono::{DateTime, Utc};
 use omicron_common::api::external::{
     ByteCount, IdentityMetadataCreateParams, IdentityMetadataUpdateParams,
-    InstanceCpuCount, Ipv4Net, Ipv6Net, Name, NameOrId, RouteDestination,
-    RouteTarget, SemverVersion,
+    InstanceAutoRestartPolicy, InstanceCpuCount, Ipv4Net, Ipv6Net, Labels,
+    Name, NameOrId, RouteDestination, RouteTarget, SemverVersion,
 };
 use schemars::JsonSchema;
 use serde::{
@@ -38,13 +38,20 @@ path_param!(VpcPath, vpc, "VPC");
 path_param!(SubnetPath, subnet, "subnet");
 path_param!(RouterPath, router, "router");
 path_param!(RoutePath, route, "route");
+path_param!(PeeringPath, peering, "peering");
 path_param!(DiskPath, disk, "disk");
 path_param!(SnapshotPath, snapshot, "snapshot");
 path_param!(ImagePath, image, "image");
 path_param!(SiloPath, silo, "silo");
 path_param!(ProviderPath, provider, "SAML identity provider");
+path_param!(OidcProviderPath, provider, "OIDC identity provider");
 path_param!(IpPoolPath, pool, "IP pool");
 path_param!(SshKeyPath, ssh_key, "SSH key");
+path_param!(AccessTokenPath, access_token, "access token");
+path_param!(AffinityGroupPath, affinity_group, "affinity group");
+path_param!(SnapshotSchedulePath, snapshot_schedule, "snapshot schedule");
+path_param!(FloatingIpPath, floating_ip, "floating IP");
+path_param!(ScimClientTokenPath, token, "SCIM client token");
 
 // Only by ID because groups have an `external_id` instead of a name and
 // therefore don't implement `ObjectIdentity`, which makes lookup by name
@@ -76,6 +83,22 @@ pub struct SamlIdentityProviderSelector {
     pub saml_identity_provider: NameOrId,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct OidcIdentityProviderSelector {
+    /// Name or ID of the silo in which the OIDC identity provider is associated
+    pub silo: Option<NameOrId>,
+    /// Name or ID of the OIDC identity provider
+    pub oidc_identity_provider: NameOrId,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ScimClientTokenSelector {
+    /// Name or ID of the silo to which the SCIM client token belongs
+    pub silo: Option<NameOrId>,
+    /// Name or ID of the SCIM client token
+    pub token: NameOrId,
+}
+
 // The shape of this selector is slightly different than the others given that
 // silos users can only be specified via ID and are automatically provided by
 // the environment the user is authetnicated in
@@ -87,6 +110,22 @@ pub struct SshKeySelector {
     pub ssh_key: NameOrId,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct AccessTokenSelector {
+    /// ID of the silo user
+    pub silo_user_id: Uuid,
+    /// Name or ID of the access token
+    pub access_token: NameOrId,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct UserAccessTokenPath {
+    /// ID of the user
+    pub user_id: Uuid,
+    /// Name or ID of the access token
+    pub access_token: NameOrId,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub struct ProjectSelector {
     /// Name or ID of the project
@@ -115,6 +154,30 @@ pub struct SnapshotSelector {
     pub snapshot: NameOrId,
 }
 
+#[derive(Deserialize, JsonSchema)]
+pub struct SnapshotScheduleSelector {
+    /// Name or ID of the project, only required if `snapshot_schedule` is provided as a `Name`
+    pub project: Option<NameOrId>,
+    /// Name or ID of the snapshot schedule
+    pub snapshot_schedule: NameOrId,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct FloatingIpSelector {
+    /// Name or ID of the project, only required if `floating_ip` is provided as a `Name`
+    pub project: Option<NameOrId>,
+    /// Name or ID of the floating IP
+    pub floating_ip: NameOrId,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AffinityGroupSelector {
+    /// Name or ID of the project, only required if `affinity_group` is provided as a `Name`
+    pub project: Option<NameOrId>,
+    /// Name or ID of the affinity group
+    pub affinity_group: NameOrId,
+}
+
 /// A specialized selector for image list, it contains an extra field to indicate
 /// if silo scoped images should be included when listing project images.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
@@ -240,6 +303,16 @@ pub struct OptionalRouterSelector {
     pub router: Option<NameOrId>,
 }
 
+#[derive(Deserialize, JsonSchema)]
+pub struct PeeringSelector {
+    /// Name or ID of the project, only required if `vpc` is provided as a `Name`
+    pub project: Option<NameOrId>,
+    /// Name or ID of the VPC, only required if `peering` is provided as a `Name`
+    pub vpc: Option<NameOrId>,
+    /// Name or ID of the peering
+    pub peering: NameOrId,
+}
+
 #[derive(Deserialize, JsonSchema)]
 pub struct RouteSelector {
     /// Name or ID of the project, only required if `vpc` is provided as a `Name`
@@ -581,6 +654,46 @@ pub struct SamlIdentityProviderCreate {
     pub group_attribute_name: Option<String>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct OidcIdentityProviderCreate {
+    #[serde(flatten)]
+    pub identity: IdentityMetadataCreateParams,
+
+    /// the provider's issuer identifier, which must match the `iss` claim of
+    /// ID tokens it issues
+    pub issuer: String,
+
+    /// client id registered with the provider
+    pub client_id: String,
+
+    /// client secret registered with the provider, if the client is
+    /// confidential
+    pub client_secret: Option<String>,
+
+    /// provider endpoint where users are sent to authenticate
+    pub authorization_endpoint: String,
+
+    /// provider endpoint where authorization codes are exchanged for tokens
+    pub token_endpoint: String,
+
+    /// provider endpoint where the ID token signing keys are published
+    pub jwks_uri: String,
+
+    /// service provider endpoint where the provider redirects users back to
+    /// after authenticating
+    pub redirect_uri: String,
+
+    /// additional scopes to request during login.  `openid` is always
+    /// requested.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+
+    /// If set, ID token claims with this name will be considered to denote a
+    /// user's group membership, where the claim value should be either a list
+    /// of group names or a comma-separated list of group names.
+    pub group_claim_name: Option<String>,
+}
+
 /// sign some junk data and validate it with the key pair
 fn sign_junk_data(key_pair: &DerEncodedKeyPair) -> Result<(), anyhow::Error> {
     let private_key = {
@@ -651,6 +764,9 @@ where
 pub struct ProjectCreate {
     #[serde(flatten)]
     pub identity: IdentityMetadataCreateParams,
+    /// labels to attach to the new project
+    #[serde(default)]
+    pub labels: Labels,
 }
 
 /// Updateable properties of a `Project`
@@ -658,6 +774,26 @@ pub struct ProjectCreate {
 pub struct ProjectUpdate {
     #[serde(flatten)]
     pub identity: IdentityMetadataUpdateParams,
+    /// if present, replaces all of the labels attached to the project
+    pub labels: Option<Labels>,
+}
+
+// QUOTAS
+
+/// Limits on the virtual resources which may be provisioned within a silo or
+/// project
+///
+/// Setting quotas replaces all existing limits: any limit which is omitted is
+/// removed. A quota may be set below current usage, in which case nothing more
+/// can be provisioned until enough resources have been released.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+pub struct QuotasUpdate {
+    /// The number of virtual CPUs which may be provisioned
+    pub cpus: Option<i64>,
+    /// The amount of memory which may be provisioned
+    pub memory: Option<ByteCount>,
+    /// The amount of disk and snapshot storage which may be provisioned
+    pub storage: Option<ByteCount>,
 }
 
 // NETWORK INTERFACES
@@ -812,7 +948,31 @@ pub enum ExternalIpCreate {
     /// automatically-assigned from the provided IP Pool, or all available pools
     /// if not specified.
     Ephemeral { pool_name: Option<Name> },
-    // TODO: Add floating IPs: https://github.com/oxidecomputer/omicron/issues/1334
+    /// An existing floating IP, attached to the instance when it's created.
+    /// A floating IP given by name must be in the same project as the
+    /// instance.
+    Floating { floating_ip: NameOrId },
+}
+
+// FLOATING IPS
+
+/// Create-time parameters for a `FloatingIp`
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct FloatingIpCreate {
+    #[serde(flatten)]
+    pub identity: IdentityMetadataCreateParams,
+
+    /// The IP Pool from which to allocate the address, or the default pool
+    /// if not specified
+    pub pool: Option<NameOrId>,
+}
+
+/// Parameters for attaching a floating IP to an instance
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct FloatingIpAttach {
+    /// Name or ID of the instance, which must be stopped and in the same
+    /// project as the floating IP
+    pub instance: NameOrId,
 }
 
 /// Create-time parameters for an `Instance`
@@ -820,6 +980,9 @@ pub enum ExternalIpCreate {
 pub struct InstanceCreate {
     #[serde(flatten)]
     pub identity: IdentityMetadataCreateParams,
+    /// labels to attach to the new instance
+    #[serde(default)]
+    pub labels: Labels,
     pub ncpus: InstanceCpuCount,
     pub memory: ByteCount,
     pub hostname: String, // TODO-cleanup different type?
@@ -854,11 +1017,37 @@ pub struct InstanceCreate {
     #[serde(default)]
     pub disks: Vec<InstanceDiskAttachment>,
 
+    /// The affinity or anti-affinity groups this instance should join.
+    ///
+    /// Groups must be in the same project as the instance. Their rules are
+    /// applied when choosing the sled on which the instance runs.
+    #[serde(default)]
+    pub affinity_groups: Vec<NameOrId>,
+
+    /// Whether the control plane should start this instance again on its own
+    /// after it fails or stops; never by default.
+    #[serde(default)]
+    pub auto_restart_policy: InstanceAutoRestartPolicy,
+
     /// Should this instance be started upon creation; true by default.
     #[serde(default = "bool_true")]
     pub start: bool,
 }
 
+/// Updateable properties of an `Instance`
+///
+/// The number of CPUs, memory, and hostname may only be changed while the
+/// instance is stopped; labels may be changed at any time. Fields that are not
+/// provided keep their current values.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct InstanceUpdate {
+    pub ncpus: Option<InstanceCpuCount>,
+    pub memory: Option<ByteCount>,
+    pub hostname: Option<String>, // TODO-cleanup different type?
+    /// if present, replaces all of the labels attached to the instance
+    pub labels: Option<Labels>,
+}
+
 #[inline]
 fn bool_true() -> bool {
     true
@@ -967,6 +1156,44 @@ pub struct InstanceSerialConsoleData {
     pub last_byte_offset: u64,
 }
 
+/// Parameters for fetching an Instance's recorded serial console output.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct InstanceSerialConsoleHistoryRequest {
+    /// Name or ID of the project, only required if `instance` is provided as a `Name`
+    pub project: Option<NameOrId>,
+    /// The boot to fetch output from. Each time the instance's VMM is started on a sled is the
+    /// next boot generation, counting from 1. If not provided, the most recent boot is used.
+    pub boot_generation: Option<u64>,
+    /// Character index from which to read, counting the bytes output since the start of the boot.
+    /// At most one of this and `most_recent` may be provided.
+    pub from_start: Option<u64>,
+    /// Character index from which to read, counting *backward* from the end of the recorded
+    /// output.
+    pub most_recent: Option<u64>,
+    /// Maximum number of bytes of recorded serial console output to return.
+    pub max_bytes: Option<u64>,
+}
+
+/// Serial console output recorded for one boot of an Instance.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct InstanceSerialConsoleHistory {
+    /// The boots of the instance, oldest first. The output of the oldest boots
+    /// may no longer be recorded.
+    pub boot_generations: Vec<u64>,
+    /// The boot the output is from.
+    pub boot_generation: u64,
+    /// The recorded bytes, starting at `first_byte_offset`. Output that was never recorded, or
+    /// that has been rotated away, is skipped, so this stops at the first gap. Provided as a u8
+    /// array rather than a string, as it may not be UTF-8.
+    pub data: Vec<u8>,
+    /// The offset of the first byte of `data`. This is later than the requested offset if the
+    /// output before it is missing.
+    pub first_byte_offset: u64,
+    /// The offset just past the last byte of `data`, suitable for use as `from_start` in a
+    /// subsequent request.
+    pub last_byte_offset: u64,
+}
+
 // VPCS
 
 /// Create-time parameters for a `Vpc`
@@ -974,6 +1201,9 @@ pub struct InstanceSerialConsoleData {
 pub struct VpcCreate {
     #[serde(flatten)]
     pub identity: IdentityMetadataCreateParams,
+    /// labels to attach to the new VPC
+    #[serde(default)]
+    pub labels: Labels,
 
     /// The IPv6 prefix for this VPC
     ///
@@ -990,6 +1220,8 @@ pub struct VpcCreate {
 pub struct VpcUpdate {
     #[serde(flatten)]
     pub identity: IdentityMetadataUpdateParams,
+    /// if present, replaces all of the labels attached to the VPC
+    pub labels: Option<Labels>,
     pub dns_name: Option<Name>,
 }
 
@@ -1012,6 +1244,12 @@ pub struct VpcSubnetCreate {
     /// be assigned if one is not provided. It must not overlap with any
     /// existing subnet in the VPC.
     pub ipv6_block: Option<Ipv6Net>,
+
+    /// An optional custom router in the same VPC, whose routes apply to
+    /// traffic from this subnet in addition to those of the VPC's system
+    /// router. A custom route takes precedence over a system route with the
+    /// same destination.
+    pub custom_router: Option<NameOrId>,
 }
 
 /// Updateable properties of a `VpcSubnet`
@@ -1019,6 +1257,12 @@ pub struct VpcSubnetCreate {
 pub struct VpcSubnetUpdate {
     #[serde(flatten)]
     pub identity: IdentityMetadataUpdateParams,
+
+    /// The custom router in the same VPC whose routes apply to traffic from
+    /// this subnet. If omitted, the subnet is detached from any custom router.
+    /// Changing it is rejected while instances in the VPC are running if any
+    /// of the subnet's routes would be removed or changed.
+    pub custom_router: Option<NameOrId>,
 }
 
 // VPC ROUTERS
@@ -1037,6 +1281,26 @@ pub struct VpcRouterUpdate {
     pub identity: IdentityMetadataUpdateParams,
 }
 
+// VPC PEERINGS
+
+/// Create-time parameters for a `VpcPeering`
+///
+/// The peered VPC must be in the same silo, and none of its subnets may
+/// overlap with those of the VPC the peering is created in.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct VpcPeeringCreate {
+    #[serde(flatten)]
+    pub identity: IdentityMetadataCreateParams,
+
+    /// Name or ID of the VPC to peer with
+    pub peer_vpc: NameOrId,
+
+    /// Name or ID of the project containing the VPC to peer with, only
+    /// used if `peer_vpc` is provided as a `Name`.  Defaults to the project
+    /// of the VPC the peering is created in.
+    pub peer_project: Option<NameOrId>,
+}
+
 // VPC ROUTER ROUTES
 
 /// Create-time parameters for a `RouterRoute`
@@ -1139,6 +1403,9 @@ pub struct DiskCreate {
     /// common identifying metadata
     #[serde(flatten)]
     pub identity: IdentityMetadataCreateParams,
+    /// labels to attach to the new disk
+    #[serde(default)]
+    pub labels: Labels,
     /// initial source for this disk
     pub disk_source: DiskSource,
     /// total size of the Disk in bytes
@@ -1155,9 +1422,10 @@ pub enum ExpectedDigest {
 /// Parameters for importing blocks from a URL to a disk
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct ImportBlocksFromUrl {
-    /// the source to pull blocks from
+    /// the source to pull blocks from: a raw, qcow2, VMDK, VHD or VHDX image
     pub url: String,
-    /// Expected digest of all blocks when importing from a URL
+    /// Expected digest of the image at the URL. Only raw images can be
+    /// checked against a digest.
     pub expected_digest: Option<ExpectedDigest>,
 }
 
@@ -1179,6 +1447,36 @@ pub struct FinalizeDisk {
     pub snapshot_name: Option<Name>,
 }
 
+/// The format of an exported disk or snapshot image
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageExportFormat {
+    /// Every block of the disk, in order
+    #[default]
+    Raw,
+    /// A sparse QCOW2 image, in which blocks that are entirely zero are
+    /// omitted
+    Qcow2,
+}
+
+/// Parameters for exporting the blocks of a disk or snapshot
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ImageExport {
+    /// The format of the exported image (by default, raw)
+    #[serde(default)]
+    pub format: ImageExportFormat,
+}
+
+/// Parameters for resizing a disk
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct DiskResize {
+    /// new total size of the Disk in bytes, which must be larger than its
+    /// current size
+    pub size: ByteCount,
+}
+
 // IMAGES
 
 /// The source of the underlying image.
@@ -1230,6 +1528,10 @@ pub struct ImageCreate {
     #[serde(flatten)]
     pub identity: IdentityMetadataCreateParams,
 
+    /// labels to attach to the new image
+    #[serde(default)]
+    pub labels: Labels,
+
     /// The family of the operating system (e.g. Debian, Ubuntu, etc.)
     pub os: String,
 
@@ -1252,10 +1554,64 @@ pub struct SnapshotCreate {
     #[serde(flatten)]
     pub identity: IdentityMetadataCreateParams,
 
+    /// labels to attach to the new snapshot
+    #[serde(default)]
+    pub labels: Labels,
+
     /// The name of the disk to be snapshotted
     pub disk: Name,
 }
 
+// SNAPSHOT SCHEDULES
+
+/// Maximum number of snapshots that a snapshot schedule may retain
+pub const MAX_SNAPSHOT_SCHEDULE_RETENTION: u32 = 256;
+
+/// Create-time parameters for a `SnapshotSchedule`
+///
+/// The first snapshot is taken shortly after the schedule is created, and
+/// then every `interval_minutes` after that.  Snapshots are named after the
+/// schedule and the time at which they were taken.  Once more than
+/// `retention_count` snapshots taken by the schedule exist, the oldest ones
+/// are deleted.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct SnapshotScheduleCreate {
+    #[serde(flatten)]
+    pub identity: IdentityMetadataCreateParams,
+
+    /// Name or ID of the disk to be snapshotted
+    pub disk: NameOrId,
+
+    /// number of minutes between snapshots
+    pub interval_minutes: u32,
+
+    /// number of snapshots taken by this schedule to keep
+    pub retention_count: u32,
+}
+
+// AFFINITY GROUPS
+
+/// Create-time parameters for an `AffinityGroup`
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AffinityGroupCreate {
+    #[serde(flatten)]
+    pub identity: IdentityMetadataCreateParams,
+
+    /// Whether members of the group are placed together or apart
+    pub kind: shared::AffinityGroupKind,
+
+    /// Whether instance placement fails or proceeds when the group's rule
+    /// cannot be satisfied
+    pub policy: shared::AffinityPolicy,
+}
+
+/// Updateable properties of an `AffinityGroup`
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AffinityGroupUpdate {
+    #[serde(flatten)]
+    pub identity: IdentityMetadataUpdateParams,
+}
+
 // USERS AND GROUPS
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
@@ -1297,6 +1653,32 @@ pub struct SshKeyCreate {
     pub public_key: String,
 }
 
+// ACCESS TOKENS
+
+/// Create-time parameters for an `AccessToken`
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AccessTokenCreate {
+    #[serde(flatten)]
+    pub identity: IdentityMetadataCreateParams,
+
+    /// When the token should stop being accepted.  If not specified, the
+    /// token remains valid until it is deleted.
+    pub time_expires: Option<DateTime<Utc>>,
+}
+
+// SCIM CLIENT TOKENS
+
+/// Create-time parameters for a `ScimClientToken`
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ScimClientTokenCreate {
+    #[serde(flatten)]
+    pub identity: IdentityMetadataCreateParams,
+
+    /// When the token should stop being accepted.  If not specified, the
+    /// token remains valid until it is deleted.
+    pub time_expires: Option<DateTime<Utc>>,
+}
+
 // METRICS
 
 /// Query parameters common to resource metrics endpoints.
@@ -1308,6 +1690,23 @@ pub struct ResourceMetrics {
     pub end_time: DateTime<Utc>,
 }
 
+// AUDIT LOG
+
+/// Filters for listing audit log entries
+#[derive(
+    Clone, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq,
+)]
+pub struct AuditLogSelector {
+    /// An inclusive start time: only list requests which started at or after
+    /// this time.
+    pub start_time: Option<DateTime<Utc>>,
+    /// An exclusive end time: only list requests which started before this
+    /// time.
+    pub end_time: Option<DateTime<Utc>>,
+    /// Only list requests made by the user with this id.
+    pub actor_id: Option<Uuid>,
+}
+
 // SYSTEM UPDATE
 
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
@@ -100,6 +100,12 @@ pub enum SiloIdentityMode {
     /// groups).
     SamlJit,
 
+    /// Users are authenticated with OpenID Connect using an external
+    /// authentication provider.  As with `saml_jit`, the system updates
+    /// information about users and groups only during successful
+    /// authentication.
+    OidcJit,
+
     /// The system is the source of truth about users.  There is no linkage to
     /// an external authentication provider or identity provider.
     // NOTE: authentication for these users is not supported yet at all.  It
@@ -112,6 +118,7 @@ impl SiloIdentityMode {
         match self {
             SiloIdentityMode::LocalOnly => AuthenticationMode::Local,
             SiloIdentityMode::SamlJit => AuthenticationMode::Saml,
+            SiloIdentityMode::OidcJit => AuthenticationMode::Oidc,
         }
     }
 
@@ -119,6 +126,7 @@ impl SiloIdentityMode {
         match self {
             SiloIdentityMode::LocalOnly => UserProvisionType::ApiOnly,
             SiloIdentityMode::SamlJit => UserProvisionType::Jit,
+            SiloIdentityMode::OidcJit => UserProvisionType::Jit,
         }
     }
 }
@@ -130,6 +138,10 @@ pub enum AuthenticationMode {
     /// Authentication is via SAML using an external authentication provider
     Saml,
 
+    /// Authentication is via OpenID Connect using an external authentication
+    /// provider
+    Oidc,
+
     /// Authentication is local to the Oxide system
     Local,
 }
@@ -156,6 +168,26 @@ pub enum ServiceUsingCertificate {
     ExternalApi,
 }
 
+/// Whether the members of an affinity group should be placed together or apart
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AffinityGroupKind {
+    /// Members should be placed on the same sled.
+    Affinity,
+    /// Members should be placed on different sleds.
+    AntiAffinity,
+}
+
+/// What to do when an affinity group's rule cannot be satisfied
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AffinityPolicy {
+    /// Refuse to place the instance.
+    Fail,
+    /// Place the instance anyway, treating the rule as a preference.
+    Allow,
+}
+
 /// The kind of an external IP address for an instance
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq)]
 #[serde(rename_all = "snake_case")]
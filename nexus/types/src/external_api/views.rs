@@ -5,14 +5,15 @@
 //! Views are response bodies, most of which are public lenses onto DB models.
 
 use crate::external_api::shared::{
-    self, IpKind, IpRange, ServiceUsingCertificate,
+    self, AffinityGroupKind, AffinityPolicy, IpKind, IpRange,
+    ServiceUsingCertificate,
 };
 use crate::identity::AssetIdentityMetadata;
 use api_identity::ObjectIdentity;
 use chrono::DateTime;
 use chrono::Utc;
 use omicron_common::api::external::{
-    ByteCount, Digest, IdentityMetadata, Ipv4Net, Ipv6Net, Name,
+    ByteCount, Digest, IdentityMetadata, Ipv4Net, Ipv6Net, Labels, Name,
     ObjectIdentity, RoleName, SemverVersion,
 };
 use schemars::JsonSchema;
@@ -45,6 +46,8 @@ pub struct Silo {
 pub enum IdentityProviderType {
     /// SAML identity provider
     Saml,
+    /// OpenID Connect identity provider
+    Oidc,
 }
 
 /// View of an Identity Provider
@@ -81,6 +84,36 @@ pub struct SamlIdentityProvider {
     pub public_cert: Option<String>,
 }
 
+#[derive(ObjectIdentity, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct OidcIdentityProvider {
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+
+    /// the provider's issuer identifier
+    pub issuer: String,
+
+    /// client id registered with the provider
+    pub client_id: String,
+
+    /// provider endpoint where users are sent to authenticate
+    pub authorization_endpoint: String,
+
+    /// provider endpoint where authorization codes are exchanged for tokens
+    pub token_endpoint: String,
+
+    /// provider endpoint where the ID token signing keys are published
+    pub jwks_uri: String,
+
+    /// service provider endpoint where the provider redirects users back to
+    pub redirect_uri: String,
+
+    /// scopes requested during login
+    pub scopes: Vec<String>,
+
+    /// name of the ID token claim denoting a user's group membership
+    pub group_claim_name: Option<String>,
+}
+
 // PROJECTS
 
 /// View of a Project
@@ -90,9 +123,42 @@ pub struct Project {
     // intent in RFD 4?
     #[serde(flatten)]
     pub identity: IdentityMetadata,
+    /// labels attached to this project
+    pub labels: Labels,
     // Important: Silo ID does not get presented to user
 }
 
+// QUOTAS
+
+/// Limits on the virtual resources which may be provisioned within a silo
+///
+/// A limit which is absent is not enforced.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct SiloQuotas {
+    pub silo_id: Uuid,
+    /// The number of virtual CPUs which may be provisioned
+    pub cpus: Option<i64>,
+    /// The amount of memory which may be provisioned
+    pub memory: Option<ByteCount>,
+    /// The amount of disk and snapshot storage which may be provisioned
+    pub storage: Option<ByteCount>,
+}
+
+/// Limits on the virtual resources which may be provisioned within a project
+///
+/// A limit which is absent is not enforced. Provisioning within the project
+/// is also subject to the quotas of its silo.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct ProjectQuotas {
+    pub project_id: Uuid,
+    /// The number of virtual CPUs which may be provisioned
+    pub cpus: Option<i64>,
+    /// The amount of memory which may be provisioned
+    pub memory: Option<ByteCount>,
+    /// The amount of disk and snapshot storage which may be provisioned
+    pub storage: Option<ByteCount>,
+}
+
 // CERTIFICATES
 
 /// View of a Certificate
@@ -140,6 +206,9 @@ pub struct Image {
     #[serde(flatten)]
     pub identity: IdentityMetadata,
 
+    /// labels attached to this image
+    pub labels: Labels,
+
     /// ID of the parent project if the image is a project image
     pub project_id: Option<Uuid>,
 
@@ -179,12 +248,58 @@ pub struct Snapshot {
     #[serde(flatten)]
     pub identity: IdentityMetadata,
 
+    /// labels attached to this snapshot
+    pub labels: Labels,
+
     pub project_id: Uuid,
     pub disk_id: Uuid,
 
     pub state: SnapshotState,
 
     pub size: ByteCount,
+
+    /// the snapshot schedule that took this snapshot, if any
+    pub snapshot_schedule_id: Option<Uuid>,
+}
+
+// SNAPSHOT SCHEDULES
+
+/// View of a Snapshot Schedule
+#[derive(ObjectIdentity, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct SnapshotSchedule {
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+
+    pub project_id: Uuid,
+    /// the disk that is snapshotted
+    pub disk_id: Uuid,
+
+    /// number of minutes between snapshots
+    pub interval_minutes: u32,
+    /// number of snapshots taken by this schedule that are kept
+    pub retention_count: u32,
+
+    /// when the next snapshot is due
+    pub time_next_snapshot: DateTime<Utc>,
+    /// when this schedule last took a snapshot successfully
+    pub time_last_snapshot: Option<DateTime<Utc>>,
+    /// the error from the most recent attempt to take or expire snapshots,
+    /// if that attempt failed
+    pub last_error: Option<String>,
+}
+
+// AFFINITY GROUPS
+
+/// View of an Affinity Group
+#[derive(ObjectIdentity, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AffinityGroup {
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+
+    pub project_id: Uuid,
+
+    pub kind: AffinityGroupKind,
+    pub policy: AffinityPolicy,
 }
 
 // VPCs
@@ -195,6 +310,9 @@ pub struct Vpc {
     #[serde(flatten)]
     pub identity: IdentityMetadata,
 
+    /// labels attached to this VPC
+    pub labels: Labels,
+
     /// id for the project containing this VPC
     pub project_id: Uuid,
 
@@ -225,6 +343,9 @@ pub struct VpcSubnet {
 
     /// The IPv6 subnet CIDR block.
     pub ipv6_block: Ipv6Net,
+
+    /// The custom router whose routes apply to this subnet, if any.
+    pub custom_router_id: Option<Uuid>,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, JsonSchema)]
@@ -248,6 +369,21 @@ pub struct VpcRouter {
     pub vpc_id: Uuid,
 }
 
+/// A VPC peering connects two VPCs in the same silo, so that instances in
+/// either VPC can reach instances in the other.
+#[derive(ObjectIdentity, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct VpcPeering {
+    /// common identifying metadata
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+
+    /// The VPC from which the peering was created, and to which it belongs.
+    pub vpc_id: Uuid,
+
+    /// The VPC at the other end of the peering.
+    pub peer_vpc_id: Uuid,
+}
+
 // IP POOLS
 
 #[derive(ObjectIdentity, Clone, Debug, Deserialize, Serialize, JsonSchema)]
@@ -272,6 +408,22 @@ pub struct ExternalIp {
     pub kind: IpKind,
 }
 
+// FLOATING IPS
+
+/// A floating IP: an external IP address that belongs to a project and can
+/// be moved between the instances in it
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct FloatingIp {
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+    /// The IP address
+    pub ip: IpAddr,
+    /// The project this floating IP belongs to
+    pub project_id: Uuid,
+    /// The instance this floating IP is attached to, if any
+    pub instance_id: Option<Uuid>,
+}
+
 // RACKS
 
 /// View of an Rack
@@ -303,6 +455,44 @@ pub struct Sled {
     pub usable_hardware_threads: u32,
     /// Amount of RAM which may be used by the Sled's OS
     pub usable_physical_ram: ByteCount,
+    /// The health of the Sled, as last observed by the control plane
+    pub state: SledState,
+    /// Whether new instances may be placed on the Sled.  This is false while
+    /// the Sled is being drained.
+    pub schedulable: bool,
+}
+
+/// The health of a Sled, as observed by the control plane
+///
+/// Only active sleds are chosen to run new instances.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SledState {
+    /// The sled is responding to health checks
+    Active,
+    /// The sled has recently missed a health check
+    Degraded,
+    /// The sled has missed several health checks in a row
+    Unreachable,
+    /// An operator has permanently removed the sled from service
+    Expunged,
+}
+
+/// Progress of draining a Sled of its instances
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct SledDrainProgress {
+    /// Whether new instances may be placed on the Sled
+    pub schedulable: bool,
+    /// Number of instances that are active on the Sled and have yet to start
+    /// migrating away from it
+    pub instances_pending: u32,
+    /// Number of instances currently migrating away from the Sled
+    pub instances_migrating: u32,
+    /// Number of instances on the Sled that aren't running and so aren't
+    /// migrated. They're moved to another sled when they're next started.
+    pub instances_not_running: u32,
 }
 
 // PHYSICAL DISKS
@@ -333,6 +523,54 @@ pub struct PhysicalDisk {
     pub disk_type: PhysicalDiskType,
 }
 
+// REGION REPLACEMENTS
+
+/// Progress of replacing a lost Crucible region
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionReplacementState {
+    /// The region was lost and the volume is degraded until it's replaced
+    Requested,
+    /// A replacement region is being allocated and repaired
+    Running,
+    /// The replacement region is part of the volume, but the running instance
+    /// using the volume keeps using the old regions until it's restarted or
+    /// migrated
+    AwaitingRestart,
+    /// The replacement region has been repaired and the volume is whole again
+    Complete,
+}
+
+/// View of the replacement of a Crucible region whose physical disk, zpool or
+/// sled was lost
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct RegionReplacement {
+    pub id: Uuid,
+    pub time_created: DateTime<Utc>,
+    pub time_modified: DateTime<Utc>,
+
+    /// The disk whose volume lost a region
+    pub disk_id: Uuid,
+    pub volume_id: Uuid,
+    /// The region that was lost
+    pub old_region_id: Uuid,
+    /// The region that replaces it, once one has been allocated
+    pub new_region_id: Option<Uuid>,
+
+    pub state: RegionReplacementState,
+    /// Whether the volume is still short of a working region. This is the case
+    /// until the replacement is complete, including while it awaits the
+    /// restart of an instance that's using the volume.
+    pub degraded: bool,
+    /// The instance that must be restarted or migrated before the replacement
+    /// region is repaired, while the replacement awaits that
+    pub instance_id: Option<Uuid>,
+    /// Why the last attempt at replacing the region failed, if it did
+    pub error: Option<String>,
+}
+
 // SILO USERS
 
 /// View of a User
@@ -411,6 +649,75 @@ pub struct SshKey {
     pub public_key: String,
 }
 
+// ACCESS TOKENS
+
+/// View of an access token
+///
+/// The token itself is only revealed once, when it is created.
+#[derive(ObjectIdentity, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AccessToken {
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+
+    /// The user to whom this token belongs
+    pub silo_user_id: Uuid,
+
+    /// When the token stops being accepted, if ever
+    pub time_expires: Option<DateTime<Utc>>,
+}
+
+/// A newly-created access token, including the token itself
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AccessTokenCreated {
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+
+    /// The user to whom this token belongs
+    pub silo_user_id: Uuid,
+
+    /// When the token stops being accepted, if ever
+    pub time_expires: Option<DateTime<Utc>>,
+
+    /// The bearer token to present in the `Authorization` header.  This
+    /// cannot be retrieved again later.
+    pub access_token: String,
+}
+
+// SCIM CLIENT TOKENS
+
+/// A bearer token with which an external identity provider provisions a
+/// Silo's users and groups over SCIM
+///
+/// The token itself is only revealed once, when it is created.
+#[derive(ObjectIdentity, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ScimClientToken {
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+
+    /// The Silo whose users and groups this token can manage
+    pub silo_id: Uuid,
+
+    /// When the token stops being accepted, if ever
+    pub time_expires: Option<DateTime<Utc>>,
+}
+
+/// A newly-created SCIM client token, including the token itself
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ScimClientTokenCreated {
+    #[serde(flatten)]
+    pub identity: IdentityMetadata,
+
+    /// The Silo whose users and groups this token can manage
+    pub silo_id: Uuid,
+
+    /// When the token stops being accepted, if ever
+    pub time_expires: Option<DateTime<Utc>>,
+
+    /// The bearer token that the SCIM client presents in the `Authorization`
+    /// header.  This cannot be retrieved again later.
+    pub bearer_token: String,
+}
+
 // OAUTH 2.0 DEVICE AUTHORIZATION REQUESTS & TOKENS
 
 /// Response to an initial device authorization request.
@@ -449,6 +756,48 @@ pub enum DeviceAccessTokenType {
     Bearer,
 }
 
+// AUDIT LOG
+
+/// A record of an API request which may have modified the system
+///
+/// An entry is recorded before its request is handled, and completed with the
+/// request's result afterwards.  Completed entries form hash chains, one per
+/// Silo plus one for requests not made by a Silo user: `hash` is the SHA-256
+/// digest of the entry's contents, which include `prev_hash`, the `hash` of
+/// the entry before it in the same chain. Modifying or removing an entry
+/// breaks the chain.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    /// The entry's position in the audit log as a whole
+    pub seq: u64,
+    /// The chain to which the entry belongs: the id of the actor's Silo, or
+    /// that of the fleet for requests not made by a Silo user
+    pub chain_id: Uuid,
+    /// The entry's position in its chain, once completed
+    pub chain_seq: Option<u64>,
+    /// The time at which Nexus began handling the request
+    pub time_started: DateTime<Utc>,
+    /// The time at which Nexus finished handling the request, if it has
+    pub time_completed: Option<DateTime<Utc>>,
+    /// The unique id assigned to the request
+    pub request_id: String,
+    pub http_method: String,
+    pub request_uri: String,
+    /// The id of the authenticated user who made the request, if any
+    pub actor_id: Option<Uuid>,
+    /// The Silo of the authenticated user who made the request, if any
+    pub actor_silo_id: Option<Uuid>,
+    /// Ids of the resources referenced by the request's URI
+    pub resource_ids: Vec<Uuid>,
+    /// The HTTP status code of the response, once completed
+    pub result_code: Option<u16>,
+    /// Hex-encoded hash of the previous entry in the chain, once completed
+    pub prev_hash: Option<String>,
+    /// Hex-encoded hash of this entry, once completed
+    pub hash: Option<String>,
+}
+
 // SYSTEM UPDATES
 
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
@@ -253,6 +253,7 @@ pub type DnsConfigParams = dns_service_client::types::DnsConfigParams;
 pub type DnsConfigZone = dns_service_client::types::DnsConfigZone;
 pub type DnsRecord = dns_service_client::types::DnsRecord;
 pub type Srv = dns_service_client::types::Srv;
+pub type Mx = dns_service_client::types::Mx;
 
 #[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct RecoverySiloConfig {
@@ -163,6 +163,7 @@ impl From<omicron_common::api::external::DiskState> for types::DiskState {
             ImportingFromUrl => Self::ImportingFromUrl,
             ImportingFromBulkWrites => Self::ImportingFromBulkWrites,
             Finalizing => Self::Finalizing,
+            Exporting => Self::Exporting,
             Maintenance => Self::Maintenance,
             Attaching(u) => Self::Attaching(u),
             Attached(u) => Self::Attached(u),
@@ -195,6 +196,7 @@ impl From<types::DiskState> for omicron_common::api::external::DiskState {
             ImportingFromUrl => Self::ImportingFromUrl,
             ImportingFromBulkWrites => Self::ImportingFromBulkWrites,
             Finalizing => Self::Finalizing,
+            Exporting => Self::Exporting,
             Maintenance => Self::Maintenance,
             Attaching(u) => Self::Attaching(u),
             Attached(u) => Self::Attached(u),
@@ -458,6 +460,33 @@ impl From<omicron_common::api::internal::shared::SourceNatConfig>
     }
 }
 
+impl From<omicron_common::api::internal::shared::RouterTarget>
+    for types::RouterTarget
+{
+    fn from(s: omicron_common::api::internal::shared::RouterTarget) -> Self {
+        use omicron_common::api::internal::shared::RouterTarget::*;
+        match s {
+            Drop => Self::Drop,
+            InternetGateway => Self::InternetGateway,
+            Ip(ip) => Self::Ip(ip),
+            VpcSubnet(net) => Self::VpcSubnet(net.into()),
+            VpcPeerSubnet { vni, subnet } => {
+                Self::VpcPeerSubnet { vni: vni.into(), subnet: subnet.into() }
+            }
+        }
+    }
+}
+
+impl From<omicron_common::api::internal::shared::ResolvedVpcRoute>
+    for types::ResolvedVpcRoute
+{
+    fn from(
+        s: omicron_common::api::internal::shared::ResolvedVpcRoute,
+    ) -> Self {
+        Self { dest: s.dest.into(), target: s.target.into() }
+    }
+}
+
 /// Exposes additional [`Client`] interfaces for use by the test suite. These
 /// are bonus endpoints, not generated in the real client.
 #[async_trait]
@@ -7,12 +7,15 @@
 use crate::params::{
     DatasetEnsureBody, DiskEnsureBody, InstanceEnsureBody,
     InstancePutMigrationIdsBody, InstancePutStateBody,
-    InstancePutStateResponse, InstanceUnregisterResponse, ServiceEnsureBody,
-    SledRole, TimeSync, VpcFirewallRulesEnsureBody, Zpool,
+    InstancePutStateResponse, InstanceSerialConsoleHistory,
+    InstanceSerialConsoleHistoryQuery, InstanceUnregisterResponse,
+    ServiceEnsureBody, SledRole, TimeSync, VpcFirewallRulesEnsureBody,
+    VpcRoutesEnsureBody, Zpool,
 };
+use crucible_client_types::VolumeConstructionRequest;
 use dropshot::{
     endpoint, ApiDescription, HttpError, HttpResponseOk,
-    HttpResponseUpdatedNoContent, Path, RequestContext, TypedBody,
+    HttpResponseUpdatedNoContent, Path, Query, RequestContext, TypedBody,
 };
 use illumos_utils::opte::params::SetVirtualNetworkInterfaceHost;
 use omicron_common::api::external::Error;
@@ -32,11 +35,15 @@ pub fn api() -> SledApiDescription {
     fn register_endpoints(api: &mut SledApiDescription) -> Result<(), String> {
         api.register(disk_put)?;
         api.register(filesystem_put)?;
+        api.register(instance_issue_disk_replace_request)?;
+        api.register(instance_issue_disk_resize_request)?;
         api.register(instance_issue_disk_snapshot_request)?;
         api.register(instance_put_migration_ids)?;
         api.register(instance_put_state)?;
         api.register(instance_register)?;
+        api.register(instance_serial_console_history)?;
         api.register(instance_unregister)?;
+        api.register(instances_get)?;
         api.register(services_put)?;
         api.register(sled_role_get)?;
         api.register(set_v2p)?;
@@ -44,6 +51,7 @@ pub fn api() -> SledApiDescription {
         api.register(timesync_get)?;
         api.register(update_artifact)?;
         api.register(vpc_firewall_rules_put)?;
+        api.register(vpc_routes_put)?;
         api.register(zpools_get)?;
 
         Ok(())
@@ -119,6 +127,18 @@ struct InstancePathParam {
     instance_id: Uuid,
 }
 
+/// Lists the IDs of the instances registered on this sled.
+#[endpoint {
+    method = GET,
+    path = "/instances",
+}]
+async fn instances_get(
+    rqctx: RequestContext<SledAgent>,
+) -> Result<HttpResponseOk<Vec<Uuid>>, HttpError> {
+    let sa = rqctx.context();
+    Ok(HttpResponseOk(sa.instances_list()))
+}
+
 #[endpoint {
     method = PUT,
     path = "/instances/{instance_id}",
@@ -282,6 +302,96 @@ async fn instance_issue_disk_snapshot_request(
     }))
 }
 
+/// Read an instance's recorded serial console output
+#[endpoint {
+    method = GET,
+    path = "/instances/{instance_id}/serial-console/history",
+}]
+async fn instance_serial_console_history(
+    rqctx: RequestContext<SledAgent>,
+    path_params: Path<InstancePathParam>,
+    query_params: Query<InstanceSerialConsoleHistoryQuery>,
+) -> Result<HttpResponseOk<InstanceSerialConsoleHistory>, HttpError> {
+    let sa = rqctx.context();
+    let instance_id = path_params.into_inner().instance_id;
+    let query = query_params.into_inner();
+    Ok(HttpResponseOk(
+        sa.instance_serial_console_history(instance_id, &query).await?,
+    ))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct InstanceIssueDiskReplaceRequestPathParam {
+    instance_id: Uuid,
+    disk_id: Uuid,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct InstanceIssueDiskReplaceRequestBody {
+    volume_construction_request: VolumeConstructionRequest,
+}
+
+/// Record the volume of one of an instance's disks after one of its regions
+/// was replaced, for use the next time a Propolis is created for the instance
+#[endpoint {
+    method = POST,
+    path = "/instances/{instance_id}/disks/{disk_id}/replace",
+}]
+async fn instance_issue_disk_replace_request(
+    rqctx: RequestContext<SledAgent>,
+    path_params: Path<InstanceIssueDiskReplaceRequestPathParam>,
+    body: TypedBody<InstanceIssueDiskReplaceRequestBody>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    let path_params = path_params.into_inner();
+    let body = body.into_inner();
+
+    sa.instance_issue_disk_replace_request(
+        path_params.instance_id,
+        path_params.disk_id,
+        body.volume_construction_request,
+    )
+    .await?;
+
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct InstanceIssueDiskResizeRequestPathParam {
+    instance_id: Uuid,
+    disk_id: Uuid,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct InstanceIssueDiskResizeRequestBody {
+    volume_construction_request: VolumeConstructionRequest,
+}
+
+/// Record the grown volume of one of an instance's disks, for use the next
+/// time a Propolis is created for the instance
+#[endpoint {
+    method = POST,
+    path = "/instances/{instance_id}/disks/{disk_id}/resize",
+}]
+async fn instance_issue_disk_resize_request(
+    rqctx: RequestContext<SledAgent>,
+    path_params: Path<InstanceIssueDiskResizeRequestPathParam>,
+    body: TypedBody<InstanceIssueDiskResizeRequestBody>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    let path_params = path_params.into_inner();
+    let body = body.into_inner();
+
+    sa.instance_issue_disk_resize_request(
+        path_params.instance_id,
+        path_params.disk_id,
+        body.volume_construction_request,
+    )
+    .await?;
+
+    Ok(HttpResponseUpdatedNoContent())
+}
+
 /// Path parameters for VPC requests (sled agent API)
 #[derive(Deserialize, JsonSchema)]
 struct VpcPathParam {
@@ -308,6 +418,27 @@ async fn vpc_firewall_rules_put(
     Ok(HttpResponseUpdatedNoContent())
 }
 
+/// Replace the route tables of a VPC's subnets
+#[endpoint {
+    method = PUT,
+    path = "/vpc/{vpc_id}/routes",
+}]
+async fn vpc_routes_put(
+    rqctx: RequestContext<SledAgent>,
+    path_params: Path<VpcPathParam>,
+    body: TypedBody<VpcRoutesEnsureBody>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    let vpc_id = path_params.into_inner().vpc_id;
+    let body_args = body.into_inner();
+
+    sa.vpc_routes_ensure(vpc_id, body_args.vni, &body_args.subnets[..])
+        .await
+        .map_err(Error::from)?;
+
+    Ok(HttpResponseUpdatedNoContent())
+}
+
 /// Path parameters for V2P mapping related requests (sled agent API)
 #[allow(dead_code)]
 #[derive(Deserialize, JsonSchema)]
@@ -11,7 +11,10 @@ use crate::params::{
     InstanceHardware, InstanceMigrationSourceParams,
     InstanceMigrationTargetParams, InstanceStateRequested, VpcFirewallRule,
 };
+use crate::serial_console::SerialConsoleRecorder;
+use crate::storage_manager::StorageResources;
 use anyhow::anyhow;
+use crucible_client_types::VolumeConstructionRequest;
 use futures::lock::{Mutex, MutexGuard};
 use illumos_utils::dladm::Etherstub;
 use illumos_utils::link::VnicAllocator;
@@ -93,6 +96,9 @@ pub enum Error {
 
     #[error("Instance already registered with Propolis ID {0}")]
     InstanceAlreadyRegistered(Uuid),
+
+    #[error("Disk {0} is not attached to this instance")]
+    NoSuchDisk(Uuid),
 }
 
 // Issues read-only, idempotent HTTP requests at propolis until it responds with
@@ -167,6 +173,9 @@ struct RunningState {
     monitor_task: Option<JoinHandle<()>>,
     // Handle to the zone.
     running_zone: RunningZone,
+    // Recorder of the instance's serial console output, if one could be
+    // started.
+    serial_console_recorder: Option<SerialConsoleRecorder>,
 }
 
 impl Drop for RunningState {
@@ -227,6 +236,9 @@ struct InstanceInner {
     requested_disks: Vec<propolis_client::handmade::api::DiskRequest>,
     cloud_init_bytes: Option<String>,
 
+    // The boot generation Nexus assigned to this instance's Propolis
+    boot_generation: u64,
+
     // Internal State management
     state: InstanceStates,
     running_state: Option<RunningState>,
@@ -234,6 +246,10 @@ struct InstanceInner {
     // Connection to Nexus
     lazy_nexus_client: LazyNexusClient,
 
+    // Storage resources, used to find the debug datasets that serial console
+    // output is recorded to
+    storage: StorageResources,
+
     // Object representing membership in the "instance manager".
     instance_ticket: InstanceTicket,
 }
@@ -407,8 +423,35 @@ impl InstanceInner {
             }
         }));
 
-        self.running_state =
-            Some(RunningState { client, monitor_task, running_zone });
+        // Record the instance's serial console output, so that it outlives
+        // Propolis's buffer. Failing to do so shouldn't stop the instance from
+        // running.
+        let debug_dirs = self
+            .storage
+            .all_u2_mountpoints(sled_hardware::disk::DEBUG_DATASET)
+            .await;
+        let serial_console_recorder = match SerialConsoleRecorder::start(
+            &self.log,
+            &debug_dirs,
+            *self.id(),
+            self.boot_generation,
+            SocketAddr::new(self.propolis_ip, PROPOLIS_PORT),
+        )
+        .await
+        {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                warn!(self.log, "failed to record serial console"; "error" => %e);
+                None
+            }
+        };
+
+        self.running_state = Some(RunningState {
+            client,
+            monitor_task,
+            running_zone,
+            serial_console_recorder,
+        });
 
         Ok(())
     }
@@ -446,6 +489,16 @@ impl InstanceInner {
     /// This routine is safe to call even if the instance's zone was never
     /// started. It is also safe to call multiple times on a single instance.
     async fn terminate(&mut self) -> Result<(), Error> {
+        // Collect the last of the serial console output while Propolis is
+        // still around to ask.
+        if let Some(recorder) = self
+            .running_state
+            .as_mut()
+            .and_then(|state| state.serial_console_recorder.take())
+        {
+            recorder.stop().await;
+        }
+
         // Ensure that no zone exists. This succeeds even if no zone was ever
         // created.
         // NOTE: we call`Zones::halt_and_remove_logged` directly instead of
@@ -496,6 +549,7 @@ mockall::mock! {
             vnic_allocator: VnicAllocator<Etherstub>,
             port_manager: PortManager,
             lazy_nexus_client: LazyNexusClient,
+            storage: StorageResources,
         ) -> Result<Self, Error>;
         pub async fn current_state(&self) -> InstanceRuntimeState;
         pub async fn put_state(
@@ -512,6 +566,16 @@ mockall::mock! {
             disk_id: Uuid,
             snapshot_name: Uuid,
         ) -> Result<(), Error>;
+        pub async fn issue_disk_resize_request(
+            &self,
+            disk_id: Uuid,
+            volume_construction_request: VolumeConstructionRequest,
+        ) -> Result<(), Error>;
+        pub async fn issue_disk_replace_request(
+            &self,
+            disk_id: Uuid,
+            volume_construction_request: VolumeConstructionRequest,
+        ) -> Result<(), Error>;
         pub async fn terminate(&self) -> Result<InstanceRuntimeState, Error>;
     }
     impl Clone for Instance {
@@ -533,6 +597,8 @@ impl Instance {
     /// * `port_manager`: Handle to the object responsible for managing OPTE
     /// ports.
     /// * `lazy_nexus_client`: Connection to Nexus, used for sending notifications.
+    /// * `storage`: Storage resources, used to find where to record the
+    /// instance's serial console.
     // TODO: This arg list is getting a little long; can we clean this up?
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -543,6 +609,7 @@ impl Instance {
         vnic_allocator: VnicAllocator<Etherstub>,
         port_manager: PortManager,
         lazy_nexus_client: LazyNexusClient,
+        storage: StorageResources,
     ) -> Result<Self, Error> {
         info!(log, "Instance::new w/initial HW: {:?}", initial);
         let instance = InstanceInner {
@@ -570,9 +637,11 @@ impl Instance {
             firewall_rules: initial.firewall_rules,
             requested_disks: initial.disks,
             cloud_init_bytes: initial.cloud_init_bytes,
+            boot_generation: initial.boot_generation,
             state: InstanceStates::new(initial.runtime),
             running_state: None,
             lazy_nexus_client,
+            storage,
             instance_ticket: ticket,
         };
 
@@ -983,6 +1052,71 @@ impl Instance {
             Err(Error::InstanceNotRunning(inner.properties.id))
         }
     }
+
+    /// Records a new volume construction request for a disk attached to this
+    /// instance after the disk has grown.
+    ///
+    /// The new request is used the next time a Propolis is ensured for this
+    /// instance (e.g. as a migration target), so that the larger volume is
+    /// presented to the guest from then on.
+    pub async fn issue_disk_resize_request(
+        &self,
+        disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        self.set_requested_disk_volume(disk_id, volume_construction_request)
+            .await
+    }
+
+    /// Records a new volume construction request for a disk attached to this
+    /// instance after one of the disk's regions was replaced.
+    ///
+    /// The running Propolis keeps using the regions it was started with until
+    /// the instance is restarted or migrated, when the new request is used.
+    pub async fn issue_disk_replace_request(
+        &self,
+        disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        self.set_requested_disk_volume(disk_id, volume_construction_request)
+            .await
+    }
+
+    /// Replaces the volume construction request of one of the disks to be
+    /// attached to the next Propolis created for this instance
+    async fn set_requested_disk_volume(
+        &self,
+        disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        let mut inner = self.inner.lock().await;
+
+        // The requested disks carry Propolis' copy of the volume construction
+        // request type rather than Crucible's, so take a round trip through
+        // JSON to compare and replace them.
+        let mut found = false;
+        for disk in inner.requested_disks.iter_mut() {
+            let vcr: VolumeConstructionRequest = serde_json::from_value(
+                serde_json::to_value(&disk.volume_construction_request)?,
+            )?;
+            if matches!(
+                vcr,
+                VolumeConstructionRequest::Volume { id, .. } if id == disk_id
+            ) {
+                disk.volume_construction_request = serde_json::from_value(
+                    serde_json::to_value(&volume_construction_request)?,
+                )?;
+                found = true;
+                break;
+            }
+        }
+
+        if found {
+            Ok(())
+        } else {
+            Err(Error::NoSuchDisk(disk_id))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -991,6 +1125,7 @@ mod test {
     use crate::instance_manager::InstanceManager;
     use crate::nexus::LazyNexusClient;
     use crate::params::InstanceStateRequested;
+    use crate::storage_manager::StorageManager;
     use chrono::Utc;
     use illumos_utils::dladm::Etherstub;
     use illumos_utils::opte::PortManager;
@@ -1040,6 +1175,7 @@ mod test {
             firewall_rules: vec![],
             disks: vec![],
             cloud_init_bytes: None,
+            boot_generation: 1,
         }
     }
 
@@ -1069,11 +1205,13 @@ mod test {
         let lazy_nexus_client =
             LazyNexusClient::new(log.clone(), std::net::Ipv6Addr::LOCALHOST)
                 .unwrap();
+        let storage = StorageManager::new(log).await.resources().clone();
         let instance_manager = InstanceManager::new(
             log.clone(),
             lazy_nexus_client.clone(),
             Etherstub("mylink".to_string()),
             port_manager.clone(),
+            storage.clone(),
         )
         .unwrap();
 
@@ -1085,6 +1223,7 @@ mod test {
             vnic_allocator,
             port_manager,
             lazy_nexus_client,
+            storage,
         )
         .unwrap();
 
@@ -8,11 +8,15 @@ use crate::nexus::LazyNexusClient;
 use crate::params::{
     InstanceHardware, InstanceMigrationSourceParams, InstancePutStateResponse,
     InstanceStateRequested, InstanceUnregisterResponse, VpcFirewallRule,
+    VpcSubnetRoutes,
 };
+use crate::storage_manager::StorageResources;
+use crucible_client_types::VolumeConstructionRequest;
 use illumos_utils::dladm::Etherstub;
 use illumos_utils::link::VnicAllocator;
 use illumos_utils::opte::params::SetVirtualNetworkInterfaceHost;
 use illumos_utils::opte::PortManager;
+use omicron_common::api::external::Vni;
 use omicron_common::api::internal::nexus::InstanceRuntimeState;
 use slog::Logger;
 use std::collections::BTreeMap;
@@ -51,6 +55,7 @@ struct InstanceManagerInternal {
 
     vnic_allocator: VnicAllocator<Etherstub>,
     port_manager: PortManager,
+    storage: StorageResources,
 }
 
 /// All instances currently running on the sled.
@@ -65,6 +70,7 @@ impl InstanceManager {
         lazy_nexus_client: LazyNexusClient,
         etherstub: Etherstub,
         port_manager: PortManager,
+        storage: StorageResources,
     ) -> Result<InstanceManager, Error> {
         Ok(InstanceManager {
             inner: Arc::new(InstanceManagerInternal {
@@ -73,10 +79,16 @@ impl InstanceManager {
                 instances: Mutex::new(BTreeMap::new()),
                 vnic_allocator: VnicAllocator::new("Instance", etherstub),
                 port_manager,
+                storage,
             }),
         })
     }
 
+    /// Returns the IDs of all of the registered instances.
+    pub fn instance_ids(&self) -> Vec<Uuid> {
+        self.inner.instances.lock().unwrap().keys().copied().collect()
+    }
+
     /// Ensures that the instance manager contains a registered instance with
     /// the supplied instance ID and the Propolis ID specified in
     /// `initial_hardware`.
@@ -144,6 +156,7 @@ impl InstanceManager {
                     self.inner.vnic_allocator.clone(),
                     self.inner.port_manager.clone(),
                     self.inner.lazy_nexus_client.clone(),
+                    self.inner.storage.clone(),
                 )?;
                 let instance_clone = instance.clone();
                 let _old = instances
@@ -259,6 +272,46 @@ impl InstanceManager {
             .map_err(Error::from)
     }
 
+    pub async fn instance_issue_disk_resize_request(
+        &self,
+        instance_id: Uuid,
+        disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        let instance = {
+            let instances = self.inner.instances.lock().unwrap();
+            let (_, instance) = instances
+                .get(&instance_id)
+                .ok_or(Error::NoSuchInstance(instance_id))?;
+            instance.clone()
+        };
+
+        instance
+            .issue_disk_resize_request(disk_id, volume_construction_request)
+            .await
+            .map_err(Error::from)
+    }
+
+    pub async fn instance_issue_disk_replace_request(
+        &self,
+        instance_id: Uuid,
+        disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        let instance = {
+            let instances = self.inner.instances.lock().unwrap();
+            let (_, instance) = instances
+                .get(&instance_id)
+                .ok_or(Error::NoSuchInstance(instance_id))?;
+            instance.clone()
+        };
+
+        instance
+            .issue_disk_replace_request(disk_id, volume_construction_request)
+            .await
+            .map_err(Error::from)
+    }
+
     pub async fn firewall_rules_ensure(
         &self,
         rules: &[VpcFirewallRule],
@@ -272,6 +325,21 @@ impl InstanceManager {
         Ok(())
     }
 
+    pub async fn vpc_routes_ensure(
+        &self,
+        vni: Vni,
+        subnets: &[VpcSubnetRoutes],
+    ) -> Result<(), Error> {
+        info!(
+            &self.inner.log,
+            "Ensuring VPC routes";
+            "vni" => ?vni,
+            "subnets" => ?&subnets,
+        );
+        self.inner.port_manager.vpc_routes_ensure(vni, subnets)?;
+        Ok(())
+    }
+
     pub async fn set_virtual_nic_host(
         &self,
         mapping: &SetVirtualNetworkInterfaceHost,
@@ -342,6 +410,7 @@ mod test {
     use crate::instance::MockInstance;
     use crate::nexus::LazyNexusClient;
     use crate::params::InstanceStateRequested;
+    use crate::storage_manager::StorageManager;
     use chrono::Utc;
     use illumos_utils::dladm::Etherstub;
     use illumos_utils::{dladm::MockDladm, zone::MockZones};
@@ -386,6 +455,7 @@ mod test {
             firewall_rules: vec![],
             disks: vec![],
             cloud_init_bytes: None,
+            boot_generation: 1,
         }
     }
 
@@ -418,6 +488,7 @@ mod test {
             lazy_nexus_client,
             Etherstub("mylink".to_string()),
             port_manager,
+            StorageManager::new(log).await.resources().clone(),
         )
         .unwrap();
 
@@ -441,7 +512,7 @@ mod test {
         // Expect one call to new() that produces an instance that expects to be
         // cloned once. The clone should expect to ask to be put into the
         // Running state.
-        instance_new_ctx.expect().return_once(move |_, _, t, _, _, _, _| {
+        instance_new_ctx.expect().return_once(move |_, _, t, _, _, _, _, _| {
             let mut inst = MockInstance::default();
 
             // Move the instance ticket out to the test, since the mock instance
@@ -533,6 +604,7 @@ mod test {
             lazy_nexus_client,
             Etherstub("mylink".to_string()),
             port_manager,
+            StorageManager::new(log).await.resources().clone(),
         )
         .unwrap();
 
@@ -540,7 +612,7 @@ mod test {
         let ticket_clone = ticket.clone();
         let instance_new_ctx = MockInstance::new_context();
         let mut seq = mockall::Sequence::new();
-        instance_new_ctx.expect().return_once(move |_, _, t, _, _, _, _| {
+        instance_new_ctx.expect().return_once(move |_, _, t, _, _, _, _, _| {
             let mut inst = MockInstance::default();
             let mut ticket_guard = ticket_clone.lock().unwrap();
             *ticket_guard = Some(t);
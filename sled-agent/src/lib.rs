@@ -28,6 +28,7 @@ pub mod params;
 mod profile;
 pub mod rack_setup;
 pub mod server;
+mod serial_console;
 mod services;
 mod sled_agent;
 mod smf_helper;
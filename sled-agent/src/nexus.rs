@@ -145,5 +145,23 @@ fn d2n_record(
                 weight: srv.weight,
             })
         }
+        dns_service_client::types::DnsRecord::Cname(target) => {
+            nexus_client::types::DnsRecord::Cname(target.clone())
+        }
+        dns_service_client::types::DnsRecord::Txt(strings) => {
+            nexus_client::types::DnsRecord::Txt(strings.clone())
+        }
+        dns_service_client::types::DnsRecord::Ptr(target) => {
+            nexus_client::types::DnsRecord::Ptr(target.clone())
+        }
+        dns_service_client::types::DnsRecord::Mx(mx) => {
+            nexus_client::types::DnsRecord::Mx(nexus_client::types::Mx {
+                exchange: mx.exchange.clone(),
+                preference: mx.preference,
+            })
+        }
+        dns_service_client::types::DnsRecord::Ns(target) => {
+            nexus_client::types::DnsRecord::Ns(target.clone())
+        }
     }
 }
@@ -18,6 +18,8 @@ use uuid::Uuid;
 
 pub use illumos_utils::opte::params::VpcFirewallRule;
 pub use illumos_utils::opte::params::VpcFirewallRulesEnsureBody;
+pub use illumos_utils::opte::params::VpcRoutesEnsureBody;
+pub use illumos_utils::opte::params::VpcSubnetRoutes;
 pub use sled_hardware::DendriteAsic;
 
 /// Used to request a Disk state change
@@ -66,6 +68,9 @@ pub struct InstanceHardware {
     // TODO: replace `propolis_client::handmade::*` with locally-modeled request type
     pub disks: Vec<propolis_client::handmade::api::DiskRequest>,
     pub cloud_init_bytes: Option<String>,
+    /// The boot generation Nexus assigned to the Propolis this registration is
+    /// for, under which its serial console output is recorded.
+    pub boot_generation: u64,
 }
 
 /// The body of a request to ensure that an instance is known to a sled agent.
@@ -184,6 +189,41 @@ pub struct InstancePutMigrationIdsBody {
     pub migration_params: Option<InstanceMigrationSourceParams>,
 }
 
+/// Parameters for reading an instance's recorded serial console output.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct InstanceSerialConsoleHistoryQuery {
+    /// The boot to read output from, as numbered by Nexus when it registered
+    /// the instance. Defaults to the most recent boot recorded on this sled.
+    pub boot_generation: Option<u64>,
+    /// Offset from which to read, counting the bytes output since the start
+    /// of the boot. At most one of this and `most_recent` may be provided.
+    pub from_start: Option<u64>,
+    /// Offset from which to read, counting backward from the end of the
+    /// recorded output.
+    pub most_recent: Option<u64>,
+    /// Maximum number of bytes to return.
+    pub max_bytes: Option<u64>,
+}
+
+/// Serial console output recorded for one boot of an instance.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct InstanceSerialConsoleHistory {
+    /// The boots of the instance with output recorded on this sled, oldest
+    /// first.
+    pub boot_generations: Vec<u64>,
+    /// The boot the output is from.
+    pub boot_generation: u64,
+    /// The recorded bytes. Output that was never recorded, or that has since
+    /// been rotated away, is skipped, so this stops at the first gap.
+    pub data: Vec<u8>,
+    /// The offset of the first byte of `data`, which may be later than the
+    /// requested offset if output before it is missing.
+    pub first_byte_offset: u64,
+    /// The offset just past the last byte of `data`, suitable for use as
+    /// `from_start` in a subsequent request.
+    pub last_byte_offset: u64,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
 pub enum DiskType {
     U2,
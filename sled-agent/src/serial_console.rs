@@ -0,0 +1,640 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Recording of instance serial console output
+//!
+//! Propolis only keeps a bounded buffer of an instance's serial console
+//! output, which is lost once it wraps or the Propolis goes away. While an
+//! instance runs, a recorder polls that buffer and appends the output to files
+//! on a U.2 debug dataset, so that it can be read back later by boot
+//! generation and byte range.
+//!
+//! An instance's output lives under `serial-console/<instance id>/<boot>`,
+//! where the boot generation is the one Nexus assigned to the Propolis when it
+//! registered the instance here. Nexus counts boots across all sleds, so the
+//! boots recorded on any one sled may have gaps between them. A boot's output is split into segment files named after
+//! the offset of their first byte. The oldest segments of a boot, and the
+//! oldest boots of an instance, are removed to bound the space used.
+
+use crate::params::InstanceSerialConsoleHistory;
+use crate::params::InstanceSerialConsoleHistoryQuery;
+use camino::{Utf8Path, Utf8PathBuf};
+use propolis_client::Client as PropolisClient;
+use slog::Logger;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Directory within a debug dataset holding serial console recordings
+const SERIAL_CONSOLE_DIR: &str = "serial-console";
+
+/// Size at which a boot's current segment is closed and a new one started
+const SEGMENT_SIZE: u64 = 1 << 20;
+
+/// Number of segments kept for each boot
+const MAX_SEGMENTS_PER_BOOT: usize = 8;
+
+/// Number of boots kept for each instance
+const MAX_BOOTS_PER_INSTANCE: usize = 4;
+
+/// Interval between polls of Propolis for new output
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of bytes requested from Propolis in one poll
+const POLL_MAX_BYTES: u64 = 64 * 1024;
+
+/// Timeout for requests made to Propolis by the recorder
+const POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time allowed for a recorder to collect its last output when it stops
+const STOP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Maximum number of bytes returned by one read of the recorded history
+const MAX_READ_BYTES: u64 = 1 << 20;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("No serial console output is recorded for instance {0}")]
+    NoRecording(Uuid),
+
+    #[error(
+        "No serial console output is recorded for boot {boot_generation} of \
+        instance {instance_id}"
+    )]
+    NoSuchBoot { instance_id: Uuid, boot_generation: u64 },
+
+    #[error("At most one of from_start and most_recent may be provided")]
+    ConflictingOffsets,
+
+    #[error("No debug dataset is available to record serial console output")]
+    NoDebugDataset,
+
+    #[error("Failed to perform I/O: {message}: {err}")]
+    Io {
+        message: String,
+        #[source]
+        err: std::io::Error,
+    },
+}
+
+impl Error {
+    fn io_path(path: &Utf8Path, err: std::io::Error) -> Self {
+        Self::Io { message: format!("Error accessing {}", path), err }
+    }
+}
+
+impl From<Error> for dropshot::HttpError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoRecording(_) | Error::NoSuchBoot { .. } => {
+                dropshot::HttpError::for_not_found(None, err.to_string())
+            }
+            Error::ConflictingOffsets => {
+                dropshot::HttpError::for_bad_request(None, err.to_string())
+            }
+            e => dropshot::HttpError::for_internal_error(e.to_string()),
+        }
+    }
+}
+
+fn instance_dir(debug_dir: &Utf8Path, instance_id: Uuid) -> Utf8PathBuf {
+    debug_dir.join(SERIAL_CONSOLE_DIR).join(instance_id.to_string())
+}
+
+/// Returns the numerically named entries of a directory, boot generations or
+/// segment offsets, in order. A directory that doesn't exist has no entries.
+async fn numbered_entries(dir: &Utf8Path) -> Result<Vec<u64>, Error> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(vec![]);
+        }
+        Err(err) => return Err(Error::io_path(dir, err)),
+    };
+    let mut numbers = vec![];
+    while let Some(entry) =
+        entries.next_entry().await.map_err(|err| Error::io_path(dir, err))?
+    {
+        if let Some(number) =
+            entry.file_name().to_str().and_then(|name| name.parse().ok())
+        {
+            numbers.push(number);
+        }
+    }
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+/// Returns the directories of an instance's recorded boots, across all debug
+/// datasets, by boot generation.
+async fn recorded_boots(
+    debug_dirs: &[Utf8PathBuf],
+    instance_id: Uuid,
+) -> Result<BTreeMap<u64, Utf8PathBuf>, Error> {
+    let mut boots = BTreeMap::new();
+    for debug_dir in debug_dirs {
+        let dir = instance_dir(debug_dir, instance_id);
+        for boot in numbered_entries(&dir).await? {
+            boots.insert(boot, dir.join(boot.to_string()));
+        }
+    }
+    Ok(boots)
+}
+
+/// The segment of a boot's output currently being appended to
+struct Segment {
+    start: u64,
+    len: u64,
+    file: tokio::fs::File,
+}
+
+/// The output of one boot of an instance, as it is being recorded
+struct BootRecording {
+    dir: Utf8PathBuf,
+    segment: Option<Segment>,
+}
+
+impl BootRecording {
+    /// Creates the directory for a new boot of an instance, removing the
+    /// instance's oldest boots on this sled to make room for it.
+    async fn new(
+        debug_dirs: &[Utf8PathBuf],
+        instance_id: Uuid,
+        boot_generation: u64,
+    ) -> Result<Self, Error> {
+        let mut boots = recorded_boots(debug_dirs, instance_id).await?;
+        let existing = boots.remove(&boot_generation);
+
+        // Keep an instance's boots on the dataset they're already on if it's
+        // still present.
+        let debug_dir = existing
+            .iter()
+            .chain(boots.values().next_back())
+            .find_map(|boot_dir| {
+                debug_dirs.iter().find(|d| boot_dir.starts_with(d))
+            })
+            .or_else(|| debug_dirs.first())
+            .ok_or(Error::NoDebugDataset)?;
+
+        let expired = (boots.len() + 1).saturating_sub(MAX_BOOTS_PER_INSTANCE);
+        for old_dir in boots.values().take(expired) {
+            tokio::fs::remove_dir_all(old_dir)
+                .await
+                .map_err(|err| Error::io_path(old_dir, err))?;
+        }
+
+        let dir = instance_dir(debug_dir, instance_id)
+            .join(boot_generation.to_string());
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|err| Error::io_path(&dir, err))?;
+        Ok(BootRecording { dir, segment: None })
+    }
+
+    /// Appends output starting at the given offset. Output that doesn't
+    /// follow on from what was last appended starts a new segment, leaving a
+    /// gap in the recording.
+    async fn append(&mut self, offset: u64, data: &[u8]) -> Result<(), Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let continues = self.segment.as_ref().map_or(false, |segment| {
+            segment.start + segment.len == offset && segment.len < SEGMENT_SIZE
+        });
+        if !continues {
+            let path = self.dir.join(offset.to_string());
+            let file = tokio::fs::File::create(&path)
+                .await
+                .map_err(|err| Error::io_path(&path, err))?;
+            self.segment = Some(Segment { start: offset, len: 0, file });
+            self.remove_old_segments().await?;
+        }
+
+        let segment = self.segment.as_mut().unwrap();
+        let path = self.dir.join(segment.start.to_string());
+        segment
+            .file
+            .write_all(data)
+            .await
+            .map_err(|err| Error::io_path(&path, err))?;
+        segment.file.flush().await.map_err(|err| Error::io_path(&path, err))?;
+        segment.len += data.len() as u64;
+        Ok(())
+    }
+
+    async fn remove_old_segments(&self) -> Result<(), Error> {
+        let segments = numbered_entries(&self.dir).await?;
+        let expired = segments.len().saturating_sub(MAX_SEGMENTS_PER_BOOT);
+        for start in &segments[..expired] {
+            let path = self.dir.join(start.to_string());
+            tokio::fs::remove_file(&path)
+                .await
+                .map_err(|err| Error::io_path(&path, err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Records an instance's serial console output until it is stopped or
+/// dropped.
+pub struct SerialConsoleRecorder {
+    boot_generation: u64,
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl SerialConsoleRecorder {
+    /// Starts recording the output of a newly started Propolis as the given
+    /// boot of the instance. Boot generations are assigned by Nexus, which
+    /// knows about the instance's boots on every sled.
+    pub async fn start(
+        log: &Logger,
+        debug_dirs: &[Utf8PathBuf],
+        instance_id: Uuid,
+        boot_generation: u64,
+        propolis_addr: SocketAddr,
+    ) -> Result<Self, Error> {
+        let recording =
+            BootRecording::new(debug_dirs, instance_id, boot_generation)
+                .await?;
+        let log = log.new(o!(
+            "component" => "SerialConsoleRecorder",
+            "boot_generation" => boot_generation,
+        ));
+        info!(log, "recording serial console"; "dir" => %recording.dir);
+
+        // The client used to manage the instance waits indefinitely, but
+        // polls that hang shouldn't hold up stopping the recorder.
+        let client = reqwest::ClientBuilder::new()
+            .connect_timeout(POLL_TIMEOUT)
+            .timeout(POLL_TIMEOUT)
+            .build()
+            .unwrap();
+        let client = PropolisClient::new_with_client(
+            &format!("http://{}", propolis_addr),
+            client,
+        );
+
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(record(log, client, recording, stop_rx));
+        Ok(Self { boot_generation, stop: Some(stop_tx), task: Some(task) })
+    }
+
+    pub fn boot_generation(&self) -> u64 {
+        self.boot_generation
+    }
+
+    /// Collects any output not yet recorded, then stops recording.
+    pub async fn stop(mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(task) = self.task.take() {
+            let _ = tokio::time::timeout(STOP_TIMEOUT, task).await;
+        }
+    }
+}
+
+impl Drop for SerialConsoleRecorder {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+async fn record(
+    log: Logger,
+    client: PropolisClient,
+    mut recording: BootRecording,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let mut offset = 0;
+    let mut resync = false;
+    let mut stopping = false;
+    loop {
+        let request = client.instance_serial_history_get();
+        let request = if resync {
+            request.most_recent(POLL_MAX_BYTES)
+        } else {
+            request.from_start(offset)
+        };
+        let more = match request.max_bytes(POLL_MAX_BYTES).send().await {
+            Ok(history) => {
+                let history = history.into_inner();
+                let len = history.data.len() as u64;
+                let start = history.last_byte_offset.saturating_sub(len);
+                if start != offset {
+                    warn!(
+                        log,
+                        "serial console output was lost before it could be \
+                        recorded";
+                        "from" => offset,
+                        "to" => start,
+                    );
+                }
+                if let Err(e) = recording.append(start, &history.data).await {
+                    warn!(log, "failed to record serial console output";
+                        "error" => %e);
+                }
+                offset = history.last_byte_offset;
+                resync = false;
+                len == POLL_MAX_BYTES
+            }
+            Err(e) => {
+                // If Propolis rejects the offset, its buffer has most likely
+                // wrapped past it; pick up from its most recent output
+                // instead.
+                if e.status().map_or(false, |s| s.is_client_error()) {
+                    resync = true;
+                }
+                debug!(log, "failed to read serial console history";
+                    "error" => %e);
+                false
+            }
+        };
+
+        if more {
+            continue;
+        }
+        if stopping {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            _ = &mut stop => stopping = true,
+        }
+    }
+    info!(log, "stopped recording serial console"; "offset" => offset);
+}
+
+/// Reads recorded serial console output for an instance from the debug
+/// datasets.
+pub async fn read_history(
+    debug_dirs: &[Utf8PathBuf],
+    instance_id: Uuid,
+    query: &InstanceSerialConsoleHistoryQuery,
+) -> Result<InstanceSerialConsoleHistory, Error> {
+    if query.from_start.is_some() && query.most_recent.is_some() {
+        return Err(Error::ConflictingOffsets);
+    }
+
+    let boots = recorded_boots(debug_dirs, instance_id).await?;
+    let boot_generation = match query.boot_generation {
+        Some(boot_generation) => boot_generation,
+        None => {
+            *boots.keys().next_back().ok_or(Error::NoRecording(instance_id))?
+        }
+    };
+    let dir = boots
+        .get(&boot_generation)
+        .ok_or(Error::NoSuchBoot { instance_id, boot_generation })?;
+
+    // Find the extent of each segment. The last one may still be growing, so
+    // the lengths used are those seen here.
+    let mut segments = vec![];
+    for start in numbered_entries(dir).await? {
+        let path = dir.join(start.to_string());
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) => segments.push((start, metadata.len())),
+            // The recorder may have removed it since it was listed.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(Error::io_path(&path, err)),
+        }
+    }
+    let end = segments.last().map_or(0, |(start, len)| start + len);
+
+    let requested = match (query.from_start, query.most_recent) {
+        (Some(from_start), _) => from_start,
+        (None, Some(most_recent)) => end.saturating_sub(most_recent),
+        (None, None) => 0,
+    };
+    let max_bytes =
+        query.max_bytes.unwrap_or(MAX_READ_BYTES).min(MAX_READ_BYTES);
+
+    // Output that was never recorded, or has been removed, is skipped over:
+    // the data starts at the first recorded byte at or after the requested
+    // offset, and stops at the next gap.
+    let mut data = vec![];
+    let mut first_byte_offset = None;
+    for (start, len) in segments {
+        let segment_end = start + len;
+        if segment_end <= requested || len == 0 {
+            continue;
+        }
+        let offset = start.max(requested);
+        let first = *first_byte_offset.get_or_insert(offset);
+        if offset != first + data.len() as u64 {
+            break;
+        }
+        let remaining = max_bytes - data.len() as u64;
+        if remaining == 0 {
+            break;
+        }
+        let n = (segment_end - offset).min(remaining);
+
+        let path = dir.join(start.to_string());
+        let mut file = match tokio::fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => break,
+            Err(err) => return Err(Error::io_path(&path, err)),
+        };
+        file.seek(std::io::SeekFrom::Start(offset - start))
+            .await
+            .map_err(|err| Error::io_path(&path, err))?;
+        let mut buf = vec![0; n as usize];
+        file.read_exact(&mut buf)
+            .await
+            .map_err(|err| Error::io_path(&path, err))?;
+        data.extend_from_slice(&buf);
+    }
+
+    let first_byte_offset = first_byte_offset.unwrap_or(requested.max(end));
+    Ok(InstanceSerialConsoleHistory {
+        boot_generations: boots.keys().copied().collect(),
+        boot_generation,
+        last_byte_offset: first_byte_offset + data.len() as u64,
+        first_byte_offset,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn query(
+        boot_generation: Option<u64>,
+        from_start: Option<u64>,
+        most_recent: Option<u64>,
+        max_bytes: Option<u64>,
+    ) -> InstanceSerialConsoleHistoryQuery {
+        InstanceSerialConsoleHistoryQuery {
+            boot_generation,
+            from_start,
+            most_recent,
+            max_bytes,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_read() {
+        let debug_dir = camino_tempfile::Utf8TempDir::new().unwrap();
+        let debug_dirs = vec![debug_dir.path().to_path_buf()];
+        let instance_id = Uuid::new_v4();
+
+        let error = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, None, None, None),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::NoRecording(_)));
+
+        let mut recording =
+            BootRecording::new(&debug_dirs, instance_id, 1).await.unwrap();
+        recording.append(0, b"hello, ").await.unwrap();
+        recording.append(7, b"world\n").await.unwrap();
+        // Output from 13 to 20 was lost.
+        recording.append(20, b"panic").await.unwrap();
+
+        let history = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, None, None, None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.boot_generations, vec![1]);
+        assert_eq!(history.boot_generation, 1);
+        assert_eq!(history.data, b"hello, world\n");
+        assert_eq!(history.first_byte_offset, 0);
+        assert_eq!(history.last_byte_offset, 13);
+
+        // Reading from the gap skips to the next recorded output.
+        let history = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, Some(history.last_byte_offset), None, None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.data, b"panic");
+        assert_eq!(history.first_byte_offset, 20);
+        assert_eq!(history.last_byte_offset, 25);
+
+        let history = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(Some(1), None, Some(3), None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.data, b"nic");
+
+        let history = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, Some(2), None, Some(3)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.data, b"llo");
+        assert_eq!(history.first_byte_offset, 2);
+        assert_eq!(history.last_byte_offset, 5);
+
+        // Reading past the end returns nothing.
+        let history = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, Some(100), None, None),
+        )
+        .await
+        .unwrap();
+        assert!(history.data.is_empty());
+        assert_eq!(history.first_byte_offset, 100);
+
+        let error = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, Some(0), Some(0), None),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::ConflictingOffsets));
+
+        let error = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(Some(2), None, None, None),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::NoSuchBoot { boot_generation: 2, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let debug_dir = camino_tempfile::Utf8TempDir::new().unwrap();
+        let debug_dirs = vec![debug_dir.path().to_path_buf()];
+        let instance_id = Uuid::new_v4();
+
+        // Write enough output to roll over the oldest segments.
+        let mut recording =
+            BootRecording::new(&debug_dirs, instance_id, 1).await.unwrap();
+        let chunk = vec![b'x'; SEGMENT_SIZE as usize];
+        let segments = MAX_SEGMENTS_PER_BOOT as u64 + 2;
+        for i in 0..segments {
+            recording.append(i * SEGMENT_SIZE, &chunk).await.unwrap();
+        }
+
+        let history = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, Some(0), None, Some(16)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.first_byte_offset, 2 * SEGMENT_SIZE);
+        assert_eq!(history.data.len(), 16);
+
+        let history = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, None, Some(10), None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.last_byte_offset, segments * SEGMENT_SIZE);
+        assert_eq!(history.data.len(), 10);
+
+        // Start enough boots to expire the first. The boots in between ran
+        // on other sleds.
+        let boots = [3, 4, 6, 9];
+        assert_eq!(boots.len(), MAX_BOOTS_PER_INSTANCE);
+        for boot in boots {
+            let mut recording =
+                BootRecording::new(&debug_dirs, instance_id, boot)
+                    .await
+                    .unwrap();
+            recording.append(0, b"booting").await.unwrap();
+        }
+        let history = read_history(
+            &debug_dirs,
+            instance_id,
+            &query(None, None, None, None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.boot_generations, vec![3, 4, 6, 9]);
+        assert_eq!(history.boot_generation, 9);
+        assert_eq!(history.data, b"booting");
+    }
+}
@@ -204,6 +204,20 @@ impl<S: Simulatable + 'static> SimCollection<S> {
         self.objects.lock().await.len()
     }
 
+    /// Returns the IDs of all of the objects in the collection.
+    pub async fn ids(&self) -> Vec<Uuid> {
+        self.objects.lock().await.keys().copied().collect()
+    }
+
+    /// Removes the object identified by `id` from the collection, abandoning
+    /// any transition that it has yet to complete.
+    pub async fn sim_remove(&self, id: &Uuid) {
+        let removed = self.objects.lock().await.remove(id);
+        if let Some(mut tx) = removed.and_then(|object| object.channel_tx) {
+            tx.close_channel();
+        }
+    }
+
     /// Body of the background task (one per `SimObject`) that simulates
     /// asynchronous transitions.  Each time we read a message from the object's
     /// channel, we sleep for a bit and then invoke `poke()` to complete whatever
@@ -226,16 +240,19 @@ impl<S: Simulatable + 'static> SimCollection<S> {
 
         while should_step {
             let (new_state, to_destroy) = {
-                // The object must be present in `objects` because it only gets
-                // removed when it comes to rest in the "Destroyed" state, but
-                // we can only get here if there's an asynchronous state
-                // transition desired.
+                // The object is normally present in `objects` because it only
+                // gets removed when it comes to rest in the "Destroyed" state,
+                // but we can only get here if there's an asynchronous state
+                // transition desired.  The exception is an object that was
+                // removed with `sim_remove`, whose transitions are abandoned.
                 //
                 // We do as little as possible with the lock held.  In
                 // particular, we want to finish this work before calling out to
                 // notify the nexus.
                 let mut objects = self.objects.lock().await;
-                let mut object = objects.remove(&id).unwrap();
+                let Some(mut object) = objects.remove(&id) else {
+                    return;
+                };
                 object.transition_finish();
                 let after = object.object.current().clone();
 
@@ -6,15 +6,19 @@
 
 use crate::params::{
     DiskEnsureBody, InstanceEnsureBody, InstancePutMigrationIdsBody,
-    InstancePutStateBody, InstancePutStateResponse, InstanceUnregisterResponse,
-    VpcFirewallRulesEnsureBody,
+    InstancePutStateBody, InstancePutStateResponse,
+    InstanceSerialConsoleHistory, InstanceSerialConsoleHistoryQuery,
+    InstanceUnregisterResponse, VpcFirewallRulesEnsureBody,
+    VpcRoutesEnsureBody,
 };
+use crucible_client_types::VolumeConstructionRequest;
 use dropshot::endpoint;
 use dropshot::ApiDescription;
 use dropshot::HttpError;
 use dropshot::HttpResponseOk;
 use dropshot::HttpResponseUpdatedNoContent;
 use dropshot::Path;
+use dropshot::Query;
 use dropshot::RequestContext;
 use dropshot::TypedBody;
 use illumos_utils::opte::params::SetVirtualNetworkInterfaceHost;
@@ -37,12 +41,17 @@ pub fn api() -> SledApiDescription {
         api.register(instance_put_state)?;
         api.register(instance_register)?;
         api.register(instance_unregister)?;
+        api.register(instances_get)?;
         api.register(instance_poke_post)?;
         api.register(disk_put)?;
         api.register(disk_poke_post)?;
         api.register(update_artifact)?;
+        api.register(instance_issue_disk_replace_request)?;
+        api.register(instance_issue_disk_resize_request)?;
         api.register(instance_issue_disk_snapshot_request)?;
+        api.register(instance_serial_console_history)?;
         api.register(vpc_firewall_rules_put)?;
+        api.register(vpc_routes_put)?;
         api.register(set_v2p)?;
         api.register(del_v2p)?;
 
@@ -62,6 +71,17 @@ struct InstancePathParam {
     instance_id: Uuid,
 }
 
+#[endpoint {
+    method = GET,
+    path = "/instances",
+}]
+async fn instances_get(
+    rqctx: RequestContext<Arc<SledAgent>>,
+) -> Result<HttpResponseOk<Vec<Uuid>>, HttpError> {
+    let sa = rqctx.context();
+    Ok(HttpResponseOk(sa.instances_list().await))
+}
+
 #[endpoint {
     method = PUT,
     path = "/instances/{instance_id}",
@@ -250,6 +270,80 @@ async fn instance_issue_disk_snapshot_request(
     }))
 }
 
+#[derive(Deserialize, JsonSchema)]
+pub struct InstanceIssueDiskReplaceRequestPathParam {
+    instance_id: Uuid,
+    disk_id: Uuid,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct InstanceIssueDiskReplaceRequestBody {
+    volume_construction_request: VolumeConstructionRequest,
+}
+
+/// Record the volume of one of an instance's disks after one of its regions
+/// was replaced, for use the next time a Propolis is created for the instance
+#[endpoint {
+    method = POST,
+    path = "/instances/{instance_id}/disks/{disk_id}/replace",
+}]
+async fn instance_issue_disk_replace_request(
+    rqctx: RequestContext<Arc<SledAgent>>,
+    path_params: Path<InstanceIssueDiskReplaceRequestPathParam>,
+    body: TypedBody<InstanceIssueDiskReplaceRequestBody>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    let path_params = path_params.into_inner();
+    let body = body.into_inner();
+
+    sa.instance_issue_disk_replace_request(
+        path_params.instance_id,
+        path_params.disk_id,
+        body.volume_construction_request,
+    )
+    .await
+    .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct InstanceIssueDiskResizeRequestPathParam {
+    instance_id: Uuid,
+    disk_id: Uuid,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct InstanceIssueDiskResizeRequestBody {
+    volume_construction_request: VolumeConstructionRequest,
+}
+
+/// Record the grown volume of one of an instance's disks, for use the next
+/// time a Propolis is created for the instance
+#[endpoint {
+    method = POST,
+    path = "/instances/{instance_id}/disks/{disk_id}/resize",
+}]
+async fn instance_issue_disk_resize_request(
+    rqctx: RequestContext<Arc<SledAgent>>,
+    path_params: Path<InstanceIssueDiskResizeRequestPathParam>,
+    body: TypedBody<InstanceIssueDiskResizeRequestBody>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    let path_params = path_params.into_inner();
+    let body = body.into_inner();
+
+    sa.instance_issue_disk_resize_request(
+        path_params.instance_id,
+        path_params.disk_id,
+        body.volume_construction_request,
+    )
+    .await
+    .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+
+    Ok(HttpResponseUpdatedNoContent())
+}
+
 /// Path parameters for VPC requests (sled agent API)
 #[derive(Deserialize, JsonSchema)]
 struct VpcPathParam {
@@ -272,6 +366,41 @@ async fn vpc_firewall_rules_put(
     Ok(HttpResponseUpdatedNoContent())
 }
 
+#[endpoint {
+    method = GET,
+    path = "/instances/{instance_id}/serial-console/history",
+}]
+async fn instance_serial_console_history(
+    rqctx: RequestContext<Arc<SledAgent>>,
+    path_params: Path<InstancePathParam>,
+    query_params: Query<InstanceSerialConsoleHistoryQuery>,
+) -> Result<HttpResponseOk<InstanceSerialConsoleHistory>, HttpError> {
+    let sa = rqctx.context();
+    let instance_id = path_params.into_inner().instance_id;
+    let query = query_params.into_inner();
+    Ok(HttpResponseOk(
+        sa.instance_serial_console_history(instance_id, &query).await?,
+    ))
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/vpc/{vpc_id}/routes",
+}]
+async fn vpc_routes_put(
+    rqctx: RequestContext<Arc<SledAgent>>,
+    path_params: Path<VpcPathParam>,
+    body: TypedBody<VpcRoutesEnsureBody>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let sa = rqctx.context();
+    let vpc_id = path_params.into_inner().vpc_id;
+    let body_args = body.into_inner();
+
+    sa.vpc_routes_ensure(vpc_id, body_args).await;
+
+    Ok(HttpResponseUpdatedNoContent())
+}
+
 /// Path parameters for V2P mapping related requests (sled agent API)
 #[derive(Deserialize, JsonSchema)]
 struct V2pPathParam {
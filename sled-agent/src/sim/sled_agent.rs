@@ -14,8 +14,9 @@ use super::storage::Storage;
 use crate::nexus::NexusClient;
 use crate::params::{
     DiskStateRequested, InstanceHardware, InstanceMigrationSourceParams,
-    InstancePutStateResponse, InstanceStateRequested,
-    InstanceUnregisterResponse,
+    InstancePutStateResponse, InstanceSerialConsoleHistory,
+    InstanceSerialConsoleHistoryQuery, InstanceStateRequested,
+    InstanceUnregisterResponse, VpcRoutesEnsureBody,
 };
 use crate::sim::simulatable::Simulatable;
 use crate::updates::UpdateManager;
@@ -28,7 +29,7 @@ use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use uuid::Uuid;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
 use crucible_client_types::VolumeConstructionRequest;
@@ -59,11 +60,18 @@ pub struct SledAgent {
     pub nexus_client: Arc<NexusClient>,
     disk_id_to_region_ids: Mutex<HashMap<String, Vec<Uuid>>>,
     pub v2p_mappings: Mutex<HashMap<Uuid, Vec<SetVirtualNetworkInterfaceHost>>>,
+    /// most recent route tables sent for each VPC, indexed by VPC uuid
+    pub vpc_routes: Mutex<HashMap<Uuid, VpcRoutesEnsureBody>>,
+    /// external IPs of each registered instance, indexed by instance uuid
+    pub external_ips: Mutex<HashMap<Uuid, HashSet<IpAddr>>>,
+    /// boot generation of each registered instance's Propolis, indexed by
+    /// instance uuid
+    boot_generations: Mutex<HashMap<Uuid, u64>>,
     mock_propolis:
         Mutex<Option<(HttpServer<Arc<PropolisContext>>, PropolisClient)>>,
 }
 
-fn extract_targets_from_volume_construction_request(
+pub(crate) fn extract_targets_from_volume_construction_request(
     vec: &mut Vec<SocketAddr>,
     vcr: &VolumeConstructionRequest,
 ) {
@@ -149,6 +157,9 @@ impl SledAgent {
             nexus_client,
             disk_id_to_region_ids: Mutex::new(HashMap::new()),
             v2p_mappings: Mutex::new(HashMap::new()),
+            vpc_routes: Mutex::new(HashMap::new()),
+            external_ips: Mutex::new(HashMap::new()),
+            boot_generations: Mutex::new(HashMap::new()),
             mock_propolis: Mutex::new(None),
         })
     }
@@ -211,6 +222,15 @@ impl SledAgent {
         Ok(())
     }
 
+    /// Returns the id of the region served on `port`, along with the name of
+    /// the snapshot of it being served if that's a running snapshot
+    pub async fn region_for_port(
+        &self,
+        port: u16,
+    ) -> Option<(Uuid, Option<String>)> {
+        self.storage.lock().await.region_for_port(port).await
+    }
+
     /// Idempotently ensures that the given API Instance (described by
     /// `api_instance`) exists on this server in the given runtime state
     /// (described by `target`).
@@ -299,6 +319,15 @@ impl SledAgent {
             .sim_ensure(&instance_id, initial_hardware.runtime, None)
             .await?;
 
+        self.external_ips.lock().await.insert(
+            instance_id,
+            initial_hardware.external_ips.iter().copied().collect(),
+        );
+        self.boot_generations
+            .lock()
+            .await
+            .insert(instance_id, initial_hardware.boot_generation);
+
         for disk_request in &initial_hardware.disks {
             // disk_request.volume_construction_request is of type
             // propolis_client::instance_spec::VolumeConstructionRequest, where
@@ -317,6 +346,11 @@ impl SledAgent {
         Ok(instance_run_time_state)
     }
 
+    /// Returns the IDs of the instances registered with this sled.
+    pub async fn instances_list(&self) -> Vec<Uuid> {
+        self.instances.ids().await
+    }
+
     /// Forcibly unregisters an instance. To simulate the rude termination that
     /// this produces in the real sled agent, the instance's mock Propolis is
     /// not notified.
@@ -336,8 +370,10 @@ impl SledAgent {
             };
 
         self.detach_disks_from_instance(instance_id).await?;
+        let updated_runtime = instance.terminate();
+        self.instances.sim_remove(&instance_id).await;
         Ok(InstanceUnregisterResponse {
-            updated_runtime: Some(instance.terminate()),
+            updated_runtime: Some(updated_runtime),
         })
     }
 
@@ -562,6 +598,35 @@ impl SledAgent {
         Ok(())
     }
 
+    /// Inform an instance that a Crucible disk attached to it has grown.
+    ///
+    /// The real sled agent records the new volume construction request for
+    /// use the next time it creates a Propolis for the instance. There is no
+    /// Propolis to update here, but the disk's new regions do need to be
+    /// recorded so that later snapshot requests cover them too.
+    pub async fn instance_issue_disk_resize_request(
+        &self,
+        _instance_id: Uuid,
+        _disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        self.map_disk_ids_to_region_ids(&volume_construction_request).await
+    }
+
+    /// Inform an instance that one of the regions of a Crucible disk attached
+    /// to it was replaced.
+    ///
+    /// As with a resize, the disk's new regions are recorded so that later
+    /// snapshot requests go to them rather than the one that was lost.
+    pub async fn instance_issue_disk_replace_request(
+        &self,
+        _instance_id: Uuid,
+        _disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        self.map_disk_ids_to_region_ids(&volume_construction_request).await
+    }
+
     pub async fn set_virtual_nic_host(
         &self,
         interface_id: Uuid,
@@ -584,6 +649,68 @@ impl SledAgent {
         Ok(())
     }
 
+    /// Reads an instance's serial console output.
+    ///
+    /// Nothing is recorded here: the mock Propolis's buffer, if one is
+    /// running, is served as the output of the boot the instance was last
+    /// registered with.
+    pub async fn instance_serial_console_history(
+        &self,
+        instance_id: Uuid,
+        query: &InstanceSerialConsoleHistoryQuery,
+    ) -> Result<InstanceSerialConsoleHistory, Error> {
+        let not_found =
+            || Error::not_found_by_id(ResourceType::Instance, &instance_id);
+        let boot_generation = *self
+            .boot_generations
+            .lock()
+            .await
+            .get(&instance_id)
+            .ok_or_else(not_found)?;
+        if query.boot_generation.map_or(false, |boot| boot != boot_generation) {
+            return Err(not_found());
+        }
+        if query.from_start.is_some() && query.most_recent.is_some() {
+            return Err(Error::invalid_request(
+                "at most one of from_start and most_recent may be provided",
+            ));
+        }
+
+        let mock_lock = self.mock_propolis.lock().await;
+        let (_, client) = mock_lock.as_ref().ok_or_else(not_found)?;
+        let mut request = client.instance_serial_history_get();
+        if let Some(most_recent) = query.most_recent {
+            request = request.most_recent(most_recent);
+        } else {
+            request = request.from_start(query.from_start.unwrap_or(0));
+        }
+        if let Some(max_bytes) = query.max_bytes {
+            request = request.max_bytes(max_bytes);
+        }
+        let history = request
+            .send()
+            .await
+            .map_err(|e| Error::internal_error(&e.to_string()))?
+            .into_inner();
+
+        let len = history.data.len() as u64;
+        Ok(InstanceSerialConsoleHistory {
+            boot_generations: vec![boot_generation],
+            boot_generation,
+            first_byte_offset: history.last_byte_offset.saturating_sub(len),
+            last_byte_offset: history.last_byte_offset,
+            data: history.data,
+        })
+    }
+
+    pub async fn vpc_routes_ensure(
+        &self,
+        vpc_id: Uuid,
+        routes: VpcRoutesEnsureBody,
+    ) {
+        self.vpc_routes.lock().await.insert(vpc_id, routes);
+    }
+
     /// Used for integration tests that require a component to talk to a
     /// mocked propolis-server API.
     // TODO: fix schemas so propolis-server's port isn't hardcoded in nexus
@@ -10,6 +10,7 @@
 
 use crate::nexus::NexusClient;
 use crate::sim::http_entrypoints_pantry::ExpectedDigest;
+use crate::sim::sled_agent::extract_targets_from_volume_construction_request;
 use crate::sim::SledAgent;
 use anyhow::{bail, Result};
 use chrono::prelude::*;
@@ -225,6 +226,23 @@ impl CrucibleDataInner {
         Ok(())
     }
 
+    /// Returns the id of the region served on `port`, along with the name of
+    /// the snapshot of it being served if that's a running snapshot
+    fn region_for_port(&self, port: u16) -> Option<(Uuid, Option<String>)> {
+        if let Some(region) =
+            self.regions.values().find(|r| r.port_number == port)
+        {
+            return Some((Uuid::from_str(&region.id.0).unwrap(), None));
+        }
+
+        self.running_snapshots.iter().find_map(|(id, running_snapshots)| {
+            running_snapshots
+                .values()
+                .find(|r| r.port_number == port)
+                .map(|r| (*id, Some(r.name.clone())))
+        })
+    }
+
     /// Return true if there are no undeleted Crucible resources
     pub fn is_empty(&self) -> bool {
         let non_destroyed_regions = self
@@ -324,6 +342,13 @@ impl CrucibleData {
         self.inner.lock().await.delete_running_snapshot(id, name)
     }
 
+    pub async fn region_for_port(
+        &self,
+        port: u16,
+    ) -> Option<(Uuid, Option<String>)> {
+        self.inner.lock().await.region_for_port(port)
+    }
+
     pub async fn is_empty(&self) -> bool {
         self.inner.lock().await.is_empty()
     }
@@ -428,6 +453,19 @@ impl Zpool {
 
         None
     }
+
+    pub async fn region_for_port(
+        &self,
+        port: u16,
+    ) -> Option<(Uuid, Option<String>)> {
+        for dataset in self.datasets.values() {
+            if let Some(region) = dataset.data().region_for_port(port).await {
+                return Some(region);
+            }
+        }
+
+        None
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -584,6 +622,21 @@ impl Storage {
 
         None
     }
+
+    /// Returns the id of the region served on `port`, along with the name of
+    /// the snapshot of it being served if that's a running snapshot
+    pub async fn region_for_port(
+        &self,
+        port: u16,
+    ) -> Option<(Uuid, Option<String>)> {
+        for zpool in self.zpools.values() {
+            if let Some(region) = zpool.region_for_port(port).await {
+                return Some(region);
+            }
+        }
+
+        None
+    }
 }
 
 /// Simulated crucible pantry
@@ -592,6 +645,12 @@ pub struct Pantry {
     vcrs: Mutex<HashMap<String, VolumeConstructionRequest>>, // Please rewind!
     sled_agent: Arc<SledAgent>,
     jobs: Mutex<HashSet<String>>,
+    /// Blocks written with bulk writes, keyed by the regions of the volume
+    /// they were written to (see [`Pantry::blocks_key`]) and then by offset,
+    /// so that they can be read back.  This outlives the volume being
+    /// attached, and follows the volume rather than the id it's attached
+    /// with, like the data in a real volume does.
+    blocks: Mutex<HashMap<String, HashMap<u64, Vec<u8>>>>,
 }
 
 impl Pantry {
@@ -601,6 +660,7 @@ impl Pantry {
             vcrs: Mutex::new(HashMap::default()),
             sled_agent,
             jobs: Mutex::new(HashSet::default()),
+            blocks: Mutex::new(HashMap::default()),
         }
     }
 
@@ -674,8 +734,7 @@ impl Pantry {
         // the simulated instance ensure, then call
         // [`instance_issue_disk_snapshot_request`] as the snapshot logic is the
         // same.
-        let vcrs = self.vcrs.lock().await;
-        let volume_construction_request = vcrs.get(&volume_id).unwrap();
+        let volume_construction_request = self.entry(volume_id.clone()).await?;
 
         self.sled_agent
             .map_disk_ids_to_region_ids(&volume_construction_request)
@@ -688,20 +747,44 @@ impl Pantry {
                 snapshot_id.parse().unwrap(),
             )
             .await
-            .map_err(|e| HttpError::for_internal_error(e.to_string()))
+            .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+
+        // Copy the volume's blocks to where they'll be found once the snapshot
+        // of each region is served by a running snapshot.
+        let mut snapshot_keys = Vec::new();
+        let mut targets = Vec::new();
+        extract_targets_from_volume_construction_request(
+            &mut targets,
+            &volume_construction_request,
+        );
+        for target in targets {
+            match self.sled_agent.region_for_port(target.port()).await {
+                Some((region_id, None)) => {
+                    snapshot_keys.push(format!("{}@{}", region_id, snapshot_id))
+                }
+                _ => return Ok(()),
+            }
+        }
+
+        let key = self.blocks_key(volume_id).await?;
+        let mut blocks = self.blocks.lock().await;
+        if let Some(volume_blocks) = blocks.get(&key).cloned() {
+            blocks.insert(snapshot_keys.join(","), volume_blocks);
+        }
+
+        Ok(())
     }
 
-    pub async fn bulk_write(
+    /// Returns the block size and total size of an attached volume
+    async fn volume_size(
         &self,
         volume_id: String,
-        offset: u64,
-        data: Vec<u8>,
-    ) -> Result<(), HttpError> {
+    ) -> Result<(u64, u64), HttpError> {
         let vcr = self.entry(volume_id).await?;
 
         // Currently, Nexus will only make volumes where the first subvolume is
         // a Region. This will change in the future!
-        let (region_block_size, region_size) = match vcr {
+        let sizes = match vcr {
             VolumeConstructionRequest::Volume { sub_volumes, .. } => {
                 match sub_volumes[0] {
                     VolumeConstructionRequest::Region {
@@ -725,6 +808,46 @@ impl Pantry {
             }
         };
 
+        Ok(sizes)
+    }
+
+    /// Identifies the data of an attached volume by the regions (or the
+    /// snapshots of regions) that its targets serve, which (unlike the
+    /// volume's generation numbers) are the same however many times it's
+    /// checked out
+    async fn blocks_key(&self, volume_id: String) -> Result<String, HttpError> {
+        let vcr = self.entry(volume_id).await?;
+
+        let mut targets = Vec::new();
+        extract_targets_from_volume_construction_request(&mut targets, &vcr);
+
+        let mut keys = Vec::new();
+        for target in targets {
+            let key = match self.sled_agent.region_for_port(target.port()).await
+            {
+                Some((region_id, None)) => region_id.to_string(),
+                Some((region_id, Some(snapshot))) => {
+                    format!("{}@{}", region_id, snapshot)
+                }
+                None => target.to_string(),
+            };
+            keys.push(key);
+        }
+
+        Ok(keys.join(","))
+    }
+
+    /// Checks that a bulk read or write of `len` bytes at `offset` fits the
+    /// volume's blocks, returning the block size
+    async fn check_bulk_range(
+        &self,
+        volume_id: String,
+        offset: u64,
+        len: u64,
+    ) -> Result<u64, HttpError> {
+        let (region_block_size, region_size) =
+            self.volume_size(volume_id).await?;
+
         if (offset % region_block_size) != 0 {
             return Err(HttpError::for_bad_request(
                 None,
@@ -732,23 +855,69 @@ impl Pantry {
             ));
         }
 
-        if (data.len() as u64 % region_block_size) != 0 {
+        if (len % region_block_size) != 0 {
             return Err(HttpError::for_bad_request(
                 None,
                 "data length not multiple of block size!".to_string(),
             ));
         }
 
-        if (offset + data.len() as u64) > region_size {
+        if (offset + len) > region_size {
             return Err(HttpError::for_bad_request(
                 None,
                 "offset + data length off end of region!".to_string(),
             ));
         }
 
+        Ok(region_block_size)
+    }
+
+    pub async fn bulk_write(
+        &self,
+        volume_id: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<(), HttpError> {
+        let block_size = self
+            .check_bulk_range(volume_id.clone(), offset, data.len() as u64)
+            .await?;
+        let key = self.blocks_key(volume_id).await?;
+
+        let mut blocks = self.blocks.lock().await;
+        let volume_blocks = blocks.entry(key).or_default();
+        for (i, block) in data.chunks(block_size as usize).enumerate() {
+            volume_blocks
+                .insert(offset + i as u64 * block_size, block.to_vec());
+        }
+
         Ok(())
     }
 
+    pub async fn bulk_read(
+        &self,
+        volume_id: String,
+        offset: u64,
+        size: u64,
+    ) -> Result<Vec<u8>, HttpError> {
+        let block_size =
+            self.check_bulk_range(volume_id.clone(), offset, size).await?;
+        let key = self.blocks_key(volume_id).await?;
+
+        // Blocks that were never written read back as zeroes.
+        let blocks = self.blocks.lock().await;
+        let volume_blocks = blocks.get(&key);
+        let mut data = Vec::with_capacity(size as usize);
+        for block_offset in (offset..offset + size).step_by(block_size as usize)
+        {
+            match volume_blocks.and_then(|b| b.get(&block_offset)) {
+                Some(block) => data.extend_from_slice(block),
+                None => data.resize(data.len() + block_size as usize, 0),
+            }
+        }
+
+        Ok(data)
+    }
+
     pub async fn scrub(&self, volume_id: String) -> Result<String, HttpError> {
         self.entry(volume_id).await?;
 
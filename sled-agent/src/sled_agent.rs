@@ -11,13 +11,16 @@ use crate::nexus::{LazyNexusClient, NexusRequestQueue};
 use crate::params::{
     DatasetKind, DiskStateRequested, InstanceHardware,
     InstanceMigrationSourceParams, InstancePutStateResponse,
+    InstanceSerialConsoleHistory, InstanceSerialConsoleHistoryQuery,
     InstanceStateRequested, InstanceUnregisterResponse, ServiceEnsureBody,
-    ServiceZoneService, SledRole, TimeSync, VpcFirewallRule, Zpool,
+    ServiceZoneService, SledRole, TimeSync, VpcFirewallRule, VpcSubnetRoutes,
+    Zpool,
 };
 use crate::services::{self, ServiceManager};
 use crate::storage_manager::{self, StorageManager};
 use crate::updates::{ConfigUpdates, UpdateManager};
 use camino::Utf8PathBuf;
+use crucible_client_types::VolumeConstructionRequest;
 use dropshot::HttpError;
 use illumos_utils::opte::params::SetVirtualNetworkInterfaceHost;
 use illumos_utils::opte::PortManager;
@@ -25,8 +28,8 @@ use omicron_common::address::{
     get_sled_address, get_switch_zone_address, Ipv6Subnet, SLED_PREFIX,
 };
 use omicron_common::api::{
-    internal::nexus::DiskRuntimeState, internal::nexus::InstanceRuntimeState,
-    internal::nexus::UpdateArtifactId,
+    external::Vni, internal::nexus::DiskRuntimeState,
+    internal::nexus::InstanceRuntimeState, internal::nexus::UpdateArtifactId,
 };
 use omicron_common::backoff::{
     retry_notify_ext, retry_policy_internal_service_aggressive, BackoffError,
@@ -89,6 +92,9 @@ pub enum Error {
 
     #[error(transparent)]
     ZpoolList(#[from] illumos_utils::zpool::ListError),
+
+    #[error("Error reading serial console history: {0}")]
+    SerialConsole(#[from] crate::serial_console::Error),
 }
 
 impl From<Error> for omicron_common::api::external::Error {
@@ -131,6 +137,10 @@ impl From<Error> for dropshot::HttpError {
                 }
             }
 
+            crate::sled_agent::Error::SerialConsole(serial_console_error) => {
+                HttpError::from(serial_console_error)
+            }
+
             e => HttpError::for_internal_error(e.to_string()),
         }
     }
@@ -254,6 +264,7 @@ impl SledAgent {
             lazy_nexus_client.clone(),
             etherstub.clone(),
             port_manager.clone(),
+            storage.resources().clone(),
         )?;
 
         let hardware = HardwareManager::new(&parent_log, services.sled_mode())
@@ -561,6 +572,11 @@ impl SledAgent {
         Ok(())
     }
 
+    /// Returns the IDs of the instances registered with this sled.
+    pub fn instances_list(&self) -> Vec<Uuid> {
+        self.inner.instances.instance_ids()
+    }
+
     /// Idempotently ensures that a given instance is registered with this sled,
     /// i.e., that it can be addressed by future calls to
     /// [`instance_ensure_state`].
@@ -664,6 +680,63 @@ impl SledAgent {
             .map_err(Error::from)
     }
 
+    /// Record the grown volume of a Crucible disk attached to an instance,
+    /// for use the next time a Propolis is created for the instance
+    pub async fn instance_issue_disk_resize_request(
+        &self,
+        instance_id: Uuid,
+        disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        self.inner
+            .instances
+            .instance_issue_disk_resize_request(
+                instance_id,
+                disk_id,
+                volume_construction_request,
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    /// Record the volume of a Crucible disk attached to an instance after one
+    /// of its regions was replaced, for use the next time a Propolis is
+    /// created for the instance
+    pub async fn instance_issue_disk_replace_request(
+        &self,
+        instance_id: Uuid,
+        disk_id: Uuid,
+        volume_construction_request: VolumeConstructionRequest,
+    ) -> Result<(), Error> {
+        self.inner
+            .instances
+            .instance_issue_disk_replace_request(
+                instance_id,
+                disk_id,
+                volume_construction_request,
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    /// Reads an instance's recorded serial console output. This works whether
+    /// or not the instance is still running here.
+    pub async fn instance_serial_console_history(
+        &self,
+        instance_id: Uuid,
+        query: &InstanceSerialConsoleHistoryQuery,
+    ) -> Result<InstanceSerialConsoleHistory, Error> {
+        let debug_dirs = self
+            .inner
+            .storage
+            .resources()
+            .all_u2_mountpoints(sled_hardware::disk::DEBUG_DATASET)
+            .await;
+        crate::serial_console::read_history(&debug_dirs, instance_id, query)
+            .await
+            .map_err(Error::from)
+    }
+
     pub async fn firewall_rules_ensure(
         &self,
         _vpc_id: Uuid,
@@ -676,6 +749,19 @@ impl SledAgent {
             .map_err(Error::from)
     }
 
+    pub async fn vpc_routes_ensure(
+        &self,
+        _vpc_id: Uuid,
+        vni: Vni,
+        subnets: &[VpcSubnetRoutes],
+    ) -> Result<(), Error> {
+        self.inner
+            .instances
+            .vpc_routes_ensure(vni, subnets)
+            .await
+            .map_err(Error::from)
+    }
+
     pub async fn set_virtual_nic_host(
         &self,
         mapping: &SetVirtualNetworkInterfaceHost,
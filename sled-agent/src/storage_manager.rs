@@ -234,6 +234,20 @@ impl StorageResources {
             .collect()
     }
 
+    /// Returns all U.2 zpools
+    pub async fn all_u2_zpools(&self) -> Vec<ZpoolName> {
+        self.all_zpools(DiskVariant::U2).await
+    }
+
+    /// Returns all mountpoints within all U.2s for a particular dataset.
+    pub async fn all_u2_mountpoints(&self, dataset: &str) -> Vec<Utf8PathBuf> {
+        let u2_zpools = self.all_u2_zpools().await;
+        u2_zpools
+            .iter()
+            .map(|zpool| zpool.dataset_mountpoint(dataset))
+            .collect()
+    }
+
     pub async fn all_zpools(&self, variant: DiskVariant) -> Vec<ZpoolName> {
         let disks = self.disks.lock().await;
         disks
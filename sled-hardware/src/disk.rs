@@ -193,11 +193,14 @@ pub const CRASH_DATASET: &'static str = "crash";
 pub const CLUSTER_DATASET: &'static str = "cluster";
 pub const CONFIG_DATASET: &'static str = "config";
 pub const ZONE_DATASET: &'static str = "zone";
+pub const DEBUG_DATASET: &'static str = "debug";
 
-const U2_EXPECTED_DATASET_COUNT: usize = 1;
+const U2_EXPECTED_DATASET_COUNT: usize = 2;
 static U2_EXPECTED_DATASETS: [&'static str; U2_EXPECTED_DATASET_COUNT] = [
     // Stores filesystems for zones
     ZONE_DATASET,
+    // Stores debugging data, such as recorded instance serial consoles.
+    DEBUG_DATASET,
 ];
 
 const M2_EXPECTED_DATASET_COUNT: usize = 4;